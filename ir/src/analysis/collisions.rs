@@ -0,0 +1,517 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+};
+
+use cgmath::{InnerSpace, SquareMatrix};
+use ldraw::{Matrix4, Vector3};
+
+use crate::{
+    geometry::BoundingBox3,
+    model::{GroupId, Model, Object, ObjectId, ObjectInstance},
+    part::{Part, PartGeometryQuerier},
+    steps::overlaps,
+};
+
+#[derive(Clone, Debug)]
+pub struct CollisionParams {
+    // Edge length of a voxel in LDU.
+    pub resolution: f32,
+    // Voxel resolution gets coarser for parts that would need more cells than this.
+    pub max_cells: usize,
+    // Parts penetrating each other less than this (in LDU) are not reported.
+    pub tolerance: f32,
+}
+
+impl Default for CollisionParams {
+    fn default() -> Self {
+        Self {
+            resolution: 1.0,
+            max_cells: 2_000_000,
+            tolerance: 0.5,
+        }
+    }
+}
+
+const OUTSIDE: u8 = 0;
+const SURFACE: u8 = 1;
+
+// Solid voxel approximation of a part. Each cell holds its distance from the outside in
+// cells, so 0 is empty space, 1 is the surface and anything above is the interior.
+#[derive(Clone, Debug)]
+pub struct VoxelGrid {
+    origin: Vector3,
+    cell_size: f32,
+    dims: [usize; 3],
+    cells: Vec<u8>,
+    interior: usize,
+}
+
+impl VoxelGrid {
+    pub fn from_part(part: &Part, params: &CollisionParams) -> Option<Self> {
+        if part.bounding_box.is_null() {
+            return None;
+        }
+
+        let bounding_box = &part.bounding_box;
+        let volume = (bounding_box.len_x() + params.resolution)
+            * (bounding_box.len_y() + params.resolution)
+            * (bounding_box.len_z() + params.resolution);
+        let cell_size = params
+            .resolution
+            .max((volume / params.max_cells.max(1) as f32).cbrt());
+
+        // One cell of padding on every side, so the outside is connected all around.
+        let origin = bounding_box.min - Vector3::new(cell_size, cell_size, cell_size);
+        let dims = [
+            (bounding_box.len_x() / cell_size).ceil() as usize + 3,
+            (bounding_box.len_y() / cell_size).ceil() as usize + 3,
+            (bounding_box.len_z() / cell_size).ceil() as usize + 3,
+        ];
+
+        let mut grid = VoxelGrid {
+            origin,
+            cell_size,
+            dims,
+            cells: vec![u8::MAX; dims[0] * dims[1] * dims[2]],
+            interior: 0,
+        };
+        grid.rasterize(part);
+        grid.fill();
+        grid.interior = grid.cells.iter().filter(|v| **v > SURFACE).count();
+
+        Some(grid)
+    }
+
+    fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        (z * self.dims[1] + y) * self.dims[0] + x
+    }
+
+    fn cell_of(&self, point: &Vector3) -> Option<usize> {
+        let local = (point - self.origin) / self.cell_size;
+        if local.x < 0.0 || local.y < 0.0 || local.z < 0.0 {
+            return None;
+        }
+
+        let (x, y, z) = (local.x as usize, local.y as usize, local.z as usize);
+        if x >= self.dims[0] || y >= self.dims[1] || z >= self.dims[2] {
+            None
+        } else {
+            Some(self.index(x, y, z))
+        }
+    }
+
+    fn center_of(&self, x: usize, y: usize, z: usize) -> Vector3 {
+        self.origin + Vector3::new(x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5) * self.cell_size
+    }
+
+    // Marks every cell touched by a triangle of the mesh as the surface.
+    fn rasterize(&mut self, part: &Part) {
+        let vertices = &part.geometry.vertex_buffer.0;
+        let fetch = |index: u32| {
+            let index = index as usize * 3;
+            Vector3::new(vertices[index], vertices[index + 1], vertices[index + 2])
+        };
+
+        let meshes = [
+            &part.geometry.uncolored_mesh,
+            &part.geometry.uncolored_without_bfc_mesh,
+        ]
+        .into_iter()
+        .chain(part.geometry.colored_meshes.values());
+
+        for mesh in meshes {
+            for indices in mesh.vertex_indices.chunks_exact(3) {
+                let (a, b, c) = (fetch(indices[0]), fetch(indices[1]), fetch(indices[2]));
+                let longest = (b - a)
+                    .magnitude()
+                    .max((c - a).magnitude())
+                    .max((c - b).magnitude());
+                let steps = (longest * 2.0 / self.cell_size).ceil().max(1.0) as usize;
+
+                for i in 0..=steps {
+                    for j in 0..=(steps - i) {
+                        let point = a
+                            + (b - a) * (i as f32 / steps as f32)
+                            + (c - a) * (j as f32 / steps as f32);
+                        if let Some(index) = self.cell_of(&point) {
+                            self.cells[index] = SURFACE;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn neighbors(&self, index: usize) -> impl Iterator<Item = usize> {
+        let [dx, dy, dz] = self.dims;
+        let (x, y, z) = (index % dx, (index / dx) % dy, index / (dx * dy));
+
+        [
+            (x > 0).then(|| index - 1),
+            (x + 1 < dx).then(|| index + 1),
+            (y > 0).then(|| index - dx),
+            (y + 1 < dy).then(|| index + dx),
+            (z > 0).then(|| index - dx * dy),
+            (z + 1 < dz).then(|| index + dx * dy),
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    // Floods the outside from the padding and assigns depths to the enclosed cells.
+    fn fill(&mut self) {
+        let mut queue = VecDeque::new();
+        self.cells[0] = OUTSIDE;
+        queue.push_back(0);
+        while let Some(index) = queue.pop_front() {
+            for neighbor in self.neighbors(index).collect::<Vec<_>>() {
+                if self.cells[neighbor] == u8::MAX {
+                    self.cells[neighbor] = OUTSIDE;
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+
+        queue.extend(
+            self.cells
+                .iter()
+                .enumerate()
+                .filter(|(_, v)| **v == SURFACE)
+                .map(|(i, _)| i),
+        );
+        while let Some(index) = queue.pop_front() {
+            // u8::MAX marks unvisited cells, so depth saturates right below it.
+            let depth = (self.cells[index] + 1).min(u8::MAX - 1);
+            for neighbor in self.neighbors(index).collect::<Vec<_>>() {
+                if self.cells[neighbor] == u8::MAX {
+                    self.cells[neighbor] = depth;
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+    }
+
+    // Distance from the surface in LDU for a point in the part's space, if it is inside.
+    pub fn depth_at(&self, point: &Vector3) -> Option<f32> {
+        match self.cell_of(point).map(|index| self.cells[index]) {
+            Some(OUTSIDE) | None => None,
+            Some(depth) => Some((depth - SURFACE) as f32 * self.cell_size),
+        }
+    }
+
+    fn interior_cells(&self) -> impl Iterator<Item = (Vector3, f32)> + '_ {
+        let [dx, dy, _] = self.dims;
+        self.cells
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| **v > SURFACE)
+            .map(move |(index, depth)| {
+                let (x, y, z) = (index % dx, (index / dx) % dy, index / (dx * dy));
+                (
+                    self.center_of(x, y, z),
+                    (depth - SURFACE) as f32 * self.cell_size,
+                )
+            })
+    }
+
+    pub fn interior_volume(&self) -> f32 {
+        self.interior as f32 * self.cell_size.powi(3)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Collision {
+    pub objects: (ObjectId, ObjectId),
+    // Approximate volume of the overlapping region in LDU³.
+    pub volume: f32,
+    // Approximate penetration depth in LDU.
+    pub depth: f32,
+    pub position: Vector3,
+}
+
+struct Overlap {
+    volume: f32,
+    depth: f32,
+    position: Vector3,
+    samples: usize,
+}
+
+impl Default for Overlap {
+    fn default() -> Self {
+        Self {
+            volume: 0.0,
+            depth: 0.0,
+            position: Vector3::new(0.0, 0.0, 0.0),
+            samples: 0,
+        }
+    }
+}
+
+fn overlap_grids(a: (&VoxelGrid, &Matrix4), b: (&VoxelGrid, &Matrix4), overlap: &mut Overlap) {
+    let inverse_b = match b.1.invert() {
+        Some(m) => m,
+        None => return,
+    };
+    let a_to_b = inverse_b * a.1;
+    let cell_volume = a.0.cell_size.powi(3);
+
+    for (point, depth_a) in a.0.interior_cells() {
+        let local = (a_to_b * point.extend(1.0)).truncate();
+        if let Some(depth_b) = b.0.depth_at(&local) {
+            if depth_b <= 0.0 {
+                continue;
+            }
+            overlap.volume += cell_volume;
+            overlap.depth = overlap.depth.max(depth_a.min(depth_b));
+            overlap.position += (a.1 * point.extend(1.0)).truncate();
+            overlap.samples += 1;
+        }
+    }
+}
+
+impl<P: Clone + Eq + PartialEq + Hash> Model<P> {
+    fn collect_instances(
+        &self,
+        instances: &mut Vec<(P, Matrix4)>,
+        objects: &[Object<P>],
+        matrix: Matrix4,
+    ) {
+        for object in objects {
+            match &object.data {
+                ObjectInstance::Part(p) => instances.push((p.part.clone(), matrix * p.matrix)),
+                ObjectInstance::PartGroup(pg) => {
+                    if let Some(group) = self.object_groups.get(&pg.group_id) {
+                        self.collect_instances(instances, &group.objects, matrix * pg.matrix);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Reports pairs of objects in the group whose parts interpenetrate. Parts inside the
+    // same object (i.e. a submodel) are not checked against each other.
+    pub fn find_collisions(
+        &self,
+        group_id: Option<GroupId>,
+        querier: &impl PartGeometryQuerier<P>,
+        params: &CollisionParams,
+    ) -> Vec<Collision> {
+        let objects = match group_id {
+            Some(group_id) => match self.object_groups.get(&group_id) {
+                Some(group) => &group.objects,
+                None => return Vec::new(),
+            },
+            None => &self.objects,
+        };
+
+        let entries = objects
+            .iter()
+            .filter_map(|object| {
+                let mut instances = Vec::new();
+                self.collect_instances(
+                    &mut instances,
+                    std::slice::from_ref(object),
+                    Matrix4::identity(),
+                );
+
+                let mut bounding_box = BoundingBox3::nil();
+                let instances = instances
+                    .into_iter()
+                    .filter_map(|(alias, matrix)| {
+                        let part = querier.query_part_geometry(&alias)?;
+                        let instance_box = part.bounding_box.transform(&matrix);
+                        bounding_box.update(&instance_box);
+                        Some((alias, matrix, instance_box))
+                    })
+                    .collect::<Vec<_>>();
+                if instances.is_empty() {
+                    return None;
+                }
+
+                Some((object.id, instances, bounding_box))
+            })
+            .collect::<Vec<_>>();
+
+        let mut grids = HashMap::new();
+        for (alias, _, _) in entries
+            .iter()
+            .flat_map(|(_, instances, _)| instances.iter())
+        {
+            if !grids.contains_key(alias) {
+                let grid = querier
+                    .query_part_geometry(alias)
+                    .and_then(|part| VoxelGrid::from_part(part, params));
+                grids.insert(alias.clone(), grid);
+            }
+        }
+        let grid_for = |alias: &P| grids.get(alias).and_then(|v| v.as_ref());
+
+        let mut collisions = Vec::new();
+        for (i, (a_id, a_instances, a_box)) in entries.iter().enumerate() {
+            for (b_id, b_instances, b_box) in entries[i + 1..].iter() {
+                if !overlaps(a_box, b_box, params.tolerance) {
+                    continue;
+                }
+
+                let mut overlap = Overlap::default();
+                for (a_alias, a_matrix, a_instance_box) in a_instances.iter() {
+                    for (b_alias, b_matrix, b_instance_box) in b_instances.iter() {
+                        if !overlaps(a_instance_box, b_instance_box, params.tolerance) {
+                            continue;
+                        }
+
+                        let (a_grid, b_grid) = match (grid_for(a_alias), grid_for(b_alias)) {
+                            (Some(a), Some(b)) => (a, b),
+                            _ => continue,
+                        };
+                        // Smaller part is sampled against the bigger one.
+                        if a_grid.interior_volume() <= b_grid.interior_volume() {
+                            overlap_grids((a_grid, a_matrix), (b_grid, b_matrix), &mut overlap);
+                        } else {
+                            overlap_grids((b_grid, b_matrix), (a_grid, a_matrix), &mut overlap);
+                        }
+                    }
+                }
+
+                if overlap.samples > 0 && overlap.depth > params.tolerance {
+                    collisions.push(Collision {
+                        objects: (*a_id, *b_id),
+                        volume: overlap.volume,
+                        depth: overlap.depth,
+                        position: overlap.position / overlap.samples as f32,
+                    });
+                }
+            }
+        }
+
+        collisions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Zero;
+    use ldraw::{
+        color::ColorReference,
+        document::Document,
+        document::MultipartDocument,
+        elements::PartReference,
+        elements::{Command, Quad},
+        library::ResolutionResult,
+        Matrix4, PartAlias, Vector3, Vector4,
+    };
+
+    use super::{CollisionParams, VoxelGrid};
+    use crate::{
+        model::Model,
+        part::{bake_part_from_document, Part, PartGeometryQuerier},
+    };
+
+    struct Cube(Part);
+
+    impl PartGeometryQuerier<PartAlias> for Cube {
+        fn query_part_geometry(&self, _alias: &PartAlias) -> Option<&Part> {
+            Some(&self.0)
+        }
+    }
+
+    fn cube(size: f32) -> Part {
+        let h = size / 2.0;
+        let v = |x: f32, y: f32, z: f32| Vector4::new(x * h, y * h, z * h, 1.0);
+        let faces = [
+            [
+                v(-1., -1., -1.),
+                v(1., -1., -1.),
+                v(1., 1., -1.),
+                v(-1., 1., -1.),
+            ],
+            [
+                v(-1., -1., 1.),
+                v(-1., 1., 1.),
+                v(1., 1., 1.),
+                v(1., -1., 1.),
+            ],
+            [
+                v(-1., -1., -1.),
+                v(-1., 1., -1.),
+                v(-1., 1., 1.),
+                v(-1., -1., 1.),
+            ],
+            [
+                v(1., -1., -1.),
+                v(1., -1., 1.),
+                v(1., 1., 1.),
+                v(1., 1., -1.),
+            ],
+            [
+                v(-1., -1., -1.),
+                v(-1., -1., 1.),
+                v(1., -1., 1.),
+                v(1., -1., -1.),
+            ],
+            [
+                v(-1., 1., -1.),
+                v(1., 1., -1.),
+                v(1., 1., 1.),
+                v(-1., 1., 1.),
+            ],
+        ];
+        let document = Document {
+            commands: faces
+                .iter()
+                .map(|[a, b, c, d]| {
+                    Command::Quad(Quad {
+                        color: ColorReference::Current,
+                        a: *a,
+                        b: *b,
+                        c: *c,
+                        d: *d,
+                    })
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        bake_part_from_document(&document, &ResolutionResult::new(), false)
+    }
+
+    #[test]
+    fn test_voxel_depth() {
+        let grid = VoxelGrid::from_part(&cube(20.0), &CollisionParams::default()).unwrap();
+
+        assert!(grid.depth_at(&Vector3::zero()).unwrap() >= 8.0);
+        assert!(grid.depth_at(&Vector3::new(9.9, 0.0, 0.0)).unwrap() <= 1.0);
+        assert!(grid.depth_at(&Vector3::new(12.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn test_find_collisions() {
+        let reference = |x: f32| {
+            Command::PartReference(PartReference {
+                color: ColorReference::Current,
+                matrix: Matrix4::from_translation(Vector3::new(x, 0.0, 0.0)),
+                name: PartAlias::from("cube.dat"),
+            })
+        };
+        let document = MultipartDocument {
+            body: Document {
+                commands: vec![reference(0.0), reference(20.0), reference(30.0)],
+                ..Default::default()
+            },
+            subparts: Default::default(),
+        };
+        let model = Model::from_ldraw_multipart_document_sync(&document);
+        let ids = model.objects.iter().map(|v| v.id).collect::<Vec<_>>();
+
+        let collisions =
+            model.find_collisions(None, &Cube(cube(20.0)), &CollisionParams::default());
+
+        // Touching cubes are fine, the last one sinks halfway into the second one.
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(collisions[0].objects, (ids[1], ids[2]));
+        assert!(collisions[0].depth >= 2.0);
+        assert!(collisions[0].volume > 1000.0);
+    }
+}
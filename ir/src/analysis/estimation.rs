@@ -0,0 +1,242 @@
+use std::{collections::HashMap, error::Error, fmt, io::BufRead};
+
+use ldraw::{color::ColorReference, PartAlias};
+use serde::{Deserialize, Serialize};
+
+use super::summary::ModelSummary;
+
+// Supplies per-part data such as weight in grams or unit price. Implementations may
+// return None for parts they don't know of.
+pub trait PartEstimateQuerier<P> {
+    fn query_part_weight(&self, part: &P, color: &ColorReference) -> Option<f32>;
+    fn query_part_price(&self, part: &P, color: &ColorReference) -> Option<f32>;
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Estimate {
+    pub weight: f32,
+    pub price: f32,
+    // Number of parts without weight or price data.
+    pub missing_weight: usize,
+    pub missing_price: usize,
+}
+
+impl Estimate {
+    pub fn is_complete(&self) -> bool {
+        self.missing_weight == 0 && self.missing_price == 0
+    }
+}
+
+impl<P> ModelSummary<P> {
+    pub fn estimate(&self, querier: &impl PartEstimateQuerier<P>) -> Estimate {
+        let mut estimate = Estimate::default();
+
+        for lot in self.lots.iter() {
+            match querier.query_part_weight(&lot.part, &lot.color) {
+                Some(weight) => estimate.weight += weight * lot.count as f32,
+                None => estimate.missing_weight += lot.count,
+            }
+            match querier.query_part_price(&lot.part, &lot.color) {
+                Some(price) => estimate.price += price * lot.count as f32,
+                None => estimate.missing_price += lot.count,
+            }
+        }
+
+        estimate
+    }
+}
+
+#[derive(Debug)]
+pub enum PartDataError {
+    IoError(std::io::Error),
+    InvalidLine(usize, String),
+}
+
+impl From<std::io::Error> for PartDataError {
+    fn from(e: std::io::Error) -> PartDataError {
+        PartDataError::IoError(e)
+    }
+}
+
+impl fmt::Display for PartDataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PartDataError::IoError(err) => write!(f, "{}", err),
+            PartDataError::InvalidLine(line, content) => {
+                write!(f, "Invalid entry at line {}: {}", line, content)
+            }
+        }
+    }
+}
+
+impl Error for PartDataError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PartDataError::IoError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct PartData {
+    weight: Option<f32>,
+    price: Option<f32>,
+}
+
+// Reads comma separated `part,color,weight,price` rows. Empty or `*` color applies to
+// all colors of the part, and empty weight or price means unknown. Lines starting with
+// `#` and a header row beginning with `part` are skipped.
+#[derive(Clone, Debug, Default)]
+pub struct CsvPartData {
+    entries: HashMap<(PartAlias, Option<u32>), PartData>,
+}
+
+impl CsvPartData {
+    pub fn parse(reader: impl BufRead) -> Result<Self, PartDataError> {
+        let mut entries = HashMap::new();
+        // The header, if any, comes before every entry but may follow comments.
+        let mut first = true;
+
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+
+            let fields = trimmed.split(',').map(str::trim).collect::<Vec<_>>();
+            if std::mem::take(&mut first) && fields[0].eq_ignore_ascii_case("part") {
+                continue;
+            }
+            if fields.len() < 3 || fields[0].is_empty() {
+                return Err(PartDataError::InvalidLine(index + 1, line));
+            }
+
+            let value = |field: Option<&&str>| -> Result<Option<f32>, PartDataError> {
+                match field {
+                    None | Some(&"") => Ok(None),
+                    Some(v) => v
+                        .parse()
+                        .map(Some)
+                        .map_err(|_| PartDataError::InvalidLine(index + 1, line.clone())),
+                }
+            };
+            let color = match fields[1] {
+                "" | "*" => None,
+                v => Some(
+                    v.parse()
+                        .map_err(|_| PartDataError::InvalidLine(index + 1, line.clone()))?,
+                ),
+            };
+            let data = PartData {
+                weight: value(fields.get(2))?,
+                price: value(fields.get(3))?,
+            };
+
+            entries.insert((PartAlias::from(fields[0]), color), data);
+        }
+
+        Ok(Self { entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Color specific entries take precedence over ones for any color.
+    fn query<T>(
+        &self,
+        part: &PartAlias,
+        color: &ColorReference,
+        field: impl Fn(&PartData) -> Option<T>,
+    ) -> Option<T> {
+        self.entries
+            .get(&(part.clone(), Some(color.code())))
+            .and_then(&field)
+            .or_else(|| self.entries.get(&(part.clone(), None)).and_then(&field))
+    }
+}
+
+impl PartEstimateQuerier<PartAlias> for CsvPartData {
+    fn query_part_weight(&self, part: &PartAlias, color: &ColorReference) -> Option<f32> {
+        self.query(part, color, |v| v.weight)
+    }
+
+    fn query_part_price(&self, part: &PartAlias, color: &ColorReference) -> Option<f32> {
+        self.query(part, color, |v| v.price)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ldraw::{color::ColorReference, PartAlias};
+
+    use super::{CsvPartData, PartEstimateQuerier};
+    use crate::analysis::summary::{Lot, ModelSummary};
+
+    #[test]
+    fn test_estimate() {
+        let data = "part,color,weight,price
+# 2x4 brick
+3001.dat,*,2.32,0.10
+3001.dat,4,,0.25
+3003.dat,,1.16,
+";
+        let data = CsvPartData::parse(data.as_bytes()).unwrap();
+        assert_eq!(data.len(), 3);
+
+        let red = ColorReference::Unknown(4);
+        let brick = PartAlias::from("3001.dat");
+        assert_eq!(data.query_part_weight(&brick, &red), Some(2.32));
+        assert_eq!(data.query_part_price(&brick, &red), Some(0.25));
+
+        let lot = |part: &str, color: ColorReference, count: usize| Lot {
+            part: PartAlias::from(part),
+            color,
+            count,
+        };
+        let summary = ModelSummary {
+            parts: 6,
+            lots: vec![
+                lot("3001.dat", red, 2),
+                lot("3001.dat", ColorReference::Unknown(1), 1),
+                lot("3003.dat", ColorReference::Unknown(1), 2),
+                lot("3004.dat", ColorReference::Unknown(1), 1),
+            ],
+            steps: 1,
+            dimensions: None,
+            weight: 0.0,
+            complete: true,
+        };
+
+        let estimate = summary.estimate(&data);
+        assert!((estimate.weight - 9.28).abs() < 0.001);
+        assert!((estimate.price - 0.6).abs() < 0.001);
+        assert_eq!(estimate.missing_weight, 1);
+        assert_eq!(estimate.missing_price, 3);
+        assert!(!estimate.is_complete());
+
+        assert!(CsvPartData::parse("3001.dat,4,heavy,\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_header_after_comments() {
+        let data = "# Prices as of 2024
+
+Part,Color,Weight,Price
+3001.dat,*,2.32,0.10
+";
+        let data = CsvPartData::parse(data.as_bytes()).unwrap();
+        assert_eq!(data.len(), 1);
+
+        // Only the first row may be a header.
+        assert!(
+            CsvPartData::parse("3001.dat,*,2.32,\npart,color,weight,price\n".as_bytes()).is_err()
+        );
+    }
+}
@@ -0,0 +1,7 @@
+pub mod collisions;
+pub mod estimation;
+pub mod query;
+pub mod summary;
+pub mod validation;
+
+pub use validation::validate;
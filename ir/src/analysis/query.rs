@@ -0,0 +1,294 @@
+use std::{collections::HashSet, hash::Hash};
+
+use cgmath::SquareMatrix;
+use ldraw::{color::ColorReference, graph::CycleGuard, Matrix4, PartAlias};
+
+use crate::{
+    geometry::BoundingBox3,
+    model::{GroupId, Model, Object, ObjectId, ObjectInstance},
+    part::PartDimensionQuerier,
+};
+
+// Criteria for Model::query(). Empty criteria let everything pass, and a part has to pass
+// all of the given ones to match.
+#[derive(Clone, Debug, Default)]
+pub struct ModelQuery {
+    // Patterns matched against part names regardless of case, where '*' stands for any run
+    // of characters and '?' for a single one. Parts matching any of them pass.
+    pub aliases: Vec<String>,
+    // Color codes, after parts inherit colors of submodels they are placed in.
+    pub colors: Vec<u32>,
+    // Region overlapping bounds of parts. Parts without known dimensions never pass.
+    pub region: Option<BoundingBox3>,
+    // Submodels parts are placed through, at any depth.
+    pub groups: Vec<GroupId>,
+}
+
+impl ModelQuery {
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+            && self.colors.is_empty()
+            && self.region.is_none()
+            && self.groups.is_empty()
+    }
+
+    fn matches_alias(&self, alias: &PartAlias) -> bool {
+        self.aliases.is_empty()
+            || self
+                .aliases
+                .iter()
+                .any(|pattern| matches_pattern(&PartAlias::normalize(pattern), &alias.normalized))
+    }
+
+    fn matches_color(&self, color: &ColorReference) -> bool {
+        self.colors.is_empty() || self.colors.contains(&color.code())
+    }
+
+    fn matches_groups(&self, ancestors: &[GroupId]) -> bool {
+        self.groups.is_empty() || self.groups.iter().any(|v| ancestors.contains(v))
+    }
+}
+
+fn matches_pattern(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+
+    let (mut p, mut t) = (0, 0);
+    // Position of the last '*' seen, and of the text it has been matched up to.
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+impl<P: Clone + Eq + PartialEq + Hash + Into<PartAlias>> Model<P> {
+    fn matches_query(
+        &self,
+        query: &ModelQuery,
+        objects: &[Object<P>],
+        matrix: Matrix4,
+        color: &ColorReference,
+        ancestors: &mut CycleGuard<GroupId>,
+        querier: &impl PartDimensionQuerier<P>,
+    ) -> bool {
+        objects.iter().any(|object| match &object.data {
+            ObjectInstance::Part(p) => {
+                // Parts in submodels inherit color of the submodel.
+                let color = match &p.color {
+                    ColorReference::Current => color,
+                    v => v,
+                };
+                query.matches_alias(&p.part.clone().into())
+                    && query.matches_color(color)
+                    && query.matches_groups(ancestors.path())
+                    && match &query.region {
+                        Some(region) => match querier.query_part_dimension(&p.part) {
+                            Some(bounding_box) if !bounding_box.is_null() => bounding_box
+                                .transform(&(matrix * p.matrix))
+                                .intersects(region),
+                            _ => false,
+                        },
+                        None => true,
+                    }
+            }
+            ObjectInstance::PartGroup(pg) => {
+                let Some(group) = self.object_groups.get(&pg.group_id) else {
+                    return false;
+                };
+                if !ancestors.enter(pg.group_id) {
+                    return false;
+                }
+                let color = match &pg.color {
+                    ColorReference::Current => color,
+                    v => v,
+                };
+
+                let result = self.matches_query(
+                    query,
+                    &group.objects,
+                    matrix * pg.matrix,
+                    color,
+                    ancestors,
+                    querier,
+                );
+                ancestors.leave();
+                result
+            }
+            _ => false,
+        })
+    }
+
+    // Finds objects directly in the given group, or the top level model if none is given,
+    // that are parts matching the query or submodels with any part under them that does.
+    // The result is in terms of the same objects the viewer selects and highlights.
+    pub fn query(
+        &self,
+        query: &ModelQuery,
+        group_id: Option<GroupId>,
+        querier: &impl PartDimensionQuerier<P>,
+    ) -> HashSet<ObjectId> {
+        let objects = match group_id {
+            Some(group_id) => match self.object_groups.get(&group_id) {
+                Some(group) => &group.objects[..],
+                None => &[],
+            },
+            None => &self.objects[..],
+        };
+        let mut ancestors = CycleGuard::new();
+        if let Some(group_id) = group_id {
+            ancestors.enter(group_id);
+        }
+
+        objects
+            .iter()
+            .filter(|object| {
+                self.matches_query(
+                    query,
+                    std::slice::from_ref(*object),
+                    Matrix4::identity(),
+                    &ColorReference::Current,
+                    &mut ancestors,
+                    querier,
+                )
+            })
+            .map(|object| object.id)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ldraw::{color::ColorReference, document::MultipartDocument, PartAlias, Vector3};
+
+    use super::{matches_pattern, ModelQuery};
+    use crate::{
+        geometry::BoundingBox3,
+        model::Model,
+        testing::{document, reference, Cubes},
+    };
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("3001.dat", "3001.dat"));
+        assert!(matches_pattern("300?.dat", "3002.dat"));
+        assert!(matches_pattern("30*.dat", "3001.dat"));
+        assert!(matches_pattern("*1*", "3001.dat"));
+        assert!(matches_pattern("*", ""));
+        assert!(!matches_pattern("30*.ldr", "3001.dat"));
+        assert!(!matches_pattern("300?.dat", "30010.dat"));
+    }
+
+    #[test]
+    fn test_query() {
+        let mut subparts = HashMap::new();
+        subparts.insert(
+            PartAlias::from("sub.ldr"),
+            document(
+                "sub.ldr",
+                vec![
+                    reference("3001.dat", ColorReference::Current, 0.0),
+                    reference("3003.dat", ColorReference::Unknown(1), 0.0),
+                ],
+            ),
+        );
+        let document = MultipartDocument {
+            body: document(
+                "main.ldr",
+                vec![
+                    reference("3001.dat", ColorReference::Unknown(4), 0.0),
+                    reference("3002.dat", ColorReference::Unknown(1), 10.0),
+                    reference("sub.ldr", ColorReference::Unknown(4), 100.0),
+                ],
+            ),
+            subparts,
+        };
+        let model = Model::<PartAlias>::from_ldraw_multipart_document_sync(&document);
+        let ids = model.objects.iter().map(|v| v.id).collect::<Vec<_>>();
+        let group = *model.object_groups.keys().next().unwrap();
+
+        let query = |query: ModelQuery| {
+            let mut result = model
+                .query(&query, None, &Cubes(1.0))
+                .into_iter()
+                .map(|id| ids.iter().position(|v| *v == id).unwrap())
+                .collect::<Vec<_>>();
+            result.sort();
+            result
+        };
+
+        assert_eq!(query(ModelQuery::default()), vec![0, 1, 2]);
+        assert_eq!(
+            query(ModelQuery {
+                aliases: vec!["3001.DAT".to_string()],
+                ..Default::default()
+            }),
+            vec![0, 2]
+        );
+        assert_eq!(
+            query(ModelQuery {
+                colors: vec![1],
+                ..Default::default()
+            }),
+            vec![1, 2]
+        );
+        // Color of the submodel applies to parts in it.
+        assert_eq!(
+            query(ModelQuery {
+                aliases: vec!["3001.dat".to_string()],
+                colors: vec![4],
+                region: Some(BoundingBox3::new(
+                    &Vector3::new(95.0, -5.0, -5.0),
+                    &Vector3::new(105.0, 5.0, 5.0),
+                )),
+                ..Default::default()
+            }),
+            vec![2]
+        );
+        assert_eq!(
+            query(ModelQuery {
+                groups: vec![group],
+                ..Default::default()
+            }),
+            vec![2]
+        );
+        assert_eq!(
+            query(ModelQuery {
+                aliases: vec!["3002.dat".to_string()],
+                groups: vec![group],
+                ..Default::default()
+            }),
+            Vec::<usize>::new()
+        );
+
+        let in_group = model.query(
+            &ModelQuery {
+                colors: vec![1],
+                ..Default::default()
+            },
+            Some(group),
+            &Cubes(1.0),
+        );
+        assert_eq!(in_group.len(), 1);
+    }
+}
@@ -0,0 +1,262 @@
+use std::{fmt, hash::Hash};
+
+use ldraw::{
+    color::ColorReference,
+    units::{LDU_PER_STUD, MM_PER_LDU},
+    PartAlias, Vector3,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    model::{GroupId, Model, Object, ObjectInstance},
+    part::PartDimensionQuerier,
+};
+
+// Density of ABS plastic in g/cm^3.
+const ABS_DENSITY: f32 = 1.05;
+// Most parts are hollow, so only a fraction of their bounding box is actually plastic.
+const FILL_FACTOR: f32 = 0.35;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Lot<P> {
+    pub part: P,
+    pub color: ColorReference,
+    pub count: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ModelSummary<P> {
+    pub parts: usize,
+    // Distinct part and color combinations, most used first.
+    pub lots: Vec<Lot<P>>,
+    pub steps: usize,
+    // Size of the bounding box in LDU, if any part has known dimensions.
+    pub dimensions: Option<Vector3>,
+    // Estimated weight in grams.
+    pub weight: f32,
+    // False if dimensions of some parts were not available.
+    pub complete: bool,
+}
+
+impl<P> ModelSummary<P> {
+    pub fn unique_lots(&self) -> usize {
+        self.lots.len()
+    }
+
+    pub fn dimensions_in_studs(&self) -> Option<Vector3> {
+        self.dimensions.map(|v| v / LDU_PER_STUD)
+    }
+
+    pub fn dimensions_in_cm(&self) -> Option<Vector3> {
+        self.dimensions.map(|v| v * MM_PER_LDU / 10.0)
+    }
+}
+
+impl<P> fmt::Display for ModelSummary<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} parts, {} lots, {} steps",
+            self.parts,
+            self.unique_lots(),
+            self.steps
+        )?;
+        if let (Some(studs), Some(cm)) = (self.dimensions_in_studs(), self.dimensions_in_cm()) {
+            write!(
+                f,
+                ", {:.1} x {:.1} x {:.1} studs ({:.1} x {:.1} x {:.1} cm)",
+                studs.x, studs.z, studs.y, cm.x, cm.z, cm.y
+            )?;
+        }
+        write!(
+            f,
+            ", {}{:.1} g",
+            if self.complete { "~" } else { ">" },
+            self.weight
+        )
+    }
+}
+
+fn estimate_weight(volume: f32) -> f32 {
+    let cm_per_ldu = MM_PER_LDU / 10.0;
+    volume * cm_per_ldu.powi(3) * FILL_FACTOR * ABS_DENSITY
+}
+
+impl<P: Clone + Eq + PartialEq + Hash + From<PartAlias>> Model<P> {
+    fn collect_lots(
+        &self,
+        lots: &mut Vec<Lot<P>>,
+        objects: &[Object<P>],
+        color: &ColorReference,
+        querier: &impl PartDimensionQuerier<P>,
+        weight: &mut f32,
+        complete: &mut bool,
+    ) {
+        for object in objects {
+            match &object.data {
+                ObjectInstance::Part(p) => {
+                    // Parts in submodels inherit color of the submodel.
+                    let color = match &p.color {
+                        ColorReference::Current => color,
+                        v => v,
+                    };
+                    match lots
+                        .iter_mut()
+                        .find(|v| v.part == p.part && v.color == *color)
+                    {
+                        Some(lot) => lot.count += 1,
+                        None => lots.push(Lot {
+                            part: p.part.clone(),
+                            color: color.clone(),
+                            count: 1,
+                        }),
+                    }
+
+                    match querier.query_part_dimension(&p.part) {
+                        Some(bounding_box) if !bounding_box.is_null() => {
+                            *weight += estimate_weight(
+                                bounding_box.len_x() * bounding_box.len_y() * bounding_box.len_z(),
+                            );
+                        }
+                        _ => *complete = false,
+                    }
+                }
+                ObjectInstance::PartGroup(pg) => {
+                    if let Some(group) = self.object_groups.get(&pg.group_id) {
+                        let color = match &pg.color {
+                            ColorReference::Current => color,
+                            v => v,
+                        };
+                        self.collect_lots(lots, &group.objects, color, querier, weight, complete);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn summary(
+        &self,
+        group_id: Option<GroupId>,
+        querier: &impl PartDimensionQuerier<P>,
+    ) -> ModelSummary<P> {
+        let objects = match group_id {
+            Some(group_id) => match self.object_groups.get(&group_id) {
+                Some(group) => &group.objects[..],
+                None => &[],
+            },
+            None => &self.objects[..],
+        };
+
+        let mut lots = Vec::new();
+        let mut weight = 0.0;
+        let mut complete = true;
+        self.collect_lots(
+            &mut lots,
+            objects,
+            &ColorReference::Current,
+            querier,
+            &mut weight,
+            &mut complete,
+        );
+        lots.sort_by_key(|v| std::cmp::Reverse(v.count));
+
+        let steps = objects
+            .split(|v| matches!(v.data, ObjectInstance::Step))
+            .filter(|v| !v.is_empty())
+            .count();
+
+        let dimensions = self
+            .calculate_bounding_box(group_id, querier)
+            .filter(|(bounding_box, _)| !bounding_box.is_null())
+            .map(|(bounding_box, _)| {
+                Vector3::new(
+                    bounding_box.len_x(),
+                    bounding_box.len_y(),
+                    bounding_box.len_z(),
+                )
+            });
+
+        ModelSummary {
+            parts: lots.iter().map(|v| v.count).sum(),
+            lots,
+            steps,
+            dimensions,
+            weight,
+            complete,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ldraw::{
+        color::ColorReference,
+        document::MultipartDocument,
+        elements::{Command, Meta},
+        PartAlias, Vector3,
+    };
+
+    use crate::{
+        model::Model,
+        testing::{document, reference, Cubes},
+    };
+
+    #[test]
+    fn test_summary() {
+        let document = MultipartDocument {
+            body: document(
+                "",
+                vec![
+                    reference("brick.dat", ColorReference::Current, 0.0),
+                    reference("brick.dat", ColorReference::Current, 20.0),
+                    Command::Meta(Meta::Step),
+                    reference("brick.dat", ColorReference::Unknown(4), 40.0),
+                    Command::Meta(Meta::Step),
+                ],
+            ),
+            subparts: Default::default(),
+        };
+        let model = Model::from_ldraw_multipart_document_sync(&document);
+
+        let summary = model.summary(None, &Cubes(10.0));
+
+        assert_eq!(summary.parts, 3);
+        assert_eq!(summary.unique_lots(), 2);
+        assert_eq!(summary.lots[0].count, 2);
+        assert_eq!(summary.steps, 2);
+        assert_eq!(
+            summary.dimensions_in_studs(),
+            Some(Vector3::new(3.0, 1.0, 1.0))
+        );
+        assert!((summary.weight - 0.564).abs() < 0.001);
+        assert!(summary.complete);
+    }
+
+    #[test]
+    fn test_summary_of_circular_document() {
+        let mut subparts = HashMap::new();
+        subparts.insert(
+            PartAlias::from("loop.ldr"),
+            document(
+                "loop.ldr",
+                vec![
+                    reference("brick.dat", ColorReference::Current, 0.0),
+                    reference("loop.ldr", ColorReference::Current, 20.0),
+                ],
+            ),
+        );
+        let document = MultipartDocument {
+            body: document(
+                "",
+                vec![reference("loop.ldr", ColorReference::Current, 0.0)],
+            ),
+            subparts,
+        };
+        let model = Model::from_ldraw_multipart_document_sync(&document);
+
+        assert_eq!(model.summary(None, &Cubes(10.0)).parts, 1);
+    }
+}
@@ -0,0 +1,407 @@
+use std::collections::HashMap;
+
+use cgmath::SquareMatrix;
+use ldraw::{
+    color::ColorReference,
+    document::{BfcCertification, Document, MultipartDocument},
+    elements::{Command, Meta},
+    error::DocumentParseError,
+    library::ResolutionResult,
+    Matrix4, PartAlias, Vector4,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug)]
+pub struct ValidationParams {
+    // Coordinates farther than this from the origin (in LDU) are reported.
+    pub max_coordinate: f32,
+    // Parts of the same kind and color whose matrices differ less than this are duplicates.
+    pub duplicate_tolerance: f32,
+    // Reports documents without description, name or author.
+    pub check_headers: bool,
+}
+
+impl Default for ValidationParams {
+    fn default() -> Self {
+        Self {
+            max_coordinate: 100_000.0,
+            duplicate_tolerance: 0.01,
+            check_headers: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelStatistics {
+    pub documents: usize,
+    pub part_references: usize,
+    pub unique_parts: usize,
+    pub lines: usize,
+    pub triangles: usize,
+    pub quads: usize,
+    pub optional_lines: usize,
+    pub steps: usize,
+}
+
+// Commands are referred to by their index within the document named `document`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ValidationIssue {
+    UnknownColor {
+        document: String,
+        command: usize,
+        code: u32,
+    },
+    MissingPart {
+        document: String,
+        command: usize,
+        name: String,
+    },
+    UncertifiedBfc {
+        name: String,
+    },
+    SingularMatrix {
+        document: String,
+        command: usize,
+        name: String,
+    },
+    DuplicatePlacement {
+        document: String,
+        command: usize,
+        duplicate_of: usize,
+        name: String,
+    },
+    OversizedCoordinate {
+        document: String,
+        command: usize,
+        value: f32,
+    },
+    MissingHeader {
+        document: String,
+        header: String,
+    },
+    // Lines skipped by the lenient parser, numbered from the start of the file.
+    MalformedLine {
+        line: usize,
+        message: String,
+    },
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ValidationReport {
+    pub statistics: ModelStatistics,
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub fn add_parse_errors(&mut self, errors: &[DocumentParseError]) {
+        let issues = errors.iter().map(|e| ValidationIssue::MalformedLine {
+            line: e.line,
+            message: e.error.to_string(),
+        });
+        self.issues.splice(0..0, issues);
+    }
+}
+
+fn unknown_color_code(color: &ColorReference) -> Option<u32> {
+    match color {
+        ColorReference::Unknown(code) | ColorReference::Unresolved(code) => Some(*code),
+        _ => None,
+    }
+}
+
+fn matrices_equal(a: &Matrix4, b: &Matrix4, tolerance: f32) -> bool {
+    let a: &[f32; 16] = a.as_ref();
+    let b: &[f32; 16] = b.as_ref();
+    a.iter()
+        .zip(b.iter())
+        .all(|(a, b)| (a - b).abs() < tolerance)
+}
+
+fn max_coordinate<'a>(vertices: impl IntoIterator<Item = &'a Vector4>) -> f32 {
+    vertices
+        .into_iter()
+        .flat_map(|v| [v.x.abs(), v.y.abs(), v.z.abs()])
+        .fold(0.0, f32::max)
+}
+
+fn validate_document(
+    document: &Document,
+    parent: &MultipartDocument,
+    resolution_result: &ResolutionResult,
+    params: &ValidationParams,
+    statistics: &mut ModelStatistics,
+    issues: &mut Vec<ValidationIssue>,
+) {
+    let name = &document.name;
+
+    if params.check_headers {
+        for (header, missing) in [
+            ("description", document.description.is_empty()),
+            ("Name", document.name.is_empty()),
+            ("Author", document.author.is_empty()),
+        ] {
+            if missing {
+                issues.push(ValidationIssue::MissingHeader {
+                    document: name.clone(),
+                    header: header.to_string(),
+                });
+            }
+        }
+    }
+
+    let mut placements: HashMap<&PartAlias, Vec<(usize, &ColorReference, &Matrix4)>> =
+        HashMap::new();
+
+    for (index, command) in document.commands.iter().enumerate() {
+        let (color, coordinate) = match command {
+            Command::PartReference(r) => {
+                statistics.part_references += 1;
+
+                if !parent.subparts.contains_key(&r.name)
+                    && resolution_result.query(&r.name, true).is_none()
+                {
+                    issues.push(ValidationIssue::MissingPart {
+                        document: name.clone(),
+                        command: index,
+                        name: r.name.original.clone(),
+                    });
+                }
+
+                if r.matrix.determinant().abs() < f32::EPSILON {
+                    issues.push(ValidationIssue::SingularMatrix {
+                        document: name.clone(),
+                        command: index,
+                        name: r.name.original.clone(),
+                    });
+                }
+
+                let entries = placements.entry(&r.name).or_default();
+                if let Some((duplicate_of, _, _)) = entries.iter().find(|(_, color, matrix)| {
+                    *color == &r.color
+                        && matrices_equal(matrix, &r.matrix, params.duplicate_tolerance)
+                }) {
+                    issues.push(ValidationIssue::DuplicatePlacement {
+                        document: name.clone(),
+                        command: index,
+                        duplicate_of: *duplicate_of,
+                        name: r.name.original.clone(),
+                    });
+                }
+                entries.push((index, &r.color, &r.matrix));
+
+                (&r.color, max_coordinate([&r.matrix.w]))
+            }
+            Command::Line(l) => {
+                statistics.lines += 1;
+                (&l.color, max_coordinate([&l.a, &l.b]))
+            }
+            Command::Triangle(t) => {
+                statistics.triangles += 1;
+                (&t.color, max_coordinate([&t.a, &t.b, &t.c]))
+            }
+            Command::Quad(q) => {
+                statistics.quads += 1;
+                (&q.color, max_coordinate([&q.a, &q.b, &q.c, &q.d]))
+            }
+            Command::OptionalLine(l) => {
+                statistics.optional_lines += 1;
+                (&l.color, max_coordinate([&l.a, &l.b, &l.c, &l.d]))
+            }
+            Command::Meta(Meta::Step) => {
+                statistics.steps += 1;
+                continue;
+            }
+            Command::Meta(_) => continue,
+        };
+
+        if let Some(code) = unknown_color_code(color) {
+            issues.push(ValidationIssue::UnknownColor {
+                document: name.clone(),
+                command: index,
+                code,
+            });
+        }
+
+        if coordinate > params.max_coordinate {
+            issues.push(ValidationIssue::OversizedCoordinate {
+                document: name.clone(),
+                command: index,
+                value: coordinate,
+            });
+        }
+    }
+}
+
+fn is_uncertified(document: &Document) -> bool {
+    document.has_primitives() && !matches!(document.bfc, BfcCertification::Certify(_))
+}
+
+pub fn validate(
+    document: &MultipartDocument,
+    resolution_result: &ResolutionResult,
+    params: &ValidationParams,
+) -> ValidationReport {
+    let mut statistics = ModelStatistics::default();
+    let mut issues = Vec::new();
+
+    let mut subparts = document.subparts.iter().collect::<Vec<_>>();
+    subparts.sort_by(|a, b| a.0.normalized.cmp(&b.0.normalized));
+
+    for subpart in [&document.body]
+        .into_iter()
+        .chain(subparts.iter().map(|(_, v)| *v))
+    {
+        statistics.documents += 1;
+        validate_document(
+            subpart,
+            document,
+            resolution_result,
+            params,
+            &mut statistics,
+            &mut issues,
+        );
+    }
+    statistics.unique_parts = document.list_dependencies().len();
+
+    let mut uncertified = subparts
+        .iter()
+        .filter(|(_, v)| is_uncertified(v))
+        .map(|(alias, _)| alias.original.clone())
+        .collect::<Vec<_>>();
+    let mut dependencies = resolution_result
+        .list_dependencies()
+        .into_iter()
+        .filter(|alias| !document.subparts.contains_key(alias))
+        .filter_map(|alias| {
+            resolution_result
+                .query(&alias, true)
+                .filter(|(part, _)| is_uncertified(&part.body))
+                .map(|_| alias.original)
+        })
+        .collect::<Vec<_>>();
+    dependencies.sort();
+    uncertified.extend(dependencies);
+    issues.extend(
+        uncertified
+            .into_iter()
+            .map(|name| ValidationIssue::UncertifiedBfc { name }),
+    );
+
+    ValidationReport { statistics, issues }
+}
+
+#[cfg(test)]
+mod tests {
+    use ldraw::{color::ColorCatalog, parser::parse_multipart_document};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_validate() {
+        let colors = ColorCatalog::new();
+        let document = "0 FILE main.ldr
+0 Main
+0 Name: main.ldr
+1 16 0 0 0 1 0 0 0 1 0 0 0 1 sub.ldr
+1 16 0 0 0 1 0 0 0 1 0 0 0 1 sub.ldr
+1 16 0 0 0 0 0 0 0 0 0 0 0 0 sub.ldr
+1 16 0 0 0 1 0 0 0 1 0 0 0 1 missing.dat
+0 STEP
+2 999 0 0 0 200000 0 0
+
+0 FILE sub.ldr
+0 Sub
+0 Name: sub.ldr
+3 16 0 0 0 1 0 0 0 0 1
+";
+        let document = parse_multipart_document(&mut document.as_bytes(), &colors)
+            .await
+            .unwrap();
+
+        let report = validate(
+            &document,
+            &ResolutionResult::default(),
+            &ValidationParams::default(),
+        );
+
+        assert_eq!(
+            report.statistics,
+            ModelStatistics {
+                documents: 2,
+                part_references: 4,
+                unique_parts: 1,
+                lines: 1,
+                triangles: 1,
+                quads: 0,
+                optional_lines: 0,
+                steps: 1,
+            }
+        );
+
+        let main = String::from("main.ldr");
+        assert_eq!(
+            report.issues,
+            vec![
+                ValidationIssue::DuplicatePlacement {
+                    document: main.clone(),
+                    command: 1,
+                    duplicate_of: 0,
+                    name: "sub.ldr".into(),
+                },
+                ValidationIssue::SingularMatrix {
+                    document: main.clone(),
+                    command: 2,
+                    name: "sub.ldr".into(),
+                },
+                ValidationIssue::MissingPart {
+                    document: main.clone(),
+                    command: 3,
+                    name: "missing.dat".into(),
+                },
+                ValidationIssue::UnknownColor {
+                    document: main.clone(),
+                    command: 5,
+                    code: 999,
+                },
+                ValidationIssue::OversizedCoordinate {
+                    document: main,
+                    command: 5,
+                    value: 200000.0,
+                },
+                ValidationIssue::UncertifiedBfc {
+                    name: "sub.ldr".into(),
+                },
+            ]
+        );
+        assert!(!report.is_valid());
+
+        let report = validate(
+            &document,
+            &ResolutionResult::default(),
+            &ValidationParams {
+                check_headers: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            report.issues[0],
+            ValidationIssue::MissingHeader {
+                document: "main.ldr".into(),
+                header: "Author".into(),
+            }
+        );
+        assert_eq!(
+            report
+                .issues
+                .iter()
+                .filter(|v| matches!(v, ValidationIssue::MissingHeader { .. }))
+                .count(),
+            2
+        );
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Matrix, Matrix3, Quaternion, SquareMatrix, VectorSpace};
+use ldraw::Matrix4;
+use serde::{Deserialize, Serialize};
+
+use crate::model::ObjectId;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    // Holds until the next keyframe.
+    Step,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    // In seconds from the beginning of the animation.
+    pub time: f32,
+    // Replaces the matrix the object is placed with.
+    pub matrix: Matrix4,
+    pub visible: bool,
+    // How the way to the next keyframe is filled in.
+    pub interpolation: Interpolation,
+}
+
+impl Keyframe {
+    pub fn new(time: f32, matrix: Matrix4) -> Self {
+        Self {
+            time,
+            matrix,
+            visible: true,
+            interpolation: Interpolation::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrackSample {
+    pub matrix: Matrix4,
+    pub visible: bool,
+}
+
+impl From<&Keyframe> for TrackSample {
+    fn from(keyframe: &Keyframe) -> Self {
+        Self {
+            matrix: keyframe.matrix,
+            visible: keyframe.visible,
+        }
+    }
+}
+
+// Keyframes of a single object, ordered by time.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Track {
+    keyframes: Vec<Keyframe>,
+}
+
+impl Track {
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    // Replaces a keyframe at the same time, if any.
+    pub fn insert(&mut self, keyframe: Keyframe) {
+        match self
+            .keyframes
+            .binary_search_by(|v| v.time.total_cmp(&keyframe.time))
+        {
+            Ok(index) => self.keyframes[index] = keyframe,
+            Err(index) => self.keyframes.insert(index, keyframe),
+        }
+    }
+
+    pub fn remove(&mut self, time: f32) -> Option<Keyframe> {
+        let index = self
+            .keyframes
+            .binary_search_by(|v| v.time.total_cmp(&time))
+            .ok()?;
+        Some(self.keyframes.remove(index))
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map(|v| v.time).unwrap_or(0.0)
+    }
+
+    // The first and the last keyframes hold before and after them.
+    pub fn sample(&self, time: f32) -> Option<TrackSample> {
+        let first = self.keyframes.first()?;
+        let next = self.keyframes.partition_point(|v| v.time <= time);
+        if next == 0 {
+            return Some(first.into());
+        }
+
+        let current = &self.keyframes[next - 1];
+        let following = match self.keyframes.get(next) {
+            Some(v) if current.interpolation == Interpolation::Linear => v,
+            _ => return Some(current.into()),
+        };
+
+        let t = (time - current.time) / (following.time - current.time);
+        Some(TrackSample {
+            matrix: interpolate_matrix(&current.matrix, &following.matrix, t),
+            visible: current.visible,
+        })
+    }
+}
+
+// Keyframed placement and visibility of objects, keyed by their ids in the model.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Animation {
+    pub tracks: HashMap<ObjectId, Track>,
+    // Starts over once the longest track ends.
+    pub looping: bool,
+}
+
+impl Animation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_keyframe(&mut self, id: ObjectId, keyframe: Keyframe) {
+        self.tracks.entry(id).or_default().insert(keyframe);
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.tracks
+            .values()
+            .map(|v| v.duration())
+            .fold(0.0, f32::max)
+    }
+
+    // Maps time since the start onto the animation, wrapping around if it loops.
+    pub fn local_time(&self, time: f32) -> f32 {
+        let duration = self.duration();
+        if self.looping && duration > 0.0 {
+            time.rem_euclid(duration)
+        } else {
+            time
+        }
+    }
+
+    pub fn is_finished(&self, time: f32) -> bool {
+        !self.looping && time >= self.duration()
+    }
+
+    pub fn sample(&self, time: f32) -> impl Iterator<Item = (ObjectId, TrackSample)> + '_ {
+        let time = self.local_time(time);
+        self.tracks
+            .iter()
+            .filter_map(move |(id, track)| track.sample(time).map(|v| (*id, v)))
+    }
+}
+
+fn linear_part(m: &Matrix4) -> Matrix3<f32> {
+    Matrix3::from_cols(m.x.truncate(), m.y.truncate(), m.z.truncate())
+}
+
+fn to_rotation(m: &Matrix3<f32>) -> Option<Quaternion<f32>> {
+    let error = m.transpose() * m - Matrix3::identity();
+    let orthonormal = [error.x, error.y, error.z]
+        .iter()
+        .all(|v| v.x.abs() < 1e-3 && v.y.abs() < 1e-3 && v.z.abs() < 1e-3);
+    if orthonormal && m.determinant() > 0.0 {
+        Some(Quaternion::from(*m))
+    } else {
+        None
+    }
+}
+
+// Rotations are interpolated along the shorter arc. Matrices that aren't rotations, like
+// mirrored or scaled ones, are blended element by element instead.
+fn interpolate_matrix(a: &Matrix4, b: &Matrix4, t: f32) -> Matrix4 {
+    let (la, lb) = (linear_part(a), linear_part(b));
+    let linear = match (to_rotation(&la), to_rotation(&lb)) {
+        (Some(qa), Some(qb)) => {
+            let qb = if qa.dot(qb) < 0.0 { -qb } else { qb };
+            Matrix3::from(qa.slerp(qb, t))
+        }
+        _ => la + (lb - la) * t,
+    };
+
+    let mut result = Matrix4::from(linear);
+    result.w = a.w.truncate().lerp(b.w.truncate(), t).extend(1.0);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Rad};
+    use ldraw::{Vector3, Vector4};
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn assert_matrix_eq(a: &Matrix4, b: &Matrix4) {
+        for (x, y) in AsRef::<[f32; 16]>::as_ref(a)
+            .iter()
+            .zip(AsRef::<[f32; 16]>::as_ref(b))
+        {
+            assert!((x - y).abs() < 1e-3, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_track_sample() {
+        let mut track = Track::default();
+        track.insert(Keyframe::new(
+            2.0,
+            Matrix4::from_translation(Vector3::new(20.0, 0.0, 0.0)),
+        ));
+        track.insert(Keyframe::new(0.0, Matrix4::identity()));
+        track.insert(Keyframe {
+            visible: false,
+            interpolation: Interpolation::Step,
+            ..Keyframe::new(4.0, Matrix4::from_translation(Vector3::new(0.0, 10.0, 0.0)))
+        });
+        track.insert(Keyframe::new(
+            6.0,
+            Matrix4::from_translation(Vector3::new(0.0, 0.0, 30.0)),
+        ));
+
+        assert_eq!(track.keyframes().len(), 4);
+        assert_eq!(track.duration(), 6.0);
+
+        let sample = track.sample(-1.0).unwrap();
+        assert_matrix_eq(&sample.matrix, &Matrix4::identity());
+
+        let sample = track.sample(1.0).unwrap();
+        assert_eq!(sample.matrix.w, Vector4::new(10.0, 0.0, 0.0, 1.0));
+        assert!(sample.visible);
+
+        let sample = track.sample(3.0).unwrap();
+        assert_eq!(sample.matrix.w, Vector4::new(10.0, 5.0, 0.0, 1.0));
+
+        let sample = track.sample(5.0).unwrap();
+        assert_eq!(sample.matrix.w, Vector4::new(0.0, 10.0, 0.0, 1.0));
+        assert!(!sample.visible);
+
+        let sample = track.sample(7.0).unwrap();
+        assert_eq!(sample.matrix.w, Vector4::new(0.0, 0.0, 30.0, 1.0));
+        assert!(sample.visible);
+
+        assert!(track.remove(4.0).is_some());
+        assert!(track.remove(4.0).is_none());
+        assert!(Track::default().sample(0.0).is_none());
+    }
+
+    #[test]
+    fn test_interpolate_rotation() {
+        let a = Matrix4::identity();
+        let b = Matrix4::from_angle_y(Deg(90.0));
+        assert_matrix_eq(
+            &interpolate_matrix(&a, &b, 0.5),
+            &Matrix4::from_angle_y(Deg(45.0)),
+        );
+
+        // Mirrored matrices are blended as they are.
+        let mirrored = Matrix4::from_nonuniform_scale(-1.0, 1.0, 1.0);
+        assert_matrix_eq(
+            &interpolate_matrix(&a, &mirrored, 0.5),
+            &Matrix4::from_nonuniform_scale(0.0, 1.0, 1.0),
+        );
+
+        let rotated = Matrix4::from_angle_z(Rad(std::f32::consts::PI * 0.75));
+        assert_matrix_eq(&interpolate_matrix(&a, &rotated, 1.0), &rotated);
+    }
+
+    #[test]
+    fn test_animation_loop() {
+        let id = ObjectId::from(Uuid::new_v4());
+        let mut animation = Animation::new();
+        animation.add_keyframe(id, Keyframe::new(0.0, Matrix4::identity()));
+        animation.add_keyframe(
+            id,
+            Keyframe::new(2.0, Matrix4::from_translation(Vector3::new(4.0, 0.0, 0.0))),
+        );
+
+        assert_eq!(animation.duration(), 2.0);
+        assert!(animation.is_finished(2.0));
+        let (_, sample) = animation.sample(3.0).next().unwrap();
+        assert_eq!(sample.matrix.w.x, 4.0);
+
+        animation.looping = true;
+        assert!(!animation.is_finished(3.0));
+        let (sampled, sample) = animation.sample(3.0).next().unwrap();
+        assert_eq!(sampled, id);
+        assert_eq!(sample.matrix.w.x, 2.0);
+    }
+}
@@ -0,0 +1,493 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    error::Error,
+    fmt,
+};
+
+use cgmath::{InnerSpace, Quaternion, Rad, SquareMatrix};
+use ldraw::{Matrix4, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constraints::{Connection, ConnectionKind, ConnectivityGraph},
+    model::ObjectId,
+};
+
+// Distance in LDraw units within which connection points count as sharing an axis.
+const AXIS_TOLERANCE: f32 = 1.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum JointKind {
+    // Turns around an axis through the pivot, like a pinned beam or a wheel on an axle.
+    Revolute { axis: Vector3 },
+    // Moves along an axis, like a shock absorber.
+    Slider { axis: Vector3 },
+    // Turns freely around the pivot, like minifig hands.
+    Ball,
+    // Follows the parent without moving by itself.
+    Fixed,
+}
+
+impl JointKind {
+    pub fn rest_value(&self) -> JointValue {
+        match self {
+            JointKind::Ball => JointValue::Rotation(Quaternion::new(1.0, 0.0, 0.0, 0.0)),
+            _ => JointValue::Scalar(0.0),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum JointValue {
+    // Radians for revolute joints, LDraw units for sliders.
+    Scalar(f32),
+    // Rotation of ball joints.
+    Rotation(Quaternion<f32>),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Joint {
+    pub parent: ObjectId,
+    pub child: ObjectId,
+    pub kind: JointKind,
+    // Center of rotation in coordinates of the model at rest.
+    pub pivot: Vector3,
+    // Range of scalar values. Ball joints are not limited.
+    pub limits: Option<(f32, f32)>,
+}
+
+impl Joint {
+    pub fn new(parent: ObjectId, child: ObjectId, kind: JointKind, pivot: Vector3) -> Self {
+        Self {
+            parent,
+            child,
+            kind,
+            pivot,
+            limits: None,
+        }
+    }
+
+    pub fn with_limits(self, min: f32, max: f32) -> Self {
+        Self {
+            limits: Some((min.min(max), min.max(max))),
+            ..self
+        }
+    }
+
+    // Returns None if the value doesn't suit the kind of the joint.
+    pub fn clamp(&self, value: JointValue) -> Option<JointValue> {
+        match (&self.kind, value) {
+            (JointKind::Revolute { .. } | JointKind::Slider { .. }, JointValue::Scalar(v)) => {
+                Some(JointValue::Scalar(match self.limits {
+                    Some((min, max)) => v.clamp(min, max),
+                    None => v,
+                }))
+            }
+            (JointKind::Ball, JointValue::Rotation(q)) if q.magnitude2() > 0.0 => {
+                Some(JointValue::Rotation(q.normalize()))
+            }
+            _ => None,
+        }
+    }
+
+    // Motion of the child relative to the parent, in coordinates of the model at rest.
+    pub fn transform(&self, value: &JointValue) -> Matrix4 {
+        let around_pivot = |m: Matrix4| {
+            Matrix4::from_translation(self.pivot) * m * Matrix4::from_translation(-self.pivot)
+        };
+
+        match (&self.kind, value) {
+            (JointKind::Revolute { axis }, JointValue::Scalar(angle))
+                if axis.magnitude2() > 0.0 =>
+            {
+                around_pivot(Matrix4::from_axis_angle(axis.normalize(), Rad(*angle)))
+            }
+            (JointKind::Slider { axis }, JointValue::Scalar(offset)) if axis.magnitude2() > 0.0 => {
+                Matrix4::from_translation(axis.normalize() * *offset)
+            }
+            (JointKind::Ball, JointValue::Rotation(q)) if q.magnitude2() > 0.0 => {
+                around_pivot(Matrix4::from(q.normalize()))
+            }
+            _ => Matrix4::identity(),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum ArticulationError {
+    SelfJoint(ObjectId),
+    // Objects hang from a single joint each.
+    AlreadyJointed(ObjectId),
+    Cycle(ObjectId),
+}
+
+impl fmt::Display for ArticulationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArticulationError::SelfJoint(id) => write!(f, "Object {} is joined to itself", id),
+            ArticulationError::AlreadyJointed(id) => {
+                write!(f, "Object {} already has a joint to its parent", id)
+            }
+            ArticulationError::Cycle(id) => {
+                write!(f, "Joint to object {} would make a cycle", id)
+            }
+        }
+    }
+}
+
+impl Error for ArticulationError {}
+
+// Tree of joints between objects at the same level of a model, along with current values of
+// the joints. Objects without a joint to a parent stay where they are.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Articulation {
+    // Keyed by the child of each joint.
+    joints: HashMap<ObjectId, Joint>,
+    values: HashMap<ObjectId, JointValue>,
+}
+
+impl Articulation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Joins objects reachable from root through the connectivity graph, following the first
+    // path found to each. Pins and axles turning in pin holes along a single axis become
+    // revolute joints, and anything else is fixed. Closed loops of parts are broken up, so
+    // they don't stay closed while posing.
+    pub fn from_connectivity(graph: &ConnectivityGraph, root: ObjectId) -> Self {
+        let mut articulation = Self::default();
+        let mut visited = HashSet::from([root]);
+        let mut queue = VecDeque::from([root]);
+
+        while let Some(parent) = queue.pop_front() {
+            let mut neighbors = graph.neighbors(&parent).into_iter().collect::<Vec<_>>();
+            // Keeps the result stable regardless of hashing.
+            neighbors.sort_by_key(|v| v.to_string());
+
+            for child in neighbors {
+                if !visited.insert(child) {
+                    continue;
+                }
+                let connections = graph
+                    .connections_of(&parent)
+                    .filter(|v| v.objects.0 == child || v.objects.1 == child)
+                    .collect::<Vec<_>>();
+                if let Some((kind, pivot)) = infer_joint_kind(&connections) {
+                    articulation
+                        .joints
+                        .insert(child, Joint::new(parent, child, kind, pivot));
+                }
+                queue.push_back(child);
+            }
+        }
+
+        articulation
+    }
+
+    pub fn add_joint(&mut self, joint: Joint) -> Result<(), ArticulationError> {
+        if joint.parent == joint.child {
+            return Err(ArticulationError::SelfJoint(joint.child));
+        }
+        if self.joints.contains_key(&joint.child) {
+            return Err(ArticulationError::AlreadyJointed(joint.child));
+        }
+        if self.ancestors(&joint.parent).any(|v| v == joint.child) {
+            return Err(ArticulationError::Cycle(joint.child));
+        }
+
+        self.joints.insert(joint.child, joint);
+        Ok(())
+    }
+
+    pub fn remove_joint(&mut self, child: &ObjectId) -> Option<Joint> {
+        self.values.remove(child);
+        self.joints.remove(child)
+    }
+
+    pub fn joint(&self, child: &ObjectId) -> Option<&Joint> {
+        self.joints.get(child)
+    }
+
+    pub fn joints(&self) -> impl Iterator<Item = &Joint> {
+        self.joints.values()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.joints.is_empty()
+    }
+
+    pub fn value(&self, child: &ObjectId) -> Option<JointValue> {
+        let joint = self.joints.get(child)?;
+        Some(
+            self.values
+                .get(child)
+                .copied()
+                .unwrap_or_else(|| joint.kind.rest_value()),
+        )
+    }
+
+    // Values are clamped to limits of the joint. Returns false if there is no such joint or
+    // the value doesn't suit it.
+    pub fn set_value(&mut self, child: &ObjectId, value: JointValue) -> bool {
+        match self.joints.get(child).and_then(|v| v.clamp(value)) {
+            Some(value) => {
+                self.values.insert(*child, value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Puts every joint back at rest.
+    pub fn reset(&mut self) {
+        self.values.clear();
+    }
+
+    // Ancestors of the object, from its parent up to the root.
+    fn ancestors<'a>(&'a self, id: &ObjectId) -> impl Iterator<Item = ObjectId> + 'a {
+        let mut current = *id;
+        std::iter::from_fn(move || {
+            let parent = self.joints.get(&current)?.parent;
+            current = parent;
+            Some(parent)
+        })
+    }
+
+    // Forward kinematics: motion of every jointed object in coordinates of the model at rest.
+    // Multiply it with the matrix an object is placed with to get its posed placement.
+    pub fn evaluate(&self) -> HashMap<ObjectId, Matrix4> {
+        let mut motions = HashMap::new();
+        for child in self.joints.keys() {
+            self.motion_of(child, &mut motions);
+        }
+        motions
+    }
+
+    fn motion_of(&self, id: &ObjectId, motions: &mut HashMap<ObjectId, Matrix4>) -> Matrix4 {
+        if let Some(motion) = motions.get(id) {
+            return *motion;
+        }
+        let Some(joint) = self.joints.get(id) else {
+            return Matrix4::identity();
+        };
+
+        let parent = self.motion_of(&joint.parent, motions);
+        let value = self.value(id).unwrap_or_else(|| joint.kind.rest_value());
+        let motion = parent * joint.transform(&value);
+        motions.insert(*id, motion);
+        motion
+    }
+}
+
+fn turns_in(kinds: (ConnectionKind, ConnectionKind)) -> bool {
+    use ConnectionKind::*;
+
+    matches!(kinds, (Pin | Axle, PinHole) | (PinHole, Pin | Axle))
+}
+
+fn infer_joint_kind(connections: &[&Connection]) -> Option<(JointKind, Vector3)> {
+    let first = connections.first()?;
+    let axis = first.direction;
+    let revolute = axis.magnitude2() > 0.0
+        && connections.iter().all(|v| {
+            let delta = v.position - first.position;
+            turns_in(v.kinds)
+                && v.direction.dot(axis).abs() >= 0.9
+                && (delta - axis * delta.dot(axis)).magnitude() <= AXIS_TOLERANCE
+        });
+
+    if revolute {
+        Some((JointKind::Revolute { axis }, first.position))
+    } else {
+        Some((JointKind::Fixed, first.position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use cgmath::{Deg, Rotation3};
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn new_id() -> ObjectId {
+        ObjectId::from(Uuid::new_v4())
+    }
+
+    fn apply(m: &Matrix4, v: Vector3) -> Vector3 {
+        (m * v.extend(1.0)).truncate()
+    }
+
+    fn assert_vector_eq(a: Vector3, b: Vector3) {
+        assert!((a - b).magnitude() < 1e-3, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_forward_kinematics() {
+        let (base, arm, hand) = (new_id(), new_id(), new_id());
+        let mut articulation = Articulation::new();
+        articulation
+            .add_joint(Joint::new(
+                base,
+                arm,
+                JointKind::Revolute {
+                    axis: Vector3::new(0.0, 1.0, 0.0),
+                },
+                Vector3::new(10.0, 0.0, 0.0),
+            ))
+            .unwrap();
+        articulation
+            .add_joint(Joint::new(
+                arm,
+                hand,
+                JointKind::Ball,
+                Vector3::new(30.0, 0.0, 0.0),
+            ))
+            .unwrap();
+
+        // Everything stays put at rest.
+        for motion in articulation.evaluate().values() {
+            assert_eq!(*motion, Matrix4::identity());
+        }
+
+        assert!(articulation.set_value(&arm, JointValue::Scalar(FRAC_PI_2)));
+        let motions = articulation.evaluate();
+        assert!(!motions.contains_key(&base));
+        assert_vector_eq(
+            apply(&motions[&arm], Vector3::new(30.0, 0.0, 0.0)),
+            Vector3::new(10.0, 0.0, -20.0),
+        );
+        // Children follow their parents.
+        assert_vector_eq(
+            apply(&motions[&hand], Vector3::new(40.0, 0.0, 0.0)),
+            Vector3::new(10.0, 0.0, -30.0),
+        );
+
+        assert!(articulation.set_value(
+            &hand,
+            JointValue::Rotation(Quaternion::from_angle_z(Deg(90.0)))
+        ));
+        let motions = articulation.evaluate();
+        assert_vector_eq(
+            apply(&motions[&hand], Vector3::new(40.0, 0.0, 0.0)),
+            Vector3::new(10.0, 10.0, -20.0),
+        );
+
+        // Values must suit the joint.
+        assert!(!articulation.set_value(&hand, JointValue::Scalar(1.0)));
+        assert!(!articulation.set_value(&base, JointValue::Scalar(1.0)));
+
+        articulation.reset();
+        assert_eq!(articulation.value(&arm), Some(JointValue::Scalar(0.0)));
+    }
+
+    #[test]
+    fn test_slider_limits() {
+        let (a, b) = (new_id(), new_id());
+        let mut articulation = Articulation::new();
+        articulation
+            .add_joint(
+                Joint::new(
+                    a,
+                    b,
+                    JointKind::Slider {
+                        axis: Vector3::new(0.0, 0.0, 2.0),
+                    },
+                    Vector3::new(0.0, 0.0, 0.0),
+                )
+                .with_limits(20.0, -20.0),
+            )
+            .unwrap();
+
+        assert!(articulation.set_value(&b, JointValue::Scalar(50.0)));
+        assert_eq!(articulation.value(&b), Some(JointValue::Scalar(20.0)));
+        let motions = articulation.evaluate();
+        assert_vector_eq(
+            apply(&motions[&b], Vector3::new(0.0, 0.0, 0.0)),
+            Vector3::new(0.0, 0.0, 20.0),
+        );
+    }
+
+    #[test]
+    fn test_invalid_joints() {
+        let (a, b, c) = (new_id(), new_id(), new_id());
+        let fixed = |parent, child| {
+            Joint::new(parent, child, JointKind::Fixed, Vector3::new(0.0, 0.0, 0.0))
+        };
+
+        let mut articulation = Articulation::new();
+        assert_eq!(
+            articulation.add_joint(fixed(a, a)),
+            Err(ArticulationError::SelfJoint(a))
+        );
+        articulation.add_joint(fixed(a, b)).unwrap();
+        articulation.add_joint(fixed(b, c)).unwrap();
+        assert_eq!(
+            articulation.add_joint(fixed(a, c)),
+            Err(ArticulationError::AlreadyJointed(c))
+        );
+        assert_eq!(
+            articulation.add_joint(fixed(c, a)),
+            Err(ArticulationError::Cycle(a))
+        );
+
+        assert!(articulation.remove_joint(&b).is_some());
+        assert_eq!(
+            articulation.add_joint(fixed(c, b)),
+            Err(ArticulationError::Cycle(b))
+        );
+        articulation.add_joint(fixed(a, b)).unwrap();
+    }
+
+    #[test]
+    fn test_from_connectivity() {
+        let (frame, beam, plate) = (new_id(), new_id(), new_id());
+        let up = Vector3::new(0.0, -1.0, 0.0);
+        let connection = |objects, kinds, position| Connection {
+            objects,
+            kinds,
+            position,
+            direction: up,
+        };
+        let graph = ConnectivityGraph {
+            connections: vec![
+                // Two pins along the same axis still turn.
+                connection(
+                    (frame, beam),
+                    (ConnectionKind::Pin, ConnectionKind::PinHole),
+                    Vector3::new(0.0, 0.0, 0.0),
+                ),
+                connection(
+                    (beam, frame),
+                    (ConnectionKind::PinHole, ConnectionKind::Pin),
+                    Vector3::new(0.0, -10.0, 0.0),
+                ),
+                // Two studs side by side hold firmly.
+                connection(
+                    (beam, plate),
+                    (ConnectionKind::Stud, ConnectionKind::AntiStud),
+                    Vector3::new(20.0, -20.0, 0.0),
+                ),
+                connection(
+                    (beam, plate),
+                    (ConnectionKind::Stud, ConnectionKind::AntiStud),
+                    Vector3::new(40.0, -20.0, 0.0),
+                ),
+            ],
+        };
+
+        let articulation = Articulation::from_connectivity(&graph, frame);
+        assert!(articulation.joint(&frame).is_none());
+
+        let joint = articulation.joint(&beam).unwrap();
+        assert_eq!(joint.parent, frame);
+        assert_eq!(joint.kind, JointKind::Revolute { axis: up });
+
+        let joint = articulation.joint(&plate).unwrap();
+        assert_eq!(joint.parent, beam);
+        assert_eq!(joint.kind, JointKind::Fixed);
+    }
+}
@@ -1 +1,447 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    ops::Deref,
+};
 
+use cgmath::{InnerSpace, SquareMatrix};
+use ldraw::{
+    document::{Document, MultipartDocument},
+    graph::CycleGuard,
+    library::ResolutionResult,
+    units::{decompose, LDU_PER_STUD},
+    Matrix4, PartAlias, Vector3,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    geometry::BoundingBox3,
+    model::{GroupId, Model, Object, ObjectId, ObjectInstance},
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum ConnectionKind {
+    Stud,
+    AntiStud,
+    Pin,
+    PinHole,
+    Axle,
+    AxleHole,
+}
+
+impl ConnectionKind {
+    pub fn from_primitive(alias: &PartAlias) -> Option<Self> {
+        let name = alias.normalized.rsplit('/').next().unwrap_or_default();
+        let name = name.strip_suffix(".dat").unwrap_or(name);
+
+        if name.starts_with("stud3") || name.starts_with("stud4") {
+            Some(ConnectionKind::AntiStud)
+        } else if name.starts_with("stud") {
+            Some(ConnectionKind::Stud)
+        } else if name.starts_with("peghole")
+            || name.starts_with("npeghol")
+            || name.starts_with("beamhole")
+            || name.starts_with("connhole")
+        {
+            Some(ConnectionKind::PinHole)
+        } else if name.starts_with("axl") && name.contains("hol") {
+            Some(ConnectionKind::AxleHole)
+        } else if name.starts_with("axle") {
+            Some(ConnectionKind::Axle)
+        } else if name.starts_with("connect") {
+            Some(ConnectionKind::Pin)
+        } else {
+            None
+        }
+    }
+
+    pub fn mates_with(&self, other: &ConnectionKind) -> bool {
+        use ConnectionKind::*;
+
+        matches!(
+            (self, other),
+            (Stud, AntiStud)
+                | (AntiStud, Stud)
+                | (Pin, PinHole)
+                | (PinHole, Pin)
+                | (Axle, AxleHole)
+                | (AxleHole, Axle)
+                | (Axle, PinHole)
+                | (PinHole, Axle)
+        )
+    }
+
+    pub fn is_system(&self) -> bool {
+        matches!(self, ConnectionKind::Stud | ConnectionKind::AntiStud)
+    }
+}
+
+// Anti-stud primitives are tubes placed in between the studs they receive, so one tube
+// stands for several connection points.
+fn primitive_offsets(alias: &PartAlias, kind: ConnectionKind) -> Vec<Vector3> {
+    let half = LDU_PER_STUD / 2.0;
+    let name = alias.normalized.rsplit('/').next().unwrap_or_default();
+
+    match kind {
+        ConnectionKind::AntiStud if name.starts_with("stud4") => vec![
+            Vector3::new(-half, 0.0, -half),
+            Vector3::new(half, 0.0, -half),
+            Vector3::new(-half, 0.0, half),
+            Vector3::new(half, 0.0, half),
+        ],
+        ConnectionKind::AntiStud => {
+            vec![Vector3::new(-half, 0.0, 0.0), Vector3::new(half, 0.0, 0.0)]
+        }
+        _ => vec![Vector3::new(0.0, 0.0, 0.0)],
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConnectionPoint {
+    pub kind: ConnectionKind,
+    pub position: Vector3,
+    // Axis of the connection. Points upwards for studs.
+    pub direction: Vector3,
+}
+
+impl ConnectionPoint {
+    pub fn transform(&self, matrix: &Matrix4) -> Self {
+        let rotation = decompose(matrix).rotation;
+        Self {
+            kind: self.kind,
+            position: (matrix * self.position.extend(1.0)).truncate(),
+            direction: (rotation * self.direction).normalize(),
+        }
+    }
+}
+
+fn traverse<M: Deref<Target = MultipartDocument>>(
+    points: &mut Vec<ConnectionPoint>,
+    stack: &mut CycleGuard<*const Document>,
+    resolutions: &ResolutionResult,
+    document: &Document,
+    parent: M,
+    matrix: Matrix4,
+    local: bool,
+) {
+    if !stack.enter(document) {
+        return;
+    }
+
+    for reference in document.iter_refs() {
+        let matrix = matrix * reference.matrix;
+
+        if let Some(kind) = ConnectionKind::from_primitive(&reference.name) {
+            let rotation = decompose(&matrix).rotation;
+            let direction = (rotation * Vector3::new(0.0, -1.0, 0.0)).normalize();
+            for offset in primitive_offsets(&reference.name, kind) {
+                // Offsets are not affected by scaling of the primitive.
+                let position = matrix.w.truncate() + rotation * offset;
+                points.push(ConnectionPoint {
+                    kind,
+                    position,
+                    direction,
+                });
+            }
+        } else if let Some(subpart) = parent.get_subpart(&reference.name) {
+            traverse(points, stack, resolutions, subpart, &*parent, matrix, local);
+        } else if let Some((document, local)) = resolutions.query(&reference.name, local) {
+            traverse(
+                points,
+                stack,
+                resolutions,
+                &document.body,
+                &*document,
+                matrix,
+                local,
+            );
+        }
+    }
+
+    stack.leave();
+}
+
+pub fn infer_connection_points<D: Deref<Target = MultipartDocument>>(
+    document: D,
+    resolutions: &ResolutionResult,
+    local: bool,
+) -> Vec<ConnectionPoint> {
+    let mut points = Vec::new();
+    traverse(
+        &mut points,
+        &mut CycleGuard::new(),
+        resolutions,
+        &document.body,
+        &*document,
+        Matrix4::identity(),
+        local,
+    );
+    points
+}
+
+pub trait PartConnectionQuerier<P> {
+    fn query_connection_points(&self, alias: &P) -> Option<&[ConnectionPoint]>;
+}
+
+#[derive(Clone, Debug)]
+pub struct ConnectionParams {
+    // Maximum distance between axes of two connection points.
+    pub radial_tolerance: f32,
+    // Maximum distance between a stud and an anti-stud along their axis.
+    pub stud_reach: f32,
+    // Maximum distance between technic connection points along their axis.
+    pub technic_reach: f32,
+}
+
+impl Default for ConnectionParams {
+    fn default() -> Self {
+        Self {
+            radial_tolerance: 0.5,
+            stud_reach: 28.0,
+            technic_reach: 40.0,
+        }
+    }
+}
+
+impl ConnectionParams {
+    fn connects(&self, a: &ConnectionPoint, b: &ConnectionPoint) -> bool {
+        if !a.kind.mates_with(&b.kind) || a.direction.dot(b.direction).abs() < 0.9 {
+            return false;
+        }
+
+        // Direction of anti-studs is not reliable as tube primitives are often mirrored, so
+        // distances are measured along the axis of the stud.
+        let (base, other) = if b.kind == ConnectionKind::Stud {
+            (b, a)
+        } else {
+            (a, b)
+        };
+        let delta = other.position - base.position;
+        let axial = delta.dot(base.direction);
+        if (delta - base.direction * axial).magnitude() > self.radial_tolerance {
+            return false;
+        }
+
+        if base.kind == ConnectionKind::Stud {
+            // Anti-studs are above the base of the studs they receive.
+            (-self.radial_tolerance..=self.stud_reach).contains(&axial)
+        } else {
+            axial.abs() <= self.technic_reach
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Connection {
+    pub objects: (ObjectId, ObjectId),
+    pub kinds: (ConnectionKind, ConnectionKind),
+    pub position: Vector3,
+    // Axis of the first connection point.
+    pub direction: Vector3,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ConnectivityGraph {
+    pub connections: Vec<Connection>,
+}
+
+impl ConnectivityGraph {
+    pub fn connections_of<'a>(&'a self, id: &'a ObjectId) -> impl Iterator<Item = &'a Connection> {
+        self.connections
+            .iter()
+            .filter(move |v| v.objects.0 == *id || v.objects.1 == *id)
+    }
+
+    pub fn neighbors(&self, id: &ObjectId) -> HashSet<ObjectId> {
+        self.connections_of(id)
+            .map(|v| {
+                if v.objects.0 == *id {
+                    v.objects.1
+                } else {
+                    v.objects.0
+                }
+            })
+            .collect()
+    }
+
+    pub fn is_connected(&self, a: &ObjectId, b: &ObjectId) -> bool {
+        self.connections_of(a)
+            .any(|v| v.objects.0 == *b || v.objects.1 == *b)
+    }
+
+    // Groups of objects that are connected to each other directly or indirectly.
+    pub fn components(&self, ids: &[ObjectId]) -> Vec<Vec<ObjectId>> {
+        let mut visited = HashSet::new();
+        let mut result = Vec::new();
+
+        for id in ids {
+            if !visited.insert(*id) {
+                continue;
+            }
+
+            let mut component = vec![*id];
+            let mut queue = vec![*id];
+            while let Some(current) = queue.pop() {
+                for neighbor in self.neighbors(&current) {
+                    if visited.insert(neighbor) {
+                        component.push(neighbor);
+                        queue.push(neighbor);
+                    }
+                }
+            }
+            result.push(component);
+        }
+
+        result
+    }
+}
+
+impl<P: Clone + Eq + PartialEq + Hash> Model<P> {
+    fn collect_connection_points(
+        &self,
+        points: &mut Vec<ConnectionPoint>,
+        objects: &[Object<P>],
+        matrix: Matrix4,
+        querier: &impl PartConnectionQuerier<P>,
+    ) {
+        for object in objects {
+            match &object.data {
+                ObjectInstance::Part(p) => {
+                    if let Some(part_points) = querier.query_connection_points(&p.part) {
+                        let matrix = matrix * p.matrix;
+                        points.extend(part_points.iter().map(|v| v.transform(&matrix)));
+                    }
+                }
+                ObjectInstance::PartGroup(pg) => {
+                    if let Some(group) = self.object_groups.get(&pg.group_id) {
+                        self.collect_connection_points(
+                            points,
+                            &group.objects,
+                            matrix * pg.matrix,
+                            querier,
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn connectivity_graph(
+        &self,
+        group_id: Option<GroupId>,
+        querier: &impl PartConnectionQuerier<P>,
+        params: &ConnectionParams,
+    ) -> ConnectivityGraph {
+        let objects = match group_id {
+            Some(group_id) => match self.object_groups.get(&group_id) {
+                Some(group) => &group.objects,
+                None => return ConnectivityGraph::default(),
+            },
+            None => &self.objects,
+        };
+
+        let reach = params.stud_reach.max(params.technic_reach) + params.radial_tolerance;
+        let entries = objects
+            .iter()
+            .filter_map(|object| {
+                let mut points = Vec::new();
+                self.collect_connection_points(
+                    &mut points,
+                    std::slice::from_ref(object),
+                    Matrix4::identity(),
+                    querier,
+                );
+                if points.is_empty() {
+                    return None;
+                }
+
+                let mut bounding_box = BoundingBox3::nil();
+                for point in points.iter() {
+                    bounding_box.update_point(&point.position);
+                }
+                Some((object.id, points, bounding_box))
+            })
+            .collect::<Vec<_>>();
+
+        let mut connections = Vec::new();
+        for (i, (a_id, a_points, a_bb)) in entries.iter().enumerate() {
+            for (b_id, b_points, b_bb) in entries[i + 1..].iter() {
+                if a_bb.min.x > b_bb.max.x + reach
+                    || b_bb.min.x > a_bb.max.x + reach
+                    || a_bb.min.y > b_bb.max.y + reach
+                    || b_bb.min.y > a_bb.max.y + reach
+                    || a_bb.min.z > b_bb.max.z + reach
+                    || b_bb.min.z > a_bb.max.z + reach
+                {
+                    continue;
+                }
+
+                // Each point takes part in one connection at most.
+                let mut used = HashMap::new();
+                for (ai, a) in a_points.iter().enumerate() {
+                    if let Some((bi, b)) = b_points
+                        .iter()
+                        .enumerate()
+                        .find(|(bi, b)| !used.contains_key(bi) && params.connects(a, b))
+                    {
+                        used.insert(bi, ai);
+                        connections.push(Connection {
+                            objects: (*a_id, *b_id),
+                            kinds: (a.kind, b.kind),
+                            position: (a.position + b.position) / 2.0,
+                            direction: a.direction,
+                        });
+                    }
+                }
+            }
+        }
+
+        ConnectivityGraph { connections }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ldraw::{PartAlias, Vector3};
+
+    use super::{ConnectionKind, ConnectionParams, ConnectionPoint};
+
+    fn kind(name: &str) -> Option<ConnectionKind> {
+        ConnectionKind::from_primitive(&PartAlias::from(name.to_string()))
+    }
+
+    #[test]
+    fn test_primitive_kinds() {
+        assert_eq!(kind("stud.dat"), Some(ConnectionKind::Stud));
+        assert_eq!(kind("48\\stud2a.dat"), Some(ConnectionKind::Stud));
+        assert_eq!(kind("stud4.dat"), Some(ConnectionKind::AntiStud));
+        assert_eq!(kind("peghole.dat"), Some(ConnectionKind::PinHole));
+        assert_eq!(kind("axlehol8.dat"), Some(ConnectionKind::AxleHole));
+        assert_eq!(kind("axle.dat"), Some(ConnectionKind::Axle));
+        assert_eq!(kind("connect2.dat"), Some(ConnectionKind::Pin));
+        assert_eq!(kind("box5.dat"), None);
+    }
+
+    #[test]
+    fn test_stud_connection() {
+        let params = ConnectionParams::default();
+        let up = Vector3::new(0.0, -1.0, 0.0);
+        let stud = ConnectionPoint {
+            kind: ConnectionKind::Stud,
+            position: Vector3::new(10.0, 0.0, 10.0),
+            direction: up,
+        };
+        let anti_stud = |x: f32, y: f32| ConnectionPoint {
+            kind: ConnectionKind::AntiStud,
+            position: Vector3::new(x, y, 10.0),
+            direction: -up,
+        };
+
+        assert!(params.connects(&stud, &anti_stud(10.0, -20.0)));
+        assert!(params.connects(&anti_stud(10.0, -4.0), &stud));
+        assert!(!params.connects(&stud, &anti_stud(10.0, 20.0)));
+        assert!(!params.connects(&stud, &anti_stud(30.0, -20.0)));
+        assert!(!params.connects(&stud, &stud));
+    }
+}
@@ -0,0 +1,221 @@
+use std::{collections::HashMap, hash::Hash};
+
+use cgmath::{InnerSpace, Matrix, SquareMatrix};
+use ldraw::{
+    color::{ColorCatalog, ColorReference, Rgba},
+    graph::CycleGuard,
+    Matrix3, Matrix4, PartAlias, Vector3,
+};
+
+use crate::{
+    model::{GroupId, Model, Object, ObjectInstance},
+    part::{EdgeColor, MeshBuffer, Part},
+};
+
+pub mod obj;
+pub mod threemf;
+mod zip;
+
+// Colors end up as materials of exported files. Faces and lines of complement color take
+// the edge color of the part.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub(crate) enum MaterialKey {
+    Main(u32),
+    Edge(u32),
+}
+
+impl MaterialKey {
+    pub(crate) fn name(&self) -> String {
+        match self {
+            MaterialKey::Main(code) => format!("ldraw_{}", code),
+            MaterialKey::Edge(code) => format!("ldraw_{}_edge", code),
+        }
+    }
+
+    pub(crate) fn from_edge_code(code: u32, top: &ColorReference) -> Self {
+        match EdgeColor::from_code(code) {
+            EdgeColor::Current => MaterialKey::Main(top.code()),
+            EdgeColor::Complement => MaterialKey::Edge(top.code()),
+            EdgeColor::Main(code) => MaterialKey::Main(code),
+            EdgeColor::Edge(code) => MaterialKey::Edge(code),
+        }
+    }
+
+    pub(crate) fn rgba(&self, colors: &ColorCatalog) -> Rgba {
+        match self {
+            MaterialKey::Main(code) => match colors.get(code) {
+                Some(color) => color.color,
+                None => Rgba::new(0x80, 0x80, 0x80, 0xff),
+            },
+            MaterialKey::Edge(code) => match colors.get(code) {
+                Some(color) => color.edge,
+                None => Rgba::new(0x33, 0x33, 0x33, 0xff),
+            },
+        }
+    }
+}
+
+fn vertex(part: &Part, index: u32) -> Vector3 {
+    let index = index as usize * 3;
+    match part.geometry.vertex_buffer.0.get(index..index + 3) {
+        Some(v) => Vector3::new(v[0], v[1], v[2]),
+        None => Vector3::new(0.0, 0.0, 0.0),
+    }
+}
+
+// Corner of a triangle, as indices of its position and normal in the vertex buffer.
+pub(crate) type Corner = (u32, u32);
+
+// A part transformed to where it is placed in the exported file.
+pub(crate) struct Placement<'a> {
+    pub part: &'a Part,
+    matrix: Matrix4,
+    normal_matrix: Matrix3,
+    // Mirroring turns faces inside out unless their winding is reversed.
+    reverse: bool,
+}
+
+impl<'a> Placement<'a> {
+    pub(crate) fn new(part: &'a Part, matrix: Matrix4) -> Self {
+        let linear = Matrix3::from_cols(
+            matrix.x.truncate(),
+            matrix.y.truncate(),
+            matrix.z.truncate(),
+        );
+        Self {
+            part,
+            matrix,
+            normal_matrix: linear.invert().map(|v| v.transpose()).unwrap_or(linear),
+            reverse: linear.determinant() < 0.0,
+        }
+    }
+
+    pub(crate) fn position(&self, index: u32) -> Vector3 {
+        (self.matrix * vertex(self.part, index).extend(1.0)).truncate()
+    }
+
+    pub(crate) fn normal(&self, index: u32) -> Vector3 {
+        let normal = self.normal_matrix * vertex(self.part, index);
+        if normal.magnitude2() > 0.0 {
+            normal.normalize()
+        } else {
+            normal
+        }
+    }
+
+    // Triangles of each mesh, grouped as in part_meshes().
+    pub(crate) fn triangles(&self, color: &ColorReference) -> Vec<(MaterialKey, Vec<[Corner; 3]>)> {
+        part_meshes(self.part, color)
+            .into_iter()
+            .map(|(material, mesh)| {
+                let triangles = mesh
+                    .vertex_indices
+                    .chunks_exact(3)
+                    .zip(mesh.normal_indices.chunks_exact(3))
+                    .map(|(v, n)| {
+                        let mut corners = [(v[0], n[0]), (v[1], n[1]), (v[2], n[2])];
+                        if self.reverse {
+                            corners.swap(1, 2);
+                        }
+                        corners
+                    })
+                    .collect();
+                (material, triangles)
+            })
+            .collect()
+    }
+}
+
+// Meshes of a part with materials they are drawn in when the part is placed in given color,
+// in a stable order.
+fn part_meshes<'a>(part: &'a Part, color: &ColorReference) -> Vec<(MaterialKey, &'a MeshBuffer)> {
+    let geometry = &part.geometry;
+    let mut meshes = vec![
+        (MaterialKey::Main(color.code()), &geometry.uncolored_mesh),
+        (
+            MaterialKey::Main(color.code()),
+            &geometry.uncolored_without_bfc_mesh,
+        ),
+    ];
+    for (key, mesh) in geometry.colored_meshes.iter() {
+        let material = match &key.color_ref {
+            ColorReference::Current => MaterialKey::Main(color.code()),
+            ColorReference::Complement => MaterialKey::Edge(color.code()),
+            v => MaterialKey::Main(v.code()),
+        };
+        meshes.push((material, mesh));
+    }
+    meshes.sort_by_key(|(material, _)| *material);
+    meshes
+        .into_iter()
+        .filter(|(_, mesh)| !mesh.is_empty() && mesh.is_valid())
+        .collect()
+}
+
+// Calls back with every part of the model placed in the top level, flattening submodels.
+// Parts missing from given parts and the model's embedded parts are skipped.
+pub(crate) fn flatten_model<P: Clone + Eq + PartialEq + Hash + Into<PartAlias>>(
+    model: &Model<P>,
+    parts: &HashMap<P, Part>,
+    callback: &mut impl FnMut(&PartAlias, &Part, &Matrix4, &ColorReference),
+) {
+    flatten_objects(
+        model,
+        &model.objects,
+        parts,
+        Matrix4::identity(),
+        &ColorReference::Current,
+        &mut CycleGuard::new(),
+        callback,
+    );
+}
+
+fn flatten_objects<P: Clone + Eq + PartialEq + Hash + Into<PartAlias>>(
+    model: &Model<P>,
+    objects: &[Object<P>],
+    parts: &HashMap<P, Part>,
+    matrix: Matrix4,
+    color: &ColorReference,
+    ancestors: &mut CycleGuard<GroupId>,
+    callback: &mut impl FnMut(&PartAlias, &Part, &Matrix4, &ColorReference),
+) {
+    for object in objects.iter() {
+        match &object.data {
+            ObjectInstance::Part(p) => {
+                let color = match &p.color {
+                    ColorReference::Current => color,
+                    v => v,
+                };
+                let part = parts
+                    .get(&p.part)
+                    .or_else(|| model.embedded_parts.get(&p.part));
+                if let Some(part) = part {
+                    callback(&p.part.clone().into(), part, &(matrix * p.matrix), color);
+                }
+            }
+            ObjectInstance::PartGroup(pg) => {
+                let Some(group) = model.object_groups.get(&pg.group_id) else {
+                    continue;
+                };
+                if !ancestors.enter(pg.group_id) {
+                    continue;
+                }
+                let color = match &pg.color {
+                    ColorReference::Current => color,
+                    v => v,
+                };
+                flatten_objects(
+                    model,
+                    &group.objects,
+                    parts,
+                    matrix * pg.matrix,
+                    color,
+                    ancestors,
+                    callback,
+                );
+                ancestors.leave();
+            }
+            _ => {}
+        }
+    }
+}
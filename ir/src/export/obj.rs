@@ -0,0 +1,376 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Write,
+    hash::Hash,
+};
+
+use cgmath::{Deg, SquareMatrix};
+use ldraw::{
+    color::{ColorCatalog, ColorReference},
+    Matrix4, PartAlias, Vector3,
+};
+
+use super::{flatten_model, MaterialKey, Placement};
+use crate::{model::Model, part::Part};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ObjGrouping {
+    // One group for each part placed, with materials switched within it.
+    Part,
+    // One group for each material, merging every part of the same color.
+    Color,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ObjExportOptions {
+    pub grouping: ObjGrouping,
+    // Writes edges as line elements. Optional edges are always left out, as whether they are
+    // drawn depends on the viewpoint.
+    pub include_edges: bool,
+    // Applied to everything exported. Defaults to turning LDraw's -Y up into Y up, as most
+    // tools reading OBJ files expect.
+    pub transform: Matrix4,
+}
+
+impl Default for ObjExportOptions {
+    fn default() -> Self {
+        Self {
+            grouping: ObjGrouping::Part,
+            include_edges: false,
+            transform: Matrix4::from_angle_x(Deg(180.0)),
+        }
+    }
+}
+
+pub struct ObjExport {
+    pub obj: String,
+    pub mtl: String,
+}
+
+struct Group {
+    name: String,
+    sections: Vec<(MaterialKey, Vec<String>)>,
+}
+
+// Accumulates parts into a single OBJ file. Vertices are written once per part placed, so
+// nothing is shared between instances.
+pub struct ObjWriter<'a> {
+    colors: &'a ColorCatalog,
+    options: ObjExportOptions,
+    positions: Vec<Vector3>,
+    normals: Vec<Vector3>,
+    groups: Vec<Group>,
+    materials: BTreeSet<MaterialKey>,
+    parts: usize,
+}
+
+impl<'a> ObjWriter<'a> {
+    pub fn new(colors: &'a ColorCatalog, options: ObjExportOptions) -> Self {
+        Self {
+            colors,
+            options,
+            positions: Vec::new(),
+            normals: Vec::new(),
+            groups: Vec::new(),
+            materials: BTreeSet::new(),
+            parts: 0,
+        }
+    }
+
+    fn section(&mut self, part: &str, material: MaterialKey) -> &mut Vec<String> {
+        self.materials.insert(material);
+
+        let name = match self.options.grouping {
+            ObjGrouping::Part => format!("{}_{}", part, self.parts),
+            ObjGrouping::Color => material.name(),
+        };
+        let index = match self.groups.iter().position(|v| v.name == name) {
+            Some(index) => index,
+            None => {
+                self.groups.push(Group {
+                    name,
+                    sections: Vec::new(),
+                });
+                self.groups.len() - 1
+            }
+        };
+
+        let sections = &mut self.groups[index].sections;
+        let index = match sections.iter().position(|(v, _)| *v == material) {
+            Some(index) => index,
+            None => {
+                sections.push((material, Vec::new()));
+                sections.len() - 1
+            }
+        };
+        &mut sections[index].1
+    }
+
+    // Places a part with given transform. Parts of current color take the given color.
+    pub fn add_part(&mut self, name: &str, part: &Part, matrix: &Matrix4, color: &ColorReference) {
+        let placement = Placement::new(part, self.options.transform * matrix);
+
+        let mut positions = HashMap::new();
+        let mut normals = HashMap::new();
+        let mut position = |this: &mut Self, index: u32| -> usize {
+            *positions.entry(index).or_insert_with(|| {
+                this.positions.push(placement.position(index));
+                this.positions.len()
+            })
+        };
+        let mut normal = |this: &mut Self, index: u32| -> usize {
+            *normals.entry(index).or_insert_with(|| {
+                this.normals.push(placement.normal(index));
+                this.normals.len()
+            })
+        };
+
+        for (material, triangles) in placement.triangles(color) {
+            let faces = triangles
+                .into_iter()
+                .map(|corners| {
+                    let corners = corners
+                        .iter()
+                        .map(|(v, n)| format!("{}//{}", position(self, *v), normal(self, *n)))
+                        .collect::<Vec<_>>();
+                    format!("f {}", corners.join(" "))
+                })
+                .collect::<Vec<_>>();
+            self.section(name, material).extend(faces);
+        }
+
+        let edges = &part.geometry.edges;
+        if self.options.include_edges && edges.is_valid() {
+            for (vertices, codes) in edges
+                .vertex_indices
+                .chunks_exact(2)
+                .zip(edges.colors.chunks_exact(2))
+            {
+                let line = format!(
+                    "l {} {}",
+                    position(self, vertices[0]),
+                    position(self, vertices[1])
+                );
+                let material = MaterialKey::from_edge_code(codes[0], color);
+                self.section(name, material).push(line);
+            }
+        }
+
+        self.parts += 1;
+    }
+
+    // Places every part of the model, flattening submodels.
+    pub fn add_model<P: Clone + Eq + PartialEq + Hash + Into<PartAlias>>(
+        &mut self,
+        model: &Model<P>,
+        parts: &HashMap<P, Part>,
+    ) {
+        flatten_model(model, parts, &mut |alias, part, matrix, color| {
+            self.add_part(&alias.normalized, part, matrix, color)
+        });
+    }
+
+    // Writes out everything added so far. The OBJ file refers to the material library by
+    // given file name, which should be where the MTL file is saved next to it.
+    pub fn finish(self, mtl_name: &str) -> ObjExport {
+        let mut obj = String::new();
+        writeln!(obj, "mtllib {}", mtl_name).unwrap();
+        for v in self.positions.iter() {
+            writeln!(obj, "v {} {} {}", v.x, v.y, v.z).unwrap();
+        }
+        for n in self.normals.iter() {
+            writeln!(obj, "vn {} {} {}", n.x, n.y, n.z).unwrap();
+        }
+        for group in self.groups.iter() {
+            writeln!(obj, "g {}", group.name.replace(char::is_whitespace, "_")).unwrap();
+            for (material, elements) in group.sections.iter() {
+                writeln!(obj, "usemtl {}", material.name()).unwrap();
+                for element in elements {
+                    writeln!(obj, "{}", element).unwrap();
+                }
+            }
+        }
+
+        let mut mtl = String::new();
+        for material in self.materials.iter() {
+            let color = material.rgba(self.colors);
+            if !mtl.is_empty() {
+                writeln!(mtl).unwrap();
+            }
+            writeln!(mtl, "newmtl {}", material.name()).unwrap();
+            writeln!(
+                mtl,
+                "Kd {:.4} {:.4} {:.4}",
+                color.red() as f32 / 255.0,
+                color.green() as f32 / 255.0,
+                color.blue() as f32 / 255.0
+            )
+            .unwrap();
+            writeln!(mtl, "d {:.4}", color.alpha() as f32 / 255.0).unwrap();
+        }
+
+        ObjExport { obj, mtl }
+    }
+}
+
+pub fn export_part(
+    part: &Part,
+    colors: &ColorCatalog,
+    options: ObjExportOptions,
+    mtl_name: &str,
+) -> ObjExport {
+    let mut writer = ObjWriter::new(colors, options);
+    writer.add_part(
+        &part.metadata.name,
+        part,
+        &Matrix4::identity(),
+        &ColorReference::Current,
+    );
+    writer.finish(mtl_name)
+}
+
+pub fn export_model<P: Clone + Eq + PartialEq + Hash + Into<PartAlias>>(
+    model: &Model<P>,
+    parts: &HashMap<P, Part>,
+    colors: &ColorCatalog,
+    options: ObjExportOptions,
+    mtl_name: &str,
+) -> ObjExport {
+    let mut writer = ObjWriter::new(colors, options);
+    writer.add_model(model, parts);
+    writer.finish(mtl_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ldraw::{
+        color::{Color, ColorCatalog, ColorReference, Material, Rgba},
+        document::{Document, MultipartDocument},
+        elements::{Command, Line, PartReference, Quad},
+        library::ResolutionResult,
+        Matrix4, PartAlias, Vector3, Vector4,
+    };
+
+    use super::{export_model, export_part, ObjExportOptions, ObjGrouping};
+    use crate::{model::Model, part::bake_part_from_multipart_document};
+
+    fn colors() -> ColorCatalog {
+        let mut colors = ColorCatalog::new();
+        colors.insert(
+            4,
+            Color {
+                code: 4,
+                name: String::from("Red"),
+                color: Rgba::new(0xff, 0x00, 0x00, 0xff),
+                edge: Rgba::new(0x33, 0x00, 0x00, 0xff),
+                luminance: 0,
+                material: Material::Plastic,
+                attributes: HashMap::new(),
+            },
+        );
+        colors
+    }
+
+    fn square() -> crate::part::Part {
+        let v = |x: f32, z: f32| Vector4::new(x, 0.0, z, 1.0);
+        let document = MultipartDocument {
+            body: Document {
+                name: String::from("square.dat"),
+                commands: vec![
+                    Command::Quad(Quad {
+                        color: ColorReference::Current,
+                        a: v(0.0, 0.0),
+                        b: v(1.0, 0.0),
+                        c: v(1.0, 1.0),
+                        d: v(0.0, 1.0),
+                    }),
+                    Command::Line(Line {
+                        color: ColorReference::Complement,
+                        a: v(0.0, 0.0),
+                        b: v(1.0, 0.0),
+                    }),
+                ],
+                ..Default::default()
+            },
+            subparts: Default::default(),
+        };
+        bake_part_from_multipart_document(&document, &ResolutionResult::new(), false)
+    }
+
+    #[test]
+    fn test_export_part() {
+        let part = square();
+        let options = ObjExportOptions {
+            include_edges: true,
+            ..Default::default()
+        };
+        let export = export_part(&part, &colors(), options, "square.mtl");
+
+        assert!(export.obj.starts_with("mtllib square.mtl\n"));
+        assert_eq!(
+            export.obj.lines().filter(|v| v.starts_with("v ")).count(),
+            4
+        );
+        assert_eq!(
+            export.obj.lines().filter(|v| v.starts_with("f ")).count(),
+            2
+        );
+        assert_eq!(
+            export.obj.lines().filter(|v| v.starts_with("l ")).count(),
+            1
+        );
+        assert!(export.obj.contains("usemtl ldraw_16\n"));
+        assert!(export.obj.contains("usemtl ldraw_16_edge\n"));
+        assert!(export
+            .mtl
+            .contains("newmtl ldraw_16\nKd 0.5020 0.5020 0.5020\n"));
+    }
+
+    #[test]
+    fn test_export_model() {
+        let part = square();
+        let reference = |x: f32, color: ColorReference| {
+            Command::PartReference(PartReference {
+                color,
+                matrix: Matrix4::from_translation(Vector3::new(x, 0.0, 0.0)),
+                name: PartAlias::from("square.dat"),
+            })
+        };
+        let document = MultipartDocument {
+            body: Document {
+                commands: vec![
+                    reference(0.0, ColorReference::Unknown(4)),
+                    reference(10.0, ColorReference::Unknown(4)),
+                    reference(20.0, ColorReference::Unknown(1)),
+                ],
+                ..Default::default()
+            },
+            subparts: Default::default(),
+        };
+        let model = Model::<PartAlias>::from_ldraw_multipart_document_sync(&document);
+        let mut parts = HashMap::new();
+        parts.insert(PartAlias::from("square.dat"), part);
+
+        let options = ObjExportOptions {
+            grouping: ObjGrouping::Color,
+            ..Default::default()
+        };
+        let export = export_model(&model, &parts, &colors(), options, "model.mtl");
+
+        assert_eq!(
+            export.obj.lines().filter(|v| v.starts_with("v ")).count(),
+            12
+        );
+        assert_eq!(
+            export.obj.lines().filter(|v| v.starts_with("g ")).count(),
+            2
+        );
+        assert!(!export.obj.contains("\nl "));
+        assert!(export
+            .mtl
+            .contains("newmtl ldraw_4\nKd 1.0000 0.0000 0.0000\nd 1.0000\n"));
+        assert!(export.mtl.contains("newmtl ldraw_1\n"));
+    }
+}
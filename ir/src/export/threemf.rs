@@ -0,0 +1,306 @@
+use std::{collections::HashMap, fmt::Write, hash::Hash};
+
+use cgmath::{Deg, SquareMatrix};
+use ldraw::{
+    color::{ColorCatalog, ColorReference},
+    units::MM_PER_LDU,
+    Matrix4, PartAlias, Vector3,
+};
+
+use super::{flatten_model, zip::ZipWriter, MaterialKey, Placement};
+use crate::{model::Model, part::Part};
+
+const CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">
+<Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>
+<Default Extension="model" ContentType="application/vnd.ms-package.3dmanufacturing-3dmodel+xml"/>
+</Types>
+"#;
+
+const RELATIONSHIPS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+<Relationship Target="/3D/3dmodel.model" Id="rel0" Type="http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel"/>
+</Relationships>
+"#;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ThreeMfExportOptions {
+    // Applied to everything exported. Defaults to millimeters with Z up, as slicers expect.
+    pub transform: Matrix4,
+}
+
+impl Default for ThreeMfExportOptions {
+    fn default() -> Self {
+        Self {
+            transform: Matrix4::from_scale(MM_PER_LDU) * Matrix4::from_angle_x(Deg(-90.0)),
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Accumulates parts into a single mesh with a material assigned to each triangle, so that
+// multi-material printers can pick filaments by color. Edges are left out.
+pub struct ThreeMfWriter<'a> {
+    colors: &'a ColorCatalog,
+    options: ThreeMfExportOptions,
+    vertices: Vec<Vector3>,
+    triangles: Vec<([usize; 3], usize)>,
+    materials: Vec<MaterialKey>,
+}
+
+impl<'a> ThreeMfWriter<'a> {
+    pub fn new(colors: &'a ColorCatalog, options: ThreeMfExportOptions) -> Self {
+        Self {
+            colors,
+            options,
+            vertices: Vec::new(),
+            triangles: Vec::new(),
+            materials: Vec::new(),
+        }
+    }
+
+    fn material(&mut self, material: MaterialKey) -> usize {
+        match self.materials.iter().position(|v| *v == material) {
+            Some(index) => index,
+            None => {
+                self.materials.push(material);
+                self.materials.len() - 1
+            }
+        }
+    }
+
+    // Places a part with given transform. Parts of current color take the given color.
+    pub fn add_part(&mut self, part: &Part, matrix: &Matrix4, color: &ColorReference) {
+        let placement = Placement::new(part, self.options.transform * matrix);
+
+        let mut indices = HashMap::new();
+        for (material, triangles) in placement.triangles(color) {
+            let material = self.material(material);
+            for triangle in triangles {
+                let mut corners = [0; 3];
+                for (corner, (index, _)) in corners.iter_mut().zip(triangle.iter()) {
+                    *corner = *indices.entry(*index).or_insert_with(|| {
+                        self.vertices.push(placement.position(*index));
+                        self.vertices.len() - 1
+                    });
+                }
+                // Slicers treat degenerate triangles as errors in the mesh.
+                if corners[0] == corners[1] || corners[1] == corners[2] || corners[0] == corners[2]
+                {
+                    continue;
+                }
+                self.triangles.push((corners, material));
+            }
+        }
+    }
+
+    // Places every part of the model, flattening submodels.
+    pub fn add_model<P: Clone + Eq + PartialEq + Hash + Into<PartAlias>>(
+        &mut self,
+        model: &Model<P>,
+        parts: &HashMap<P, Part>,
+    ) {
+        flatten_model(model, parts, &mut |_, part, matrix, color| {
+            self.add_part(part, matrix, color)
+        });
+    }
+
+    fn model_xml(&self) -> String {
+        let mut xml = String::new();
+        writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+        writeln!(
+            xml,
+            r#"<model unit="millimeter" xml:lang="en-US" xmlns="http://schemas.microsoft.com/3dmanufacturing/core/2015/02">"#
+        )
+        .unwrap();
+        writeln!(xml, "<resources>").unwrap();
+
+        writeln!(xml, r#"<basematerials id="1">"#).unwrap();
+        for material in self.materials.iter() {
+            let rgba = material.rgba(self.colors);
+            let name = match material {
+                MaterialKey::Main(code) => self.colors.get(code).map(|v| v.name.clone()),
+                MaterialKey::Edge(code) => {
+                    self.colors.get(code).map(|v| format!("{} Edge", v.name))
+                }
+            }
+            .unwrap_or_else(|| material.name());
+            writeln!(
+                xml,
+                r##"<base name="{}" displaycolor="#{:02X}{:02X}{:02X}{:02X}"/>"##,
+                escape(&name),
+                rgba.red(),
+                rgba.green(),
+                rgba.blue(),
+                rgba.alpha()
+            )
+            .unwrap();
+        }
+        writeln!(xml, "</basematerials>").unwrap();
+
+        writeln!(xml, r#"<object id="2" type="model" pid="1" pindex="0">"#).unwrap();
+        writeln!(xml, "<mesh>").unwrap();
+        writeln!(xml, "<vertices>").unwrap();
+        for v in self.vertices.iter() {
+            writeln!(xml, r#"<vertex x="{}" y="{}" z="{}"/>"#, v.x, v.y, v.z).unwrap();
+        }
+        writeln!(xml, "</vertices>").unwrap();
+        writeln!(xml, "<triangles>").unwrap();
+        for ([a, b, c], material) in self.triangles.iter() {
+            writeln!(
+                xml,
+                r#"<triangle v1="{}" v2="{}" v3="{}" pid="1" p1="{}"/>"#,
+                a, b, c, material
+            )
+            .unwrap();
+        }
+        writeln!(xml, "</triangles>").unwrap();
+        writeln!(xml, "</mesh>").unwrap();
+        writeln!(xml, "</object>").unwrap();
+
+        writeln!(xml, "</resources>").unwrap();
+        writeln!(xml, r#"<build><item objectid="2"/></build>"#).unwrap();
+        writeln!(xml, "</model>").unwrap();
+        xml
+    }
+
+    // Packages everything added so far into a 3MF archive.
+    pub fn finish(self) -> Vec<u8> {
+        let mut zip = ZipWriter::default();
+        zip.add("[Content_Types].xml", CONTENT_TYPES.as_bytes());
+        zip.add("_rels/.rels", RELATIONSHIPS.as_bytes());
+        zip.add("3D/3dmodel.model", self.model_xml().as_bytes());
+        zip.finish()
+    }
+}
+
+pub fn export_part(part: &Part, colors: &ColorCatalog, options: ThreeMfExportOptions) -> Vec<u8> {
+    let mut writer = ThreeMfWriter::new(colors, options);
+    writer.add_part(part, &Matrix4::identity(), &ColorReference::Current);
+    writer.finish()
+}
+
+pub fn export_model<P: Clone + Eq + PartialEq + Hash + Into<PartAlias>>(
+    model: &Model<P>,
+    parts: &HashMap<P, Part>,
+    colors: &ColorCatalog,
+    options: ThreeMfExportOptions,
+) -> Vec<u8> {
+    let mut writer = ThreeMfWriter::new(colors, options);
+    writer.add_model(model, parts);
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, io::Read};
+
+    use flate2::read::DeflateDecoder;
+    use ldraw::{
+        color::{Color, ColorCatalog, ColorReference, Material, Rgba},
+        document::{Document, MultipartDocument},
+        elements::{Command, PartReference, Quad},
+        library::ResolutionResult,
+        Matrix4, PartAlias, Vector3, Vector4,
+    };
+
+    use super::{export_model, ThreeMfExportOptions};
+    use crate::{model::Model, part::bake_part_from_multipart_document};
+
+    // Reads an entry back from an archive, relying on local headers alone.
+    fn read_entry(archive: &[u8], name: &str) -> Option<String> {
+        let u16_at = |i: usize| u16::from_le_bytes([archive[i], archive[i + 1]]) as usize;
+        let u32_at = |i: usize| {
+            u32::from_le_bytes([archive[i], archive[i + 1], archive[i + 2], archive[i + 3]])
+                as usize
+        };
+
+        let mut offset = 0;
+        while u32_at(offset) == 0x04034b50 {
+            let compressed_size = u32_at(offset + 18);
+            let name_length = u16_at(offset + 26);
+            let data = offset + 30 + name_length;
+            if &archive[offset + 30..data] == name.as_bytes() {
+                let mut result = String::new();
+                DeflateDecoder::new(&archive[data..data + compressed_size])
+                    .read_to_string(&mut result)
+                    .unwrap();
+                return Some(result);
+            }
+            offset = data + compressed_size;
+        }
+        None
+    }
+
+    #[test]
+    fn test_export_model() {
+        let v = |x: f32, z: f32| Vector4::new(x, 0.0, z, 1.0);
+        let square = MultipartDocument {
+            body: Document {
+                name: String::from("square.dat"),
+                commands: vec![Command::Quad(Quad {
+                    color: ColorReference::Current,
+                    a: v(0.0, 0.0),
+                    b: v(1.0, 0.0),
+                    c: v(1.0, 1.0),
+                    d: v(0.0, 1.0),
+                })],
+                ..Default::default()
+            },
+            subparts: Default::default(),
+        };
+        let mut parts = HashMap::new();
+        parts.insert(
+            PartAlias::from("square.dat"),
+            bake_part_from_multipart_document(&square, &ResolutionResult::new(), false),
+        );
+
+        let reference = |x: f32, color: u32| {
+            Command::PartReference(PartReference {
+                color: ColorReference::Unknown(color),
+                matrix: Matrix4::from_translation(Vector3::new(x, 0.0, 0.0)),
+                name: PartAlias::from("square.dat"),
+            })
+        };
+        let document = MultipartDocument {
+            body: Document {
+                commands: vec![reference(0.0, 4), reference(10.0, 1)],
+                ..Default::default()
+            },
+            subparts: Default::default(),
+        };
+        let model = Model::<PartAlias>::from_ldraw_multipart_document_sync(&document);
+
+        let mut colors = ColorCatalog::new();
+        colors.insert(
+            4,
+            Color {
+                code: 4,
+                name: String::from("Red"),
+                color: Rgba::new(0xff, 0x00, 0x00, 0xff),
+                edge: Rgba::new(0x33, 0x00, 0x00, 0xff),
+                luminance: 0,
+                material: Material::Plastic,
+                attributes: HashMap::new(),
+            },
+        );
+
+        let archive = export_model(&model, &parts, &colors, ThreeMfExportOptions::default());
+        assert!(read_entry(&archive, "[Content_Types].xml").is_some());
+        assert!(read_entry(&archive, "_rels/.rels").is_some());
+
+        let xml = read_entry(&archive, "3D/3dmodel.model").unwrap();
+        assert_eq!(xml.matches("<vertex ").count(), 8);
+        assert_eq!(xml.matches("<triangle ").count(), 4);
+        assert!(xml.contains(r##"<base name="Red" displaycolor="#FF0000FF"/>"##));
+        assert!(xml.contains(r##"<base name="ldraw_1" displaycolor="#808080FF"/>"##));
+        assert_eq!(xml.matches(r#"p1="1""#).count(), 2);
+    }
+}
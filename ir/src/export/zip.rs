@@ -0,0 +1,108 @@
+use std::io::Write;
+
+use crc32fast::Hasher;
+use flate2::{write::DeflateEncoder, Compression};
+
+// 1980-01-01 00:00, the earliest date a ZIP archive can hold.
+const DOS_DATE: u16 = 0x21;
+const DOS_TIME: u16 = 0;
+
+struct Entry {
+    name: String,
+    crc: u32,
+    compressed_size: u32,
+    size: u32,
+    offset: u32,
+}
+
+// Just enough of the ZIP format for packaging exported files. Entries are deflated and
+// written in memory, and archives over 4GB are not supported.
+#[derive(Default)]
+pub(crate) struct ZipWriter {
+    buffer: Vec<u8>,
+    entries: Vec<Entry>,
+}
+
+fn put_u16(buffer: &mut Vec<u8>, v: u16) {
+    buffer.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u32(buffer: &mut Vec<u8>, v: u32) {
+    buffer.extend_from_slice(&v.to_le_bytes());
+}
+
+impl ZipWriter {
+    pub(crate) fn add(&mut self, name: &str, data: &[u8]) {
+        let mut hasher = Hasher::new();
+        hasher.update(data);
+        let crc = hasher.finalize();
+
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let entry = Entry {
+            name: name.to_string(),
+            crc,
+            compressed_size: compressed.len() as u32,
+            size: data.len() as u32,
+            offset: self.buffer.len() as u32,
+        };
+
+        let buffer = &mut self.buffer;
+        put_u32(buffer, 0x04034b50);
+        put_u16(buffer, 20);
+        put_u16(buffer, 0);
+        put_u16(buffer, 8);
+        put_u16(buffer, DOS_TIME);
+        put_u16(buffer, DOS_DATE);
+        put_u32(buffer, entry.crc);
+        put_u32(buffer, entry.compressed_size);
+        put_u32(buffer, entry.size);
+        put_u16(buffer, entry.name.len() as u16);
+        put_u16(buffer, 0);
+        buffer.extend_from_slice(entry.name.as_bytes());
+        buffer.extend_from_slice(&compressed);
+
+        self.entries.push(entry);
+    }
+
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        let directory_offset = self.buffer.len() as u32;
+
+        let buffer = &mut self.buffer;
+        for entry in self.entries.iter() {
+            put_u32(buffer, 0x02014b50);
+            put_u16(buffer, 20);
+            put_u16(buffer, 20);
+            put_u16(buffer, 0);
+            put_u16(buffer, 8);
+            put_u16(buffer, DOS_TIME);
+            put_u16(buffer, DOS_DATE);
+            put_u32(buffer, entry.crc);
+            put_u32(buffer, entry.compressed_size);
+            put_u32(buffer, entry.size);
+            put_u16(buffer, entry.name.len() as u16);
+            // Extra field, comment, disk number, internal and external attributes
+            put_u16(buffer, 0);
+            put_u16(buffer, 0);
+            put_u16(buffer, 0);
+            put_u16(buffer, 0);
+            put_u32(buffer, 0);
+            put_u32(buffer, entry.offset);
+            buffer.extend_from_slice(entry.name.as_bytes());
+        }
+        let directory_size = buffer.len() as u32 - directory_offset;
+
+        put_u32(buffer, 0x06054b50);
+        put_u16(buffer, 0);
+        put_u16(buffer, 0);
+        put_u16(buffer, self.entries.len() as u16);
+        put_u16(buffer, self.entries.len() as u16);
+        put_u32(buffer, directory_size);
+        put_u32(buffer, directory_offset);
+        put_u16(buffer, 0);
+
+        self.buffer
+    }
+}
@@ -0,0 +1,444 @@
+use std::{collections::HashMap, f32::consts::PI};
+
+use cgmath::{InnerSpace, SquareMatrix};
+use ldraw::{
+    color::{ColorCatalog, ColorReference},
+    document::{Document as LdrawDocument, MultipartDocument as LdrawMultipartDocument},
+    elements::{
+        Command, LdCadGenerator, LdCadPathPoint, Line, Meta, OptionalLine, PartReference, Quad,
+    },
+    parser::parse_ldcad_generator,
+    Matrix4, PartAlias, Vector3,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct FlexOptions {
+    // Radius of the tube drawn in place of skin parts, which are not looked at.
+    pub radius: f32,
+    pub sides: usize,
+    // Paths are split into pieces no longer than this.
+    pub segment_length: f32,
+}
+
+impl Default for FlexOptions {
+    fn default() -> Self {
+        Self {
+            radius: 4.0,
+            sides: 12,
+            segment_length: 4.0,
+        }
+    }
+}
+
+// Path of a flexible part described with LDCad commands.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlexPath {
+    pub points: Vec<LdCadPathPoint>,
+    pub color: u32,
+    // Bands loop back to their first point.
+    pub closed: bool,
+    // Index of the command the path starts at.
+    pub position: usize,
+}
+
+// Finds the path of a document LDCad has not generated geometry for. Malformed commands are
+// skipped.
+pub fn find_flex_path(document: &LdrawDocument) -> Option<FlexPath> {
+    let mut path = FlexPath {
+        points: Vec::new(),
+        color: 16,
+        closed: false,
+        position: 0,
+    };
+    for (index, command) in document.commands.iter().enumerate() {
+        let generator = match command {
+            Command::Meta(Meta::LdCad(command)) => match parse_ldcad_generator(command) {
+                Ok(Some(generator)) => generator,
+                _ => continue,
+            },
+            _ => continue,
+        };
+        match generator {
+            LdCadGenerator::Content(kind) => path.closed = kind == "band",
+            LdCadGenerator::PathPoint(point) => {
+                if path.points.is_empty() {
+                    path.position = index;
+                }
+                path.points.push(point);
+            }
+            LdCadGenerator::PathSkin(skin) => path.color = skin.color,
+            LdCadGenerator::Generated => return None,
+            LdCadGenerator::Snap(_) => {}
+        }
+    }
+
+    if path.points.len() < 2 {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+fn position(point: &LdCadPathPoint) -> Vector3 {
+    point.matrix.w.truncate()
+}
+
+// Direction the path leaves a point at, falling back to where neighbors are.
+fn direction(point: &LdCadPathPoint, fallback: Vector3) -> Vector3 {
+    let axis = point.matrix.y.truncate();
+    if axis.magnitude2() > f32::EPSILON {
+        axis.normalize()
+    } else if fallback.magnitude2() > f32::EPSILON {
+        fallback.normalize()
+    } else {
+        Vector3::unit_y()
+    }
+}
+
+// Samples positions and tangents along bezier curves between points.
+fn sample_path(path: &FlexPath, options: &FlexOptions) -> Vec<(Vector3, Vector3)> {
+    let count = path.points.len();
+    let segments = if path.closed { count } else { count - 1 };
+    let neighbors = |i: usize| {
+        let prev = &path.points[(i + count - 1) % count];
+        let next = &path.points[(i + 1) % count];
+        position(next) - position(prev)
+    };
+
+    let mut samples = Vec::new();
+    for i in 0..segments {
+        let (a, b) = (&path.points[i], &path.points[(i + 1) % count]);
+        let p0 = position(a);
+        let p3 = position(b);
+        let p1 = p0 + direction(a, neighbors(i)) * a.next_distance;
+        let p2 = p3 - direction(b, neighbors((i + 1) % count)) * b.prev_distance;
+
+        let length = (p1 - p0).magnitude() + (p2 - p1).magnitude() + (p3 - p2).magnitude();
+        let steps = ((length / options.segment_length.max(0.01)).ceil() as usize).clamp(1, 256);
+        let last = i + 1 == segments && !path.closed;
+        for step in 0..steps + last as usize {
+            let t = step as f32 / steps as f32;
+            let u = 1.0 - t;
+            let point = p0 * (u * u * u)
+                + p1 * (3.0 * u * u * t)
+                + p2 * (3.0 * u * t * t)
+                + p3 * (t * t * t);
+            let tangent =
+                (p1 - p0) * (3.0 * u * u) + (p2 - p1) * (6.0 * u * t) + (p3 - p2) * (3.0 * t * t);
+            let tangent = if tangent.magnitude2() > f32::EPSILON {
+                tangent.normalize()
+            } else {
+                direction(a, p3 - p0)
+            };
+            samples.push((point, tangent));
+        }
+    }
+    samples
+}
+
+// Builds rings around sampled points, carrying the normal along so that the tube does not twist.
+fn build_rings(
+    path: &FlexPath,
+    samples: &[(Vector3, Vector3)],
+    options: &FlexOptions,
+) -> Vec<Vec<Vector3>> {
+    let sides = options.sides.max(3);
+    let (_, first_tangent) = samples[0];
+    let mut normal = path.points[0].matrix.x.truncate();
+    normal -= first_tangent * normal.dot(first_tangent);
+    if normal.magnitude2() <= f32::EPSILON {
+        normal = first_tangent.cross(Vector3::unit_x());
+        if normal.magnitude2() <= f32::EPSILON {
+            normal = first_tangent.cross(Vector3::unit_z());
+        }
+    }
+
+    let mut rings = Vec::with_capacity(samples.len());
+    for (point, tangent) in samples.iter() {
+        normal -= tangent * normal.dot(*tangent);
+        if normal.magnitude2() > f32::EPSILON {
+            normal = normal.normalize();
+        }
+        let binormal = tangent.cross(normal);
+        rings.push(
+            (0..sides)
+                .map(|side| {
+                    let angle = 2.0 * PI * side as f32 / sides as f32;
+                    point + (normal * angle.cos() + binormal * angle.sin()) * options.radius
+                })
+                .collect(),
+        );
+    }
+    rings
+}
+
+// Tessellates a path into a tube drawn in current color, with edges in complement color.
+pub fn tessellate_flex_path(path: &FlexPath, options: &FlexOptions) -> Vec<Command> {
+    let samples = sample_path(path, options);
+    if samples.is_empty() {
+        return Vec::new();
+    }
+    let rings = build_rings(path, &samples, options);
+    let sides = rings[0].len();
+
+    let mut pairs = rings.windows(2).map(|v| (&v[0], &v[1])).collect::<Vec<_>>();
+    if path.closed {
+        pairs.push((&rings[rings.len() - 1], &rings[0]));
+    }
+
+    let mut commands = Vec::new();
+    for (a, b) in pairs {
+        for j in 0..sides {
+            let k = (j + 1) % sides;
+            commands.push(Command::Quad(Quad {
+                color: ColorReference::Current,
+                a: a[j].extend(1.0),
+                b: a[k].extend(1.0),
+                c: b[k].extend(1.0),
+                d: b[j].extend(1.0),
+            }));
+            commands.push(Command::OptionalLine(OptionalLine {
+                color: ColorReference::Complement,
+                a: a[j].extend(1.0),
+                b: b[j].extend(1.0),
+                c: a[(j + sides - 1) % sides].extend(1.0),
+                d: a[k].extend(1.0),
+            }));
+        }
+    }
+    if !path.closed {
+        for ring in [&rings[0], &rings[rings.len() - 1]] {
+            for j in 0..sides {
+                commands.push(Command::Line(Line {
+                    color: ColorReference::Complement,
+                    a: ring[j].extend(1.0),
+                    b: ring[(j + 1) % sides].extend(1.0),
+                }));
+            }
+        }
+    }
+    commands
+}
+
+pub struct FlexParts {
+    // Copy of the document referring to generated parts where paths start.
+    pub document: LdrawMultipartDocument,
+    pub parts: HashMap<PartAlias, LdrawMultipartDocument>,
+    // Where references to generated parts have been inserted, by subpart or None for the body.
+    pub insertions: HashMap<Option<PartAlias>, usize>,
+}
+
+impl FlexParts {
+    // Index of a command of the generated document in the original one. References to
+    // generated parts are taken as the first point of their paths.
+    pub fn original_command(&self, subpart: Option<&PartAlias>, command: usize) -> usize {
+        match self.insertions.get(&subpart.cloned()) {
+            Some(position) if command > *position => command - 1,
+            _ => command,
+        }
+    }
+}
+
+fn generate_flex_part(
+    document: &mut LdrawDocument,
+    colors: &ColorCatalog,
+    options: &FlexOptions,
+    parts: &mut HashMap<PartAlias, LdrawMultipartDocument>,
+) -> Option<usize> {
+    let path = find_flex_path(document)?;
+    let name = if document.name.is_empty() {
+        String::from("main")
+    } else {
+        document.name.clone()
+    };
+    let alias = PartAlias::from(format!("{}.flex.dat", name));
+
+    document.commands.insert(
+        path.position,
+        Command::PartReference(PartReference {
+            color: ColorReference::resolve(path.color, colors),
+            matrix: Matrix4::identity(),
+            name: alias.clone(),
+        }),
+    );
+    parts.insert(
+        alias.clone(),
+        LdrawMultipartDocument {
+            body: LdrawDocument {
+                name: alias.original,
+                description: format!("Flexible part of {}", name),
+                commands: tessellate_flex_path(&path, options),
+                ..Default::default()
+            },
+            subparts: HashMap::new(),
+        },
+    );
+    Some(path.position)
+}
+
+// Generates geometry for flexible parts saved without what LDCad generates from their paths,
+// which would otherwise be missing. Returns None if there is nothing to generate.
+pub fn generate_flex_parts(
+    document: &LdrawMultipartDocument,
+    colors: &ColorCatalog,
+    options: &FlexOptions,
+) -> Option<FlexParts> {
+    if find_flex_path(&document.body).is_none()
+        && document
+            .subparts
+            .values()
+            .all(|v| find_flex_path(v).is_none())
+    {
+        return None;
+    }
+
+    let mut document = document.clone();
+    let mut parts = HashMap::new();
+    let mut insertions = HashMap::new();
+
+    if let Some(position) = generate_flex_part(&mut document.body, colors, options, &mut parts) {
+        insertions.insert(None, position);
+    }
+    for (alias, subpart) in document.subparts.iter_mut() {
+        if let Some(position) = generate_flex_part(subpart, colors, options, &mut parts) {
+            insertions.insert(Some(alias.clone()), position);
+        }
+    }
+
+    if parts.is_empty() {
+        None
+    } else {
+        Some(FlexParts {
+            document,
+            parts,
+            insertions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ldraw::{
+        color::ColorCatalog,
+        document::{Document, MultipartDocument},
+        elements::{Command, LdCadCommand, Meta},
+        PartAlias,
+    };
+
+    use super::{find_flex_path, generate_flex_parts, tessellate_flex_path, FlexOptions};
+
+    fn ldcad(kind: &str, parameters: &[(&str, &str)]) -> Command {
+        Command::Meta(Meta::LdCad(LdCadCommand {
+            kind: kind.to_string(),
+            parameters: parameters
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }))
+    }
+
+    fn hose() -> Document {
+        Document {
+            name: String::from("hose.ldr"),
+            commands: vec![
+                ldcad("CONTENT", &[("type", "path")]),
+                ldcad("PATH_SKIN", &[("donCol", "4"), ("donPart", "754.dat")]),
+                Command::Meta(Meta::Step),
+                ldcad(
+                    "PATH_POINT",
+                    &[("posOri", "0 0 0 1 0 0 0 1 0 0 0 1"), ("nextCPDist", "20")],
+                ),
+                ldcad(
+                    "PATH_POINT",
+                    &[("posOri", "40 0 0 1 0 0 0 1 0 0 0 1"), ("prevCPDist", "20")],
+                ),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_find_flex_path() {
+        let path = find_flex_path(&hose()).unwrap();
+        assert_eq!(path.points.len(), 2);
+        assert_eq!(path.color, 4);
+        assert_eq!(path.position, 3);
+        assert!(!path.closed);
+
+        let mut generated = hose();
+        generated
+            .commands
+            .push(ldcad("GENERATED", &[("generator", "LDCad")]));
+        assert_eq!(find_flex_path(&generated), None);
+    }
+
+    #[test]
+    fn test_tessellate_flex_path() {
+        let path = find_flex_path(&hose()).unwrap();
+        let options = FlexOptions {
+            sides: 6,
+            segment_length: 10.0,
+            ..Default::default()
+        };
+        let commands = tessellate_flex_path(&path, &options);
+
+        let quads = commands
+            .iter()
+            .filter(|v| matches!(v, Command::Quad(_)))
+            .count();
+        let lines = commands
+            .iter()
+            .filter(|v| matches!(v, Command::Line(_)))
+            .count();
+        assert!(quads > 0 && quads % 6 == 0);
+        assert_eq!(lines, 12);
+
+        // Every vertex lies at the radius from the axis of the curve, which stays in the XY plane
+        // when starting out towards Y.
+        for command in commands.iter() {
+            if let Command::Quad(quad) = command {
+                for v in [quad.a, quad.b, quad.c, quad.d] {
+                    assert!(v.z.abs() <= options.radius + 1e-3);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_flex_parts() {
+        let document = MultipartDocument {
+            body: Document {
+                name: String::from("main.ldr"),
+                ..Default::default()
+            },
+            subparts: [(PartAlias::from("hose.ldr"), hose())]
+                .into_iter()
+                .collect(),
+        };
+        let flex =
+            generate_flex_parts(&document, &ColorCatalog::new(), &Default::default()).unwrap();
+
+        let alias = PartAlias::from("hose.ldr.flex.dat");
+        assert!(flex.parts[&alias].body.has_primitives());
+        let subpart = &flex.document.subparts[&PartAlias::from("hose.ldr")];
+        match &subpart.commands[3] {
+            Command::PartReference(r) => assert_eq!(r.name, alias),
+            v => panic!("expected Command::PartReference(...), got {:?}", v),
+        }
+        let hose = Some(&PartAlias::from("hose.ldr"));
+        assert_eq!(flex.original_command(hose, 2), 2);
+        assert_eq!(flex.original_command(hose, 3), 3);
+        assert_eq!(flex.original_command(hose, 4), 3);
+        assert_eq!(flex.original_command(None, 4), 4);
+
+        assert!(generate_flex_parts(
+            &MultipartDocument {
+                body: Document::default(),
+                subparts: Default::default(),
+            },
+            &ColorCatalog::new(),
+            &Default::default()
+        )
+        .is_none());
+    }
+}
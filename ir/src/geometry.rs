@@ -234,6 +234,17 @@ impl BoundingBox3 {
             Vector3::new(self.max.x, self.max.y, self.max.z),
         ]
     }
+
+    pub fn intersects(&self, other: &Self) -> bool {
+        !self.null
+            && !other.null
+            && self.min.x < other.max.x
+            && self.max.x > other.min.x
+            && self.min.y < other.max.y
+            && self.max.y > other.min.y
+            && self.min.z < other.max.z
+            && self.max.z > other.min.z
+    }
 }
 
 impl Default for BoundingBox3 {
@@ -11,10 +11,19 @@ use serde::{
     Deserialize, Serialize,
 };
 
+pub mod analysis;
+pub mod animation;
+pub mod articulation;
 pub mod constraints;
+pub mod export;
+pub mod flex;
 pub mod geometry;
 pub mod model;
+pub mod occlusion;
 pub mod part;
+pub mod steps;
+#[cfg(test)]
+mod testing;
 
 #[derive(Clone, Debug)]
 pub struct MeshGroupKey {
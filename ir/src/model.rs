@@ -2,20 +2,22 @@ use std::{
     collections::{HashMap, HashSet},
     fmt,
     hash::Hash,
-    sync::{Arc, RwLock},
     vec::Vec,
 };
 
 use cgmath::SquareMatrix;
 use ldraw::{
     color::{ColorCatalog, ColorReference},
-    document::{Document as LdrawDocument, MultipartDocument as LdrawMultipartDocument},
-    elements::{Command, Meta},
+    document::{
+        Document as LdrawDocument, MultipartDocument as LdrawMultipartDocument, SourceLines,
+    },
+    elements::{Command, Meta, PartReference},
+    graph::CycleGuard,
     library::{resolve_dependencies, LibraryLoader, PartCache, ResolutionResult},
     Matrix4, PartAlias, Vector3,
 };
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
+use uuid::{Builder, Uuid};
 
 use crate::{
     geometry::BoundingBox3,
@@ -24,9 +26,49 @@ use crate::{
     },
 };
 
+// FNV-1a, which unlike the hasher of the standard library gives the same result on every
+// platform and release.
+fn stable_hash(fields: &[&[u8]]) -> [u8; 16] {
+    const OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+
+    let mut hash = OFFSET_BASIS;
+    for field in fields {
+        // Lengths go in first, so that moving bytes between fields changes the hash.
+        for byte in (field.len() as u64)
+            .to_le_bytes()
+            .iter()
+            .chain(field.iter())
+        {
+            hash ^= *byte as u128;
+            hash = hash.wrapping_mul(PRIME);
+        }
+    }
+    hash.to_le_bytes()
+}
+
 #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq, Deserialize, Serialize)]
 pub struct ObjectId(Uuid);
 
+impl ObjectId {
+    // Derives an id from the path of the document and where the object is in there, so that
+    // building a model from the same file again gives the same ids.
+    pub fn stable(path: &str, source: &ObjectSource) -> Self {
+        let subpart = source.subpart.as_ref().map(|v| v.normalized.as_bytes());
+        let command = source.command.map(|v| (v as u64).to_le_bytes());
+        Self(
+            Builder::from_custom_bytes(stable_hash(&[
+                b"object",
+                path.as_bytes(),
+                subpart.unwrap_or_default(),
+                &[subpart.is_some() as u8],
+                command.as_ref().map(|v| &v[..]).unwrap_or_default(),
+            ]))
+            .into_uuid(),
+        )
+    }
+}
+
 impl From<Uuid> for ObjectId {
     fn from(value: Uuid) -> Self {
         Self(value)
@@ -48,6 +90,20 @@ impl fmt::Display for ObjectId {
 #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq, Deserialize, Serialize)]
 pub struct GroupId(Uuid);
 
+impl GroupId {
+    // Derives an id from the path of the document and the subpart the group is made of.
+    pub fn stable(path: &str, subpart: &PartAlias) -> Self {
+        Self(
+            Builder::from_custom_bytes(stable_hash(&[
+                b"group",
+                path.as_bytes(),
+                subpart.normalized.as_bytes(),
+            ]))
+            .into_uuid(),
+        )
+    }
+}
+
 impl From<Uuid> for GroupId {
     fn from(value: Uuid) -> Self {
         Self(value)
@@ -74,6 +130,17 @@ pub enum ObjectInstance<P> {
     Annotation(Annotation),
 }
 
+// Where an object has been built from: the subpart of the document, or None for the body, and
+// the index of the command among its commands. Objects made of the lines, triangles and quads
+// placed directly in a file have no command. The line is known only if the lines of the
+// document have been given.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub struct ObjectSource {
+    pub subpart: Option<PartAlias>,
+    pub command: Option<usize>,
+    pub line: Option<usize>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Object<P> {
     pub id: ObjectId,
@@ -104,6 +171,9 @@ pub struct Annotation {
 pub struct ObjectGroup<P> {
     pub id: GroupId,
     pub name: String,
+    // Alias of the subpart in the document, which may differ from the name.
+    #[serde(default)]
+    pub subpart: Option<PartAlias>,
     pub objects: Vec<Object<P>>,
     pub pivot: Vector3,
 }
@@ -113,10 +183,18 @@ impl<P> ObjectGroup<P> {
         Self {
             id,
             name,
+            subpart: None,
             objects: Vec::new(),
             pivot: pivot.unwrap_or_else(|| Vector3::new(0.0, 0.0, 0.0)),
         }
     }
+
+    // Alias references to the group go by.
+    pub fn alias(&self) -> PartAlias {
+        self.subpart
+            .clone()
+            .unwrap_or_else(|| PartAlias::from(&self.name))
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -124,6 +202,26 @@ pub struct Model<P: Clone + Eq + PartialEq + Hash> {
     pub object_groups: HashMap<GroupId, ObjectGroup<P>>,
     pub objects: Vec<Object<P>>,
     pub embedded_parts: HashMap<P, Part>,
+    // Objects built from a document. Ones added afterwards are not in here.
+    #[serde(default)]
+    pub sources: HashMap<ObjectId, ObjectSource>,
+}
+
+pub(crate) fn matches_reference<P: Into<PartAlias> + Clone>(
+    object: &Object<P>,
+    reference: &PartReference,
+    object_groups: &HashMap<GroupId, ObjectGroup<P>>,
+) -> bool {
+    let (matrix, color, name): (_, &ColorReference, PartAlias) = match &object.data {
+        ObjectInstance::Part(p) => (&p.matrix, &p.color, p.part.clone().into()),
+        ObjectInstance::PartGroup(pg) => match object_groups.get(&pg.group_id) {
+            Some(group) => (&pg.matrix, &pg.color, group.alias()),
+            None => return false,
+        },
+        _ => return false,
+    };
+
+    reference.matrix == *matrix && reference.color == *color && reference.name == name
 }
 
 impl<P: Clone + Eq + PartialEq + Hash> Default for Model<P> {
@@ -132,18 +230,22 @@ impl<P: Clone + Eq + PartialEq + Hash> Default for Model<P> {
             object_groups: HashMap::new(),
             objects: Vec::new(),
             embedded_parts: HashMap::new(),
+            sources: HashMap::new(),
         }
     }
 }
 
 fn build_objects<P: Clone + Eq + PartialEq + Hash + From<PartAlias>>(
     document: &LdrawDocument,
+    subpart: Option<&PartAlias>,
     subparts: Option<&HashMap<P, GroupId>>,
+    sources: &mut HashMap<ObjectId, ObjectSource>,
 ) -> Vec<Object<P>> {
     document
         .commands
         .iter()
-        .filter_map(|cmd| {
+        .enumerate()
+        .filter_map(|(index, cmd)| {
             let data = match cmd {
                 Command::PartReference(r) => match subparts {
                     Some(subparts) => match subparts.get(&r.name.clone().into()) {
@@ -168,9 +270,17 @@ fn build_objects<P: Clone + Eq + PartialEq + Hash + From<PartAlias>>(
                 _ => None,
             };
 
-            data.map(|v| Object {
-                id: Uuid::new_v4().into(),
-                data: v,
+            data.map(|v| {
+                let id = ObjectId::from(Uuid::new_v4());
+                sources.insert(
+                    id,
+                    ObjectSource {
+                        subpart: subpart.cloned(),
+                        command: Some(index),
+                        line: None,
+                    },
+                );
+                Object { id, data: v }
             })
         })
         .collect::<Vec<_>>()
@@ -244,6 +354,7 @@ impl<P: Eq + PartialEq + Hash + Clone + From<PartAlias>> Model<P> {
 
         let mut embedded_parts: HashMap<P, Part> = HashMap::new();
 
+        let mut sources = HashMap::new();
         let mut object_groups = HashMap::new();
         for (alias, subpart) in document.subparts.iter() {
             let converted_alias = alias.clone().into();
@@ -254,30 +365,68 @@ impl<P: Eq + PartialEq + Hash + Clone + From<PartAlias>> Model<P> {
                     ObjectGroup {
                         id,
                         name: subpart.name.clone(),
-                        objects: build_objects::<P>(subpart, Some(&subparts)),
+                        subpart: Some(alias.clone()),
+                        objects: build_objects::<P>(
+                            subpart,
+                            Some(alias),
+                            Some(&subparts),
+                            &mut sources,
+                        ),
                         pivot: Vector3::new(0.0, 0.0, 0.0),
                     },
                 );
             }
         }
-        let mut objects = build_objects::<P>(&document.body, Some(&subparts));
+        let mut objects = build_objects::<P>(&document.body, None, Some(&subparts), &mut sources);
 
         if let Some((alias, part, object)) = extract_document_primitives::<P>(&document.body) {
             embedded_parts.insert(alias.clone(), part);
+            sources.insert(object.id, ObjectSource::default());
             objects.push(object);
         }
 
-        Model {
+        let mut model = Model {
             object_groups,
             objects,
             embedded_parts: HashMap::new(),
-        }
+            sources,
+        };
+        model.drop_circular_references();
+        model
     }
 
     pub async fn from_ldraw_multipart_document<L: LibraryLoader>(
         document: &LdrawMultipartDocument,
         colors: &ColorCatalog,
-        inline_loader: Option<(&L, Arc<RwLock<PartCache>>)>,
+        inline_loader: Option<(&L, PartCache)>,
+    ) -> Self {
+        Self::build_from_ldraw_multipart_document(document, colors, inline_loader, None).await
+    }
+
+    // Builds the model of a document that has changed since this model was built from it.
+    // Embedded parts of subparts not in changed are taken from this model instead of being
+    // baked again.
+    pub async fn rebuild_from_ldraw_multipart_document<L: LibraryLoader>(
+        &self,
+        document: &LdrawMultipartDocument,
+        changed: &HashSet<PartAlias>,
+        colors: &ColorCatalog,
+        inline_loader: Option<(&L, PartCache)>,
+    ) -> Self {
+        Self::build_from_ldraw_multipart_document(
+            document,
+            colors,
+            inline_loader,
+            Some((&self.embedded_parts, changed)),
+        )
+        .await
+    }
+
+    async fn build_from_ldraw_multipart_document<L: LibraryLoader>(
+        document: &LdrawMultipartDocument,
+        colors: &ColorCatalog,
+        inline_loader: Option<(&L, PartCache)>,
+        previous: Option<(&HashMap<P, Part>, &HashSet<PartAlias>)>,
     ) -> Self {
         let subparts = document
             .subparts
@@ -289,14 +438,17 @@ impl<P: Eq + PartialEq + Hash + Clone + From<PartAlias>> Model<P> {
         if let Some((loader, cache)) = inline_loader {
             for (alias, subpart) in document.subparts.iter() {
                 if subpart.has_primitives() {
-                    let resolution_result = resolve_dependencies(
-                        subpart,
-                        Arc::clone(&cache),
-                        colors,
-                        loader,
-                        &|_, _| {},
-                    )
-                    .await;
+                    let reused = previous
+                        .filter(|(_, changed)| !changed.contains(alias))
+                        .and_then(|(parts, _)| parts.get(&alias.clone().into()));
+                    if let Some(part) = reused {
+                        embedded_parts.insert(alias.clone().into(), part.clone());
+                        continue;
+                    }
+
+                    let resolution_result =
+                        resolve_dependencies(subpart, cache.clone(), colors, loader, &|_, _| {})
+                            .await;
 
                     let part = bake_part_from_document(subpart, &resolution_result, true);
 
@@ -305,6 +457,7 @@ impl<P: Eq + PartialEq + Hash + Clone + From<PartAlias>> Model<P> {
             }
         }
 
+        let mut sources = HashMap::new();
         let mut object_groups = HashMap::new();
         for (alias, subpart) in document.subparts.iter() {
             let converted_alias = alias.clone().into();
@@ -315,32 +468,100 @@ impl<P: Eq + PartialEq + Hash + Clone + From<PartAlias>> Model<P> {
                     ObjectGroup {
                         id,
                         name: subpart.name.clone(),
-                        objects: build_objects::<P>(subpart, Some(&subparts)),
+                        subpart: Some(alias.clone()),
+                        objects: build_objects::<P>(
+                            subpart,
+                            Some(alias),
+                            Some(&subparts),
+                            &mut sources,
+                        ),
                         pivot: Vector3::new(0.0, 0.0, 0.0),
                     },
                 );
             }
         }
-        let mut objects = build_objects::<P>(&document.body, Some(&subparts));
+        let mut objects = build_objects::<P>(&document.body, None, Some(&subparts), &mut sources);
 
         if let Some((alias, part, object)) = extract_document_primitives::<P>(&document.body) {
             embedded_parts.insert(alias.clone(), part);
+            sources.insert(object.id, ObjectSource::default());
             objects.push(object);
         }
 
-        Model {
+        let mut model = Model {
             object_groups,
             objects,
             embedded_parts,
+            sources,
+        };
+        model.drop_circular_references();
+        model
+    }
+
+    // Submodels referring back to themselves could never be drawn, and every traversal of
+    // the model would recurse forever on them.
+    fn drop_circular_references(&mut self) {
+        fn visit<P>(
+            groups: &HashMap<GroupId, ObjectGroup<P>>,
+            id: GroupId,
+            ancestors: &mut CycleGuard<GroupId>,
+            visited: &mut HashSet<GroupId>,
+            circular: &mut HashSet<ObjectId>,
+        ) {
+            if visited.contains(&id) || !ancestors.enter(id) {
+                return;
+            }
+            for object in groups.get(&id).map_or(&[][..], |v| &v.objects) {
+                if let ObjectInstance::PartGroup(pg) = &object.data {
+                    if ancestors.path().contains(&pg.group_id) {
+                        circular.insert(object.id);
+                    } else {
+                        visit(groups, pg.group_id, ancestors, visited, circular);
+                    }
+                }
+            }
+            ancestors.leave();
+            visited.insert(id);
+        }
+
+        let mut roots = self
+            .objects
+            .iter()
+            .filter_map(|v| match &v.data {
+                ObjectInstance::PartGroup(pg) => Some(pg.group_id),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let mut rest = self.object_groups.values().collect::<Vec<_>>();
+        rest.sort_by(|a, b| a.alias().normalized.cmp(&b.alias().normalized));
+        roots.extend(rest.into_iter().map(|v| v.id));
+
+        let mut visited = HashSet::new();
+        let mut circular = HashSet::new();
+        for id in roots {
+            visit(
+                &self.object_groups,
+                id,
+                &mut CycleGuard::new(),
+                &mut visited,
+                &mut circular,
+            );
+        }
+
+        for group in self.object_groups.values_mut() {
+            group.objects.retain(|v| !circular.contains(&v.id));
         }
+        self.sources.retain(|id, _| !circular.contains(id));
     }
 
     pub fn from_ldraw_document(document: &LdrawDocument) -> Self {
         let mut embedded_parts = HashMap::new();
-        let mut objects = build_objects(document, None);
+        let mut sources = HashMap::new();
+        let mut objects = build_objects(document, None, None, &mut sources);
 
         if let Some((alias, part, object)) = extract_document_primitives::<P>(document) {
             embedded_parts.insert(alias.clone(), part);
+            sources.insert(object.id, ObjectSource::default());
             objects.push(object);
         }
 
@@ -348,6 +569,7 @@ impl<P: Eq + PartialEq + Hash + Clone + From<PartAlias>> Model<P> {
             object_groups: HashMap::new(),
             objects,
             embedded_parts,
+            sources,
         }
     }
 
@@ -361,10 +583,8 @@ impl<P: Eq + PartialEq + Hash + Clone + From<PartAlias>> Model<P> {
     fn build_dependencies(&self, deps: &mut HashSet<P>, objects: &[Object<P>]) {
         for object in objects.iter() {
             match &object.data {
-                ObjectInstance::Part(p) => {
-                    if !deps.contains(&p.part) {
-                        deps.insert(p.part.clone());
-                    }
+                ObjectInstance::Part(p) if !deps.contains(&p.part) => {
+                    deps.insert(p.part.clone());
                 }
                 ObjectInstance::PartGroup(pg) => {
                     if let Some(pg) = self.object_groups.get(&pg.group_id) {
@@ -384,7 +604,7 @@ impl<P: Eq + PartialEq + Hash + Clone + From<PartAlias>> Model<P> {
         dependencies
     }
 
-    fn calculate_bounding_box_recursive(
+    pub(crate) fn calculate_bounding_box_recursive(
         &self,
         bounding_box: &mut BoundingBox3,
         matrix: Matrix4,
@@ -456,4 +676,613 @@ impl<P: Eq + PartialEq + Hash + Clone + From<PartAlias>> Model<P> {
             None => Some(self.objects.iter()),
         }
     }
+
+    pub fn source_of(&self, id: &ObjectId) -> Option<&ObjectSource> {
+        self.sources.get(id)
+    }
+
+    pub fn find_object(&self, subpart: Option<&PartAlias>, command: usize) -> Option<ObjectId> {
+        self.sources
+            .iter()
+            .find(|(_, v)| v.subpart.as_ref() == subpart && v.command == Some(command))
+            .map(|(id, _)| *id)
+    }
+
+    // Records lines of the commands objects have been built from, as returned by the parser
+    // along with the document.
+    pub fn set_source_lines(&mut self, lines: &SourceLines) {
+        for source in self.sources.values_mut() {
+            source.line = source
+                .command
+                .and_then(|v| lines.line(source.subpart.as_ref(), v));
+        }
+    }
+
+    // Points sources at commands of another version of the document, such as the one a
+    // generated document has been derived from.
+    pub fn map_source_commands(&mut self, map: impl Fn(Option<&PartAlias>, usize) -> usize) {
+        for source in self.sources.values_mut() {
+            source.command = source.command.map(|v| map(source.subpart.as_ref(), v));
+        }
+    }
+
+    pub fn line_of(&self, id: &ObjectId) -> Option<usize> {
+        self.sources.get(id)?.line
+    }
+
+    pub fn object_at_line(&self, subpart: Option<&PartAlias>, line: usize) -> Option<ObjectId> {
+        self.sources
+            .iter()
+            .find(|(_, v)| v.subpart.as_ref() == subpart && v.line == Some(line))
+            .map(|(id, _)| *id)
+    }
+
+    // Replaces random ids of objects and groups built from the document at the given path with
+    // ones derived from where they are in there. Ids of objects added afterwards are kept.
+    pub fn assign_stable_ids(&mut self, path: &str) {
+        let groups = self
+            .object_groups
+            .drain()
+            .map(|(id, mut group)| {
+                group.id = GroupId::stable(path, &group.alias());
+                (id, group)
+            })
+            .collect::<Vec<_>>();
+        let group_ids = groups
+            .iter()
+            .map(|(id, group)| (*id, group.id))
+            .collect::<HashMap<_, _>>();
+        self.object_groups = groups
+            .into_iter()
+            .map(|(_, group)| (group.id, group))
+            .collect();
+
+        let mut sources = HashMap::with_capacity(self.sources.len());
+        let mut assign = |objects: &mut Vec<Object<P>>| {
+            for object in objects.iter_mut() {
+                if let Some(source) = self.sources.get(&object.id) {
+                    object.id = ObjectId::stable(path, source);
+                    sources.insert(object.id, source.clone());
+                }
+                if let ObjectInstance::PartGroup(ref mut pg) = object.data {
+                    if let Some(id) = group_ids.get(&pg.group_id) {
+                        pg.group_id = *id;
+                    }
+                }
+            }
+        };
+        assign(&mut self.objects);
+        for group in self.object_groups.values_mut() {
+            assign(&mut group.objects);
+        }
+        self.sources = sources;
+    }
+}
+
+impl<P: Clone + Eq + PartialEq + Hash + Into<PartAlias>> Model<P> {
+    pub fn get_ldraw_document_mut<'a>(
+        &self,
+        document: &'a mut LdrawMultipartDocument,
+        group_id: Option<GroupId>,
+    ) -> Option<&'a mut LdrawDocument> {
+        match group_id {
+            Some(group_id) => {
+                let alias = self.object_groups.get(&group_id)?.alias();
+                document.subparts.get_mut(&alias)
+            }
+            None => Some(&mut document.body),
+        }
+    }
+
+    // Finds the part reference that the object has been built from. Placed objects of a group
+    // follow the references of its document in order, which edits to other commands leave
+    // alone. Nothing is returned once they no longer line up, e.g. after references have been
+    // dropped or reordered.
+    pub fn find_part_reference_mut<'a>(
+        &self,
+        document: &'a mut LdrawMultipartDocument,
+        id: &ObjectId,
+    ) -> Option<&'a mut PartReference> {
+        let (group_id, objects) = std::iter::once((None, &self.objects))
+            .chain(
+                self.object_groups
+                    .iter()
+                    .map(|(group_id, group)| (Some(*group_id), &group.objects)),
+            )
+            .find(|(_, objects)| objects.iter().any(|v| v.id == *id))?;
+        let placed = objects
+            .iter()
+            .filter(|v| {
+                matches!(
+                    v.data,
+                    ObjectInstance::Part(_) | ObjectInstance::PartGroup(_)
+                )
+            })
+            .collect::<Vec<_>>();
+        let index = placed.iter().position(|v| v.id == *id)?;
+
+        let document = self.get_ldraw_document_mut(document, group_id)?;
+        if document.iter_refs().count() != placed.len() {
+            return None;
+        }
+        let reference = document.iter_refs_mut().nth(index)?;
+        let (name, matrix, color) = match &placed[index].data {
+            ObjectInstance::Part(p) => (p.part.clone().into(), p.matrix, &p.color),
+            ObjectInstance::PartGroup(pg) => (
+                self.object_groups.get(&pg.group_id)?.alias(),
+                pg.matrix,
+                &pg.color,
+            ),
+            _ => return None,
+        };
+        if reference.name == name
+            && reference.matrix == matrix
+            && reference.color.code() == color.code()
+        {
+            Some(reference)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub struct SubmodelPathSegment {
+    pub name: String,
+    // Number of instances of the same submodel before this one among its siblings.
+    pub index: usize,
+}
+
+// Addresses a submodel instance by the submodels leading to it. Unlike object ids, paths stay
+// the same when a model is built again from the same document. Written as
+// "/chassis.ldr/wheel.ldr#1", where "#1" picks the second instance of wheel.ldr.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub struct SubmodelPath(pub Vec<SubmodelPathSegment>);
+
+impl SubmodelPath {
+    pub fn root() -> Self {
+        Self(Vec::new())
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn child(&self, name: &str, index: usize) -> Self {
+        let mut segments = self.0.clone();
+        segments.push(SubmodelPathSegment {
+            name: name.to_string(),
+            index,
+        });
+        Self(segments)
+    }
+
+    pub fn parent(&self) -> Option<Self> {
+        if self.is_root() {
+            None
+        } else {
+            Some(Self(self.0[..self.0.len() - 1].to_vec()))
+        }
+    }
+}
+
+impl fmt::Display for SubmodelPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_root() {
+            return write!(f, "/");
+        }
+        for segment in self.0.iter() {
+            write!(f, "/{}", segment.name)?;
+            if segment.index > 0 {
+                write!(f, "#{}", segment.index)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<&str> for SubmodelPath {
+    fn from(value: &str) -> Self {
+        Self(
+            value
+                .split('/')
+                .filter(|v| !v.is_empty())
+                .map(|v| match v.rsplit_once('#') {
+                    Some((name, index)) if index.parse::<usize>().is_ok() => SubmodelPathSegment {
+                        name: name.to_string(),
+                        index: index.parse().unwrap(),
+                    },
+                    _ => SubmodelPathSegment {
+                        name: v.to_string(),
+                        index: 0,
+                    },
+                })
+                .collect(),
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SubmodelNode {
+    pub path: SubmodelPath,
+    // Empty for the top level model.
+    pub name: String,
+    // Object instantiating the submodel in its parent. None for the top level model.
+    pub object_id: Option<ObjectId>,
+    pub group_id: Option<GroupId>,
+    // Placement relative to the parent, and to the top level model.
+    pub matrix: Matrix4,
+    pub world_matrix: Matrix4,
+    pub color: ColorReference,
+    // Parts placed directly in the submodel, not counting ones in its children.
+    pub parts: usize,
+    pub children: Vec<SubmodelNode>,
+}
+
+impl SubmodelNode {
+    // Looks up a node by absolute path. Names are compared case-insensitively, as LDraw
+    // references are.
+    pub fn find(&self, path: &SubmodelPath) -> Option<&SubmodelNode> {
+        if path.0.len() < self.path.0.len() || path.0[..self.path.0.len()] != self.path.0[..] {
+            return None;
+        }
+
+        let mut node = self;
+        for segment in path.0[self.path.0.len()..].iter() {
+            node = node.children.iter().find(|child| {
+                let last = child.path.0.last().unwrap();
+                last.index == segment.index && last.name.eq_ignore_ascii_case(&segment.name)
+            })?;
+        }
+        Some(node)
+    }
+
+    // Visits this node and every descendant, parents before children.
+    pub fn iter(&self) -> impl Iterator<Item = &SubmodelNode> {
+        let mut stack = vec![self];
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.children.iter().rev());
+            Some(node)
+        })
+    }
+}
+
+impl<P: Clone + Eq + PartialEq + Hash> Model<P> {
+    pub fn submodel_tree(&self) -> SubmodelNode {
+        let path = SubmodelPath::root();
+        let (children, parts) = self.build_submodel_nodes(
+            &path,
+            Matrix4::identity(),
+            &self.objects,
+            &mut CycleGuard::new(),
+        );
+        SubmodelNode {
+            path,
+            name: String::new(),
+            object_id: None,
+            group_id: None,
+            matrix: Matrix4::identity(),
+            world_matrix: Matrix4::identity(),
+            color: ColorReference::Current,
+            parts,
+            children,
+        }
+    }
+
+    pub fn find_submodel(&self, path: &SubmodelPath) -> Option<SubmodelNode> {
+        self.submodel_tree().find(path).cloned()
+    }
+
+    fn build_submodel_nodes(
+        &self,
+        parent: &SubmodelPath,
+        parent_matrix: Matrix4,
+        objects: &[Object<P>],
+        ancestors: &mut CycleGuard<GroupId>,
+    ) -> (Vec<SubmodelNode>, usize) {
+        let mut nodes = Vec::new();
+        let mut parts = 0;
+        let mut occurrences: HashMap<GroupId, usize> = HashMap::new();
+
+        for object in objects.iter() {
+            match &object.data {
+                ObjectInstance::Part(_) => parts += 1,
+                ObjectInstance::PartGroup(pg) => {
+                    let Some(group) = self.object_groups.get(&pg.group_id) else {
+                        continue;
+                    };
+                    if !ancestors.enter(pg.group_id) {
+                        continue;
+                    }
+
+                    let index = occurrences.entry(pg.group_id).or_default();
+                    let path = parent.child(&group.name, *index);
+                    *index += 1;
+
+                    let world_matrix = parent_matrix * pg.matrix;
+                    let (children, group_parts) =
+                        self.build_submodel_nodes(&path, world_matrix, &group.objects, ancestors);
+                    ancestors.leave();
+
+                    nodes.push(SubmodelNode {
+                        path,
+                        name: group.name.clone(),
+                        object_id: Some(object.id),
+                        group_id: Some(pg.group_id),
+                        matrix: pg.matrix,
+                        world_matrix,
+                        color: pg.color.clone(),
+                        parts: group_parts,
+                        children,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        (nodes, parts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ldraw::{color::ColorReference, document::MultipartDocument, PartAlias};
+
+    use super::{Model, SubmodelPath};
+    use crate::testing::{document, reference};
+
+    const CURRENT: ColorReference = ColorReference::Current;
+
+    #[test]
+    fn test_submodel_path() {
+        let path = SubmodelPath::from("/chassis.ldr/wheel.ldr#1");
+        assert_eq!(path.0.len(), 2);
+        assert_eq!(path.0[1].name, "wheel.ldr");
+        assert_eq!(path.0[1].index, 1);
+        assert_eq!(path.to_string(), "/chassis.ldr/wheel.ldr#1");
+        assert_eq!(path.parent().unwrap().to_string(), "/chassis.ldr");
+        assert!(SubmodelPath::from("/").is_root());
+        assert_eq!(SubmodelPath::from("a#b").0[0].name, "a#b");
+    }
+
+    #[test]
+    fn test_submodel_tree() {
+        let mut subparts = HashMap::new();
+        subparts.insert(
+            PartAlias::from("chassis.ldr"),
+            document(
+                "chassis.ldr",
+                vec![
+                    reference("3001.dat", CURRENT, 0.0),
+                    reference("wheel.ldr", CURRENT, -20.0),
+                    reference("wheel.ldr", CURRENT, 20.0),
+                ],
+            ),
+        );
+        subparts.insert(
+            PartAlias::from("wheel.ldr"),
+            document(
+                "wheel.ldr",
+                vec![
+                    reference("3641.dat", CURRENT, 0.0),
+                    reference("4624.dat", CURRENT, 0.0),
+                ],
+            ),
+        );
+        let document = MultipartDocument {
+            body: document(
+                "main.ldr",
+                vec![
+                    reference("chassis.ldr", CURRENT, 100.0),
+                    reference("3001.dat", CURRENT, 0.0),
+                ],
+            ),
+            subparts,
+        };
+        let model = Model::<PartAlias>::from_ldraw_multipart_document_sync(&document);
+
+        let tree = model.submodel_tree();
+        assert_eq!(tree.parts, 1);
+        assert_eq!(tree.children.len(), 1);
+        assert_eq!(tree.iter().count(), 4);
+
+        let wheel = tree
+            .find(&SubmodelPath::from("/CHASSIS.LDR/wheel.ldr#1"))
+            .unwrap();
+        assert_eq!(wheel.name, "wheel.ldr");
+        assert_eq!(wheel.parts, 2);
+        assert_eq!(wheel.world_matrix.w.x, 120.0);
+        assert_eq!(wheel.path.to_string(), "/chassis.ldr/wheel.ldr#1");
+        assert!(tree
+            .find(&SubmodelPath::from("/chassis.ldr/wheel.ldr#2"))
+            .is_none());
+
+        let chassis = model
+            .find_submodel(&SubmodelPath::from("/chassis.ldr"))
+            .unwrap();
+        assert_eq!(
+            chassis.find(&wheel.path).unwrap().object_id,
+            wheel.object_id
+        );
+    }
+
+    #[test]
+    fn test_circular_references() {
+        let mut subparts = HashMap::new();
+        subparts.insert(
+            PartAlias::from("a.ldr"),
+            document(
+                "a.ldr",
+                vec![
+                    reference("3001.dat", CURRENT, 0.0),
+                    reference("b.ldr", CURRENT, 0.0),
+                ],
+            ),
+        );
+        subparts.insert(
+            PartAlias::from("b.ldr"),
+            document(
+                "b.ldr",
+                vec![
+                    reference("a.ldr", CURRENT, 0.0),
+                    reference("b.ldr", CURRENT, 0.0),
+                    reference("3003.dat", CURRENT, 0.0),
+                ],
+            ),
+        );
+        let document = MultipartDocument {
+            body: document("main.ldr", vec![reference("a.ldr", CURRENT, 0.0)]),
+            subparts,
+        };
+        let model = Model::<PartAlias>::from_ldraw_multipart_document_sync(&document);
+
+        let group = |name: &str| {
+            model
+                .object_groups
+                .values()
+                .find(|v| v.name == name)
+                .unwrap()
+        };
+        assert_eq!(group("a.ldr").objects.len(), 2);
+        assert_eq!(group("b.ldr").objects.len(), 1);
+        assert_eq!(model.sources.len(), 4);
+        assert_eq!(model.submodel_tree().iter().count(), 3);
+        assert_eq!(model.list_dependencies().len(), 2);
+    }
+
+    #[test]
+    fn test_find_part_reference() {
+        use ldraw::elements::{Command, Meta};
+        use uuid::Uuid;
+
+        use super::ObjectId;
+
+        let mut subparts = HashMap::new();
+        subparts.insert(
+            PartAlias::from("wall.ldr"),
+            document(
+                "Wall section",
+                vec![
+                    reference("3001.dat", CURRENT, 0.0),
+                    reference("3001.dat", CURRENT, 0.0),
+                ],
+            ),
+        );
+        let mut document = MultipartDocument {
+            body: document("main.ldr", vec![reference("wall.ldr", CURRENT, 0.0)]),
+            subparts,
+        };
+        let model = Model::<PartAlias>::from_ldraw_multipart_document_sync(&document);
+        let group = model.object_groups.values().next().unwrap();
+
+        // Commands added since the model has been built move references around.
+        document
+            .subparts
+            .get_mut(&PartAlias::from("wall.ldr"))
+            .unwrap()
+            .commands
+            .insert(0, Command::Meta(Meta::Step));
+
+        let reference = model
+            .find_part_reference_mut(&mut document, &group.objects[1].id)
+            .unwrap();
+        reference.color = ColorReference::Unknown(4);
+
+        let commands = &document.subparts[&PartAlias::from("wall.ldr")].commands;
+        let colors = commands
+            .iter()
+            .map(|v| match v {
+                Command::PartReference(r) => r.color.code(),
+                _ => 0,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(colors, vec![0, 16, 4]);
+
+        // References no longer matching their objects are left alone.
+        assert!(model
+            .find_part_reference_mut(&mut document, &group.objects[1].id)
+            .is_none());
+        assert!(model
+            .find_part_reference_mut(&mut document, &ObjectId::from(Uuid::new_v4()))
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_stable_ids() {
+        use ldraw::{
+            color::ColorCatalog,
+            parser::{parse_multipart_document_with_lines, ParseOptions},
+        };
+
+        use super::{ObjectInstance, ObjectSource};
+
+        let (document, lines, _) = parse_multipart_document_with_lines(
+            &mut "0 FILE main.ldr\n\
+                  0 Main\n\
+                  1 16 0 0 0 1 0 0 0 1 0 0 0 1 sub.ldr\n\
+                  0 STEP\n\
+                  1 16 0 0 0 1 0 0 0 1 0 0 0 1 3001.dat\n\
+                  0 FILE sub.ldr\n\
+                  0 Sub\n\
+                  1 16 0 0 0 1 0 0 0 1 0 0 0 1 3002.dat\n"
+                .as_bytes(),
+            &ColorCatalog::new(),
+            &ParseOptions::default(),
+        )
+        .await
+        .unwrap();
+
+        let build = || {
+            let mut model = Model::<PartAlias>::from_ldraw_multipart_document_sync(&document);
+            model.assign_stable_ids("models/main.ldr");
+            model
+        };
+        let (a, b) = (build(), build());
+        let ids = |model: &Model<PartAlias>| model.objects.iter().map(|v| v.id).collect::<Vec<_>>();
+        assert_eq!(ids(&a), ids(&b));
+        assert_eq!(
+            a.object_groups.keys().collect::<Vec<_>>(),
+            b.object_groups.keys().collect::<Vec<_>>()
+        );
+        assert_ne!(
+            ids(&a),
+            ids(&Model::<PartAlias>::from_ldraw_multipart_document_sync(
+                &document
+            ))
+        );
+
+        // Groups are still found from the objects placing them.
+        let ObjectInstance::PartGroup(pg) = &a.objects[0].data else {
+            panic!("expected a group");
+        };
+        let group_id = pg.group_id;
+        assert_eq!(a.object_groups[&group_id].id, group_id);
+
+        let mut a = a;
+        assert_eq!(a.line_of(&a.objects[2].id), None);
+        a.set_source_lines(&lines);
+        let group = &a.object_groups[&group_id];
+        assert_eq!(a.line_of(&a.objects[2].id), Some(5));
+        assert_eq!(a.line_of(&group.objects[0].id), Some(8));
+        assert_eq!(
+            a.object_at_line(Some(&PartAlias::from("sub.ldr")), 8),
+            Some(group.objects[0].id)
+        );
+        assert_eq!(a.object_at_line(None, 2), None);
+        assert_eq!(
+            a.source_of(&a.objects[1].id),
+            Some(&ObjectSource {
+                subpart: None,
+                command: Some(1),
+                line: Some(4),
+            })
+        );
+        assert_eq!(
+            a.find_object(Some(&PartAlias::from("sub.ldr")), 0),
+            Some(group.objects[0].id)
+        );
+    }
 }
@@ -0,0 +1,486 @@
+use std::{collections::HashMap, f32, hash::Hash};
+
+use cgmath::{InnerSpace, Matrix, SquareMatrix};
+use ldraw::{Matrix3, Matrix4, Vector3};
+
+use crate::{geometry::BoundingBox3, part::Part};
+
+const MAX_LEAF_TRIANGLES: usize = 4;
+const RAY_EPSILON: f32 = 0.05;
+
+#[derive(Clone, Debug)]
+pub struct OcclusionParams {
+    pub samples_per_instance: usize,
+    pub rays_per_sample: usize,
+    pub max_distance: f32,
+}
+
+impl Default for OcclusionParams {
+    fn default() -> Self {
+        Self {
+            samples_per_instance: 64,
+            rays_per_sample: 16,
+            max_distance: 40.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Triangle {
+    vertices: [Vector3; 3],
+    normal: Vector3,
+    owner: usize,
+}
+
+impl Triangle {
+    fn centroid(&self) -> Vector3 {
+        (self.vertices[0] + self.vertices[1] + self.vertices[2]) / 3.0
+    }
+
+    // Möller-Trumbore, both faces are considered as occluders.
+    fn intersects(&self, origin: &Vector3, direction: &Vector3, max_distance: f32) -> bool {
+        let e1 = self.vertices[1] - self.vertices[0];
+        let e2 = self.vertices[2] - self.vertices[0];
+        let p = direction.cross(e2);
+        let det = e1.dot(p);
+        if det.abs() < f32::EPSILON {
+            return false;
+        }
+
+        let inv_det = 1.0 / det;
+        let t = origin - self.vertices[0];
+        let u = t.dot(p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return false;
+        }
+
+        let q = t.cross(e1);
+        let v = direction.dot(q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return false;
+        }
+
+        let distance = e2.dot(q) * inv_det;
+        distance > RAY_EPSILON && distance < max_distance
+    }
+}
+
+#[derive(Clone, Debug)]
+enum BvhNodeKind {
+    Leaf { start: usize, count: usize },
+    Inner { left: usize, right: usize },
+}
+
+#[derive(Clone, Debug)]
+struct BvhNode {
+    bounding_box: BoundingBox3,
+    kind: BvhNodeKind,
+}
+
+fn ray_intersects_box(
+    bounding_box: &BoundingBox3,
+    origin: &Vector3,
+    inv_direction: &Vector3,
+    max_distance: f32,
+) -> bool {
+    let mut tmin = 0.0f32;
+    let mut tmax = max_distance;
+
+    for axis in 0..3 {
+        // A ray parallel to the slab stays either inside or outside of it. Going through the
+        // math below would give NaN for rays starting right on its boundary.
+        if inv_direction[axis].is_infinite() {
+            if origin[axis] < bounding_box.min[axis] || origin[axis] > bounding_box.max[axis] {
+                return false;
+            }
+            continue;
+        }
+        let t1 = (bounding_box.min[axis] - origin[axis]) * inv_direction[axis];
+        let t2 = (bounding_box.max[axis] - origin[axis]) * inv_direction[axis];
+        tmin = tmin.max(t1.min(t2));
+        tmax = tmax.min(t1.max(t2));
+    }
+
+    tmin <= tmax
+}
+
+#[derive(Clone, Debug)]
+struct Bvh {
+    nodes: Vec<BvhNode>,
+    triangles: Vec<Triangle>,
+}
+
+impl Bvh {
+    fn new(mut triangles: Vec<Triangle>) -> Self {
+        let mut nodes = Vec::new();
+        if !triangles.is_empty() {
+            let len = triangles.len();
+            Self::build_recursive(&mut nodes, &mut triangles, 0, len);
+        }
+
+        Self { nodes, triangles }
+    }
+
+    fn build_recursive(
+        nodes: &mut Vec<BvhNode>,
+        triangles: &mut [Triangle],
+        start: usize,
+        end: usize,
+    ) -> usize {
+        let mut bounding_box = BoundingBox3::nil();
+        let mut centroid_box = BoundingBox3::nil();
+        for triangle in triangles[start..end].iter() {
+            for vertex in triangle.vertices.iter() {
+                bounding_box.update_point(vertex);
+            }
+            centroid_box.update_point(&triangle.centroid());
+        }
+
+        let index = nodes.len();
+        nodes.push(BvhNode {
+            bounding_box,
+            kind: BvhNodeKind::Leaf {
+                start,
+                count: end - start,
+            },
+        });
+
+        if end - start <= MAX_LEAF_TRIANGLES {
+            return index;
+        }
+
+        let axis = if centroid_box.len_x() >= centroid_box.len_y()
+            && centroid_box.len_x() >= centroid_box.len_z()
+        {
+            0
+        } else if centroid_box.len_y() >= centroid_box.len_z() {
+            1
+        } else {
+            2
+        };
+
+        let mid = (start + end) / 2;
+        triangles[start..end].select_nth_unstable_by(mid - start, |a, b| {
+            a.centroid()[axis].total_cmp(&b.centroid()[axis])
+        });
+
+        let left = Self::build_recursive(nodes, triangles, start, mid);
+        let right = Self::build_recursive(nodes, triangles, mid, end);
+        nodes[index].kind = BvhNodeKind::Inner { left, right };
+
+        index
+    }
+
+    fn any_hit(
+        &self,
+        origin: &Vector3,
+        direction: &Vector3,
+        max_distance: f32,
+        ignore: usize,
+    ) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let inv_direction = Vector3::new(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z);
+
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !ray_intersects_box(&node.bounding_box, origin, &inv_direction, max_distance) {
+                continue;
+            }
+
+            match node.kind {
+                BvhNodeKind::Leaf { start, count } => {
+                    for triangle in self.triangles[start..start + count].iter() {
+                        if triangle.owner != ignore
+                            && triangle.intersects(origin, direction, max_distance)
+                        {
+                            return true;
+                        }
+                    }
+                }
+                BvhNodeKind::Inner { left, right } => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+
+        false
+    }
+}
+
+fn collect_triangles(part: &Part, matrix: &Matrix4, owner: usize, triangles: &mut Vec<Triangle>) {
+    let vertices = &part.geometry.vertex_buffer.0;
+    let fetch = |index: u32| {
+        let index = index as usize * 3;
+        Vector3::new(vertices[index], vertices[index + 1], vertices[index + 2])
+    };
+
+    let normal_matrix = Matrix3::from_cols(
+        matrix.x.truncate(),
+        matrix.y.truncate(),
+        matrix.z.truncate(),
+    )
+    .invert()
+    .map(|m| m.transpose())
+    .unwrap_or_else(Matrix3::identity);
+
+    let meshes = [
+        &part.geometry.uncolored_mesh,
+        &part.geometry.uncolored_without_bfc_mesh,
+    ]
+    .into_iter()
+    .chain(part.geometry.colored_meshes.values());
+
+    for mesh in meshes {
+        for (vertex_indices, normal_indices) in mesh
+            .vertex_indices
+            .chunks_exact(3)
+            .zip(mesh.normal_indices.chunks_exact(3))
+        {
+            let vertices = [
+                (matrix * fetch(vertex_indices[0]).extend(1.0)).truncate(),
+                (matrix * fetch(vertex_indices[1]).extend(1.0)).truncate(),
+                (matrix * fetch(vertex_indices[2]).extend(1.0)).truncate(),
+            ];
+            let normal = normal_indices
+                .iter()
+                .fold(Vector3::new(0.0, 0.0, 0.0), |acc, v| acc + fetch(*v));
+            let normal = normal_matrix * normal;
+            if normal.magnitude2() < f32::EPSILON {
+                continue;
+            }
+
+            triangles.push(Triangle {
+                vertices,
+                normal: normal.normalize(),
+                owner,
+            });
+        }
+    }
+}
+
+// Cosine-weighted hemisphere directions from a Hammersley sequence.
+fn hemisphere_samples(count: usize) -> Vec<Vector3> {
+    (0..count)
+        .map(|i| {
+            let u = (i as f32 + 0.5) / count as f32;
+            let v = (i as u32).reverse_bits() as f32 / u32::MAX as f32;
+            let r = u.sqrt();
+            let phi = 2.0 * f32::consts::PI * v;
+            Vector3::new(r * phi.cos(), r * phi.sin(), (1.0 - u).sqrt())
+        })
+        .collect()
+}
+
+fn tangent_frame(normal: &Vector3) -> (Vector3, Vector3) {
+    let up = if normal.y.abs() < 0.9 {
+        Vector3::new(0.0, 1.0, 0.0)
+    } else {
+        Vector3::new(1.0, 0.0, 0.0)
+    };
+    let tangent = up.cross(*normal).normalize();
+    let bitangent = normal.cross(tangent);
+    (tangent, bitangent)
+}
+
+pub fn bake_ambient_occlusion<K: Clone + Eq + Hash>(
+    instances: &[(K, &Part, Matrix4)],
+    params: &OcclusionParams,
+) -> HashMap<K, f32> {
+    let mut triangles = Vec::new();
+    let mut ranges = Vec::with_capacity(instances.len());
+    for (owner, (_, part, matrix)) in instances.iter().enumerate() {
+        let start = triangles.len();
+        collect_triangles(part, matrix, owner, &mut triangles);
+        ranges.push(start..triangles.len());
+    }
+
+    let samples = hemisphere_samples(params.rays_per_sample.max(1));
+
+    // Sample points are picked before triangles get reordered by BVH construction.
+    let sample_points = ranges
+        .into_iter()
+        .map(|range| {
+            let count = range.len();
+            let step = (count / params.samples_per_instance.max(1)).max(1);
+            triangles[range]
+                .iter()
+                .step_by(step)
+                .map(|t| (t.centroid(), t.normal))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let bvh = Bvh::new(triangles);
+
+    instances
+        .iter()
+        .zip(sample_points)
+        .enumerate()
+        .map(|(owner, ((key, _, _), points))| {
+            let mut hits = 0;
+            let mut total = 0;
+
+            for (point, normal) in points.iter() {
+                let (tangent, bitangent) = tangent_frame(normal);
+                let origin = point + normal * RAY_EPSILON;
+                for sample in samples.iter() {
+                    let direction = tangent * sample.x + bitangent * sample.y + normal * sample.z;
+                    if bvh.any_hit(&origin, &direction, params.max_distance, owner) {
+                        hits += 1;
+                    }
+                    total += 1;
+                }
+            }
+
+            let visibility = if total > 0 {
+                1.0 - hits as f32 / total as f32
+            } else {
+                1.0
+            };
+
+            (key.clone(), visibility)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{InnerSpace, SquareMatrix};
+    use ldraw::{
+        color::ColorReference,
+        document::{Document, MultipartDocument},
+        elements::{Command, Quad},
+        library::ResolutionResult,
+        Matrix4, Vector3, Vector4,
+    };
+
+    use super::{bake_ambient_occlusion, ray_intersects_box, Bvh, OcclusionParams, Triangle};
+    use crate::{geometry::BoundingBox3, part::bake_part_from_multipart_document};
+
+    #[test]
+    fn test_ray_along_box_face() {
+        let bounding_box = BoundingBox3::new(
+            &Vector3::new(0.0, 0.0, 0.0),
+            &Vector3::new(10.0, 10.0, 10.0),
+        );
+        let inv = |v: Vector3| Vector3::new(1.0 / v.x, 1.0 / v.y, 1.0 / v.z);
+
+        // Starts on the face at y = 0, heading along it.
+        let direction = Vector3::new(1.0, 0.0, 0.0);
+        assert!(ray_intersects_box(
+            &bounding_box,
+            &Vector3::new(-5.0, 0.0, 5.0),
+            &inv(direction),
+            100.0
+        ));
+        assert!(!ray_intersects_box(
+            &bounding_box,
+            &Vector3::new(-5.0, -1.0, 5.0),
+            &inv(direction),
+            100.0
+        ));
+        assert!(!ray_intersects_box(
+            &bounding_box,
+            &Vector3::new(-5.0, 0.0, 5.0),
+            &inv(direction),
+            4.0
+        ));
+    }
+
+    fn triangle(origin: Vector3, owner: usize) -> Triangle {
+        Triangle {
+            vertices: [
+                origin,
+                origin + Vector3::new(4.0, 0.0, 0.0),
+                origin + Vector3::new(0.0, 0.0, 4.0),
+            ],
+            normal: Vector3::new(0.0, -1.0, 0.0),
+            owner,
+        }
+    }
+
+    #[test]
+    fn test_bvh_matches_brute_force() {
+        let mut triangles = Vec::new();
+        for i in 0..8 {
+            for j in 0..8 {
+                let origin = Vector3::new(i as f32 * 5.0, (i * j % 3) as f32 * 3.0, j as f32 * 5.0);
+                triangles.push(triangle(origin, i * 8 + j));
+            }
+        }
+        let bvh = Bvh::new(triangles.clone());
+
+        let directions = [
+            Vector3::new(0.0, 1.0, 0.0),
+            Vector3::new(0.0, -1.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(0.3, 1.0, 0.2).normalize(),
+            Vector3::new(-0.5, -1.0, 0.7).normalize(),
+        ];
+        for x in 0..10 {
+            for z in 0..10 {
+                let origin = Vector3::new(x as f32 * 4.0 + 0.5, 1.5, z as f32 * 4.0 + 0.5);
+                for direction in directions.iter() {
+                    let expected = triangles
+                        .iter()
+                        .any(|t| t.owner != 0 && t.intersects(&origin, direction, 20.0));
+                    assert_eq!(bvh.any_hit(&origin, direction, 20.0, 0), expected);
+                }
+            }
+        }
+    }
+
+    fn plate() -> MultipartDocument {
+        let v = |x: f32, z: f32| Vector4::new(x, 0.0, z, 1.0);
+        MultipartDocument {
+            body: Document {
+                commands: vec![Command::Quad(Quad {
+                    color: ColorReference::Current,
+                    a: v(-500.0, -500.0),
+                    b: v(500.0, -500.0),
+                    c: v(500.0, 500.0),
+                    d: v(-500.0, 500.0),
+                })],
+                ..Default::default()
+            },
+            subparts: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_ambient_occlusion() {
+        let part = bake_part_from_multipart_document(&plate(), &ResolutionResult::new(), false);
+        let at = |y: f32| Matrix4::from_translation(Vector3::new(0.0, y, 0.0));
+        let params = OcclusionParams::default();
+
+        let alone = bake_ambient_occlusion(&[("a", &part, Matrix4::identity())], &params);
+        assert_eq!(alone["a"], 1.0);
+
+        // Sandwiched between two other plates, whichever way the plate faces.
+        let stacked = bake_ambient_occlusion(
+            &[
+                ("a", &part, at(0.0)),
+                ("b", &part, at(-10.0)),
+                ("c", &part, at(10.0)),
+            ],
+            &params,
+        );
+        assert!(stacked["a"] < 0.5);
+
+        // Plates far apart don't occlude each other.
+        let apart = bake_ambient_occlusion(
+            &[
+                ("a", &part, at(0.0)),
+                ("b", &part, at(-100.0)),
+                ("c", &part, at(100.0)),
+            ],
+            &params,
+        );
+        assert_eq!(apart["a"], 1.0);
+    }
+}
@@ -1,5 +1,12 @@
 use std::{
-    cell::RefCell, collections::HashMap, f32, fmt::Debug, ops::Deref, rc::Rc, sync::Arc, vec::Vec,
+    cell::RefCell,
+    collections::{HashMap, HashSet, VecDeque},
+    f32,
+    fmt::Debug,
+    ops::Deref,
+    rc::Rc,
+    sync::Arc,
+    vec::Vec,
 };
 
 use cgmath::{AbsDiffEq, InnerSpace, Rad, SquareMatrix};
@@ -8,15 +15,213 @@ use ldraw::{
     color::{ColorCatalog, ColorReference},
     document::{Document, MultipartDocument},
     elements::{BfcStatement, Command, Meta},
-    library::ResolutionResult,
-    Matrix4, Vector3, Winding,
+    graph::CycleGuard,
+    library::{PartKind, ResolutionResult},
+    Matrix4, PartAlias, Vector3, Winding,
 };
 use serde::{Deserialize, Serialize};
 
-use crate::{geometry::BoundingBox3, MeshGroupKey};
+use crate::{constraints::ConnectionKind, geometry::BoundingBox3, MeshGroupKey};
+
+pub mod baked;
+pub mod io;
+pub mod pack;
+mod simplify;
+
+pub use self::simplify::simplify;
 
 const NORMAL_BLEND_THRESHOLD: Rad<f32> = Rad(f32::consts::FRAC_PI_6);
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum StudDetail {
+    Full,
+    Hidden,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum PrimitiveResolution {
+    Low,
+    Standard,
+    High,
+}
+
+impl PrimitiveResolution {
+    // Alias of the low (8/) or high (48/) resolution variant of a primitive. Parts have no
+    // variants, even if they happen to share a name with a primitive.
+    pub fn alternative(&self, alias: &PartAlias, kind: PartKind) -> Option<PartAlias> {
+        if kind != PartKind::Primitive {
+            return None;
+        }
+        let prefix = match self {
+            PrimitiveResolution::Low => "8/",
+            PrimitiveResolution::Standard => return None,
+            PrimitiveResolution::High => "48/",
+        };
+
+        if alias.normalized.contains('/') {
+            None
+        } else {
+            Some(PartAlias::from(format!(
+                "{}{}",
+                prefix,
+                alias.original.trim()
+            )))
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BakeOptions {
+    pub smoothing_angle: Rad<f32>,
+    pub stud_detail: StudDetail,
+    pub primitive_resolution: PrimitiveResolution,
+    // Collects faces from non-certified or mis-wound geometry into `bfc_error_mesh`.
+    pub tag_bfc_errors: bool,
+    // Splits faces at vertices lying on their edges so that meshes are watertight.
+    pub repair_t_junctions: bool,
+    // Keeps degenerate and duplicate faces, which are removed by default.
+    pub skip_cleanup: bool,
+    // Keeps vertex buffer and triangle order as generated, without welding and reordering.
+    pub skip_optimization: bool,
+}
+
+impl Default for BakeOptions {
+    fn default() -> Self {
+        Self {
+            smoothing_angle: NORMAL_BLEND_THRESHOLD,
+            stud_detail: StudDetail::Full,
+            primitive_resolution: PrimitiveResolution::Standard,
+            tag_bfc_errors: false,
+            repair_t_junctions: false,
+            skip_cleanup: false,
+            skip_optimization: false,
+        }
+    }
+}
+
+// Amount of geometry removed while baking.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CleanupStatistics {
+    pub degenerate_faces: usize,
+    pub duplicate_faces: usize,
+    pub merged_vertices: usize,
+}
+
+impl CleanupStatistics {
+    pub fn is_empty(&self) -> bool {
+        self.degenerate_faces == 0 && self.duplicate_faces == 0 && self.merged_vertices == 0
+    }
+}
+
+// Vertex buffer entries closer than this are merged while optimizing.
+const WELD_TOLERANCE: f32 = 1e-3;
+// Size of the post-transform vertex cache assumed for triangle reordering.
+pub const VERTEX_CACHE_SIZE: usize = 32;
+
+// Scores a vertex by its position in the cache and the number of triangles still using it.
+// See Tom Forsyth, "Linear-Speed Vertex Cache Optimisation".
+fn vertex_cache_score(cache_position: Option<usize>, remaining: usize) -> f32 {
+    if remaining == 0 {
+        return -1.0;
+    }
+
+    let score = match cache_position {
+        None => 0.0,
+        // The last triangle's vertices are penalized to avoid strip-like orderings.
+        Some(p) if p < 3 => 0.75,
+        Some(p) => (1.0 - (p - 3) as f32 / (VERTEX_CACHE_SIZE - 3) as f32).powf(1.5),
+    };
+    score + 2.0 * (remaining as f32).powf(-0.5)
+}
+
+// Returns triangle order which maximizes reuse of recently transformed vertices.
+fn reorder_triangles(corners: &[usize], vertex_count: usize) -> Vec<usize> {
+    let triangle_count = corners.len() / 3;
+
+    let mut triangles_of = vec![Vec::new(); vertex_count];
+    for (i, vertex) in corners.iter().enumerate() {
+        triangles_of[*vertex].push(i / 3);
+    }
+    let mut remaining = triangles_of.iter().map(Vec::len).collect::<Vec<_>>();
+    let mut cache_position: Vec<Option<usize>> = vec![None; vertex_count];
+    let mut vertex_score = remaining
+        .iter()
+        .map(|v| vertex_cache_score(None, *v))
+        .collect::<Vec<_>>();
+    let triangle_score = |vertex_score: &[f32], t: usize| -> f32 {
+        corners[t * 3..t * 3 + 3]
+            .iter()
+            .map(|v| vertex_score[*v])
+            .sum()
+    };
+    let mut score = (0..triangle_count)
+        .map(|t| triangle_score(&vertex_score, t))
+        .collect::<Vec<_>>();
+    let mut emitted = vec![false; triangle_count];
+
+    let mut order = Vec::with_capacity(triangle_count);
+    let mut cache: Vec<usize> = Vec::with_capacity(VERTEX_CACHE_SIZE + 3);
+    let mut cursor = 0;
+    while order.len() < triangle_count {
+        // Prefer triangles using cached vertices, otherwise start over from the next unused one.
+        let best = cache
+            .iter()
+            .flat_map(|v| triangles_of[*v].iter())
+            .filter(|t| !emitted[**t])
+            .max_by(|a, b| score[**a].total_cmp(&score[**b]))
+            .copied();
+        let next = match best {
+            Some(t) => t,
+            None => {
+                while emitted[cursor] {
+                    cursor += 1;
+                }
+                cursor
+            }
+        };
+
+        emitted[next] = true;
+        order.push(next);
+
+        let vertices = &corners[next * 3..next * 3 + 3];
+        for vertex in vertices {
+            remaining[*vertex] -= 1;
+        }
+        let mut updated = vertices.to_vec();
+        for vertex in cache.iter() {
+            if !vertices.contains(vertex) {
+                updated.push(*vertex);
+            }
+        }
+        for vertex in updated.iter().skip(VERTEX_CACHE_SIZE) {
+            cache_position[*vertex] = None;
+        }
+        updated.truncate(VERTEX_CACHE_SIZE);
+        for (position, vertex) in updated.iter().enumerate() {
+            cache_position[*vertex] = Some(position);
+        }
+        let touched = cache
+            .iter()
+            .chain(updated.iter())
+            .copied()
+            .collect::<HashSet<_>>();
+        cache = updated;
+
+        for vertex in touched.iter() {
+            vertex_score[*vertex] = vertex_cache_score(cache_position[*vertex], remaining[*vertex]);
+        }
+        for vertex in touched.iter() {
+            for t in triangles_of[*vertex].iter() {
+                if !emitted[*t] {
+                    score[*t] = triangle_score(&vertex_score, *t);
+                }
+            }
+        }
+    }
+
+    order
+}
+
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct VertexBuffer(pub Vec<f32>);
 
@@ -117,6 +322,113 @@ impl MeshBuffer {
         self.vertex_indices.extend(vertex_indices);
         self.normal_indices.extend(normal_indices);
     }
+
+    // Each distinct pair of vertex and normal becomes a single vertex on the GPU.
+    fn corners(&self) -> (Vec<usize>, usize) {
+        let mut ids = HashMap::new();
+        let corners = self
+            .vertex_indices
+            .iter()
+            .zip(self.normal_indices.iter())
+            .map(|key| {
+                let id = ids.len();
+                *ids.entry(key).or_insert(id)
+            })
+            .collect();
+        (corners, ids.len())
+    }
+
+    // Average number of vertices transformed per triangle with a FIFO cache of given size.
+    pub fn cache_miss_ratio(&self, cache_size: usize) -> f32 {
+        if self.len() < 3 {
+            return 0.0;
+        }
+
+        let (corners, _) = self.corners();
+        let mut cache = VecDeque::with_capacity(cache_size);
+        let mut misses = 0;
+        for corner in corners {
+            if !cache.contains(&corner) {
+                misses += 1;
+                if cache.len() == cache_size {
+                    cache.pop_front();
+                }
+                cache.push_back(corner);
+            }
+        }
+        misses as f32 / (self.len() / 3) as f32
+    }
+
+    pub fn reorder_for_vertex_cache(&mut self) {
+        if !self.is_valid() || !self.len().is_multiple_of(3) {
+            return;
+        }
+
+        let (corners, count) = self.corners();
+        let order = reorder_triangles(&corners, count);
+        let reorder = |indices: &[u32]| {
+            order
+                .iter()
+                .flat_map(|t| indices[t * 3..t * 3 + 3].iter().copied())
+                .collect::<Vec<_>>()
+        };
+        self.vertex_indices = reorder(&self.vertex_indices);
+        self.normal_indices = reorder(&self.normal_indices);
+    }
+}
+
+// Color of an edge vertex, stored in edge buffers as a single u32.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EdgeColor {
+    Current,
+    Complement,
+    Main(u32),
+    Edge(u32),
+}
+
+impl EdgeColor {
+    const CURRENT: u32 = 2 << 30;
+    const COMPLEMENT: u32 = 2 << 29;
+    const EDGE: u32 = 1 << 28;
+
+    pub fn new(color: &ColorReference, top: &ColorReference) -> Self {
+        if color.is_current() {
+            match top.get_color() {
+                Some(c) => EdgeColor::Main(c.code),
+                None => EdgeColor::Current,
+            }
+        } else if color.is_complement() {
+            match top.get_color() {
+                Some(c) => EdgeColor::Edge(c.code),
+                None => EdgeColor::Complement,
+            }
+        } else if let Some(c) = color.get_color() {
+            EdgeColor::Main(c.code)
+        } else {
+            EdgeColor::Main(0)
+        }
+    }
+
+    pub fn from_code(code: u32) -> Self {
+        if code == Self::CURRENT {
+            EdgeColor::Current
+        } else if code == Self::COMPLEMENT {
+            EdgeColor::Complement
+        } else if code & Self::EDGE != 0 {
+            EdgeColor::Edge(code & !Self::EDGE)
+        } else {
+            EdgeColor::Main(code)
+        }
+    }
+
+    pub fn code(self) -> u32 {
+        match self {
+            EdgeColor::Current => Self::CURRENT,
+            EdgeColor::Complement => Self::COMPLEMENT,
+            EdgeColor::Main(code) => code,
+            EdgeColor::Edge(code) => Self::EDGE | code,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
@@ -135,23 +447,7 @@ impl EdgeBuffer {
     ) {
         self.vertex_indices.push(vertex_buffer.add(vec));
 
-        let code = if color.is_current() {
-            if let Some(c) = top.get_color() {
-                2 << 31 | c.code
-            } else {
-                2 << 30
-            }
-        } else if color.is_complement() {
-            if let Some(c) = top.get_color() {
-                c.code
-            } else {
-                2 << 29
-            }
-        } else if let Some(c) = color.get_color() {
-            2 << 31 | c.code
-        } else {
-            0
-        };
+        let code = EdgeColor::new(color, top).code();
         self.colors.push(code);
     }
 
@@ -200,23 +496,7 @@ impl OptionalEdgeBuffer {
         self.direction_indices.push(vertex_buffer.add(d));
         self.direction_indices.push(vertex_buffer.add(d));
 
-        let code = if color.is_current() {
-            if let Some(c) = top.get_color() {
-                2 << 31 | c.code
-            } else {
-                2 << 30
-            }
-        } else if color.is_complement() {
-            if let Some(c) = top.get_color() {
-                c.code
-            } else {
-                2 << 29
-            }
-        } else if let Some(c) = color.get_color() {
-            2 << 31 | c.code
-        } else {
-            0
-        };
+        let code = EdgeColor::new(color, top).code();
         self.colors.push(code);
         self.colors.push(code);
     }
@@ -243,16 +523,126 @@ pub struct PartBufferBundle {
     pub uncolored_mesh: MeshBuffer,
     pub uncolored_without_bfc_mesh: MeshBuffer,
     pub colored_meshes: HashMap<MeshGroupKey, MeshBuffer>,
+    pub bfc_error_mesh: MeshBuffer,
     pub edges: EdgeBuffer,
     pub optional_edges: OptionalEdgeBuffer,
 }
 
+impl PartBufferBundle {
+    fn meshes_mut(&mut self) -> impl Iterator<Item = &mut MeshBuffer> {
+        [
+            &mut self.uncolored_mesh,
+            &mut self.uncolored_without_bfc_mesh,
+        ]
+        .into_iter()
+        .chain(self.colored_meshes.values_mut())
+        .chain([&mut self.bfc_error_mesh])
+    }
+
+    // Index lists in a stable order so that renumbered vertex buffers are deterministic.
+    fn index_lists_mut(&mut self) -> Vec<&mut Vec<u32>> {
+        let PartBufferBundle {
+            uncolored_mesh,
+            uncolored_without_bfc_mesh,
+            colored_meshes,
+            bfc_error_mesh,
+            edges,
+            optional_edges,
+            ..
+        } = self;
+
+        let mut colored_meshes = colored_meshes.iter_mut().collect::<Vec<_>>();
+        colored_meshes.sort_by(|a, b| a.0.cmp(b.0));
+
+        let mut lists = vec![];
+        for mesh in [uncolored_mesh, uncolored_without_bfc_mesh]
+            .into_iter()
+            .chain(colored_meshes.into_iter().map(|(_, mesh)| mesh))
+            .chain([bfc_error_mesh])
+        {
+            lists.push(&mut mesh.vertex_indices);
+            lists.push(&mut mesh.normal_indices);
+        }
+        lists.push(&mut edges.vertex_indices);
+        lists.push(&mut optional_edges.vertex_indices);
+        lists.push(&mut optional_edges.control_1_indices);
+        lists.push(&mut optional_edges.control_2_indices);
+        lists.push(&mut optional_edges.direction_indices);
+        lists
+    }
+
+    // Merges nearly identical vertex buffer entries, reorders triangles for vertex cache
+    // reuse and renumbers the vertex buffer in order of first use. Returns the number of
+    // vertex buffer entries removed.
+    pub fn optimize(&mut self) -> usize {
+        let count = self.vertex_buffer.0.len() / 3;
+        if self
+            .index_lists_mut()
+            .iter()
+            .any(|list| list.iter().any(|v| *v as usize >= count))
+        {
+            return 0;
+        }
+
+        let mut tree = KdTree::new(3);
+        let mut welded = Vec::with_capacity(count);
+        for (i, v) in self.vertex_buffer.0.chunks_exact(3).enumerate() {
+            let point = [v[0], v[1], v[2]];
+            let existing = tree
+                .nearest(&point, 1, &squared_euclidean)
+                .ok()
+                .and_then(|entries| {
+                    entries
+                        .first()
+                        .filter(|(dist, _)| *dist <= WELD_TOLERANCE * WELD_TOLERANCE)
+                        .map(|(_, index)| **index)
+                });
+            match existing {
+                Some(index) => welded.push(index),
+                None => {
+                    // Entries with non-finite coordinates can't be indexed and are kept as-is.
+                    let _ = tree.add(point, i as u32);
+                    welded.push(i as u32);
+                }
+            }
+        }
+
+        for mesh in self.meshes_mut() {
+            for index in mesh
+                .vertex_indices
+                .iter_mut()
+                .chain(mesh.normal_indices.iter_mut())
+            {
+                *index = welded[*index as usize];
+            }
+            mesh.reorder_for_vertex_cache();
+        }
+
+        let mut renumbered = vec![None; count];
+        let mut vertices = Vec::with_capacity(self.vertex_buffer.0.len());
+        let source = std::mem::take(&mut self.vertex_buffer.0);
+        for list in self.index_lists_mut() {
+            for index in list.iter_mut() {
+                let welded = welded[*index as usize] as usize;
+                *index = *renumbered[welded].get_or_insert_with(|| {
+                    vertices.extend(&source[welded * 3..welded * 3 + 3]);
+                    (vertices.len() / 3 - 1) as u32
+                });
+            }
+        }
+        self.vertex_buffer.0 = vertices;
+
+        count - self.vertex_buffer.0.len() / 3
+    }
+}
+
 #[derive(Default)]
 pub struct PartBufferBundleBuilder {
     pub vertex_buffer_builder: VertexBufferBuilder,
     uncolored_mesh: MeshBuffer,
     uncolored_without_bfc_mesh: MeshBuffer,
     colored_meshes: HashMap<MeshGroupKey, MeshBuffer>,
+    bfc_error_mesh: MeshBuffer,
     edges: EdgeBuffer,
     optional_edges: OptionalEdgeBuffer,
 }
@@ -279,6 +669,7 @@ impl PartBufferBundleBuilder {
             uncolored_mesh: self.uncolored_mesh,
             uncolored_without_bfc_mesh: self.uncolored_without_bfc_mesh,
             colored_meshes: self.colored_meshes,
+            bfc_error_mesh: self.bfc_error_mesh,
             edges: self.edges,
             optional_edges: self.optional_edges,
         }
@@ -410,7 +801,7 @@ impl<'a> Iterator for FaceIterator<'a> {
 }
 
 impl FaceVertices {
-    pub fn triangles(&self, reverse: bool) -> FaceIterator {
+    pub fn triangles(&self, reverse: bool) -> FaceIterator<'_> {
         let order = match self {
             FaceVertices::Triangle(_) => TRIANGLE_INDEX_ORDER,
             FaceVertices::Quad(_) => QUAD_INDEX_ORDER,
@@ -447,6 +838,22 @@ impl FaceVertices {
 struct Face {
     vertices: FaceVertices,
     winding: Winding,
+    // Goes into `bfc_error_mesh` as well. Faces split or trimmed from it keep the tag.
+    bfc_error: bool,
+}
+
+impl Face {
+    // Bowtie or concave quads have halves facing opposite directions on either diagonal.
+    fn is_miswound(&self) -> bool {
+        match &self.vertices {
+            FaceVertices::Triangle(_) => false,
+            FaceVertices::Quad(v) => {
+                let [a, b, c, d] = [v[0].position, v[1].position, v[2].position, v[3].position];
+                calculate_normal(&a, &b, &c).dot(calculate_normal(&c, &d, &a)) < 0.0
+                    || calculate_normal(&b, &c, &d).dot(calculate_normal(&d, &a, &b)) < 0.0
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -464,6 +871,39 @@ impl Adjacency {
     }
 }
 
+// Vertices closer than this (in LDU) to an edge are considered to lie on it.
+const T_JUNCTION_TOLERANCE: f32 = 0.001;
+const MAX_T_JUNCTION_SPLITS: usize = 8;
+
+// Faces smaller than this (in square LDU) are considered degenerate.
+const DEGENERATE_AREA: f32 = 1e-6;
+
+fn triangle_area(a: &Vector3, b: &Vector3, c: &Vector3) -> f32 {
+    (b - a).cross(c - a).magnitude() * 0.5
+}
+
+// Quantized vertex positions starting from the smallest one, so that faces with the
+// same vertices and winding produce the same key.
+fn face_key(vertices: &FaceVertices) -> Vec<[i64; 3]> {
+    let positions = match vertices {
+        FaceVertices::Triangle(v) => v.iter().map(|v| v.position).collect::<Vec<_>>(),
+        FaceVertices::Quad(v) => v.iter().map(|v| v.position).collect::<Vec<_>>(),
+    };
+    let mut key = positions
+        .iter()
+        .map(|v| {
+            [
+                (v.x * 1000.0).round() as i64,
+                (v.y * 1000.0).round() as i64,
+                (v.z * 1000.0).round() as i64,
+            ]
+        })
+        .collect::<Vec<_>>();
+    let start = (0..key.len()).min_by_key(|i| key[*i]).unwrap_or(0);
+    key.rotate_left(start);
+    key
+}
+
 fn calculate_normal(v1: &Vector3, v2: &Vector3, v3: &Vector3) -> Vector3 {
     let normal = (v2 - v3).cross(v2 - v1).normalize();
     if normal.x.is_nan() || normal.y.is_nan() || normal.z.is_nan() {
@@ -525,7 +965,180 @@ impl MeshBuilder {
         }
     }
 
-    pub fn smooth_normals(&mut self) {
+    // Nearest vertex lying in the interior of an edge from a to b.
+    fn find_t_junction(&self, a: &Vector3, b: &Vector3) -> Option<Vector3> {
+        let edge = b - a;
+        let length = edge.magnitude();
+        if length < T_JUNCTION_TOLERANCE * 2.0 {
+            return None;
+        }
+        let direction = edge / length;
+
+        let center = (a + b) * 0.5;
+        let r: &[f32; 3] = center.as_ref();
+        let candidates = self
+            .point_cloud
+            .within(r, (length * 0.5).powi(2), &squared_euclidean)
+            .ok()?;
+
+        candidates
+            .into_iter()
+            .filter_map(|(_, adjacency)| {
+                let adjacency = adjacency.borrow();
+                let (face, index) = adjacency.faces.first()?;
+                let position = face.borrow().vertices.query(*index).position;
+                let t = (position - a).dot(direction);
+                let inside = t > T_JUNCTION_TOLERANCE && t < length - T_JUNCTION_TOLERANCE;
+                if inside && (a + direction * t - position).magnitude() <= T_JUNCTION_TOLERANCE {
+                    Some((t, position))
+                } else {
+                    None
+                }
+            })
+            .min_by(|lhs, rhs| lhs.0.total_cmp(&rhs.0))
+            .map(|(_, position)| position)
+    }
+
+    fn split_triangle(
+        &self,
+        triangle: [FaceVertex; 3],
+        depth: usize,
+        result: &mut Vec<[FaceVertex; 3]>,
+    ) {
+        if depth < MAX_T_JUNCTION_SPLITS {
+            for i in 0..3 {
+                let a = &triangle[i];
+                let b = &triangle[(i + 1) % 3];
+                let c = &triangle[(i + 2) % 3];
+                if let Some(position) = self.find_t_junction(&a.position, &b.position) {
+                    let middle = FaceVertex {
+                        position,
+                        normal: a.normal,
+                    };
+                    self.split_triangle([a.clone(), middle.clone(), c.clone()], depth + 1, result);
+                    self.split_triangle([middle, b.clone(), c.clone()], depth + 1, result);
+                    return;
+                }
+            }
+        }
+        result.push(triangle);
+    }
+
+    pub fn repair_t_junctions(&mut self) {
+        let mut repaired = HashMap::new();
+        let mut modified = false;
+
+        for (group_key, faces) in self.faces.iter() {
+            let list: &mut Vec<_> = repaired.entry(group_key.clone()).or_default();
+            for face in faces.iter() {
+                let f = face.borrow();
+                let triangles = match &f.vertices {
+                    FaceVertices::Triangle(v) => vec![v.clone()],
+                    FaceVertices::Quad([a, b, c, d]) => vec![
+                        [a.clone(), b.clone(), c.clone()],
+                        [c.clone(), d.clone(), a.clone()],
+                    ],
+                };
+                let count = triangles.len();
+
+                let mut result = Vec::new();
+                for triangle in triangles {
+                    self.split_triangle(triangle, 0, &mut result);
+                }
+
+                if result.len() == count {
+                    list.push(Rc::clone(face));
+                } else {
+                    modified = true;
+                    list.extend(result.into_iter().map(|v| {
+                        Rc::new(RefCell::new(Face {
+                            vertices: FaceVertices::Triangle(v),
+                            winding: f.winding,
+                            bfc_error: f.bfc_error,
+                        }))
+                    }));
+                }
+            }
+        }
+
+        if modified {
+            self.rebuild(repaired);
+        }
+    }
+
+    fn rebuild(&mut self, faces: HashMap<MeshGroupKey, Vec<Rc<RefCell<Face>>>>) {
+        self.faces.clear();
+        self.adjacencies.clear();
+        self.point_cloud = KdTree::new(3);
+        for (group_key, faces) in faces {
+            for face in faces {
+                self.add(&group_key, face);
+            }
+        }
+    }
+
+    // Removes zero-area faces and faces sharing the same vertices and winding with
+    // another face of the same group.
+    pub fn cleanup(&mut self) -> CleanupStatistics {
+        let mut statistics = CleanupStatistics::default();
+        let mut cleaned = HashMap::new();
+
+        for (group_key, faces) in self.faces.iter() {
+            let list: &mut Vec<_> = cleaned.entry(group_key.clone()).or_default();
+            let mut seen = HashSet::new();
+
+            for face in faces.iter() {
+                let f = face.borrow();
+                let face = match &f.vertices {
+                    FaceVertices::Triangle([a, b, c]) => {
+                        if triangle_area(&a.position, &b.position, &c.position) < DEGENERATE_AREA {
+                            statistics.degenerate_faces += 1;
+                            continue;
+                        }
+                        Rc::clone(face)
+                    }
+                    FaceVertices::Quad([a, b, c, d]) => {
+                        let first = triangle_area(&a.position, &b.position, &c.position);
+                        let second = triangle_area(&c.position, &d.position, &a.position);
+                        let remaining = match (first < DEGENERATE_AREA, second < DEGENERATE_AREA) {
+                            (false, false) => None,
+                            (true, true) => {
+                                statistics.degenerate_faces += 1;
+                                continue;
+                            }
+                            (true, false) => Some([c.clone(), d.clone(), a.clone()]),
+                            (false, true) => Some([a.clone(), b.clone(), c.clone()]),
+                        };
+                        match remaining {
+                            Some(triangle) => {
+                                statistics.degenerate_faces += 1;
+                                Rc::new(RefCell::new(Face {
+                                    vertices: FaceVertices::Triangle(triangle),
+                                    winding: f.winding,
+                                    bfc_error: f.bfc_error,
+                                }))
+                            }
+                            None => Rc::clone(face),
+                        }
+                    }
+                };
+
+                if !seen.insert(face_key(&face.borrow().vertices)) {
+                    statistics.duplicate_faces += 1;
+                    continue;
+                }
+                list.push(face);
+            }
+        }
+
+        if statistics.degenerate_faces > 0 || statistics.duplicate_faces > 0 {
+            self.rebuild(cleaned);
+        }
+
+        statistics
+    }
+
+    pub fn smooth_normals(&mut self, threshold: Rad<f32>) {
         for adjacency in self.adjacencies.iter() {
             let adjacency = adjacency.borrow_mut();
             let length = adjacency.faces.len();
@@ -550,7 +1163,7 @@ impl MeshBuilder {
                                 if angle.0 < f32::default_epsilon() {
                                     *flag = true;
                                 }
-                                if angle < NORMAL_BLEND_THRESHOLD {
+                                if angle < threshold {
                                     ops += 1;
                                     *flag = true;
                                     marked.push(j);
@@ -632,6 +1245,23 @@ impl MeshBuilder {
             }
         }
 
+        let mut vertex_indices = vec![];
+        let mut normal_indices = vec![];
+        // Taken after cleanup and repair so that faces dropped or split there are left out or
+        // split here too.
+        for face in self.faces.values().flatten() {
+            if !face.borrow().bfc_error {
+                continue;
+            }
+            for vertex in face.borrow().vertices.triangles(false) {
+                vertex_indices.push(builder.vertex_buffer_builder.add(vertex.position));
+                normal_indices.push(builder.vertex_buffer_builder.add(vertex.normal));
+            }
+        }
+        builder
+            .bfc_error_mesh
+            .add_indices(vertex_indices, normal_indices);
+
         if let Some(bounding_box_min) = bounding_box_min {
             if let Some(bounding_box_max) = bounding_box_max {
                 bounding_box.update_point(&bounding_box_min);
@@ -643,11 +1273,13 @@ impl MeshBuilder {
 
 struct PartBaker<'a> {
     resolutions: &'a ResolutionResult,
+    options: &'a BakeOptions,
 
     metadata: PartMetadata,
     builder: PartBufferBundleBuilder,
     mesh_builder: MeshBuilder,
     color_stack: Vec<ColorReference>,
+    document_stack: CycleGuard<*const Document>,
 }
 
 impl<'a> PartBaker<'a> {
@@ -660,9 +1292,15 @@ impl<'a> PartBaker<'a> {
         invert: bool,
         local: bool,
     ) {
+        if !self.document_stack.enter(document) {
+            return;
+        }
+
         let mut local_cull = true;
         let mut winding = Winding::Ccw;
         let bfc_certified = document.bfc.is_certified().unwrap_or(true);
+        let tag_uncertified =
+            self.options.tag_bfc_errors && document.bfc.is_certified() != Some(true);
         let mut invert_next = false;
 
         if bfc_certified {
@@ -697,8 +1335,11 @@ impl<'a> PartBaker<'a> {
                         self.color_stack.push(color);
                         self.traverse(part, &*parent, matrix, cull_next, invert_child, local);
                         self.color_stack.pop();
-                    } else if let Some((document, local)) = self.resolutions.query(&cmd.name, local)
+                    } else if self.options.stud_detail == StudDetail::Hidden
+                        && ConnectionKind::from_primitive(&cmd.name) == Some(ConnectionKind::Stud)
                     {
+                        // Skip studs altogether
+                    } else if let Some((document, local)) = self.resolve(&cmd.name, local) {
                         self.color_stack.push(color);
                         self.traverse(
                             &document.body,
@@ -748,7 +1389,7 @@ impl<'a> PartBaker<'a> {
                         e => e,
                     };
 
-                    let face = match winding {
+                    let mut face = match winding {
                         Winding::Ccw => {
                             let v1 = (matrix * cmd.a).truncate();
                             let v2 = (matrix * cmd.b).truncate();
@@ -770,6 +1411,7 @@ impl<'a> PartBaker<'a> {
                                     },
                                 ]),
                                 winding: Winding::Ccw,
+                                bfc_error: false,
                             }
                         }
                         Winding::Cw => {
@@ -793,6 +1435,7 @@ impl<'a> PartBaker<'a> {
                                     },
                                 ]),
                                 winding: Winding::Cw,
+                                bfc_error: false,
                             }
                         }
                     };
@@ -806,6 +1449,8 @@ impl<'a> PartBaker<'a> {
                         },
                     };
 
+                    face.bfc_error =
+                        tag_uncertified || (self.options.tag_bfc_errors && face.is_miswound());
                     self.mesh_builder
                         .add(&category, Rc::new(RefCell::new(face)));
                 }
@@ -815,7 +1460,7 @@ impl<'a> PartBaker<'a> {
                         e => e,
                     };
 
-                    let face = match winding {
+                    let mut face = match winding {
                         Winding::Ccw => {
                             let v1 = (matrix * cmd.a).truncate();
                             let v2 = (matrix * cmd.b).truncate();
@@ -842,6 +1487,7 @@ impl<'a> PartBaker<'a> {
                                     },
                                 ]),
                                 winding: Winding::Ccw,
+                                bfc_error: false,
                             }
                         }
                         Winding::Cw => {
@@ -870,6 +1516,7 @@ impl<'a> PartBaker<'a> {
                                     },
                                 ]),
                                 winding: Winding::Cw,
+                                bfc_error: false,
                             }
                         }
                     };
@@ -883,6 +1530,8 @@ impl<'a> PartBaker<'a> {
                         },
                     };
 
+                    face.bfc_error =
+                        tag_uncertified || (self.options.tag_bfc_errors && face.is_miswound());
                     self.mesh_builder
                         .add(&category, Rc::new(RefCell::new(face)));
                 }
@@ -909,29 +1558,61 @@ impl<'a> PartBaker<'a> {
                 }
             };
         }
+
+        self.document_stack.leave();
+    }
+
+    fn resolve(&self, alias: &PartAlias, local: bool) -> Option<(Arc<MultipartDocument>, bool)> {
+        // Falls back to the standard primitive if the alternative is not available.
+        self.resolutions
+            .kind(alias)
+            .and_then(|kind| self.options.primitive_resolution.alternative(alias, kind))
+            .and_then(|alternative| self.resolutions.query(&alternative, false))
+            .or_else(|| self.resolutions.query(alias, local))
     }
 
-    pub fn bake(mut self) -> Part {
+    pub fn bake(mut self) -> (Part, CleanupStatistics) {
         let mut bounding_box = BoundingBox3::nil();
-        self.mesh_builder.smooth_normals();
+        let mut statistics = if self.options.skip_cleanup {
+            CleanupStatistics::default()
+        } else {
+            self.mesh_builder.cleanup()
+        };
+        if self.options.repair_t_junctions {
+            self.mesh_builder.repair_t_junctions();
+        }
+        self.mesh_builder
+            .smooth_normals(self.options.smoothing_angle);
         self.mesh_builder.bake(&mut self.builder, &mut bounding_box);
 
-        Part::new(
+        let mut geometry = self.builder.build();
+        if !self.options.skip_optimization {
+            statistics.merged_vertices = geometry.optimize();
+        }
+
+        let part = Part::new(
             self.metadata,
-            self.builder.build(),
+            geometry,
             bounding_box,
             Vector3::new(0.0, 0.0, 0.0),
-        )
+        );
+        (part, statistics)
     }
 
-    pub fn new(metadata: PartMetadata, resolutions: &'a ResolutionResult) -> Self {
+    pub fn new(
+        metadata: PartMetadata,
+        resolutions: &'a ResolutionResult,
+        options: &'a BakeOptions,
+    ) -> Self {
         let mut mb = PartBaker {
             resolutions,
+            options,
 
             metadata,
             builder: PartBufferBundleBuilder::default(),
             mesh_builder: MeshBuilder::new(),
             color_stack: Vec::new(),
+            document_stack: CycleGuard::new(),
         };
 
         mb.color_stack.push(ColorReference::Current);
@@ -945,7 +1626,30 @@ pub fn bake_part_from_multipart_document<D: Deref<Target = MultipartDocument>>(
     resolutions: &ResolutionResult,
     local: bool,
 ) -> Part {
-    let mut baker = PartBaker::new(PartMetadata::from(&document.body), resolutions);
+    bake_part_from_multipart_document_with_options(
+        document,
+        resolutions,
+        local,
+        &BakeOptions::default(),
+    )
+}
+
+pub fn bake_part_from_multipart_document_with_options<D: Deref<Target = MultipartDocument>>(
+    document: D,
+    resolutions: &ResolutionResult,
+    local: bool,
+    options: &BakeOptions,
+) -> Part {
+    bake_part_from_multipart_document_with_statistics(document, resolutions, local, options).0
+}
+
+pub fn bake_part_from_multipart_document_with_statistics<D: Deref<Target = MultipartDocument>>(
+    document: D,
+    resolutions: &ResolutionResult,
+    local: bool,
+    options: &BakeOptions,
+) -> (Part, CleanupStatistics) {
+    let mut baker = PartBaker::new(PartMetadata::from(&document.body), resolutions, options);
 
     baker.traverse(
         &document.body,
@@ -963,7 +1667,8 @@ pub fn bake_part_from_document(
     resolutions: &ResolutionResult,
     local: bool,
 ) -> Part {
-    let mut baker = PartBaker::new(document.into(), resolutions);
+    let options = BakeOptions::default();
+    let mut baker = PartBaker::new(document.into(), resolutions, &options);
 
     baker.traverse(
         document,
@@ -977,9 +1682,256 @@ pub fn bake_part_from_document(
         false,
         local,
     );
-    baker.bake()
+    baker.bake().0
 }
 
 pub trait PartDimensionQuerier<P> {
     fn query_part_dimension(&self, alias: &P) -> Option<BoundingBox3>;
 }
+
+pub trait PartGeometryQuerier<P> {
+    fn query_part_geometry(&self, alias: &P) -> Option<&Part>;
+}
+
+#[cfg(test)]
+mod tests {
+    use ldraw::{
+        color::ColorReference,
+        document::{Document, MultipartDocument},
+        elements::{Command, Quad, Triangle},
+        library::{PartKind, ResolutionResult},
+        PartAlias, Vector4,
+    };
+
+    use super::{
+        bake_part_from_multipart_document_with_options,
+        bake_part_from_multipart_document_with_statistics, BakeOptions, CleanupStatistics,
+        MeshBuffer, Part, PartBufferBundle, PrimitiveResolution, VertexBuffer, VERTEX_CACHE_SIZE,
+    };
+
+    #[test]
+    fn test_primitive_alternative() {
+        let disc = PartAlias::from("4-4disc.dat");
+        assert_eq!(
+            PrimitiveResolution::Low.alternative(&disc, PartKind::Primitive),
+            Some(PartAlias::from("8/4-4disc.dat"))
+        );
+        assert_eq!(
+            PrimitiveResolution::High.alternative(&disc, PartKind::Primitive),
+            Some(PartAlias::from("48/4-4disc.dat"))
+        );
+        assert_eq!(
+            PrimitiveResolution::Standard.alternative(&disc, PartKind::Primitive),
+            None
+        );
+        assert_eq!(
+            PrimitiveResolution::Low.alternative(&PartAlias::from("3001.dat"), PartKind::Part),
+            None
+        );
+        assert_eq!(
+            PrimitiveResolution::Low
+                .alternative(&PartAlias::from("48/4-4disc.dat"), PartKind::Primitive),
+            None
+        );
+    }
+
+    #[test]
+    fn test_repair_t_junctions() {
+        let quad = |a: (f32, f32), b: (f32, f32), c: (f32, f32), d: (f32, f32)| {
+            let v = |(x, z): (f32, f32)| Vector4::new(x, 0.0, z, 1.0);
+            Command::Quad(Quad {
+                color: ColorReference::Current,
+                a: v(a),
+                b: v(b),
+                c: v(c),
+                d: v(d),
+            })
+        };
+        // The right edge of the first quad meets the shared corner of the other two.
+        let document = MultipartDocument {
+            body: Document {
+                commands: vec![
+                    quad((0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 2.0)),
+                    quad((2.0, 0.0), (4.0, 0.0), (4.0, 1.0), (2.0, 1.0)),
+                    quad((2.0, 1.0), (4.0, 1.0), (4.0, 2.0), (2.0, 2.0)),
+                ],
+                ..Default::default()
+            },
+            subparts: Default::default(),
+        };
+        let resolutions = ResolutionResult::new();
+
+        let triangles = |options: &BakeOptions| {
+            let part = bake_part_from_multipart_document_with_options(
+                &document,
+                &resolutions,
+                false,
+                options,
+            );
+            (part.geometry.uncolored_mesh.len() + part.geometry.uncolored_without_bfc_mesh.len())
+                / 3
+        };
+
+        assert_eq!(triangles(&BakeOptions::default()), 6);
+        assert_eq!(
+            triangles(&BakeOptions {
+                repair_t_junctions: true,
+                ..Default::default()
+            }),
+            7
+        );
+    }
+
+    #[test]
+    fn test_cleanup() {
+        let v = |x: f32, z: f32| Vector4::new(x, 0.0, z, 1.0);
+        let quad = |a, b, c, d| {
+            Command::Quad(Quad {
+                color: ColorReference::Current,
+                a,
+                b,
+                c,
+                d,
+            })
+        };
+        let document = MultipartDocument {
+            body: Document {
+                commands: vec![
+                    quad(v(0.0, 0.0), v(1.0, 0.0), v(1.0, 1.0), v(0.0, 1.0)),
+                    // Same face starting from another vertex
+                    quad(v(1.0, 1.0), v(0.0, 1.0), v(0.0, 0.0), v(1.0, 0.0)),
+                    // Back side of the face is not a duplicate
+                    quad(v(0.0, 1.0), v(1.0, 1.0), v(1.0, 0.0), v(0.0, 0.0)),
+                    Command::Triangle(Triangle {
+                        color: ColorReference::Current,
+                        a: v(0.0, 0.0),
+                        b: v(1.0, 0.0),
+                        c: v(2.0, 0.0),
+                    }),
+                ],
+                ..Default::default()
+            },
+            subparts: Default::default(),
+        };
+        let resolutions = ResolutionResult::new();
+
+        let (part, statistics) = bake_part_from_multipart_document_with_statistics(
+            &document,
+            &resolutions,
+            false,
+            &BakeOptions::default(),
+        );
+        assert_eq!(
+            statistics,
+            CleanupStatistics {
+                degenerate_faces: 1,
+                duplicate_faces: 1,
+                merged_vertices: 0,
+            }
+        );
+        let triangles = |part: &Part| {
+            (part.geometry.uncolored_mesh.len() + part.geometry.uncolored_without_bfc_mesh.len())
+                / 3
+        };
+        assert_eq!(triangles(&part), 4);
+
+        let (part, statistics) = bake_part_from_multipart_document_with_statistics(
+            &document,
+            &resolutions,
+            false,
+            &BakeOptions {
+                skip_cleanup: true,
+                ..Default::default()
+            },
+        );
+        assert!(statistics.is_empty());
+        assert_eq!(triangles(&part), 7);
+
+        // Faces removed by cleanup are not shown as BFC errors either.
+        let (part, _) = bake_part_from_multipart_document_with_statistics(
+            &document,
+            &resolutions,
+            false,
+            &BakeOptions {
+                tag_bfc_errors: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(part.geometry.bfc_error_mesh.len() / 3, 4);
+    }
+
+    #[test]
+    fn test_optimize() {
+        // Two triangles sharing an edge, with one corner slightly off.
+        let mut bundle = PartBufferBundle {
+            vertex_buffer: VertexBuffer(vec![
+                0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0005, 0.0, 0.0, 1.0, 0.0, 1.0, 0.0,
+                -1.0, 0.0, 7.0, 7.0, 7.0,
+            ]),
+            uncolored_mesh: MeshBuffer {
+                vertex_indices: vec![0, 1, 2, 3, 4, 2],
+                normal_indices: vec![5; 6],
+            },
+            ..Default::default()
+        };
+        assert_eq!(bundle.optimize(), 2);
+        assert_eq!(bundle.vertex_buffer.0.len(), 15);
+        assert_eq!(bundle.uncolored_mesh.vertex_indices, vec![0, 1, 2, 1, 3, 2]);
+        assert_eq!(bundle.uncolored_mesh.normal_indices, vec![4; 6]);
+
+        // Grid of quads emitted column by column thrashes the cache.
+        let size = 48;
+        let v = |x: usize, z: usize| Vector4::new(x as f32, 0.0, z as f32, 1.0);
+        let mut commands = vec![];
+        for x in 0..size {
+            for z in 0..size {
+                commands.push(Command::Quad(Quad {
+                    color: ColorReference::Current,
+                    a: v(x, z),
+                    b: v(x + 1, z),
+                    c: v(x + 1, z + 1),
+                    d: v(x, z + 1),
+                }));
+            }
+        }
+        let document = MultipartDocument {
+            body: Document {
+                commands,
+                ..Default::default()
+            },
+            subparts: Default::default(),
+        };
+        let resolutions = ResolutionResult::new();
+        let bake = |skip_optimization| {
+            bake_part_from_multipart_document_with_options(
+                &document,
+                &resolutions,
+                false,
+                &BakeOptions {
+                    skip_optimization,
+                    ..Default::default()
+                },
+            )
+            .geometry
+        };
+
+        let original = bake(true);
+        let optimized = bake(false);
+        let mesh = |bundle: &PartBufferBundle| {
+            if bundle.uncolored_mesh.is_empty() {
+                bundle.uncolored_without_bfc_mesh.clone()
+            } else {
+                bundle.uncolored_mesh.clone()
+            }
+        };
+        assert_eq!(mesh(&original).len(), mesh(&optimized).len());
+        assert_eq!(
+            original.vertex_buffer.0.len(),
+            optimized.vertex_buffer.0.len()
+        );
+
+        let before = mesh(&original).cache_miss_ratio(VERTEX_CACHE_SIZE);
+        let after = mesh(&optimized).cache_miss_ratio(VERTEX_CACHE_SIZE);
+        assert!(after < before * 0.8, "{} -> {}", before, after);
+    }
+}
@@ -0,0 +1,230 @@
+use async_trait::async_trait;
+use ldraw::{
+    color::ColorCatalog,
+    error::ResolutionError,
+    library::{resolve_dependencies_multipart, FileLocation, LibraryLoader, PartCache},
+    PartAlias,
+};
+
+use super::{
+    bake_part_from_multipart_document_with_options,
+    io::{decode_part, PartFormatError},
+    BakeOptions, Part,
+};
+
+// Where parts baked ahead of time are fetched from, e.g. an output directory of the baker or
+// a web server serving one.
+#[async_trait(?Send)]
+pub trait BakedPartSource {
+    // Returns Ok(None) if the part was not baked.
+    async fn fetch(&self, alias: &PartAlias) -> Result<Option<Vec<u8>>, ResolutionError>;
+}
+
+// Path of a baked part relative to where the baker wrote it, e.g. 3001.dat.part.
+pub fn baked_part_path(alias: &PartAlias) -> String {
+    format!("{}.part", alias.normalized.replace('\\', "/"))
+}
+
+// Baked parts in a directory on the disk.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct BakedDirectory {
+    path: std::path::PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl BakedDirectory {
+    pub fn new(path: std::path::PathBuf) -> Self {
+        BakedDirectory { path }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait(?Send)]
+impl BakedPartSource for BakedDirectory {
+    async fn fetch(&self, alias: &PartAlias) -> Result<Option<Vec<u8>>, ResolutionError> {
+        match tokio::fs::read(self.path.join(baked_part_path(alias))).await {
+            Ok(v) => Ok(Some(v)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PartOrigin {
+    Baked,
+    // Parsed and baked as there was no baked part.
+    Source,
+    // Parsed and baked as the baked part could not be read, e.g. it was baked by a version
+    // writing another format.
+    Rebaked(PartFormatError),
+}
+
+// Loads parts baked ahead of time, parsing and baking ones that are missing from the source
+// instead. Baked parts are used as they are, so they must have been baked with the same
+// options for the results to match.
+pub struct BakedLoader<S, L> {
+    source: S,
+    fallback: L,
+    colors: ColorCatalog,
+    options: BakeOptions,
+
+    cache: PartCache,
+}
+
+impl<S: BakedPartSource, L: LibraryLoader> BakedLoader<S, L> {
+    pub fn new(source: S, fallback: L, colors: ColorCatalog, options: BakeOptions) -> Self {
+        BakedLoader {
+            source,
+            fallback,
+            colors,
+            options,
+            cache: PartCache::new(),
+        }
+    }
+
+    pub fn fallback(&self) -> &L {
+        &self.fallback
+    }
+
+    pub async fn load(&self, alias: &PartAlias) -> Result<(Part, PartOrigin), ResolutionError> {
+        let origin = match self.source.fetch(alias).await {
+            Ok(Some(data)) => match decode_part(&data) {
+                Ok(part) => return Ok((part, PartOrigin::Baked)),
+                Err(e) => PartOrigin::Rebaked(e),
+            },
+            // Baked parts are only a shortcut, so the source is tried whatever went wrong.
+            Ok(None) | Err(_) => PartOrigin::Source,
+        };
+
+        let (location, document) = self
+            .fallback
+            .load_ref(alias.clone(), false, &self.colors)
+            .await?;
+        let resolution_result = resolve_dependencies_multipart(
+            &document,
+            self.cache.clone(),
+            &self.colors,
+            &self.fallback,
+            &|_, _| {},
+        )
+        .await;
+        let part = bake_part_from_multipart_document_with_options(
+            &document,
+            &resolution_result,
+            matches!(location, FileLocation::Local),
+            &self.options,
+        );
+        Ok((part, origin))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use async_trait::async_trait;
+    use ldraw::{
+        color::ColorCatalog,
+        document::{Document, MultipartDocument},
+        error::ResolutionError,
+        library::{FileLocation, LibraryLoader, PartKind, ResolutionResult},
+        parser::parse_multipart_document,
+        PartAlias,
+    };
+
+    use crate::part::{
+        bake_part_from_document,
+        io::{encode_part, PartFormatError, PartWriteOptions},
+        BakeOptions,
+    };
+
+    use super::{baked_part_path, BakedLoader, BakedPartSource, PartOrigin};
+
+    struct MockSource(HashMap<String, Vec<u8>>);
+
+    #[async_trait(?Send)]
+    impl BakedPartSource for MockSource {
+        async fn fetch(&self, alias: &PartAlias) -> Result<Option<Vec<u8>>, ResolutionError> {
+            Ok(self.0.get(&baked_part_path(alias)).cloned())
+        }
+    }
+
+    struct MockLoader(HashMap<PartAlias, &'static str>);
+
+    #[async_trait(?Send)]
+    impl LibraryLoader for MockLoader {
+        async fn load_colors(&self) -> Result<ColorCatalog, ResolutionError> {
+            Ok(ColorCatalog::new())
+        }
+
+        async fn load_ref(
+            &self,
+            alias: PartAlias,
+            _local: bool,
+            colors: &ColorCatalog,
+        ) -> Result<(FileLocation, MultipartDocument), ResolutionError> {
+            let source = self.0.get(&alias).ok_or(ResolutionError::FileNotFound {
+                suggestions: Vec::new(),
+            })?;
+            let document = parse_multipart_document(&mut source.as_bytes(), colors).await?;
+            Ok((FileLocation::Library(PartKind::Part), document))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_baked_loader() {
+        let loader = MockLoader(HashMap::from([
+            (
+                PartAlias::from("a.dat"),
+                "0 A\n1 16 0 0 0 1 0 0 0 1 0 0 0 1 tri.dat\n",
+            ),
+            (
+                PartAlias::from("tri.dat"),
+                "0 Triangle\n3 16 0 0 0 1 0 0 0 0 1\n",
+            ),
+            (PartAlias::from("b.dat"), "0 B\n3 16 0 0 0 1 0 0 0 0 1\n"),
+        ]));
+
+        let baked = bake_part_from_document(
+            &Document {
+                description: String::from("Baked B"),
+                ..Default::default()
+            },
+            &ResolutionResult::default(),
+            false,
+        );
+        let mut stale = encode_part(&baked, &PartWriteOptions::default()).unwrap();
+        stale[4] = 0xff;
+        let source = MockSource(HashMap::from([
+            (
+                String::from("b.dat.part"),
+                encode_part(&baked, &PartWriteOptions::default()).unwrap(),
+            ),
+            (String::from("tri.dat.part"), stale),
+        ]));
+
+        let loader = BakedLoader::new(source, loader, ColorCatalog::new(), BakeOptions::default());
+
+        let (part, origin) = loader.load(&PartAlias::from("B.DAT")).await.unwrap();
+        assert!(matches!(origin, PartOrigin::Baked));
+        assert_eq!(part.metadata.description, "Baked B");
+
+        // Parts not baked are baked from the source along with their dependencies.
+        let (part, origin) = loader.load(&PartAlias::from("a.dat")).await.unwrap();
+        assert!(matches!(origin, PartOrigin::Source));
+        assert_eq!(part.metadata.description, "A");
+        assert!(!part.geometry.vertex_buffer.0.is_empty());
+
+        let (_, origin) = loader.load(&PartAlias::from("tri.dat")).await.unwrap();
+        assert!(matches!(
+            origin,
+            PartOrigin::Rebaked(PartFormatError::UnsupportedVersion(_))
+        ));
+
+        assert!(matches!(
+            loader.load(&PartAlias::from("missing.dat")).await,
+            Err(ResolutionError::FileNotFound { .. })
+        ));
+    }
+}
@@ -0,0 +1,265 @@
+use std::{error::Error, fmt};
+
+use super::Part;
+
+// Baked parts are stored in a small container so that readers can tell a part baked in
+// another layout apart from a corrupt one, rather than misreading either.
+//
+//   magic    4 bytes, "LDRP"
+//   version  u16, little endian
+//   flags    u16, little endian
+//   length   u64, little endian, of the payload as stored
+//   payload  Part serialized with bincode, compressed with zstd if FLAG_ZSTD is set
+
+pub const MAGIC: &[u8; 4] = b"LDRP";
+
+// Bumped whenever Part or anything in it changes shape.
+pub const FORMAT_VERSION: u16 = 2;
+
+pub const FLAG_ZSTD: u16 = 1 << 0;
+
+const KNOWN_FLAGS: u16 = FLAG_ZSTD;
+
+const HEADER_LENGTH: usize = 16;
+
+#[derive(Debug)]
+pub enum PartFormatError {
+    // Missing magic bytes, e.g. parts baked as bare bincode before the container existed.
+    NotAPart,
+    NotAPack,
+    UnsupportedPackVersion(u16),
+    UnsupportedVersion(u16),
+    // Flags unknown to this build, or zstd compression when built without it.
+    UnsupportedFlags(u16),
+    Truncated,
+    CompressionError(std::io::Error),
+    SerializationError(bincode::Error),
+}
+
+impl From<bincode::Error> for PartFormatError {
+    fn from(e: bincode::Error) -> PartFormatError {
+        PartFormatError::SerializationError(e)
+    }
+}
+
+impl fmt::Display for PartFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PartFormatError::NotAPart => write!(
+                f,
+                "Not a baked part, or baked by a version without a format header"
+            ),
+            PartFormatError::NotAPack => write!(f, "Not a pack of baked parts"),
+            PartFormatError::UnsupportedPackVersion(v) => write!(
+                f,
+                "Pack is in format version {}, while version {} is supported",
+                v,
+                super::pack::PACK_FORMAT_VERSION
+            ),
+            PartFormatError::UnsupportedVersion(v) => write!(
+                f,
+                "Part is in format version {}, while version {} is supported",
+                v, FORMAT_VERSION
+            ),
+            PartFormatError::UnsupportedFlags(v) => {
+                write!(f, "Part uses unsupported features (flags {:#06x})", v)
+            }
+            PartFormatError::Truncated => write!(f, "Part is truncated"),
+            PartFormatError::CompressionError(e) => write!(f, "Compression error: {}", e),
+            PartFormatError::SerializationError(e) => write!(f, "Serialization error: {}", e),
+        }
+    }
+}
+
+impl Error for PartFormatError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PartFormatError::CompressionError(e) => Some(e),
+            PartFormatError::SerializationError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PartWriteOptions {
+    // zstd compression level, or None to store the payload as is.
+    pub compression: Option<i32>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PartHeader {
+    pub version: u16,
+    pub flags: u16,
+    pub length: u64,
+}
+
+impl PartHeader {
+    pub fn is_compressed(&self) -> bool {
+        self.flags & FLAG_ZSTD != 0
+    }
+}
+
+// Reads the header alone, e.g. to check a part before reading the rest of it.
+pub fn read_part_header(data: &[u8]) -> Result<PartHeader, PartFormatError> {
+    if !data.starts_with(MAGIC) {
+        return Err(PartFormatError::NotAPart);
+    }
+    if data.len() < HEADER_LENGTH {
+        return Err(PartFormatError::Truncated);
+    }
+
+    let u16_at = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+    let mut length = [0u8; 8];
+    length.copy_from_slice(&data[8..16]);
+    Ok(PartHeader {
+        version: u16_at(4),
+        flags: u16_at(6),
+        length: u64::from_le_bytes(length),
+    })
+}
+
+pub fn encode_part(part: &Part, options: &PartWriteOptions) -> Result<Vec<u8>, PartFormatError> {
+    let serialized = bincode::serialize(part)?;
+    let (flags, payload) = match options.compression {
+        None => (0u16, serialized),
+        #[cfg(feature = "compression")]
+        Some(level) => (
+            FLAG_ZSTD,
+            zstd::encode_all(&serialized[..], level).map_err(PartFormatError::CompressionError)?,
+        ),
+        #[cfg(not(feature = "compression"))]
+        Some(_) => return Err(PartFormatError::UnsupportedFlags(FLAG_ZSTD)),
+    };
+
+    let mut result = Vec::with_capacity(HEADER_LENGTH + payload.len());
+    result.extend_from_slice(MAGIC);
+    result.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    result.extend_from_slice(&flags.to_le_bytes());
+    result.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    result.extend_from_slice(&payload);
+    Ok(result)
+}
+
+pub fn decode_part(data: &[u8]) -> Result<Part, PartFormatError> {
+    let header = read_part_header(data)?;
+    if header.version != FORMAT_VERSION {
+        return Err(PartFormatError::UnsupportedVersion(header.version));
+    }
+    let unknown = header.flags & !KNOWN_FLAGS;
+    if unknown != 0 {
+        return Err(PartFormatError::UnsupportedFlags(unknown));
+    }
+
+    let payload = &data[HEADER_LENGTH..];
+    let payload = match usize::try_from(header.length) {
+        Ok(length) if length <= payload.len() => &payload[..length],
+        _ => return Err(PartFormatError::Truncated),
+    };
+
+    if header.is_compressed() {
+        #[cfg(feature = "compression")]
+        {
+            let decompressed =
+                zstd::decode_all(payload).map_err(PartFormatError::CompressionError)?;
+            return Ok(bincode::deserialize(&decompressed)?);
+        }
+        #[cfg(not(feature = "compression"))]
+        return Err(PartFormatError::UnsupportedFlags(FLAG_ZSTD));
+    }
+    Ok(bincode::deserialize(payload)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use ldraw::{
+        color::ColorReference,
+        document::{Document, MultipartDocument},
+        elements::{Command, Triangle},
+        library::ResolutionResult,
+        Vector4,
+    };
+
+    use crate::part::{bake_part_from_multipart_document, Part};
+
+    use super::{
+        decode_part, encode_part, read_part_header, PartFormatError, PartWriteOptions,
+        FORMAT_VERSION,
+    };
+
+    fn part() -> Part {
+        let document = MultipartDocument {
+            body: Document {
+                commands: vec![Command::Triangle(Triangle {
+                    color: ColorReference::Current,
+                    a: Vector4::new(0.0, 0.0, 0.0, 1.0),
+                    b: Vector4::new(1.0, 0.0, 0.0, 1.0),
+                    c: Vector4::new(0.0, 0.0, 1.0, 1.0),
+                })],
+                ..Default::default()
+            },
+            subparts: Default::default(),
+        };
+        bake_part_from_multipart_document(&document, &ResolutionResult::default(), false)
+    }
+
+    #[test]
+    fn test_part_round_trip() {
+        let part = part();
+        let encoded = encode_part(&part, &PartWriteOptions::default()).unwrap();
+        let header = read_part_header(&encoded).unwrap();
+        assert_eq!(header.version, FORMAT_VERSION);
+        assert!(!header.is_compressed());
+
+        let decoded = decode_part(&encoded).unwrap();
+        assert_eq!(
+            encode_part(&decoded, &PartWriteOptions::default()).unwrap(),
+            encoded
+        );
+
+        #[cfg(feature = "compression")]
+        {
+            let options = PartWriteOptions {
+                compression: Some(3),
+            };
+            let compressed = encode_part(&part, &options).unwrap();
+            assert!(read_part_header(&compressed).unwrap().is_compressed());
+            let decoded = decode_part(&compressed).unwrap();
+            assert_eq!(encode_part(&decoded, &options).unwrap(), compressed);
+        }
+    }
+
+    #[test]
+    fn test_part_format_errors() {
+        let encoded = encode_part(&part(), &PartWriteOptions::default()).unwrap();
+
+        let legacy = bincode::serialize(&part()).unwrap();
+        assert!(matches!(
+            decode_part(&legacy),
+            Err(PartFormatError::NotAPart)
+        ));
+
+        let mut newer = encoded.clone();
+        newer[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            decode_part(&newer),
+            Err(PartFormatError::UnsupportedVersion(v)) if v == FORMAT_VERSION + 1
+        ));
+
+        let mut flagged = encoded.clone();
+        flagged[6..8].copy_from_slice(&0x8000u16.to_le_bytes());
+        assert!(matches!(
+            decode_part(&flagged),
+            Err(PartFormatError::UnsupportedFlags(0x8000))
+        ));
+
+        assert!(matches!(
+            decode_part(&encoded[..encoded.len() - 1]),
+            Err(PartFormatError::Truncated)
+        ));
+        assert!(matches!(
+            decode_part(&encoded[..10]),
+            Err(PartFormatError::Truncated)
+        ));
+    }
+}
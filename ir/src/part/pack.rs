@@ -0,0 +1,224 @@
+use std::{collections::HashMap, ops::Range};
+
+use async_trait::async_trait;
+use ldraw::{color::ColorCatalog, error::ResolutionError, PartAlias};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    baked::BakedPartSource,
+    io::{decode_part, PartFormatError},
+    Part,
+};
+
+// Baked parts and the color catalog bundled into one file, so that they can be fetched at once.
+//
+//   magic    4 bytes, "LDPK"
+//   version  u16, little endian
+//   flags    u16, little endian, reserved
+//   length   u64, little endian, of the index
+//   index    PackIndex serialized with bincode
+//   parts    each part in the container written by encode_part, in the order of the index
+
+pub const PACK_MAGIC: &[u8; 4] = b"LDPK";
+
+pub const PACK_FORMAT_VERSION: u16 = 1;
+
+const PACK_HEADER_LENGTH: usize = 16;
+
+#[derive(Serialize, Deserialize)]
+struct PackIndex {
+    colors: ColorCatalog,
+    // Aliases along with the length of each part.
+    parts: Vec<(PartAlias, u64)>,
+}
+
+// Writes everything up to where the parts begin. Parts of given lengths are to be written
+// right after it in the same order, which lets the parts be copied from files one by one
+// rather than read into memory all at once.
+pub fn encode_pack_header(
+    colors: &ColorCatalog,
+    parts: &[(PartAlias, u64)],
+) -> Result<Vec<u8>, PartFormatError> {
+    let index = bincode::serialize(&PackIndex {
+        colors: colors.clone(),
+        parts: parts.to_vec(),
+    })?;
+
+    let mut result = Vec::with_capacity(PACK_HEADER_LENGTH + index.len());
+    result.extend_from_slice(PACK_MAGIC);
+    result.extend_from_slice(&PACK_FORMAT_VERSION.to_le_bytes());
+    result.extend_from_slice(&0u16.to_le_bytes());
+    result.extend_from_slice(&(index.len() as u64).to_le_bytes());
+    result.extend_from_slice(&index);
+    Ok(result)
+}
+
+// Parts are expected to have been encoded with encode_part already.
+pub fn encode_pack(
+    colors: &ColorCatalog,
+    parts: &[(PartAlias, Vec<u8>)],
+) -> Result<Vec<u8>, PartFormatError> {
+    let lengths = parts
+        .iter()
+        .map(|(alias, data)| (alias.clone(), data.len() as u64))
+        .collect::<Vec<_>>();
+    let mut result = encode_pack_header(colors, &lengths)?;
+    for (_, data) in parts {
+        result.extend_from_slice(data);
+    }
+    Ok(result)
+}
+
+// Serves baked parts out of a pack held in memory.
+pub struct PackLoader {
+    data: Vec<u8>,
+    colors: ColorCatalog,
+    parts: HashMap<PartAlias, Range<usize>>,
+}
+
+impl PackLoader {
+    // Reads the index. Parts are left as they are until loaded.
+    pub fn new(data: Vec<u8>) -> Result<Self, PartFormatError> {
+        if !data.starts_with(PACK_MAGIC) {
+            return Err(PartFormatError::NotAPack);
+        }
+        if data.len() < PACK_HEADER_LENGTH {
+            return Err(PartFormatError::Truncated);
+        }
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        if version != PACK_FORMAT_VERSION {
+            return Err(PartFormatError::UnsupportedPackVersion(version));
+        }
+        let flags = u16::from_le_bytes([data[6], data[7]]);
+        if flags != 0 {
+            return Err(PartFormatError::UnsupportedFlags(flags));
+        }
+
+        let mut length = [0u8; 8];
+        length.copy_from_slice(&data[8..16]);
+        let index_end = match usize::try_from(u64::from_le_bytes(length)) {
+            Ok(v) if v <= data.len() - PACK_HEADER_LENGTH => PACK_HEADER_LENGTH + v,
+            _ => return Err(PartFormatError::Truncated),
+        };
+        let index: PackIndex = bincode::deserialize(&data[PACK_HEADER_LENGTH..index_end])?;
+
+        let mut parts = HashMap::with_capacity(index.parts.len());
+        let mut offset = index_end;
+        for (alias, length) in index.parts {
+            let end = match usize::try_from(length) {
+                Ok(v) if v <= data.len() - offset => offset + v,
+                _ => return Err(PartFormatError::Truncated),
+            };
+            parts.insert(alias, offset..end);
+            offset = end;
+        }
+
+        Ok(PackLoader {
+            data,
+            colors: index.colors,
+            parts,
+        })
+    }
+
+    pub fn colors(&self) -> &ColorCatalog {
+        &self.colors
+    }
+
+    pub fn len(&self) -> usize {
+        self.parts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parts.is_empty()
+    }
+
+    pub fn contains(&self, alias: &PartAlias) -> bool {
+        self.parts.contains_key(alias)
+    }
+
+    pub fn aliases(&self) -> impl Iterator<Item = &PartAlias> {
+        self.parts.keys()
+    }
+
+    // Returns the part as encoded by encode_part.
+    pub fn get(&self, alias: &PartAlias) -> Option<&[u8]> {
+        self.parts.get(alias).map(|v| &self.data[v.clone()])
+    }
+
+    pub fn load(&self, alias: &PartAlias) -> Result<Option<Part>, PartFormatError> {
+        self.get(alias).map(decode_part).transpose()
+    }
+}
+
+#[async_trait(?Send)]
+impl BakedPartSource for PackLoader {
+    async fn fetch(&self, alias: &PartAlias) -> Result<Option<Vec<u8>>, ResolutionError> {
+        Ok(self.get(alias).map(<[u8]>::to_vec))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ldraw::{
+        color::{Color, ColorCatalog},
+        document::Document,
+        library::ResolutionResult,
+        PartAlias,
+    };
+
+    use crate::part::{
+        bake_part_from_document,
+        io::{encode_part, PartFormatError, PartWriteOptions},
+    };
+
+    use super::{encode_pack, PackLoader};
+
+    #[test]
+    fn test_pack_round_trip() {
+        let mut colors = ColorCatalog::new();
+        colors.insert(
+            4,
+            Color {
+                code: 4,
+                name: String::from("Red"),
+                ..Default::default()
+            },
+        );
+
+        let parts = ["a.dat", "s\\b.dat"]
+            .iter()
+            .map(|name| {
+                let part = bake_part_from_document(
+                    &Document {
+                        description: String::from(*name),
+                        ..Default::default()
+                    },
+                    &ResolutionResult::default(),
+                    false,
+                );
+                (
+                    PartAlias::from(*name),
+                    encode_part(&part, &PartWriteOptions::default()).unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let pack = encode_pack(&colors, &parts).unwrap();
+
+        let loader = PackLoader::new(pack.clone()).unwrap();
+        assert_eq!(loader.len(), 2);
+        assert_eq!(loader.colors(), &colors);
+        assert_eq!(loader.get(&PartAlias::from("A.DAT")), Some(&parts[0].1[..]));
+        let part = loader.load(&PartAlias::from("s/b.dat")).unwrap().unwrap();
+        assert_eq!(part.metadata.description, "s\\b.dat");
+        assert!(loader.load(&PartAlias::from("c.dat")).unwrap().is_none());
+
+        assert!(matches!(
+            PackLoader::new(parts[0].1.clone()),
+            Err(PartFormatError::NotAPack)
+        ));
+        assert!(matches!(
+            PackLoader::new(pack[..pack.len() - 1].to_vec()),
+            Err(PartFormatError::Truncated)
+        ));
+    }
+}
@@ -0,0 +1,481 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+};
+
+use cgmath::InnerSpace;
+use ldraw::Vector3;
+
+use super::{MeshBuffer, Part};
+
+// Open and color boundaries are held in place by planes perpendicular to the adjacent face,
+// weighted by this factor.
+const BOUNDARY_WEIGHT: f64 = 1000.0;
+// Collapses turning any remaining face further than this (as cosine) are rejected.
+const MIN_NORMAL_COSINE: f32 = 0.2;
+const MIN_DOUBLE_AREA: f32 = 1e-8;
+
+// Symmetric 4x4 matrix of a sum of squared distances to planes.
+#[derive(Clone, Copy, Debug, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(normal: &Vector3, point: &Vector3, weight: f64) -> Self {
+        let (a, b, c) = (normal.x as f64, normal.y as f64, normal.z as f64);
+        let d = -(normal.dot(*point) as f64);
+        Quadric([
+            a * a * weight,
+            a * b * weight,
+            a * c * weight,
+            a * d * weight,
+            b * b * weight,
+            b * c * weight,
+            b * d * weight,
+            c * c * weight,
+            c * d * weight,
+            d * d * weight,
+        ])
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a += b;
+        }
+    }
+
+    fn error(&self, v: &Vector3) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (v.x as f64, v.y as f64, v.z as f64);
+        q[0] * x * x
+            + 2.0 * q[1] * x * y
+            + 2.0 * q[2] * x * z
+            + 2.0 * q[3] * x
+            + q[4] * y * y
+            + 2.0 * q[5] * y * z
+            + 2.0 * q[6] * y
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9]
+    }
+}
+
+// Collapse of vertex `from` onto `to`, valid while neither vertex has changed since.
+#[derive(Debug)]
+struct Collapse {
+    cost: f64,
+    from: u32,
+    to: u32,
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    // Reversed so that BinaryHeap pops the cheapest collapse first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| (other.from, other.to).cmp(&(self.from, self.to)))
+    }
+}
+
+fn face_normal(a: &Vector3, b: &Vector3, c: &Vector3) -> Option<Vector3> {
+    let cross = (b - a).cross(c - a);
+    let length = cross.magnitude();
+    if length < MIN_DOUBLE_AREA {
+        None
+    } else {
+        Some(cross / length)
+    }
+}
+
+struct Simplifier {
+    positions: Vec<Vector3>,
+    triangles: Vec<[u32; 3]>,
+    alive: Vec<bool>,
+    triangles_of: Vec<Vec<usize>>,
+    quadrics: Vec<Quadric>,
+    versions: Vec<u32>,
+    collapsed_to: Vec<Option<u32>>,
+    queue: BinaryHeap<Collapse>,
+}
+
+impl Simplifier {
+    fn new(positions: Vec<Vector3>, triangles: Vec<[u32; 3]>, groups: &[usize]) -> Self {
+        let count = positions.len();
+        let mut triangles_of = vec![Vec::new(); count];
+        let mut quadrics = vec![Quadric::default(); count];
+        let mut edges: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+
+        for (index, triangle) in triangles.iter().enumerate() {
+            for (i, vertex) in triangle.iter().enumerate() {
+                triangles_of[*vertex as usize].push(index);
+                let next = triangle[(i + 1) % 3];
+                edges
+                    .entry((*vertex.min(&next), *vertex.max(&next)))
+                    .or_default()
+                    .push(index);
+            }
+
+            let [a, b, c] = triangle.map(|v| positions[v as usize]);
+            if let Some(normal) = face_normal(&a, &b, &c) {
+                let area = (b - a).cross(c - a).magnitude() as f64 * 0.5;
+                let quadric = Quadric::from_plane(&normal, &a, area);
+                for vertex in triangle.iter() {
+                    quadrics[*vertex as usize].add(&quadric);
+                }
+            }
+        }
+
+        // Edges used by a single face, or by faces of different meshes, must stay in place.
+        for ((a, b), faces) in edges.iter() {
+            let group = groups[faces[0]];
+            let boundary = faces.len() == 1 || faces.iter().any(|f| groups[*f] != group);
+            if !boundary {
+                continue;
+            }
+            for face in faces {
+                let [p, q, r] = triangles[*face].map(|v| positions[v as usize]);
+                let Some(normal) = face_normal(&p, &q, &r) else {
+                    continue;
+                };
+                let (pa, pb) = (positions[*a as usize], positions[*b as usize]);
+                let edge = pb - pa;
+                let perpendicular = edge.cross(normal);
+                if perpendicular.magnitude() < MIN_DOUBLE_AREA {
+                    continue;
+                }
+                let quadric = Quadric::from_plane(
+                    &perpendicular.normalize(),
+                    &pa,
+                    BOUNDARY_WEIGHT * edge.magnitude2() as f64,
+                );
+                quadrics[*a as usize].add(&quadric);
+                quadrics[*b as usize].add(&quadric);
+            }
+        }
+
+        let mut simplifier = Simplifier {
+            alive: vec![true; triangles.len()],
+            positions,
+            triangles,
+            triangles_of,
+            quadrics,
+            versions: vec![0; count],
+            collapsed_to: vec![None; count],
+            queue: BinaryHeap::new(),
+        };
+        for (a, b) in edges.keys() {
+            simplifier.push(*a, *b);
+            simplifier.push(*b, *a);
+        }
+        simplifier
+    }
+
+    fn push(&mut self, from: u32, to: u32) {
+        let mut quadric = self.quadrics[from as usize];
+        quadric.add(&self.quadrics[to as usize]);
+        self.queue.push(Collapse {
+            cost: quadric.error(&self.positions[to as usize]),
+            from,
+            to,
+            versions: (self.versions[from as usize], self.versions[to as usize]),
+        });
+    }
+
+    // Moving `from` onto `to` must not flip or squash any face that remains.
+    fn is_collapsible(&self, from: u32, to: u32) -> bool {
+        let target = self.positions[to as usize];
+        self.triangles_of[from as usize]
+            .iter()
+            .filter(|t| self.alive[**t] && !self.triangles[**t].contains(&to))
+            .all(|t| {
+                let before = self.triangles[*t].map(|v| self.positions[v as usize]);
+                let after = self.triangles[*t].map(|v| {
+                    if v == from {
+                        target
+                    } else {
+                        self.positions[v as usize]
+                    }
+                });
+                match (
+                    face_normal(&before[0], &before[1], &before[2]),
+                    face_normal(&after[0], &after[1], &after[2]),
+                ) {
+                    (Some(n1), Some(n2)) => n1.dot(n2) >= MIN_NORMAL_COSINE,
+                    (None, Some(_)) => true,
+                    (_, None) => false,
+                }
+            })
+    }
+
+    // Returns number of removed faces.
+    fn collapse(&mut self, from: u32, to: u32) -> usize {
+        let mut removed = 0;
+        let faces = std::mem::take(&mut self.triangles_of[from as usize]);
+        for face in faces {
+            if !self.alive[face] {
+                continue;
+            }
+            if self.triangles[face].contains(&to) {
+                self.alive[face] = false;
+                removed += 1;
+            } else {
+                for vertex in self.triangles[face].iter_mut() {
+                    if *vertex == from {
+                        *vertex = to;
+                    }
+                }
+                self.triangles_of[to as usize].push(face);
+            }
+        }
+
+        let quadric = self.quadrics[from as usize];
+        self.quadrics[to as usize].add(&quadric);
+        self.collapsed_to[from as usize] = Some(to);
+        self.versions[from as usize] += 1;
+        self.versions[to as usize] += 1;
+        self.triangles_of[to as usize].retain(|t| self.alive[*t]);
+
+        let mut neighbors = self.triangles_of[to as usize]
+            .iter()
+            .flat_map(|t| self.triangles[*t])
+            .filter(|v| *v != to)
+            .collect::<Vec<_>>();
+        neighbors.sort();
+        neighbors.dedup();
+        for neighbor in neighbors {
+            self.push(to, neighbor);
+            self.push(neighbor, to);
+        }
+
+        removed
+    }
+
+    fn run(&mut self, target: usize) {
+        let mut remaining = self.triangles.len();
+        while remaining > target {
+            let Some(candidate) = self.queue.pop() else {
+                break;
+            };
+            let (from, to) = (candidate.from as usize, candidate.to as usize);
+            if self.collapsed_to[from].is_some()
+                || self.collapsed_to[to].is_some()
+                || candidate.versions != (self.versions[from], self.versions[to])
+                || !self.is_collapsible(candidate.from, candidate.to)
+            {
+                continue;
+            }
+            remaining -= self.collapse(candidate.from, candidate.to);
+        }
+    }
+
+    fn resolve(&self, mut vertex: u32) -> u32 {
+        while let Some(next) = self.collapsed_to[vertex as usize] {
+            vertex = next;
+        }
+        vertex
+    }
+}
+
+// Reduces the number of faces to about `target_ratio` of the original by collapsing edges
+// with the lowest quadric error (Garland and Heckbert). Vertices are only moved onto
+// existing neighbors, so that edges and meshes of other colors stay attached.
+pub fn simplify(part: &Part, target_ratio: f32) -> Part {
+    let mut part = part.clone();
+    let geometry = &mut part.geometry;
+
+    let count = geometry.vertex_buffer.0.len() / 3;
+    let valid = geometry.meshes_mut().all(|mesh| {
+        mesh.is_valid()
+            && mesh.len().is_multiple_of(3)
+            && mesh.vertex_indices.iter().all(|v| (*v as usize) < count)
+    }) && geometry
+        .edges
+        .vertex_indices
+        .iter()
+        .all(|v| (*v as usize) < count)
+        && geometry
+            .optional_edges
+            .vertex_indices
+            .iter()
+            .all(|v| (*v as usize) < count);
+    if !valid || target_ratio >= 1.0 {
+        return part;
+    }
+
+    let positions = geometry
+        .vertex_buffer
+        .0
+        .chunks_exact(3)
+        .map(|v| Vector3::new(v[0], v[1], v[2]))
+        .collect::<Vec<_>>();
+
+    let mut meshes = geometry.meshes_mut().collect::<Vec<_>>();
+    let mut triangles = vec![];
+    let mut groups = vec![];
+    for (group, mesh) in meshes.iter().enumerate() {
+        for corners in mesh.vertex_indices.chunks_exact(3) {
+            triangles.push([corners[0], corners[1], corners[2]]);
+            groups.push(group);
+        }
+    }
+
+    let target = (triangles.len() as f32 * target_ratio.max(0.0)).ceil() as usize;
+    let mut simplifier = Simplifier::new(positions, triangles, &groups);
+    simplifier.run(target);
+
+    let mut face = 0;
+    for mesh in meshes.iter_mut() {
+        let mut simplified = MeshBuffer::default();
+        for normals in mesh.normal_indices.chunks_exact(3) {
+            if simplifier.alive[face] {
+                simplified.add_indices(simplifier.triangles[face].to_vec(), normals.to_vec());
+            }
+            face += 1;
+        }
+        **mesh = simplified;
+    }
+
+    // Drop edges that became degenerate, and update directions of moved optional edges.
+    let edges = &mut geometry.edges;
+    let (mut vertex_indices, mut colors) = (vec![], vec![]);
+    for (v, c) in edges
+        .vertex_indices
+        .chunks_exact(2)
+        .zip(edges.colors.chunks_exact(2))
+    {
+        let (a, b) = (simplifier.resolve(v[0]), simplifier.resolve(v[1]));
+        if a != b {
+            vertex_indices.extend([a, b]);
+            colors.extend(c);
+        }
+    }
+    edges.vertex_indices = vertex_indices;
+    edges.colors = colors;
+
+    let optional_edges = std::mem::take(&mut geometry.optional_edges);
+    for i in (0..optional_edges.len() / 2).map(|i| i * 2) {
+        let (a, b) = (
+            simplifier.resolve(optional_edges.vertex_indices[i]),
+            simplifier.resolve(optional_edges.vertex_indices[i + 1]),
+        );
+        if a == b {
+            continue;
+        }
+
+        let mut direction = optional_edges.direction_indices[i];
+        if a != optional_edges.vertex_indices[i] || b != optional_edges.vertex_indices[i + 1] {
+            let d = simplifier.positions[b as usize] - simplifier.positions[a as usize];
+            geometry.vertex_buffer.0.extend([d.x, d.y, d.z]);
+            direction = (geometry.vertex_buffer.0.len() / 3 - 1) as u32;
+        }
+
+        let target = &mut geometry.optional_edges;
+        target.vertex_indices.extend([a, b]);
+        target
+            .control_1_indices
+            .extend(&optional_edges.control_1_indices[i..i + 2]);
+        target
+            .control_2_indices
+            .extend(&optional_edges.control_2_indices[i..i + 2]);
+        target.direction_indices.extend([direction, direction]);
+        target.colors.extend(&optional_edges.colors[i..i + 2]);
+    }
+
+    // Removes vertices no longer referenced.
+    geometry.optimize();
+
+    part
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace;
+    use ldraw::{
+        color::ColorReference,
+        document::{Document, MultipartDocument},
+        elements::{Command, Quad},
+        library::ResolutionResult,
+        Vector3, Vector4,
+    };
+
+    use super::simplify;
+    use crate::part::{bake_part_from_multipart_document, Part};
+
+    fn triangles(part: &Part) -> Vec<[Vector3; 3]> {
+        let buffer = &part.geometry.vertex_buffer.0;
+        let position = |i: &u32| {
+            let i = *i as usize * 3;
+            Vector3::new(buffer[i], buffer[i + 1], buffer[i + 2])
+        };
+        [
+            &part.geometry.uncolored_mesh,
+            &part.geometry.uncolored_without_bfc_mesh,
+        ]
+        .iter()
+        .flat_map(|mesh| mesh.vertex_indices.chunks_exact(3))
+        .map(|v| [position(&v[0]), position(&v[1]), position(&v[2])])
+        .collect()
+    }
+
+    #[test]
+    fn test_simplify() {
+        let size = 8;
+        let v = |x: usize, z: usize| Vector4::new(x as f32, 0.0, z as f32, 1.0);
+        let mut commands = vec![];
+        for x in 0..size {
+            for z in 0..size {
+                commands.push(Command::Quad(Quad {
+                    color: ColorReference::Current,
+                    a: v(x, z),
+                    b: v(x + 1, z),
+                    c: v(x + 1, z + 1),
+                    d: v(x, z + 1),
+                }));
+            }
+        }
+        let document = MultipartDocument {
+            body: Document {
+                commands,
+                ..Default::default()
+            },
+            subparts: Default::default(),
+        };
+        let part = bake_part_from_multipart_document(&document, &ResolutionResult::new(), false);
+        assert_eq!(triangles(&part).len(), 128);
+
+        let simplified = simplify(&part, 0.25);
+        let faces = triangles(&simplified);
+        assert!(!faces.is_empty() && faces.len() <= 32, "{}", faces.len());
+
+        // The plane keeps its shape and outline.
+        let area: f32 = faces
+            .iter()
+            .map(|[a, b, c]| (b - a).cross(c - a).magnitude() * 0.5)
+            .sum();
+        assert!((area - 64.0).abs() < 0.001, "{}", area);
+        assert!(faces.iter().flatten().all(|v| v.y == 0.0));
+        for corner in [(0.0, 0.0), (8.0, 0.0), (8.0, 8.0), (0.0, 8.0)] {
+            assert!(faces
+                .iter()
+                .flatten()
+                .any(|v| v.x == corner.0 && v.z == corner.1));
+        }
+
+        assert_eq!(triangles(&simplify(&part, 1.0)).len(), 128);
+    }
+}
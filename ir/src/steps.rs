@@ -0,0 +1,1152 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap},
+    hash::Hash,
+    mem,
+};
+
+use cgmath::{InnerSpace, SquareMatrix};
+use ldraw::{
+    color::ColorReference,
+    document::{Document as LdrawDocument, MultipartDocument as LdrawMultipartDocument},
+    elements::{Command, Meta, PartReference},
+    Matrix4, PartAlias, Vector3,
+};
+use uuid::Uuid;
+
+use crate::{
+    geometry::BoundingBox3,
+    model::{matches_reference, GroupId, Model, Object, ObjectGroup, ObjectId, ObjectInstance},
+    part::PartDimensionQuerier,
+};
+
+// A part or a submodel along with the metas and annotations right in front of it.
+type PartWithMetas<P> = (Vec<Object<P>>, Object<P>);
+
+#[derive(Clone, Debug)]
+pub struct StepInferenceParams {
+    pub parts_per_step: usize,
+    // Objects whose bottoms are within this distance are put into the same layer.
+    pub layer_tolerance: f32,
+    // Objects whose bounding boxes are apart less than this are considered connected.
+    pub connection_margin: f32,
+}
+
+impl Default for StepInferenceParams {
+    fn default() -> Self {
+        Self {
+            parts_per_step: 8,
+            layer_tolerance: 4.0,
+            connection_margin: 1.0,
+        }
+    }
+}
+
+fn is_connected(a: &BoundingBox3, b: &BoundingBox3, margin: f32) -> bool {
+    a.min.x <= b.max.x + margin
+        && a.max.x + margin >= b.min.x
+        && a.min.y <= b.max.y + margin
+        && a.max.y + margin >= b.min.y
+        && a.min.z <= b.max.z + margin
+        && a.max.z + margin >= b.min.z
+}
+
+fn find_root(parents: &mut [usize], mut index: usize) -> usize {
+    while parents[index] != index {
+        parents[index] = parents[parents[index]];
+        index = parents[index];
+    }
+    index
+}
+
+fn cluster(boxes: &[&BoundingBox3], margin: f32) -> Vec<Vec<usize>> {
+    let mut parents = (0..boxes.len()).collect::<Vec<_>>();
+
+    for i in 0..boxes.len() {
+        for j in (i + 1)..boxes.len() {
+            if is_connected(boxes[i], boxes[j], margin) {
+                let a = find_root(&mut parents, i);
+                let b = find_root(&mut parents, j);
+                parents[a] = b;
+            }
+        }
+    }
+
+    let mut clusters: Vec<Vec<usize>> = Vec::new();
+    let mut roots = Vec::new();
+    for i in 0..boxes.len() {
+        let root = find_root(&mut parents, i);
+        match roots.iter().position(|v| *v == root) {
+            Some(position) => clusters[position].push(i),
+            None => {
+                roots.push(root);
+                clusters.push(vec![i]);
+            }
+        }
+    }
+
+    clusters
+}
+
+#[derive(Clone, Debug)]
+pub struct StepOptimizationParams {
+    // Maximum vertical gap between a part and the one resting on it.
+    pub support_tolerance: f32,
+    pub group_by_color: bool,
+}
+
+impl Default for StepOptimizationParams {
+    fn default() -> Self {
+        Self {
+            support_tolerance: 4.0,
+            group_by_color: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PartUsage<P> {
+    pub part: P,
+    pub color: ColorReference,
+    pub count: usize,
+}
+
+// Parts and submodels newly placed in a step, in order of their first appearance.
+#[derive(Clone, Debug)]
+pub struct StepCallout<P> {
+    pub parts: Vec<PartUsage<P>>,
+    pub groups: Vec<(GroupId, usize)>,
+}
+
+impl<P> Default for StepCallout<P> {
+    fn default() -> Self {
+        Self {
+            parts: Vec::new(),
+            groups: Vec::new(),
+        }
+    }
+}
+
+impl<P: PartialEq + Clone> StepCallout<P> {
+    fn add(&mut self, object: &Object<P>) {
+        match &object.data {
+            ObjectInstance::Part(p) => {
+                match self
+                    .parts
+                    .iter_mut()
+                    .find(|v| v.part == p.part && v.color == p.color)
+                {
+                    Some(usage) => usage.count += 1,
+                    None => self.parts.push(PartUsage {
+                        part: p.part.clone(),
+                        color: p.color.clone(),
+                        count: 1,
+                    }),
+                }
+            }
+            ObjectInstance::PartGroup(pg) => {
+                match self.groups.iter_mut().find(|(id, _)| *id == pg.group_id) {
+                    Some((_, count)) => *count += 1,
+                    None => self.groups.push((pg.group_id, 1)),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn total_parts(&self) -> usize {
+        self.parts.iter().map(|v| v.count).sum()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct InsertionParams {
+    // Distance between the part and the model at the staging position.
+    pub clearance: f32,
+    // Overlap smaller than this is not considered as a collision.
+    pub tolerance: f32,
+}
+
+impl Default for InsertionParams {
+    fn default() -> Self {
+        Self {
+            clearance: 40.0,
+            tolerance: 0.5,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PartInsertion {
+    pub object_id: ObjectId,
+    // Unit vector of the movement from the staging position to the final position.
+    pub direction: Vector3,
+    pub distance: f32,
+    pub staging_matrix: Matrix4,
+    pub matrix: Matrix4,
+    // Centers of the object at the staging and the final position, for placing arrows.
+    pub start: Vector3,
+    pub end: Vector3,
+    // No collision-free direction has been found, and the default one is used.
+    pub blocked: bool,
+}
+
+pub(crate) fn overlaps(a: &BoundingBox3, b: &BoundingBox3, tolerance: f32) -> bool {
+    a.min.x < b.max.x - tolerance
+        && a.max.x > b.min.x + tolerance
+        && a.min.y < b.max.y - tolerance
+        && a.max.y > b.min.y + tolerance
+        && a.min.z < b.max.z - tolerance
+        && a.max.z > b.min.z + tolerance
+}
+
+fn translate_bounding_box(bounding_box: &BoundingBox3, offset: Vector3) -> BoundingBox3 {
+    BoundingBox3::new(&(bounding_box.min + offset), &(bounding_box.max + offset))
+}
+
+// Space the object moves through before reaching its final position. The final position
+// itself is left out, as parts usually overlap ones they are attached to, e.g. by the height
+// of studs.
+fn approach(bounding_box: &BoundingBox3, direction: &Vector3, distance: f32) -> BoundingBox3 {
+    let mut swept = bounding_box.clone();
+    swept.update(&translate_bounding_box(
+        bounding_box,
+        -*direction * distance,
+    ));
+
+    let (x, y, z) = (direction.x.abs(), direction.y.abs(), direction.z.abs());
+    let (d, min, max, box_min, box_max) = if x >= y && x >= z {
+        (
+            direction.x,
+            &mut swept.min.x,
+            &mut swept.max.x,
+            bounding_box.min.x,
+            bounding_box.max.x,
+        )
+    } else if y >= z {
+        (
+            direction.y,
+            &mut swept.min.y,
+            &mut swept.max.y,
+            bounding_box.min.y,
+            bounding_box.max.y,
+        )
+    } else {
+        (
+            direction.z,
+            &mut swept.min.z,
+            &mut swept.max.z,
+            bounding_box.min.z,
+            bounding_box.max.z,
+        )
+    };
+    if d > 0.0 {
+        *max = box_min;
+    } else {
+        *min = box_max;
+    }
+    swept
+}
+
+// Candidate directions in the object's local space, starting from pushing down along +Y.
+fn insertion_directions(matrix: &Matrix4) -> Vec<Vector3> {
+    let axes = [
+        matrix.y.truncate(),
+        matrix.x.truncate(),
+        matrix.z.truncate(),
+    ];
+    axes.iter()
+        .filter(|v| v.magnitude2() > f32::EPSILON)
+        .flat_map(|v| {
+            let v = v.normalize();
+            [v, -v]
+        })
+        .collect()
+}
+
+fn supports(a: &BoundingBox3, b: &BoundingBox3, tolerance: f32) -> bool {
+    a.min.x < b.max.x
+        && a.max.x > b.min.x
+        && a.min.z < b.max.z
+        && a.max.z > b.min.z
+        && (b.max.y - a.min.y).abs() <= tolerance
+}
+
+// Objects each object rests on, found among objects whose tops are level with its bottom.
+fn support_edges(boxes: &[Option<BoundingBox3>], tolerance: f32) -> Vec<Vec<usize>> {
+    let mut tops = boxes
+        .iter()
+        .enumerate()
+        .filter_map(|(i, v)| v.as_ref().map(|v| (v.min.y, i)))
+        .collect::<Vec<_>>();
+    tops.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    boxes
+        .iter()
+        .enumerate()
+        .map(|(i, a)| {
+            let Some(a) = a else {
+                return Vec::new();
+            };
+            let start = tops.partition_point(|(y, _)| *y < a.max.y - tolerance);
+            tops[start..]
+                .iter()
+                .take_while(|(y, _)| *y <= a.max.y + tolerance)
+                .filter(|(_, j)| *j != i && supports(boxes[*j].as_ref().unwrap(), a, tolerance))
+                .map(|(_, j)| *j)
+                .collect()
+        })
+        .collect()
+}
+
+// Topological ordering that keeps the original order as far as possible, given items each
+// item depends on. Dependency cycles are broken by taking the earliest remaining item.
+fn order_items(dependencies: &[Vec<usize>], color: impl Fn(usize) -> Option<u32>) -> Vec<usize> {
+    let len = dependencies.len();
+    let mut pending = vec![0usize; len];
+    let mut dependents = vec![Vec::new(); len];
+    for (i, deps) in dependencies.iter().enumerate() {
+        let mut deps = deps.iter().filter(|j| **j != i).collect::<Vec<_>>();
+        deps.sort();
+        deps.dedup();
+        pending[i] = deps.len();
+        for j in deps {
+            dependents[*j].push(i);
+        }
+    }
+
+    let mut ready = BTreeSet::new();
+    let mut ready_by_color: HashMap<u32, BTreeSet<usize>> = HashMap::new();
+    let make_ready = |i: usize,
+                      ready: &mut BTreeSet<usize>,
+                      ready_by_color: &mut HashMap<u32, BTreeSet<usize>>| {
+        ready.insert(i);
+        if let Some(c) = color(i) {
+            ready_by_color.entry(c).or_default().insert(i);
+        }
+    };
+    for i in (0..len).filter(|i| pending[*i] == 0) {
+        make_ready(i, &mut ready, &mut ready_by_color);
+    }
+
+    let mut placed = vec![false; len];
+    let mut earliest = 0;
+    let mut result: Vec<usize> = Vec::with_capacity(len);
+    while result.len() < len {
+        let last_color = result.last().and_then(|v| color(*v));
+        let next = last_color
+            .and_then(|c| ready_by_color.get(&c))
+            .and_then(|v| v.first())
+            .or(ready.first())
+            .copied();
+        let next = match next {
+            Some(v) => v,
+            None => {
+                while placed[earliest] {
+                    earliest += 1;
+                }
+                earliest
+            }
+        };
+
+        ready.remove(&next);
+        if let Some(c) = color(next) {
+            if let Some(v) = ready_by_color.get_mut(&c) {
+                v.remove(&next);
+            }
+        }
+        placed[next] = true;
+        result.push(next);
+
+        for &i in dependents[next].iter() {
+            if placed[i] || pending[i] == 0 {
+                continue;
+            }
+            pending[i] -= 1;
+            if pending[i] == 0 {
+                make_ready(i, &mut ready, &mut ready_by_color);
+            }
+        }
+    }
+
+    result
+}
+
+fn object_color<P>(object: &Object<P>) -> Option<u32> {
+    match &object.data {
+        ObjectInstance::Part(p) => Some(p.color.code()),
+        ObjectInstance::PartGroup(pg) => Some(pg.color.code()),
+        _ => None,
+    }
+}
+
+// Rebuilds the order of part references and STEP metas in the document after the given
+// objects. Other commands stay attached to the part reference that follows them.
+fn write_steps_to_document<P: Into<PartAlias> + Clone>(
+    document: &mut LdrawDocument,
+    objects: &[Object<P>],
+    object_groups: &HashMap<GroupId, ObjectGroup<P>>,
+) {
+    let mut units: Vec<Option<(PartReference, Vec<Command>)>> = Vec::new();
+    let mut pending = Vec::new();
+    for command in mem::take(&mut document.commands) {
+        match command {
+            Command::PartReference(r) => units.push(Some((r, mem::take(&mut pending)))),
+            Command::Meta(Meta::Step) => {}
+            _ => pending.push(command),
+        }
+    }
+
+    for object in objects {
+        match object.data {
+            ObjectInstance::Step => document.commands.push(Command::Meta(Meta::Step)),
+            ObjectInstance::Part(_) | ObjectInstance::PartGroup(_) => {
+                let unit = units.iter_mut().find(|v| match v {
+                    Some((r, _)) => matches_reference(object, r, object_groups),
+                    None => false,
+                });
+                if let Some((reference, commands)) = unit.and_then(|v| v.take()) {
+                    document.commands.extend(commands);
+                    document.commands.push(Command::PartReference(reference));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (reference, commands) in units.into_iter().flatten() {
+        document.commands.extend(commands);
+        document.commands.push(Command::PartReference(reference));
+    }
+    document.commands.extend(pending);
+}
+
+fn compare_position(a: &BoundingBox3, b: &BoundingBox3) -> Ordering {
+    let (a, b) = (a.center(), b.center());
+    a.x.total_cmp(&b.x).then(a.z.total_cmp(&b.z))
+}
+
+impl<P: Clone + Eq + PartialEq + Hash + From<PartAlias>> Model<P> {
+    pub fn has_steps(&self, group_id: Option<GroupId>) -> bool {
+        self.get_objects(group_id)
+            .map(|mut objects| objects.any(|v| matches!(v.data, ObjectInstance::Step)))
+            .unwrap_or(false)
+    }
+
+    fn object_insertion(
+        object: &Object<P>,
+        bounding_box: &BoundingBox3,
+        placed: &[BoundingBox3],
+        params: &InsertionParams,
+    ) -> Option<PartInsertion> {
+        let matrix = match &object.data {
+            ObjectInstance::Part(p) => p.matrix,
+            ObjectInstance::PartGroup(pg) => pg.matrix,
+            _ => return None,
+        };
+
+        let directions = insertion_directions(&matrix);
+        let extent = |d: &Vector3| {
+            d.x.abs() * bounding_box.len_x()
+                + d.y.abs() * bounding_box.len_y()
+                + d.z.abs() * bounding_box.len_z()
+        };
+
+        let found = directions.iter().find(|d| {
+            let swept = approach(bounding_box, d, extent(d) + params.clearance);
+            !placed
+                .iter()
+                .any(|other| overlaps(&swept, other, params.tolerance))
+        });
+
+        let (direction, blocked) = match found {
+            Some(d) => (*d, false),
+            None => (*directions.first()?, true),
+        };
+        let distance = extent(&direction) + params.clearance;
+        let offset = -direction * distance;
+        let end = bounding_box.center();
+
+        Some(PartInsertion {
+            object_id: object.id,
+            direction,
+            distance,
+            staging_matrix: Matrix4::from_translation(offset) * matrix,
+            matrix,
+            start: end + offset,
+            end,
+            blocked,
+        })
+    }
+
+    // Insertion paths of objects for each step. Objects are moved along one of their local
+    // axes so that the path does not go through objects placed before.
+    pub fn step_insertions(
+        &self,
+        group_id: Option<GroupId>,
+        querier: &impl PartDimensionQuerier<P>,
+        params: &InsertionParams,
+    ) -> Vec<Vec<PartInsertion>> {
+        let mut result = vec![Vec::new()];
+        let mut trailing_step = false;
+        let mut placed = Vec::new();
+
+        if let Some(objects) = self.get_objects(group_id) {
+            for object in objects {
+                trailing_step = matches!(object.data, ObjectInstance::Step);
+                if trailing_step {
+                    result.push(Vec::new());
+                    continue;
+                }
+
+                if let Some(bounding_box) = self.object_bounding_box(object, querier) {
+                    if let Some(insertion) =
+                        Self::object_insertion(object, &bounding_box, &placed, params)
+                    {
+                        result.last_mut().unwrap().push(insertion);
+                    }
+                    placed.push(bounding_box);
+                }
+            }
+        }
+
+        if trailing_step {
+            result.pop();
+        }
+
+        result
+    }
+
+    pub fn step_callouts(&self, group_id: Option<GroupId>) -> Vec<StepCallout<P>> {
+        let mut result = vec![StepCallout::default()];
+        let mut trailing_step = false;
+
+        if let Some(objects) = self.get_objects(group_id) {
+            for object in objects {
+                trailing_step = matches!(object.data, ObjectInstance::Step);
+                if trailing_step {
+                    result.push(StepCallout::default());
+                } else {
+                    result.last_mut().unwrap().add(object);
+                }
+            }
+        }
+
+        if trailing_step {
+            result.pop();
+        }
+
+        result
+    }
+
+    fn object_bounding_box(
+        &self,
+        object: &Object<P>,
+        querier: &impl PartDimensionQuerier<P>,
+    ) -> Option<BoundingBox3> {
+        let mut bounding_box = BoundingBox3::nil();
+        self.calculate_bounding_box_recursive(
+            &mut bounding_box,
+            Matrix4::identity(),
+            std::slice::from_ref(object),
+            true,
+            querier,
+        );
+
+        if bounding_box.is_null() {
+            None
+        } else {
+            Some(bounding_box)
+        }
+    }
+
+    fn infer_steps_for_objects(
+        &self,
+        objects: Vec<Object<P>>,
+        querier: &impl PartDimensionQuerier<P>,
+        params: &StepInferenceParams,
+    ) -> Vec<Object<P>> {
+        // Metas and annotations travel with the part that follows them, so that colour and
+        // similar commands stay in front of the parts they apply to. The ones after the last
+        // part stay at the end.
+        let mut placeable = Vec::new();
+        let mut unplaceable = Vec::new();
+        let mut pending = Vec::new();
+
+        for object in objects {
+            match object.data {
+                ObjectInstance::Part(_) | ObjectInstance::PartGroup(_) => {
+                    let leading = mem::take(&mut pending);
+                    match self.object_bounding_box(&object, querier) {
+                        Some(bounding_box) => placeable.push(((leading, object), bounding_box)),
+                        None => unplaceable.push((leading, object)),
+                    }
+                }
+                ObjectInstance::Step => {}
+                _ => pending.push(object),
+            }
+        }
+
+        // -Y is up in LDraw, so objects are built from the largest Y.
+        placeable.sort_by(|a, b| b.1.max.y.total_cmp(&a.1.max.y));
+
+        let mut layers: Vec<Vec<(PartWithMetas<P>, BoundingBox3)>> = Vec::new();
+        for item in placeable {
+            match layers.last_mut() {
+                Some(layer) if layer[0].1.max.y - item.1.max.y <= params.layer_tolerance => {
+                    layer.push(item)
+                }
+                _ => layers.push(vec![item]),
+            }
+        }
+
+        let parts_per_step = params.parts_per_step.max(1);
+        let mut steps: Vec<Vec<PartWithMetas<P>>> = Vec::new();
+        for layer in layers {
+            let boxes = layer.iter().map(|(_, bb)| bb).collect::<Vec<_>>();
+            let mut clusters = cluster(&boxes, params.connection_margin);
+            for cluster in clusters.iter_mut() {
+                cluster.sort_by(|a, b| compare_position(boxes[*a], boxes[*b]));
+            }
+            clusters.sort_by(|a, b| compare_position(boxes[a[0]], boxes[b[0]]));
+
+            let mut slots = layer.into_iter().map(Some).collect::<Vec<_>>();
+            let mut current = Vec::new();
+            for cluster in clusters {
+                if !current.is_empty() && current.len() + cluster.len() > parts_per_step {
+                    steps.push(mem::take(&mut current));
+                }
+                for index in cluster {
+                    if let Some((object, _)) = slots[index].take() {
+                        current.push(object);
+                    }
+                    if current.len() >= parts_per_step {
+                        steps.push(mem::take(&mut current));
+                    }
+                }
+            }
+            if !current.is_empty() {
+                steps.push(current);
+            }
+        }
+
+        if !unplaceable.is_empty() {
+            steps.push(unplaceable);
+        }
+
+        let mut result = Vec::new();
+        let len = steps.len();
+        for (index, step) in steps.into_iter().enumerate() {
+            for (leading, object) in step {
+                result.extend(leading);
+                result.push(object);
+            }
+            if index + 1 < len {
+                result.push(Object {
+                    id: Uuid::new_v4().into(),
+                    data: ObjectInstance::Step,
+                });
+            }
+        }
+
+        result.extend(pending);
+
+        result
+    }
+
+    pub fn infer_steps(
+        &mut self,
+        querier: &impl PartDimensionQuerier<P>,
+        params: &StepInferenceParams,
+    ) {
+        let group_ids = self
+            .object_groups
+            .keys()
+            .filter(|id| !self.has_steps(Some(**id)))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for group_id in group_ids {
+            if let Some(group) = self.object_groups.get_mut(&group_id) {
+                let objects = mem::take(&mut group.objects);
+                let objects = self.infer_steps_for_objects(objects, querier, params);
+                if let Some(group) = self.object_groups.get_mut(&group_id) {
+                    group.objects = objects;
+                }
+            }
+        }
+
+        if !self.has_steps(None) {
+            let objects = mem::take(&mut self.objects);
+            self.objects = self.infer_steps_for_objects(objects, querier, params);
+        }
+    }
+
+    fn optimize_steps_for_objects(
+        &self,
+        objects: Vec<Object<P>>,
+        querier: &impl PartDimensionQuerier<P>,
+        params: &StepOptimizationParams,
+    ) -> Vec<Object<P>> {
+        let trailing_step = matches!(objects.last().map(|v| &v.data), Some(ObjectInstance::Step));
+
+        let mut steps: Vec<Vec<(Object<P>, Option<BoundingBox3>)>> = vec![Vec::new()];
+        for object in objects {
+            match object.data {
+                ObjectInstance::Step => steps.push(Vec::new()),
+                _ => {
+                    let bounding_box = self.object_bounding_box(&object, querier);
+                    steps.last_mut().unwrap().push((object, bounding_box));
+                }
+            }
+        }
+        if trailing_step {
+            steps.pop();
+        }
+
+        let mut step_of = Vec::new();
+        let mut index_in_step = Vec::new();
+        let mut boxes = Vec::new();
+        for (i, step) in steps.iter().enumerate() {
+            for (j, (_, bounding_box)) in step.iter().enumerate() {
+                step_of.push(i);
+                index_in_step.push(j);
+                boxes.push(bounding_box.clone());
+            }
+        }
+        let edges = support_edges(&boxes, params.support_tolerance);
+
+        let mut step_dependencies = vec![Vec::new(); steps.len()];
+        let mut object_dependencies = steps
+            .iter()
+            .map(|v| vec![Vec::new(); v.len()])
+            .collect::<Vec<_>>();
+        for (a, supporters) in edges.iter().enumerate() {
+            for &b in supporters {
+                if step_of[a] == step_of[b] {
+                    object_dependencies[step_of[a]][index_in_step[a]].push(index_in_step[b]);
+                } else {
+                    step_dependencies[step_of[a]].push(step_of[b]);
+                }
+            }
+        }
+
+        let step_order = order_items(&step_dependencies, |_| None);
+
+        let mut slots = steps.into_iter().map(Some).collect::<Vec<_>>();
+        let len = slots.len();
+        let mut result = Vec::new();
+        for (index, step_index) in step_order.into_iter().enumerate() {
+            let step = slots[step_index].take().unwrap();
+            let object_order = order_items(&object_dependencies[step_index], |i| {
+                if params.group_by_color {
+                    object_color(&step[i].0)
+                } else {
+                    None
+                }
+            });
+
+            let mut objects = step.into_iter().map(|(v, _)| Some(v)).collect::<Vec<_>>();
+            result.extend(object_order.into_iter().filter_map(|i| objects[i].take()));
+            if index + 1 < len || trailing_step {
+                result.push(Object {
+                    id: Uuid::new_v4().into(),
+                    data: ObjectInstance::Step,
+                });
+            }
+        }
+
+        result
+    }
+
+    // Reorders steps and objects within steps so that supporting parts are placed before the
+    // parts resting on them. Objects never move between steps and submodels are kept as is.
+    pub fn optimize_steps(
+        &mut self,
+        querier: &impl PartDimensionQuerier<P>,
+        params: &StepOptimizationParams,
+    ) {
+        let group_ids = self.object_groups.keys().cloned().collect::<Vec<_>>();
+        for group_id in group_ids {
+            if let Some(group) = self.object_groups.get_mut(&group_id) {
+                let objects = mem::take(&mut group.objects);
+                let objects = self.optimize_steps_for_objects(objects, querier, params);
+                if let Some(group) = self.object_groups.get_mut(&group_id) {
+                    group.objects = objects;
+                }
+            }
+        }
+
+        let objects = mem::take(&mut self.objects);
+        self.objects = self.optimize_steps_for_objects(objects, querier, params);
+    }
+}
+
+impl<P: Clone + Eq + PartialEq + Hash + Into<PartAlias>> Model<P> {
+    // Writes step structure of the model back into the document it has been built from.
+    pub fn write_steps(&self, document: &mut LdrawMultipartDocument) {
+        for (group_id, group) in self.object_groups.iter() {
+            if let Some(subpart) = self.get_ldraw_document_mut(document, Some(*group_id)) {
+                write_steps_to_document(subpart, &group.objects, &self.object_groups);
+            }
+        }
+
+        write_steps_to_document(&mut document.body, &self.objects, &self.object_groups);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ldraw::{
+        color::ColorReference,
+        document::MultipartDocument,
+        elements::{Command, Meta},
+        Matrix4, PartAlias, Vector3,
+    };
+
+    use cgmath::SquareMatrix;
+    use uuid::Uuid;
+
+    use super::{
+        order_items, support_edges, translate_bounding_box, InsertionParams, PartInsertion,
+        StepCallout, StepInferenceParams,
+    };
+    use crate::{
+        geometry::BoundingBox3,
+        model::{Annotation, GroupId, Model, Object, ObjectId, ObjectInstance, PartInstance},
+        testing::{document, reference, Cubes},
+    };
+
+    const CURRENT: ColorReference = ColorReference::Current;
+
+    fn names(commands: &[Command]) -> Vec<String> {
+        commands
+            .iter()
+            .map(|v| match v {
+                Command::PartReference(r) => r.name.normalized.clone(),
+                Command::Meta(Meta::Step) => "STEP".to_string(),
+                _ => String::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_write_steps_to_subpart_named_differently() {
+        let mut subparts = HashMap::new();
+        subparts.insert(
+            PartAlias::from("wall.ldr"),
+            document(
+                "Wall section",
+                vec![
+                    reference("3001.dat", CURRENT, 0.0),
+                    Command::Meta(Meta::Step),
+                    reference("3002.dat", CURRENT, 20.0),
+                ],
+            ),
+        );
+        let mut document = MultipartDocument {
+            body: document(
+                "main.ldr",
+                vec![
+                    reference("wall.ldr", CURRENT, 0.0),
+                    Command::Meta(Meta::Step),
+                    reference("3003.dat", CURRENT, 0.0),
+                ],
+            ),
+            subparts,
+        };
+        let mut model = Model::<PartAlias>::from_ldraw_multipart_document_sync(&document);
+        let group = model.object_groups.values_mut().next().unwrap();
+        group.objects.reverse();
+
+        model.write_steps(&mut document);
+
+        assert_eq!(
+            names(&document.subparts[&PartAlias::from("wall.ldr")].commands),
+            vec!["3002.dat", "STEP", "3001.dat"]
+        );
+        assert_eq!(
+            names(&document.body.commands),
+            vec!["wall.ldr", "STEP", "3003.dat"]
+        );
+    }
+
+    fn group_id(model: &Model<PartAlias>, alias: &str) -> GroupId {
+        model
+            .object_groups
+            .values()
+            .find(|v| v.alias() == PartAlias::from(alias))
+            .unwrap()
+            .id
+    }
+
+    fn parts(callout: &StepCallout<PartAlias>) -> Vec<(String, u32, usize)> {
+        callout
+            .parts
+            .iter()
+            .map(|v| (v.part.normalized.clone(), v.color.code(), v.count))
+            .collect()
+    }
+
+    #[test]
+    fn test_step_callouts_of_nested_submodels() {
+        let mut subparts = HashMap::new();
+        subparts.insert(
+            PartAlias::from("wheel.ldr"),
+            document(
+                "Wheel",
+                vec![
+                    reference("3001.dat", CURRENT, 0.0),
+                    reference("3001.dat", CURRENT, 20.0),
+                    reference("3002.dat", CURRENT, 0.0),
+                ],
+            ),
+        );
+        subparts.insert(
+            PartAlias::from("axle.ldr"),
+            document(
+                "Axle",
+                vec![
+                    reference("wheel.ldr", CURRENT, 0.0),
+                    reference("wheel.ldr", CURRENT, 40.0),
+                    Command::Meta(Meta::Step),
+                    reference("3003.dat", CURRENT, 0.0),
+                ],
+            ),
+        );
+        let document = MultipartDocument {
+            body: document(
+                "main.ldr",
+                vec![
+                    reference("axle.ldr", CURRENT, 0.0),
+                    Command::Meta(Meta::Step),
+                    reference("3004.dat", ColorReference::Unknown(4), 0.0),
+                    reference("3004.dat", CURRENT, 20.0),
+                ],
+            ),
+            subparts,
+        };
+        let model = Model::<PartAlias>::from_ldraw_multipart_document_sync(&document);
+        let axle = group_id(&model, "axle.ldr");
+        let wheel = group_id(&model, "wheel.ldr");
+
+        // Submodels are called out as a whole rather than by their parts.
+        let callouts = model.step_callouts(None);
+        assert_eq!(callouts.len(), 2);
+        assert_eq!(callouts[0].groups, vec![(axle, 1)]);
+        assert!(callouts[0].parts.is_empty());
+        assert!(callouts[1].groups.is_empty());
+        assert_eq!(
+            parts(&callouts[1]),
+            vec![
+                ("3004.dat".to_string(), 4, 1),
+                ("3004.dat".to_string(), 16, 1)
+            ]
+        );
+
+        let callouts = model.step_callouts(Some(axle));
+        assert_eq!(callouts.len(), 2);
+        assert_eq!(callouts[0].groups, vec![(wheel, 2)]);
+        assert_eq!(callouts[0].total_parts(), 0);
+        assert_eq!(parts(&callouts[1]), vec![("3003.dat".to_string(), 16, 1)]);
+
+        let callouts = model.step_callouts(Some(wheel));
+        assert_eq!(callouts.len(), 1);
+        assert_eq!(
+            parts(&callouts[0]),
+            vec![
+                ("3001.dat".to_string(), 16, 2),
+                ("3002.dat".to_string(), 16, 1)
+            ]
+        );
+        assert_eq!(callouts[0].total_parts(), 3);
+    }
+
+    #[test]
+    fn test_step_callouts_of_repeated_submodels() {
+        let mut subparts = HashMap::new();
+        subparts.insert(
+            PartAlias::from("wheel.ldr"),
+            document("Wheel", vec![reference("3001.dat", CURRENT, 0.0)]),
+        );
+        let document = MultipartDocument {
+            body: document(
+                "main.ldr",
+                vec![
+                    reference("wheel.ldr", CURRENT, 0.0),
+                    reference("wheel.ldr", CURRENT, 20.0),
+                    Command::Meta(Meta::Step),
+                    reference("3001.dat", CURRENT, 0.0),
+                    reference("wheel.ldr", CURRENT, 40.0),
+                    Command::Meta(Meta::Step),
+                ],
+            ),
+            subparts,
+        };
+        let model = Model::<PartAlias>::from_ldraw_multipart_document_sync(&document);
+        let wheel = group_id(&model, "wheel.ldr");
+
+        // Each step counts the instances placed in it, and the trailing step adds none.
+        let callouts = model.step_callouts(None);
+        assert_eq!(callouts.len(), 2);
+        assert_eq!(callouts[0].groups, vec![(wheel, 2)]);
+        assert!(callouts[0].parts.is_empty());
+        assert_eq!(callouts[1].groups, vec![(wheel, 1)]);
+        assert_eq!(parts(&callouts[1]), vec![("3001.dat".to_string(), 16, 1)]);
+    }
+
+    fn brick(y: f32) -> BoundingBox3 {
+        BoundingBox3::new(
+            &Vector3::new(-20.0, y, -10.0),
+            &Vector3::new(20.0, y + 28.0, 10.0),
+        )
+    }
+
+    fn insertion(placed: &[BoundingBox3]) -> PartInsertion {
+        let object = Object {
+            id: ObjectId::from(Uuid::new_v4()),
+            data: ObjectInstance::Part(PartInstance {
+                matrix: Matrix4::identity(),
+                color: CURRENT,
+                part: PartAlias::from("3001.dat"),
+            }),
+        };
+        Model::object_insertion(&object, &brick(-24.0), placed, &InsertionParams::default())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_insertion_onto_stacked_brick() {
+        let insertion = insertion(&[brick(0.0)]);
+        assert!(!insertion.blocked);
+        assert_eq!(insertion.direction, Vector3::new(0.0, 1.0, 0.0));
+        assert!(insertion.start.y < insertion.end.y);
+    }
+
+    #[test]
+    fn test_insertion_sideways_under_brick() {
+        let insertion = insertion(&[brick(0.0), brick(-48.0)]);
+        assert!(!insertion.blocked);
+        assert_eq!(insertion.direction.y, 0.0);
+    }
+
+    #[test]
+    fn test_blocked_insertion() {
+        let offsets = [
+            Vector3::new(0.0, -48.0, 0.0),
+            Vector3::new(0.0, 48.0, 0.0),
+            Vector3::new(-40.0, 0.0, 0.0),
+            Vector3::new(40.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, -20.0),
+            Vector3::new(0.0, 0.0, 20.0),
+        ];
+        let placed = offsets
+            .iter()
+            .map(|v| translate_bounding_box(&brick(-24.0), *v))
+            .collect::<Vec<_>>();
+
+        let insertion = insertion(&placed);
+        assert!(insertion.blocked);
+        assert_eq!(insertion.direction, Vector3::new(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn test_order_items() {
+        assert_eq!(
+            order_items(&[vec![1], vec![2], vec![]], |_| None),
+            vec![2, 1, 0]
+        );
+        assert_eq!(
+            order_items(&[vec![1], vec![0], vec![]], |_| None),
+            vec![2, 0, 1]
+        );
+
+        let colors = [1, 2, 1, 2];
+        assert_eq!(
+            order_items(&[vec![], vec![], vec![], vec![]], |i| Some(colors[i])),
+            vec![0, 2, 1, 3]
+        );
+    }
+
+    #[test]
+    fn test_support_edges() {
+        let boxes = [
+            Some(brick(-24.0)),
+            None,
+            Some(brick(4.0)),
+            Some(brick(-52.0)),
+        ];
+        assert_eq!(
+            support_edges(&boxes, 0.5),
+            vec![vec![2], vec![], vec![], vec![0]]
+        );
+    }
+
+    fn part_at(y: f32) -> Object<PartAlias> {
+        Object {
+            id: ObjectId::from(Uuid::new_v4()),
+            data: ObjectInstance::Part(PartInstance {
+                matrix: Matrix4::from_translation(Vector3::new(0.0, y, 0.0)),
+                color: CURRENT,
+                part: PartAlias::from("3001.dat"),
+            }),
+        }
+    }
+
+    fn annotation(body: &str) -> Object<PartAlias> {
+        Object {
+            id: ObjectId::from(Uuid::new_v4()),
+            data: ObjectInstance::Annotation(Annotation {
+                position: Vector3::new(0.0, 0.0, 0.0),
+                body: body.to_string(),
+            }),
+        }
+    }
+
+    fn labels(objects: &[Object<PartAlias>]) -> Vec<String> {
+        objects
+            .iter()
+            .map(|v| match &v.data {
+                ObjectInstance::Part(p) => format!("{}", p.matrix.w.y),
+                ObjectInstance::Annotation(a) => a.body.clone(),
+                ObjectInstance::Step => "STEP".to_string(),
+                ObjectInstance::PartGroup(_) => String::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_infer_steps_keeps_annotations_with_parts() {
+        let mut model = Model::<PartAlias> {
+            objects: vec![
+                annotation("top"),
+                part_at(-24.0),
+                annotation("bottom"),
+                part_at(0.0),
+                annotation("end"),
+            ],
+            ..Default::default()
+        };
+
+        model.infer_steps(&Cubes(10.0), &StepInferenceParams::default());
+
+        assert_eq!(
+            labels(&model.objects),
+            vec!["bottom", "0", "STEP", "top", "-24", "end"]
+        );
+    }
+
+    #[test]
+    fn test_infer_steps_without_parts() {
+        let mut model = Model::<PartAlias> {
+            objects: vec![annotation("a"), annotation("b")],
+            ..Default::default()
+        };
+
+        model.infer_steps(&Cubes(10.0), &StepInferenceParams::default());
+
+        assert_eq!(labels(&model.objects), vec!["a", "b"]);
+    }
+}
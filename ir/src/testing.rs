@@ -0,0 +1,36 @@
+use ldraw::{
+    color::ColorReference,
+    document::Document,
+    elements::{Command, PartReference},
+    Matrix4, PartAlias, Vector3,
+};
+
+use crate::{geometry::BoundingBox3, part::PartDimensionQuerier};
+
+pub fn reference(name: &str, color: ColorReference, x: f32) -> Command {
+    Command::PartReference(PartReference {
+        color,
+        matrix: Matrix4::from_translation(Vector3::new(x, 0.0, 0.0)),
+        name: PartAlias::from(name),
+    })
+}
+
+pub fn document(name: &str, commands: Vec<Command>) -> Document {
+    Document {
+        name: name.to_string(),
+        commands,
+        ..Default::default()
+    }
+}
+
+// Takes every part as a cube of the given half size around its origin.
+pub struct Cubes(pub f32);
+
+impl PartDimensionQuerier<PartAlias> for Cubes {
+    fn query_part_dimension(&self, _alias: &PartAlias) -> Option<BoundingBox3> {
+        Some(BoundingBox3::new(
+            &Vector3::new(-self.0, -self.0, -self.0),
+            &Vector3::new(self.0, self.0, self.0),
+        ))
+    }
+}
@@ -0,0 +1,9 @@
+#![no_main]
+
+use futures::executor::block_on;
+use ldraw::parser::parse_color_definitions;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = block_on(parse_color_definitions(&mut &data[..]));
+});
@@ -0,0 +1,23 @@
+#![no_main]
+
+use futures::executor::block_on;
+use ldraw::{
+    color::ColorCatalog,
+    parser::{parse_multipart_document, parse_multipart_document_lenient, DocumentStream},
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let colors = ColorCatalog::new();
+    let _ = block_on(parse_multipart_document(&mut &data[..], &colors));
+    if let Ok((document, _)) = block_on(parse_multipart_document_lenient(&mut &data[..], &colors)) {
+        for document in std::iter::once(&document.body).chain(document.subparts.values()) {
+            let _ = document.metadata();
+            let _ = document.camera();
+        }
+    }
+
+    let mut reader = data;
+    let mut stream = DocumentStream::new(&mut reader, &colors);
+    while block_on(stream.next()).is_some() {}
+});
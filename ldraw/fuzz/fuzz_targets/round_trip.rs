@@ -0,0 +1,24 @@
+#![no_main]
+
+use futures::executor::block_on;
+use ldraw::{color::ColorCatalog, parser::parse_multipart_document, writer::LDrawWriter};
+use libfuzzer_sys::fuzz_target;
+
+// Whatever parses must still parse after being written out. Documents are not compared, as
+// some inputs are legitimately normalized on the way, e.g. whitespace in comments.
+fuzz_target!(|data: &[u8]| {
+    let colors = ColorCatalog::new();
+    let Ok(document) = block_on(parse_multipart_document(&mut &data[..], &colors)) else {
+        return;
+    };
+
+    let mut written = Vec::new();
+    block_on(document.write(&mut written)).unwrap();
+    if let Err(e) = block_on(parse_multipart_document(&mut &written[..], &colors)) {
+        panic!(
+            "written document does not parse: {}\n{}",
+            e,
+            String::from_utf8_lossy(&written)
+        );
+    }
+});
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use cgmath::SquareMatrix;
+
+use crate::{
+    color::ColorReference,
+    document::{Document, MultipartDocument},
+    elements::{Command, Meta, PartReference},
+    error::EditError,
+    Matrix4, PartAlias,
+};
+
+// Colors are kept as codes until the document is resolved with resolve_colors().
+fn color_reference(code: u32) -> ColorReference {
+    match code {
+        16 => ColorReference::Current,
+        24 => ColorReference::Complement,
+        _ => ColorReference::Unresolved(code),
+    }
+}
+
+// Assembles a document in code, for generating models procedurally.
+//
+//   let document = ModelBuilder::new("wall.ldr")
+//       .author("Someone")
+//       .group("row.ldr", |row| {
+//           row.add_part("3001.dat", 4, Matrix4::identity());
+//       })
+//       .begin_step()
+//       .add_part("row.ldr", 16, Matrix4::from_translation(Vector3::new(0.0, -24.0, 0.0)))
+//       .build()?;
+pub struct ModelBuilder {
+    // Documents being built, the innermost group being the last.
+    stack: Vec<Document>,
+    subparts: HashMap<PartAlias, Document>,
+    error: Option<EditError>,
+}
+
+impl ModelBuilder {
+    pub fn new(name: &str) -> Self {
+        ModelBuilder {
+            stack: vec![Document {
+                name: name.to_string(),
+                description: name.to_string(),
+                ..Default::default()
+            }],
+            subparts: HashMap::new(),
+            error: None,
+        }
+    }
+
+    fn current(&mut self) -> &mut Document {
+        self.stack.last_mut().unwrap()
+    }
+
+    // Applies to the document being built, which is a group inside group().
+    pub fn description(&mut self, description: &str) -> &mut Self {
+        self.current().description = description.to_string();
+        self
+    }
+
+    pub fn author(&mut self, author: &str) -> &mut Self {
+        self.current().author = author.to_string();
+        self
+    }
+
+    pub fn add_command(&mut self, command: Command) -> &mut Self {
+        self.current().commands.push(command);
+        self
+    }
+
+    // Places a part, or a group defined with group(), with the given color code.
+    pub fn add_part(
+        &mut self,
+        alias: impl Into<PartAlias>,
+        color: u32,
+        matrix: Matrix4,
+    ) -> &mut Self {
+        self.add_command(Command::PartReference(PartReference {
+            color: color_reference(color),
+            matrix,
+            name: alias.into(),
+        }))
+    }
+
+    pub fn comment(&mut self, text: &str) -> &mut Self {
+        self.add_command(Command::Meta(Meta::Comment(text.to_string())))
+    }
+
+    // Ends the step being built, unless nothing has been added to it.
+    pub fn begin_step(&mut self) -> &mut Self {
+        let commands = &self.current().commands;
+        if !matches!(commands.last(), None | Some(Command::Meta(Meta::Step))) {
+            self.add_command(Command::Meta(Meta::Step));
+        }
+        self
+    }
+
+    // Defines a submodel with whatever build adds to it, and places it where the builder is
+    // with the current color. Further copies can be placed with add_part().
+    pub fn group<F: FnOnce(&mut ModelBuilder)>(&mut self, name: &str, build: F) -> &mut Self {
+        self.define(name, build);
+        self.add_part(name, 16, Matrix4::identity())
+    }
+
+    // As group(), without placing the submodel.
+    pub fn define<F: FnOnce(&mut ModelBuilder)>(&mut self, name: &str, build: F) -> &mut Self {
+        self.stack.push(Document {
+            name: name.to_string(),
+            description: name.to_string(),
+            ..Default::default()
+        });
+        build(self);
+        let document = self.stack.pop().unwrap();
+
+        let alias = PartAlias::from(name);
+        if self.subparts.contains_key(&alias) || self.stack[0].name == name {
+            self.error
+                .get_or_insert(EditError::SubpartAlreadyExists(alias));
+        } else {
+            self.subparts.insert(alias, document);
+        }
+        self
+    }
+
+    // Fails if a name has been defined more than once.
+    pub fn build(&mut self) -> Result<MultipartDocument, EditError> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        Ok(MultipartDocument {
+            body: self.stack[0].clone(),
+            subparts: self.subparts.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use crate::{
+        color::ColorCatalog, parser::parse_multipart_document, writer::LDrawWriter, Vector3,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_build_document() {
+        let document = ModelBuilder::new("wall.ldr")
+            .author("Someone")
+            .group("row.ldr", |row| {
+                row.description("Row of bricks");
+                for i in 0..3 {
+                    row.add_part(
+                        "3001.dat",
+                        4,
+                        Matrix4::from_translation(Vector3::new(i as f32 * 80.0, 0.0, 0.0)),
+                    );
+                }
+            })
+            .begin_step()
+            .begin_step()
+            .add_part(
+                "row.ldr",
+                1,
+                Matrix4::from_translation(Vector3::new(0.0, -24.0, 0.0)),
+            )
+            .build()
+            .unwrap();
+
+        assert_eq!(document.body.author, "Someone");
+        assert_eq!(document.body.commands.len(), 3);
+        assert_eq!(document.body.step_ranges().len(), 2);
+        let row = document.get_subpart(&PartAlias::from("row.ldr")).unwrap();
+        assert_eq!(row.description, "Row of bricks");
+        assert_eq!(row.commands.len(), 3);
+        match &document.body.commands[0] {
+            Command::PartReference(r) => {
+                assert!(r.color.is_current());
+                assert_eq!(r.matrix, Matrix4::identity());
+            }
+            _ => panic!("Expected a part reference"),
+        }
+
+        let mut written = Vec::new();
+        block_on(document.write(&mut written)).unwrap();
+        let parsed = block_on(parse_multipart_document(
+            &mut &written[..],
+            &ColorCatalog::new(),
+        ))
+        .unwrap();
+        assert_eq!(parsed, document);
+    }
+
+    #[test]
+    fn test_duplicate_group() {
+        let result = ModelBuilder::new("model.ldr")
+            .define("sub.ldr", |_| {})
+            .define("sub.ldr", |_| {})
+            .build();
+        assert!(matches!(result, Err(EditError::SubpartAlreadyExists(_))));
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
 
 use serde::de::Deserializer;
@@ -7,11 +7,33 @@ use serde::{Deserialize, Serialize};
 
 use crate::Vector4;
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Rgba {
     value: [u8; 4],
 }
 
+fn unit_to_u8(v: f32) -> u8 {
+    (v.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+// Takes and returns a channel between 0 and 1.
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(v: f32) -> u8 {
+    let v = v.clamp(0.0, 1.0);
+    unit_to_u8(if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    })
+}
+
 impl Rgba {
     pub fn new(r: u8, g: u8, b: u8, a: u8) -> Rgba {
         Rgba {
@@ -44,6 +66,99 @@ impl Rgba {
     pub fn alpha(self) -> u8 {
         self.value[3]
     }
+
+    // Channels in linear light, between 0 and 1. Alpha is left as it is.
+    pub fn to_linear(self) -> Vector4 {
+        Vector4::new(
+            srgb_to_linear(f32::from(self.red()) / 255.0),
+            srgb_to_linear(f32::from(self.green()) / 255.0),
+            srgb_to_linear(f32::from(self.blue()) / 255.0),
+            f32::from(self.alpha()) / 255.0,
+        )
+    }
+
+    pub fn from_linear(linear: Vector4) -> Rgba {
+        Rgba::new(
+            linear_to_srgb(linear.x),
+            linear_to_srgb(linear.y),
+            linear_to_srgb(linear.z),
+            unit_to_u8(linear.w),
+        )
+    }
+
+    // Relative luminance as defined by Rec. 709, between 0 and 1.
+    pub fn luminance(self) -> f32 {
+        let linear = self.to_linear();
+        0.2126 * linear.x + 0.7152 * linear.y + 0.0722 * linear.z
+    }
+
+    // Moves towards other by t, 0 being self and 1 being other. Alpha of self is kept.
+    pub fn blend(self, other: Rgba, t: f32) -> Rgba {
+        let mix =
+            |a: u8, b: u8| unit_to_u8((f32::from(a) + (f32::from(b) - f32::from(a)) * t) / 255.0);
+        Rgba::new(
+            mix(self.red(), other.red()),
+            mix(self.green(), other.green()),
+            mix(self.blue(), other.blue()),
+            self.alpha(),
+        )
+    }
+
+    // Hue in degrees, saturation and lightness between 0 and 1.
+    pub fn to_hsl(self) -> (f32, f32, f32) {
+        let r = f32::from(self.red()) / 255.0;
+        let g = f32::from(self.green()) / 255.0;
+        let b = f32::from(self.blue()) / 255.0;
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let chroma = max - min;
+        let lightness = (max + min) / 2.0;
+
+        if chroma == 0.0 {
+            return (0.0, 0.0, lightness);
+        }
+        let hue = if max == r {
+            ((g - b) / chroma).rem_euclid(6.0)
+        } else if max == g {
+            (b - r) / chroma + 2.0
+        } else {
+            (r - g) / chroma + 4.0
+        };
+        let saturation = chroma / (1.0 - (2.0 * lightness - 1.0).abs());
+
+        (hue * 60.0, saturation, lightness)
+    }
+
+    pub fn from_hsl(hue: f32, saturation: f32, lightness: f32, alpha: u8) -> Rgba {
+        let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+        let hue = hue.rem_euclid(360.0) / 60.0;
+        let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+        let (r, g, b) = match hue as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let m = lightness - chroma / 2.0;
+        Rgba::new(
+            unit_to_u8(r + m),
+            unit_to_u8(g + m),
+            unit_to_u8(b + m),
+            alpha,
+        )
+    }
+
+    // Edge color for a color defined without one, such as direct colors. LDConfig.ldr edges
+    // most colors in dark gray, and the darkest ones in a lighter gray to stay visible.
+    pub fn contrasting_edge(self) -> Rgba {
+        if self.luminance() < 0.02 {
+            Rgba::new(0x59, 0x59, 0x59, 255)
+        } else {
+            Rgba::new(0x33, 0x33, 0x33, 255)
+        }
+    }
 }
 
 impl From<&Rgba> for Vector4 {
@@ -68,7 +183,7 @@ impl From<Rgba> for Vector4 {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct MaterialGlitter {
     pub value: Rgba,
     pub luminance: u8,
@@ -79,7 +194,7 @@ pub struct MaterialGlitter {
     pub maxsize: f32,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct MaterialSpeckle {
     pub value: Rgba,
     pub luminance: u8,
@@ -89,13 +204,17 @@ pub struct MaterialSpeckle {
     pub maxsize: f32,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum CustomizedMaterial {
     Glitter(MaterialGlitter),
     Speckle(MaterialSpeckle),
+    // Cloth such as sails and capes, with the kind of fabric if given, e.g. CANVAS.
+    Fabric(Option<String>),
+    // Materials introduced after this parser was written, kept as written.
+    Other { kind: String, parameters: String },
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Material {
     Plastic,
     Chrome,
@@ -106,7 +225,7 @@ pub enum Material {
     Custom(CustomizedMaterial),
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Color {
     pub code: u32,
     pub name: String,
@@ -114,6 +233,8 @@ pub struct Color {
     pub edge: Rgba,
     pub luminance: u8,
     pub material: Material,
+    // Attributes not understood by the parser, keyed by name. Flags have empty values.
+    pub attributes: HashMap<String, String>,
 }
 
 impl Default for Color {
@@ -125,6 +246,7 @@ impl Default for Color {
             edge: Rgba::new(0x59, 0x59, 0x59, 0xff),
             luminance: 0x00,
             material: Material::Plastic,
+            attributes: HashMap::new(),
         }
     }
 }
@@ -133,6 +255,24 @@ impl Color {
     pub fn is_translucent(&self) -> bool {
         self.color.alpha() < 255u8
     }
+
+    // Moves both the color and its edge towards other, keeping their alpha.
+    pub fn blend(&self, other: Rgba, t: f32) -> Color {
+        Color {
+            color: self.color.blend(other, t),
+            edge: self.edge.blend(other, t),
+            ..self.clone()
+        }
+    }
+
+    // What lines in the complement color (code 24) are drawn with under this color.
+    pub fn complement(&self) -> Color {
+        Color {
+            color: self.edge,
+            edge: self.color,
+            ..self.clone()
+        }
+    }
 }
 
 pub type ColorCatalog = HashMap<u32, Color>;
@@ -194,14 +334,8 @@ impl ColorReference {
         let code1 = code / 16;
         let code2 = code % 16;
 
-        let color1 = match colors.get(&code1) {
-            Some(c) => c,
-            None => return None,
-        };
-        let color2 = match colors.get(&code2) {
-            Some(c) => c,
-            None => return None,
-        };
+        let color1 = colors.get(&code1)?;
+        let color2 = colors.get(&code2)?;
 
         let new_color = Rgba::new(
             color1.color.red() / 2 + color2.color.red() / 2,
@@ -216,6 +350,7 @@ impl ColorReference {
             edge: Rgba::from_value(0xff59_5959),
             luminance: 0,
             material: Material::Plastic,
+            attributes: HashMap::new(),
         })
     }
 
@@ -235,6 +370,7 @@ impl ColorReference {
             edge: Rgba::new(edge_red, edge_green, edge_blue, 255),
             luminance: 0,
             material: Material::Plastic,
+            attributes: HashMap::new(),
         }
     }
 
@@ -246,6 +382,7 @@ impl ColorReference {
             edge: Rgba::from_value(0xff59_5959),
             luminance: 0,
             material: Material::Plastic,
+            attributes: HashMap::new(),
         }
     }
 
@@ -340,3 +477,330 @@ impl From<u32> for ColorCode {
         Self(value)
     }
 }
+
+// Color in CIELAB under D65, for comparing colors as they are perceived.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lab {
+    pub l: f64,
+    pub a: f64,
+    pub b: f64,
+}
+
+impl From<Rgba> for Lab {
+    fn from(rgba: Rgba) -> Lab {
+        let linear = rgba.to_linear();
+        let (r, g, b) = (
+            f64::from(linear.x),
+            f64::from(linear.y),
+            f64::from(linear.z),
+        );
+
+        // Relative to the D65 white point.
+        let x = (0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / 0.95047;
+        let y = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+        let z = (0.0193339 * r + 0.1191920 * g + 0.9503041 * b) / 1.08883;
+
+        let f = |t: f64| {
+            const DELTA: f64 = 6.0 / 29.0;
+            if t > DELTA.powi(3) {
+                t.cbrt()
+            } else {
+                t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+            }
+        };
+        let (fx, fy, fz) = (f(x), f(y), f(z));
+
+        Lab {
+            l: 116.0 * fy - 16.0,
+            a: 500.0 * (fx - fy),
+            b: 200.0 * (fy - fz),
+        }
+    }
+}
+
+impl Lab {
+    // CIEDE2000 color difference, where differences below 1 are hardly noticeable.
+    pub fn distance(&self, other: &Lab) -> f64 {
+        let pow7 = |v: f64| v.powi(7);
+        let c1 = self.a.hypot(self.b);
+        let c2 = other.a.hypot(other.b);
+        let c_mean = (c1 + c2) / 2.0;
+        let g = 0.5 * (1.0 - (pow7(c_mean) / (pow7(c_mean) + pow7(25.0))).sqrt());
+
+        let a1 = (1.0 + g) * self.a;
+        let a2 = (1.0 + g) * other.a;
+        let c1 = a1.hypot(self.b);
+        let c2 = a2.hypot(other.b);
+        let hue = |b: f64, a: f64| {
+            if a == 0.0 && b == 0.0 {
+                0.0
+            } else {
+                b.atan2(a).to_degrees().rem_euclid(360.0)
+            }
+        };
+        let h1 = hue(self.b, a1);
+        let h2 = hue(other.b, a2);
+
+        let delta_l = other.l - self.l;
+        let delta_c = c2 - c1;
+        let delta_h = if c1 * c2 == 0.0 {
+            0.0
+        } else if (h2 - h1).abs() <= 180.0 {
+            h2 - h1
+        } else if h2 - h1 > 180.0 {
+            h2 - h1 - 360.0
+        } else {
+            h2 - h1 + 360.0
+        };
+        let delta_h = 2.0 * (c1 * c2).sqrt() * (delta_h / 2.0).to_radians().sin();
+
+        let l_mean = (self.l + other.l) / 2.0;
+        let c_mean = (c1 + c2) / 2.0;
+        let h_mean = if c1 * c2 == 0.0 {
+            h1 + h2
+        } else if (h1 - h2).abs() <= 180.0 {
+            (h1 + h2) / 2.0
+        } else if h1 + h2 < 360.0 {
+            (h1 + h2 + 360.0) / 2.0
+        } else {
+            (h1 + h2 - 360.0) / 2.0
+        };
+
+        let cos = |degrees: f64| degrees.to_radians().cos();
+        let t = 1.0 - 0.17 * cos(h_mean - 30.0)
+            + 0.24 * cos(2.0 * h_mean)
+            + 0.32 * cos(3.0 * h_mean + 6.0)
+            - 0.20 * cos(4.0 * h_mean - 63.0);
+        let delta_theta = 30.0 * (-((h_mean - 275.0) / 25.0).powi(2)).exp();
+        let r_c = 2.0 * (pow7(c_mean) / (pow7(c_mean) + pow7(25.0))).sqrt();
+        let s_l = 1.0 + 0.015 * (l_mean - 50.0).powi(2) / (20.0 + (l_mean - 50.0).powi(2)).sqrt();
+        let s_c = 1.0 + 0.045 * c_mean;
+        let s_h = 1.0 + 0.015 * c_mean * t;
+        let r_t = -(2.0 * delta_theta).to_radians().sin() * r_c;
+
+        let (l, c, h) = (delta_l / s_l, delta_c / s_c, delta_h / s_h);
+        (l * l + c * c + h * h + r_t * c * h).sqrt()
+    }
+}
+
+// Colors retired in 2004 in favor of reddish brown and bluish grays.
+const DISCONTINUED_COLORS: &[u32] = &[6, 7, 8];
+
+// Kinds of colors to pick from when matching colors.
+#[derive(Clone, Debug)]
+pub struct ColorFilter {
+    pub translucent: bool,
+    // Chrome, metallic, pearlescent and metal colors.
+    pub metallic: bool,
+    // Glowing colors, and ones with glitter, speckles, rubber or fabric.
+    pub special: bool,
+    pub discontinued: bool,
+    pub exclude: HashSet<u32>,
+}
+
+impl Default for ColorFilter {
+    fn default() -> Self {
+        ColorFilter {
+            translucent: true,
+            metallic: true,
+            special: true,
+            discontinued: true,
+            exclude: HashSet::new(),
+        }
+    }
+}
+
+impl ColorFilter {
+    // Opaque plastic colors in production, as for mosaics.
+    pub fn solid() -> Self {
+        ColorFilter {
+            translucent: false,
+            metallic: false,
+            special: false,
+            discontinued: false,
+            exclude: HashSet::new(),
+        }
+    }
+
+    pub fn accepts(&self, color: &Color) -> bool {
+        if self.exclude.contains(&color.code)
+            || (!self.translucent && color.is_translucent())
+            || (!self.discontinued && DISCONTINUED_COLORS.contains(&color.code))
+        {
+            return false;
+        }
+
+        match color.material {
+            Material::Plastic => self.special || color.luminance == 0,
+            Material::Chrome
+            | Material::Pearlescent
+            | Material::MatteMetallic
+            | Material::Metal => self.metallic,
+            Material::Rubber | Material::Custom(_) => self.special,
+        }
+    }
+}
+
+pub trait ColorMatching {
+    // Color looking closest to rgb among ones the filter accepts. Alpha is not compared.
+    fn nearest(&self, rgb: Rgba, filter: &ColorFilter) -> Option<&Color>;
+}
+
+impl ColorMatching for ColorCatalog {
+    fn nearest(&self, rgb: Rgba, filter: &ColorFilter) -> Option<&Color> {
+        let target = Lab::from(rgb);
+        self.values()
+            .filter(|v| filter.accepts(v))
+            .map(|v| (target.distance(&Lab::from(v.color)), v))
+            // Ties go to the lowest code, regardless of the order of the catalog.
+            .min_by(|(a, c), (b, d)| a.total_cmp(b).then(c.code.cmp(&d.code)))
+            .map(|(_, v)| v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color(code: u32, rgba: u32, material: Material) -> Color {
+        Color {
+            code,
+            name: format!("Color {}", code),
+            color: Rgba::from_value(rgba),
+            material,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_ciede2000() {
+        // From the test data of Sharma, Wu and Dalal.
+        let pairs = [
+            ((50.0, 2.6772, -79.7751), (50.0, 0.0, -82.7485), 2.0425),
+            ((50.0, 2.5, 0.0), (73.0, 25.0, -18.0), 27.1492),
+            ((50.0, 2.5, 0.0), (50.0, 0.0, -2.5), 4.3065),
+            (
+                (60.2574, -34.0099, 36.2677),
+                (60.4626, -34.1751, 39.4387),
+                1.2644,
+            ),
+            (
+                (22.7233, 20.0904, -46.694),
+                (23.0331, 14.973, -42.5619),
+                2.0373,
+            ),
+        ];
+        for ((l1, a1, b1), (l2, a2, b2), expected) in pairs {
+            let x = Lab {
+                l: l1,
+                a: a1,
+                b: b1,
+            };
+            let y = Lab {
+                l: l2,
+                a: a2,
+                b: b2,
+            };
+            assert!((x.distance(&y) - expected).abs() < 1e-4);
+            assert!((y.distance(&x) - expected).abs() < 1e-4);
+        }
+
+        let white = Lab::from(Rgba::new(255, 255, 255, 255));
+        assert!((white.l - 100.0).abs() < 1e-3);
+        assert!(white.a.abs() < 1e-3 && white.b.abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_nearest() {
+        let catalog = [
+            color(1, 0xff0055bf, Material::Plastic),
+            color(4, 0xffc91a09, Material::Plastic),
+            color(7, 0xff9ba19d, Material::Plastic),
+            color(36, 0x80c91a09, Material::Plastic),
+            color(71, 0xffa0a5a9, Material::Plastic),
+            color(383, 0xffe0e0e0, Material::Chrome),
+        ]
+        .into_iter()
+        .map(|v| (v.code, v))
+        .collect::<ColorCatalog>();
+
+        let red = Rgba::new(0xd0, 0x20, 0x10, 0xff);
+        assert_eq!(
+            catalog.nearest(red, &ColorFilter::default()).unwrap().code,
+            4
+        );
+        let gray = Rgba::new(0x9b, 0xa1, 0x9d, 0xff);
+        assert_eq!(
+            catalog.nearest(gray, &ColorFilter::default()).unwrap().code,
+            7
+        );
+        assert_eq!(
+            catalog.nearest(gray, &ColorFilter::solid()).unwrap().code,
+            71
+        );
+        let silver = Rgba::new(0xe0, 0xe0, 0xe0, 0xff);
+        assert_eq!(
+            catalog.nearest(silver, &ColorFilter::solid()).unwrap().code,
+            71
+        );
+
+        let filter = ColorFilter {
+            exclude: HashSet::from([4]),
+            ..ColorFilter::default()
+        };
+        assert_eq!(catalog.nearest(red, &filter).unwrap().code, 36);
+        assert!(ColorCatalog::new()
+            .nearest(red, &ColorFilter::default())
+            .is_none());
+    }
+
+    #[test]
+    fn test_linear_round_trip() {
+        for value in [0xff00_0000, 0xff12_3456, 0x80c8_6420, 0xffff_ffff] {
+            let rgba = Rgba::from_value(value);
+            assert_eq!(Rgba::from_linear(rgba.to_linear()), rgba);
+        }
+        assert!((Rgba::from_value(0xff80_8080).to_linear().x - 0.2158).abs() < 1e-3);
+        assert_eq!(Rgba::from_value(0xffff_ffff).luminance(), 1.0);
+        assert_eq!(Rgba::from_value(0xff00_0000).luminance(), 0.0);
+    }
+
+    #[test]
+    fn test_hsl() {
+        assert_eq!(Rgba::from_value(0xffff_0000).to_hsl(), (0.0, 1.0, 0.5));
+        assert_eq!(Rgba::from_value(0xff80_8080).to_hsl().1, 0.0);
+        for value in [0xff12_3456, 0xffc8_6420, 0xff05_ff80, 0xffff_00c0] {
+            let rgba = Rgba::from_value(value);
+            let (h, s, l) = rgba.to_hsl();
+            assert_eq!(Rgba::from_hsl(h, s, l, 255), rgba);
+        }
+    }
+
+    #[test]
+    fn test_blend() {
+        let black = Rgba::from_value(0x8000_0000);
+        let white = Rgba::from_value(0xffff_ffff);
+        assert_eq!(black.blend(white, 0.0), black);
+        assert_eq!(black.blend(white, 0.5), Rgba::new(128, 128, 128, 128));
+        assert_eq!(black.blend(white, 1.0), Rgba::new(255, 255, 255, 128));
+
+        let blue = color(1, 0xff00_55bf, Material::Plastic);
+        let complement = blue.complement();
+        assert_eq!(complement.color, blue.edge);
+        assert_eq!(complement.edge, blue.color);
+        assert_eq!(black.contrasting_edge(), Rgba::from_value(0xff59_5959));
+        assert_eq!(white.contrasting_edge(), Rgba::from_value(0xff33_3333));
+    }
+
+    #[test]
+    fn test_dithered() {
+        let mut colors = ColorCatalog::new();
+        colors.insert(17, color(17, 0xff00_55bf, Material::Plastic));
+        colors.insert(15, color(15, 0xffff_ffff, Material::Plastic));
+
+        match ColorReference::resolve(287, &colors) {
+            ColorReference::Color(c) => assert_eq!(c.color, Rgba::new(127, 169, 222, 255)),
+            _ => panic!("dithered color is not resolved"),
+        }
+    }
+}
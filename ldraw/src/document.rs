@@ -1,11 +1,20 @@
 use std::{
     collections::{HashMap, HashSet},
     iter::Iterator,
+    mem,
+    ops::Range,
     vec::Vec,
 };
 
 use crate::{
-    elements::{Command, Header, Line, Meta, OptionalLine, PartReference, Quad, Triangle},
+    color::{ColorCatalog, ColorReference},
+    elements::{
+        Camera, Command, Header, HistoryEntry, LDrawOrg, Line, Meta, OptionalLine, PartMetadata,
+        PartReference, Quad, Triangle, CATEGORY_HEADER, KEYWORDS_HEADER, LICENSE_HEADER,
+    },
+    error::EditError,
+    graph::{DependencyGraph, DependencyNode},
+    parser::{parse_camera, parse_history, parse_ldraw_org},
     PartAlias, Winding,
 };
 
@@ -56,23 +65,44 @@ impl Default for Document {
     }
 }
 
-fn traverse_dependencies(
-    document: &Document,
-    parent: Option<&MultipartDocument>,
-    list: &mut HashSet<PartAlias>,
-) {
-    for part_ref in document.iter_refs() {
-        if let Some(parent) = parent {
-            if parent.subparts.contains_key(&part_ref.name) {
-                traverse_dependencies(
-                    parent.subparts.get(&part_ref.name).unwrap(),
-                    Some(parent),
-                    list,
-                );
-                continue;
-            }
+fn string_size(value: &str) -> usize {
+    mem::size_of::<String>() + value.len()
+}
+
+fn color_size(color: &ColorReference) -> usize {
+    match color {
+        // Resolved colors carry a copy of the definition.
+        ColorReference::Color(color) => {
+            string_size(&color.name)
+                + color
+                    .attributes
+                    .iter()
+                    .map(|(k, v)| string_size(k) + string_size(v))
+                    .sum::<usize>()
+        }
+        _ => 0,
+    }
+}
+
+// Heap memory owned by a command, on top of its own size.
+fn command_heap_size(command: &Command) -> usize {
+    match command {
+        Command::Meta(Meta::Comment(v) | Meta::Write(v) | Meta::Print(v)) => v.len(),
+        Command::Meta(Meta::LdCad(v)) => {
+            v.kind.len()
+                + v.parameters
+                    .iter()
+                    .map(|(k, v)| string_size(k) + string_size(v))
+                    .sum::<usize>()
         }
-        list.insert(part_ref.name.clone());
+        Command::Meta(_) => 0,
+        Command::PartReference(v) => {
+            v.name.normalized.len() + v.name.original.len() + color_size(&v.color)
+        }
+        Command::Line(v) => color_size(&v.color),
+        Command::Triangle(v) => color_size(&v.color),
+        Command::Quad(v) => color_size(&v.color),
+        Command::OptionalLine(v) => color_size(&v.color),
     }
 }
 
@@ -93,13 +123,208 @@ impl Document {
         false
     }
 
+    fn header(&self, key: &str) -> Option<&str> {
+        self.headers.iter().find(|v| v.0 == key).map(|v| v.1.trim())
+    }
+
+    // Without !CATEGORY, the category is the first word of the description, ignoring prefixes
+    // marking moved, aliased or obsolete parts.
+    pub fn category(&self) -> String {
+        match self.header(CATEGORY_HEADER) {
+            Some(v) => v.to_string(),
+            None => self
+                .description
+                .trim_start_matches(['~', '_', '=', '|'])
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        }
+    }
+
+    pub fn keywords(&self) -> Vec<String> {
+        self.headers
+            .iter()
+            .filter(|v| v.0 == KEYWORDS_HEADER)
+            .flat_map(|v| v.1.split(','))
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect()
+    }
+
+    pub fn ldraw_org(&self) -> Option<LDrawOrg> {
+        self.headers
+            .iter()
+            .find_map(|header| parse_ldraw_org(header).ok().flatten())
+    }
+
+    pub fn license(&self) -> Option<&str> {
+        self.header(LICENSE_HEADER)
+    }
+
+    // Malformed history lines are ignored.
+    pub fn history(&self) -> Vec<HistoryEntry> {
+        self.headers
+            .iter()
+            .filter_map(|header| parse_history(header).ok().flatten())
+            .collect()
+    }
+
+    // Whether the file was released as part of the official library.
+    pub fn is_official(&self) -> bool {
+        matches!(
+            self.ldraw_org(),
+            Some(v) if !v.is_unofficial() && v.release.is_some()
+        )
+    }
+
+    pub fn is_redistributable(&self) -> bool {
+        self.license()
+            .is_some_and(|v| !v.to_ascii_lowercase().starts_with("not redistributable"))
+    }
+
+    pub fn metadata(&self) -> PartMetadata {
+        PartMetadata {
+            description: self.description.clone(),
+            category: self.category(),
+            keywords: self.keywords(),
+            ldraw_org: self.ldraw_org(),
+            license: self.license().map(String::from),
+            history: self.history(),
+        }
+    }
+
+    // Malformed camera headers are ignored.
+    pub fn camera(&self) -> Option<Camera> {
+        self.headers
+            .iter()
+            .find_map(|header| parse_camera(header).ok().flatten())
+    }
+
+    pub fn set_camera(&mut self, camera: Option<Camera>) {
+        let existing = self
+            .headers
+            .iter()
+            .position(|header| matches!(parse_camera(header), Ok(Some(_))));
+        match (existing, camera) {
+            (Some(index), Some(camera)) => self.headers[index] = camera.to_header(),
+            (Some(index), None) => {
+                self.headers.remove(index);
+            }
+            (None, Some(camera)) => self.headers.push(camera.to_header()),
+            (None, None) => (),
+        }
+    }
+
     pub fn list_dependencies(&self) -> HashSet<PartAlias> {
-        let mut result = HashSet::new();
+        self.iter_refs().map(|v| v.name.clone()).collect()
+    }
 
-        traverse_dependencies(self, None, &mut result);
+    pub fn insert_command(&mut self, index: usize, command: Command) -> Result<(), EditError> {
+        if index > self.commands.len() {
+            return Err(EditError::CommandOutOfRange(index));
+        }
+        self.commands.insert(index, command);
+        Ok(())
+    }
 
+    pub fn remove_command(&mut self, index: usize) -> Result<Command, EditError> {
+        if index >= self.commands.len() {
+            return Err(EditError::CommandOutOfRange(index));
+        }
+        Ok(self.commands.remove(index))
+    }
+
+    pub fn replace_command(
+        &mut self,
+        index: usize,
+        command: Command,
+    ) -> Result<Command, EditError> {
+        match self.commands.get_mut(index) {
+            Some(v) => Ok(mem::replace(v, command)),
+            None => Err(EditError::CommandOutOfRange(index)),
+        }
+    }
+
+    // Ranges of commands in each step, excluding STEP metas.
+    // Looks up colors left as codes, as in documents put together with a ModelBuilder.
+    pub fn resolve_colors(&mut self, colors: &ColorCatalog) {
+        for command in self.commands.iter_mut() {
+            match command {
+                Command::PartReference(v) => v.color.resolve_self(colors),
+                Command::Line(v) => v.color.resolve_self(colors),
+                Command::Triangle(v) => v.color.resolve_self(colors),
+                Command::Quad(v) => v.color.resolve_self(colors),
+                Command::OptionalLine(v) => v.color.resolve_self(colors),
+                Command::Meta(_) => {}
+            }
+        }
+    }
+
+    pub fn step_ranges(&self) -> Vec<Range<usize>> {
+        let mut result = Vec::new();
+        let mut start = 0;
+        for (index, command) in self.commands.iter().enumerate() {
+            if let Command::Meta(Meta::Step) = command {
+                result.push(start..index);
+                start = index + 1;
+            }
+        }
+        if start < self.commands.len() || result.is_empty() {
+            result.push(start..self.commands.len());
+        }
         result
     }
+
+    // Approximate memory taken by the document in bytes, for keeping caches within a budget.
+    pub fn estimated_size(&self) -> usize {
+        mem::size_of::<Self>()
+            + self.name.len()
+            + self.description.len()
+            + self.author.len()
+            + self
+                .headers
+                .iter()
+                .map(|v| string_size(&v.0) + string_size(&v.1))
+                .sum::<usize>()
+            + self.commands.capacity() * mem::size_of::<Command>()
+            + self.commands.iter().map(command_heap_size).sum::<usize>()
+    }
+
+    pub fn move_step(&mut self, from: usize, to: usize) -> Result<(), EditError> {
+        let ranges = self.step_ranges();
+        if from >= ranges.len() {
+            return Err(EditError::StepOutOfRange(from));
+        }
+        if to >= ranges.len() {
+            return Err(EditError::StepOutOfRange(to));
+        }
+        if from == to {
+            return Ok(());
+        }
+
+        let trailing_step = matches!(self.commands.last(), Some(Command::Meta(Meta::Step)));
+        let mut commands = mem::take(&mut self.commands);
+        let mut steps = ranges
+            .into_iter()
+            .rev()
+            .map(|range| commands.drain(range).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        steps.reverse();
+
+        let step = steps.remove(from);
+        steps.insert(to, step);
+
+        let len = steps.len();
+        for (index, step) in steps.into_iter().enumerate() {
+            self.commands.extend(step);
+            if index + 1 < len || trailing_step {
+                self.commands.push(Command::Meta(Meta::Step));
+            }
+        }
+
+        Ok(())
+    }
 }
 
 macro_rules! define_iterator(
@@ -155,11 +380,210 @@ impl MultipartDocument {
         self.subparts.get(alias)
     }
 
+    pub fn get_subpart_mut(&mut self, alias: &PartAlias) -> Option<&mut Document> {
+        self.subparts.get_mut(alias)
+    }
+
+    pub fn add_subpart(&mut self, alias: PartAlias, document: Document) -> Result<(), EditError> {
+        if self.subparts.contains_key(&alias) {
+            return Err(EditError::SubpartAlreadyExists(alias));
+        }
+        self.subparts.insert(alias, document);
+        Ok(())
+    }
+
+    pub fn remove_subpart(&mut self, alias: &PartAlias) -> Result<Document, EditError> {
+        self.subparts
+            .remove(alias)
+            .ok_or_else(|| EditError::SubpartNotFound(alias.clone()))
+    }
+
+    pub fn resolve_colors(&mut self, colors: &ColorCatalog) {
+        self.body.resolve_colors(colors);
+        for subpart in self.subparts.values_mut() {
+            subpart.resolve_colors(colors);
+        }
+    }
+
+    // Files outside the document used by the body, directly or through subparts.
     pub fn list_dependencies(&self) -> HashSet<PartAlias> {
-        let mut result = HashSet::new();
+        self.dependency_graph().external_dependencies()
+    }
 
-        traverse_dependencies(&self.body, Some(self), &mut result);
+    pub fn dependency_graph(&self) -> DependencyGraph {
+        DependencyGraph::new(self)
+    }
 
-        result
+    // Compares the document with an earlier version of it. Files referring to changed
+    // subparts, directly or through other subparts, count as changed as well.
+    pub fn changes_from(&self, previous: &MultipartDocument) -> DocumentChanges {
+        let modified = self
+            .subparts
+            .iter()
+            .filter(|(alias, subpart)| previous.subparts.get(alias) != Some(*subpart))
+            .map(|(alias, _)| alias)
+            .chain(
+                previous
+                    .subparts
+                    .keys()
+                    .filter(|alias| !self.subparts.contains_key(alias)),
+            );
+
+        // Removed subparts are external files to the new document.
+        let graph = self.dependency_graph();
+        let mut pending = modified
+            .flat_map(|alias| {
+                [
+                    DependencyNode::Subpart(alias.clone()),
+                    DependencyNode::External(alias.clone()),
+                ]
+            })
+            .filter(|node| graph.contains(node))
+            .collect::<Vec<_>>();
+        let mut changes = DocumentChanges {
+            body: self.body != previous.body,
+            subparts: HashSet::new(),
+        };
+        while let Some(node) = pending.pop() {
+            match &node {
+                DependencyNode::Body => changes.body = true,
+                DependencyNode::Subpart(alias) | DependencyNode::External(alias) => {
+                    if !changes.subparts.insert(alias.clone()) {
+                        continue;
+                    }
+                }
+            }
+            pending.extend(graph.dependents(&node).into_iter().cloned());
+        }
+        changes.subparts.extend(
+            previous
+                .subparts
+                .keys()
+                .filter(|alias| !self.subparts.contains_key(alias))
+                .cloned(),
+        );
+
+        changes
+    }
+
+    pub fn estimated_size(&self) -> usize {
+        self.body.estimated_size()
+            + self
+                .subparts
+                .iter()
+                .map(|(k, v)| k.normalized.len() + k.original.len() + v.estimated_size())
+                .sum::<usize>()
+    }
+}
+
+// Files that differ between two versions of a document.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DocumentChanges {
+    pub body: bool,
+    // Subparts added, removed or modified, and ones depending on them.
+    pub subparts: HashSet<PartAlias>,
+}
+
+impl DocumentChanges {
+    pub fn is_empty(&self) -> bool {
+        !self.body && self.subparts.is_empty()
+    }
+}
+
+// Line numbers in the input, starting from 1, of the commands of each file of a document as
+// parsed. Lines with a file's description, name, author, BFC certification or headers are not
+// commands, and are not recorded.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SourceLines {
+    pub body: Vec<usize>,
+    pub subparts: HashMap<PartAlias, Vec<usize>>,
+}
+
+impl SourceLines {
+    // Lines of the body when no subpart is given.
+    pub fn get(&self, subpart: Option<&PartAlias>) -> Option<&[usize]> {
+        match subpart {
+            Some(alias) => self.subparts.get(alias).map(Vec::as_slice),
+            None => Some(&self.body),
+        }
+    }
+
+    pub fn line(&self, subpart: Option<&PartAlias>, command: usize) -> Option<usize> {
+        self.get(subpart)?.get(command).copied()
+    }
+
+    // Index of the command on the given line of the file.
+    pub fn command(&self, subpart: Option<&PartAlias>, line: usize) -> Option<usize> {
+        self.get(subpart)?.binary_search(&line).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{color::ColorCatalog, parser::parse_multipart_document, PartAlias};
+
+    use super::MultipartDocument;
+
+    async fn parse(text: &str) -> MultipartDocument {
+        parse_multipart_document(&mut text.as_bytes(), &ColorCatalog::new())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_document_changes() {
+        let previous = parse(
+            "0 FILE main.ldr\n\
+             0 Main\n\
+             1 16 0 0 0 1 0 0 0 1 0 0 0 1 a.ldr\n\
+             1 16 0 0 0 1 0 0 0 1 0 0 0 1 c.ldr\n\
+             0 FILE a.ldr\n\
+             0 A\n\
+             1 16 0 0 0 1 0 0 0 1 0 0 0 1 b.ldr\n\
+             0 FILE b.ldr\n\
+             0 B\n\
+             1 16 0 0 0 1 0 0 0 1 0 0 0 1 3001.dat\n\
+             0 FILE c.ldr\n\
+             0 C\n\
+             1 16 0 0 0 1 0 0 0 1 0 0 0 1 3002.dat\n",
+        )
+        .await;
+        assert!(previous.changes_from(&previous).is_empty());
+
+        let document = parse(
+            "0 FILE main.ldr\n\
+             0 Main\n\
+             1 16 0 0 0 1 0 0 0 1 0 0 0 1 a.ldr\n\
+             1 16 0 0 0 1 0 0 0 1 0 0 0 1 c.ldr\n\
+             0 FILE a.ldr\n\
+             0 A\n\
+             1 16 0 0 0 1 0 0 0 1 0 0 0 1 b.ldr\n\
+             0 FILE b.ldr\n\
+             0 B\n\
+             1 4 0 0 0 1 0 0 0 1 0 0 0 1 3001.dat\n\
+             0 FILE c.ldr\n\
+             0 C\n\
+             1 16 0 0 0 1 0 0 0 1 0 0 0 1 3002.dat\n",
+        )
+        .await;
+        let changes = document.changes_from(&previous);
+        assert!(changes.body);
+        assert_eq!(
+            changes.subparts,
+            ["a.ldr", "b.ldr"]
+                .into_iter()
+                .map(PartAlias::from)
+                .collect()
+        );
+
+        // Dropping c.ldr leaves the body referring to a file outside the document.
+        let mut document = previous.clone();
+        document.subparts.remove(&PartAlias::from("c.ldr"));
+        let changes = document.changes_from(&previous);
+        assert!(changes.body);
+        assert_eq!(
+            changes.subparts,
+            [PartAlias::from("c.ldr")].into_iter().collect()
+        );
     }
 }
@@ -0,0 +1,333 @@
+use crate::{
+    document::{Document, MultipartDocument},
+    elements::Command,
+    error::EditError,
+    PartAlias,
+};
+
+// Every edit is applied to a document in a MultipartDocument, where `None` denotes the body.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Edit {
+    InsertCommand {
+        subpart: Option<PartAlias>,
+        index: usize,
+        command: Command,
+    },
+    RemoveCommand {
+        subpart: Option<PartAlias>,
+        index: usize,
+    },
+    ReplaceCommand {
+        subpart: Option<PartAlias>,
+        index: usize,
+        command: Command,
+    },
+    MoveStep {
+        subpart: Option<PartAlias>,
+        from: usize,
+        to: usize,
+    },
+    AddSubpart {
+        alias: PartAlias,
+        document: Document,
+    },
+    RemoveSubpart {
+        alias: PartAlias,
+    },
+    Batch(Vec<Edit>),
+}
+
+fn get_document<'a>(
+    document: &'a mut MultipartDocument,
+    subpart: &Option<PartAlias>,
+) -> Result<&'a mut Document, EditError> {
+    match subpart {
+        Some(alias) => document
+            .get_subpart_mut(alias)
+            .ok_or_else(|| EditError::SubpartNotFound(alias.clone())),
+        None => Ok(&mut document.body),
+    }
+}
+
+impl Edit {
+    // Applies the edit and returns another edit that reverts it.
+    pub fn apply(self, document: &mut MultipartDocument) -> Result<Edit, EditError> {
+        match self {
+            Edit::InsertCommand {
+                subpart,
+                index,
+                command,
+            } => {
+                get_document(document, &subpart)?.insert_command(index, command)?;
+                Ok(Edit::RemoveCommand { subpart, index })
+            }
+            Edit::RemoveCommand { subpart, index } => {
+                let command = get_document(document, &subpart)?.remove_command(index)?;
+                Ok(Edit::InsertCommand {
+                    subpart,
+                    index,
+                    command,
+                })
+            }
+            Edit::ReplaceCommand {
+                subpart,
+                index,
+                command,
+            } => {
+                let command = get_document(document, &subpart)?.replace_command(index, command)?;
+                Ok(Edit::ReplaceCommand {
+                    subpart,
+                    index,
+                    command,
+                })
+            }
+            Edit::MoveStep { subpart, from, to } => {
+                get_document(document, &subpart)?.move_step(from, to)?;
+                Ok(Edit::MoveStep {
+                    subpart,
+                    from: to,
+                    to: from,
+                })
+            }
+            Edit::AddSubpart { alias, document: d } => {
+                document.add_subpart(alias.clone(), d)?;
+                Ok(Edit::RemoveSubpart { alias })
+            }
+            Edit::RemoveSubpart { alias } => {
+                let d = document.remove_subpart(&alias)?;
+                Ok(Edit::AddSubpart { alias, document: d })
+            }
+            Edit::Batch(edits) => {
+                let mut reverts = Vec::with_capacity(edits.len());
+                for edit in edits {
+                    match edit.apply(document) {
+                        Ok(revert) => reverts.push(revert),
+                        Err(e) => {
+                            // Roll back what has been applied so far.
+                            for revert in reverts.into_iter().rev() {
+                                let _ = revert.apply(document);
+                            }
+                            return Err(e);
+                        }
+                    }
+                }
+                reverts.reverse();
+                Ok(Edit::Batch(reverts))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Journal {
+    undo_stack: Vec<Edit>,
+    redo_stack: Vec<Edit>,
+    limit: Option<usize>,
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limit(limit: usize) -> Self {
+        Self {
+            limit: Some(limit),
+            ..Default::default()
+        }
+    }
+
+    pub fn apply(&mut self, document: &mut MultipartDocument, edit: Edit) -> Result<(), EditError> {
+        let revert = edit.apply(document)?;
+        self.undo_stack.push(revert);
+        self.redo_stack.clear();
+        if let Some(limit) = self.limit {
+            if self.undo_stack.len() > limit {
+                let excess = self.undo_stack.len() - limit;
+                self.undo_stack.drain(..excess);
+            }
+        }
+        Ok(())
+    }
+
+    // Edits that fail, e.g. because the document has been changed outside of the journal,
+    // stay on the stack.
+    pub fn undo(&mut self, document: &mut MultipartDocument) -> Result<bool, EditError> {
+        match self.undo_stack.last() {
+            Some(edit) => {
+                let revert = edit.clone().apply(document)?;
+                self.undo_stack.pop();
+                self.redo_stack.push(revert);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub fn redo(&mut self, document: &mut MultipartDocument) -> Result<bool, EditError> {
+        match self.redo_stack.last() {
+            Some(edit) => {
+                let revert = edit.clone().apply(document)?;
+                self.redo_stack.pop();
+                self.undo_stack.push(revert);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        document::MultipartDocument,
+        elements::{Command, Meta},
+        testing::{document, multipart},
+    };
+
+    use super::{Edit, Journal};
+
+    fn comment(text: &str) -> Command {
+        Command::Meta(Meta::Comment(text.to_string()))
+    }
+
+    fn body(commands: Vec<Command>) -> MultipartDocument {
+        multipart(document("", commands))
+    }
+
+    #[test]
+    fn test_move_step() {
+        let step = Command::Meta(Meta::Step);
+        let mut doc = body(vec![
+            comment("a"),
+            step.clone(),
+            comment("b"),
+            comment("c"),
+            step.clone(),
+            comment("d"),
+        ]);
+
+        assert_eq!(doc.body.step_ranges(), vec![0..1, 2..4, 5..6]);
+
+        doc.body.move_step(2, 0).unwrap();
+        assert_eq!(
+            doc.body.commands,
+            vec![
+                comment("d"),
+                step.clone(),
+                comment("a"),
+                step.clone(),
+                comment("b"),
+                comment("c"),
+            ]
+        );
+        assert!(doc.body.move_step(3, 0).is_err());
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let original = body(vec![comment("a"), comment("b")]);
+        let mut doc = original.clone();
+        let mut journal = Journal::new();
+
+        journal
+            .apply(
+                &mut doc,
+                Edit::Batch(vec![
+                    Edit::RemoveCommand {
+                        subpart: None,
+                        index: 0,
+                    },
+                    Edit::InsertCommand {
+                        subpart: None,
+                        index: 1,
+                        command: comment("c"),
+                    },
+                ]),
+            )
+            .unwrap();
+        journal
+            .apply(
+                &mut doc,
+                Edit::ReplaceCommand {
+                    subpart: None,
+                    index: 0,
+                    command: comment("d"),
+                },
+            )
+            .unwrap();
+        assert_eq!(doc.body.commands, vec![comment("d"), comment("c")]);
+
+        assert!(journal.undo(&mut doc).unwrap());
+        assert!(journal.undo(&mut doc).unwrap());
+        assert!(!journal.undo(&mut doc).unwrap());
+        assert_eq!(doc, original);
+
+        assert!(journal.redo(&mut doc).unwrap());
+        assert_eq!(doc.body.commands, vec![comment("b"), comment("c")]);
+
+        // Failed edit in a batch leaves the document untouched.
+        let before = doc.clone();
+        assert!(journal
+            .apply(
+                &mut doc,
+                Edit::Batch(vec![
+                    Edit::RemoveCommand {
+                        subpart: None,
+                        index: 0,
+                    },
+                    Edit::RemoveCommand {
+                        subpart: None,
+                        index: 5,
+                    },
+                ]),
+            )
+            .is_err());
+        assert_eq!(doc, before);
+        assert!(journal.can_redo());
+    }
+
+    #[test]
+    fn test_failed_undo_keeps_edit() {
+        let mut doc = body(vec![comment("a")]);
+        let mut journal = Journal::new();
+
+        journal
+            .apply(
+                &mut doc,
+                Edit::InsertCommand {
+                    subpart: None,
+                    index: 1,
+                    command: comment("b"),
+                },
+            )
+            .unwrap();
+
+        // Removed behind the journal's back, so there is nothing to undo at index 1.
+        doc.body.commands.truncate(1);
+        assert!(journal.undo(&mut doc).is_err());
+        assert!(journal.can_undo());
+        assert!(!journal.can_redo());
+
+        doc.body.commands.push(comment("b"));
+        assert!(journal.undo(&mut doc).unwrap());
+        assert_eq!(doc.body.commands, vec![comment("a")]);
+
+        doc.body.commands.clear();
+        assert!(journal.redo(&mut doc).is_err());
+        assert!(journal.can_redo());
+    }
+}
@@ -1,9 +1,175 @@
+use cgmath::Deg;
+
 use crate::color::ColorReference;
 use crate::{Matrix4, PartAlias, Vector4, Winding};
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Header(pub String, pub String);
 
+pub const LDRAWRS_HEADER: &str = "LDRAWRS";
+
+// Preferred viewing angle stored as `0 !LDRAWRS CAMERA ...`. Distance is a multiplier relative
+// to the distance each renderer picks to frame the whole model.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    pub latitude: Deg<f32>,
+    pub longitude: Deg<f32>,
+    pub distance: f32,
+    pub fov: Option<Deg<f32>>,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            latitude: Deg(30.0),
+            longitude: Deg(45.0),
+            distance: 1.0,
+            fov: None,
+        }
+    }
+}
+
+impl Camera {
+    pub fn to_header(&self) -> Header {
+        let mut value = format!(
+            "CAMERA LATITUDE {} LONGITUDE {} DISTANCE {}",
+            self.latitude.0, self.longitude.0, self.distance
+        );
+        if let Some(fov) = self.fov {
+            value.push_str(&format!(" FOV {}", fov.0));
+        }
+        Header(String::from(LDRAWRS_HEADER), value)
+    }
+}
+
+pub const CATEGORY_HEADER: &str = "CATEGORY";
+pub const KEYWORDS_HEADER: &str = "KEYWORDS";
+pub const LDRAW_ORG_HEADER: &str = "LDRAW_ORG";
+pub const LICENSE_HEADER: &str = "LICENSE";
+pub const HISTORY_HEADER: &str = "HISTORY";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Release {
+    Original,
+    // Library update the file was released in, e.g. 2004-03.
+    Update(String),
+}
+
+// Type of a library file as stated in `0 !LDRAW_ORG <type> [qualifiers] [release]`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LDrawOrg {
+    pub part_type: String,
+    pub qualifiers: Vec<String>,
+    // Unofficial files are not part of any release.
+    pub release: Option<Release>,
+}
+
+impl LDrawOrg {
+    pub fn is_unofficial(&self) -> bool {
+        self.part_type.starts_with("Unofficial_")
+    }
+
+    pub fn to_header(&self) -> Header {
+        let mut words = vec![self.part_type.clone()];
+        words.extend(self.qualifiers.iter().cloned());
+        match &self.release {
+            Some(Release::Original) => words.push(String::from("ORIGINAL")),
+            Some(Release::Update(v)) => words.push(format!("UPDATE {}", v)),
+            None => (),
+        }
+        Header(String::from(LDRAW_ORG_HEADER), words.join(" "))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum HistoryAuthor {
+    // Written as [name] by authors with an account on LDraw.org.
+    Username(String),
+    // Written as {name} otherwise.
+    RealName(String),
+}
+
+// Line of `0 !HISTORY YYYY-MM-DD [author] description`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryEntry {
+    pub date: String,
+    pub author: HistoryAuthor,
+    pub description: String,
+}
+
+impl HistoryEntry {
+    pub fn to_header(&self) -> Header {
+        let author = match &self.author {
+            HistoryAuthor::Username(v) => format!("[{}]", v),
+            HistoryAuthor::RealName(v) => format!("{{{}}}", v),
+        };
+        Header(
+            String::from(HISTORY_HEADER),
+            format!("{} {} {}", self.date, author, self.description),
+        )
+    }
+}
+
+// Headers of library files gathered in one place, for browsing parts.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PartMetadata {
+    pub description: String,
+    pub category: String,
+    pub keywords: Vec<String>,
+    pub ldraw_org: Option<LDrawOrg>,
+    pub license: Option<String>,
+    pub history: Vec<HistoryEntry>,
+}
+
+pub const LDCAD_META: &str = "LDCAD";
+
+// Meta command of LDCad in the form of `0 !LDCAD <KIND> [key=value] ...`. Parameters are kept
+// in order so that the command is written back as it was read.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LdCadCommand {
+    pub kind: String,
+    pub parameters: Vec<(String, String)>,
+}
+
+impl LdCadCommand {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+// Control point of a flexible part. The path passes through the origin of the matrix along its
+// Y axis, with bezier control points placed at given distances before and after.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LdCadPathPoint {
+    pub matrix: Matrix4,
+    pub prev_distance: f32,
+    pub next_distance: f32,
+}
+
+// Part repeated along a path to give it a body.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LdCadPathSkin {
+    pub color: u32,
+    pub part: Option<PartAlias>,
+    pub length: Option<f32>,
+}
+
+// Structured form of LDCad commands that matter for generating flexible parts.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LdCadGenerator {
+    // Kind of content the document holds, such as `path` or `spring`.
+    Content(String),
+    PathPoint(LdCadPathPoint),
+    PathSkin(LdCadPathSkin),
+    // Everything after this is geometry LDCad generated from the commands before.
+    Generated,
+    // Snapping information, with the shape such as `CYL` or `CLEAR`.
+    Snap(String),
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum BfcStatement {
     Winding(Winding),
@@ -22,6 +188,7 @@ pub enum Meta {
     Pause,
     Save,
     Bfc(BfcStatement),
+    LdCad(LdCadCommand),
 }
 
 #[derive(Clone, Debug, PartialEq)]
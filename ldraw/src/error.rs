@@ -1,4 +1,6 @@
-use std::{error::Error, fmt, io::Error as IoError};
+use std::{error::Error, fmt, io::Error as IoError, ops::Range};
+
+use crate::PartAlias;
 
 #[cfg(any(target_arch = "wasm32", feature = "http"))]
 use reqwest::Error as ReqwestError;
@@ -70,9 +72,23 @@ impl Error for ParseError {
     }
 }
 
+// Location of a piece of a document.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Span {
+    // Starting from 1.
+    pub line: usize,
+    // Starting from 1, counted in characters.
+    pub column: usize,
+    // Byte offsets in the input.
+    pub range: Range<usize>,
+    pub token: String,
+}
+
 #[derive(Debug)]
 pub struct DocumentParseError {
     pub line: usize,
+    // Offending token in the line, if the error is about one.
+    pub span: Option<Span>,
     pub error: ParseError,
 }
 
@@ -84,7 +100,14 @@ impl From<DocumentParseError> for ColorDefinitionParseError {
 
 impl fmt::Display for DocumentParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} (at line {})", self.error, self.line)
+        match &self.span {
+            Some(span) => write!(
+                f,
+                "{} (at line {}, column {})",
+                self.error, span.line, span.column
+            ),
+            None => write!(f, "{} (at line {})", self.error, self.line),
+        }
     }
 }
 
@@ -157,16 +180,70 @@ impl Error for SerializeError {
     }
 }
 
+#[derive(Debug)]
+pub enum ImportError {
+    IoError(Box<IoError>),
+    InvalidArchive(String),
+    InvalidXml(String),
+    DocumentParseError(DocumentParseError),
+    MissingModel,
+}
+
+impl From<IoError> for ImportError {
+    fn from(e: IoError) -> ImportError {
+        ImportError::IoError(Box::new(e))
+    }
+}
+
+impl From<DocumentParseError> for ImportError {
+    fn from(e: DocumentParseError) -> ImportError {
+        ImportError::DocumentParseError(e)
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::IoError(err) => write!(f, "{}", err),
+            ImportError::InvalidArchive(reason) => write!(f, "Invalid archive: {}", reason),
+            ImportError::InvalidXml(reason) => write!(f, "Invalid XML: {}", reason),
+            ImportError::DocumentParseError(e) => write!(f, "{}", e),
+            ImportError::MissingModel => write!(f, "No model found in archive."),
+        }
+    }
+}
+
+impl Error for ImportError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ImportError::IoError(e) => Some(e),
+            ImportError::DocumentParseError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ResolutionError {
     NoLDrawDir,
-    FileNotFound,
+    // Carries names of similar files in the library, if the loader could find any.
+    FileNotFound { suggestions: Vec<PartAlias> },
     IoError(Box<IoError>),
     DocumentParseError(DocumentParseError),
     ColorDefinitionParseError(ColorDefinitionParseError),
+    ImportError(ImportError),
     RemoteError(ReqwestError),
 }
 
+impl ResolutionError {
+    pub fn suggestions(&self) -> &[PartAlias] {
+        match self {
+            ResolutionError::FileNotFound { suggestions } => suggestions,
+            _ => &[],
+        }
+    }
+}
+
 impl From<IoError> for ResolutionError {
     fn from(e: IoError) -> ResolutionError {
         ResolutionError::IoError(Box::new(e))
@@ -185,6 +262,12 @@ impl From<ColorDefinitionParseError> for ResolutionError {
     }
 }
 
+impl From<ImportError> for ResolutionError {
+    fn from(e: ImportError) -> ResolutionError {
+        ResolutionError::ImportError(e)
+    }
+}
+
 impl From<ReqwestError> for ResolutionError {
     fn from(e: ReqwestError) -> ResolutionError {
         ResolutionError::RemoteError(e)
@@ -195,10 +278,21 @@ impl fmt::Display for ResolutionError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             ResolutionError::NoLDrawDir => write!(f, "No LDraw library found."),
-            ResolutionError::FileNotFound => write!(f, "File not found."),
+            ResolutionError::FileNotFound { suggestions } => {
+                write!(f, "File not found.")?;
+                if !suggestions.is_empty() {
+                    let names = suggestions
+                        .iter()
+                        .map(|v| v.original.as_str())
+                        .collect::<Vec<_>>();
+                    write!(f, " Did you mean {}?", names.join(", "))?;
+                }
+                Ok(())
+            }
             ResolutionError::IoError(err) => write!(f, "{}", err),
             ResolutionError::DocumentParseError(err) => write!(f, "{}", err),
             ResolutionError::ColorDefinitionParseError(err) => write!(f, "{}", err),
+            ResolutionError::ImportError(err) => write!(f, "{}", err),
             ResolutionError::RemoteError(err) => write!(f, "{}", err),
         }
     }
@@ -210,8 +304,55 @@ impl Error for ResolutionError {
             ResolutionError::IoError(e) => Some(e),
             ResolutionError::DocumentParseError(e) => Some(e),
             ResolutionError::ColorDefinitionParseError(e) => Some(e),
+            ResolutionError::ImportError(e) => Some(e),
             ResolutionError::RemoteError(e) => Some(e),
             _ => None,
         }
     }
 }
+
+#[derive(Debug)]
+pub enum EditError {
+    CommandOutOfRange(usize),
+    StepOutOfRange(usize),
+    SubpartNotFound(PartAlias),
+    SubpartAlreadyExists(PartAlias),
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EditError::CommandOutOfRange(index) => {
+                write!(f, "Command index {} is out of range.", index)
+            }
+            EditError::StepOutOfRange(index) => write!(f, "Step index {} is out of range.", index),
+            EditError::SubpartNotFound(alias) => write!(f, "Subpart {} not found.", alias),
+            EditError::SubpartAlreadyExists(alias) => {
+                write!(f, "Subpart {} already exists.", alias)
+            }
+        }
+    }
+}
+
+impl Error for EditError {}
+
+// Subparts referring back to themselves, in the order they refer to one another.
+#[derive(Debug)]
+pub struct CircularReferenceError {
+    pub cycle: Vec<PartAlias>,
+}
+
+impl fmt::Display for CircularReferenceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Circular reference: ")?;
+        for alias in self.cycle.iter() {
+            write!(f, "{} -> ", alias)?;
+        }
+        match self.cycle.first() {
+            Some(alias) => write!(f, "{}", alias),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Error for CircularReferenceError {}
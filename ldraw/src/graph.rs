@@ -0,0 +1,355 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{document::MultipartDocument, error::CircularReferenceError, PartAlias};
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum DependencyNode {
+    // The first file of the document.
+    Body,
+    Subpart(PartAlias),
+    // Files outside the document, e.g. parts in the library. They are not looked into, so
+    // they never depend on anything.
+    External(PartAlias),
+}
+
+// Which files of a document refer to which. Files referred to more than once are connected
+// only once.
+#[derive(Clone, Debug)]
+pub struct DependencyGraph {
+    nodes: Vec<DependencyNode>,
+    index: HashMap<DependencyNode, usize>,
+    // Nodes each node refers to, in order of the first reference.
+    edges: Vec<Vec<usize>>,
+}
+
+impl DependencyGraph {
+    pub fn new(document: &MultipartDocument) -> Self {
+        let mut subparts = document.subparts.iter().collect::<Vec<_>>();
+        subparts.sort_by(|a, b| a.0.normalized.cmp(&b.0.normalized));
+
+        let mut graph = DependencyGraph {
+            nodes: Vec::new(),
+            index: HashMap::new(),
+            edges: Vec::new(),
+        };
+        graph.add_node(DependencyNode::Body);
+        for (alias, _) in subparts.iter() {
+            graph.add_node(DependencyNode::Subpart((*alias).clone()));
+        }
+
+        let documents = [(0, &document.body)].into_iter().chain(
+            subparts
+                .iter()
+                .enumerate()
+                .map(|(index, (_, subpart))| (index + 1, *subpart)),
+        );
+        for (from, subfile) in documents {
+            for reference in subfile.iter_refs() {
+                let node = if document.subparts.contains_key(&reference.name) {
+                    DependencyNode::Subpart(reference.name.clone())
+                } else {
+                    DependencyNode::External(reference.name.clone())
+                };
+                let to = graph.add_node(node);
+                if !graph.edges[from].contains(&to) {
+                    graph.edges[from].push(to);
+                }
+            }
+        }
+
+        graph
+    }
+
+    fn add_node(&mut self, node: DependencyNode) -> usize {
+        if let Some(index) = self.index.get(&node) {
+            return *index;
+        }
+        let index = self.nodes.len();
+        self.index.insert(node.clone(), index);
+        self.nodes.push(node);
+        self.edges.push(Vec::new());
+        index
+    }
+
+    // The body first, then subparts and external files.
+    pub fn nodes(&self) -> &[DependencyNode] {
+        &self.nodes
+    }
+
+    pub fn contains(&self, node: &DependencyNode) -> bool {
+        self.index.contains_key(node)
+    }
+
+    // Files the node refers to directly.
+    pub fn dependencies(&self, node: &DependencyNode) -> Vec<&DependencyNode> {
+        match self.index.get(node) {
+            Some(index) => self.edges[*index].iter().map(|v| &self.nodes[*v]).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // Files referring to the node directly.
+    pub fn dependents(&self, node: &DependencyNode) -> Vec<&DependencyNode> {
+        let Some(index) = self.index.get(node) else {
+            return Vec::new();
+        };
+        self.edges
+            .iter()
+            .enumerate()
+            .filter(|(_, edges)| edges.contains(index))
+            .map(|(from, _)| &self.nodes[from])
+            .collect()
+    }
+
+    // External files used by the body, directly or through subparts. Subparts nothing refers
+    // to are left out.
+    pub fn external_dependencies(&self) -> HashSet<PartAlias> {
+        let mut visited = vec![false; self.nodes.len()];
+        let mut pending = vec![0];
+        let mut result = HashSet::new();
+        while let Some(index) = pending.pop() {
+            if visited[index] {
+                continue;
+            }
+            visited[index] = true;
+            if let DependencyNode::External(alias) = &self.nodes[index] {
+                result.insert(alias.clone());
+            }
+            pending.extend(self.edges[index].iter().copied());
+        }
+        result
+    }
+
+    // Walks every node depth first. Returns nodes in the order they were finished with, which
+    // puts dependencies before their dependents, along with the cycles met on the way.
+    fn walk(&self) -> (Vec<usize>, Vec<Vec<usize>>) {
+        const UNVISITED: u8 = 0;
+        const ACTIVE: u8 = 1;
+        const FINISHED: u8 = 2;
+
+        let mut state = vec![UNVISITED; self.nodes.len()];
+        let mut finished = Vec::with_capacity(self.nodes.len());
+        let mut cycles = Vec::new();
+
+        for start in 0..self.nodes.len() {
+            if state[start] != UNVISITED {
+                continue;
+            }
+            state[start] = ACTIVE;
+            let mut stack = vec![(start, 0)];
+            while let Some((node, next)) = stack.last_mut() {
+                let node = *node;
+                match self.edges[node].get(*next) {
+                    Some(&child) => {
+                        *next += 1;
+                        match state[child] {
+                            UNVISITED => {
+                                state[child] = ACTIVE;
+                                stack.push((child, 0));
+                            }
+                            ACTIVE => {
+                                let position = stack.iter().position(|(v, _)| *v == child).unwrap();
+                                cycles.push(stack[position..].iter().map(|(v, _)| *v).collect());
+                            }
+                            _ => {}
+                        }
+                    }
+                    None => {
+                        state[node] = FINISHED;
+                        finished.push(node);
+                        stack.pop();
+                    }
+                }
+            }
+        }
+
+        (finished, cycles)
+    }
+
+    fn aliases(&self, indices: &[usize]) -> Vec<PartAlias> {
+        indices
+            .iter()
+            .filter_map(|v| match &self.nodes[*v] {
+                DependencyNode::Subpart(alias) | DependencyNode::External(alias) => {
+                    Some(alias.clone())
+                }
+                DependencyNode::Body => None,
+            })
+            .collect()
+    }
+
+    // Subparts referring back to themselves, each listed in the order they refer to one
+    // another. At least one is found for every group of subparts referring to one another,
+    // though not every way around each group is.
+    pub fn cycles(&self) -> Vec<Vec<PartAlias>> {
+        let (_, cycles) = self.walk();
+        cycles.iter().map(|v| self.aliases(v)).collect()
+    }
+
+    pub fn has_cycles(&self) -> bool {
+        !self.walk().1.is_empty()
+    }
+
+    // Every node, each after all of its dependencies.
+    pub fn topological_order(&self) -> Result<Vec<&DependencyNode>, CircularReferenceError> {
+        let (finished, cycles) = self.walk();
+        match cycles.first() {
+            Some(cycle) => Err(CircularReferenceError {
+                cycle: self.aliases(cycle),
+            }),
+            None => Ok(finished.into_iter().map(|v| &self.nodes[v]).collect()),
+        }
+    }
+}
+
+// What a traversal is in the middle of, innermost last. Files or submodels referring back to
+// themselves, directly or not, are not entered again, so that traversals always end.
+#[derive(Clone, Debug)]
+pub struct CycleGuard<K> {
+    stack: Vec<K>,
+}
+
+impl<K: PartialEq> Default for CycleGuard<K> {
+    fn default() -> Self {
+        Self { stack: Vec::new() }
+    }
+}
+
+impl<K: PartialEq> CycleGuard<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns false if the key is already being traversed, in which case nothing is entered.
+    pub fn enter(&mut self, key: K) -> bool {
+        if self.stack.contains(&key) {
+            return false;
+        }
+        self.stack.push(key);
+        true
+    }
+
+    pub fn leave(&mut self) {
+        self.stack.pop();
+    }
+
+    pub fn path(&self) -> &[K] {
+        &self.stack
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{color::ColorCatalog, parser::parse_multipart_document, PartAlias};
+
+    use super::{CycleGuard, DependencyNode};
+
+    fn subpart(name: &str) -> DependencyNode {
+        DependencyNode::Subpart(PartAlias::from(name))
+    }
+
+    fn external(name: &str) -> DependencyNode {
+        DependencyNode::External(PartAlias::from(name))
+    }
+
+    #[test]
+    fn test_cycle_guard() {
+        let mut guard = CycleGuard::new();
+        assert!(guard.enter("a"));
+        assert!(guard.enter("b"));
+        assert!(!guard.enter("a"));
+        assert_eq!(guard.path(), &["a", "b"]);
+        guard.leave();
+        assert!(guard.enter("c"));
+        assert_eq!(guard.path(), &["a", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_dependency_graph() {
+        let document = parse_multipart_document(
+            &mut "0 FILE main.ldr\n\
+                  0 Main\n\
+                  1 16 0 0 0 1 0 0 0 1 0 0 0 1 a.ldr\n\
+                  1 16 0 0 0 1 0 0 0 1 0 0 0 1 b.ldr\n\
+                  1 16 0 0 0 1 0 0 0 1 0 0 0 1 3001.dat\n\
+                  0 FILE a.ldr\n\
+                  0 A\n\
+                  1 16 0 0 0 1 0 0 0 1 0 0 0 1 b.ldr\n\
+                  1 16 0 0 0 1 0 0 0 1 0 0 0 1 3001.dat\n\
+                  0 FILE b.ldr\n\
+                  0 B\n\
+                  1 16 0 0 0 1 0 0 0 1 0 0 0 1 3002.dat\n\
+                  0 FILE unused.ldr\n\
+                  0 Unused\n\
+                  1 16 0 0 0 1 0 0 0 1 0 0 0 1 3003.dat\n"
+                .as_bytes(),
+            &ColorCatalog::new(),
+        )
+        .await
+        .unwrap();
+        let graph = document.dependency_graph();
+
+        assert_eq!(
+            graph.dependencies(&DependencyNode::Body),
+            vec![&subpart("a.ldr"), &subpart("b.ldr"), &external("3001.dat")]
+        );
+        assert_eq!(
+            graph.dependents(&subpart("b.ldr")),
+            vec![&DependencyNode::Body, &subpart("a.ldr")]
+        );
+        assert_eq!(
+            graph.external_dependencies(),
+            ["3001.dat", "3002.dat"]
+                .into_iter()
+                .map(PartAlias::from)
+                .collect()
+        );
+        assert!(!graph.has_cycles());
+
+        let order = graph.topological_order().unwrap();
+        assert_eq!(order.len(), graph.nodes().len());
+        for (index, node) in order.iter().enumerate() {
+            for dependency in graph.dependencies(node) {
+                assert!(order[..index].contains(&dependency));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dependency_graph_cycles() {
+        let document = parse_multipart_document(
+            &mut "0 FILE main.ldr\n\
+                  0 Main\n\
+                  1 16 0 0 0 1 0 0 0 1 0 0 0 1 a.ldr\n\
+                  0 FILE a.ldr\n\
+                  0 A\n\
+                  1 16 0 0 0 1 0 0 0 1 0 0 0 1 b.ldr\n\
+                  0 FILE b.ldr\n\
+                  0 B\n\
+                  1 16 0 0 0 1 0 0 0 1 0 0 0 1 a.ldr\n\
+                  1 16 0 0 0 1 0 0 0 1 0 0 0 1 b.ldr\n\
+                  1 16 0 0 0 1 0 0 0 1 0 0 0 1 3001.dat\n"
+                .as_bytes(),
+            &ColorCatalog::new(),
+        )
+        .await
+        .unwrap();
+        let graph = document.dependency_graph();
+
+        let mut cycles = graph
+            .cycles()
+            .into_iter()
+            .map(|v| v.into_iter().map(|v| v.normalized).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        cycles.sort();
+        assert_eq!(cycles, vec![vec!["a.ldr", "b.ldr"], vec!["b.ldr"]]);
+        let error = graph.topological_order().unwrap_err();
+        assert!(!error.cycle.is_empty());
+
+        // Does not recurse forever.
+        assert_eq!(
+            document.list_dependencies(),
+            [PartAlias::from("3001.dat")].into_iter().collect()
+        );
+    }
+}
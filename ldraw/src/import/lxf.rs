@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+
+use cgmath::{Deg, InnerSpace, SquareMatrix};
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader, XmlVersion,
+};
+
+use super::zip::read_archive;
+use crate::{
+    color::{ColorCatalog, ColorReference},
+    document::{Document, MultipartDocument},
+    elements::{Command, PartReference},
+    error::ImportError,
+    Matrix4, PartAlias, Vector3,
+};
+
+// LDD measures in centimeters.
+const LDU_PER_LDD_UNIT: f32 = 25.0;
+
+const BUNDLED_MAPPING: &str = include_str!("lxf_mapping.txt");
+
+// Maps LDD design and material IDs to LDraw parts and colors.
+#[derive(Clone, Debug, Default)]
+pub struct LxfMapping {
+    parts: HashMap<String, (PartAlias, Vector3)>,
+    colors: HashMap<u32, u32>,
+}
+
+impl LxfMapping {
+    // The table shipped with the crate, covering common parts and colors.
+    pub fn bundled() -> Self {
+        Self::parse(BUNDLED_MAPPING)
+    }
+
+    // Reads a table in the format of the bundled one. Malformed lines are skipped.
+    pub fn parse(text: &str) -> Self {
+        let mut mapping = Self::default();
+        for line in text.lines() {
+            let tokens = line.split_whitespace().collect::<Vec<_>>();
+            match tokens.as_slice() {
+                ["part", design_id, alias, rest @ ..] => {
+                    let offset = match rest {
+                        [x, y, z] => match (x.parse(), y.parse(), z.parse()) {
+                            (Ok(x), Ok(y), Ok(z)) => Vector3::new(x, y, z),
+                            _ => continue,
+                        },
+                        [] => Vector3::new(0.0, 0.0, 0.0),
+                        _ => continue,
+                    };
+                    mapping
+                        .parts
+                        .insert(design_id.to_string(), (PartAlias::from(*alias), offset));
+                }
+                ["color", material, code] => {
+                    if let (Ok(material), Ok(code)) = (material.parse(), code.parse()) {
+                        mapping.colors.insert(material, code);
+                    }
+                }
+                _ => {}
+            }
+        }
+        mapping
+    }
+
+    // Entries of the other table take precedence.
+    pub fn extend(&mut self, other: LxfMapping) {
+        self.parts.extend(other.parts);
+        self.colors.extend(other.colors);
+    }
+
+    pub fn part(&self, design_id: &str) -> (PartAlias, Vector3) {
+        match self.parts.get(design_id) {
+            Some((alias, offset)) => (alias.clone(), *offset),
+            None => (
+                PartAlias::from(format!("{}.dat", design_id)),
+                Vector3::new(0.0, 0.0, 0.0),
+            ),
+        }
+    }
+
+    // Unknown materials fall back to the current color.
+    pub fn color(&self, material: u32) -> u32 {
+        self.colors.get(&material).copied().unwrap_or(16)
+    }
+}
+
+#[derive(Default)]
+struct PendingPart {
+    design_id: String,
+    material: u32,
+    matrix: Option<Matrix4>,
+}
+
+fn attribute(element: &BytesStart, name: &str) -> Result<Option<String>, ImportError> {
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(|e| ImportError::InvalidXml(e.to_string()))?;
+        if attribute.key.as_ref() == name.as_bytes() {
+            return attribute
+                .normalized_value(XmlVersion::Implicit1_0)
+                .map(|v| Some(v.to_string()))
+                .map_err(|e| ImportError::InvalidXml(e.to_string()));
+        }
+    }
+    Ok(None)
+}
+
+fn parse_floats(value: &str) -> Result<Vec<f32>, ImportError> {
+    value
+        .split(',')
+        .map(|v| {
+            v.trim()
+                .parse::<f32>()
+                .map_err(|_| ImportError::InvalidXml(format!("Invalid number: {}", v)))
+        })
+        .collect()
+}
+
+// Converts a placement in LDD space, where Y points up, into LDraw space.
+fn convert_matrix(ldd: Matrix4) -> Matrix4 {
+    let flip = Matrix4::from_nonuniform_scale(1.0, -1.0, -1.0);
+    let mut matrix = flip * ldd * flip;
+    matrix.w.x *= LDU_PER_LDD_UNIT;
+    matrix.w.y *= LDU_PER_LDD_UNIT;
+    matrix.w.z *= LDU_PER_LDD_UNIT;
+    matrix
+}
+
+// Placement given as rotation and translation, as in transformation attributes of bones.
+fn parse_transformation(value: &str) -> Result<Matrix4, ImportError> {
+    match parse_floats(value)?.as_slice() {
+        [a, b, c, d, e, f, g, h, i, x, y, z] => Ok(convert_matrix(Matrix4::new(
+            *a, *b, *c, 0.0, *d, *e, *f, 0.0, *g, *h, *i, 0.0, *x, *y, *z, 1.0,
+        ))),
+        _ => Err(ImportError::InvalidXml(format!(
+            "Invalid transformation: {}",
+            value
+        ))),
+    }
+}
+
+// Placement given as angle and axis, as in attributes of parts in older files.
+fn parse_angle_axis(element: &BytesStart) -> Result<Option<Matrix4>, ImportError> {
+    let mut values = Vec::new();
+    for name in ["angle", "ax", "ay", "az", "tx", "ty", "tz"] {
+        match attribute(element, name)? {
+            Some(v) => values.extend(parse_floats(&v)?),
+            None => return Ok(None),
+        }
+    }
+
+    let axis = Vector3::new(values[1], values[2], values[3]);
+    let rotation = if axis.magnitude2() > 0.0 {
+        Matrix4::from_axis_angle(axis.normalize(), Deg(values[0]))
+    } else {
+        Matrix4::identity()
+    };
+    Ok(Some(convert_matrix(
+        Matrix4::from_translation(Vector3::new(values[4], values[5], values[6])) * rotation,
+    )))
+}
+
+fn start_part(element: &BytesStart, brick: &Option<String>) -> Result<PendingPart, ImportError> {
+    let design_id = match attribute(element, "designID")? {
+        Some(v) => v,
+        None => brick.clone().unwrap_or_default(),
+    };
+    // Parts made of several materials list all of them, and the first is the main one.
+    let material = match attribute(element, "materials")? {
+        Some(v) => v,
+        None => attribute(element, "materialID")?.unwrap_or_default(),
+    };
+    let material = material
+        .split(',')
+        .next()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(0);
+
+    Ok(PendingPart {
+        design_id,
+        material,
+        matrix: parse_angle_axis(element)?,
+    })
+}
+
+fn finish_part(
+    part: PendingPart,
+    mapping: &LxfMapping,
+    colors: &ColorCatalog,
+    commands: &mut Vec<Command>,
+) {
+    let Some(matrix) = part.matrix else {
+        return;
+    };
+    if part.design_id.is_empty() {
+        return;
+    }
+
+    let (alias, offset) = mapping.part(&part.design_id);
+    commands.push(Command::PartReference(PartReference {
+        color: ColorReference::resolve(mapping.color(part.material), colors),
+        matrix: matrix * Matrix4::from_translation(-offset),
+        name: alias,
+    }));
+}
+
+// Reads a model saved by LEGO Digital Designer as LXFML. Every part becomes a reference in
+// the main document, as LDD has no notion of submodels.
+pub fn parse_lxfml(
+    xml: &str,
+    mapping: &LxfMapping,
+    colors: &ColorCatalog,
+) -> Result<MultipartDocument, ImportError> {
+    let mut reader = Reader::from_str(xml);
+
+    let mut name = String::new();
+    let mut brick = None;
+    let mut part: Option<PendingPart> = None;
+    let mut commands = Vec::new();
+
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| ImportError::InvalidXml(e.to_string()))?;
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                let empty = matches!(event, Event::Empty(_));
+                match e.name().as_ref() {
+                    b"LXFML" => name = attribute(e, "name")?.unwrap_or_default(),
+                    b"Brick" if !empty => brick = attribute(e, "designID")?,
+                    b"Part" => {
+                        let pending = start_part(e, &brick)?;
+                        if empty {
+                            finish_part(pending, mapping, colors, &mut commands);
+                        } else {
+                            part = Some(pending);
+                        }
+                    }
+                    b"Bone" => {
+                        if let (Some(part), Some(transformation)) =
+                            (part.as_mut(), attribute(e, "transformation")?)
+                        {
+                            // Parts bent with several bones are placed by the first one.
+                            if part.matrix.is_none() {
+                                part.matrix = Some(parse_transformation(&transformation)?);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::End(ref e) => match e.name().as_ref() {
+                b"Part" => {
+                    if let Some(part) = part.take() {
+                        finish_part(part, mapping, colors, &mut commands);
+                    }
+                }
+                b"Brick" => brick = None,
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    let description = if name.is_empty() {
+        String::from("Imported from LEGO Digital Designer")
+    } else {
+        name.clone()
+    };
+    Ok(MultipartDocument {
+        body: Document {
+            name: if name.is_empty() {
+                String::from("model.ldr")
+            } else {
+                format!("{}.ldr", name)
+            },
+            description,
+            commands,
+            ..Default::default()
+        },
+        subparts: HashMap::new(),
+    })
+}
+
+// Reads an LXF file, which is a ZIP archive with an LXFML file and a thumbnail in it.
+pub fn parse_lxf(
+    data: &[u8],
+    mapping: &LxfMapping,
+    colors: &ColorCatalog,
+) -> Result<MultipartDocument, ImportError> {
+    let (_, xml) = read_archive(data, None)?
+        .into_iter()
+        .find(|(name, _)| name.to_ascii_lowercase().ends_with(".lxfml"))
+        .ok_or(ImportError::MissingModel)?;
+    parse_lxfml(&String::from_utf8_lossy(&xml), mapping, colors)
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace;
+
+    use crate::{color::ColorCatalog, elements::Command, Vector3};
+
+    use super::{parse_lxf, parse_lxfml, LxfMapping};
+    use crate::import::zip::build_archive;
+
+    const LXFML: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="no" ?>
+<LXFML versionMajor="5" versionMinor="0" name="Tower">
+  <Bricks cameraRef="0">
+    <Brick refID="0" designID="3001">
+      <Part refID="0" designID="3001" materials="21,0">
+        <Bone refID="0" transformation="1,0,0,0,1,0,0,0,1,0.8,0.96,-0.4"/>
+      </Part>
+    </Brick>
+    <Brick refID="1" designID="6141">
+      <Part refID="1" designID="6141" materials="999">
+        <Bone refID="1" transformation="0,0,-1,0,1,0,1,0,0,0,0,0"/>
+      </Part>
+    </Brick>
+    <Brick refID="2" designID="99999">
+      <Part refID="2" designID="99999" materials="23">
+        <Bone refID="2" transformation="1,0,0,0,1,0,0,0,1,0,0,0"/>
+      </Part>
+    </Brick>
+  </Bricks>
+</LXFML>
+"#;
+
+    fn references(xml: &str) -> Vec<(String, u32, Vector3)> {
+        let document = parse_lxfml(xml, &LxfMapping::bundled(), &ColorCatalog::new()).unwrap();
+        document
+            .body
+            .commands
+            .iter()
+            .filter_map(|v| match v {
+                Command::PartReference(r) => Some((
+                    r.name.normalized.clone(),
+                    r.color.code(),
+                    r.matrix.w.truncate(),
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_lxfml() {
+        let references = references(LXFML);
+        assert_eq!(references.len(), 3);
+
+        // Origin of LDD bricks is at the bottom, and Y points the other way.
+        assert_eq!(references[0].0, "3001.dat");
+        assert_eq!(references[0].1, 4);
+        assert!((references[0].2 - Vector3::new(20.0, -48.0, 10.0)).magnitude() < 1e-4);
+
+        assert_eq!(references[1].0, "4073.dat");
+        assert_eq!(references[1].1, 16);
+        assert!((references[1].2 - Vector3::new(0.0, -8.0, 0.0)).magnitude() < 1e-4);
+
+        assert_eq!(references[2].0, "99999.dat");
+        assert_eq!(references[2].1, 1);
+    }
+
+    #[test]
+    fn test_parse_lxfml_angle_axis() {
+        let xml = r#"<LXFML versionMajor="4"><Bricks>
+            <Brick refID="0" designID="3005">
+              <Part refID="0" materialID="26" angle="90" ax="0" ay="1" az="0" tx="0" ty="0" tz="0.8"/>
+            </Brick>
+        </Bricks></LXFML>"#;
+        let references = references(xml);
+        assert_eq!(references.len(), 1);
+        assert_eq!(references[0].1, 0);
+        assert!((references[0].2 - Vector3::new(0.0, -24.0, -20.0)).magnitude() < 1e-4);
+    }
+
+    #[test]
+    fn test_parse_lxf() {
+        let archive = build_archive(&[("IMAGE100.LXFML", LXFML.as_bytes())], None);
+
+        let document = parse_lxf(&archive, &LxfMapping::bundled(), &ColorCatalog::new()).unwrap();
+        assert_eq!(document.body.name, "Tower.ldr");
+        assert_eq!(document.body.commands.len(), 3);
+
+        assert!(parse_lxf(
+            b"not an archive",
+            &LxfMapping::bundled(),
+            &ColorCatalog::new()
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,3 @@
+pub mod lxf;
+pub mod studio;
+pub(crate) mod zip;
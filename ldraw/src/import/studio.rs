@@ -0,0 +1,159 @@
+use tokio::io::BufReader;
+
+use super::zip::read_archive;
+use crate::{
+    color::ColorCatalog,
+    document::MultipartDocument,
+    error::ImportError,
+    parser::{parse_multipart_document, parse_single_document},
+    PartAlias,
+};
+
+// Studio encrypts its archives with a fixed password.
+const PASSWORD: &[u8] = b"soho0909";
+
+// Models are looked up in this order before falling back to any LDraw file in the archive.
+const MODEL_NAMES: &[&str] = &["model.ldr", "model2.ldr"];
+
+// Custom parts are stored as in the LDraw library, relative to these directories.
+const CUSTOM_PART_DIRECTORIES: &[&str] = &["customparts/parts/", "customparts/p/"];
+
+// Reads a model saved by BrickLink Studio. Steps and submodels are kept as they are, and meta
+// commands only Studio understands, such as groups, are preserved as comments. Custom parts
+// bundled in the archive are added as subparts so that they resolve without the library.
+pub async fn parse_io(
+    data: &[u8],
+    colors: &ColorCatalog,
+) -> Result<MultipartDocument, ImportError> {
+    let entries = read_archive(data, Some(PASSWORD))?;
+
+    let model = MODEL_NAMES
+        .iter()
+        .find_map(|name| {
+            entries
+                .iter()
+                .find(|(path, _)| path.eq_ignore_ascii_case(name))
+        })
+        .or_else(|| {
+            entries
+                .iter()
+                .find(|(path, _)| path.to_ascii_lowercase().ends_with(".ldr"))
+        })
+        .ok_or(ImportError::MissingModel)?;
+
+    let mut document = parse_multipart_document(&mut BufReader::new(&model.1[..]), colors).await?;
+
+    for (path, contents) in entries.iter() {
+        let lowercased = path.replace('\\', "/").to_ascii_lowercase();
+        let Some(name) = CUSTOM_PART_DIRECTORIES
+            .iter()
+            .find_map(|prefix| lowercased.strip_prefix(prefix))
+        else {
+            continue;
+        };
+        if !name.ends_with(".dat") {
+            continue;
+        }
+
+        // Submodels of the model take precedence over custom parts of the same name.
+        let alias = PartAlias::from(&path[path.len() - name.len()..]);
+        if document.subparts.contains_key(&alias) {
+            continue;
+        }
+        let part = parse_single_document(&mut BufReader::new(&contents[..]), colors).await?;
+        document.subparts.insert(alias, part);
+    }
+
+    Ok(document)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        color::ColorCatalog, elements::Command, error::ImportError, import::zip::build_archive,
+        PartAlias,
+    };
+
+    use super::{parse_io, PASSWORD};
+
+    const MODEL: &str = "\u{feff}0 FILE main.ldr
+0 Untitled
+0 Name: main.ldr
+1 16 0 0 0 1 0 0 0 1 0 0 0 1 sub.ldr
+0 STEP
+1 4 0 -24 0 1 0 0 0 1 0 0 0 1 custom.dat
+0 STEP
+0 FILE sub.ldr
+0 Wheels
+0 Name: sub.ldr
+0 GROUP 1 Wheels
+1 1 0 0 0 1 0 0 0 1 0 0 0 1 3001.dat
+";
+
+    const CUSTOM_PART: &str = "0 Custom Brick
+1 16 0 0 0 1 0 0 0 1 0 0 0 1 s\\custom1.dat
+";
+
+    const CUSTOM_SUBPART: &str = "0 Custom Brick Subpart
+4 16 0 0 0 1 0 0 1 0 1 0 0 1
+";
+
+    #[tokio::test]
+    async fn test_parse_io() {
+        let archive = build_archive(
+            &[
+                (".info", b"{}"),
+                ("model.ldr", MODEL.as_bytes()),
+                ("CustomParts/parts/custom.dat", CUSTOM_PART.as_bytes()),
+                ("CustomParts/parts/s/custom1.dat", CUSTOM_SUBPART.as_bytes()),
+            ],
+            Some(PASSWORD),
+        );
+
+        let document = parse_io(&archive, &ColorCatalog::new()).await.unwrap();
+        assert_eq!(document.body.name, "main.ldr");
+        assert_eq!(
+            document
+                .body
+                .commands
+                .iter()
+                .filter(|v| matches!(v, Command::Meta(_)))
+                .count(),
+            2
+        );
+        assert!(document.subparts.contains_key(&PartAlias::from("sub.ldr")));
+        assert!(document
+            .subparts
+            .contains_key(&PartAlias::from("custom.dat")));
+        assert!(document
+            .subparts
+            .contains_key(&PartAlias::from("s\\custom1.dat")));
+        assert_eq!(
+            document
+                .get_subpart(&PartAlias::from("sub.ldr"))
+                .unwrap()
+                .commands
+                .iter()
+                .filter(|v| matches!(v, Command::Meta(_)))
+                .count(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_io_errors() {
+        let colors = ColorCatalog::new();
+
+        let archive = build_archive(&[("model.ldr", MODEL.as_bytes())], Some(b"wrong"));
+        assert!(matches!(
+            parse_io(&archive, &colors).await,
+            Err(ImportError::InvalidArchive(_))
+        ));
+
+        let archive = build_archive(&[("thumbnail.png", b"")], Some(PASSWORD));
+        assert!(matches!(
+            parse_io(&archive, &colors).await,
+            Err(ImportError::MissingModel)
+        ));
+    }
+}
@@ -0,0 +1,301 @@
+use std::io::Read;
+
+use flate2::read::DeflateDecoder;
+
+use crate::error::ImportError;
+
+fn u16_at(data: &[u8], offset: usize) -> Result<usize, ImportError> {
+    data.get(offset..offset + 2)
+        .map(|v| u16::from_le_bytes([v[0], v[1]]) as usize)
+        .ok_or_else(|| ImportError::InvalidArchive(String::from("Unexpected end of archive")))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<usize, ImportError> {
+    data.get(offset..offset + 4)
+        .map(|v| u32::from_le_bytes([v[0], v[1], v[2], v[3]]) as usize)
+        .ok_or_else(|| ImportError::InvalidArchive(String::from("Unexpected end of archive")))
+}
+
+fn crc32_update(crc: u32, byte: u8) -> u32 {
+    let mut c = (crc ^ byte as u32) & 0xff;
+    for _ in 0..8 {
+        c = if c & 1 != 0 {
+            0xedb88320 ^ (c >> 1)
+        } else {
+            c >> 1
+        };
+    }
+    c ^ (crc >> 8)
+}
+
+// Traditional PKWARE encryption, which is weak but still used to keep casual users out.
+struct ZipCrypto {
+    keys: [u32; 3],
+}
+
+impl ZipCrypto {
+    fn new(password: &[u8]) -> Self {
+        let mut crypto = ZipCrypto {
+            keys: [0x12345678, 0x23456789, 0x34567890],
+        };
+        for byte in password {
+            crypto.update(*byte);
+        }
+        crypto
+    }
+
+    fn update(&mut self, byte: u8) {
+        self.keys[0] = crc32_update(self.keys[0], byte);
+        self.keys[1] = self.keys[1]
+            .wrapping_add(self.keys[0] & 0xff)
+            .wrapping_mul(134775813)
+            .wrapping_add(1);
+        self.keys[2] = crc32_update(self.keys[2], (self.keys[1] >> 24) as u8);
+    }
+
+    fn stream_byte(&self) -> u8 {
+        let temp = (self.keys[2] | 2) & 0xffff;
+        ((temp * (temp ^ 1)) >> 8) as u8
+    }
+
+    fn decrypt(&mut self, data: &[u8]) -> Vec<u8> {
+        data.iter()
+            .map(|v| {
+                let byte = v ^ self.stream_byte();
+                self.update(byte);
+                byte
+            })
+            .collect()
+    }
+
+    #[cfg(test)]
+    fn encrypt(&mut self, data: &[u8]) -> Vec<u8> {
+        data.iter()
+            .map(|v| {
+                let byte = v ^ self.stream_byte();
+                self.update(*v);
+                byte
+            })
+            .collect()
+    }
+}
+
+// File listed in the central directory of a ZIP archive.
+#[derive(Clone, Debug)]
+pub(crate) struct ZipEntry {
+    pub name: String,
+    flags: usize,
+    method: usize,
+    time: usize,
+    crc: usize,
+    compressed_size: usize,
+    header: usize,
+}
+
+// Lists files in a ZIP archive held in memory without reading them.
+pub(crate) fn read_directory(data: &[u8]) -> Result<Vec<ZipEntry>, ImportError> {
+    // The end of central directory record is followed by a comment of up to 64KB.
+    let end = (0..data.len().saturating_sub(21))
+        .rev()
+        .take(0x10000 + 22)
+        .find(|i| data[*i..].starts_with(&[0x50, 0x4b, 0x05, 0x06]))
+        .ok_or_else(|| ImportError::InvalidArchive(String::from("Not a ZIP archive")))?;
+
+    let count = u16_at(data, end + 10)?;
+    let mut offset = u32_at(data, end + 16)?;
+
+    let mut result = Vec::with_capacity(count);
+    for _ in 0..count {
+        if u32_at(data, offset)? != 0x02014b50 {
+            return Err(ImportError::InvalidArchive(String::from(
+                "Broken central directory",
+            )));
+        }
+        let name_length = u16_at(data, offset + 28)?;
+        let extra_length = u16_at(data, offset + 30)?;
+        let comment_length = u16_at(data, offset + 32)?;
+        let name = data
+            .get(offset + 46..offset + 46 + name_length)
+            .map(|v| String::from_utf8_lossy(v).to_string())
+            .ok_or_else(|| ImportError::InvalidArchive(String::from("Broken file name")))?;
+        result.push(ZipEntry {
+            name,
+            flags: u16_at(data, offset + 8)?,
+            method: u16_at(data, offset + 10)?,
+            time: u16_at(data, offset + 12)?,
+            crc: u32_at(data, offset + 16)?,
+            compressed_size: u32_at(data, offset + 20)?,
+            header: u32_at(data, offset + 42)?,
+        });
+        offset += 46 + name_length + extra_length + comment_length;
+    }
+
+    Ok(result)
+}
+
+// Reads a file listed in the archive. Only stored and deflated entries are supported, which
+// is all that archives of supported formats use. Encrypted entries are read with given
+// password.
+pub(crate) fn read_entry(
+    data: &[u8],
+    entry: &ZipEntry,
+    password: Option<&[u8]>,
+) -> Result<Vec<u8>, ImportError> {
+    let name = &entry.name;
+
+    // Sizes in local headers may be left out, so only offsets are taken from there.
+    let header = entry.header;
+    let start = header + 30 + u16_at(data, header + 26)? + u16_at(data, header + 28)?;
+    let compressed = data
+        .get(start..start + entry.compressed_size)
+        .ok_or_else(|| ImportError::InvalidArchive(format!("{} is truncated", name)))?;
+
+    let compressed = if entry.flags & 1 != 0 {
+        let Some(password) = password else {
+            return Err(ImportError::InvalidArchive(format!(
+                "{} is encrypted",
+                name
+            )));
+        };
+        if compressed.len() < 12 {
+            return Err(ImportError::InvalidArchive(format!(
+                "{} is truncated",
+                name
+            )));
+        }
+        let decrypted = ZipCrypto::new(password).decrypt(compressed);
+        // The last byte of the encryption header is there to check the password.
+        let check = if entry.flags & 8 != 0 {
+            entry.time >> 8
+        } else {
+            entry.crc >> 24
+        };
+        if decrypted[11] as usize != check {
+            return Err(ImportError::InvalidArchive(format!(
+                "Wrong password for {}",
+                name
+            )));
+        }
+        decrypted[12..].to_vec()
+    } else {
+        compressed.to_vec()
+    };
+
+    match entry.method {
+        0 => Ok(compressed),
+        8 => {
+            let mut contents = Vec::new();
+            DeflateDecoder::new(&compressed[..]).read_to_end(&mut contents)?;
+            Ok(contents)
+        }
+        method => Err(ImportError::InvalidArchive(format!(
+            "{} is compressed with unsupported method {}",
+            name, method
+        ))),
+    }
+}
+
+// Reads every file in a ZIP archive held in memory.
+pub(crate) fn read_archive(
+    data: &[u8],
+    password: Option<&[u8]>,
+) -> Result<Vec<(String, Vec<u8>)>, ImportError> {
+    read_directory(data)?
+        .into_iter()
+        .map(|entry| {
+            let contents = read_entry(data, &entry, password)?;
+            Ok((entry.name, contents))
+        })
+        .collect()
+}
+
+// Builds an archive of stored entries for testing readers.
+#[cfg(test)]
+pub(crate) fn build_archive(entries: &[(&str, &[u8])], password: Option<&[u8]>) -> Vec<u8> {
+    fn put(archive: &mut Vec<u8>, values: &[(u32, usize)]) {
+        for (value, width) in values {
+            archive.extend_from_slice(&value.to_le_bytes()[..*width]);
+        }
+    }
+
+    let mut archive = Vec::new();
+    let mut directory = Vec::new();
+    for (name, data) in entries {
+        let crc = !data.iter().fold(!0, |crc, v| crc32_update(crc, *v));
+        let stored = match password {
+            Some(password) => {
+                let mut crypto = ZipCrypto::new(password);
+                let mut header = vec![0; 11];
+                header.push((crc >> 24) as u8);
+                let mut stored = crypto.encrypt(&header);
+                stored.extend(crypto.encrypt(data));
+                stored
+            }
+            None => data.to_vec(),
+        };
+        let flags = password.is_some() as u32;
+        let (size, stored_size, name_length) =
+            (data.len() as u32, stored.len() as u32, name.len() as u32);
+
+        put(
+            &mut directory,
+            &[
+                (0x02014b50, 4),
+                (10, 2),
+                (10, 2),
+                (flags, 2),
+                (0, 2),
+                (0, 2),
+                (0, 2),
+                (crc, 4),
+                (stored_size, 4),
+                (size, 4),
+                (name_length, 2),
+                (0, 2),
+                (0, 2),
+                (0, 2),
+                (0, 2),
+                (0, 4),
+                (archive.len() as u32, 4),
+            ],
+        );
+        directory.extend_from_slice(name.as_bytes());
+
+        put(
+            &mut archive,
+            &[
+                (0x04034b50, 4),
+                (10, 2),
+                (flags, 2),
+                (0, 2),
+                (0, 2),
+                (0, 2),
+                (crc, 4),
+                (stored_size, 4),
+                (size, 4),
+                (name_length, 2),
+                (0, 2),
+            ],
+        );
+        archive.extend_from_slice(name.as_bytes());
+        archive.extend(stored);
+    }
+
+    let offset = archive.len() as u32;
+    let directory_size = directory.len() as u32;
+    archive.extend(directory);
+    put(
+        &mut archive,
+        &[
+            (0x06054b50, 4),
+            (0, 2),
+            (0, 2),
+            (entries.len() as u32, 2),
+            (entries.len() as u32, 2),
+            (directory_size, 4),
+            (offset, 4),
+            (0, 2),
+        ],
+    );
+    archive
+}
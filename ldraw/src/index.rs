@@ -0,0 +1,239 @@
+use std::{
+    collections::HashMap,
+    io::{Error as IoError, ErrorKind},
+};
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{document::Document, library::PartKind, PartAlias};
+
+// Index is kept in the LDraw directory under this name, next to parts.lst.
+pub const INDEX_FILE_NAME: &str = "parts.idx";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LibraryIndexEntry {
+    // Relative to the LDraw directory, separated with slashes.
+    pub path: String,
+    pub kind: PartKind,
+    pub description: String,
+    pub category: String,
+    pub keywords: Vec<String>,
+}
+
+impl LibraryIndexEntry {
+    // Builds an entry out of headers of a library file. Files outside parts/ and p/ are not
+    // indexed.
+    pub fn from_document(path: &str, document: &Document) -> Option<Self> {
+        let path = path.replace('\\', "/");
+        let kind = if path.starts_with("parts/") {
+            PartKind::Part
+        } else if path.starts_with("p/") {
+            PartKind::Primitive
+        } else {
+            return None;
+        };
+
+        Some(LibraryIndexEntry {
+            path,
+            kind,
+            description: document.description.clone(),
+            category: document.category(),
+            keywords: document.keywords(),
+        })
+    }
+
+    pub fn alias(&self) -> PartAlias {
+        let (_, alias) = self.path.split_once('/').unwrap_or(("", &self.path));
+        PartAlias::from(alias.replace('/', "\\"))
+    }
+}
+
+// Lookup table of every part and primitive in a library, so that parts can be found without
+// probing the disk and searched by their descriptions with library::search.
+#[derive(Clone, Debug, Default)]
+pub struct LibraryIndex {
+    entries: HashMap<PartAlias, LibraryIndexEntry>,
+}
+
+impl LibraryIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Parts take precedence over primitives of the same name, as in resolution.
+    pub fn insert(&mut self, entry: LibraryIndexEntry) {
+        let alias = entry.alias();
+        if let Some(existing) = self.entries.get(&alias) {
+            if existing.kind == PartKind::Part && entry.kind == PartKind::Primitive {
+                return;
+            }
+        }
+        self.entries.insert(alias, entry);
+    }
+
+    pub fn get(&self, alias: &PartAlias) -> Option<&LibraryIndexEntry> {
+        self.entries.get(alias)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn aliases(&self) -> impl Iterator<Item = &PartAlias> {
+        self.entries.keys()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&PartAlias, &LibraryIndexEntry)> {
+        self.entries.iter()
+    }
+}
+
+fn sanitize(value: &str) -> String {
+    value.replace(['\t', '\r', '\n'], " ")
+}
+
+// Reads an index, written as tab separated lines of path, description, category and comma
+// separated keywords.
+pub async fn parse_library_index<T: AsyncBufRead + Unpin>(
+    reader: &mut T,
+) -> Result<LibraryIndex, IoError> {
+    let mut index = LibraryIndex::new();
+    let mut lines = reader.lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = line.split('\t').collect::<Vec<_>>();
+        let &[path, description, category, keywords] = fields.as_slice() else {
+            return Err(IoError::new(
+                ErrorKind::InvalidData,
+                format!("Malformed index entry: {}", line),
+            ));
+        };
+        let kind = if path.starts_with("parts/") {
+            PartKind::Part
+        } else if path.starts_with("p/") {
+            PartKind::Primitive
+        } else {
+            return Err(IoError::new(
+                ErrorKind::InvalidData,
+                format!("Unexpected path in index: {}", path),
+            ));
+        };
+        index.insert(LibraryIndexEntry {
+            path: path.to_string(),
+            kind,
+            description: description.to_string(),
+            category: category.to_string(),
+            keywords: keywords
+                .split(',')
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .collect(),
+        });
+    }
+    Ok(index)
+}
+
+pub async fn write_library_index<W: AsyncWrite + Unpin>(
+    index: &LibraryIndex,
+    writer: &mut W,
+) -> Result<(), IoError> {
+    let mut entries = index.entries.values().collect::<Vec<_>>();
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    writer
+        .write_all(b"# path\tdescription\tcategory\tkeywords\n")
+        .await?;
+    for entry in entries {
+        let keywords = entry
+            .keywords
+            .iter()
+            .map(|v| sanitize(v).replace(',', " "))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let line = format!(
+            "{}\t{}\t{}\t{}\n",
+            sanitize(&entry.path),
+            sanitize(&entry.description),
+            sanitize(&entry.category),
+            keywords
+        );
+        writer.write_all(line.as_bytes()).await?;
+    }
+    writer.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::BufReader;
+
+    use crate::{color::ColorCatalog, library::PartKind, parser::parse_single_document, PartAlias};
+
+    use super::{parse_library_index, write_library_index, LibraryIndex, LibraryIndexEntry};
+
+    async fn entry(path: &str, source: &str) -> LibraryIndexEntry {
+        let document =
+            parse_single_document(&mut BufReader::new(source.as_bytes()), &ColorCatalog::new())
+                .await
+                .unwrap();
+        LibraryIndexEntry::from_document(path, &document).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_library_index() {
+        let mut index = LibraryIndex::new();
+        index.insert(
+            entry(
+                "parts/3001.dat",
+                "0 Brick  2 x  4\n0 Name: 3001.dat\n0 !KEYWORDS Basic, Classic\n",
+            )
+            .await,
+        );
+        index.insert(
+            entry(
+                "parts/s/3001s01.dat",
+                "0 ~Brick  2 x  4 without Front Studs\n0 Name: s\\3001s01.dat\n",
+            )
+            .await,
+        );
+        index.insert(
+            entry(
+                "p/stud.dat",
+                "0 Stud\n0 Name: stud.dat\n0 !CATEGORY Primitive_Stud\n",
+            )
+            .await,
+        );
+
+        let brick = index.get(&PartAlias::from("3001.dat")).unwrap();
+        assert_eq!(brick.category, "Brick");
+        assert_eq!(brick.keywords, vec!["Basic", "Classic"]);
+        assert!(matches!(
+            index.get(&PartAlias::from("S\\3001S01.DAT")).unwrap().kind,
+            PartKind::Part
+        ));
+        let stud = index.get(&PartAlias::from("stud.dat")).unwrap();
+        assert!(matches!(stud.kind, PartKind::Primitive));
+        assert_eq!(stud.category, "Primitive_Stud");
+
+        let mut written = Vec::new();
+        write_library_index(&index, &mut written).await.unwrap();
+        let parsed = parse_library_index(&mut BufReader::new(&written[..]))
+            .await
+            .unwrap();
+        assert_eq!(parsed.len(), 3);
+        for (alias, entry) in index.iter() {
+            assert_eq!(parsed.get(alias), Some(entry));
+        }
+
+        assert!(
+            parse_library_index(&mut BufReader::new(&b"parts/3001.dat\tBrick\n"[..]))
+                .await
+                .is_err()
+        );
+    }
+}
@@ -10,13 +10,21 @@ use cgmath::{
 use serde::de::{Error as DeserializeError, Visitor};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+pub mod build;
 pub mod color;
 pub mod document;
+pub mod edit;
 pub mod elements;
 pub mod error;
+pub mod graph;
+pub mod import;
+pub mod index;
 pub mod library;
 pub mod parser;
 pub mod resolvers;
+#[cfg(test)]
+mod testing;
+pub mod units;
 pub mod writer;
 
 pub type Matrix3 = Matrix3_<f32>;
@@ -39,8 +47,9 @@ impl PartAlias {
         self.original = alias;
     }
 
+    // Only ASCII letters are folded, so that non-ASCII names are kept as they are.
     pub fn normalize(alias: &str) -> String {
-        alias.trim().to_lowercase().replace('\\', "/")
+        alias.trim().to_ascii_lowercase().replace('\\', "/")
     }
 }
 
@@ -181,4 +190,12 @@ mod tests {
         assert_eq!(alias.normalized, "disc.dat");
         assert_eq!(alias.original, "Disc.dat");
     }
+
+    #[test]
+    fn test_part_alias_keeps_non_ascii() {
+        let alias = PartAlias::from("Škoda\\Ärmel.ldr".to_string());
+
+        assert_eq!(alias.normalized, "Škoda/Ärmel.ldr");
+        assert_eq!(alias.original, "Škoda\\Ärmel.ldr");
+    }
 }
@@ -1,7 +1,11 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{hash_map::RandomState, HashMap, HashSet},
+    hash::BuildHasher,
     ops::Deref,
-    sync::{Arc, RwLock},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
 };
 
 use async_trait::async_trait;
@@ -15,7 +19,9 @@ use crate::{
     PartAlias,
 };
 
-#[derive(Serialize, Deserialize, Clone, Copy, Debug, Hash)]
+pub mod search;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Hash, PartialEq, Eq)]
 pub enum PartKind {
     Primitive,
     Part,
@@ -48,10 +54,52 @@ pub trait LibraryLoader {
     ) -> Result<(FileLocation, MultipartDocument), ResolutionError>;
 }
 
-#[derive(Debug, Default)]
-pub struct PartCache {
-    primitives: HashMap<PartAlias, Arc<MultipartDocument>>,
-    parts: HashMap<PartAlias, Arc<MultipartDocument>>,
+// Where a resolved document came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PartSource {
+    Library,
+    Local,
+    // Named fallback the document was fetched from after the loader failed to find it.
+    Fallback(String),
+}
+
+// Loaders tried in order for parts the primary loader could not find, such as the
+// unofficial library or a user-specified URL.
+#[derive(Default)]
+pub struct FallbackChain {
+    loaders: Vec<(String, Box<dyn LibraryLoader>)>,
+}
+
+impl FallbackChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push<L: LibraryLoader + 'static>(&mut self, name: &str, loader: L) {
+        self.loaders.push((name.to_string(), Box::new(loader)));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.loaders.is_empty()
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.loaders.iter().map(|(name, _)| name.as_str()).collect()
+    }
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    document: Arc<MultipartDocument>,
+    size: usize,
+    // Value of the cache's clock when the entry was last registered or queried.
+    last_used: AtomicU64,
+}
+
+impl CacheEntry {
+    fn is_in_use(&self) -> bool {
+        Arc::strong_count(&self.document) > 1 || Arc::weak_count(&self.document) > 0
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -61,60 +109,307 @@ pub enum CacheCollectionStrategy {
     PartsAndPrimitives,
 }
 
-impl Drop for PartCache {
-    fn drop(&mut self) {
-        self.collect(CacheCollectionStrategy::PartsAndPrimitives);
+impl CacheCollectionStrategy {
+    fn includes(self, kind: PartKind) -> bool {
+        matches!(
+            (self, kind),
+            (CacheCollectionStrategy::PartsAndPrimitives, _)
+                | (CacheCollectionStrategy::Parts, PartKind::Part)
+                | (CacheCollectionStrategy::Primitives, PartKind::Primitive)
+        )
     }
 }
 
+const CACHE_SHARDS: usize = 16;
+
+// Part of the budget freed at once when the cache goes over it.
+fn eviction_batch(budget: usize) -> usize {
+    budget / 8
+}
+
+#[derive(Debug, Default)]
+struct CacheShard {
+    primitives: HashMap<PartAlias, CacheEntry>,
+    parts: HashMap<PartAlias, CacheEntry>,
+    fallback_sources: HashMap<PartAlias, String>,
+}
+
+impl CacheShard {
+    fn get(&self, alias: &PartAlias) -> Option<&CacheEntry> {
+        self.parts.get(alias).or_else(|| self.primitives.get(alias))
+    }
+
+    fn entries(&self) -> impl Iterator<Item = (&PartAlias, &CacheEntry)> {
+        self.parts.iter().chain(self.primitives.iter())
+    }
+
+    // Returns the size of documents removed, if any was.
+    fn remove(&mut self, alias: &PartAlias) -> Option<usize> {
+        self.fallback_sources.remove(alias);
+        [self.parts.remove(alias), self.primitives.remove(alias)]
+            .into_iter()
+            .flatten()
+            .map(|v| v.size)
+            .reduce(|a, b| a + b)
+    }
+
+    // Returns the number and size of documents removed.
+    fn collect(&mut self, collection_strategy: CacheCollectionStrategy) -> (usize, usize) {
+        let (mut count, mut size) = (0, 0);
+        for (kind, entries) in [
+            (PartKind::Part, &mut self.parts),
+            (PartKind::Primitive, &mut self.primitives),
+        ] {
+            if collection_strategy.includes(kind) {
+                entries.retain(|_, v| {
+                    let keep = v.is_in_use();
+                    if !keep {
+                        count += 1;
+                        size += v.size;
+                    }
+                    keep
+                });
+            }
+        }
+        let (parts, primitives) = (&self.parts, &self.primitives);
+        self.fallback_sources
+            .retain(|k, _| parts.contains_key(k) || primitives.contains_key(k));
+        (count, size)
+    }
+}
+
+#[derive(Debug, Default)]
+struct PartCacheState {
+    shards: [RwLock<CacheShard>; CACHE_SHARDS],
+    hasher: RandomState,
+
+    clock: AtomicU64,
+    // Estimated size of every cached document in bytes.
+    size: AtomicUsize,
+    budget: RwLock<Option<usize>>,
+    // Size of the cache when eviction last failed to bring it within the budget, as every
+    // document left was in use.
+    stalled_at: AtomicUsize,
+}
+
+// Documents read from the library, shared across resolutions so that each is parsed once.
+// Clones are handles to the same cache, which can be used from any number of tasks at once.
+// Documents are spread over separately locked shards so that resolutions running in parallel
+// do not wait on each other.
+#[derive(Clone, Debug, Default)]
+pub struct PartCache {
+    state: Arc<PartCacheState>,
+}
+
 impl PartCache {
     pub fn new() -> Self {
         Self::default()
     }
 
-    pub fn register(&mut self, kind: PartKind, alias: PartAlias, document: Arc<MultipartDocument>) {
-        match kind {
-            PartKind::Part => self.parts.insert(alias, document),
-            PartKind::Primitive => self.primitives.insert(alias, document),
+    // Keeps the cache within the budget in bytes by evicting documents least recently used
+    // whenever one is registered. See set_budget.
+    pub fn with_budget(budget: usize) -> Self {
+        let cache = Self::default();
+        cache.set_budget(Some(budget));
+        cache
+    }
+
+    // Documents still in use elsewhere are never evicted, as doing so would free nothing, so
+    // the cache may stay over the budget for a while. Sizes are estimated, so the budget is
+    // not exact either.
+    pub fn set_budget(&self, budget: Option<usize>) {
+        *self.state.budget.write().unwrap() = budget;
+        self.evict();
+    }
+
+    pub fn budget(&self) -> Option<usize> {
+        *self.state.budget.read().unwrap()
+    }
+
+    // Estimated memory taken by cached documents in bytes.
+    pub fn size(&self) -> usize {
+        self.state.size.load(Ordering::Relaxed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.state
+            .shards
+            .iter()
+            .map(|v| {
+                let shard = v.read().unwrap();
+                shard.parts.len() + shard.primitives.len()
+            })
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn shard(&self, alias: &PartAlias) -> &RwLock<CacheShard> {
+        let index = self.state.hasher.hash_one(alias) as usize % CACHE_SHARDS;
+        &self.state.shards[index]
+    }
+
+    fn tick(&self) -> u64 {
+        self.state.clock.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn insert(
+        &self,
+        kind: PartKind,
+        alias: PartAlias,
+        document: Arc<MultipartDocument>,
+        fallback: Option<&str>,
+    ) {
+        let entry = CacheEntry {
+            size: document.estimated_size(),
+            document,
+            last_used: AtomicU64::new(self.tick()),
+        };
+        self.state.size.fetch_add(entry.size, Ordering::Relaxed);
+
+        let previous = {
+            let mut shard = self.shard(&alias).write().unwrap();
+            match fallback {
+                Some(name) => shard
+                    .fallback_sources
+                    .insert(alias.clone(), name.to_string()),
+                None => shard.fallback_sources.remove(&alias),
+            };
+            match kind {
+                PartKind::Part => shard.parts.insert(alias, entry),
+                PartKind::Primitive => shard.primitives.insert(alias, entry),
+            }
         };
+        if let Some(previous) = previous {
+            self.state.size.fetch_sub(previous.size, Ordering::Relaxed);
+        }
+
+        // Scanning is skipped until another batch has been added since the last eviction that
+        // found nothing to evict, as documents in use tend to stay so for a while.
+        if let Some(budget) = self.budget() {
+            let stalled_at = self.state.stalled_at.load(Ordering::Relaxed);
+            if self.size() >= stalled_at.saturating_add(eviction_batch(budget)) {
+                self.evict();
+            }
+        }
+    }
+
+    pub fn register(&self, kind: PartKind, alias: PartAlias, document: Arc<MultipartDocument>) {
+        self.insert(kind, alias, document, None);
+    }
+
+    // Registers a document fetched from a fallback, remembering which one.
+    pub fn register_fallback(
+        &self,
+        kind: PartKind,
+        alias: PartAlias,
+        document: Arc<MultipartDocument>,
+        source: &str,
+    ) {
+        self.insert(kind, alias, document, Some(source));
+    }
+
+    pub fn source(&self, alias: &PartAlias) -> PartSource {
+        match self
+            .shard(alias)
+            .read()
+            .unwrap()
+            .fallback_sources
+            .get(alias)
+        {
+            Some(name) => PartSource::Fallback(name.clone()),
+            None => PartSource::Library,
+        }
+    }
+
+    pub fn kind(&self, alias: &PartAlias) -> Option<PartKind> {
+        let shard = self.shard(alias).read().unwrap();
+        if shard.parts.contains_key(alias) {
+            Some(PartKind::Part)
+        } else if shard.primitives.contains_key(alias) {
+            Some(PartKind::Primitive)
+        } else {
+            None
+        }
     }
 
     pub fn query(&self, alias: &PartAlias) -> Option<Arc<MultipartDocument>> {
-        match self.parts.get(alias) {
-            Some(part) => Some(Arc::clone(part)),
-            None => self.primitives.get(alias).cloned(),
+        let shard = self.shard(alias).read().unwrap();
+        let entry = shard.get(alias)?;
+        entry.last_used.store(self.tick(), Ordering::Relaxed);
+        Some(Arc::clone(&entry.document))
+    }
+
+    // Forgets a document so that it is read again on next resolution. Returns whether it
+    // was cached.
+    pub fn invalidate(&self, alias: &PartAlias) -> bool {
+        match self.shard(alias).write().unwrap().remove(alias) {
+            Some(size) => {
+                self.state.size.fetch_sub(size, Ordering::Relaxed);
+                true
+            }
+            None => false,
         }
     }
 
-    fn collect_round(&mut self, collection_strategy: CacheCollectionStrategy) -> usize {
-        let prev_size = self.parts.len() + self.primitives.len();
-        match collection_strategy {
-            CacheCollectionStrategy::Parts => {
-                self.parts
-                    .retain(|_, v| Arc::strong_count(v) > 1 || Arc::weak_count(v) > 0);
+    // Evicts documents not in use, least recently used first, once the cache goes over the
+    // budget. Documents are evicted in batches, down to a bit below the budget, so that the
+    // cache is not scanned again on every document registered afterwards. Returns the number
+    // of documents evicted.
+    pub fn evict(&self) -> usize {
+        let Some(budget) = self.budget() else {
+            return 0;
+        };
+        if self.size() <= budget {
+            self.state.stalled_at.store(0, Ordering::Relaxed);
+            return 0;
+        }
+        let target = budget - eviction_batch(budget);
+
+        let mut candidates = Vec::new();
+        for shard in self.state.shards.iter() {
+            let shard = shard.read().unwrap();
+            candidates.extend(
+                shard
+                    .entries()
+                    .filter(|(_, entry)| !entry.is_in_use())
+                    .map(|(alias, entry)| (entry.last_used.load(Ordering::Relaxed), alias.clone())),
+            );
+        }
+        candidates.sort_unstable_by_key(|(last_used, _)| *last_used);
+
+        let mut evicted = 0;
+        for (_, alias) in candidates {
+            if self.size() <= target {
+                break;
             }
-            CacheCollectionStrategy::Primitives => {
-                self.primitives
-                    .retain(|_, v| Arc::strong_count(v) > 1 || Arc::weak_count(v) > 0);
+            let mut shard = self.shard(&alias).write().unwrap();
+            // Another task may have picked it up in the meantime.
+            if shard.get(&alias).is_none_or(CacheEntry::is_in_use) {
+                continue;
             }
-            CacheCollectionStrategy::PartsAndPrimitives => {
-                self.parts
-                    .retain(|_, v| Arc::strong_count(v) > 1 || Arc::weak_count(v) > 0);
-                self.primitives
-                    .retain(|_, v| Arc::strong_count(v) > 1 || Arc::weak_count(v) > 0);
+            if let Some(size) = shard.remove(&alias) {
+                self.state.size.fetch_sub(size, Ordering::Relaxed);
+                evicted += 1;
             }
-        };
-        prev_size - self.parts.len() - self.primitives.len()
+        }
+
+        let size = self.size();
+        self.state
+            .stalled_at
+            .store(if size > budget { size } else { 0 }, Ordering::Relaxed);
+        evicted
     }
 
-    pub fn collect(&mut self, collection_strategy: CacheCollectionStrategy) -> usize {
+    // Drops every document not in use elsewhere. Returns the number of documents dropped.
+    pub fn collect(&self, collection_strategy: CacheCollectionStrategy) -> usize {
         let mut total_collected = 0;
-        loop {
-            let collected = self.collect_round(collection_strategy);
-            if collected == 0 {
-                break;
-            }
-            total_collected += collected;
+        for shard in self.state.shards.iter() {
+            let (count, size) = shard.write().unwrap().collect(collection_strategy);
+            self.state.size.fetch_sub(size, Ordering::Relaxed);
+            total_collected += count;
         }
         total_collected
     }
@@ -145,13 +440,16 @@ pub enum ResolutionState {
 
 struct DependencyResolver<'a, F, L> {
     colors: &'a ColorCatalog,
-    cache: Arc<RwLock<PartCache>>,
+    cache: PartCache,
     local_cache: TransientDocumentCache,
     on_update: &'a F,
     loader: &'a L,
+    fallbacks: &'a FallbackChain,
 
     pub map: HashMap<PartAlias, ResolutionState>,
     pub local_map: HashMap<PartAlias, ResolutionState>,
+    pub sources: HashMap<PartAlias, PartSource>,
+    pub kinds: HashMap<PartAlias, PartKind>,
 }
 
 impl<'a, F: Fn(PartAlias, Result<(), ResolutionError>), L: LibraryLoader>
@@ -159,9 +457,10 @@ impl<'a, F: Fn(PartAlias, Result<(), ResolutionError>), L: LibraryLoader>
 {
     pub fn new(
         colors: &'a ColorCatalog,
-        cache: Arc<RwLock<PartCache>>,
+        cache: PartCache,
         on_update: &'a F,
         loader: &'a L,
+        fallbacks: &'a FallbackChain,
     ) -> DependencyResolver<'a, F, L> {
         DependencyResolver {
             colors,
@@ -169,8 +468,11 @@ impl<'a, F: Fn(PartAlias, Result<(), ResolutionError>), L: LibraryLoader>
             local_cache: TransientDocumentCache::default(),
             on_update,
             loader,
+            fallbacks,
             map: HashMap::new(),
             local_map: HashMap::new(),
+            sources: HashMap::new(),
+            kinds: HashMap::new(),
         }
     }
 
@@ -209,6 +511,7 @@ impl<'a, F: Fn(PartAlias, Result<(), ResolutionError>), L: LibraryLoader>
             if local {
                 if let Some(cached) = self.local_cache.query(alias) {
                     self.scan_dependencies_with_parent(None, Arc::clone(&cached), true);
+                    self.sources.insert(alias.clone(), PartSource::Local);
 
                     self.put_state(
                         alias.clone(),
@@ -219,9 +522,14 @@ impl<'a, F: Fn(PartAlias, Result<(), ResolutionError>), L: LibraryLoader>
                 }
             }
 
-            let cached = self.cache.read().unwrap().query(alias);
+            let cached = self.cache.query(alias);
             if let Some(cached) = cached {
                 self.scan_dependencies_with_parent(None, Arc::clone(&cached), false);
+                let source = self.cache.source(alias);
+                self.sources.insert(alias.clone(), source);
+                if let Some(kind) = self.cache.kind(alias) {
+                    self.kinds.insert(alias.clone(), kind);
+                }
 
                 self.put_state(
                     alias.clone(),
@@ -265,6 +573,7 @@ impl<'a, F: Fn(PartAlias, Result<(), ResolutionError>), L: LibraryLoader>
             if local {
                 if let Some(cached) = self.local_cache.query(alias) {
                     self.scan_dependencies_with_parent(None, Arc::clone(&cached), true);
+                    self.sources.insert(alias.clone(), PartSource::Local);
 
                     self.put_state(
                         alias.clone(),
@@ -275,9 +584,14 @@ impl<'a, F: Fn(PartAlias, Result<(), ResolutionError>), L: LibraryLoader>
                 }
             }
 
-            let cached = self.cache.read().unwrap().query(alias);
+            let cached = self.cache.query(alias);
             if let Some(cached) = cached {
                 self.scan_dependencies_with_parent(None, Arc::clone(&cached), false);
+                let source = self.cache.source(alias);
+                self.sources.insert(alias.clone(), source);
+                if let Some(kind) = self.cache.kind(alias) {
+                    self.kinds.insert(alias.clone(), kind);
+                }
 
                 self.put_state(
                     alias.clone(),
@@ -291,6 +605,31 @@ impl<'a, F: Fn(PartAlias, Result<(), ResolutionError>), L: LibraryLoader>
         }
     }
 
+    // Tries fallbacks in order when the loader fails, returning the name of the one that
+    // found the document. The loader's error is reported if none of them do.
+    async fn load_ref(
+        &self,
+        alias: PartAlias,
+        local: bool,
+    ) -> Result<(FileLocation, Option<&'a str>, MultipartDocument), ResolutionError> {
+        let error = match self
+            .loader
+            .load_ref(alias.clone(), local, self.colors)
+            .await
+        {
+            Ok((location, document)) => return Ok((location, None, document)),
+            Err(e) => e,
+        };
+        for (name, loader) in self.fallbacks.loaders.iter() {
+            if let Ok((location, document)) =
+                loader.load_ref(alias.clone(), local, self.colors).await
+            {
+                return Ok((location, Some(name.as_str()), document));
+            }
+        }
+        Err(error)
+    }
+
     pub async fn resolve_pending_dependencies(&mut self) -> bool {
         let mut pending = self
             .local_map
@@ -311,33 +650,48 @@ impl<'a, F: Fn(PartAlias, Result<(), ResolutionError>), L: LibraryLoader>
 
         let futs = pending
             .iter()
-            .map(|(alias, local)| self.loader.load_ref(alias.clone(), *local, self.colors))
+            .map(|(alias, local)| self.load_ref(alias.clone(), *local))
             .collect::<Vec<_>>();
 
         let result = join_all(futs).await;
 
         for ((alias, mut local), result) in pending.iter().zip(result) {
             let state = match result {
-                Ok((location, document)) => {
+                Ok((location, fallback, document)) => {
                     (self.on_update)(alias.clone(), Ok(()));
                     let document = Arc::new(document);
-                    match location {
+                    let source = match location {
                         FileLocation::Library(kind) => {
                             if local {
                                 self.clear_state(alias, true);
                             }
                             local = false;
-                            self.cache.write().unwrap().register(
-                                kind,
-                                alias.clone(),
-                                Arc::clone(&document),
-                            );
+                            self.kinds.insert(alias.clone(), kind);
+                            let cache = &self.cache;
+                            match fallback {
+                                Some(name) => cache.register_fallback(
+                                    kind,
+                                    alias.clone(),
+                                    Arc::clone(&document),
+                                    name,
+                                ),
+                                None => cache.register(kind, alias.clone(), Arc::clone(&document)),
+                            }
+                            PartSource::Library
                         }
                         FileLocation::Local => {
                             self.local_cache
                                 .register(alias.clone(), Arc::clone(&document));
+                            PartSource::Local
                         }
                     };
+                    self.sources.insert(
+                        alias.clone(),
+                        match fallback {
+                            Some(name) => PartSource::Fallback(name.to_string()),
+                            None => source,
+                        },
+                    );
 
                     self.scan_dependencies_with_parent(None, Arc::clone(&document), local);
 
@@ -359,6 +713,8 @@ impl<'a, F: Fn(PartAlias, Result<(), ResolutionError>), L: LibraryLoader>
 pub struct ResolutionResult {
     library_entries: HashMap<PartAlias, Arc<MultipartDocument>>,
     local_entries: HashMap<PartAlias, Arc<MultipartDocument>>,
+    sources: HashMap<PartAlias, PartSource>,
+    kinds: HashMap<PartAlias, PartKind>,
 }
 
 impl ResolutionResult {
@@ -378,6 +734,38 @@ impl ResolutionResult {
             .map(|e| (Arc::clone(e), false))
     }
 
+    pub fn merge(&mut self, other: ResolutionResult) {
+        self.library_entries.extend(other.library_entries);
+        self.local_entries.extend(other.local_entries);
+        self.sources.extend(other.sources);
+        self.kinds.extend(other.kinds);
+    }
+
+    // Where a resolved document came from. Subparts of documents are not recorded.
+    pub fn source(&self, alias: &PartAlias) -> Option<&PartSource> {
+        self.sources.get(alias)
+    }
+
+    // Whether a document resolved from the library is a part or a primitive. Local documents
+    // are neither.
+    pub fn kind(&self, alias: &PartAlias) -> Option<PartKind> {
+        self.kinds.get(alias).copied()
+    }
+
+    // Parts fetched from fallbacks along with their names.
+    pub fn fallback_parts(&self) -> Vec<(&PartAlias, &str)> {
+        let mut result = self
+            .sources
+            .iter()
+            .filter_map(|(alias, source)| match source {
+                PartSource::Fallback(name) => Some((alias, name.as_str())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        result.sort_by(|a, b| a.0.normalized.cmp(&b.0.normalized));
+        result
+    }
+
     pub fn list_dependencies(&self) -> HashSet<PartAlias> {
         let mut result = HashSet::new();
 
@@ -388,45 +776,91 @@ impl ResolutionResult {
     }
 }
 
+impl<F, L> From<DependencyResolver<'_, F, L>> for ResolutionResult {
+    fn from(resolver: DependencyResolver<'_, F, L>) -> Self {
+        let library_entries = resolver
+            .map
+            .into_iter()
+            .filter_map(|(k, v)| match v {
+                ResolutionState::Associated(e) => Some((k, e)),
+                _ => None,
+            })
+            .collect::<HashMap<_, _>>();
+        let local_entries = resolver
+            .local_map
+            .into_iter()
+            .filter_map(|(k, v)| match v {
+                ResolutionState::Associated(e) => Some((k, e)),
+                _ => None,
+            })
+            .collect::<HashMap<_, _>>();
+        let sources = resolver
+            .sources
+            .into_iter()
+            .filter(|(k, _)| library_entries.contains_key(k) || local_entries.contains_key(k))
+            .collect::<HashMap<_, _>>();
+        let kinds = resolver
+            .kinds
+            .into_iter()
+            .filter(|(k, _)| library_entries.contains_key(k))
+            .collect::<HashMap<_, _>>();
+
+        ResolutionResult {
+            library_entries,
+            local_entries,
+            sources,
+            kinds,
+        }
+    }
+}
+
 pub async fn resolve_dependencies_multipart<F, L>(
     document: &MultipartDocument,
-    cache: Arc<RwLock<PartCache>>,
+    cache: PartCache,
+    colors: &ColorCatalog,
+    loader: &L,
+    on_update: &F,
+) -> ResolutionResult
+where
+    F: Fn(PartAlias, Result<(), ResolutionError>),
+    L: LibraryLoader,
+{
+    resolve_dependencies_multipart_with_fallbacks(
+        document,
+        cache,
+        colors,
+        loader,
+        &FallbackChain::default(),
+        on_update,
+    )
+    .await
+}
+
+// Same as resolve_dependencies_multipart(), trying given fallbacks for parts the loader
+// cannot find before reporting them missing.
+pub async fn resolve_dependencies_multipart_with_fallbacks<F, L>(
+    document: &MultipartDocument,
+    cache: PartCache,
     colors: &ColorCatalog,
     loader: &L,
+    fallbacks: &FallbackChain,
     on_update: &F,
 ) -> ResolutionResult
 where
     F: Fn(PartAlias, Result<(), ResolutionError>),
     L: LibraryLoader,
 {
-    let mut resolver = DependencyResolver::new(colors, cache, on_update, loader);
+    let mut resolver = DependencyResolver::new(colors, cache, on_update, loader, fallbacks);
 
     resolver.scan_dependencies_with_parent(None, document, true);
     while resolver.resolve_pending_dependencies().await {}
 
-    ResolutionResult {
-        library_entries: resolver
-            .map
-            .into_iter()
-            .filter_map(|(k, v)| match v {
-                ResolutionState::Associated(e) => Some((k, e)),
-                _ => None,
-            })
-            .collect::<HashMap<_, _>>(),
-        local_entries: resolver
-            .local_map
-            .into_iter()
-            .filter_map(|(k, v)| match v {
-                ResolutionState::Associated(e) => Some((k, e)),
-                _ => None,
-            })
-            .collect::<HashMap<_, _>>(),
-    }
+    resolver.into()
 }
 
 pub async fn resolve_dependencies<F, L>(
     document: &Document,
-    cache: Arc<RwLock<PartCache>>,
+    cache: PartCache,
     colors: &ColorCatalog,
     loader: &L,
     on_update: &F,
@@ -435,41 +869,140 @@ where
     F: Fn(PartAlias, Result<(), ResolutionError>),
     L: LibraryLoader,
 {
-    let mut resolver = DependencyResolver::new(colors, cache, on_update, loader);
+    let fallbacks = FallbackChain::default();
+    let mut resolver = DependencyResolver::new(colors, cache, on_update, loader, &fallbacks);
 
     resolver.scan_dependencies(document, true);
     while resolver.resolve_pending_dependencies().await {}
 
-    ResolutionResult {
-        library_entries: resolver
-            .map
-            .into_iter()
-            .filter_map(|(k, v)| match v {
-                ResolutionState::Associated(e) => Some((k, e)),
-                _ => None,
-            })
-            .collect::<HashMap<_, _>>(),
-        local_entries: resolver
-            .local_map
-            .into_iter()
-            .filter_map(|(k, v)| match v {
-                ResolutionState::Associated(e) => Some((k, e)),
-                _ => None,
-            })
-            .collect::<HashMap<_, _>>(),
+    resolver.into()
+}
+
+const MAX_SUGGESTIONS: usize = 5;
+const DOCUMENT_EXTENSIONS: [&str; 3] = ["dat", "ldr", "mpd"];
+
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { diagonal } else { diagonal + 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = cost.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+fn split_extension(name: &str) -> (&str, Option<&str>) {
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if DOCUMENT_EXTENSIONS.contains(&ext) => (stem, Some(ext)),
+        _ => (name, None),
     }
 }
 
+// Picks near-miss aliases for an unresolved one. Names differing only by a document
+// extension (.dat, .ldr, .mpd) come first, followed by the closest ones by edit distance.
+pub fn suggest_aliases<'a, I: IntoIterator<Item = &'a PartAlias>>(
+    alias: &PartAlias,
+    catalog: I,
+) -> Vec<PartAlias> {
+    let name = alias.normalized.as_str();
+    let (stem, _) = split_extension(name);
+    let length = name.chars().count();
+    let threshold = (length / 4).clamp(1, 3);
+
+    // Names differing in length by more than the threshold are too far apart anyway, and
+    // dropping them early spares computing edit distances against the whole library.
+    let mut candidates = catalog
+        .into_iter()
+        .filter(|v| v.normalized != alias.normalized)
+        .filter(|v| {
+            v.normalized.starts_with(stem)
+                || v.normalized.chars().count().abs_diff(length) <= threshold
+        })
+        .filter_map(|v| {
+            let distance = if split_extension(&v.normalized).0 == stem {
+                0
+            } else {
+                edit_distance(name, &v.normalized)
+            };
+            if distance <= threshold {
+                Some((distance, v))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    candidates.sort_by(|a, b| {
+        a.0.cmp(&b.0)
+            .then_with(|| a.1.normalized.cmp(&b.1.normalized))
+    });
+    candidates.dedup_by(|a, b| a.1.normalized == b.1.normalized);
+
+    candidates
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, v)| v.clone())
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
-    use std::{collections::HashMap, sync::Arc};
+    use std::{
+        collections::HashMap,
+        sync::{Arc, RwLock},
+    };
 
-    use super::{PartCache, PartKind};
+    use async_trait::async_trait;
+
+    use super::{
+        edit_distance, resolve_dependencies_multipart_with_fallbacks, suggest_aliases,
+        CacheCollectionStrategy, FallbackChain, FileLocation, LibraryLoader, PartCache, PartKind,
+        PartSource,
+    };
     use crate::{
+        color::ColorCatalog,
         document::{BfcCertification, Document, MultipartDocument},
+        error::ResolutionError,
+        testing::{document, multipart, reference},
         PartAlias,
     };
 
+    // Serves documents with given names, each referring to the listed parts.
+    struct MockLoader(Vec<(&'static str, Vec<&'static str>)>);
+
+    #[async_trait(?Send)]
+    impl LibraryLoader for MockLoader {
+        async fn load_colors(&self) -> Result<ColorCatalog, ResolutionError> {
+            Ok(ColorCatalog::new())
+        }
+
+        async fn load_ref(
+            &self,
+            alias: PartAlias,
+            _local: bool,
+            _colors: &ColorCatalog,
+        ) -> Result<(FileLocation, MultipartDocument), ResolutionError> {
+            let (name, refs) = self
+                .0
+                .iter()
+                .find(|(name, _)| alias.normalized == *name)
+                .ok_or(ResolutionError::FileNotFound {
+                    suggestions: Vec::new(),
+                })?;
+            Ok((FileLocation::Library(PartKind::Part), model(name, refs)))
+        }
+    }
+
+    fn model(name: &str, refs: &[&str]) -> MultipartDocument {
+        multipart(document(name, refs.iter().map(|v| reference(v)).collect()))
+    }
+
     #[test]
     fn test_part_cache_query_existing() {
         let document = MultipartDocument {
@@ -484,7 +1017,7 @@ mod tests {
             subparts: HashMap::new(),
         };
 
-        let mut cache = PartCache::new();
+        let cache = PartCache::new();
 
         let existing_key = PartAlias::from("existing".to_string());
         let document = Arc::new(document);
@@ -492,6 +1025,7 @@ mod tests {
         cache.register(PartKind::Primitive, existing_key.clone(), document.clone());
 
         assert_eq!(cache.query(&existing_key).unwrap(), document);
+        assert_eq!(cache.kind(&existing_key), Some(PartKind::Primitive));
     }
 
     #[test]
@@ -501,4 +1035,202 @@ mod tests {
 
         assert!(cache.query(&missing_key).is_none());
     }
+
+    #[test]
+    fn test_part_cache_invalidate() {
+        let document = Arc::new(MultipartDocument {
+            body: Document {
+                name: "Doc".to_string(),
+                author: "Author".to_string(),
+                description: "Description".to_string(),
+                bfc: BfcCertification::NoCertify,
+                headers: vec![],
+                commands: vec![],
+            },
+            subparts: HashMap::new(),
+        });
+
+        let cache = PartCache::new();
+        let key = PartAlias::from("3001.dat");
+        cache.register(PartKind::Part, key.clone(), document);
+
+        assert!(cache.invalidate(&key));
+        assert!(cache.query(&key).is_none());
+        assert!(!cache.invalidate(&key));
+    }
+
+    #[test]
+    fn test_part_cache_budget() {
+        let document = |description: &str| {
+            Arc::new(MultipartDocument {
+                body: Document {
+                    description: description.to_string(),
+                    ..Default::default()
+                },
+                subparts: HashMap::new(),
+            })
+        };
+        let size = document("a").estimated_size();
+
+        let cache = PartCache::with_budget(size * 4);
+        let [a, b, c, d, e] =
+            ["a", "b", "c", "d", "e"].map(|v| PartAlias::from(format!("{}.dat", v)));
+        for (alias, description) in [(&a, "a"), (&b, "b"), (&c, "c"), (&d, "d")] {
+            cache.register(PartKind::Part, alias.clone(), document(description));
+        }
+        assert_eq!(cache.size(), size * 4);
+
+        // a was used more recently than b and c, so those go first. Documents are evicted
+        // down to below the budget, so two go at once.
+        assert!(cache.query(&a).is_some());
+        cache.register(PartKind::Primitive, e.clone(), document("e"));
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.size(), size * 3);
+        assert!(cache.query(&b).is_none());
+        assert!(cache.query(&c).is_none());
+        assert!(cache.query(&a).is_some());
+
+        // Documents in use are kept even if it means going over the budget.
+        let in_use = cache.query(&a).unwrap();
+        cache.set_budget(Some(0));
+        assert_eq!(cache.len(), 1);
+        assert!(cache.query(&a).is_some());
+        drop(in_use);
+        assert_eq!(cache.evict(), 1);
+        assert!(cache.is_empty());
+        assert_eq!(cache.size(), 0);
+
+        let cache = PartCache::new();
+        cache.register(PartKind::Part, a.clone(), document("a"));
+        cache.register(PartKind::Part, a.clone(), document("a"));
+        assert_eq!(cache.size(), size);
+        cache.collect(CacheCollectionStrategy::Parts);
+        assert_eq!(cache.size(), 0);
+    }
+
+    #[test]
+    fn test_part_cache_shared() {
+        let cache = PartCache::new();
+        let document = MultipartDocument {
+            body: Document::default(),
+            subparts: HashMap::new(),
+        };
+
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let (cache, document) = (cache.clone(), &document);
+                scope.spawn(move || {
+                    for index in 0..100 {
+                        let alias = PartAlias::from(format!("{}-{}.dat", thread, index));
+                        cache.register(PartKind::Part, alias.clone(), Arc::new(document.clone()));
+                        assert!(cache.query(&alias).is_some());
+                    }
+                });
+            }
+        });
+
+        assert_eq!(cache.len(), 400);
+        assert_eq!(cache.size(), document.estimated_size() * 400);
+        assert_eq!(
+            cache.collect(CacheCollectionStrategy::PartsAndPrimitives),
+            400
+        );
+        assert_eq!(cache.size(), 0);
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("3001.dat", "3001.dat"), 0);
+        assert_eq!(edit_distance("3001.dat", "3010.dat"), 2);
+        assert_eq!(edit_distance("3001.dat", "3001a.dat"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn test_suggest_aliases() {
+        let catalog = [
+            "3001.dat",
+            "3002.dat",
+            "3003.dat",
+            "3010.dat",
+            "stud.dat",
+            "model.ldr",
+        ]
+        .iter()
+        .map(|v| PartAlias::from(*v))
+        .collect::<Vec<_>>();
+
+        let names = |alias: &str| {
+            suggest_aliases(&PartAlias::from(alias), &catalog)
+                .into_iter()
+                .map(|v| v.normalized)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(names("3001.ldr"), vec!["3001.dat"]);
+        assert_eq!(
+            names("3011.dat"),
+            vec!["3001.dat", "3010.dat", "3002.dat", "3003.dat"]
+        );
+        assert_eq!(names("model.dat"), vec!["model.ldr"]);
+        assert_eq!(names("stub.dat"), vec!["stud.dat"]);
+        assert!(names("technic.dat").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_with_fallbacks() {
+        let loader = MockLoader(vec![("3001.dat", vec!["stud.dat"]), ("stud.dat", vec![])]);
+        let mut fallbacks = FallbackChain::new();
+        fallbacks.push("empty", MockLoader(vec![]));
+        fallbacks.push(
+            "unofficial",
+            MockLoader(vec![("u9999.dat", vec!["stud.dat"]), ("stud.dat", vec![])]),
+        );
+
+        let cache = PartCache::new();
+        let model = model("model.ldr", &["3001.dat", "u9999.dat", "missing.dat"]);
+        let failures = RwLock::new(Vec::new());
+        let result = resolve_dependencies_multipart_with_fallbacks(
+            &model,
+            cache.clone(),
+            &ColorCatalog::new(),
+            &loader,
+            &fallbacks,
+            &|alias, result| {
+                if result.is_err() {
+                    failures.write().unwrap().push(alias.normalized);
+                }
+            },
+        )
+        .await;
+
+        let source = |alias: &str| result.source(&PartAlias::from(alias)).cloned();
+        assert_eq!(source("3001.dat"), Some(PartSource::Library));
+        assert_eq!(source("stud.dat"), Some(PartSource::Library));
+        assert_eq!(
+            source("u9999.dat"),
+            Some(PartSource::Fallback("unofficial".into()))
+        );
+        assert_eq!(source("missing.dat"), None);
+        assert_eq!(*failures.read().unwrap(), vec!["missing.dat".to_string()]);
+        assert_eq!(
+            result.fallback_parts(),
+            vec![(&PartAlias::from("u9999.dat"), "unofficial")]
+        );
+
+        // Provenance survives being served from the cache.
+        let result = resolve_dependencies_multipart_with_fallbacks(
+            &model,
+            cache.clone(),
+            &ColorCatalog::new(),
+            &MockLoader(vec![]),
+            &FallbackChain::new(),
+            &|_, _| {},
+        )
+        .await;
+        assert_eq!(
+            result.source(&PartAlias::from("u9999.dat")),
+            Some(&PartSource::Fallback("unofficial".into()))
+        );
+    }
 }
@@ -0,0 +1,306 @@
+use std::{cmp::Ordering, collections::BTreeMap};
+
+use crate::{
+    index::{LibraryIndex, LibraryIndexEntry},
+    library::{edit_distance, PartKind},
+    PartAlias,
+};
+
+#[derive(Clone, Debug)]
+pub struct SearchOptions {
+    // Only parts of the category, compared case-insensitively.
+    pub category: Option<String>,
+    pub include_primitives: bool,
+    // Subparts, moved, aliased and obsolete parts, which are not meant to be placed directly.
+    pub include_hidden: bool,
+    pub limit: Option<usize>,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            category: None,
+            include_primitives: false,
+            include_hidden: false,
+            limit: Some(100),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchHit<'a> {
+    pub alias: &'a PartAlias,
+    pub entry: &'a LibraryIndexEntry,
+    pub score: u32,
+}
+
+fn is_hidden(alias: &PartAlias, entry: &LibraryIndexEntry) -> bool {
+    alias.normalized.contains('/')
+        || entry.description.starts_with(['~', '_', '='])
+        || entry.category.eq_ignore_ascii_case("Moved")
+}
+
+fn is_visible(alias: &PartAlias, entry: &LibraryIndexEntry, options: &SearchOptions) -> bool {
+    (options.include_primitives || entry.kind == PartKind::Part)
+        && (options.include_hidden || !is_hidden(alias, entry))
+        && options
+            .category
+            .as_ref()
+            .is_none_or(|v| entry.category.eq_ignore_ascii_case(v))
+}
+
+fn part_number(alias: &PartAlias) -> &str {
+    let name = alias.normalized.rsplit('/').next().unwrap_or_default();
+    name.rsplit_once('.').map_or(name, |(stem, _)| stem)
+}
+
+// Scores how well a lowercased term matches an entry, or 0 if it does not. Part numbers
+// weigh the most, then words of the description, keywords and the category. Misspelt words
+// are matched loosely.
+fn score_term(alias: &PartAlias, entry: &LibraryIndexEntry, term: &str) -> u32 {
+    let number = part_number(alias);
+    let description = entry.description.to_ascii_lowercase();
+    let words = description.split_whitespace().collect::<Vec<_>>();
+    let keywords = entry
+        .keywords
+        .iter()
+        .map(|v| v.to_ascii_lowercase())
+        .collect::<Vec<_>>();
+
+    let score = [
+        (100, number == term),
+        (60, number.starts_with(term)),
+        (50, words.contains(&term)),
+        (40, keywords.iter().any(|v| v == term)),
+        (30, entry.category.eq_ignore_ascii_case(term)),
+        (30, words.iter().any(|v| v.starts_with(term))),
+        (25, number.contains(term)),
+        (20, keywords.iter().any(|v| v.contains(term))),
+        // Dimensions are written spaced out as in "2 x 4", but searched for as "2x4".
+        (20, words.concat().contains(term)),
+    ]
+    .into_iter()
+    .filter_map(|(score, matched)| matched.then_some(score))
+    .max()
+    .unwrap_or(0);
+
+    let length = term.chars().count();
+    if score == 0 && length >= 4 {
+        let threshold = (length / 4).min(2);
+        let misspelt = words
+            .iter()
+            .copied()
+            .chain(keywords.iter().map(String::as_str))
+            .any(|v| edit_distance(term, v) <= threshold);
+        if misspelt {
+            return 10;
+        }
+    }
+    score
+}
+
+// Compares names so that numbers in them are ordered by value, e.g. 3001 before 30010.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let take = |it: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut digits = String::new();
+                    while let Some(v) = it.next_if(char::is_ascii_digit) {
+                        digits.push(v);
+                    }
+                    digits
+                };
+                let (x, y) = (take(&mut a), take(&mut b));
+                let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                match x.len().cmp(&y.len()).then_with(|| x.cmp(y)) {
+                    Ordering::Equal => (),
+                    v => return v,
+                }
+            }
+            (Some(x), Some(y)) => match x.cmp(&y) {
+                Ordering::Equal => {
+                    a.next();
+                    b.next();
+                }
+                v => return v,
+            },
+        }
+    }
+}
+
+// Finds parts matching every word of the query, best matches first. An empty query lists
+// everything allowed by the options in the order of part numbers.
+pub fn search<'a>(
+    index: &'a LibraryIndex,
+    query: &str,
+    options: &SearchOptions,
+) -> Vec<SearchHit<'a>> {
+    let terms = query
+        .split_whitespace()
+        .map(|v| v.to_ascii_lowercase())
+        .collect::<Vec<_>>();
+
+    let mut hits = index
+        .iter()
+        .filter(|(alias, entry)| is_visible(alias, entry, options))
+        .filter_map(|(alias, entry)| {
+            let mut score = 0;
+            for term in terms.iter() {
+                match score_term(alias, entry, term) {
+                    0 => return None,
+                    v => score += v,
+                }
+            }
+            Some(SearchHit {
+                alias,
+                entry,
+                score,
+            })
+        })
+        .collect::<Vec<_>>();
+    hits.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then_with(|| natural_cmp(&a.alias.normalized, &b.alias.normalized))
+    });
+    if let Some(limit) = options.limit {
+        hits.truncate(limit);
+    }
+    hits
+}
+
+// Lists categories with the number of parts in each, in alphabetical order.
+pub fn categories(index: &LibraryIndex, options: &SearchOptions) -> Vec<(String, usize)> {
+    let mut result = BTreeMap::new();
+    for (alias, entry) in index.iter() {
+        if !entry.category.is_empty() && is_visible(alias, entry, options) {
+            *result.entry(entry.category.clone()).or_insert(0) += 1;
+        }
+    }
+    result.into_iter().collect()
+}
+
+// Lists parts of a category in the order of part numbers.
+pub fn browse<'a>(
+    index: &'a LibraryIndex,
+    category: &str,
+    options: &SearchOptions,
+) -> Vec<SearchHit<'a>> {
+    let options = SearchOptions {
+        category: Some(category.to_string()),
+        ..options.clone()
+    };
+    search(index, "", &options)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        index::{LibraryIndex, LibraryIndexEntry},
+        library::PartKind,
+    };
+
+    use super::{browse, categories, search, SearchOptions};
+
+    fn index() -> LibraryIndex {
+        let mut index = LibraryIndex::new();
+        for (path, description, category, keywords) in [
+            ("parts/3001.dat", "Brick  2 x  4", "Brick", vec!["Classic"]),
+            ("parts/3003.dat", "Brick  2 x  2", "Brick", vec![]),
+            (
+                "parts/30010.dat",
+                "Brick  1 x  2 x  3 with Window",
+                "Brick",
+                vec![],
+            ),
+            ("parts/3020.dat", "Plate  2 x  4", "Plate", vec![]),
+            ("parts/3626b.dat", "Minifig Head", "Minifig", vec!["Face"]),
+            (
+                "parts/s/3001s01.dat",
+                "~Brick  2 x  4 without Studs",
+                "Brick",
+                vec![],
+            ),
+            ("parts/3002.dat", "~Moved to 3001", "Moved", vec![]),
+            ("p/stud.dat", "Stud", "Primitive_Stud", vec![]),
+        ] {
+            index.insert(LibraryIndexEntry {
+                path: path.to_string(),
+                kind: if path.starts_with("p/") {
+                    PartKind::Primitive
+                } else {
+                    PartKind::Part
+                },
+                description: description.to_string(),
+                category: category.to_string(),
+                keywords: keywords.into_iter().map(String::from).collect(),
+            });
+        }
+        index
+    }
+
+    fn names(hits: Vec<super::SearchHit>) -> Vec<String> {
+        hits.into_iter()
+            .map(|v| v.alias.normalized.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_search() {
+        let index = index();
+        let options = SearchOptions::default();
+
+        assert_eq!(names(search(&index, "3001", &options))[0], "3001.dat");
+        assert_eq!(
+            names(search(&index, "brick 2x4", &options)),
+            vec!["3001.dat"]
+        );
+        assert_eq!(
+            names(search(&index, "2x4", &options)),
+            vec!["3001.dat", "3020.dat"]
+        );
+        assert_eq!(names(search(&index, "minifg", &options)), vec!["3626b.dat"]);
+        assert_eq!(names(search(&index, "face", &options)), vec!["3626b.dat"]);
+        assert!(search(&index, "stud", &options).is_empty());
+
+        let everything = SearchOptions {
+            include_primitives: true,
+            include_hidden: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            names(search(&index, "stud", &everything)),
+            vec!["stud.dat", "s/3001s01.dat"]
+        );
+
+        let limited = SearchOptions {
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(search(&index, "brick", &limited).len(), 1);
+    }
+
+    #[test]
+    fn test_browse() {
+        let index = index();
+        let options = SearchOptions::default();
+
+        assert_eq!(
+            categories(&index, &options),
+            vec![
+                (String::from("Brick"), 3),
+                (String::from("Minifig"), 1),
+                (String::from("Plate"), 1)
+            ]
+        );
+        assert_eq!(
+            names(browse(&index, "brick", &options)),
+            vec!["3001.dat", "3003.dat", "30010.dat"]
+        );
+    }
+}
@@ -1,20 +1,20 @@
-use std::{collections::HashMap, marker::Unpin, str::Chars};
+use std::{collections::HashMap, marker::Unpin, ops::Range, str::Chars};
 
-use cgmath::Matrix;
-use futures::{stream::Enumerate, StreamExt};
+use cgmath::{Deg, Matrix, SquareMatrix};
 use tokio::io::{AsyncBufRead, AsyncBufReadExt};
-use tokio_stream::wrappers::LinesStream;
 
 use crate::{
     color::{
         Color, ColorCatalog, ColorReference, CustomizedMaterial, Material, MaterialGlitter,
         MaterialSpeckle, Rgba,
     },
-    document::{BfcCertification, Document, MultipartDocument},
+    document::{BfcCertification, Document, MultipartDocument, SourceLines},
     elements::{
-        BfcStatement, Command, Header, Line, Meta, OptionalLine, PartReference, Quad, Triangle,
+        BfcStatement, Camera, Command, Header, HistoryAuthor, HistoryEntry, LDrawOrg, LdCadCommand,
+        LdCadGenerator, LdCadPathPoint, LdCadPathSkin, Line, Meta, OptionalLine, PartReference,
+        Quad, Release, Triangle, HISTORY_HEADER, LDCAD_META, LDRAWRS_HEADER, LDRAW_ORG_HEADER,
     },
-    error::{ColorDefinitionParseError, DocumentParseError, ParseError},
+    error::{ColorDefinitionParseError, DocumentParseError, ParseError, Span},
     {Matrix4, PartAlias, Vector4, Winding},
 };
 
@@ -46,9 +46,11 @@ fn next_token(iterator: &mut Chars, glob_remaining: bool) -> Result<String, Pars
         }
     }
 
-    match buffer.len() {
-        0 => Err(ParseError::EndOfLine),
-        _ => Ok(buffer.trim_end().to_string()),
+    // Trimmed the same way it was split, so that control characters such as vertical tabs are
+    // kept rather than dropped only at the end.
+    match buffer.trim_end_matches(is_whitespace) {
+        "" => Err(ParseError::EndOfLine),
+        v => Ok(v.to_string()),
     }
 }
 
@@ -132,6 +134,192 @@ fn parse_bfc_statement(iterator: &mut Chars) -> Result<Line0, ParseError> {
     }
 }
 
+// Returns Ok(None) if the header is not a camera definition.
+pub fn parse_camera(header: &Header) -> Result<Option<Camera>, ParseError> {
+    if header.0 != LDRAWRS_HEADER {
+        return Ok(None);
+    }
+
+    let mut iterator = header.1.chars();
+    match next_token(&mut iterator, false) {
+        Ok(v) if v == "CAMERA" => (),
+        _ => return Ok(None),
+    }
+
+    let mut camera = Camera::default();
+    loop {
+        let key = match next_token(&mut iterator, false) {
+            Ok(v) => v,
+            Err(ParseError::EndOfLine) => break,
+            Err(e) => return Err(e),
+        };
+        let value = next_token_f32(&mut iterator)?;
+        match key.as_str() {
+            "LATITUDE" => camera.latitude = Deg(value),
+            "LONGITUDE" => camera.longitude = Deg(value),
+            "DISTANCE" => camera.distance = value,
+            "FOV" => camera.fov = Some(Deg(value)),
+            _ => return Err(ParseError::InvalidToken(key)),
+        }
+    }
+
+    Ok(Some(camera))
+}
+
+// Returns Ok(None) if the header is not a !LDRAW_ORG line.
+pub fn parse_ldraw_org(header: &Header) -> Result<Option<LDrawOrg>, ParseError> {
+    if header.0 != LDRAW_ORG_HEADER {
+        return Ok(None);
+    }
+
+    let mut words = header.1.split_whitespace();
+    let part_type = match words.next() {
+        Some(v) => v.to_string(),
+        None => return Err(ParseError::EndOfLine),
+    };
+
+    let mut qualifiers = Vec::new();
+    let mut release = None;
+    while let Some(word) = words.next() {
+        match word {
+            "ORIGINAL" => release = Some(Release::Original),
+            "UPDATE" => match words.next() {
+                Some(v) => release = Some(Release::Update(v.to_string())),
+                None => return Err(ParseError::EndOfLine),
+            },
+            _ => qualifiers.push(word.to_string()),
+        }
+    }
+
+    Ok(Some(LDrawOrg {
+        part_type,
+        qualifiers,
+        release,
+    }))
+}
+
+// Returns Ok(None) if the header is not a !HISTORY line.
+pub fn parse_history(header: &Header) -> Result<Option<HistoryEntry>, ParseError> {
+    if header.0 != HISTORY_HEADER {
+        return Ok(None);
+    }
+
+    let value = header.1.trim();
+    let (date, rest) = value
+        .split_once(char::is_whitespace)
+        .ok_or(ParseError::EndOfLine)?;
+    let rest = rest.trim_start();
+    let (close, author): (char, fn(String) -> HistoryAuthor) = match rest.chars().next() {
+        Some('[') => (']', HistoryAuthor::Username),
+        Some('{') => ('}', HistoryAuthor::RealName),
+        _ => return Err(ParseError::InvalidToken(rest.to_string())),
+    };
+    let end = rest
+        .find(close)
+        .ok_or_else(|| ParseError::InvalidToken(rest.to_string()))?;
+
+    Ok(Some(HistoryEntry {
+        date: date.to_string(),
+        author: author(rest[1..end].trim().to_string()),
+        description: rest[end + 1..].trim().to_string(),
+    }))
+}
+
+// Splits `KIND [key=value] [key=value] ...`. Returns None if brackets are unbalanced.
+fn parse_ldcad_command(value: &str) -> Option<LdCadCommand> {
+    let (kind, mut rest) = match value.find('[') {
+        Some(index) => (value[..index].trim(), &value[index..]),
+        None => (value.trim(), ""),
+    };
+    if kind.is_empty() || kind.contains(char::is_whitespace) {
+        return None;
+    }
+
+    let mut parameters = Vec::new();
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        let inner = rest.strip_prefix('[')?;
+        let end = inner.find(']')?;
+        let (key, value) = match inner[..end].split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => (inner[..end].trim(), ""),
+        };
+        parameters.push((key.to_string(), value.to_string()));
+        rest = &inner[end + 1..];
+    }
+
+    Some(LdCadCommand {
+        kind: kind.to_string(),
+        parameters,
+    })
+}
+
+fn ldcad_f32(command: &LdCadCommand, key: &str) -> Result<Option<f32>, ParseError> {
+    match command.get(key) {
+        Some(v) => match v.parse::<f32>() {
+            Ok(v) => Ok(Some(v)),
+            Err(_) => Err(ParseError::TypeMismatch("f32", v.to_string())),
+        },
+        None => Ok(None),
+    }
+}
+
+// Returns Ok(None) for commands that have nothing to do with generating flexible parts.
+pub fn parse_ldcad_generator(command: &LdCadCommand) -> Result<Option<LdCadGenerator>, ParseError> {
+    let kind = command.kind.to_ascii_uppercase();
+    if let Some(shape) = kind.strip_prefix("SNAP_") {
+        return Ok(Some(LdCadGenerator::Snap(shape.to_string())));
+    }
+
+    match kind.as_str() {
+        "CONTENT" => Ok(Some(LdCadGenerator::Content(
+            command.get("type").unwrap_or_default().to_ascii_lowercase(),
+        ))),
+        "GENERATED" => Ok(Some(LdCadGenerator::Generated)),
+        "PATH_POINT" => {
+            let mut matrix = Matrix4::identity();
+            if let Some(value) = command.get("posOri") {
+                let mut iterator = value.chars();
+                let mut values = [0.0; 12];
+                for v in values.iter_mut() {
+                    *v = next_token_f32(&mut iterator)?;
+                }
+                matrix = Matrix4::new(
+                    values[3], values[4], values[5], values[0], values[6], values[7], values[8],
+                    values[1], values[9], values[10], values[11], values[2], 0.0, 0.0, 0.0, 1.0,
+                )
+                .transpose();
+            }
+            Ok(Some(LdCadGenerator::PathPoint(LdCadPathPoint {
+                matrix,
+                prev_distance: ldcad_f32(command, "prevCPDist")?.unwrap_or(0.0),
+                next_distance: ldcad_f32(command, "nextCPDist")?.unwrap_or(0.0),
+            })))
+        }
+        "PATH_SKIN" => {
+            let color = match command.get("donCol") {
+                Some(v) => match v.parse::<u32>() {
+                    Ok(v) => v,
+                    Err(_) => return Err(ParseError::TypeMismatch("u32", v.to_string())),
+                },
+                None => 16,
+            };
+            Ok(Some(LdCadGenerator::PathSkin(LdCadPathSkin {
+                color,
+                part: command
+                    .get("donPart")
+                    .filter(|v| !v.is_empty())
+                    .map(PartAlias::from),
+                length: ldcad_f32(command, "donLen")?,
+            })))
+        }
+        _ => Ok(None),
+    }
+}
+
 fn parse_line_0(iterator: &mut Chars) -> Result<Line0, ParseError> {
     let text = match next_token(iterator, true) {
         Ok(v) => v,
@@ -144,6 +332,12 @@ fn parse_line_0(iterator: &mut Chars) -> Result<Line0, ParseError> {
     if cmd.starts_with('!') {
         let key: String = cmd.chars().skip(1).collect();
         let value = next_token(&mut inner_iterator, true).unwrap_or_default();
+        // LDCad commands are positional, so they are kept along with other commands.
+        if key == LDCAD_META {
+            if let Some(command) = parse_ldcad_command(&value) {
+                return Ok(Line0::Meta(Meta::LdCad(command)));
+            }
+        }
         return Ok(Line0::Header(Header(key, value)));
     }
 
@@ -334,10 +528,250 @@ fn parse_line_5(colors: &ColorCatalog, iterator: &mut Chars) -> Result<OptionalL
     })
 }
 
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
+// Characters Windows-1252 puts in place of the C1 control codes of Latin-1, which editors on
+// Windows wrote quotes and dashes with. Codes it leaves undefined are kept as in Latin-1.
+const WINDOWS_1252: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+// Decodes a line as UTF-8, falling back to Latin-1 as many older files were written
+// that way. Every byte maps to a character, so decoding never fails. A BOM is dropped from
+// any line, not just the first, as files pasted together may carry one in the middle.
+fn decode_line(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(UTF8_BOM).unwrap_or(bytes);
+    let bytes = bytes.strip_suffix(b"\n").unwrap_or(bytes);
+    let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+
+    match std::str::from_utf8(bytes) {
+        Ok(v) => v.to_string(),
+        Err(_) => bytes
+            .iter()
+            .map(|&v| match v {
+                0x80..=0x9f => WINDOWS_1252[(v - 0x80) as usize],
+                _ => v as char,
+            })
+            .collect(),
+    }
+}
+
+// Line as read from the input, along with where it was in there.
+struct RawLine {
+    index: usize,
+    // Byte offset of the decoded text in the input.
+    offset: usize,
+    text: String,
+    latin1: bool,
+}
+
+impl RawLine {
+    // Maps a byte range of the decoded text back to the input. Latin-1 characters take a
+    // single byte there, while they may take two once decoded.
+    fn span(&self, range: Range<usize>) -> Span {
+        let to_input = |v: usize| match self.latin1 {
+            true => self.offset + self.text[..v].chars().count(),
+            false => self.offset + v,
+        };
+        Span {
+            line: self.index + 1,
+            column: self.text[..range.start].chars().count() + 1,
+            range: to_input(range.start)..to_input(range.end),
+            token: self.text[range].to_string(),
+        }
+    }
+
+    fn error(&self, error: ParseError, range: Option<Range<usize>>) -> DocumentParseError {
+        DocumentParseError {
+            line: self.index + 1,
+            span: range.map(|v| self.span(v)),
+            error,
+        }
+    }
+}
+
+// Numbered lines, decoded one at a time so that a stray Latin-1 line does not affect
+// the rest of the file.
+struct LineReader<'a, T> {
+    reader: &'a mut T,
+    index: usize,
+    offset: usize,
+    buffer: Vec<u8>,
+}
+
+impl<'a, T: AsyncBufRead + Unpin> LineReader<'a, T> {
+    fn new(reader: &'a mut T) -> Self {
+        LineReader {
+            reader,
+            index: 0,
+            offset: 0,
+            buffer: Vec::new(),
+        }
+    }
+
+    async fn next(&mut self) -> Option<Result<RawLine, DocumentParseError>> {
+        self.buffer.clear();
+        let index = self.index;
+        match self.reader.read_until(b'\n', &mut self.buffer).await {
+            Ok(0) => None,
+            Ok(length) => {
+                let bom = match self.buffer.starts_with(UTF8_BOM) {
+                    true => UTF8_BOM.len(),
+                    false => 0,
+                };
+                let line = RawLine {
+                    index,
+                    offset: self.offset + bom,
+                    text: decode_line(&self.buffer),
+                    latin1: std::str::from_utf8(&self.buffer).is_err(),
+                };
+                self.index += 1;
+                self.offset += length;
+                Some(Ok(line))
+            }
+            Err(e) => Some(Err(DocumentParseError {
+                line: index + 1,
+                span: None,
+                error: ParseError::from(e),
+            })),
+        }
+    }
+}
+
+// Locates the token a parse error is about, given what was left unread of the line.
+fn error_range(line: &str, rest: &str, error: &ParseError) -> Range<usize> {
+    let consumed = line[..line.len() - rest.len()].trim_end();
+    let token = match error {
+        ParseError::TypeMismatch(_, v)
+        | ParseError::InvalidToken(v)
+        | ParseError::InvalidBfcStatement(v)
+        | ParseError::UnexpectedCommand(v) => Some(v.as_str()),
+        _ => None,
+    };
+    if let Some((start, token)) = token
+        .filter(|v| !v.is_empty())
+        .and_then(|v| consumed.rfind(v).map(|start| (start, v)))
+    {
+        return start..start + token.len();
+    }
+
+    match error {
+        // Missing values are pointed at the end of the line.
+        ParseError::EndOfLine => {
+            let end = line.trim_end().len();
+            end..end
+        }
+        _ => {
+            let start = consumed.rfind(is_whitespace).map_or(0, |v| v + 1);
+            start..consumed.len()
+        }
+    }
+}
+
+// Line of a document as yielded by DocumentStream. Comments are left as they are, including
+// the first one, which is taken as the description when a whole document is parsed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DocumentItem {
+    File(String),
+    Name(String),
+    Author(String),
+    BfcCertification(BfcCertification),
+    Header(Header),
+    Command(Command),
+}
+
+fn parse_line(colors: &ColorCatalog, line: &str) -> Result<Option<DocumentItem>, ParseError> {
+    let mut it = line.chars();
+    let token = match next_token(&mut it, false) {
+        Ok(v) => v,
+        Err(ParseError::EndOfLine) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let item = match token.as_str() {
+        "0" => match parse_line_0(&mut it)? {
+            Line0::BfcCertification(v) => DocumentItem::BfcCertification(v),
+            Line0::File(v) => DocumentItem::File(v),
+            Line0::Name(v) => DocumentItem::Name(v),
+            Line0::Author(v) => DocumentItem::Author(v),
+            Line0::Meta(v) => DocumentItem::Command(Command::Meta(v)),
+            Line0::Header(v) => DocumentItem::Header(v),
+        },
+        "1" => DocumentItem::Command(Command::PartReference(parse_line_1(colors, &mut it)?)),
+        "2" => DocumentItem::Command(Command::Line(parse_line_2(colors, &mut it)?)),
+        "3" => DocumentItem::Command(Command::Triangle(parse_line_3(colors, &mut it)?)),
+        "4" => DocumentItem::Command(Command::Quad(parse_line_4(colors, &mut it)?)),
+        "5" => DocumentItem::Command(Command::OptionalLine(parse_line_5(colors, &mut it)?)),
+        _ => return Err(ParseError::UnexpectedCommand(token)),
+    };
+    Ok(Some(item))
+}
+
+// Parses a line, pointing errors at the offending token.
+fn parse_raw_line(
+    colors: &ColorCatalog,
+    line: &RawLine,
+) -> Result<Option<DocumentItem>, DocumentParseError> {
+    parse_line(colors, &line.text).map_err(|error| {
+        // Parsing is repeated up to the error to find out how far it got.
+        let rest = remaining_after_error(colors, &line.text);
+        let range = error_range(&line.text, rest, &error);
+        line.error(error, Some(range))
+    })
+}
+
+// Returns what is left unread of a malformed line when parsing stops.
+fn remaining_after_error<'a>(colors: &ColorCatalog, line: &'a str) -> &'a str {
+    let mut it = line.chars();
+    let _ = next_token(&mut it, false).and_then(|token| match token.as_str() {
+        "0" => parse_line_0(&mut it).map(|_| ()),
+        "1" => parse_line_1(colors, &mut it).map(|_| ()),
+        "2" => parse_line_2(colors, &mut it).map(|_| ()),
+        "3" => parse_line_3(colors, &mut it).map(|_| ()),
+        "4" => parse_line_4(colors, &mut it).map(|_| ()),
+        "5" => parse_line_5(colors, &mut it).map(|_| ()),
+        _ => Ok(()),
+    });
+    it.as_str()
+}
+
+// Pulls lines of a document one at a time, for showing diagnostics while the input is still
+// being read. Malformed lines are reported and skipped, and the stream goes on after them.
+pub struct DocumentStream<'a, T> {
+    lines: LineReader<'a, T>,
+    colors: &'a ColorCatalog,
+}
+
+impl<'a, T: AsyncBufRead + Unpin> DocumentStream<'a, T> {
+    pub fn new(reader: &'a mut T, colors: &'a ColorCatalog) -> Self {
+        DocumentStream {
+            lines: LineReader::new(reader),
+            colors,
+        }
+    }
+
+    // Returns the next item with the line it came from, skipping empty lines.
+    pub async fn next(&mut self) -> Option<Result<(Span, DocumentItem), DocumentParseError>> {
+        loop {
+            let line = match self.lines.next().await? {
+                Ok(v) => v,
+                Err(e) => return Some(Err(e)),
+            };
+            match parse_raw_line(self.colors, &line) {
+                Ok(Some(item)) => return Some(Ok((line.span(0..line.text.len()), item))),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
 async fn parse_inner<T: AsyncBufRead + Unpin>(
     colors: &ColorCatalog,
-    iterator: &mut Enumerate<LinesStream<T>>,
+    iterator: &mut LineReader<'_, T>,
     multipart: bool,
+    mut diagnostics: Option<&mut Vec<DocumentParseError>>,
+    mut lines: Option<&mut Vec<usize>>,
 ) -> Result<(Document, Option<String>), DocumentParseError> {
     let mut next: Option<String> = None;
     let mut name = String::new();
@@ -347,123 +781,54 @@ async fn parse_inner<T: AsyncBufRead + Unpin>(
     let mut commands = Vec::new();
     let mut headers = Vec::new();
 
-    'read_loop: while let Some((index, line_)) = iterator.next().await {
-        let line = match line_ {
-            Ok(v) => v,
-            Err(e) => {
-                return Err(DocumentParseError {
-                    line: index + 1,
-                    error: ParseError::from(e),
-                });
-            }
+    while let Some(line) = iterator.next().await {
+        let line = line?;
+        let item = match parse_raw_line(colors, &line) {
+            Ok(Some(v)) => v,
+            Ok(None) => continue,
+            // In lenient mode malformed lines are recorded and skipped.
+            Err(error) => match diagnostics {
+                Some(ref mut diagnostics) => {
+                    diagnostics.push(error);
+                    continue;
+                }
+                None => return Err(error),
+            },
         };
-        let mut it = line.chars();
-        match next_token(&mut it, false) {
-            Ok(token) => match token.as_str() {
-                "0" => match parse_line_0(&mut it) {
-                    Ok(val) => match val {
-                        Line0::BfcCertification(bfc_) => {
-                            bfc = bfc_;
-                        }
-                        Line0::File(file_) => {
-                            if multipart {
-                                if !description.is_empty() {
-                                    next = Some(file_);
-                                    break 'read_loop;
-                                }
-                            } else {
-                                return Err(DocumentParseError {
-                                    line: index + 1,
-                                    error: ParseError::MultipartDocument,
-                                });
-                            }
-                        }
-                        Line0::Name(name_) => {
-                            name = name_;
-                        }
-                        Line0::Author(author_) => {
-                            author = author_;
-                        }
-                        Line0::Meta(meta) => {
-                            if let Meta::Comment(comment) = meta {
-                                if description.is_empty() {
-                                    description = comment;
-                                } else {
-                                    commands.push(Command::Meta(Meta::Comment(comment)));
-                                }
-                            } else {
-                                commands.push(Command::Meta(meta));
-                            }
-                        }
-                        Line0::Header(header) => {
-                            headers.push(header);
-                        }
-                    },
-                    Err(e) => {
-                        return Err(DocumentParseError {
-                            line: index + 1,
-                            error: e,
-                        });
-                    }
-                },
-                "1" => match parse_line_1(colors, &mut it) {
-                    Ok(val) => commands.push(Command::PartReference(val)),
-                    Err(e) => {
-                        return Err(DocumentParseError {
-                            line: index + 1,
-                            error: e,
-                        });
-                    }
-                },
-                "2" => match parse_line_2(colors, &mut it) {
-                    Ok(val) => commands.push(Command::Line(val)),
-                    Err(e) => {
-                        return Err(DocumentParseError {
-                            line: index + 1,
-                            error: e,
-                        });
-                    }
-                },
-                "3" => match parse_line_3(colors, &mut it) {
-                    Ok(val) => commands.push(Command::Triangle(val)),
-                    Err(e) => {
-                        return Err(DocumentParseError {
-                            line: index + 1,
-                            error: e,
-                        });
-                    }
-                },
-                "4" => match parse_line_4(colors, &mut it) {
-                    Ok(val) => commands.push(Command::Quad(val)),
-                    Err(e) => {
-                        return Err(DocumentParseError {
-                            line: index + 1,
-                            error: e,
-                        });
-                    }
-                },
-                "5" => match parse_line_5(colors, &mut it) {
-                    Ok(val) => commands.push(Command::OptionalLine(val)),
-                    Err(e) => {
-                        return Err(DocumentParseError {
-                            line: index + 1,
-                            error: e,
-                        });
+
+        match item {
+            DocumentItem::BfcCertification(bfc_) => {
+                bfc = bfc_;
+            }
+            DocumentItem::File(file_) => {
+                if multipart {
+                    if !description.is_empty() {
+                        next = Some(file_);
+                        break;
                     }
-                },
-                _ => {
-                    return Err(DocumentParseError {
-                        line: index + 1,
-                        error: ParseError::UnexpectedCommand(token),
-                    });
+                } else {
+                    return Err(line.error(ParseError::MultipartDocument, None));
                 }
-            },
-            Err(ParseError::EndOfLine) => {}
-            Err(e) => {
-                return Err(DocumentParseError {
-                    line: index + 1,
-                    error: e,
-                });
+            }
+            DocumentItem::Name(name_) => {
+                name = name_;
+            }
+            DocumentItem::Author(author_) => {
+                author = author_;
+            }
+            DocumentItem::Command(Command::Meta(Meta::Comment(comment)))
+                if description.is_empty() =>
+            {
+                description = comment;
+            }
+            DocumentItem::Command(command) => {
+                commands.push(command);
+                if let Some(ref mut lines) = lines {
+                    lines.push(line.index + 1);
+                }
+            }
+            DocumentItem::Header(header) => {
+                headers.push(header);
             }
         }
     }
@@ -481,28 +846,125 @@ async fn parse_inner<T: AsyncBufRead + Unpin>(
     ))
 }
 
+#[derive(Clone, Debug)]
+pub struct ParseOptions {
+    // Fails on the first malformed line. Otherwise malformed lines are skipped and returned as
+    // warnings along with the document, which lets files with junk left by old tools load.
+    // I/O errors are fatal either way.
+    pub strict: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions { strict: true }
+    }
+}
+
 pub async fn parse_single_document<T: AsyncBufRead + Unpin>(
     reader: &mut T,
     colors: &ColorCatalog,
 ) -> Result<Document, DocumentParseError> {
-    let mut it = LinesStream::new(reader.lines()).enumerate();
-    let (document, _) = parse_inner(colors, &mut it, false).await?;
+    let (document, _) =
+        parse_single_document_with_options(reader, colors, &ParseOptions::default()).await?;
 
     Ok(document)
 }
 
-pub async fn parse_multipart_document<T: AsyncBufRead + Unpin>(
+pub async fn parse_single_document_with_options<T: AsyncBufRead + Unpin>(
+    reader: &mut T,
+    colors: &ColorCatalog,
+    options: &ParseOptions,
+) -> Result<(Document, Vec<DocumentParseError>), DocumentParseError> {
+    let mut warnings = Vec::new();
+    let mut it = LineReader::new(reader);
+    let diagnostics = (!options.strict).then_some(&mut warnings);
+    let (document, _) = parse_inner(colors, &mut it, false, diagnostics, None).await?;
+
+    Ok((document, warnings))
+}
+
+// Reads only the header of a document, i.e. the meta lines at the top with its description,
+// name, author, BFC certification and headers such as !LDRAW_ORG and !CATEGORY. Reading stops
+// at the first other command, so the rest of the file is neither read nor parsed. Comments in
+// the header other than the description are left out.
+pub async fn parse_document_header<T: AsyncBufRead + Unpin>(
+    reader: &mut T,
+) -> Result<Document, DocumentParseError> {
+    let colors = ColorCatalog::new();
+    let mut lines = LineReader::new(reader);
+    let mut document = Document {
+        name: String::new(),
+        description: String::new(),
+        author: String::new(),
+        bfc: BfcCertification::NotApplicable,
+        headers: Vec::new(),
+        commands: Vec::new(),
+    };
+
+    while let Some(line) = lines.next().await {
+        let line = line?;
+        // Anything but a meta line ends the header, and is not worth parsing.
+        match line.text.split(is_whitespace).find(|v| !v.is_empty()) {
+            None => continue,
+            Some("0") => (),
+            Some(_) => break,
+        }
+
+        match parse_raw_line(&colors, &line)? {
+            Some(DocumentItem::Name(name)) => document.name = name,
+            Some(DocumentItem::Author(author)) => document.author = author,
+            Some(DocumentItem::BfcCertification(bfc)) => document.bfc = bfc,
+            Some(DocumentItem::Header(header)) => document.headers.push(header),
+            Some(DocumentItem::Command(Command::Meta(Meta::Comment(comment))))
+                if document.description.is_empty() =>
+            {
+                document.description = comment;
+            }
+            Some(DocumentItem::Command(Command::Meta(Meta::Comment(_)))) => (),
+            // A FILE line before the description starts the document, while one after it
+            // starts the next.
+            Some(DocumentItem::File(_)) if document.description.is_empty() => (),
+            Some(_) => break,
+            None => (),
+        }
+    }
+
+    Ok(document)
+}
+
+async fn parse_multipart_inner<T: AsyncBufRead + Unpin>(
     reader: &mut T,
     colors: &ColorCatalog,
+    mut diagnostics: Option<&mut Vec<DocumentParseError>>,
+    mut lines: Option<&mut SourceLines>,
 ) -> Result<MultipartDocument, DocumentParseError> {
-    let mut it = LinesStream::new(reader.lines()).enumerate();
-    let (document, mut next) = parse_inner(colors, &mut it, true).await?;
+    let mut it = LineReader::new(reader);
+    let (document, mut next) = parse_inner(
+        colors,
+        &mut it,
+        true,
+        diagnostics.as_deref_mut(),
+        lines.as_deref_mut().map(|v| &mut v.body),
+    )
+    .await?;
     let mut subparts = HashMap::new();
 
     while next.is_some() {
-        let (part, next_) = parse_inner(colors, &mut it, true).await?;
+        let alias = PartAlias::from(&next.unwrap());
+        let mut part_lines = Vec::new();
+        let (part, next_) = parse_inner(
+            colors,
+            &mut it,
+            true,
+            diagnostics.as_deref_mut(),
+            lines.is_some().then_some(&mut part_lines),
+        )
+        .await?;
 
-        subparts.insert(PartAlias::from(&next.unwrap()), part);
+        if let Some(ref mut lines) = lines {
+            lines.subparts.insert(alias.clone(), part_lines);
+        }
+        subparts.insert(alias, part);
         next = next_;
     }
 
@@ -512,6 +974,40 @@ pub async fn parse_multipart_document<T: AsyncBufRead + Unpin>(
     })
 }
 
+pub async fn parse_multipart_document<T: AsyncBufRead + Unpin>(
+    reader: &mut T,
+    colors: &ColorCatalog,
+) -> Result<MultipartDocument, DocumentParseError> {
+    parse_multipart_inner(reader, colors, None, None).await
+}
+
+pub async fn parse_multipart_document_with_options<T: AsyncBufRead + Unpin>(
+    reader: &mut T,
+    colors: &ColorCatalog,
+    options: &ParseOptions,
+) -> Result<(MultipartDocument, Vec<DocumentParseError>), DocumentParseError> {
+    let mut warnings = Vec::new();
+    let diagnostics = (!options.strict).then_some(&mut warnings);
+    let document = parse_multipart_inner(reader, colors, diagnostics, None).await?;
+
+    Ok((document, warnings))
+}
+
+// Also returns the line each command has been read from, for mapping what is built from the
+// document back to the input.
+pub async fn parse_multipart_document_with_lines<T: AsyncBufRead + Unpin>(
+    reader: &mut T,
+    colors: &ColorCatalog,
+    options: &ParseOptions,
+) -> Result<(MultipartDocument, SourceLines, Vec<DocumentParseError>), DocumentParseError> {
+    let mut warnings = Vec::new();
+    let mut lines = SourceLines::default();
+    let diagnostics = (!options.strict).then_some(&mut warnings);
+    let document = parse_multipart_inner(reader, colors, diagnostics, Some(&mut lines)).await?;
+
+    Ok((document, lines, warnings))
+}
+
 fn parse_customized_material(
     iterator: &mut Chars,
 ) -> Result<CustomizedMaterial, ColorDefinitionParseError> {
@@ -562,10 +1058,9 @@ fn parse_customized_material(
                     "MAXSIZE" => {
                         maxsize = next_token_f32(iterator)?;
                     }
+                    // Parameters added in later revisions are skipped.
                     _ => {
-                        return Err(ColorDefinitionParseError::ParseError(
-                            ParseError::InvalidToken(token.clone()),
-                        ));
+                        next_unknown_attribute(iterator)?;
                     }
                 }
             }
@@ -621,10 +1116,9 @@ fn parse_customized_material(
                     "MAXSIZE" => {
                         maxsize = next_token_f32(iterator)?;
                     }
+                    // Parameters added in later revisions are skipped.
                     _ => {
-                        return Err(ColorDefinitionParseError::ParseError(
-                            ParseError::InvalidToken(token.clone()),
-                        ));
+                        next_unknown_attribute(iterator)?;
                     }
                 }
             }
@@ -637,7 +1131,36 @@ fn parse_customized_material(
                 maxsize,
             }))
         }
-        e => Err(ColorDefinitionParseError::UnknownMaterial(e.to_string())),
+        "FABRIC" => match next_token(iterator, false) {
+            Ok(v) => Ok(CustomizedMaterial::Fabric(Some(v))),
+            Err(ParseError::EndOfLine) => Ok(CustomizedMaterial::Fabric(None)),
+            Err(e) => Err(ColorDefinitionParseError::ParseError(e)),
+        },
+        kind => Ok(CustomizedMaterial::Other {
+            kind: kind.to_string(),
+            parameters: iterator.as_str().trim().to_string(),
+        }),
+    }
+}
+
+fn is_keyword(token: &str) -> bool {
+    token.starts_with(|c: char| c.is_ascii_uppercase())
+        && token
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
+
+// Reads an attribute the parser does not know of, taking the following token as its value
+// unless it is another keyword.
+fn next_unknown_attribute(iterator: &mut Chars) -> Result<String, ParseError> {
+    let mut lookahead = iterator.clone();
+    match next_token(&mut lookahead, false) {
+        Ok(v) if !is_keyword(&v) => {
+            *iterator = lookahead;
+            Ok(v)
+        }
+        Ok(_) | Err(ParseError::EndOfLine) => Ok(String::new()),
+        Err(e) => Err(e),
     }
 }
 
@@ -651,6 +1174,7 @@ pub async fn parse_color_definitions<T: AsyncBufRead + Unpin>(
     let mut colors = ColorCatalog::new();
     for Header(_, value) in document.headers.iter().filter(|s| s.0 == "COLOUR") {
         let mut material = Material::Plastic;
+        let mut attributes = HashMap::new();
         let mut alpha = 255u8;
         let mut luminance = 0u8;
 
@@ -720,9 +1244,8 @@ pub async fn parse_color_definitions<T: AsyncBufRead + Unpin>(
                     material = Material::Custom(parse_customized_material(&mut it)?);
                 }
                 _ => {
-                    return Err(ColorDefinitionParseError::ParseError(
-                        ParseError::InvalidToken(token.clone()),
-                    ));
+                    let value = next_unknown_attribute(&mut it)?;
+                    attributes.insert(token, value);
                 }
             }
         }
@@ -736,6 +1259,7 @@ pub async fn parse_color_definitions<T: AsyncBufRead + Unpin>(
                 edge: Rgba::new(er, eg, eb, 255),
                 luminance,
                 material,
+                attributes,
             },
         );
     }
@@ -746,6 +1270,7 @@ pub async fn parse_color_definitions<T: AsyncBufRead + Unpin>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{elements::CATEGORY_HEADER, writer::LDrawWriter};
 
     fn parse_line_0_or_panic(input: &str) -> Line0 {
         match parse_line_0(&mut input.chars()) {
@@ -836,6 +1361,164 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_camera_roundtrips() {
+        let camera = Camera {
+            latitude: Deg(-15.5),
+            longitude: Deg(120.0),
+            distance: 0.75,
+            fov: Some(Deg(30.0)),
+        };
+        let parsed =
+            match parse_line_0_or_panic(&format!("!{} {}", LDRAWRS_HEADER, camera.to_header().1)) {
+                Line0::Header(header) => header,
+                v => panic!("expected Line0::Header(...), got {:?}", v),
+            };
+        assert_eq!(parse_camera(&parsed).unwrap(), Some(camera));
+
+        let partial = Header(LDRAWRS_HEADER.into(), "CAMERA LONGITUDE 90".into());
+        assert_eq!(
+            parse_camera(&partial).unwrap(),
+            Some(Camera {
+                longitude: Deg(90.0),
+                ..Default::default()
+            })
+        );
+
+        let other = Header("LDRAW_ORG".into(), "Part UPDATE 2006-01".into());
+        assert_eq!(parse_camera(&other).unwrap(), None);
+
+        let invalid = Header(LDRAWRS_HEADER.into(), "CAMERA ZOOM 2".into());
+        assert!(parse_camera(&invalid).is_err());
+    }
+
+    #[tokio::test]
+    async fn parse_part_metadata() {
+        let input = "0 ~Brick  2 x  4 without Front Studs
+0 Name: s\\3001s01.dat
+0 Author: James Jessiman
+0 !LDRAW_ORG Subpart UPDATE 2004-03
+0 !LICENSE Redistributable under CCAL version 2.0 : see CAreadme.txt
+0 !KEYWORDS Basic, Classic
+0 !KEYWORDS Studless
+0 !HISTORY 2002-04-25 [PTadmin] Official update 2002-02
+0 !HISTORY 2000-08-?? {Axel Poque} fixes to resolve L3P error messages
+";
+        let document = parse_single_document(&mut input.as_bytes(), &ColorCatalog::new())
+            .await
+            .unwrap();
+
+        let metadata = document.metadata();
+        assert_eq!(metadata.category, "Brick");
+        assert_eq!(metadata.keywords, vec!["Basic", "Classic", "Studless"]);
+        assert_eq!(
+            metadata.ldraw_org,
+            Some(LDrawOrg {
+                part_type: "Subpart".into(),
+                qualifiers: vec![],
+                release: Some(Release::Update("2004-03".into())),
+            })
+        );
+        assert_eq!(
+            metadata.history,
+            vec![
+                HistoryEntry {
+                    date: "2002-04-25".into(),
+                    author: HistoryAuthor::Username("PTadmin".into()),
+                    description: "Official update 2002-02".into(),
+                },
+                HistoryEntry {
+                    date: "2000-08-??".into(),
+                    author: HistoryAuthor::RealName("Axel Poque".into()),
+                    description: "fixes to resolve L3P error messages".into(),
+                },
+            ]
+        );
+        assert!(document.is_official());
+        assert!(document.is_redistributable());
+        for entry in metadata.history.iter() {
+            assert_eq!(
+                parse_history(&entry.to_header()).unwrap().as_ref(),
+                Some(entry)
+            );
+        }
+
+        let unofficial = Header(
+            LDRAW_ORG_HEADER.into(),
+            "Unofficial_Part Alias Flexible_Section".into(),
+        );
+        let parsed = parse_ldraw_org(&unofficial).unwrap().unwrap();
+        assert!(parsed.is_unofficial());
+        assert_eq!(parsed.qualifiers, vec!["Alias", "Flexible_Section"]);
+        assert_eq!(parsed.release, None);
+        assert_eq!(parsed.to_header(), unofficial);
+
+        assert_eq!(
+            parse_ldraw_org(&Header(CATEGORY_HEADER.into(), "Brick".into())).unwrap(),
+            None
+        );
+        assert!(parse_history(&Header(HISTORY_HEADER.into(), "2002-04-25".into())).is_err());
+    }
+
+    #[tokio::test]
+    async fn parse_line_0_parses_ldcad_commands() {
+        let input = "!LDCAD PATH_POINT [type=xyz] [posOri=10 -20 30 1 0 0 0 0 -1 0 1 0] [prevCPDist=5] [nextCPDist=7.5]";
+        let command = match parse_line_0_or_panic(input) {
+            Line0::Meta(Meta::LdCad(command)) => command,
+            v => panic!("expected Line0::Meta(Meta::LdCad(...)), got {:?}", v),
+        };
+        assert_eq!(command.kind, "PATH_POINT");
+        assert_eq!(command.parameters.len(), 4);
+        assert_eq!(command.get("prevcpdist"), Some("5"));
+
+        let point = match parse_ldcad_generator(&command).unwrap() {
+            Some(LdCadGenerator::PathPoint(point)) => point,
+            v => panic!("expected LdCadGenerator::PathPoint(...), got {:?}", v),
+        };
+        assert_eq!(point.prev_distance, 5.0);
+        assert_eq!(point.next_distance, 7.5);
+        assert_eq!(
+            point.matrix,
+            Matrix4::new(1., 0., 0., 0., 0., 0., 1., 0., 0., -1., 0., 0., 10., -20., 30., 1.)
+        );
+
+        let mut written = Vec::new();
+        command.write(&mut written).await.unwrap();
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            format!("0 {}\n", input)
+        );
+
+        let skin = match parse_line_0_or_panic("!LDCAD PATH_SKIN [donCol=0] [donPart=754.dat]") {
+            Line0::Meta(Meta::LdCad(command)) => parse_ldcad_generator(&command).unwrap(),
+            v => panic!("expected Line0::Meta(Meta::LdCad(...)), got {:?}", v),
+        };
+        assert_eq!(
+            skin,
+            Some(LdCadGenerator::PathSkin(LdCadPathSkin {
+                color: 0,
+                part: Some("754.dat".into()),
+                length: None,
+            }))
+        );
+
+        match parse_line_0_or_panic("!LDCAD SNAP_CYL [gender=M] [secs=R 8 2]") {
+            Line0::Meta(Meta::LdCad(command)) => assert_eq!(
+                parse_ldcad_generator(&command).unwrap(),
+                Some(LdCadGenerator::Snap("CYL".into()))
+            ),
+            v => panic!("expected Line0::Meta(Meta::LdCad(...)), got {:?}", v),
+        }
+
+        // Malformed commands are left as headers.
+        assert_eq!(
+            parse_line_0_or_panic("!LDCAD PATH_POINT [posOri=0"),
+            Line0::Header(Header("LDCAD".into(), "PATH_POINT [posOri=0".into()))
+        );
+        let invalid = parse_ldcad_command("PATH_POINT [nextCPDist=far]").unwrap();
+        assert!(parse_ldcad_generator(&invalid).is_err());
+    }
+
     #[test]
     fn parse_line_0_parses_headers() {
         let cases = [
@@ -1024,6 +1707,52 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn parse_color_definitions_keeps_unknown_attributes() {
+        let input = "0 !COLOUR Canvas CODE 30 VALUE #F2F3F2 EDGE #333333 MATERIAL FABRIC CANVAS
+0 !COLOUR Velvet CODE 31 VALUE #720E0F EDGE #333333 MATERIAL FABRIC
+0 !COLOUR Future CODE 32 VALUE #101010 EDGE #333333 SATIN MATERIAL HOLOGRAM SHIFT 0.5
+0 !COLOUR Finish CODE 33 VALUE #202020 EDGE #333333 FINISH brushed ALPHA 128 MATTE
+0 !COLOUR Glitter CODE 34 VALUE #303030 EDGE #333333 MATERIAL GLITTER VALUE #FFFFFF FRACTION 0.1 SPARKLE 2 SIZE 1
+";
+        let colors = parse_color_definitions(&mut input.as_bytes())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            colors[&30].material,
+            Material::Custom(CustomizedMaterial::Fabric(Some("CANVAS".into())))
+        );
+        assert_eq!(
+            colors[&31].material,
+            Material::Custom(CustomizedMaterial::Fabric(None))
+        );
+        assert_eq!(
+            colors[&32].material,
+            Material::Custom(CustomizedMaterial::Other {
+                kind: "HOLOGRAM".into(),
+                parameters: "SHIFT 0.5".into(),
+            })
+        );
+        assert_eq!(colors[&32].attributes.get("SATIN"), Some(&String::new()));
+
+        let finish = &colors[&33];
+        assert_eq!(finish.color.alpha(), 128);
+        assert_eq!(
+            finish.attributes.get("FINISH").map(String::as_str),
+            Some("brushed")
+        );
+        assert_eq!(finish.attributes.get("MATTE"), Some(&String::new()));
+
+        match &colors[&34].material {
+            Material::Custom(CustomizedMaterial::Glitter(glitter)) => {
+                assert_eq!(glitter.fraction, 0.1);
+                assert_eq!(glitter.size, 1);
+            }
+            v => panic!("expected glitter, got {:?}", v),
+        }
+    }
+
     const COLOR_DEFINITIONS: &str =
 "0 Color Definition for testing
 0 Name: LDConfig.ldr
@@ -1053,6 +1782,7 @@ mod tests {
                 edge: Rgba::new(0x59, 0x59, 0x59, 255),
                 luminance: 0,
                 material: Material::Plastic,
+                attributes: HashMap::new(),
             },
             Color {
                 code: 1,
@@ -1061,6 +1791,7 @@ mod tests {
                 edge: Rgba::new(0x00, 0xff, 0x00, 255),
                 luminance: 0,
                 material: Material::Plastic,
+                attributes: HashMap::new(),
             },
             Color {
                 code: 2,
@@ -1069,6 +1800,7 @@ mod tests {
                 edge: Rgba::new(0xff, 0x00, 0x00, 255),
                 luminance: 0,
                 material: Material::Chrome,
+                attributes: HashMap::new(),
             },
             Color {
                 code: 3,
@@ -1077,6 +1809,7 @@ mod tests {
                 edge: Rgba::new(0x00, 0xff, 0x00, 255),
                 luminance: 0,
                 material: Material::Pearlescent,
+                attributes: HashMap::new(),
             },
             Color {
                 code: 4,
@@ -1085,6 +1818,7 @@ mod tests {
                 edge: Rgba::new(0x00, 0x00, 0xff, 255),
                 luminance: 0,
                 material: Material::Metal,
+                attributes: HashMap::new(),
             },
             Color {
                 code: 5,
@@ -1093,6 +1827,7 @@ mod tests {
                 edge: Rgba::new(0x00, 0xff, 0x00, 255),
                 luminance: 15,
                 material: Material::Plastic,
+                attributes: HashMap::new(),
             },
             Color {
                 code: 6,
@@ -1109,6 +1844,7 @@ mod tests {
                     minsize: 0.,
                     maxsize: 0.,
                 })),
+                attributes: HashMap::new(),
             },
             Color {
                 code: 7,
@@ -1125,6 +1861,7 @@ mod tests {
                     minsize: 0.,
                     maxsize: 0.,
                 })),
+                attributes: HashMap::new(),
             },
             Color {
                 code: 8,
@@ -1140,6 +1877,7 @@ mod tests {
                     minsize: 1.,
                     maxsize: 3.,
                 })),
+                attributes: HashMap::new(),
             },
             Color {
                 code: 9,
@@ -1148,6 +1886,7 @@ mod tests {
                 edge: Rgba::new(0xfe, 0xdc, 0xba, 255),
                 luminance: 0,
                 material: Material::Rubber,
+                attributes: HashMap::new(),
             },
         ];
         for material in colors {
@@ -1367,4 +2106,318 @@ mod tests {
             }
         )
     }
+
+    #[tokio::test]
+    async fn test_parse_multipart_document_lenient() {
+        let colors = parse_color_definitions(&mut COLOR_DEFINITIONS.as_bytes())
+            .await
+            .unwrap();
+        let document = "0 FILE test.ldr
+0 LDraw.rs
+0 Name: test.ldr
+3 7 22.04 -.25 -1.16 23.72 -.25
+7 garbage
+0 BFC FLIP
+1 3 0 0 0 1 0 0 0 1 0 0 0 1 apple.ldr
+
+0 FILE apple.ldr
+0 Apple
+2 24 0 0 0 one 0 0
+2 24 0 0 0 1 0 0";
+        assert!(parse_multipart_document(&mut document.as_bytes(), &colors)
+            .await
+            .is_err());
+
+        let (parsed, diagnostics) = parse_multipart_document_with_options(
+            &mut document.as_bytes(),
+            &colors,
+            &ParseOptions { strict: false },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            diagnostics.iter().map(|v| v.line).collect::<Vec<_>>(),
+            vec![4, 5, 6, 11]
+        );
+        assert!(matches!(
+            diagnostics[1].error,
+            ParseError::UnexpectedCommand(_)
+        ));
+        assert_eq!(parsed.body.commands.len(), 1);
+        assert_eq!(
+            parsed.subparts[&PartAlias::from("apple.ldr")]
+                .commands
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_document_header() {
+        let document = "0 FILE 3001.dat
+0 Brick  2 x  4
+0 Name: 3001.dat
+0 Author: James Jessiman
+0 !LDRAW_ORG Part UPDATE 2004-03
+0 !LICENSE Licensed under CC BY 4.0 : see CAreadme.txt
+
+0 BFC CERTIFY CCW
+
+0 // Kept out of the document
+0 !CATEGORY Brick
+0 !KEYWORDS Classic
+0 !HISTORY 2002-05-07 [unknown] BFC Certification
+
+1 16 0 0 0 1 0 0 0 1 0 0 0 1 s\\3001s01.dat
+0 !KEYWORDS Ignored
+4 16 not a valid quad
+";
+        let header = parse_document_header(&mut document.as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(header.description, "Brick  2 x  4");
+        assert_eq!(header.name, "3001.dat");
+        assert_eq!(header.author, "James Jessiman");
+        assert_eq!(header.bfc, BfcCertification::Certify(Winding::Ccw));
+        assert_eq!(header.category(), "Brick");
+        assert_eq!(header.keywords(), vec!["Classic"]);
+        assert!(header.is_official());
+        assert!(header.commands.is_empty());
+
+        // The next part of a multipart document ends the header too.
+        let header = parse_document_header(
+            &mut "0 Model\n0 Name: model.ldr\n0 FILE sub.ldr\n0 !CATEGORY Sub\n".as_bytes(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(header.name, "model.ldr");
+        assert!(header.headers.is_empty());
+    }
+
+    #[test]
+    fn next_token_splits_and_trims_alike() {
+        let mut it = "1 \x0b \x0b \t".chars();
+        assert_eq!(next_token(&mut it, false).unwrap(), "1");
+        assert_eq!(next_token(&mut it, true).unwrap(), "\x0b \x0b");
+        assert!(matches!(
+            next_token(&mut " \t\r".chars(), true),
+            Err(ParseError::EndOfLine)
+        ));
+    }
+
+    #[test]
+    fn decode_line_falls_back_to_latin1() {
+        assert_eq!(
+            decode_line(b"0 Author: J\xc3\xbcrgen\r\n"),
+            "0 Author: Jürgen"
+        );
+        assert_eq!(decode_line(b"0 Author: J\xfcrgen\n"), "0 Author: Jürgen");
+        assert_eq!(decode_line(b"\xef\xbb\xbf0 Brick 2 x 4"), "0 Brick 2 x 4");
+        assert_eq!(
+            decode_line(b"0 \x93Tile\x94 \x96 \x80 1\x81\r\n"),
+            "0 “Tile” – € 1\u{81}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_latin1_document() {
+        let colors = ColorCatalog::new();
+        let mut document = b"\xef\xbb\xbf0 Stra\xdfenbahn\r\n0 Name: tram.ldr\r\n".to_vec();
+        document.extend_from_slice(b"0 Author: Ren\xe9 \xc5ngstr\xf6m\r\n");
+        document.extend_from_slice("0 // Zürich 🚋\r\n".as_bytes());
+        // Files pasted together may carry a BOM in the middle.
+        document.extend_from_slice(b"\xef\xbb\xbf0 // Fa\xe7ade \x96 west\r\n");
+
+        let parsed = parse_single_document(&mut &document[..], &colors)
+            .await
+            .unwrap();
+        assert_eq!(parsed.description, "Straßenbahn");
+        assert_eq!(parsed.author, "René Ångström");
+        assert_eq!(
+            parsed.commands,
+            vec![
+                Command::Meta(Meta::Comment("Zürich 🚋".into())),
+                Command::Meta(Meta::Comment("Façade – west".into())),
+            ]
+        );
+
+        let mut written = Vec::new();
+        parsed.write(&mut written).await.unwrap();
+        let reparsed = parse_single_document(&mut &written[..], &colors)
+            .await
+            .unwrap();
+        assert_eq!(reparsed.description, parsed.description);
+        assert_eq!(reparsed.author, parsed.author);
+        assert_eq!(reparsed.commands[0], parsed.commands[0]);
+    }
+
+    #[tokio::test]
+    async fn test_parse_error_span() {
+        let colors = ColorCatalog::new();
+        let document = "0 Test\n1 16 0 0 0 1 0 0 0 1 0 0 abc 1 3001.dat\n";
+
+        let error = parse_single_document(&mut document.as_bytes(), &colors)
+            .await
+            .unwrap_err();
+        assert_eq!(error.line, 2);
+        let span = error.span.unwrap();
+        assert_eq!(span.line, 2);
+        assert_eq!(span.column, 26);
+        assert_eq!(span.token, "abc");
+        assert_eq!(span.range, 32..35);
+        assert_eq!(&document[span.range], "abc");
+
+        let error = parse_single_document(&mut &b"\xef\xbb\xbf2 16 0 0"[..], &colors)
+            .await
+            .unwrap_err();
+        assert!(matches!(error.error, ParseError::EndOfLine));
+        let span = error.span.unwrap();
+        assert_eq!(span.column, 9);
+        assert_eq!(span.range, 11..11);
+
+        let error = parse_single_document(&mut "7 foo".as_bytes(), &colors)
+            .await
+            .unwrap_err();
+        assert_eq!(error.span.as_ref().unwrap().range, 0..1);
+        assert!(error.to_string().ends_with("(at line 1, column 1)"));
+    }
+
+    #[tokio::test]
+    async fn test_document_stream() {
+        let colors = ColorCatalog::new();
+        let document = "0 Test\n0 Name: test.ldr\n\n3 16 0 0 0 1 0 0 0 1 x\n2 4 0 0 0 1 1 1\n";
+        let mut reader = document.as_bytes();
+        let mut stream = DocumentStream::new(&mut reader, &colors);
+
+        let (span, item) = stream.next().await.unwrap().unwrap();
+        assert_eq!((span.line, span.range), (1, 0..6));
+        assert_eq!(
+            item,
+            DocumentItem::Command(Command::Meta(Meta::Comment("Test".into())))
+        );
+
+        let (span, item) = stream.next().await.unwrap().unwrap();
+        assert_eq!(span.line, 2);
+        assert_eq!(item, DocumentItem::Name("test.ldr".into()));
+
+        let error = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(error.line, 4);
+        assert_eq!(error.span.unwrap().token, "x");
+
+        let (span, item) = stream.next().await.unwrap().unwrap();
+        assert_eq!(span.line, 5);
+        assert_eq!(span.range, 48..63);
+        assert!(matches!(item, DocumentItem::Command(Command::Line(_))));
+
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_parse_options() {
+        let colors = ColorCatalog::new();
+        let document = "0 Junk\n1 16 0 0 0 1 0 0 0 1 0 0 0 1 3001.dat\nZZ\n2 24 0 0 0 1 0\n";
+
+        let error = parse_single_document_with_options(
+            &mut document.as_bytes(),
+            &colors,
+            &ParseOptions::default(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.line, 3);
+
+        let (parsed, warnings) = parse_single_document_with_options(
+            &mut document.as_bytes(),
+            &colors,
+            &ParseOptions { strict: false },
+        )
+        .await
+        .unwrap();
+        assert_eq!(parsed.description, "Junk");
+        assert_eq!(parsed.commands.len(), 1);
+        assert_eq!(
+            warnings.iter().map(|v| v.line).collect::<Vec<_>>(),
+            vec![3, 4]
+        );
+        assert!(matches!(
+            warnings[0].error,
+            ParseError::UnexpectedCommand(ref v) if v == "ZZ"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_parse_source_lines() {
+        let colors = ColorCatalog::new();
+        let document = "0 FILE main.ldr\n\
+                        0 Main\n\
+                        0 Name: main.ldr\n\
+                        \n\
+                        1 16 0 0 0 1 0 0 0 1 0 0 0 1 sub.ldr\n\
+                        ZZ\n\
+                        0 STEP\n\
+                        0 FILE sub.ldr\n\
+                        0 Sub\n\
+                        1 16 0 0 0 1 0 0 0 1 0 0 0 1 3001.dat\n";
+
+        let (parsed, lines, warnings) = parse_multipart_document_with_lines(
+            &mut document.as_bytes(),
+            &colors,
+            &ParseOptions { strict: false },
+        )
+        .await
+        .unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(lines.body, vec![5, 7]);
+        assert_eq!(lines.body.len(), parsed.body.commands.len());
+
+        let sub = PartAlias::from("sub.ldr");
+        assert_eq!(lines.line(Some(&sub), 0), Some(10));
+        assert_eq!(lines.command(Some(&sub), 10), Some(0));
+        assert_eq!(lines.command(None, 6), None);
+        assert_eq!(lines.line(Some(&PartAlias::from("x.ldr")), 0), None);
+    }
+
+    // Lines made of tokens the parser expects, in no particular order, reach deeper than
+    // random bytes do.
+    fn malformed_document() -> impl proptest::strategy::Strategy<Value = Vec<u8>> {
+        use proptest::{collection::vec, prelude::*};
+
+        let token = prop_oneof![
+            "[0-5]",
+            "-?[0-9]{0,3}(\\.[0-9]{0,3})?(e-?[0-9]{1,2})?",
+            "(BFC|CERTIFY|CLIP|CCW|CW|FILE|Name:|Author:|STEP|!LDRAW_ORG|!HISTORY|!LDCAD|!COLOUR)",
+            "(CODE|VALUE|EDGE|ALPHA|LUMINANCE|GLITTER|SPECKLE|FABRIC|MATERIAL|SIZE)",
+            "#[0-9A-F]{0,6}",
+            "[\\[\\]{}=~/]{1,3}",
+            ".{0,4}",
+        ];
+        let line = vec(token, 0..16).prop_map(|v| v.join(" "));
+        let text = vec(line, 0..8).prop_map(|v| v.join("\n").into_bytes());
+        prop_oneof![text, vec(any::<u8>(), 0..256)]
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn parser_does_not_panic(input in malformed_document()) {
+            use futures::executor::block_on;
+
+            let colors = ColorCatalog::new();
+            let _ = block_on(parse_single_document(&mut &input[..], &colors));
+            let _ = block_on(parse_multipart_document_with_options(
+                &mut &input[..],
+                &colors,
+                &ParseOptions { strict: false },
+            ));
+            let _ = block_on(parse_color_definitions(&mut &input[..]));
+
+            let mut reader = &input[..];
+            let mut stream = DocumentStream::new(&mut reader, &colors);
+            while block_on(stream.next()).is_some() {}
+
+            if let Ok(document) = block_on(parse_single_document(&mut &input[..], &colors)) {
+                let _ = document.metadata();
+                let _ = document.camera();
+            }
+        }
+    }
 }
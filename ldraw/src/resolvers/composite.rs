@@ -0,0 +1,265 @@
+use std::{collections::HashMap, sync::RwLock};
+
+use async_trait::async_trait;
+
+use crate::{
+    color::ColorCatalog,
+    document::MultipartDocument,
+    error::ResolutionError,
+    library::{FileLocation, LibraryLoader},
+    parser::ParseOptions,
+    PartAlias,
+};
+
+// Tries a list of loaders in order, e.g. a local directory, then a zipped library and then an
+// HTTP mirror. Remembers which loader satisfied each alias so that it is asked first next time.
+#[derive(Default)]
+pub struct CompositeLoader {
+    loaders: Vec<(String, Box<dyn LibraryLoader>)>,
+
+    sources: RwLock<HashMap<PartAlias, usize>>,
+    parse_options: ParseOptions,
+}
+
+impl CompositeLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push<L: LibraryLoader + 'static>(&mut self, name: &str, loader: L) {
+        self.loaders.push((name.to_string(), Box::new(loader)));
+    }
+
+    // Applies to models loaded with load_document.
+    pub fn set_parse_options(&mut self, options: ParseOptions) {
+        self.parse_options = options;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.loaders.is_empty()
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.loaders.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    // Name of the loader the alias was last loaded from.
+    pub fn source(&self, alias: &PartAlias) -> Option<String> {
+        let index = *self.sources.read().unwrap().get(alias)?;
+        self.loaders.get(index).map(|(name, _)| name.clone())
+    }
+
+    // Builds a chain out of locations given on the command line. URLs are fetched over HTTP,
+    // files ending with .zip are read as zipped libraries and anything else is taken as an
+    // LDraw directory. Documents local to the model are looked up in cwd.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn from_locations<S: AsRef<str>>(
+        locations: &[S],
+        cwd: Option<std::path::PathBuf>,
+    ) -> Result<Self, ResolutionError> {
+        use std::path::PathBuf;
+
+        use super::{local::LocalLoader, zip::ZipLoader};
+
+        let mut loader = Self::new();
+        for location in locations {
+            let location = location.as_ref();
+            #[cfg(feature = "http")]
+            if location.starts_with("http://") || location.starts_with("https://") {
+                let url = reqwest::Url::parse(location).map_err(|_| ResolutionError::NoLDrawDir)?;
+                loader.push(location, super::http::HttpLoader::new(Some(url), None));
+                continue;
+            }
+            let path = PathBuf::from(location);
+            if path
+                .extension()
+                .is_some_and(|v| v.eq_ignore_ascii_case("zip"))
+            {
+                loader.push(location, ZipLoader::open(&path).await?);
+            } else {
+                // Libraries with an index saved in them are looked up through it.
+                let mut local = LocalLoader::new(Some(path), cwd.clone());
+                local.load_index().await?;
+                loader.push(location, local);
+            }
+        }
+        Ok(loader)
+    }
+}
+
+// Models given by path are read from the disk whichever sources the library comes from.
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait(?Send)]
+impl crate::library::DocumentLoader<std::path::PathBuf> for CompositeLoader {
+    async fn load_document(
+        &self,
+        locator: &std::path::PathBuf,
+        colors: &ColorCatalog,
+    ) -> Result<MultipartDocument, ResolutionError> {
+        let mut loader = super::local::LocalLoader::new(None, None);
+        loader.set_parse_options(self.parse_options.clone());
+        loader.load_document(locator, colors).await
+    }
+}
+
+#[async_trait(?Send)]
+impl LibraryLoader for CompositeLoader {
+    async fn load_colors(&self) -> Result<ColorCatalog, ResolutionError> {
+        let mut error = None;
+        for (_, loader) in self.loaders.iter() {
+            match loader.load_colors().await {
+                Ok(v) => return Ok(v),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        Err(error.unwrap_or(ResolutionError::NoLDrawDir))
+    }
+
+    async fn load_ref(
+        &self,
+        alias: PartAlias,
+        local: bool,
+        colors: &ColorCatalog,
+    ) -> Result<(FileLocation, MultipartDocument), ResolutionError> {
+        let cached = self.sources.read().unwrap().get(&alias).copied();
+        let order = cached
+            .into_iter()
+            .chain((0..self.loaders.len()).filter(|v| Some(*v) != cached));
+
+        let mut error = None;
+        let mut suggestions = Vec::new();
+        for index in order {
+            let (_, loader) = &self.loaders[index];
+            match loader.load_ref(alias.clone(), local, colors).await {
+                Ok(v) => {
+                    self.sources.write().unwrap().insert(alias, index);
+                    return Ok(v);
+                }
+                Err(ResolutionError::FileNotFound { suggestions: v }) => {
+                    for suggestion in v {
+                        if !suggestions.contains(&suggestion) {
+                            suggestions.push(suggestion);
+                        }
+                    }
+                }
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+
+        // A part missing from every source is more telling than a source failing to load.
+        match error {
+            Some(e) if suggestions.is_empty() => Err(e),
+            _ => Err(ResolutionError::FileNotFound { suggestions }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, collections::HashMap, rc::Rc};
+
+    use async_trait::async_trait;
+
+    use crate::{
+        color::ColorCatalog,
+        document::{Document, MultipartDocument},
+        error::ResolutionError,
+        library::{FileLocation, LibraryLoader, PartKind},
+        PartAlias,
+    };
+
+    use super::CompositeLoader;
+
+    struct MockLoader {
+        parts: Vec<&'static str>,
+        hits: Rc<Cell<usize>>,
+    }
+
+    #[async_trait(?Send)]
+    impl LibraryLoader for MockLoader {
+        async fn load_colors(&self) -> Result<ColorCatalog, ResolutionError> {
+            Err(ResolutionError::FileNotFound {
+                suggestions: Vec::new(),
+            })
+        }
+
+        async fn load_ref(
+            &self,
+            alias: PartAlias,
+            _local: bool,
+            _colors: &ColorCatalog,
+        ) -> Result<(FileLocation, MultipartDocument), ResolutionError> {
+            self.hits.set(self.hits.get() + 1);
+            if !self.parts.contains(&alias.normalized.as_str()) {
+                return Err(ResolutionError::FileNotFound {
+                    suggestions: vec![PartAlias::from(self.parts[0])],
+                });
+            }
+            let document = MultipartDocument {
+                body: Document {
+                    name: alias.original.clone(),
+                    ..Default::default()
+                },
+                subparts: HashMap::new(),
+            };
+            Ok((FileLocation::Library(PartKind::Part), document))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_composite_loader() {
+        let first_hits = Rc::new(Cell::new(0));
+        let second_hits = Rc::new(Cell::new(0));
+
+        let mut loader = CompositeLoader::new();
+        loader.push(
+            "first",
+            MockLoader {
+                parts: vec!["3001.dat"],
+                hits: first_hits.clone(),
+            },
+        );
+        loader.push(
+            "second",
+            MockLoader {
+                parts: vec!["3002.dat"],
+                hits: second_hits.clone(),
+            },
+        );
+
+        let colors = ColorCatalog::new();
+        let alias = PartAlias::from("3002.dat");
+        let (_, document) = loader
+            .load_ref(alias.clone(), false, &colors)
+            .await
+            .unwrap();
+        assert_eq!(document.body.name, "3002.dat");
+        assert_eq!(loader.source(&alias), Some(String::from("second")));
+        assert_eq!((first_hits.get(), second_hits.get()), (1, 1));
+
+        // The source that satisfied the alias is asked first afterwards.
+        loader
+            .load_ref(alias.clone(), false, &colors)
+            .await
+            .unwrap();
+        assert_eq!((first_hits.get(), second_hits.get()), (1, 2));
+
+        match loader
+            .load_ref(PartAlias::from("3003.dat"), false, &colors)
+            .await
+        {
+            Err(e) => assert_eq!(
+                e.suggestions(),
+                &[PartAlias::from("3001.dat"), PartAlias::from("3002.dat")]
+            ),
+            Ok(_) => panic!("3003.dat should not resolve"),
+        }
+        assert_eq!(loader.source(&PartAlias::from("3003.dat")), None);
+
+        assert!(loader.load_colors().await.is_err());
+    }
+}
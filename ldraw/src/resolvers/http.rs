@@ -1,7 +1,12 @@
+use std::{collections::HashMap, sync::RwLock, time::Duration};
+
 use async_trait::async_trait;
 use futures::join;
-use reqwest::{Client, Error, Response, StatusCode, Url};
-use tokio::io::BufReader;
+use reqwest::{
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    Client, StatusCode, Url,
+};
+use tokio::{io::BufReader, sync::Semaphore};
 
 use crate::{
     color::ColorCatalog,
@@ -12,23 +17,170 @@ use crate::{
     PartAlias,
 };
 
+// Parts not yet released in the official library, laid out the same way.
+pub const UNOFFICIAL_LIBRARY_URL: &str = "https://library.ldraw.org/library/unofficial/";
+
+#[derive(Clone, Debug)]
+pub struct HttpLoaderOptions {
+    // Requests sent at once. Others wait for one of them to finish.
+    pub max_in_flight: usize,
+    // Attempts made after a failed one, waiting twice as long each time.
+    pub retries: u32,
+    pub backoff: Duration,
+    pub timeout: Option<Duration>,
+    // Keeps fetched files with their ETag and Last-Modified headers, and asks the server whether
+    // they changed instead of downloading them again.
+    pub conditional_requests: bool,
+}
+
+impl Default for HttpLoaderOptions {
+    fn default() -> Self {
+        HttpLoaderOptions {
+            max_in_flight: 8,
+            retries: 3,
+            backoff: Duration::from_millis(250),
+            timeout: Some(Duration::from_secs(30)),
+            conditional_requests: true,
+        }
+    }
+}
+
+struct CachedResponse {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    body: Vec<u8>,
+}
+
 pub struct HttpLoader {
     ldraw_url_base: Option<Url>,
     document_url_base: Option<Url>,
 
     client: Client,
+    options: HttpLoaderOptions,
+    semaphore: Semaphore,
+    cache: RwLock<HashMap<Url, CachedResponse>>,
 }
 
 impl HttpLoader {
     pub fn new(ldraw_url_base: Option<Url>, document_url_base: Option<Url>) -> Self {
+        Self::with_options(
+            ldraw_url_base,
+            document_url_base,
+            HttpLoaderOptions::default(),
+        )
+    }
+
+    pub fn with_options(
+        ldraw_url_base: Option<Url>,
+        document_url_base: Option<Url>,
+        options: HttpLoaderOptions,
+    ) -> Self {
         HttpLoader {
             ldraw_url_base,
             document_url_base,
             client: Client::new(),
+            semaphore: Semaphore::new(options.max_in_flight.max(1)),
+            options,
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    // Fetches a file, returning None if the server does not have it. Connection failures, timeouts
+    // and server errors are retried.
+    async fn fetch(&self, url: Url) -> Result<Option<Vec<u8>>, ResolutionError> {
+        let mut attempt = 0;
+        loop {
+            let permit = self.semaphore.acquire().await.unwrap();
+            let result = self.request(&url).await;
+            // Other requests may go ahead while this one waits for the next attempt.
+            drop(permit);
+
+            let error = match result {
+                Ok(v) => return Ok(v),
+                Err(e) => e,
+            };
+            let retriable = match &error {
+                ResolutionError::RemoteError(e) => match e.status() {
+                    Some(status) => {
+                        status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+                    }
+                    None => !e.is_decode() && !e.is_builder(),
+                },
+                _ => false,
+            };
+            if !retriable || attempt >= self.options.retries {
+                return Err(error);
+            }
+            sleep(self.options.backoff * 2u32.saturating_pow(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    async fn request(&self, url: &Url) -> Result<Option<Vec<u8>>, ResolutionError> {
+        let mut request = self.client.get(url.clone());
+        if let Some(timeout) = self.options.timeout {
+            request = request.timeout(timeout);
+        }
+        if self.options.conditional_requests {
+            if let Some(cached) = self.cache.read().unwrap().get(url) {
+                if let Some(etag) = &cached.etag {
+                    request = request.header(IF_NONE_MATCH, etag);
+                }
+                if let Some(last_modified) = &cached.last_modified {
+                    request = request.header(IF_MODIFIED_SINCE, last_modified);
+                }
+            }
+        }
+
+        let response = request.send().await?;
+        match response.status() {
+            StatusCode::NOT_MODIFIED => {
+                if let Some(cached) = self.cache.read().unwrap().get(url) {
+                    return Ok(Some(cached.body.clone()));
+                }
+                Ok(None)
+            }
+            status if status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS => {
+                Ok(None)
+            }
+            _ => {
+                let response = response.error_for_status()?;
+                let header = |name| {
+                    response
+                        .headers()
+                        .get(name)
+                        .and_then(|v| v.to_str().ok())
+                        .map(String::from)
+                };
+                let (etag, last_modified) = (header(ETAG), header(LAST_MODIFIED));
+                let body = response.bytes().await?.to_vec();
+                if self.options.conditional_requests && (etag.is_some() || last_modified.is_some())
+                {
+                    self.cache.write().unwrap().insert(
+                        url.clone(),
+                        CachedResponse {
+                            etag,
+                            last_modified,
+                            body: body.clone(),
+                        },
+                    );
+                }
+                Ok(Some(body))
+            }
         }
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await;
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn sleep(duration: Duration) {
+    gloo_timers::future::sleep(duration).await;
+}
+
 #[async_trait(?Send)]
 impl DocumentLoader<String> for HttpLoader {
     async fn load_document(
@@ -38,9 +190,18 @@ impl DocumentLoader<String> for HttpLoader {
     ) -> Result<MultipartDocument, ResolutionError> {
         let url = match Url::parse(locator) {
             Ok(e) => e,
-            Err(_) => return Err(ResolutionError::FileNotFound),
+            Err(_) => {
+                return Err(ResolutionError::FileNotFound {
+                    suggestions: Vec::new(),
+                })
+            }
         };
-        let bytes = self.client.get(url).send().await?.bytes().await?;
+        let bytes = self
+            .fetch(url)
+            .await?
+            .ok_or(ResolutionError::FileNotFound {
+                suggestions: Vec::new(),
+            })?;
 
         Ok(parse_multipart_document(&mut BufReader::new(&*bytes), colors).await?)
     }
@@ -56,13 +217,13 @@ impl LibraryLoader for HttpLoader {
         };
 
         let url = ldraw_url_base.join("LDConfig.ldr").unwrap();
-        let response = self.client.get(url).send().await?;
-        if response.status() == StatusCode::NOT_FOUND {
-            Err(ResolutionError::FileNotFound)
-        } else {
-            let bytes = response.bytes().await?;
-            Ok(parse_color_definitions(&mut BufReader::new(&*bytes)).await?)
-        }
+        let bytes = self
+            .fetch(url)
+            .await?
+            .ok_or(ResolutionError::FileNotFound {
+                suggestions: Vec::new(),
+            })?;
+        Ok(parse_color_definitions(&mut BufReader::new(&*bytes)).await?)
     }
 
     async fn load_ref(
@@ -84,53 +245,44 @@ impl LibraryLoader for HttpLoader {
             .join(&format!("p/{}", alias.normalized))
             .unwrap();
 
-        let parts_fut = self.client.get(parts_url).send();
-        let p_fut = self.client.get(p_url).send();
-
-        let (location, res) = if local && self.document_url_base.is_some() {
-            let document_url_base = self.document_url_base.as_ref().unwrap();
-
-            let local_url = document_url_base.join(&alias.normalized).unwrap();
-            let local_fut = self.client.get(local_url).send();
-            let (local, parts, p) = join!(local_fut, parts_fut, p_fut);
+        let parts_fut = self.fetch(parts_url);
+        let p_fut = self.fetch(p_url);
 
-            if let Some(v) = select_response(local) {
-                (FileLocation::Local, v)
-            } else if let Some(v) = select_response(parts) {
-                (FileLocation::Library(PartKind::Part), v)
-            } else if let Some(v) = select_response(p) {
-                (FileLocation::Library(PartKind::Primitive), v)
+        let candidates =
+            if let (true, Some(document_url_base)) = (local, self.document_url_base.as_ref()) {
+                let local_url = document_url_base.join(&alias.normalized).unwrap();
+                let (local, parts, p) = join!(self.fetch(local_url), parts_fut, p_fut);
+                vec![
+                    (FileLocation::Local, local),
+                    (FileLocation::Library(PartKind::Part), parts),
+                    (FileLocation::Library(PartKind::Primitive), p),
+                ]
             } else {
-                return Err(ResolutionError::FileNotFound);
-            }
-        } else {
-            let (parts, p) = join!(parts_fut, p_fut);
-            if let Some(v) = select_response(parts) {
-                (FileLocation::Library(PartKind::Part), v)
-            } else if let Some(v) = select_response(p) {
-                (FileLocation::Library(PartKind::Primitive), v)
-            } else {
-                return Err(ResolutionError::FileNotFound);
-            }
-        };
+                let (parts, p) = join!(parts_fut, p_fut);
+                vec![
+                    (FileLocation::Library(PartKind::Part), parts),
+                    (FileLocation::Library(PartKind::Primitive), p),
+                ]
+            };
 
-        let bytes = res.bytes().await?;
-        Ok((
-            location,
-            parse_multipart_document(&mut BufReader::new(&*bytes), colors).await?,
-        ))
-    }
-}
-
-fn select_response(response: Result<Response, Error>) -> Option<Response> {
-    match response {
-        Ok(r) => {
-            if r.status() == StatusCode::OK {
-                Some(r)
-            } else {
-                None
+        // Failed requests are only reported if no other location has the file.
+        let mut error = None;
+        for (location, result) in candidates {
+            match result {
+                Ok(Some(bytes)) => {
+                    return Ok((
+                        location,
+                        parse_multipart_document(&mut BufReader::new(&*bytes), colors).await?,
+                    ));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
             }
         }
-        Err(_) => None,
+        Err(error.unwrap_or(ResolutionError::FileNotFound {
+            suggestions: Vec::new(),
+        }))
     }
 }
@@ -1,8 +1,11 @@
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
 
 use async_trait::async_trait;
 use tokio::{
-    fs::{try_exists, File},
+    fs::{read, read_dir, read_to_string, try_exists, File},
     io::BufReader,
 };
 
@@ -10,19 +13,173 @@ use crate::{
     color::ColorCatalog,
     document::MultipartDocument,
     error::ResolutionError,
-    library::{DocumentLoader, FileLocation, LibraryLoader, PartKind},
-    parser::{parse_color_definitions, parse_multipart_document},
+    import::{
+        lxf::{parse_lxf, parse_lxfml, LxfMapping},
+        studio::parse_io,
+    },
+    index::{parse_library_index, LibraryIndex, LibraryIndexEntry, INDEX_FILE_NAME},
+    library::{suggest_aliases, DocumentLoader, FileLocation, LibraryLoader, PartKind},
+    parser::{
+        parse_color_definitions, parse_document_header, parse_multipart_document,
+        parse_multipart_document_with_options, ParseOptions,
+    },
     PartAlias,
 };
 
+// Files to pick suggestions for missing parts from.
+#[derive(Default)]
+struct Catalog {
+    local: Vec<PartAlias>,
+    library: Vec<PartAlias>,
+}
+
 pub struct LocalLoader {
     ldrawdir: Option<PathBuf>,
     cwd: Option<PathBuf>,
+
+    catalog: RwLock<Option<Catalog>>,
+    index: Option<LibraryIndex>,
+    parse_options: ParseOptions,
 }
 
 impl LocalLoader {
     pub fn new(ldrawdir: Option<PathBuf>, cwd: Option<PathBuf>) -> Self {
-        LocalLoader { ldrawdir, cwd }
+        LocalLoader {
+            ldrawdir,
+            cwd,
+            catalog: RwLock::new(None),
+            index: None,
+            parse_options: ParseOptions::default(),
+        }
+    }
+
+    // Applies to models loaded with load_document. Library files are always parsed strictly.
+    pub fn set_parse_options(&mut self, options: ParseOptions) {
+        self.parse_options = options;
+    }
+
+    // Looks parts up in the index rather than on the disk. Parts missing from the index are
+    // still searched for, in case they were added after it was built.
+    pub fn set_index(&mut self, index: Option<LibraryIndex>) {
+        self.index = index;
+        *self.catalog.get_mut().unwrap() = None;
+    }
+
+    pub fn index(&self) -> Option<&LibraryIndex> {
+        self.index.as_ref()
+    }
+
+    // Reads the index saved in the LDraw directory, if there is one.
+    pub async fn load_index(&mut self) -> Result<bool, ResolutionError> {
+        let Some(ldrawdir) = self.ldrawdir.as_ref() else {
+            return Err(ResolutionError::NoLDrawDir);
+        };
+        let path = ldrawdir.join(INDEX_FILE_NAME);
+        if !try_exists(&path).await? {
+            return Ok(false);
+        }
+
+        let index = parse_library_index(&mut BufReader::new(File::open(&path).await?)).await?;
+        self.index = Some(index);
+        Ok(true)
+    }
+
+    // Reads headers of every part and primitive in the library. Files that could not be read
+    // are left out of the index, and returned along with it.
+    pub async fn build_index(
+        &self,
+    ) -> Result<(LibraryIndex, Vec<(String, ResolutionError)>), ResolutionError> {
+        let Some(ldrawdir) = self.ldrawdir.as_ref() else {
+            return Err(ResolutionError::NoLDrawDir);
+        };
+
+        let mut index = LibraryIndex::new();
+        let mut skipped = Vec::new();
+        for directory in ["parts", "p"] {
+            for alias in Self::list_aliases(&ldrawdir.join(directory), true).await {
+                if !alias.normalized.ends_with(".dat") {
+                    continue;
+                }
+                let path = format!("{}/{}", directory, alias.original.replace('\\', "/"));
+                // Headers are all there is to the index, so the rest is left unread.
+                let document = match File::open(ldrawdir.join(&path)).await {
+                    Ok(file) => parse_document_header(&mut BufReader::new(file))
+                        .await
+                        .map_err(ResolutionError::from),
+                    Err(e) => Err(e.into()),
+                };
+                match document {
+                    Ok(document) => {
+                        if let Some(entry) = LibraryIndexEntry::from_document(&path, &document) {
+                            index.insert(entry);
+                        }
+                    }
+                    Err(e) => skipped.push((path, e)),
+                }
+            }
+        }
+        Ok((index, skipped))
+    }
+
+    // Lists every file under the directory as aliases relative to it.
+    async fn list_aliases(base: &Path, recursive: bool) -> Vec<PartAlias> {
+        let mut result = Vec::new();
+        let mut pending = vec![base.to_path_buf()];
+
+        while let Some(dir) = pending.pop() {
+            let mut entries = match read_dir(&dir).await {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let path = entry.path();
+                let is_dir = match entry.file_type().await {
+                    Ok(v) => v.is_dir(),
+                    Err(_) => continue,
+                };
+                if is_dir {
+                    if recursive {
+                        pending.push(path);
+                    }
+                } else if let Ok(relative) = path.strip_prefix(base) {
+                    result.push(PartAlias::from(relative.to_string_lossy().as_ref()));
+                }
+            }
+        }
+
+        result
+    }
+
+    // The library and the model directory are listed on the first miss only, so that models
+    // with many missing parts don't walk the library over and over.
+    async fn suggest(&self, alias: &PartAlias, local: bool) -> Vec<PartAlias> {
+        if self.catalog.read().unwrap().is_none() {
+            let mut catalog = Catalog::default();
+            if let Some(ldrawdir) = self.ldrawdir.as_ref().filter(|_| self.index.is_none()) {
+                catalog
+                    .library
+                    .extend(Self::list_aliases(&ldrawdir.join("parts"), true).await);
+                catalog
+                    .library
+                    .extend(Self::list_aliases(&ldrawdir.join("p"), true).await);
+            }
+            if let Some(cwd) = self.cwd.as_ref() {
+                catalog.local = Self::list_aliases(cwd, false).await;
+            }
+            *self.catalog.write().unwrap() = Some(catalog);
+        }
+
+        let catalog = self.catalog.read().unwrap();
+        let catalog = catalog.as_ref().unwrap();
+        suggest_aliases(
+            alias,
+            catalog
+                .local
+                .iter()
+                .filter(|_| local)
+                .chain(catalog.library.iter())
+                .chain(self.index.iter().flat_map(|v| v.aliases())),
+        )
     }
 }
 
@@ -34,13 +191,39 @@ impl DocumentLoader<PathBuf> for LocalLoader {
         colors: &ColorCatalog,
     ) -> Result<MultipartDocument, ResolutionError> {
         if !try_exists(&locator).await? {
-            return Err(ResolutionError::FileNotFound);
+            return Err(ResolutionError::FileNotFound {
+                suggestions: Vec::new(),
+            });
         }
 
-        Ok(
-            parse_multipart_document(&mut BufReader::new(File::open(locator).await?), colors)
-                .await?,
-        )
+        // Models saved by LEGO Digital Designer and BrickLink Studio are converted on the way in.
+        let extension = locator
+            .extension()
+            .map(|v| v.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("lxf") => Ok(parse_lxf(
+                &read(locator).await?,
+                &LxfMapping::bundled(),
+                colors,
+            )?),
+            Some("lxfml") => Ok(parse_lxfml(
+                &read_to_string(locator).await?,
+                &LxfMapping::bundled(),
+                colors,
+            )?),
+            Some("io") => Ok(parse_io(&read(locator).await?, colors).await?),
+            // Lines skipped in lenient mode are not reported from here. Parse the file directly
+            // to get them.
+            _ => {
+                let (document, _) = parse_multipart_document_with_options(
+                    &mut BufReader::new(File::open(locator).await?),
+                    colors,
+                    &self.parse_options,
+                )
+                .await?;
+                Ok(document)
+            }
+        }
     }
 }
 
@@ -59,7 +242,9 @@ impl LibraryLoader for LocalLoader {
         };
 
         if !try_exists(&path).await? {
-            return Err(ResolutionError::FileNotFound);
+            return Err(ResolutionError::FileNotFound {
+                suggestions: Vec::new(),
+            });
         }
 
         Ok(parse_color_definitions(&mut BufReader::new(File::open(&*path).await?)).await?)
@@ -94,16 +279,33 @@ impl LibraryLoader for LocalLoader {
             path
         };
 
-        let (kind, path) =
-            if local && cwd_path.is_some() && try_exists(&cwd_path.as_ref().unwrap()).await? {
-                (FileLocation::Local, cwd_path.as_ref().unwrap())
-            } else if try_exists(&parts_path).await? {
-                (FileLocation::Library(PartKind::Part), &parts_path)
-            } else if try_exists(&p_path).await? {
-                (FileLocation::Library(PartKind::Primitive), &p_path)
-            } else {
-                return Err(ResolutionError::FileNotFound);
-            };
+        let local_path = match cwd_path.as_ref() {
+            Some(v) if local && try_exists(v).await? => Some(v),
+            _ => None,
+        };
+
+        let indexed_path = match self.index.as_ref().and_then(|v| v.get(&alias)) {
+            // The file may have been removed since the index was built, in which case it is
+            // looked up as if there was no index.
+            Some(entry) if try_exists(ldrawdir.join(&entry.path)).await? => {
+                Some((entry.kind, ldrawdir.join(&entry.path)))
+            }
+            _ => None,
+        };
+
+        let (kind, path) = if let Some(local_path) = local_path {
+            (FileLocation::Local, local_path)
+        } else if let Some((kind, path)) = indexed_path.as_ref() {
+            (FileLocation::Library(*kind), path)
+        } else if try_exists(&parts_path).await? {
+            (FileLocation::Library(PartKind::Part), &parts_path)
+        } else if try_exists(&p_path).await? {
+            (FileLocation::Library(PartKind::Primitive), &p_path)
+        } else {
+            return Err(ResolutionError::FileNotFound {
+                suggestions: self.suggest(&alias, local).await,
+            });
+        };
 
         let document =
             parse_multipart_document(&mut BufReader::new(File::open(&**path).await?), colors)
@@ -112,3 +314,47 @@ impl LibraryLoader for LocalLoader {
         Ok((kind, document))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, remove_dir_all, remove_file, write};
+
+    use super::LocalLoader;
+    use crate::{
+        color::ColorCatalog,
+        library::{FileLocation, LibraryLoader, PartKind},
+        PartAlias,
+    };
+
+    #[tokio::test]
+    async fn test_index_of_changed_library() {
+        let ldrawdir = std::env::temp_dir().join(format!("ldraw-index-{}", std::process::id()));
+        let _ = remove_dir_all(&ldrawdir);
+        create_dir_all(ldrawdir.join("parts")).unwrap();
+        create_dir_all(ldrawdir.join("p")).unwrap();
+        write(ldrawdir.join("parts/3001.dat"), "0 Brick  2 x  4\n").unwrap();
+        write(ldrawdir.join("parts/readme.txt"), "Not a part\n").unwrap();
+
+        let mut loader = LocalLoader::new(Some(ldrawdir.clone()), None);
+        let (index, skipped) = loader.build_index().await.unwrap();
+        assert!(skipped.is_empty());
+        assert_eq!(index.len(), 1);
+        assert!(index.get(&PartAlias::from("3001.dat")).is_some());
+        loader.set_index(Some(index));
+
+        remove_file(ldrawdir.join("parts/3001.dat")).unwrap();
+        write(ldrawdir.join("p/3001.dat"), "0 Brick  2 x  4\n").unwrap();
+
+        let (location, document) = loader
+            .load_ref(PartAlias::from("3001.dat"), false, &ColorCatalog::new())
+            .await
+            .unwrap();
+        assert!(matches!(
+            location,
+            FileLocation::Library(PartKind::Primitive)
+        ));
+        assert_eq!(document.body.description, "Brick  2 x  4");
+
+        remove_dir_all(&ldrawdir).unwrap();
+    }
+}
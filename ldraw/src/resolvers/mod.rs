@@ -1,4 +1,8 @@
+pub mod composite;
 #[cfg(any(target_arch = "wasm32", feature = "http"))]
 pub mod http;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod local;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod watch;
+pub mod zip;
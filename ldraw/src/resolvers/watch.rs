@@ -0,0 +1,165 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use tokio::fs::read_dir;
+
+use crate::{library::PartCache, PartAlias};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LibraryChange {
+    Added(PartAlias),
+    Modified(PartAlias),
+    Removed(PartAlias),
+}
+
+impl LibraryChange {
+    pub fn alias(&self) -> &PartAlias {
+        match self {
+            LibraryChange::Added(alias)
+            | LibraryChange::Modified(alias)
+            | LibraryChange::Removed(alias) => alias,
+        }
+    }
+}
+
+// Notices files added, modified or removed in library directories since the last poll.
+// Every poll reads metadata of the whole library, so it should be done every few seconds
+// rather than every frame.
+pub struct LibraryWatcher {
+    roots: Vec<PathBuf>,
+    snapshot: HashMap<PartAlias, SystemTime>,
+}
+
+impl LibraryWatcher {
+    // Watches parts and primitives under the LDraw directory, as LocalLoader looks them up.
+    pub async fn new(ldrawdir: &Path) -> Self {
+        Self::with_roots(vec![ldrawdir.join("parts"), ldrawdir.join("p")]).await
+    }
+
+    // Roots come in order of precedence, as an alias found in more than one of them
+    // resolves to the first.
+    pub async fn with_roots(roots: Vec<PathBuf>) -> Self {
+        let snapshot = scan(&roots).await;
+        LibraryWatcher { roots, snapshot }
+    }
+
+    pub async fn poll(&mut self) -> Vec<LibraryChange> {
+        let snapshot = scan(&self.roots).await;
+
+        let mut changes = Vec::new();
+        for (alias, modified) in snapshot.iter() {
+            match self.snapshot.get(alias) {
+                None => changes.push(LibraryChange::Added(alias.clone())),
+                Some(previous) if previous != modified => {
+                    changes.push(LibraryChange::Modified(alias.clone()))
+                }
+                _ => {}
+            }
+        }
+        changes.extend(
+            self.snapshot
+                .keys()
+                .filter(|alias| !snapshot.contains_key(alias))
+                .map(|alias| LibraryChange::Removed(alias.clone())),
+        );
+        changes.sort_by(|a, b| a.alias().normalized.cmp(&b.alias().normalized));
+
+        self.snapshot = snapshot;
+        changes
+    }
+}
+
+async fn scan(roots: &[PathBuf]) -> HashMap<PartAlias, SystemTime> {
+    let mut result = HashMap::new();
+
+    for root in roots {
+        let mut pending = vec![root.clone()];
+        while let Some(dir) = pending.pop() {
+            let mut entries = match read_dir(&dir).await {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let path = entry.path();
+                let metadata = match entry.metadata().await {
+                    Ok(v) => v,
+                    Err(_) => continue,
+                };
+                if metadata.is_dir() {
+                    pending.push(path);
+                } else if let (Ok(relative), Ok(modified)) =
+                    (path.strip_prefix(root), metadata.modified())
+                {
+                    result
+                        .entry(PartAlias::from(relative.to_string_lossy().as_ref()))
+                        .or_insert(modified);
+                }
+            }
+        }
+    }
+
+    result
+}
+
+// Drops cached documents of changed files so that they are read again on next
+// resolution. Returns the number of documents dropped.
+pub fn invalidate_cache(cache: &PartCache, changes: &[LibraryChange]) -> usize {
+    changes
+        .iter()
+        .filter(|change| cache.invalidate(change.alias()))
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{create_dir_all, remove_dir_all, remove_file, write, File},
+        time::{Duration, SystemTime},
+    };
+
+    use super::{LibraryChange, LibraryWatcher};
+    use crate::PartAlias;
+
+    #[tokio::test]
+    async fn test_library_watcher_poll() {
+        let ldrawdir = std::env::temp_dir().join(format!("ldraw-watch-{}", std::process::id()));
+        let _ = remove_dir_all(&ldrawdir);
+        create_dir_all(ldrawdir.join("parts/s")).unwrap();
+        create_dir_all(ldrawdir.join("p")).unwrap();
+        write(ldrawdir.join("parts/3001.dat"), "0 Brick  2 x  4\n").unwrap();
+        write(
+            ldrawdir.join("parts/s/3001s01.dat"),
+            "0 ~Brick  2 x  4 Side\n",
+        )
+        .unwrap();
+        write(ldrawdir.join("p/stud.dat"), "0 Stud\n").unwrap();
+
+        let mut watcher = LibraryWatcher::new(&ldrawdir).await;
+        assert!(watcher.poll().await.is_empty());
+
+        write(ldrawdir.join("parts/3002.dat"), "0 Brick  2 x  3\n").unwrap();
+        remove_file(ldrawdir.join("p/stud.dat")).unwrap();
+        // Timestamps may be too coarse to tell apart writes made within the test.
+        File::options()
+            .write(true)
+            .open(ldrawdir.join("parts/s/3001s01.dat"))
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+
+        assert_eq!(
+            watcher.poll().await,
+            vec![
+                LibraryChange::Added(PartAlias::from("3002.dat")),
+                LibraryChange::Modified(PartAlias::from("s/3001s01.dat")),
+                LibraryChange::Removed(PartAlias::from("stud.dat")),
+            ]
+        );
+        assert!(watcher.poll().await.is_empty());
+
+        remove_dir_all(&ldrawdir).unwrap();
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use tokio::io::BufReader;
+
+use crate::{
+    color::ColorCatalog,
+    document::MultipartDocument,
+    error::ResolutionError,
+    import::zip::{read_directory, read_entry, ZipEntry},
+    library::{suggest_aliases, FileLocation, LibraryLoader, PartKind},
+    parser::{parse_color_definitions, parse_multipart_document},
+    PartAlias,
+};
+
+// Loads parts from a library packed in a ZIP archive, such as complete.zip distributed by
+// LDraw.org or ldrawunf.zip of unofficial parts. Files are only decompressed when requested.
+pub struct ZipLoader {
+    data: Vec<u8>,
+    entries: HashMap<String, ZipEntry>,
+}
+
+impl ZipLoader {
+    pub fn new(data: Vec<u8>) -> Result<Self, ResolutionError> {
+        let directory = read_directory(&data)?;
+
+        // Archives keep the library either at the top or in a directory such as ldraw/.
+        let prefix = directory
+            .iter()
+            .filter_map(|entry| {
+                let name = entry.name.replace('\\', "/").to_ascii_lowercase();
+                ["parts/", "p/"].iter().find_map(|dir| {
+                    if name.starts_with(dir) {
+                        Some(String::new())
+                    } else {
+                        name.find(&format!("/{}", dir))
+                            .map(|index| name[..index + 1].to_string())
+                    }
+                })
+            })
+            .min_by_key(|v| v.len())
+            .unwrap_or_default();
+
+        let entries = directory
+            .into_iter()
+            .filter_map(|entry| {
+                let name = entry.name.replace('\\', "/").to_ascii_lowercase();
+                name.strip_prefix(&prefix)
+                    .map(|v| (v.to_string(), entry.clone()))
+            })
+            .collect();
+
+        Ok(ZipLoader { data, entries })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn open(path: &std::path::Path) -> Result<Self, ResolutionError> {
+        Self::new(tokio::fs::read(path).await?)
+    }
+
+    fn read(&self, name: &str) -> Option<Result<Vec<u8>, ResolutionError>> {
+        self.entries
+            .get(name)
+            .map(|entry| Ok(read_entry(&self.data, entry, None)?))
+    }
+
+    fn suggest(&self, alias: &PartAlias) -> Vec<PartAlias> {
+        let catalog = self
+            .entries
+            .keys()
+            .filter_map(|name| {
+                name.strip_prefix("parts/")
+                    .or_else(|| name.strip_prefix("p/"))
+                    .map(PartAlias::from)
+            })
+            .collect::<Vec<_>>();
+        suggest_aliases(alias, &catalog)
+    }
+}
+
+#[async_trait(?Send)]
+impl LibraryLoader for ZipLoader {
+    async fn load_colors(&self) -> Result<ColorCatalog, ResolutionError> {
+        let data = self
+            .read("ldconfig.ldr")
+            .ok_or(ResolutionError::FileNotFound {
+                suggestions: Vec::new(),
+            })??;
+        Ok(parse_color_definitions(&mut BufReader::new(&*data)).await?)
+    }
+
+    // Archives hold no documents local to the model, so those are looked up in the library.
+    async fn load_ref(
+        &self,
+        alias: PartAlias,
+        _local: bool,
+        colors: &ColorCatalog,
+    ) -> Result<(FileLocation, MultipartDocument), ResolutionError> {
+        let (kind, data) = if let Some(data) = self.read(&format!("parts/{}", alias.normalized)) {
+            (PartKind::Part, data?)
+        } else if let Some(data) = self.read(&format!("p/{}", alias.normalized)) {
+            (PartKind::Primitive, data?)
+        } else {
+            return Err(ResolutionError::FileNotFound {
+                suggestions: self.suggest(&alias),
+            });
+        };
+
+        let document = parse_multipart_document(&mut BufReader::new(&*data), colors).await?;
+        Ok((FileLocation::Library(kind), document))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::ResolutionError,
+        import::zip::build_archive,
+        library::{FileLocation, LibraryLoader, PartKind},
+        PartAlias,
+    };
+
+    use super::ZipLoader;
+
+    #[tokio::test]
+    async fn test_zip_loader() {
+        let archive = build_archive(
+            &[
+                (
+                    "ldraw/LDConfig.ldr",
+                    b"0 !COLOUR Red CODE 4 VALUE #C91A09 EDGE #333333\n",
+                ),
+                ("ldraw/parts/3001.dat", b"0 Brick 2 x 4\n"),
+                (
+                    "ldraw/parts/s/3001s01.dat",
+                    b"0 ~Brick 2 x 4 without Front Studs\n",
+                ),
+                ("ldraw/p/stud.dat", b"0 Stud\n"),
+            ],
+            None,
+        );
+        let loader = ZipLoader::new(archive).unwrap();
+        let colors = loader.load_colors().await.unwrap();
+        assert!(colors.contains_key(&4));
+
+        let (location, document) = loader
+            .load_ref(PartAlias::from("3001.dat"), false, &colors)
+            .await
+            .unwrap();
+        assert!(matches!(location, FileLocation::Library(PartKind::Part)));
+        assert_eq!(document.body.description, "Brick 2 x 4");
+
+        let (location, _) = loader
+            .load_ref(PartAlias::from("S\\3001S01.DAT"), false, &colors)
+            .await
+            .unwrap();
+        assert!(matches!(location, FileLocation::Library(PartKind::Part)));
+
+        let (location, _) = loader
+            .load_ref(PartAlias::from("stud.dat"), false, &colors)
+            .await
+            .unwrap();
+        assert!(matches!(
+            location,
+            FileLocation::Library(PartKind::Primitive)
+        ));
+
+        match loader
+            .load_ref(PartAlias::from("3010.dat"), false, &colors)
+            .await
+        {
+            Err(ResolutionError::FileNotFound { suggestions }) => {
+                assert_eq!(suggestions.first(), Some(&PartAlias::from("3001.dat")))
+            }
+            _ => panic!("3010.dat should not resolve"),
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use std::collections::HashMap;
+
+use cgmath::SquareMatrix;
+
+use crate::{
+    color::ColorReference,
+    document::{Document, MultipartDocument},
+    elements::{Command, PartReference},
+    Matrix4, PartAlias,
+};
+
+pub fn reference(name: &str) -> Command {
+    Command::PartReference(PartReference {
+        color: ColorReference::Current,
+        matrix: Matrix4::identity(),
+        name: PartAlias::from(name),
+    })
+}
+
+pub fn document(name: &str, commands: Vec<Command>) -> Document {
+    Document {
+        name: name.to_string(),
+        commands,
+        ..Default::default()
+    }
+}
+
+pub fn multipart(body: Document) -> MultipartDocument {
+    MultipartDocument {
+        body,
+        subparts: HashMap::new(),
+    }
+}
@@ -0,0 +1,203 @@
+use cgmath::{InnerSpace, Matrix, SquareMatrix};
+
+use crate::{Matrix3, Matrix4, Vector3};
+
+pub const LDU_PER_STUD: f32 = 20.0;
+pub const LDU_PER_PLATE: f32 = 8.0;
+pub const LDU_PER_BRICK: f32 = 24.0;
+pub const MM_PER_LDU: f32 = 0.4;
+
+// One stud horizontally, one plate vertically.
+pub const STUD_GRID: Vector3 = Vector3 {
+    x: LDU_PER_STUD,
+    y: LDU_PER_PLATE,
+    z: LDU_PER_STUD,
+};
+
+pub fn studs(count: f32) -> f32 {
+    count * LDU_PER_STUD
+}
+
+pub fn plates(count: f32) -> f32 {
+    count * LDU_PER_PLATE
+}
+
+pub fn bricks(count: f32) -> f32 {
+    count * LDU_PER_BRICK
+}
+
+pub fn ldu_to_mm(value: f32) -> f32 {
+    value * MM_PER_LDU
+}
+
+pub fn mm_to_ldu(value: f32) -> f32 {
+    value / MM_PER_LDU
+}
+
+pub fn snap(value: f32, grid: f32) -> f32 {
+    if grid > 0.0 {
+        (value / grid).round() * grid
+    } else {
+        value
+    }
+}
+
+pub fn snap_vector(vector: &Vector3, grid: &Vector3) -> Vector3 {
+    Vector3::new(
+        snap(vector.x, grid.x),
+        snap(vector.y, grid.y),
+        snap(vector.z, grid.z),
+    )
+}
+
+pub fn snap_translation(matrix: &Matrix4, grid: &Vector3) -> Matrix4 {
+    let mut result = *matrix;
+    let translation = snap_vector(&matrix.w.truncate(), grid);
+    result.w.x = translation.x;
+    result.w.y = translation.y;
+    result.w.z = translation.z;
+    result
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Decomposition {
+    pub translation: Vector3,
+    pub rotation: Matrix3,
+    pub scale: Vector3,
+}
+
+impl Decomposition {
+    pub fn to_matrix(&self) -> Matrix4 {
+        let m = self.rotation * Matrix3::from_diagonal(self.scale);
+        let mut result = Matrix4::from(m);
+        result.w = self.translation.extend(1.0);
+        result
+    }
+}
+
+// Splits an affine matrix into translation, rotation and scale. Mirroring is folded into
+// a negative X scale so that the rotation part stays a proper rotation.
+pub fn decompose(matrix: &Matrix4) -> Decomposition {
+    let translation = matrix.w.truncate();
+    let x = matrix.x.truncate();
+    let y = matrix.y.truncate();
+    let z = matrix.z.truncate();
+
+    let mut scale = Vector3::new(x.magnitude(), y.magnitude(), z.magnitude());
+    let normalize = |v: Vector3, s: f32| {
+        if s > f32::EPSILON {
+            v / s
+        } else {
+            v
+        }
+    };
+    let mut rotation = Matrix3::from_cols(
+        normalize(x, scale.x),
+        normalize(y, scale.y),
+        normalize(z, scale.z),
+    );
+
+    if rotation.determinant() < 0.0 {
+        scale.x = -scale.x;
+        rotation.x = -rotation.x;
+    }
+
+    Decomposition {
+        translation,
+        rotation,
+        scale,
+    }
+}
+
+// Rounds rotation of the matrix to the closest multiple of 90 degrees on every axis.
+pub fn snap_rotation_90(matrix: &Matrix4) -> Matrix4 {
+    let mut decomposition = decompose(matrix);
+    let rotation = decomposition.rotation;
+
+    let mut snapped = Matrix3::from_value(0.0);
+    let mut used = [false; 3];
+    // Columns that are most aligned with an axis are snapped first.
+    let mut columns = [0, 1, 2];
+    columns.sort_by(|a, b| {
+        let max = |c: usize| {
+            let v = rotation[c];
+            v.x.abs().max(v.y.abs()).max(v.z.abs())
+        };
+        max(*b).total_cmp(&max(*a))
+    });
+
+    for column in columns {
+        let v = rotation[column];
+        let axis = (0..3)
+            .filter(|i| !used[*i])
+            .max_by(|a, b| v[*a].abs().total_cmp(&v[*b].abs()))
+            .unwrap();
+        used[axis] = true;
+        snapped[column][axis] = if v[axis] < 0.0 { -1.0 } else { 1.0 };
+    }
+
+    // Keep it a proper rotation, the last snapped column may have ended up flipped.
+    if snapped.determinant() < 0.0 {
+        let column = columns[2];
+        snapped[column] = -snapped[column];
+    }
+
+    decomposition.rotation = snapped;
+    decomposition.to_matrix()
+}
+
+pub fn is_orthogonal(matrix: &Matrix3) -> bool {
+    let product = matrix.transpose() * matrix;
+    let identity = Matrix3::identity();
+    (0..3).all(|c| (0..3).all(|r| (product[c][r] - identity[c][r]).abs() < 1e-4))
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, SquareMatrix};
+
+    use crate::{Matrix3, Matrix4, Vector3};
+
+    use super::{decompose, snap_rotation_90, snap_translation, STUD_GRID};
+
+    fn assert_matrix_eq(a: &Matrix4, b: &Matrix4) {
+        for c in 0..4 {
+            for r in 0..4 {
+                assert!((a[c][r] - b[c][r]).abs() < 1e-4, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_snap_translation() {
+        let matrix = Matrix4::from_translation(Vector3::new(29.0, -13.0, 11.0));
+        let snapped = snap_translation(&matrix, &STUD_GRID);
+
+        assert_eq!(snapped.w.truncate(), Vector3::new(20.0, -16.0, 20.0));
+    }
+
+    #[test]
+    fn test_snap_rotation_90() {
+        let matrix = Matrix4::from_translation(Vector3::new(10.0, 0.0, 0.0))
+            * Matrix4::from_angle_y(Deg(97.0))
+            * Matrix4::from_angle_x(Deg(-4.0));
+        let expected = Matrix4::from_translation(Vector3::new(10.0, 0.0, 0.0))
+            * Matrix4::from_angle_y(Deg(90.0));
+
+        assert_matrix_eq(&snap_rotation_90(&matrix), &expected);
+    }
+
+    #[test]
+    fn test_decompose() {
+        let matrix = Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0))
+            * Matrix4::from_angle_z(Deg(30.0))
+            * Matrix4::from_nonuniform_scale(-2.0, 1.0, 0.5);
+        let decomposition = decompose(&matrix);
+
+        assert_eq!(decomposition.translation, Vector3::new(1.0, 2.0, 3.0));
+        assert!(decomposition.rotation.determinant() > 0.0);
+        assert!(super::is_orthogonal(&decomposition.rotation));
+        assert_matrix_eq(&decomposition.to_matrix(), &matrix);
+        assert_ne!(decomposition.rotation, Matrix3::identity());
+    }
+}
@@ -7,7 +7,8 @@ use tokio::io::{AsyncWrite, AsyncWriteExt};
 use crate::color::ColorReference;
 use crate::document::{BfcCertification, Document, MultipartDocument};
 use crate::elements::{
-    BfcStatement, Command, Header, Line, Meta, OptionalLine, PartReference, Quad, Triangle,
+    BfcStatement, Command, Header, LdCadCommand, Line, Meta, OptionalLine, PartReference, Quad,
+    Triangle, LDCAD_META,
 };
 use crate::error::SerializeError;
 use crate::Winding;
@@ -81,7 +82,7 @@ impl LDrawWriter for BfcStatement {
             BfcStatement::Winding(Winding::Ccw) => writer.write_all(b"0 BFC CCW\n").await?,
             BfcStatement::Clip(None) => writer.write_all(b"0 BFC CLIP\n").await?,
             BfcStatement::Clip(Some(Winding::Cw)) => writer.write_all(b"0 BFC CLIP CW\n").await?,
-            BfcStatement::Clip(Some(Winding::Ccw)) => writer.write_all(b"0 BFC CLIP CW\n").await?,
+            BfcStatement::Clip(Some(Winding::Ccw)) => writer.write_all(b"0 BFC CLIP CCW\n").await?,
             BfcStatement::NoClip => writer.write_all(b"0 BFC NOCLIP\n").await?,
             BfcStatement::InvertNext => writer.write_all(b"0 BFC INVERTNEXT\n").await?,
         };
@@ -89,15 +90,53 @@ impl LDrawWriter for BfcStatement {
     }
 }
 
+#[async_trait]
+impl LDrawWriter for LdCadCommand {
+    async fn write(
+        &self,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> Result<(), SerializeError> {
+        let mut line = format!("0 !{} {}", LDCAD_META, self.kind);
+        for (key, value) in self.parameters.iter() {
+            line.push_str(&format!(" [{}={}]", key, value));
+        }
+        line.push('\n');
+        writer.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+// Whether a comment written as is would be read back as a meta command.
+fn is_meta_command(text: &str) -> bool {
+    let command = text.split_whitespace().next().unwrap_or_default();
+    command.starts_with('!')
+        || command.starts_with("//")
+        || matches!(
+            command,
+            "BFC"
+                | "Name:"
+                | "Author:"
+                | "FILE"
+                | "STEP"
+                | "WRITE"
+                | "PRINT"
+                | "CLEAR"
+                | "PAUSE"
+                | "SAVE"
+        )
+}
+
 #[async_trait]
 impl LDrawWriter for Document {
     async fn write(
         &self,
         writer: &mut (dyn AsyncWrite + Unpin + Send),
     ) -> Result<(), SerializeError> {
-        writer
-            .write_all(format!("0 {}\n", self.description).as_bytes())
-            .await?;
+        let description = match is_meta_command(&self.description) {
+            true => format!("0 // {}\n", self.description),
+            false => format!("0 {}\n", self.description),
+        };
+        writer.write_all(description.as_bytes()).await?;
         writer
             .write_all(format!("0 Name: {}\n", self.name).as_bytes())
             .await?;
@@ -118,7 +157,7 @@ impl LDrawWriter for Document {
         for command in &self.commands {
             command.write(writer).await?;
         }
-        writer.write_all(b"0\n\n").await?;
+        writer.write_all(b"\n").await?;
 
         Ok(())
     }
@@ -131,9 +170,11 @@ impl LDrawWriter for MultipartDocument {
         writer: &mut (dyn AsyncWrite + Unpin + Send),
     ) -> Result<(), SerializeError> {
         self.body.write(writer).await?;
-        for subpart in self.subparts.values() {
+        // Subparts are referred to by the name in the FILE line, which may differ from the
+        // one in their own header.
+        for (alias, subpart) in self.subparts.iter() {
             writer
-                .write_all(format!("0 FILE {}\n", subpart.name).as_bytes())
+                .write_all(format!("0 FILE {}\n", alias.original).as_bytes())
                 .await?;
             subpart.write(writer).await?;
         }
@@ -185,6 +226,9 @@ impl LDrawWriter for Meta {
             Meta::Bfc(bfc) => {
                 bfc.write(writer).await?;
             }
+            Meta::LdCad(command) => {
+                command.write(writer).await?;
+            }
         };
 
         Ok(())
@@ -201,7 +245,7 @@ impl LDrawWriter for PartReference {
         writer
             .write_all(
                 format!(
-                    "1 {} {} {} {} {} {} {} {} {} {} {} {} {}\n",
+                    "1 {} {} {} {} {} {} {} {} {} {} {} {} {} {}\n",
                     self.color,
                     m.x.w,
                     m.y.w,
@@ -214,7 +258,8 @@ impl LDrawWriter for PartReference {
                     m.y.z,
                     m.z.x,
                     m.z.y,
-                    m.z.z
+                    m.z.z,
+                    self.name.original
                 )
                 .as_bytes(),
             )
@@ -253,7 +298,7 @@ impl LDrawWriter for Triangle {
         writer
             .write_all(
                 format!(
-                    "2 {} {} {} {}\n",
+                    "3 {} {} {} {}\n",
                     self.color,
                     serialize_vec3(&self.a),
                     serialize_vec3(&self.b),
@@ -275,7 +320,7 @@ impl LDrawWriter for Quad {
         writer
             .write_all(
                 format!(
-                    "2 {} {} {} {} {}\n",
+                    "4 {} {} {} {} {}\n",
                     self.color,
                     serialize_vec3(&self.a),
                     serialize_vec3(&self.b),
@@ -298,7 +343,7 @@ impl LDrawWriter for OptionalLine {
         writer
             .write_all(
                 format!(
-                    "2 {} {} {} {} {}\n",
+                    "5 {} {} {} {} {}\n",
                     self.color,
                     serialize_vec3(&self.a),
                     serialize_vec3(&self.b),
@@ -328,3 +373,157 @@ impl LDrawWriter for Command {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use cgmath::{Matrix, Matrix4, Vector4};
+    use futures::executor::block_on;
+    use proptest::{collection::vec, num::f32, prelude::*};
+
+    use crate::{
+        color::{ColorCatalog, ColorReference},
+        document::{BfcCertification, Document, MultipartDocument},
+        elements::{
+            BfcStatement, Command, Header, Line, Meta, OptionalLine, PartReference, Quad, Triangle,
+        },
+        parser::{parse_multipart_document, parse_single_document},
+        testing, PartAlias, Winding,
+    };
+
+    use super::LDrawWriter;
+
+    // Anything written by {} for an f32 reads back as the same value.
+    fn number() -> impl Strategy<Value = f32> {
+        f32::NORMAL | f32::SUBNORMAL | f32::ZERO
+    }
+
+    fn point() -> impl Strategy<Value = Vector4<f32>> {
+        (number(), number(), number()).prop_map(|(x, y, z)| Vector4::new(x, y, z, 1.0))
+    }
+
+    // Colours are not in the catalog, so they stay unknown apart from 16 and 24.
+    fn color() -> impl Strategy<Value = ColorReference> {
+        (0u32..600).prop_map(|v| ColorReference::resolve(v, &ColorCatalog::new()))
+    }
+
+    // Text is trimmed when read, and must not start with a meta command.
+    fn text() -> impl Strategy<Value = String> {
+        "[a-z][a-zA-Z0-9 ,.()#-]{0,24}[a-zA-Z0-9]"
+    }
+
+    fn winding() -> impl Strategy<Value = Winding> {
+        prop_oneof![Just(Winding::Ccw), Just(Winding::Cw)]
+    }
+
+    fn meta() -> impl Strategy<Value = Meta> {
+        prop_oneof![
+            text().prop_map(Meta::Comment),
+            text().prop_map(Meta::Write),
+            text().prop_map(Meta::Print),
+            Just(Meta::Step),
+            Just(Meta::Clear),
+            Just(Meta::Pause),
+            Just(Meta::Save),
+            winding().prop_map(|v| Meta::Bfc(BfcStatement::Winding(v))),
+            prop::option::of(winding()).prop_map(|v| Meta::Bfc(BfcStatement::Clip(v))),
+            Just(Meta::Bfc(BfcStatement::NoClip)),
+            Just(Meta::Bfc(BfcStatement::InvertNext)),
+        ]
+    }
+
+    fn command() -> impl Strategy<Value = Command> {
+        prop_oneof![
+            meta().prop_map(Command::Meta),
+            (color(), vec(number(), 12), "[a-z0-9]{1,8}\\.dat").prop_map(|(color, m, name)| {
+                Command::PartReference(PartReference {
+                    color,
+                    matrix: Matrix4::new(
+                        m[3], m[4], m[5], m[0], m[6], m[7], m[8], m[1], m[9], m[10], m[11], m[2],
+                        0.0, 0.0, 0.0, 1.0,
+                    )
+                    .transpose(),
+                    name: PartAlias::from(name),
+                })
+            }),
+            (color(), point(), point()).prop_map(|(color, a, b)| Command::Line(Line {
+                color,
+                a,
+                b
+            })),
+            (color(), point(), point(), point())
+                .prop_map(|(color, a, b, c)| { Command::Triangle(Triangle { color, a, b, c }) }),
+            (color(), point(), point(), point(), point())
+                .prop_map(|(color, a, b, c, d)| { Command::Quad(Quad { color, a, b, c, d }) }),
+            (color(), point(), point(), point(), point()).prop_map(|(color, a, b, c, d)| {
+                Command::OptionalLine(OptionalLine { color, a, b, c, d })
+            }),
+        ]
+    }
+
+    fn document(name: impl Strategy<Value = String>) -> impl Strategy<Value = Document> {
+        (
+            name,
+            prop_oneof![text(), "(BFC|STEP|FILE|Name:|!KEYWORDS|//) [a-z]{1,8}",],
+            text(),
+            prop_oneof![
+                Just(BfcCertification::NotApplicable),
+                Just(BfcCertification::NoCertify),
+                winding().prop_map(BfcCertification::Certify),
+            ],
+            vec(
+                ("X[A-Z_]{0,10}", text()).prop_map(|(k, v)| Header(k, v)),
+                0..4,
+            ),
+            vec(command(), 0..16),
+        )
+            .prop_map(
+                |(name, description, author, bfc, headers, commands)| Document {
+                    description,
+                    author,
+                    bfc,
+                    headers,
+                    ..testing::document(&name, commands)
+                },
+            )
+    }
+
+    fn write<W: LDrawWriter>(value: &W) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        block_on(value.write(&mut buffer)).unwrap();
+        buffer
+    }
+
+    proptest! {
+        #[test]
+        fn document_round_trip(document in document("[a-z0-9]{1,8}\\.ldr")) {
+            let written = write(&document);
+            let parsed = block_on(parse_single_document(&mut &written[..], &ColorCatalog::new()))
+                .unwrap();
+            prop_assert_eq!(parsed, document);
+        }
+
+        #[test]
+        fn multipart_document_round_trip(
+            body in document("[a-z0-9]{1,8}\\.ldr"),
+            subparts in vec(document(Just(String::new())), 0..4),
+        ) {
+            let subparts = subparts
+                .into_iter()
+                .enumerate()
+                .map(|(index, mut document)| {
+                    document.name = format!("sub{}.ldr", index);
+                    (PartAlias::from(&document.name), document)
+                })
+                .collect::<HashMap<_, _>>();
+            let document = MultipartDocument { body, subparts };
+
+            let written = write(&document);
+            let parsed =
+                block_on(parse_multipart_document(&mut &written[..], &ColorCatalog::new()))
+                    .unwrap();
+            prop_assert_eq!(parsed, document);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use ldraw::{
+    color::ColorCatalog,
+    document::MultipartDocument,
+    error::ResolutionError,
+    library::{resolve_dependencies_multipart, DocumentLoader, LibraryLoader, PartCache},
+    PartAlias,
+};
+use ldraw_ir::{
+    geometry::BoundingBox3,
+    model::Model,
+    part::{bake_part_from_multipart_document_with_options, BakeOptions, PartDimensionQuerier},
+};
+use ldraw_renderer::part::{Part, PartQuerier};
+
+use crate::context::Context;
+
+// Parts uploaded to the device, shared by every model of a batch.
+#[derive(Default)]
+pub struct PartsPool(pub HashMap<PartAlias, Part>);
+
+impl PartQuerier<PartAlias> for PartsPool {
+    fn get(&self, alias: &PartAlias) -> Option<&Part> {
+        self.0.get(alias)
+    }
+}
+
+impl PartDimensionQuerier<PartAlias> for PartsPool {
+    fn query_part_dimension(&self, alias: &PartAlias) -> Option<BoundingBox3> {
+        self.0.get(alias).map(|v| v.bounding_box.clone())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BatchTimings {
+    pub load: Duration,
+    pub resolve: Duration,
+    pub bake: Duration,
+    pub build: Duration,
+}
+
+impl BatchTimings {
+    pub fn total(&self) -> Duration {
+        self.load + self.resolve + self.bake + self.build
+    }
+
+    fn add(&mut self, other: &BatchTimings) {
+        self.load += other.load;
+        self.resolve += other.resolve;
+        self.bake += other.bake;
+        self.build += other.build;
+    }
+}
+
+pub struct BatchModel {
+    pub document: MultipartDocument,
+    pub model: Model<PartAlias>,
+    // Parts that could not be resolved, which are left out of the model.
+    pub missing: Vec<(PartAlias, ResolutionError)>,
+    // Parts baked for this model. Ones baked earlier in the batch are not counted.
+    pub parts_baked: usize,
+    pub timings: BatchTimings,
+}
+
+pub struct BatchResult<T> {
+    pub models: Vec<(T, Result<BatchModel, ResolutionError>)>,
+    pub parts: PartsPool,
+    pub timings: BatchTimings,
+}
+
+// Loads documents against one part cache and pool, so that each part is parsed and baked
+// once for the whole batch, and every model sees the same version of it. Parts are keyed by
+// alias, so models sharing a local part under the same name get the first one loaded.
+pub async fn load_batch<T, L>(
+    context: &Context,
+    loader: &L,
+    colors: &ColorCatalog,
+    locators: Vec<T>,
+    options: &BakeOptions,
+) -> BatchResult<T>
+where
+    L: LibraryLoader + DocumentLoader<T>,
+{
+    let cache = PartCache::new();
+    let mut parts = PartsPool::default();
+    let mut timings = BatchTimings::default();
+    let mut models = Vec::new();
+
+    for locator in locators {
+        let result = load_model(
+            context,
+            loader,
+            colors,
+            &locator,
+            options,
+            cache.clone(),
+            &mut parts,
+        )
+        .await;
+        if let Ok(model) = &result {
+            timings.add(&model.timings);
+        }
+        models.push((locator, result));
+    }
+
+    BatchResult {
+        models,
+        parts,
+        timings,
+    }
+}
+
+async fn load_model<T, L>(
+    context: &Context,
+    loader: &L,
+    colors: &ColorCatalog,
+    locator: &T,
+    options: &BakeOptions,
+    cache: PartCache,
+    parts: &mut PartsPool,
+) -> Result<BatchModel, ResolutionError>
+where
+    L: LibraryLoader + DocumentLoader<T>,
+{
+    let mut timings = BatchTimings::default();
+
+    let started = Instant::now();
+    let document = loader.load_document(locator, colors).await?;
+    timings.load = started.elapsed();
+
+    let started = Instant::now();
+    let missing = RefCell::new(Vec::new());
+    let resolution_result = resolve_dependencies_multipart(
+        &document,
+        cache.clone(),
+        colors,
+        loader,
+        &|alias, result| {
+            if let Err(e) = result {
+                missing.borrow_mut().push((alias, e));
+            }
+        },
+    )
+    .await;
+    timings.resolve = started.elapsed();
+
+    let started = Instant::now();
+    let mut parts_baked = 0;
+    for alias in document.list_dependencies() {
+        if parts.0.contains_key(&alias) {
+            continue;
+        }
+        if let Some((part, local)) = resolution_result.query(&alias, true) {
+            let baked = bake_part_from_multipart_document_with_options(
+                part,
+                &resolution_result,
+                local,
+                options,
+            );
+            parts
+                .0
+                .insert(alias, Part::new(&baked, &context.device, colors));
+            parts_baked += 1;
+        }
+    }
+    timings.bake = started.elapsed();
+
+    let started = Instant::now();
+    let model =
+        Model::from_ldraw_multipart_document(&document, colors, Some((loader, cache))).await;
+    timings.build = started.elapsed();
+
+    Ok(BatchModel {
+        document,
+        model,
+        missing: missing.into_inner(),
+        parts_baked,
+        timings,
+    })
+}
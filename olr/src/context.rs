@@ -1,10 +1,76 @@
+use std::str::FromStr;
+
 use image::RgbaImage;
 use ldraw::Vector2;
 use ldraw_ir::geometry::BoundingBox2;
-use ldraw_renderer::{pipeline::RenderingPipelineManager, projection::Projection, Entity};
+use ldraw_renderer::{
+    pipeline::RenderingPipelineManager,
+    profiler::{GpuTimings, GPU_PROFILING_FEATURES},
+    projection::Projection,
+    Entity,
+};
 
 use crate::error::ContextCreationError;
 
+// Graphics API to render with. Auto picks whatever works best on the system.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    Auto,
+    Vulkan,
+    Metal,
+    Dx12,
+    Gl,
+}
+
+impl Backend {
+    fn backends(self) -> wgpu::Backends {
+        match self {
+            Backend::Auto => wgpu::Backends::all(),
+            Backend::Vulkan => wgpu::Backends::VULKAN,
+            Backend::Metal => wgpu::Backends::METAL,
+            Backend::Dx12 => wgpu::Backends::DX12,
+            Backend::Gl => wgpu::Backends::GL,
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = ContextCreationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(Backend::Auto),
+            "vulkan" | "vk" => Ok(Backend::Vulkan),
+            "metal" => Ok(Backend::Metal),
+            "dx12" | "d3d12" => Ok(Backend::Dx12),
+            "gl" | "gles" | "opengl" => Ok(Backend::Gl),
+            _ => Err(ContextCreationError::UnknownBackend(s.to_string())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ContextOptions {
+    pub backend: Backend,
+    // Only considers software rasterizers such as llvmpipe or WARP, so that rendering works
+    // on machines without a GPU and gives the same results everywhere.
+    pub software: bool,
+    pub power_preference: wgpu::PowerPreference,
+    // Limits requested from the device. If not given, default limits are used where the
+    // adapter supports them, and downlevel ones otherwise.
+    pub limits: Option<wgpu::Limits>,
+}
+
+impl ContextOptions {
+    pub fn software() -> Self {
+        Self {
+            software: true,
+            ..Default::default()
+        }
+    }
+}
+
 pub struct Context {
     pub width: u32,
     pub height: u32,
@@ -12,12 +78,18 @@ pub struct Context {
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
 
+    adapter_info: wgpu::AdapterInfo,
+
     pub(super) pipelines: RenderingPipelineManager,
     pub(super) projection: Entity<Projection>,
 
+    pub(super) framebuffer_format: wgpu::TextureFormat,
     pub(super) framebuffer_texture: wgpu::Texture,
     pub(super) framebuffer_texture_view: wgpu::TextureView,
 
+    pub(super) postprocess_texture: wgpu::Texture,
+    pub(super) postprocess_texture_view: wgpu::TextureView,
+
     pub(super) _multisampled_framebuffer_texture: Option<wgpu::Texture>,
     pub(super) multisampled_framebuffer_texture_view: Option<wgpu::TextureView>,
 
@@ -33,27 +105,60 @@ impl Context {
         height: u32,
         sample_count: u32,
     ) -> Result<Self, ContextCreationError> {
+        Self::with_options(width, height, sample_count, &ContextOptions::default()).await
+    }
+
+    pub async fn with_options(
+        width: u32,
+        height: u32,
+        sample_count: u32,
+        options: &ContextOptions,
+    ) -> Result<Self, ContextCreationError> {
+        let backends = options.backend.backends();
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
+            backends,
             dx12_shader_compiler: wgpu::Dx12Compiler::default(),
             flags: wgpu::InstanceFlags::default(),
             gles_minor_version: wgpu::Gles3MinorVersion::Automatic,
         });
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptionsBase {
-                power_preference: wgpu::PowerPreference::default(),
-                force_fallback_adapter: false,
-                compatible_surface: None,
-            })
-            .await
-            .ok_or(ContextCreationError::NoAdapterFound)?;
+        let adapter = if options.software {
+            instance
+                .enumerate_adapters(backends)
+                .into_iter()
+                .find(|v| v.get_info().device_type == wgpu::DeviceType::Cpu)
+        } else {
+            instance
+                .request_adapter(&wgpu::RequestAdapterOptionsBase {
+                    power_preference: options.power_preference,
+                    force_fallback_adapter: false,
+                    compatible_surface: None,
+                })
+                .await
+        }
+        .ok_or(ContextCreationError::NoAdapterFound)?;
+
+        let adapter_limits = adapter.limits();
+        let required_limits = match &options.limits {
+            Some(limits) => limits.clone(),
+            None if wgpu::Limits::default().check_limits(&adapter_limits) => {
+                wgpu::Limits::default()
+            }
+            None => wgpu::Limits::downlevel_defaults().using_resolution(adapter_limits.clone()),
+        };
+        let max_size = required_limits
+            .max_texture_dimension_2d
+            .min(adapter_limits.max_texture_dimension_2d);
+        if width > max_size || height > max_size {
+            return Err(ContextCreationError::SizeTooLarge(max_size));
+        }
 
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: Some("Device Descriptor"),
-                    required_features: wgpu::Features::POLYGON_MODE_LINE,
-                    required_limits: wgpu::Limits::default(),
+                    required_features: wgpu::Features::POLYGON_MODE_LINE
+                        | (adapter.features() & GPU_PROFILING_FEATURES),
+                    required_limits,
                     memory_hints: Default::default(),
                 },
                 None,
@@ -77,6 +182,22 @@ impl Context {
         });
         let framebuffer_texture_view = framebuffer_texture.create_view(&Default::default());
 
+        let postprocess_texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: framebuffer_format,
+            usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
+            label: Some("Post-process source"),
+            view_formats: &[],
+        });
+        let postprocess_texture_view = postprocess_texture.create_view(&Default::default());
+
         let (multisampled_framebuffer_texture, multisampled_framebuffer_texture_view) =
             if sample_count > 1 {
                 let texture = device.create_texture(&wgpu::TextureDescriptor {
@@ -135,12 +256,18 @@ impl Context {
             device,
             queue,
 
+            adapter_info: adapter.get_info(),
+
             pipelines,
             projection,
 
+            framebuffer_format,
             framebuffer_texture,
             framebuffer_texture_view,
 
+            postprocess_texture,
+            postprocess_texture_view,
+
             _multisampled_framebuffer_texture: multisampled_framebuffer_texture,
             multisampled_framebuffer_texture_view,
 
@@ -151,6 +278,37 @@ impl Context {
         })
     }
 
+    // Describes the adapter picked, including its backend and driver.
+    pub fn adapter_info(&self) -> &wgpu::AdapterInfo {
+        &self.adapter_info
+    }
+
+    pub fn framebuffer_format(&self) -> wgpu::TextureFormat {
+        self.framebuffer_format
+    }
+
+    pub fn pipelines(&self) -> &RenderingPipelineManager {
+        &self.pipelines
+    }
+
+    pub fn pipelines_mut(&mut self) -> &mut RenderingPipelineManager {
+        &mut self.pipelines
+    }
+
+    pub fn enable_gpu_profiling(&mut self) -> bool {
+        self.pipelines
+            .enable_gpu_profiling(&self.device, &self.queue)
+    }
+
+    // Waits for timings of the last rendered frame.
+    pub fn collect_gpu_timings(&mut self) -> Option<GpuTimings> {
+        self.pipelines.collect_gpu_timings(&self.device, true)
+    }
+
+    pub fn projection(&self) -> &Projection {
+        &self.projection
+    }
+
     pub async fn finish(
         &self,
         mut encoder: wgpu::CommandEncoder,
@@ -6,6 +6,8 @@ use std::{
 #[derive(Debug)]
 pub enum ContextCreationError {
     NoAdapterFound,
+    UnknownBackend(String),
+    SizeTooLarge(u32),
     RequestDeviceError(wgpu::RequestDeviceError),
 }
 
@@ -15,6 +17,12 @@ impl Display for ContextCreationError {
             ContextCreationError::NoAdapterFound => {
                 write!(f, "No adapter found.")
             }
+            ContextCreationError::UnknownBackend(name) => {
+                write!(f, "Unknown backend: {}", name)
+            }
+            ContextCreationError::SizeTooLarge(max) => {
+                write!(f, "Render size exceeds the limit of {} pixels.", max)
+            }
             ContextCreationError::RequestDeviceError(e) => {
                 write!(f, "Error requesting device: {}", e)
             }
@@ -23,9 +31,11 @@ impl Display for ContextCreationError {
 }
 
 impl Error for ContextCreationError {
-    fn cause(&self) -> Option<&(dyn Error)> {
+    fn cause(&self) -> Option<&dyn Error> {
         match *self {
-            ContextCreationError::NoAdapterFound => None,
+            ContextCreationError::NoAdapterFound
+            | ContextCreationError::UnknownBackend(_)
+            | ContextCreationError::SizeTooLarge(_) => None,
             ContextCreationError::RequestDeviceError(ref e) => Some(e),
         }
     }
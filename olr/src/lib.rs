@@ -1,3 +1,5 @@
+pub mod batch;
 pub mod context;
 pub mod error;
 pub mod ops;
+pub mod target;
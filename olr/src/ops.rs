@@ -1,25 +1,202 @@
-use cgmath::SquareMatrix;
+use std::{
+    fmt::{Debug, Display},
+    hash::Hash,
+};
+
+use cgmath::{Angle, Deg, SquareMatrix};
 use image::RgbaImage;
 use ldraw::{
-    color::{Color, ColorCatalog},
-    Matrix4, PartAlias, Point3,
+    color::{Color, ColorCatalog, Rgba},
+    Matrix4, PartAlias, Point3, Vector3,
 };
 use ldraw_ir::{
-    geometry::BoundingBox2,
+    geometry::{BoundingBox2, BoundingBox3},
     model::{GroupId, Model},
 };
 use ldraw_renderer::{
     display_list::DisplayList,
     part::{Part, PartQuerier},
-    projection::{OrthographicCamera, ProjectionModifier, ViewBounds},
+    pipeline::RenderStats,
+    projection::{
+        OrthographicCamera, PerspectiveCamera, ProjectionModifier, ProjectionMutator, ViewBounds,
+    },
     util::calculate_model_bounding_box,
+    AspectRatio, Entity,
 };
 
 use crate::context::Context;
 
+#[derive(Clone, Copy, Debug)]
+pub enum CameraProjection {
+    Orthographic,
+    Perspective { fov: Deg<f32> },
+}
+
+#[derive(Clone, Debug)]
+pub struct CameraOptions {
+    pub latitude: Deg<f32>,
+    pub longitude: Deg<f32>,
+    pub distance: f32,
+    pub projection: CameraProjection,
+    // Frame bounding sphere instead of bounding box so that scale stays the same
+    // regardless of camera angle.
+    pub fit_to_sphere: bool,
+}
+
+impl Default for CameraOptions {
+    fn default() -> Self {
+        Self {
+            latitude: Deg(30.0),
+            longitude: Deg(45.0),
+            distance: 1.0,
+            projection: CameraProjection::Orthographic,
+            fit_to_sphere: false,
+        }
+    }
+}
+
+impl CameraOptions {
+    fn direction(&self) -> Vector3 {
+        // Y axis points downward in LDraw coordinates.
+        let latitude = Deg(self.latitude.0.clamp(-89.9, 89.9));
+        Vector3::new(
+            latitude.cos() * self.longitude.sin(),
+            -latitude.sin(),
+            -latitude.cos() * self.longitude.cos(),
+        )
+    }
+
+    fn update_projections(
+        &self,
+        bounding_box: BoundingBox3,
+        aspect_ratio: AspectRatio,
+    ) -> (Vec<ProjectionMutator>, Option<ViewBounds>) {
+        let center = bounding_box.center();
+        let look_at = Point3::new(center.x, center.y, center.z);
+
+        match self.projection {
+            CameraProjection::Orthographic => {
+                let scale = Matrix4::from_translation(center)
+                    * Matrix4::from_scale(self.distance)
+                    * Matrix4::from_translation(-center);
+                let view_bounds = if self.fit_to_sphere {
+                    ViewBounds::Radius(bounding_box.len() * 0.5 * self.distance)
+                } else {
+                    ViewBounds::BoundingBox3(bounding_box.transform(&scale))
+                };
+                let camera = OrthographicCamera::new(
+                    look_at + self.direction() * 1000.0,
+                    look_at,
+                    view_bounds,
+                );
+
+                (
+                    camera.update_projections(aspect_ratio),
+                    Some(camera.view_bounds),
+                )
+            }
+            CameraProjection::Perspective { fov } => {
+                let aspect_ratio_value: f32 = aspect_ratio.into();
+                let half_fov = Deg((fov.0 * 0.5)
+                    .min(Deg::atan((fov / 2.0).tan() * aspect_ratio_value).0)
+                    .max(0.5));
+                let distance = bounding_box.len() * 0.5 / half_fov.sin() * self.distance;
+                let camera =
+                    PerspectiveCamera::new(look_at + self.direction() * distance, look_at, fov);
+
+                (camera.update_projections(aspect_ratio), None)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RenderOptions {
+    pub background: Rgba,
+    pub transparent: bool,
+    pub crop_to_content: bool,
+    pub camera: CameraOptions,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            background: Rgba::new(0xff, 0xff, 0xff, 0xff),
+            transparent: false,
+            crop_to_content: false,
+            camera: CameraOptions::default(),
+        }
+    }
+}
+
+impl RenderOptions {
+    fn clear_color(&self) -> wgpu::Color {
+        // Framebuffer is sRGB, so clear color has to be specified in linear space.
+        let linear = self.background.to_linear();
+        wgpu::Color {
+            r: f64::from(linear.x),
+            g: f64::from(linear.y),
+            b: f64::from(linear.z),
+            a: if self.transparent {
+                0.0
+            } else {
+                f64::from(linear.w)
+            },
+        }
+    }
+
+    pub fn content_bounds(&self, image: &RgbaImage) -> Option<(u32, u32, u32, u32)> {
+        let (mut x1, mut y1, mut x2, mut y2) = (u32::MAX, u32::MAX, 0, 0);
+        for (x, y, pixel) in image.enumerate_pixels() {
+            if !self.is_background(pixel) {
+                x1 = x1.min(x);
+                y1 = y1.min(y);
+                x2 = x2.max(x + 1);
+                y2 = y2.max(y + 1);
+            }
+        }
+
+        if x1 < x2 && y1 < y2 {
+            Some((x1, y1, x2 - x1, y2 - y1))
+        } else {
+            None
+        }
+    }
+
+    fn is_background(&self, pixel: &image::Rgba<u8>) -> bool {
+        if self.transparent {
+            pixel[3] == 0
+        } else {
+            pixel[0].abs_diff(self.background.red()) <= 1
+                && pixel[1].abs_diff(self.background.green()) <= 1
+                && pixel[2].abs_diff(self.background.blue()) <= 1
+        }
+    }
+}
+
+fn crop_to_content(image: RgbaImage, options: &RenderOptions) -> RgbaImage {
+    match options.content_bounds(&image) {
+        Some((x, y, width, height)) => {
+            image::imageops::crop_imm(&image, x, y, width, height).to_image()
+        }
+        None => image,
+    }
+}
+
+pub trait PostProcessPass {
+    fn process(
+        &self,
+        context: &Context,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+    );
+}
+
 pub struct Ops<'a> {
     context: &'a mut Context,
     encoder: wgpu::CommandEncoder,
+    post_process_passes: Vec<Box<dyn PostProcessPass + 'a>>,
 }
 
 impl<'a> Ops<'a> {
@@ -30,14 +207,26 @@ impl<'a> Ops<'a> {
                 label: Some("Command Encoder for Offscreen"),
             });
 
-        Self { context, encoder }
+        Self {
+            context,
+            encoder,
+            post_process_passes: Vec::new(),
+        }
     }
 
-    pub async fn render_single_part(mut self, part: &Part, color: &Color) -> RgbaImage {
-        let camera = OrthographicCamera::new_isometric(
-            Point3::new(0.0, 0.0, 0.0),
-            ViewBounds::BoundingBox3(part.bounding_box.clone()),
-        );
+    pub fn context(&self) -> &Context {
+        self.context
+    }
+
+    pub fn context_mut(&mut self) -> &mut Context {
+        self.context
+    }
+
+    pub fn encoder(&mut self) -> &mut wgpu::CommandEncoder {
+        &mut self.encoder
+    }
+
+    pub fn set_camera(&mut self, camera: &impl ProjectionModifier) {
         self.context.projection.mutate_all(
             camera
                 .update_projections((self.context.width, self.context.height).into())
@@ -46,7 +235,12 @@ impl<'a> Ops<'a> {
         self.context
             .projection
             .update(&self.context.device, &self.context.queue);
+    }
 
+    pub fn begin_render_pass(
+        &mut self,
+        clear_color: Option<wgpu::Color>,
+    ) -> wgpu::RenderPass<'static> {
         let (view, resolve_target) =
             if let Some(t) = self.context.multisampled_framebuffer_texture_view.as_ref() {
                 (t, Some(&self.context.framebuffer_texture_view))
@@ -54,27 +248,26 @@ impl<'a> Ops<'a> {
                 (&self.context.framebuffer_texture_view, None)
             };
 
-        let mut render_pass = self
-            .encoder
+        let (load, depth_load) = match clear_color {
+            Some(color) => (wgpu::LoadOp::Clear(color), wgpu::LoadOp::Clear(1.0)),
+            None => (wgpu::LoadOp::Load, wgpu::LoadOp::Load),
+        };
+
+        self.encoder
             .begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Offscreen Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
                     view,
                     resolve_target,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 1.0,
-                            g: 1.0,
-                            b: 1.0,
-                            a: 0.0,
-                        }),
+                        load,
                         store: wgpu::StoreOp::Store,
                     },
                 })],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                     view: &self.context.depth_texture_view,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        load: depth_load,
                         store: wgpu::StoreOp::Store,
                     }),
                     stencil_ops: None,
@@ -82,7 +275,57 @@ impl<'a> Ops<'a> {
                 occlusion_query_set: None,
                 timestamp_writes: None,
             })
-            .forget_lifetime();
+            .forget_lifetime()
+    }
+
+    pub fn render_display_list<
+        K: Clone + Debug + Eq + PartialEq + Hash + Display,
+        G: Clone + Eq + PartialEq + Hash + Display,
+    >(
+        &mut self,
+        display_list: &mut Entity<DisplayList<K, G>>,
+        parts: &impl PartQuerier<G>,
+        clear_color: Option<wgpu::Color>,
+    ) -> RenderStats {
+        display_list.update(&self.context.device, &self.context.queue);
+
+        let mut render_pass = self.begin_render_pass(clear_color);
+        let stats = self.context.pipelines.render(
+            &mut render_pass,
+            &self.context.projection,
+            parts,
+            display_list,
+        );
+        drop(render_pass);
+        self.context
+            .pipelines
+            .resolve_gpu_timings(&mut self.encoder);
+
+        stats
+    }
+
+    pub fn render_overlay(&mut self, f: impl FnOnce(&Context, &mut wgpu::RenderPass<'static>)) {
+        let mut render_pass = self.begin_render_pass(None);
+        f(self.context, &mut render_pass);
+    }
+
+    pub fn add_post_process_pass(&mut self, pass: impl PostProcessPass + 'a) {
+        self.post_process_passes.push(Box::new(pass));
+    }
+
+    pub async fn render_single_part(mut self, part: &Part, color: &Color) -> RgbaImage {
+        let camera = OrthographicCamera::new_isometric(
+            Point3::new(0.0, 0.0, 0.0),
+            ViewBounds::BoundingBox3(part.bounding_box.clone()),
+        );
+        self.set_camera(&camera);
+
+        let mut render_pass = self.begin_render_pass(Some(wgpu::Color {
+            r: 1.0,
+            g: 1.0,
+            b: 1.0,
+            a: 0.0,
+        }));
 
         self.context.pipelines.render_single_part(
             &self.context.device,
@@ -104,86 +347,75 @@ impl<'a> Ops<'a> {
     }
 
     pub async fn render_model(
-        mut self,
+        self,
         model: &Model<PartAlias>,
         group_id: Option<GroupId>,
         parts: &impl PartQuerier<PartAlias>,
         colors: &ColorCatalog,
+        options: &RenderOptions,
     ) -> RgbaImage {
         let bounding_box = calculate_model_bounding_box(model, group_id, parts);
-        let center = bounding_box.center();
+        let mut display_list = DisplayList::from_model(model, group_id, colors);
 
-        let camera = OrthographicCamera::new_isometric(
-            Point3::new(center.x, center.y, center.z),
-            ViewBounds::BoundingBox3(bounding_box),
-        );
+        self.render_scene(&mut display_list, parts, bounding_box, options)
+            .await
+    }
 
-        self.context.projection.mutate_all(
-            camera
-                .update_projections((self.context.width, self.context.height).into())
-                .into_iter(),
+    pub async fn render_scene<
+        K: Clone + Debug + Eq + PartialEq + Hash + Display,
+        G: Clone + Eq + PartialEq + Hash + Display,
+    >(
+        mut self,
+        display_list: &mut Entity<DisplayList<K, G>>,
+        parts: &impl PartQuerier<G>,
+        bounding_box: BoundingBox3,
+        options: &RenderOptions,
+    ) -> RgbaImage {
+        let (mutators, view_bounds) = options.camera.update_projections(
+            bounding_box,
+            (self.context.width, self.context.height).into(),
         );
+
+        self.context.projection.mutate_all(mutators.into_iter());
         self.context
             .projection
             .update(&self.context.device, &self.context.queue);
 
-        let mut display_list = DisplayList::from_model(model, group_id, colors);
-        display_list.update(&self.context.device, &self.context.queue);
+        self.render_display_list(display_list, parts, Some(options.clear_color()));
 
-        let (view, resolve_target) =
-            if let Some(t) = self.context.multisampled_framebuffer_texture_view.as_ref() {
-                (t, Some(&self.context.framebuffer_texture_view))
-            } else {
-                (&self.context.framebuffer_texture_view, None)
-            };
+        let bounds =
+            view_bounds.and_then(|v| v.fraction(&self.context.projection.get_model_view_matrix()));
 
-        let mut render_pass = self
-            .encoder
-            .begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Offscreen Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view,
-                    resolve_target,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 1.0,
-                            g: 1.0,
-                            b: 1.0,
-                            a: 0.0,
-                        }),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.context.depth_texture_view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: wgpu::StoreOp::Store,
-                    }),
-                    stencil_ops: None,
-                }),
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            })
-            .forget_lifetime();
-
-        self.context.pipelines.render(
-            &mut render_pass,
-            &self.context.projection,
-            parts,
-            &display_list,
-        );
+        let image = self.finish(bounds).await;
 
-        drop(render_pass);
+        if options.crop_to_content {
+            crop_to_content(image, options)
+        } else {
+            image
+        }
+    }
 
-        let bounds = camera
-            .view_bounds
-            .fraction(&self.context.projection.get_model_view_matrix());
+    pub async fn finish(mut self, bounds: Option<BoundingBox2>) -> RgbaImage {
+        let extent = wgpu::Extent3d {
+            width: self.context.width,
+            height: self.context.height,
+            depth_or_array_layers: 1,
+        };
 
-        self.finish(bounds).await
-    }
+        for pass in self.post_process_passes.iter() {
+            self.encoder.copy_texture_to_texture(
+                self.context.framebuffer_texture.as_image_copy(),
+                self.context.postprocess_texture.as_image_copy(),
+                extent,
+            );
+            pass.process(
+                self.context,
+                &mut self.encoder,
+                &self.context.postprocess_texture_view,
+                &self.context.framebuffer_texture_view,
+            );
+        }
 
-    async fn finish(self, bounds: Option<BoundingBox2>) -> RgbaImage {
         self.context.finish(self.encoder, bounds).await
     }
 }
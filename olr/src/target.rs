@@ -0,0 +1,229 @@
+use std::{
+    fmt::{Debug, Display},
+    hash::Hash,
+};
+
+use ldraw_renderer::{
+    display_list::DisplayList,
+    part::PartQuerier,
+    pipeline::{RenderStats, RenderingPipelineManager},
+    projection::{Projection, ProjectionModifier},
+    Entity,
+};
+
+const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+// Renders into texture views owned by the caller, for applications that bring their own
+// device and composite LDraw views themselves, such as game engines or GUI frameworks.
+// Pipelines are built for a single color format, while the size can be changed any time.
+//
+// Parts and display lists drawn with it must be uploaded to the same device.
+pub struct TextureRenderer {
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+
+    pipelines: RenderingPipelineManager,
+    projection: Entity<Projection>,
+
+    depth_texture_view: wgpu::TextureView,
+    multisampled_texture_view: Option<wgpu::TextureView>,
+}
+
+fn create_attachment(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+    label: &str,
+) -> wgpu::TextureView {
+    device
+        .create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            label: Some(label),
+            view_formats: &[],
+        })
+        .create_view(&Default::default())
+}
+
+impl TextureRenderer {
+    // Target views given to render() must be of the given format and size. With more than
+    // one sample, rendering is resolved into them.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> Self {
+        let (width, height) = (width.max(1), height.max(1));
+        let pipelines = RenderingPipelineManager::new(device, queue, format, sample_count);
+        let projection = Projection::new(device).into();
+
+        Self {
+            width,
+            height,
+            format,
+            sample_count,
+
+            pipelines,
+            projection,
+
+            depth_texture_view: create_attachment(
+                device,
+                width,
+                height,
+                DEPTH_FORMAT,
+                sample_count,
+                "Depth buffer for texture target",
+            ),
+            multisampled_texture_view: (sample_count > 1).then(|| {
+                create_attachment(
+                    device,
+                    width,
+                    height,
+                    format,
+                    sample_count,
+                    "Multisampled framebuffer for texture target",
+                )
+            }),
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    // Recreates buffers for targets of another size. The camera has to be set again.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        let (width, height) = (width.max(1), height.max(1));
+        if (width, height) == (self.width, self.height) {
+            return;
+        }
+
+        self.width = width;
+        self.height = height;
+        self.depth_texture_view = create_attachment(
+            device,
+            width,
+            height,
+            DEPTH_FORMAT,
+            self.sample_count,
+            "Depth buffer for texture target",
+        );
+        if self.sample_count > 1 {
+            self.multisampled_texture_view = Some(create_attachment(
+                device,
+                width,
+                height,
+                self.format,
+                self.sample_count,
+                "Multisampled framebuffer for texture target",
+            ));
+        }
+    }
+
+    pub fn pipelines(&self) -> &RenderingPipelineManager {
+        &self.pipelines
+    }
+
+    pub fn pipelines_mut(&mut self) -> &mut RenderingPipelineManager {
+        &mut self.pipelines
+    }
+
+    pub fn projection(&self) -> &Projection {
+        &self.projection
+    }
+
+    pub fn set_camera(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera: &impl ProjectionModifier,
+    ) {
+        self.projection.mutate_all(
+            camera
+                .update_projections((self.width, self.height).into())
+                .into_iter(),
+        );
+        self.projection.update(device, queue);
+    }
+
+    // Records drawing of the display list into target with the given encoder, which the
+    // caller submits. Without a clear color, whatever is in target is drawn over, although
+    // depth is always cleared.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render<
+        K: Clone + Debug + Eq + PartialEq + Hash + Display,
+        G: Clone + Eq + PartialEq + Hash + Display,
+    >(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        display_list: &mut Entity<DisplayList<K, G>>,
+        parts: &impl PartQuerier<G>,
+        clear_color: Option<wgpu::Color>,
+    ) -> RenderStats {
+        display_list.update(device, queue);
+
+        let (view, resolve_target) = match self.multisampled_texture_view.as_ref() {
+            Some(v) => (v, Some(target)),
+            None => (target, None),
+        };
+        // Multisampled contents are not kept between passes, so they can only start cleared.
+        let load = match clear_color {
+            Some(color) => wgpu::LoadOp::Clear(color),
+            None if resolve_target.is_some() => wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+            None => wgpu::LoadOp::Load,
+        };
+
+        let mut render_pass = encoder
+            .begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Texture Target Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            })
+            .forget_lifetime();
+        let stats = self
+            .pipelines
+            .render(&mut render_pass, &self.projection, parts, display_list);
+        drop(render_pass);
+        self.pipelines.resolve_gpu_timings(encoder);
+
+        stats
+    }
+}
@@ -0,0 +1,138 @@
+const cubeUV_maxMipLevel: f32 = 8.0;
+const cubeUV_minMipLevel: f32 = 4.0;
+const cubeUV_maxTileSize: f32 = 256.0;
+const cubeUV_minTileSize: f32 = 16.0;
+
+fn getFace(direction: vec3<f32>) -> f32 {
+    let absDirection = abs(direction);
+    var face = -1.0;
+    if (absDirection.x > absDirection.z) {
+        if (absDirection.x > absDirection.y) {
+            face = select(3.0, 0.0, direction.x > 0.0);
+        } else {
+            face = select(4.0, 1.0, direction.y > 0.0);
+        }
+    } else {
+        if (absDirection.z > absDirection.y) {
+            face = select(5.0, 2.0, direction.z > 0.0);
+        } else {
+            face = select(4.0, 1.0, direction.y > 0.0);
+        }
+    }
+    return face;
+}
+
+fn getUV(direction: vec3<f32>, face: f32) -> vec2<f32> {
+    var uv: vec2<f32>;
+    if (face == 0.0) {
+        uv = vec2<f32>(direction.z, direction.y) / abs(direction.x);
+    } else if (face == 1.0) {
+        uv = vec2<f32>(-direction.x, -direction.z) / abs(direction.y);
+    } else if (face == 2.0) {
+        uv = vec2<f32>(-direction.x, direction.y) / abs(direction.z);
+    } else if (face == 3.0) {
+        uv = vec2<f32>(-direction.z, direction.y) / abs(direction.x);
+    } else if (face == 4.0) {
+        uv = vec2<f32>(-direction.x, direction.z) / abs(direction.y);
+    } else {
+        uv = vec2(direction.x, direction.y) / abs(direction.z);
+    }
+    return 0.5 * (uv + 1.0);
+}
+
+fn envMapTexelToLinear(value: vec4<f32>) -> vec4<f32> {
+    return vec4<f32>(value.rgb * exp2(value.a * 255.0 - 128.0), 1.0);
+}
+
+fn fauxColor(face: f32) -> vec3<f32> {
+    if (face == 0.0) {
+        return vec3<f32>(1.0, 0.0, 0.0);
+    } else if (face == 1.0) {
+        return vec3<f32>(0.0, 1.0, 0.0);
+    } else if (face == 2.0) {
+        return vec3<f32>(0.0, 0.0, 1.0);
+    } else if (face == 3.0) {
+        return vec3<f32>(1.0, 1.0, 0.0);
+    } else if (face == 4.0) {
+        return vec3<f32>(0.0, 1.0, 1.0);
+    } else {
+        return vec3<f32>(1.0, 0.0, 1.0);
+    }
+}
+
+fn bilinearCubeUV(envMapTexture: texture_2d<f32>, envMapSampler: sampler, direction: vec3<f32>, mipInt_: f32) -> vec3<f32> {
+    var face = getFace(direction);
+    let filterInt = max(cubeUV_minMipLevel - mipInt_, 0.0);
+    let mipInt = max(mipInt_, cubeUV_minMipLevel);
+    let faceSize = exp2(mipInt);
+    let texelSize = 1.0 / (3.0 * cubeUV_maxTileSize);
+    var uv = getUV(direction, face) * (faceSize - 1.0);
+    let f = fract(uv);
+    uv += 0.5 - f;
+    if (face > 2.0) {
+        uv.y += faceSize;
+        face -= 3.0;
+    }
+    uv.x += face * faceSize;
+    if (mipInt < cubeUV_maxMipLevel) {
+        uv.y += 2.0 * cubeUV_maxTileSize;
+    }
+    uv.y += filterInt * 2.0 * cubeUV_minTileSize;
+    uv.x += 3.0 * max(0.0, cubeUV_maxTileSize - 2.0 * faceSize);
+    uv *= texelSize;
+    let tl = envMapTexelToLinear(textureSample(envMapTexture, envMapSampler, uv)).rgb;
+    uv.x += texelSize;
+    let tr = envMapTexelToLinear(textureSample(envMapTexture, envMapSampler, uv)).rgb;
+    uv.y += texelSize;
+    let br = envMapTexelToLinear(textureSample(envMapTexture, envMapSampler, uv)).rgb;
+    uv.x -= texelSize;
+    let bl = envMapTexelToLinear(textureSample(envMapTexture, envMapSampler, uv)).rgb;
+    let tm = mix(tl, tr, f.x);
+    let bm = mix(bl, br, f.x);
+
+    return mix(tm, bm, f.y);
+    //return fauxColor(getFace(direction));
+}
+
+const r0: f32 = 1.0;
+const v0: f32 = 0.339;
+const m0: f32 = -2.0;
+const r1: f32 = 0.8;
+const v1: f32 = 0.276;
+const m1: f32 = -1.0;
+const r4: f32 = 0.4;
+const v4: f32 = 0.046;
+const m4: f32 = 2.0;
+const r5: f32 = 0.305;
+const v5: f32 = 0.016;
+const m5: f32 = 3.0;
+const r6: f32 = 0.21;
+const v6: f32 = 0.0038;
+const m6: f32 = 4.0;
+
+fn roughnessToMip(roughness: f32) -> f32 {
+    if (roughness >= r1) {
+        return (r0 - roughness) * (m1 - m0) / (r0 - r1) + m0;
+    } else if (roughness >= r4) {
+        return (r1 - roughness) * (m4 - m1) / (r1 - r4) + m1;
+    } else if (roughness >= r5) {
+        return (r4 - roughness) * (m5 - m4) / (r4 - r5) + m4;
+    } else if (roughness >= r6) {
+        return (r5 - roughness) * (m6 - m5) / (r5 - r6) + m5;
+    } else {
+        return -2.0 * log2(1.16 * roughness);
+    }
+}
+
+fn textureCubeUV(envMapTexture: texture_2d<f32>, envMapSampler: sampler, sampleDir: vec3<f32>, roughness: f32) -> vec4<f32> {
+    let mip = clamp(roughnessToMip(roughness), m0, cubeUV_maxMipLevel);
+    let mipF = fract(mip);
+    let mipInt = floor(mip);
+    let color0 = bilinearCubeUV(envMapTexture, envMapSampler, sampleDir, mipInt);
+    let color1 = bilinearCubeUV(envMapTexture, envMapSampler, sampleDir, mipInt + 1.0);
+    if (mipF == 0.0) {
+        return vec4<f32>(color0, 1.0);
+    } else {
+        return vec4<f32>(mix(color0, color1, mipF), 1.0);
+    }
+}
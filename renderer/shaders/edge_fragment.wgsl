@@ -1,8 +1,13 @@
 struct VertexOutput {
     @location(0) color: vec4<f32>,
+    @location(1) clipDistances: vec4<f32>,
 }
 
 @fragment
 fn fs(in: VertexOutput) -> @location(0) vec4<f32> {
+    if (in.color.a <= 0.0 || any(in.clipDistances < vec4<f32>(0.0))) {
+        discard;
+    }
+
     return in.color;
 }
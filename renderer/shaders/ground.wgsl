@@ -0,0 +1,83 @@
+struct ProjectionData {
+    modelMatrix: mat4x4<f32>,
+    projectionMatrix: mat4x4<f32>,
+    viewMatrix: mat4x4<f32>,
+    normalMatrix: mat3x3<f32>,
+    isOrthographic: i32,
+    clipPlanes: array<vec4<f32>, 4>,
+    clipPlaneCount: i32,
+}
+
+struct GroundUniforms {
+    center: vec3<f32>,
+    size: f32,
+    footprint: vec2<f32>,
+    gridSpacing: f32,
+    shadowBlur: f32,
+    gridColor: vec4<f32>,
+    shadowOpacity: f32,
+    showGrid: i32,
+}
+
+@group(0) @binding(0)
+var<uniform> projection: ProjectionData;
+
+@group(1) @binding(0)
+var<uniform> ground: GroundUniforms;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) local: vec2<f32>,
+}
+
+@vertex
+fn vs(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+
+    // Two triangles covering [-1, 1] on the XZ plane.
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let local = corners[index] * ground.size;
+    let position = vec4<f32>(ground.center + vec3<f32>(local.x, 0.0, local.y), 1.0);
+
+    out.local = local;
+    out.position = projection.projectionMatrix * projection.viewMatrix * projection.modelMatrix * position;
+
+    return out;
+}
+
+fn gridIntensity(local: vec2<f32>) -> f32 {
+    let coord = local / ground.gridSpacing;
+    let width = fwidth(coord);
+    let distance = abs(fract(coord - 0.5) - 0.5) / max(width, vec2<f32>(1e-4));
+    let line = 1.0 - min(min(distance.x, distance.y), 1.0);
+    // Lines closer than a few pixels turn into noise, so they fade out.
+    let density = 1.0 - smoothstep(0.2, 0.5, max(width.x, width.y));
+    let falloff = 1.0 - smoothstep(ground.size * 0.5, ground.size, length(local));
+    return line * density * falloff;
+}
+
+@fragment
+fn fs(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Distance from the rectangle under the model, fading out over the blur radius.
+    let outside = max(abs(in.local) - ground.footprint, vec2<f32>(0.0));
+    let shadow = ground.shadowOpacity * (1.0 - smoothstep(0.0, ground.shadowBlur, length(outside)));
+
+    var grid = 0.0;
+    if (ground.showGrid != 0) {
+        grid = gridIntensity(in.local) * ground.gridColor.a;
+    }
+
+    let alpha = grid + shadow * (1.0 - grid);
+    if (alpha <= 0.0) {
+        return vec4<f32>(0.0);
+    }
+
+    return vec4<f32>(ground.gridColor.rgb * grid / alpha, alpha);
+}
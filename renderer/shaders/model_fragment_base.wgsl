@@ -4,6 +4,8 @@ struct ProjectionData {
     viewMatrix: mat4x4<f32>,
     normalMatrix: mat3x3<f32>,
     isOrthographic: i32,
+    clipPlanes: array<vec4<f32>, 4>,
+    clipPlaneCount: i32,
 }
 
 struct MaterialUniforms {
@@ -31,6 +33,9 @@ struct VertexOutput {
     @location(0) viewPosition: vec3<f32>,
     @location(1) normal: vec3<f32>,
     @location(2) color: vec4<f32>,
+    @location(3) material: vec2<f32>,
+    @location(4) occlusion: f32,
+    @location(5) clipDistances: vec4<f32>,
 }
 
 struct ReflectedLight {
@@ -64,145 +69,6 @@ fn inverseTransformDirection(dir: vec3<f32>, matrix: mat4x4<f32>) -> vec3<f32> {
 const PI: f32 = 3.141592653589793;
 const RECIPROCAL_PI: f32 = 0.3183098861837907;
 
-const cubeUV_maxMipLevel: f32 = 8.0;
-const cubeUV_minMipLevel: f32 = 4.0;
-const cubeUV_maxTileSize: f32 = 256.0;
-const cubeUV_minTileSize: f32 = 16.0;
-
-fn getFace(direction: vec3<f32>) -> f32 {
-    let absDirection = abs(direction);
-    var face = -1.0;
-    if (absDirection.x > absDirection.z) {
-        if (absDirection.x > absDirection.y) {
-            face = select(3.0, 0.0, direction.x > 0.0);
-        } else {
-            face = select(4.0, 1.0, direction.y > 0.0);
-        }
-    } else {
-        if (absDirection.z > absDirection.y) {
-            face = select(5.0, 2.0, direction.z > 0.0);
-        } else {
-            face = select(4.0, 1.0, direction.y > 0.0);
-        }
-    }
-    return face;
-}
-
-fn getUV(direction: vec3<f32>, face: f32) -> vec2<f32> {
-    var uv: vec2<f32>;
-    if (face == 0.0) {
-        uv = vec2<f32>(direction.z, direction.y) / abs(direction.x);
-    } else if (face == 1.0) {
-        uv = vec2<f32>(-direction.x, -direction.z) / abs(direction.y);
-    } else if (face == 2.0) {
-        uv = vec2<f32>(-direction.x, direction.y) / abs(direction.z);
-    } else if (face == 3.0) {
-        uv = vec2<f32>(-direction.z, direction.y) / abs(direction.x);
-    } else if (face == 4.0) {
-        uv = vec2<f32>(-direction.x, direction.z) / abs(direction.y);
-    } else {
-        uv = vec2(direction.x, direction.y) / abs(direction.z);
-    }
-    return 0.5 * (uv + 1.0);
-}
-
-fn envMapTexelToLinear(value: vec4<f32>) -> vec4<f32> {
-    return vec4<f32>(value.rgb * exp2(value.a * 255.0 - 128.0), 1.0);
-}
-
-fn fauxColor(face: f32) -> vec3<f32> {
-    if (face == 0.0) {
-        return vec3<f32>(1.0, 0.0, 0.0);
-    } else if (face == 1.0) {
-        return vec3<f32>(0.0, 1.0, 0.0);
-    } else if (face == 2.0) {
-        return vec3<f32>(0.0, 0.0, 1.0);
-    } else if (face == 3.0) {
-        return vec3<f32>(1.0, 1.0, 0.0);
-    } else if (face == 4.0) {
-        return vec3<f32>(0.0, 1.0, 1.0);
-    } else {
-        return vec3<f32>(1.0, 0.0, 1.0);
-    }
-}
-
-fn bilinearCubeUV(envMapTexture: texture_2d<f32>, envMapSampler: sampler, direction: vec3<f32>, mipInt_: f32) -> vec3<f32> {
-    var face = getFace(direction);
-    let filterInt = max(cubeUV_minMipLevel - mipInt_, 0.0);
-    let mipInt = max(mipInt_, cubeUV_minMipLevel);
-    let faceSize = exp2(mipInt);
-    let texelSize = 1.0 / (3.0 * cubeUV_maxTileSize);
-    var uv = getUV(direction, face) * (faceSize - 1.0);
-    let f = fract(uv);
-    uv += 0.5 - f;
-    if (face > 2.0) {
-        uv.y += faceSize;
-        face -= 3.0;
-    }
-    uv.x += face * faceSize;
-    if (mipInt < cubeUV_maxMipLevel) {
-        uv.y += 2.0 * cubeUV_maxTileSize;
-    }
-    uv.y += filterInt * 2.0 * cubeUV_minTileSize;
-    uv.x += 3.0 * max(0.0, cubeUV_maxTileSize - 2.0 * faceSize);
-    uv *= texelSize;
-    let tl = envMapTexelToLinear(textureSample(envMapTexture, envMapSampler, uv)).rgb;
-    uv.x += texelSize;
-    let tr = envMapTexelToLinear(textureSample(envMapTexture, envMapSampler, uv)).rgb;
-    uv.y += texelSize;
-    let br = envMapTexelToLinear(textureSample(envMapTexture, envMapSampler, uv)).rgb;
-    uv.x -= texelSize;
-    let bl = envMapTexelToLinear(textureSample(envMapTexture, envMapSampler, uv)).rgb;
-    let tm = mix(tl, tr, f.x);
-    let bm = mix(bl, br, f.x);
-
-    return mix(tm, bm, f.y);
-    //return fauxColor(getFace(direction));
-}
-
-const r0: f32 = 1.0;
-const v0: f32 = 0.339;
-const m0: f32 = -2.0;
-const r1: f32 = 0.8;
-const v1: f32 = 0.276;
-const m1: f32 = -1.0;
-const r4: f32 = 0.4;
-const v4: f32 = 0.046;
-const m4: f32 = 2.0;
-const r5: f32 = 0.305;
-const v5: f32 = 0.016;
-const m5: f32 = 3.0;
-const r6: f32 = 0.21;
-const v6: f32 = 0.0038;
-const m6: f32 = 4.0;
-
-fn roughnessToMip(roughness: f32) -> f32 {
-    if (roughness >= r1) {
-        return (r0 - roughness) * (m1 - m0) / (r0 - r1) + m0;
-    } else if (roughness >= r4) {
-        return (r1 - roughness) * (m4 - m1) / (r1 - r4) + m1;
-    } else if (roughness >= r5) {
-        return (r4 - roughness) * (m5 - m4) / (r4 - r5) + m4;
-    } else if (roughness >= r6) {
-        return (r5 - roughness) * (m6 - m5) / (r5 - r6) + m5;
-    } else {
-        return -2.0 * log2(1.16 * roughness);
-    }
-}
-
-fn textureCubeUV(envMapTexture: texture_2d<f32>, envMapSampler: sampler, sampleDir: vec3<f32>, roughness: f32) -> vec4<f32> {
-    let mip = clamp(roughnessToMip(roughness), m0, cubeUV_maxMipLevel);
-    let mipF = fract(mip);
-    let mipInt = floor(mip);
-    let color0 = bilinearCubeUV(envMapTexture, envMapSampler, sampleDir, mipInt);
-    let color1 = bilinearCubeUV(envMapTexture, envMapSampler, sampleDir, mipInt + 1.0);
-    if (mipF == 0.0) {
-        return vec4<f32>(color0, 1.0);
-    } else {
-        return vec4<f32>(mix(color0, color1, mipF), 1.0);
-    }
-}
-
 const envMapIntensity: f32 = 1.0;
 const ambientLightColor: vec3<f32> = vec3<f32>(0.0, 0.0, 0.0);
 
@@ -246,6 +112,10 @@ fn computeMultiscattering(normal: vec3<f32>, viewDir: vec3<f32>, specularColor:
     *multiScatter += Fms * Ems;
 }
 
+fn computeSpecularOcclusion(dotNV: f32, ambientOcclusion: f32, roughness: f32) -> f32 {
+    return saturate(pow(dotNV + ambientOcclusion, exp2(-16.0 * roughness - 1.0)) - 1.0 + ambientOcclusion);
+}
+
 fn RE_IndirectDiffuse(irradiance: vec3<f32>, geometry: GeometricContext, material: PhysicalMaterial, reflectedLight: ptr<function, ReflectedLight>) {
     (*reflectedLight).indirectDiffuse += irradiance * BRDF_Lambert(material.diffuseColor);
 }
@@ -263,12 +133,16 @@ fn RE_IndirectSpecular(radiance: vec3<f32>, irradiance: vec3<f32>, clearcoatRadi
 
 @fragment
 fn fs(in: VertexOutput) -> @location(0) vec4<f32> {
+    if (any(in.clipDistances < vec4<f32>(0.0))) {
+        discard;
+    }
+
     var diffuseColor = vec4<f32>(materialUniforms.diffuse, 1.0);
     var reflectedLight = ReflectedLight(vec3<f32>(0.0), vec3<f32>(0.0), vec3<f32>(0.0), vec3<f32>(0.0));
     let totalEmissiveRadiance = materialUniforms.emissive;
     diffuseColor *= in.color;
-    let roughnessFactor = materialUniforms.roughness;
-    let metalnessFactor = materialUniforms.metalness;
+    let roughnessFactor = select(materialUniforms.roughness, in.material.x, in.material.x >= 0.0);
+    let metalnessFactor = select(materialUniforms.metalness, in.material.y, in.material.y >= 0.0);
     let faceDirection = select(-1.0, 1.0, in.frontFacing);
     let normal = normalize(in.normal);
 
@@ -294,6 +168,9 @@ fn fs(in: VertexOutput) -> @location(0) vec4<f32> {
     radiance += getIBLRadiance(geometry.viewDir, geometry.normal, material.roughness);
     RE_IndirectDiffuse(irradiance, geometry, material, &reflectedLight);
     RE_IndirectSpecular(radiance, iblIrradiance, clearcoatRadiance, geometry, material, &reflectedLight);
+    reflectedLight.indirectDiffuse *= in.occlusion;
+    let dotNV = saturate(dot(geometry.normal, geometry.viewDir));
+    reflectedLight.indirectSpecular *= computeSpecularOcclusion(dotNV, in.occlusion, material.roughness);
     let totalDiffuse = reflectedLight.directDiffuse + reflectedLight.indirectDiffuse;
     let totalSpecular = reflectedLight.directSpecular + reflectedLight.indirectSpecular;
     let outgoingLight = totalDiffuse + totalSpecular + totalEmissiveRadiance;
@@ -0,0 +1,33 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) viewPosition: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) color: vec4<f32>,
+    @location(5) clipDistances: vec4<f32>,
+}
+
+fn intensity(normal: vec3<f32>) -> f32 {
+    return 0.4 + 0.6 * abs(normalize(normal).z);
+}
+
+@fragment
+fn fs(in: VertexOutput, @builtin(front_facing) front: bool) -> @location(0) vec4<f32> {
+    if (any(in.clipDistances < vec4<f32>(0.0))) {
+        discard;
+    }
+
+    if (front) {
+        return vec4<f32>(0.0, intensity(in.normal), 0.0, 1.0);
+    } else {
+        return vec4<f32>(intensity(in.normal), 0.0, 0.0, 1.0);
+    }
+}
+
+@fragment
+fn fs_error(in: VertexOutput) -> @location(0) vec4<f32> {
+    if (any(in.clipDistances < vec4<f32>(0.0))) {
+        discard;
+    }
+
+    return vec4<f32>(0.1, 0.3, intensity(in.normal), 1.0);
+}
@@ -3,9 +3,14 @@ struct VertexOutput {
     @location(0) viewPosition: vec3<f32>,
     @location(1) normal: vec3<f32>,
     @location(2) color: vec4<f32>,
+    @location(5) clipDistances: vec4<f32>,
 }
 
 @fragment
 fn fs(in: VertexOutput) -> @location(0) vec4<f32> {
+    if (any(in.clipDistances < vec4<f32>(0.0))) {
+        discard;
+    }
+
     return in.color;
 }
@@ -4,11 +4,22 @@ struct ProjectionData {
     viewMatrix: mat4x4<f32>,
     normalMatrix: mat3x3<f32>,
     isOrthographic: i32,
+    clipPlanes: array<vec4<f32>, 4>,
+    clipPlaneCount: i32,
 }
 
 @group(0) @binding(0)
 var<uniform> projection: ProjectionData;
 
+// Signed distances to each clip plane. Unused planes never cut anything.
+fn clipDistances(position: vec4<f32>) -> vec4<f32> {
+    var distances = vec4<f32>(1.0);
+    for (var i = 0; i < projection.clipPlaneCount; i++) {
+        distances[i] = dot(projection.clipPlanes[i], position);
+    }
+    return distances;
+}
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) normal: vec3<f32>,
@@ -22,6 +33,8 @@ struct InstanceInput {
     @location(13) modelMatrix3: vec4<f32>,
     @location(14) instanceColor: vec4<f32>,
     @location(15) instanceEdgeColor: vec4<f32>,
+    @location(9) instanceMaterial: vec2<f32>,
+    @location(8) instanceOcclusion: f32,
 }
 
 struct VertexOutput {
@@ -29,6 +42,9 @@ struct VertexOutput {
     @location(0) viewPosition: vec3<f32>,
     @location(1) normal: vec3<f32>,
     @location(2) color: vec4<f32>,
+    @location(3) material: vec2<f32>,
+    @location(4) occlusion: f32,
+    @location(5) clipDistances: vec4<f32>,
 }
 
 @vertex
@@ -58,8 +74,8 @@ fn vs(
     );
     transformedNormal = instanceNormalMatrix * transformedNormal;
 
-    var mvPosition = vec4<f32>(vertex.position, 1.0);
-    mvPosition = projection.viewMatrix * projection.modelMatrix * instanceModelMatrix * mvPosition;
+    let position = instanceModelMatrix * vec4<f32>(vertex.position, 1.0);
+    let mvPosition = projection.viewMatrix * projection.modelMatrix * position;
 
     if (vertex.color.x < -1.0) {
         out.color = instance.instanceEdgeColor;
@@ -68,10 +84,13 @@ fn vs(
     } else {
         out.color = vertex.color;
     }
+    out.material = instance.instanceMaterial;
+    out.occlusion = instance.instanceOcclusion;
     out.normal = normalize(projection.normalMatrix * transformedNormal);
     out.normal.y *= -1.0;
     out.viewPosition = -mvPosition.xyz;
     out.position = projection.projectionMatrix * mvPosition;
+    out.clipDistances = clipDistances(position);
 
     return out;
 }
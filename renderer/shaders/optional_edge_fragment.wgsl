@@ -1,11 +1,12 @@
 struct VertexOutput {
     @location(0) color: vec4<f32>,
     @location(1) discardFlag: f32,
+    @location(2) clipDistances: vec4<f32>,
 }
 
 @fragment
 fn fs(in: VertexOutput) -> @location(0) vec4<f32> {
-    if (in.discardFlag >= 0.5) {
+    if (in.discardFlag >= 0.5 || in.color.a <= 0.0 || any(in.clipDistances < vec4<f32>(0.0))) {
         discard;
     }
 
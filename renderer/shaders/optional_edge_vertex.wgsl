@@ -4,11 +4,22 @@ struct ProjectionData {
     viewMatrix: mat4x4<f32>,
     normalMatrix: mat3x3<f32>,
     isOrthographic: i32,
+    clipPlanes: array<vec4<f32>, 4>,
+    clipPlaneCount: i32,
 }
 
 @group(0) @binding(0)
 var<uniform> projection: ProjectionData;
 
+// Signed distances to each clip plane. Unused planes never cut anything.
+fn clipDistances(position: vec4<f32>) -> vec4<f32> {
+    var distances = vec4<f32>(1.0);
+    for (var i = 0; i < projection.clipPlaneCount; i++) {
+        distances[i] = dot(projection.clipPlanes[i], position);
+    }
+    return distances;
+}
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) control1: vec3<f32>,
@@ -30,6 +41,7 @@ struct VertexOutput {
     @builtin(position) position: vec4<f32>,
     @location(0) color: vec4<f32>,
     @location(1) discardFlag: f32,
+    @location(2) clipDistances: vec4<f32>,
 }
 
 @vertex
@@ -80,10 +92,15 @@ fn vs(
     } else {
         out.color = vec4<f32>(vertex.color, 1.0);
     }
+    // Instances with fully transparent edges, such as ghosted ones, have no edges at all.
+    if (edgeColor.a <= 0.0) {
+        out.color.a = 0.0;
+    }
 
     var mvPosition = vec4<f32>(vertex.position, 1.0);
     mvPosition = mvMatrix * mvPosition;
     out.position = mvPosition;
+    out.clipDistances = clipDistances(instanceModelMatrix * vec4<f32>(vertex.position, 1.0));
 
     return out;
 }
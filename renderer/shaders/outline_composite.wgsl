@@ -0,0 +1,50 @@
+struct OutlineData {
+    color: vec4<f32>,
+    thickness: i32,
+}
+
+@group(0) @binding(0)
+var<uniform> outline: OutlineData;
+
+@group(0) @binding(1)
+var mask: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+}
+
+@vertex
+fn vs(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+
+    let ndc = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u)) * 2.0 - 1.0;
+    out.position = vec4<f32>(ndc, 0.0, 1.0);
+
+    return out;
+}
+
+@fragment
+fn fs(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<i32>(textureDimensions(mask));
+    let center = vec2<i32>(in.position.xy);
+
+    // Only pixels just outside of the silhouette are painted.
+    if (textureLoad(mask, center, 0).r > 0.5) {
+        discard;
+    }
+
+    let r = outline.thickness;
+    for (var y = -r; y <= r; y++) {
+        for (var x = -r; x <= r; x++) {
+            if (x * x + y * y > r * r) {
+                continue;
+            }
+            let p = clamp(center + vec2<i32>(x, y), vec2<i32>(0, 0), size - vec2<i32>(1, 1));
+            if (textureLoad(mask, p, 0).r > 0.5) {
+                return outline.color;
+            }
+        }
+    }
+
+    discard;
+}
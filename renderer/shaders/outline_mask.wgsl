@@ -0,0 +1,13 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(5) clipDistances: vec4<f32>,
+}
+
+@fragment
+fn fs(in: VertexOutput) -> @location(0) vec4<f32> {
+    if (any(in.clipDistances < vec4<f32>(0.0))) {
+        discard;
+    }
+
+    return vec4<f32>(1.0, 1.0, 1.0, 1.0);
+}
@@ -4,6 +4,8 @@ struct ProjectionData {
     viewMatrix: mat4x4<f32>,
     normalMatrix: mat3x3<f32>,
     isOrthographic: i32,
+    clipPlanes: array<vec4<f32>, 4>,
+    clipPlaneCount: i32,
 }
 
 @group(0) @binding(0)
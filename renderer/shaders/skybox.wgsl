@@ -0,0 +1,68 @@
+struct ProjectionData {
+    modelMatrix: mat4x4<f32>,
+    projectionMatrix: mat4x4<f32>,
+    viewMatrix: mat4x4<f32>,
+    normalMatrix: mat3x3<f32>,
+    isOrthographic: i32,
+    clipPlanes: array<vec4<f32>, 4>,
+    clipPlaneCount: i32,
+}
+
+struct SkyboxUniforms {
+    blurriness: f32,
+    intensity: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> projection: ProjectionData;
+
+@group(1) @binding(0)
+var<uniform> skyboxUniforms: SkyboxUniforms;
+
+@group(1) @binding(1)
+var envMapTexture: texture_2d<f32>;
+
+@group(1) @binding(2)
+var envMapSampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+// Field of view used for orthographic projections, which have no vanishing point.
+const orthographicTanHalfFov: f32 = 0.41421356;
+
+@vertex
+fn vs(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+
+    let ndc = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u)) * 2.0 - 1.0;
+    out.ndc = ndc;
+    out.position = vec4<f32>(ndc, 1.0, 1.0);
+
+    return out;
+}
+
+fn inverseTransformDirection(dir: vec3<f32>, matrix: mat4x4<f32>) -> vec3<f32> {
+    return normalize((vec4<f32>(dir, 0.0) * matrix).xyz);
+}
+
+@fragment
+fn fs(in: VertexOutput) -> @location(0) vec4<f32> {
+    let p = projection.projectionMatrix;
+    var ray: vec3<f32>;
+    if (projection.isOrthographic == 1) {
+        let aspect = p[1][1] / p[0][0];
+        ray = vec3<f32>(in.ndc.x * aspect * orthographicTanHalfFov, in.ndc.y * orthographicTanHalfFov, -1.0);
+    } else {
+        ray = vec3<f32>(in.ndc.x / p[0][0], in.ndc.y / p[1][1], -1.0);
+    }
+    // Normals are flipped along Y in model_vertex.wgsl, so follow the same convention here.
+    ray.y *= -1.0;
+
+    let direction = inverseTransformDirection(ray, projection.viewMatrix);
+    let color = textureCubeUV(envMapTexture, envMapSampler, direction, skyboxUniforms.blurriness);
+
+    return vec4<f32>(color.rgb * skyboxUniforms.intensity, 1.0);
+}
@@ -9,21 +9,67 @@ use std::{
 
 use cgmath::SquareMatrix;
 use ldraw::{
-    color::{Color, ColorCatalog, ColorReference},
+    color::{Color, ColorCatalog, ColorReference, Material},
     Matrix4, PartAlias, Vector4,
 };
-use ldraw_ir::model::{GroupId, Model, Object, ObjectGroup, ObjectId, ObjectInstance};
+use ldraw_ir::{
+    model::{GroupId, Model, Object, ObjectGroup, ObjectId, ObjectInstance},
+    occlusion::{bake_ambient_occlusion, OcclusionParams},
+    part::PartGeometryQuerier,
+};
 use uuid::Uuid;
 use wgpu::util::DeviceExt;
 
 use crate::{Entity, GpuUpdate, GpuUpdateResult};
 
+// Negative values fall back to the defaults in pipeline::MaterialUniformData.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MaterialParams {
+    pub roughness: f32,
+    pub metalness: f32,
+}
+
+impl Default for MaterialParams {
+    fn default() -> Self {
+        Self {
+            roughness: -1.0,
+            metalness: -1.0,
+        }
+    }
+}
+
+impl From<&Material> for MaterialParams {
+    fn from(material: &Material) -> Self {
+        let (roughness, metalness) = match material {
+            Material::Chrome => (0.05, 1.0),
+            Material::Metal => (0.25, 1.0),
+            Material::MatteMetallic => (0.55, 0.8),
+            Material::Pearlescent => (0.25, 0.45),
+            Material::Rubber => (0.85, 0.0),
+            Material::Plastic | Material::Custom(_) => return Self::default(),
+        };
+
+        Self {
+            roughness,
+            metalness,
+        }
+    }
+}
+
+impl From<&Color> for MaterialParams {
+    fn from(color: &Color) -> Self {
+        Self::from(&color.material)
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 struct InstanceData {
     model_matrix: [[f32; 4]; 4],
     color: [f32; 4],
     edge_color: [f32; 4],
+    material: [f32; 2],
+    occlusion: f32,
 }
 
 impl InstanceData {
@@ -38,11 +84,29 @@ impl InstanceData {
     pub fn get_edge_color(&self) -> Vector4 {
         self.edge_color.into()
     }
+
+    pub fn get_material(&self) -> MaterialParams {
+        MaterialParams {
+            roughness: self.material[0],
+            metalness: self.material[1],
+        }
+    }
+
+    fn set_material(&mut self, material: MaterialParams) {
+        self.material = [material.roughness, material.metalness];
+    }
+
+    pub fn get_occlusion(&self) -> f32 {
+        self.occlusion
+    }
 }
 
+// Matrix, color, edge color, material and occlusion of an instance.
+type InstanceRow = (Matrix4, Vector4, Vector4, MaterialParams, f32);
+
 #[derive(Debug)]
 struct InstanceTransaction<K> {
-    rows_to_insert: HashMap<K, (Matrix4, Vector4, Vector4)>,
+    rows_to_insert: HashMap<K, InstanceRow>,
     rows_to_remove: Vec<K>,
     changed_indices: Vec<usize>,
 }
@@ -81,6 +145,10 @@ impl<K, G> Instances<K, G> {
         self.count() == 0
     }
 
+    pub fn matrices(&self) -> impl Iterator<Item = Matrix4> + '_ {
+        self.instance_data.iter().map(InstanceData::get_matrix)
+    }
+
     fn update_buffer_partial(&self, queue: &wgpu::Queue, range: RangeInclusive<usize>) {
         if let Some(buffer) = &self.instance_buffer {
             queue.write_buffer(
@@ -126,6 +194,16 @@ impl<K, G> Instances<K, G> {
                     shader_location: 15,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 24]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 26]>() as wgpu::BufferAddress,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Float32,
+                },
             ],
         }
     }
@@ -168,12 +246,15 @@ pub enum InstanceOps<K> {
         matrix: Matrix4,
         color: Vector4,
         edge_color: Vector4,
+        material: MaterialParams,
+        occlusion: f32,
     },
     Update {
         key: K,
         matrix: Matrix4,
         color: Vector4,
         edge_color: Vector4,
+        material: MaterialParams,
     },
     UpdateMatrix {
         key: K,
@@ -183,14 +264,43 @@ pub enum InstanceOps<K> {
         key: K,
         color: Vector4,
         edge_color: Vector4,
+        material: MaterialParams,
     },
     UpdateAlpha {
         key: K,
         alpha: f32,
     },
+    UpdateOcclusion {
+        key: K,
+        occlusion: f32,
+    },
     Remove(K),
 }
 
+impl<K: Eq + PartialEq + Hash, G> Instances<K, G> {
+    // Current values of an instance, including ones not applied to GPU yet.
+    fn row(&self, key: &K) -> Option<InstanceRow> {
+        let pending = self
+            .transaction
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|tr| tr.rows_to_insert.get(key).cloned());
+        pending.or_else(|| {
+            self.index.get(key).map(|index| {
+                let data = &self.instance_data[*index];
+                (
+                    data.get_matrix(),
+                    data.get_color(),
+                    data.get_edge_color(),
+                    data.get_material(),
+                    data.get_occlusion(),
+                )
+            })
+        })
+    }
+}
+
 impl<K: Clone + Eq + PartialEq + Hash + Debug, G: Display> GpuUpdate for Instances<K, G> {
     type Mutator = InstanceOps<K>;
 
@@ -204,8 +314,11 @@ impl<K: Clone + Eq + PartialEq + Hash + Debug, G: Display> GpuUpdate for Instanc
                 matrix,
                 color,
                 edge_color,
+                material,
+                occlusion,
             } => {
-                tr.rows_to_insert.insert(key, (matrix, color, edge_color));
+                tr.rows_to_insert
+                    .insert(key, (matrix, color, edge_color, material, occlusion));
             }
             InstanceOps::Remove(key) => {
                 tr.rows_to_insert.remove(&key);
@@ -216,17 +329,20 @@ impl<K: Clone + Eq + PartialEq + Hash + Debug, G: Display> GpuUpdate for Instanc
                 matrix,
                 color,
                 edge_color,
+                material,
             } => {
                 if let Some(entry_idx) = self.index.get(&key).cloned() {
                     let data = &mut self.instance_data[entry_idx];
                     data.model_matrix = matrix.into();
                     data.color = color.into();
                     data.edge_color = edge_color.into();
+                    data.set_material(material);
                     tr.changed_indices.push(entry_idx);
                 } else if let Some(entry) = tr.rows_to_insert.get_mut(&key) {
                     entry.0 = matrix;
                     entry.1 = color;
                     entry.2 = edge_color;
+                    entry.3 = material;
                 }
             }
             InstanceOps::UpdateMatrix { key, matrix } => {
@@ -242,15 +358,18 @@ impl<K: Clone + Eq + PartialEq + Hash + Debug, G: Display> GpuUpdate for Instanc
                 key,
                 color,
                 edge_color,
+                material,
             } => {
                 if let Some(entry_idx) = self.index.get(&key).cloned() {
                     let data = &mut self.instance_data[entry_idx];
                     data.color = color.into();
                     data.edge_color = edge_color.into();
+                    data.set_material(material);
                     tr.changed_indices.push(entry_idx);
                 } else if let Some(entry) = tr.rows_to_insert.get_mut(&key) {
                     entry.1 = color;
                     entry.2 = edge_color;
+                    entry.3 = material;
                 }
             }
             InstanceOps::UpdateAlpha { key, alpha } => {
@@ -264,6 +383,14 @@ impl<K: Clone + Eq + PartialEq + Hash + Debug, G: Display> GpuUpdate for Instanc
                     entry.2.w = alpha;
                 }
             }
+            InstanceOps::UpdateOcclusion { key, occlusion } => {
+                if let Some(entry_idx) = self.index.get(&key).cloned() {
+                    self.instance_data[entry_idx].occlusion = occlusion;
+                    tr.changed_indices.push(entry_idx);
+                } else if let Some(entry) = tr.rows_to_insert.get_mut(&key) {
+                    entry.4 = occlusion;
+                }
+            }
         }
 
         GpuUpdateResult::Modified
@@ -283,13 +410,16 @@ impl<K: Clone + Eq + PartialEq + Hash + Debug, G: Display> GpuUpdate for Instanc
             .collect::<Vec<_>>();
         rows_to_remove.sort_by_key(|v| std::cmp::Reverse(v.1));
 
-        for (key, (matrix, color, edge_color)) in tr.rows_to_insert.into_iter() {
+        for (key, (matrix, color, edge_color, material, occlusion)) in tr.rows_to_insert.into_iter()
+        {
             if let Some((old_key, idx_to_reuse)) = rows_to_remove.pop() {
                 // Take over removed rows and fill with inserted ones if available
                 let data = &mut self.instance_data[idx_to_reuse];
                 data.model_matrix = matrix.into();
                 data.color = color.into();
                 data.edge_color = edge_color.into();
+                data.set_material(material);
+                data.occlusion = occlusion;
                 self.index.remove(&old_key);
                 self.index.insert(key, idx_to_reuse);
                 tr.changed_indices.push(idx_to_reuse);
@@ -300,6 +430,8 @@ impl<K: Clone + Eq + PartialEq + Hash + Debug, G: Display> GpuUpdate for Instanc
                     model_matrix: matrix.into(),
                     color: color.into(),
                     edge_color: edge_color.into(),
+                    material: [material.roughness, material.metalness],
+                    occlusion,
                 });
                 self.index.insert(key, self.instance_data.len() - 1);
             }
@@ -366,6 +498,11 @@ struct Group<G>(GroupKind, G);
 pub struct DisplayList<K, G> {
     map: HashMap<Group<G>, Entity<Instances<K, G>>>,
     lookup_table: HashMap<K, Group<G>>,
+    // Instances taken out of instance buffers, kept aside until they are shown again. Their
+    // groups stay in lookup_table.
+    hidden: HashMap<K, InstanceRow>,
+    // Color, edge color and material to go back to for instances with overridden colors.
+    overrides: HashMap<K, (Vector4, Vector4, MaterialParams)>,
 }
 
 impl<K, G> DisplayList<K, G> {
@@ -373,6 +510,8 @@ impl<K, G> DisplayList<K, G> {
         Self {
             map: HashMap::new(),
             lookup_table: HashMap::new(),
+            hidden: HashMap::new(),
+            overrides: HashMap::new(),
         }
     }
 
@@ -414,6 +553,18 @@ impl<
             .or_insert_with(|| Instances::new(group.1).into())
     }
 
+    pub fn is_visible(&self, k: &K) -> bool {
+        self.lookup_table.contains_key(k) && !self.hidden.contains_key(k)
+    }
+
+    pub fn hidden_keys(&self) -> impl Iterator<Item = &K> {
+        self.hidden.keys()
+    }
+
+    pub fn is_overridden(&self, k: &K) -> bool {
+        self.overrides.contains_key(k)
+    }
+
     pub fn get_by_key(&self, k: &K) -> Option<&Entity<Instances<K, G>>> {
         if let Some(group) = self.lookup_table.get(k) {
             self.map.get(group)
@@ -421,6 +572,46 @@ impl<
             None
         }
     }
+
+    fn get_occlusion(&self, k: &K) -> f32 {
+        self.get_by_key(k)
+            .and_then(|instances| {
+                instances
+                    .index
+                    .get(k)
+                    .map(|index| instances.instance_data[*index].get_occlusion())
+            })
+            .unwrap_or(1.0)
+    }
+
+    // Only instances already applied to GPU are taken into account, so this
+    // should be called after update().
+    pub fn bake_ambient_occlusion(
+        &self,
+        parts: &impl PartGeometryQuerier<G>,
+        params: &OcclusionParams,
+    ) -> Vec<DisplayListOps<K, G>> {
+        let instances = self
+            .map
+            .values()
+            .filter_map(|instances| {
+                let part = parts.query_part_geometry(&instances.group)?;
+                Some(instances.index.iter().map(move |(key, index)| {
+                    (
+                        key.clone(),
+                        part,
+                        instances.instance_data[*index].get_matrix(),
+                    )
+                }))
+            })
+            .flatten()
+            .collect::<Vec<_>>();
+
+        bake_ambient_occlusion(&instances, params)
+            .into_iter()
+            .map(|(key, occlusion)| DisplayListOps::UpdateOcclusion { key, occlusion })
+            .collect()
+    }
 }
 
 fn uuid_xor(a: ObjectId, b: ObjectId) -> ObjectId {
@@ -432,9 +623,11 @@ fn uuid_xor(a: ObjectId, b: ObjectId) -> ObjectId {
 }
 
 impl<P: Clone + Eq + PartialEq + Hash + From<PartAlias> + Display> DisplayList<ObjectId, P> {
+    #[allow(clippy::too_many_arguments)]
     fn expand_object_group(
         ops: &mut Vec<DisplayListOps<ObjectId, P>>,
         color_catalog: &ColorCatalog,
+        map_color: &dyn Fn(&Color) -> Color,
         parent_id: ObjectId,
         groups: &HashMap<GroupId, ObjectGroup<P>>,
         objects: &[Object<P>],
@@ -458,7 +651,7 @@ impl<P: Clone + Eq + PartialEq + Hash + From<PartAlias> + Display> DisplayList<O
                         group: p.part.clone(),
                         key: uuid_xor(parent_id, object.id),
                         matrix: local_matrix,
-                        color: color.clone(),
+                        color: map_color(color),
                         alpha: None,
                     });
                 }
@@ -474,6 +667,7 @@ impl<P: Clone + Eq + PartialEq + Hash + From<PartAlias> + Display> DisplayList<O
                         Self::expand_object_group(
                             ops,
                             color_catalog,
+                            map_color,
                             uuid_xor(parent_id, object.id),
                             groups,
                             &group.objects,
@@ -499,22 +693,51 @@ impl<P: Clone + Eq + PartialEq + Hash + From<PartAlias> + Display> DisplayList<O
             None => Some(&model.objects),
         };
 
-        let mut ops = vec![];
         if let Some(objects) = objects {
-            Self::expand_object_group(
-                &mut ops,
-                color_catalog,
-                Uuid::nil().into(),
-                &model.object_groups,
+            Self::insert_objects(
+                &mut display_list,
+                model,
                 objects,
-                Matrix4::identity(),
-                ColorReference::Color(color_catalog.get(&0).cloned().unwrap()),
-            )
+                color_catalog,
+                Clone::clone,
+            );
         }
-        display_list.mutate_all(ops.into_iter());
 
         display_list
     }
+
+    pub fn insert_objects(
+        display_list: &mut Entity<Self>,
+        model: &Model<P>,
+        objects: &[Object<P>],
+        color_catalog: &ColorCatalog,
+        map_color: impl Fn(&Color) -> Color,
+    ) {
+        let ops = Self::expand_objects(model, objects, color_catalog, map_color);
+        display_list.mutate_all(ops.into_iter());
+    }
+
+    // Insert operations for every part instance under given objects, keyed the same way
+    // as display lists built from the model.
+    pub fn expand_objects(
+        model: &Model<P>,
+        objects: &[Object<P>],
+        color_catalog: &ColorCatalog,
+        map_color: impl Fn(&Color) -> Color,
+    ) -> Vec<DisplayListOps<ObjectId, P>> {
+        let mut ops = vec![];
+        Self::expand_object_group(
+            &mut ops,
+            color_catalog,
+            &map_color,
+            Uuid::nil().into(),
+            &model.object_groups,
+            objects,
+            Matrix4::identity(),
+            ColorReference::Color(color_catalog.get(&0).cloned().unwrap()),
+        );
+        ops
+    }
 }
 
 pub struct DisplayListOpsReinstantiate<G, K> {
@@ -523,6 +746,8 @@ pub struct DisplayListOpsReinstantiate<G, K> {
     matrix: Matrix4,
     color: Vector4,
     edge_color: Vector4,
+    material: MaterialParams,
+    occlusion: f32,
 }
 
 pub enum DisplayListOps<K, G> {
@@ -550,10 +775,289 @@ pub enum DisplayListOps<K, G> {
         key: K,
         alpha: f32,
     },
+    UpdateOcclusion {
+        key: K,
+        occlusion: f32,
+    },
     Remove {
         key: K,
     },
+    // Hidden instances are left out of instance buffers, but keep receiving updates.
+    SetVisible {
+        key: K,
+        visible: bool,
+    },
+    // Applies to every instance of the group.
+    SetGroupVisible {
+        group: G,
+        visible: bool,
+    },
+    SetAllVisible {
+        visible: bool,
+    },
+    // Shows the instance in another color until RestoreColor. Color updates made in the
+    // meantime apply to the original color, which is what gets restored.
+    OverrideColor {
+        key: K,
+        color: Color,
+    },
+    // Overrides the color with a translucent one without edges.
+    Ghost {
+        key: K,
+        alpha: f32,
+    },
+    RestoreColor {
+        key: K,
+    },
+    RestoreAllColors,
     _Reinstantiate(DisplayListOpsReinstantiate<G, K>),
+    _UpdateRawColor {
+        key: K,
+        color: Vector4,
+        edge_color: Vector4,
+        material: MaterialParams,
+    },
+}
+
+impl<K, G> DisplayListOps<K, G> {
+    fn key(&self) -> Option<&K> {
+        match self {
+            DisplayListOps::Insert { key, .. }
+            | DisplayListOps::Update { key, .. }
+            | DisplayListOps::UpdateMatrix { key, .. }
+            | DisplayListOps::UpdateColor { key, .. }
+            | DisplayListOps::UpdateAlpha { key, .. }
+            | DisplayListOps::UpdateOcclusion { key, .. }
+            | DisplayListOps::Remove { key }
+            | DisplayListOps::SetVisible { key, .. }
+            | DisplayListOps::OverrideColor { key, .. }
+            | DisplayListOps::Ghost { key, .. }
+            | DisplayListOps::RestoreColor { key }
+            | DisplayListOps::_UpdateRawColor { key, .. } => Some(key),
+            DisplayListOps::SetGroupVisible { .. }
+            | DisplayListOps::SetAllVisible { .. }
+            | DisplayListOps::RestoreAllColors
+            | DisplayListOps::_Reinstantiate(_) => None,
+        }
+    }
+}
+
+impl<
+        K: Clone + Debug + Eq + PartialEq + Hash + Display,
+        G: Clone + Eq + PartialEq + Hash + Display,
+    > DisplayList<K, G>
+{
+    fn set_group_kind(&mut self, key: &K, is_translucent: bool) {
+        if let Some(group) = self.lookup_table.get_mut(key) {
+            group.0 = if is_translucent {
+                GroupKind::Translucent
+            } else {
+                GroupKind::Opaque
+            };
+        }
+    }
+
+    fn current_colors(&self, key: &K) -> Option<(Vector4, Vector4, MaterialParams)> {
+        let row = match self.hidden.get(key) {
+            Some(row) => Some(*row),
+            None => self
+                .lookup_table
+                .get(key)
+                .and_then(|group| self.map.get(group))
+                .and_then(|entity| entity.get().row(key)),
+        };
+        row.map(|(_, color, edge_color, material, _)| (color, edge_color, material))
+    }
+
+    fn is_override_op(&self, mutator: &DisplayListOps<K, G>) -> bool {
+        match mutator {
+            DisplayListOps::OverrideColor { .. }
+            | DisplayListOps::Ghost { .. }
+            | DisplayListOps::RestoreColor { .. }
+            | DisplayListOps::RestoreAllColors => true,
+            DisplayListOps::Update { key, .. }
+            | DisplayListOps::UpdateColor { key, .. }
+            | DisplayListOps::UpdateAlpha { key, .. } => self.overrides.contains_key(key),
+            _ => false,
+        }
+    }
+
+    // Color overrides are applied as raw color updates. Regular color updates of overridden
+    // instances change the colors to restore instead.
+    fn mutate_overrides(
+        &mut self,
+        mutator: DisplayListOps<K, G>,
+    ) -> GpuUpdateResult<DisplayListOps<K, G>> {
+        match mutator {
+            DisplayListOps::OverrideColor { key, color } => {
+                let Some(original) = self.current_colors(&key) else {
+                    return GpuUpdateResult::NotModified;
+                };
+                self.overrides.entry(key.clone()).or_insert(original);
+                GpuUpdateResult::AdditionalMutations {
+                    modified: false,
+                    mutations: vec![DisplayListOps::_UpdateRawColor {
+                        key,
+                        color: color.color.into(),
+                        edge_color: color.edge.into(),
+                        material: (&color).into(),
+                    }],
+                }
+            }
+            DisplayListOps::Ghost { key, alpha } => {
+                let Some(current) = self.current_colors(&key) else {
+                    return GpuUpdateResult::NotModified;
+                };
+                let (mut color, mut edge_color, material) =
+                    *self.overrides.entry(key.clone()).or_insert(current);
+                color.w = color.w.min(alpha);
+                edge_color.w = 0.0;
+                GpuUpdateResult::AdditionalMutations {
+                    modified: false,
+                    mutations: vec![DisplayListOps::_UpdateRawColor {
+                        key,
+                        color,
+                        edge_color,
+                        material,
+                    }],
+                }
+            }
+            DisplayListOps::RestoreColor { key } => {
+                let Some((color, edge_color, material)) = self.overrides.remove(&key) else {
+                    return GpuUpdateResult::NotModified;
+                };
+                GpuUpdateResult::AdditionalMutations {
+                    modified: false,
+                    mutations: vec![DisplayListOps::_UpdateRawColor {
+                        key,
+                        color,
+                        edge_color,
+                        material,
+                    }],
+                }
+            }
+            DisplayListOps::RestoreAllColors => GpuUpdateResult::AdditionalMutations {
+                modified: false,
+                mutations: self
+                    .overrides
+                    .keys()
+                    .map(|key| DisplayListOps::RestoreColor { key: key.clone() })
+                    .collect(),
+            },
+            DisplayListOps::Update { key, matrix, color } => {
+                self.overrides.insert(
+                    key.clone(),
+                    (color.color.into(), color.edge.into(), (&color).into()),
+                );
+                GpuUpdateResult::AdditionalMutations {
+                    modified: false,
+                    mutations: vec![DisplayListOps::UpdateMatrix { key, matrix }],
+                }
+            }
+            DisplayListOps::UpdateColor { key, color } => {
+                self.overrides.insert(
+                    key,
+                    (color.color.into(), color.edge.into(), (&color).into()),
+                );
+                GpuUpdateResult::NotModified
+            }
+            DisplayListOps::UpdateAlpha { key, alpha } => {
+                if let Some((color, edge_color, _)) = self.overrides.get_mut(&key) {
+                    color.w = alpha;
+                    edge_color.w = alpha;
+                }
+                GpuUpdateResult::NotModified
+            }
+            _ => GpuUpdateResult::NotModified,
+        }
+    }
+
+    // Updates of hidden instances only touch what is kept aside, and take effect when they
+    // are shown again.
+    fn mutate_hidden(
+        &mut self,
+        mutator: DisplayListOps<K, G>,
+    ) -> GpuUpdateResult<DisplayListOps<K, G>> {
+        match mutator {
+            DisplayListOps::SetVisible { key, visible: true } => {
+                let (Some((matrix, color, edge_color, material, occlusion)), Some(group)) = (
+                    self.hidden.remove(&key),
+                    self.lookup_table.get(&key).cloned(),
+                ) else {
+                    return GpuUpdateResult::NotModified;
+                };
+                self.get_or_create(group)
+                    .mutate(InstanceOps::Insert {
+                        key,
+                        matrix,
+                        color,
+                        edge_color,
+                        material,
+                        occlusion,
+                    })
+                    .into()
+            }
+            DisplayListOps::Update { key, matrix, color } => {
+                if let Some(row) = self.hidden.get_mut(&key) {
+                    row.0 = matrix;
+                    row.1 = color.color.into();
+                    row.2 = color.edge.into();
+                    row.3 = (&color).into();
+                }
+                self.set_group_kind(&key, color.is_translucent());
+                GpuUpdateResult::NotModified
+            }
+            DisplayListOps::UpdateMatrix { key, matrix } => {
+                if let Some(row) = self.hidden.get_mut(&key) {
+                    row.0 = matrix;
+                }
+                GpuUpdateResult::NotModified
+            }
+            DisplayListOps::UpdateColor { key, color } => {
+                if let Some(row) = self.hidden.get_mut(&key) {
+                    row.1 = color.color.into();
+                    row.2 = color.edge.into();
+                    row.3 = (&color).into();
+                }
+                self.set_group_kind(&key, color.is_translucent());
+                GpuUpdateResult::NotModified
+            }
+            DisplayListOps::UpdateAlpha { key, alpha } => {
+                if let Some(row) = self.hidden.get_mut(&key) {
+                    row.1.w = alpha;
+                    row.2.w = alpha;
+                }
+                self.set_group_kind(&key, alpha < 1.0);
+                GpuUpdateResult::NotModified
+            }
+            DisplayListOps::UpdateOcclusion { key, occlusion } => {
+                if let Some(row) = self.hidden.get_mut(&key) {
+                    row.4 = occlusion;
+                }
+                GpuUpdateResult::NotModified
+            }
+            DisplayListOps::_UpdateRawColor {
+                key,
+                color,
+                edge_color,
+                material,
+            } => {
+                if let Some(row) = self.hidden.get_mut(&key) {
+                    row.1 = color;
+                    row.2 = edge_color;
+                    row.3 = material;
+                }
+                self.set_group_kind(&key, color.w < 1.0);
+                GpuUpdateResult::NotModified
+            }
+            DisplayListOps::Remove { key } => {
+                self.hidden.remove(&key);
+                self.lookup_table.remove(&key);
+                GpuUpdateResult::NotModified
+            }
+            _ => GpuUpdateResult::NotModified,
+        }
+    }
 }
 
 impl<
@@ -564,6 +1068,19 @@ impl<
     type Mutator = DisplayListOps<K, G>;
 
     fn mutate(&mut self, mutator: Self::Mutator) -> GpuUpdateResult<Self::Mutator> {
+        if self.is_override_op(&mutator) {
+            return self.mutate_overrides(mutator);
+        }
+        if let DisplayListOps::Remove { key } = &mutator {
+            self.overrides.remove(key);
+        }
+        if mutator
+            .key()
+            .is_some_and(|key| self.hidden.contains_key(key))
+        {
+            return self.mutate_hidden(mutator);
+        }
+
         match mutator {
             DisplayListOps::Insert {
                 group,
@@ -602,6 +1119,8 @@ impl<
                             matrix,
                             color: main_color,
                             edge_color,
+                            material: (&color).into(),
+                            occlusion: 1.0,
                         })
                         .into()
                 }
@@ -615,6 +1134,7 @@ impl<
                         } else {
                             Group(GroupKind::Opaque, id)
                         };
+                        let occlusion = self.get_occlusion(&key);
 
                         GpuUpdateResult::AdditionalMutations {
                             modified: false,
@@ -625,6 +1145,8 @@ impl<
                                     matrix,
                                     color: color.color.into(),
                                     edge_color: color.edge.into(),
+                                    material: (&color).into(),
+                                    occlusion,
                                 },
                             )],
                         }
@@ -635,6 +1157,7 @@ impl<
                                 matrix,
                                 color: color.color.into(),
                                 edge_color: color.edge.into(),
+                                material: (&color).into(),
                             })
                             .into()
                     } else {
@@ -679,6 +1202,8 @@ impl<
                                     matrix: instance.get_matrix(),
                                     color,
                                     edge_color,
+                                    material: instance.get_material(),
+                                    occlusion: instance.get_occlusion(),
                                 },
                             )],
                         }
@@ -722,6 +1247,8 @@ impl<
                                     matrix: instance.get_matrix(),
                                     color: color.color.into(),
                                     edge_color: color.edge.into(),
+                                    material: (&color).into(),
+                                    occlusion: instance.get_occlusion(),
                                 },
                             )],
                         }
@@ -731,6 +1258,7 @@ impl<
                                 key,
                                 color: color.color.into(),
                                 edge_color: color.edge.into(),
+                                material: (&color).into(),
                             })
                             .into()
                     } else {
@@ -740,6 +1268,19 @@ impl<
                     GpuUpdateResult::NotModified
                 }
             }
+            DisplayListOps::UpdateOcclusion { key, occlusion } => {
+                let Some(group) = self.lookup_table.get(&key) else {
+                    return GpuUpdateResult::NotModified;
+                };
+
+                if let Some(entity) = self.map.get_mut(group) {
+                    entity
+                        .mutate(InstanceOps::UpdateOcclusion { key, occlusion })
+                        .into()
+                } else {
+                    GpuUpdateResult::NotModified
+                }
+            }
             DisplayListOps::UpdateMatrix { key, matrix } => {
                 let Some(group) = self.lookup_table.get(&key) else {
                     return GpuUpdateResult::NotModified;
@@ -772,12 +1313,111 @@ impl<
                     GpuUpdateResult::NotModified
                 }
             }
+            DisplayListOps::SetVisible { key, visible } => {
+                if visible {
+                    return GpuUpdateResult::NotModified;
+                }
+                let Some(group) = self.lookup_table.get(&key) else {
+                    return GpuUpdateResult::NotModified;
+                };
+                let Some(entity) = self.map.get_mut(group) else {
+                    return GpuUpdateResult::NotModified;
+                };
+                let Some(row) = entity.get().row(&key) else {
+                    return GpuUpdateResult::NotModified;
+                };
+
+                // The group is kept even if it runs empty, as the instance may come back.
+                entity.mutate(InstanceOps::Remove(key.clone()));
+                self.hidden.insert(key, row);
+                GpuUpdateResult::Modified
+            }
+            DisplayListOps::SetGroupVisible { group, visible } => {
+                GpuUpdateResult::AdditionalMutations {
+                    modified: false,
+                    mutations: self
+                        .lookup_table
+                        .iter()
+                        .filter(|(_, v)| v.1 == group)
+                        .map(|(key, _)| DisplayListOps::SetVisible {
+                            key: key.clone(),
+                            visible,
+                        })
+                        .collect(),
+                }
+            }
+            DisplayListOps::_UpdateRawColor {
+                key,
+                color,
+                edge_color,
+                material,
+            } => {
+                let is_translucent = color.w < 1.0;
+                let Some(group) = self.lookup_table.get(&key).cloned() else {
+                    return GpuUpdateResult::NotModified;
+                };
+                let Some(entity) = self.map.get_mut(&group) else {
+                    return GpuUpdateResult::NotModified;
+                };
+
+                if group.0.is_translucent() != is_translucent {
+                    let Some((matrix, _, _, _, occlusion)) = entity.get().row(&key) else {
+                        return GpuUpdateResult::NotModified;
+                    };
+                    let kind = if is_translucent {
+                        GroupKind::Translucent
+                    } else {
+                        GroupKind::Opaque
+                    };
+
+                    GpuUpdateResult::AdditionalMutations {
+                        modified: false,
+                        mutations: vec![DisplayListOps::_Reinstantiate(
+                            DisplayListOpsReinstantiate {
+                                group: Group(kind, group.1),
+                                key,
+                                matrix,
+                                color,
+                                edge_color,
+                                material,
+                                occlusion,
+                            },
+                        )],
+                    }
+                } else {
+                    entity
+                        .mutate(InstanceOps::UpdateColor {
+                            key,
+                            color,
+                            edge_color,
+                            material,
+                        })
+                        .into()
+                }
+            }
+            DisplayListOps::OverrideColor { .. }
+            | DisplayListOps::Ghost { .. }
+            | DisplayListOps::RestoreColor { .. }
+            | DisplayListOps::RestoreAllColors => GpuUpdateResult::NotModified,
+            DisplayListOps::SetAllVisible { visible } => GpuUpdateResult::AdditionalMutations {
+                modified: false,
+                mutations: self
+                    .lookup_table
+                    .keys()
+                    .map(|key| DisplayListOps::SetVisible {
+                        key: key.clone(),
+                        visible,
+                    })
+                    .collect(),
+            },
             DisplayListOps::_Reinstantiate(DisplayListOpsReinstantiate {
                 group,
                 key,
                 matrix,
                 color,
                 edge_color,
+                material,
+                occlusion,
             }) => {
                 let Some(prev_group) = self.lookup_table.remove(&key) else {
                     return GpuUpdateResult::NotModified;
@@ -796,6 +1436,8 @@ impl<
                         matrix,
                         color,
                         edge_color,
+                        material,
+                        occlusion,
                     })
                 {
                     self.lookup_table.insert(key.clone(), group);
@@ -952,7 +1594,12 @@ impl<G: Clone + Eq + PartialEq + Hash + From<PartAlias> + Display>
                             lookup_table,
                             cur_instance_id,
                             use_parent_object_id,
-                            object.id,
+                            // Flattened parts take the id of the object at the top.
+                            if use_parent_object_id && depth > 0 {
+                                parent_id
+                            } else {
+                                object.id
+                            },
                             groups,
                             &group.objects,
                             matrix * g.matrix,
@@ -0,0 +1,353 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+
+use cgmath::{InnerSpace, Vector3};
+use image::{DynamicImage, Rgb32FImage, RgbaImage};
+use ldraw::color::srgb_to_linear;
+
+use crate::error::EnvironmentLoadError;
+
+// Layout of the prefiltered environment atlas. These must be kept in sync
+// with the constants in shaders/cube_uv.wgsl.
+const CUBE_UV_MAX_MIP_LEVEL: i32 = 8;
+const CUBE_UV_MIN_MIP_LEVEL: i32 = 4;
+const CUBE_UV_MAX_TILE_SIZE: u32 = 256;
+const CUBE_UV_MIN_TILE_SIZE: u32 = 16;
+const CUBE_UV_MIN_FILTER_MIP: i32 = -2;
+
+const ATLAS_SIZE: u32 = 3 * CUBE_UV_MAX_TILE_SIZE;
+const PREFILTER_SAMPLES: usize = 24;
+
+#[derive(Default)]
+pub enum Environment {
+    #[default]
+    Default,
+    Equirectangular(Rgb32FImage),
+    // Faces are in +X, -X, +Y, -Y, +Z, -Z order.
+    Cubemap(Box<[Rgb32FImage; 6]>),
+    Prefiltered(RgbaImage),
+}
+
+impl Environment {
+    pub fn from_equirectangular_bytes(bytes: &[u8]) -> Result<Self, EnvironmentLoadError> {
+        Ok(Self::Equirectangular(to_linear(image::load_from_memory(
+            bytes,
+        )?)))
+    }
+
+    pub fn from_cubemap_bytes(faces: [&[u8]; 6]) -> Result<Self, EnvironmentLoadError> {
+        let mut images: Vec<Rgb32FImage> = Vec::with_capacity(6);
+        for face in faces {
+            let image = to_linear(image::load_from_memory(face)?);
+            if image.width() != image.height() {
+                return Err(EnvironmentLoadError::NonSquareCubemapFace);
+            }
+            if let Some(first) = images.first() {
+                if image.dimensions() != first.dimensions() {
+                    return Err(EnvironmentLoadError::CubemapFaceSizeMismatch);
+                }
+            }
+            images.push(image);
+        }
+
+        Ok(Self::Cubemap(Box::new(images.try_into().unwrap())))
+    }
+
+    pub fn prefilter(&self) -> RgbaImage {
+        match self {
+            Self::Default => image::load_from_memory_with_format(
+                include_bytes!("../assets/env_cubemap.png"),
+                image::ImageFormat::Png,
+            )
+            .unwrap()
+            .to_rgba8(),
+            Self::Equirectangular(image) => {
+                build_cube_uv_atlas(&EquirectangularPyramid::new(image.clone()))
+            }
+            Self::Cubemap(faces) => {
+                let width = (faces[0].width() * 4).clamp(64, 2048);
+                let mut image = Rgb32FImage::new(width, width / 2);
+                for (x, y, pixel) in image.enumerate_pixels_mut() {
+                    let direction = equirectangular_to_direction(
+                        (x as f32 + 0.5) / width as f32,
+                        (y as f32 + 0.5) / (width / 2) as f32,
+                    );
+                    pixel.0 = sample_cubemap(faces, direction);
+                }
+                build_cube_uv_atlas(&EquirectangularPyramid::new(image))
+            }
+            Self::Prefiltered(image) => image.clone(),
+        }
+    }
+}
+
+fn to_linear(image: DynamicImage) -> Rgb32FImage {
+    match image {
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => image.into_rgb32f(),
+        _ => {
+            let mut image = image.into_rgb32f();
+            for pixel in image.pixels_mut() {
+                for c in pixel.0.iter_mut() {
+                    *c = srgb_to_linear(*c);
+                }
+            }
+            image
+        }
+    }
+}
+
+fn direction_to_equirectangular(d: Vector3<f32>) -> (f32, f32) {
+    let u = d.z.atan2(d.x) / (2.0 * PI) + 0.5;
+    let v = d.y.clamp(-1.0, 1.0).acos() / PI;
+    (u, v)
+}
+
+fn equirectangular_to_direction(u: f32, v: f32) -> Vector3<f32> {
+    let phi = (u - 0.5) * 2.0 * PI;
+    let theta = v * PI;
+    Vector3::new(
+        theta.sin() * phi.cos(),
+        theta.cos(),
+        theta.sin() * phi.sin(),
+    )
+}
+
+fn sample_cubemap(faces: &[Rgb32FImage; 6], d: Vector3<f32>) -> [f32; 3] {
+    let a = Vector3::new(d.x.abs(), d.y.abs(), d.z.abs());
+    let (face, sc, tc, ma) = if a.x >= a.y && a.x >= a.z {
+        if d.x > 0.0 {
+            (0, -d.z, -d.y, a.x)
+        } else {
+            (1, d.z, -d.y, a.x)
+        }
+    } else if a.y >= a.z {
+        if d.y > 0.0 {
+            (2, d.x, d.z, a.y)
+        } else {
+            (3, d.x, -d.z, a.y)
+        }
+    } else if d.z > 0.0 {
+        (4, d.x, -d.y, a.z)
+    } else {
+        (5, -d.x, -d.y, a.z)
+    };
+
+    let image = &faces[face];
+    let size = image.width();
+    let x = (((sc / ma + 1.0) * 0.5 * size as f32) as u32).min(size - 1);
+    let y = (((tc / ma + 1.0) * 0.5 * size as f32) as u32).min(size - 1);
+
+    image.get_pixel(x, y).0
+}
+
+struct EquirectangularPyramid {
+    levels: Vec<Rgb32FImage>,
+}
+
+impl EquirectangularPyramid {
+    fn new(image: Rgb32FImage) -> Self {
+        let mut levels = vec![image];
+
+        loop {
+            let last = levels.last().unwrap();
+            if last.width() <= 8 || last.height() <= 4 {
+                break;
+            }
+
+            let (width, height) = (last.width() / 2, last.height() / 2);
+            let mut next = Rgb32FImage::new(width, height);
+            for (x, y, pixel) in next.enumerate_pixels_mut() {
+                let mut sum = [0.0; 3];
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let p = last.get_pixel(
+                        (x * 2 + dx).min(last.width() - 1),
+                        (y * 2 + dy).min(last.height() - 1),
+                    );
+                    for (s, v) in sum.iter_mut().zip(p.0) {
+                        *s += v * 0.25;
+                    }
+                }
+                pixel.0 = sum;
+            }
+            levels.push(next);
+        }
+
+        Self { levels }
+    }
+
+    fn level_for_angle(&self, angle: f32) -> usize {
+        let mut level = 0;
+        while level + 1 < self.levels.len()
+            && 2.0 * PI / self.levels[level + 1].width() as f32 <= angle
+        {
+            level += 1;
+        }
+        level
+    }
+
+    fn sample(&self, level: usize, d: Vector3<f32>) -> [f32; 3] {
+        let image = &self.levels[level];
+        let (width, height) = (image.width() as i64, image.height() as i64);
+        let (u, v) = direction_to_equirectangular(d);
+
+        let x = u * width as f32 - 0.5;
+        let y = (v * height as f32 - 0.5).clamp(0.0, (height - 1) as f32);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let fetch = |x: i64, y: i64| {
+            image
+                .get_pixel(x.rem_euclid(width) as u32, y.clamp(0, height - 1) as u32)
+                .0
+        };
+
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let tl = fetch(x0, y0);
+        let tr = fetch(x0 + 1, y0);
+        let bl = fetch(x0, y0 + 1);
+        let br = fetch(x0 + 1, y0 + 1);
+
+        let mut result = [0.0; 3];
+        for c in 0..3 {
+            let top = tl[c] + (tr[c] - tl[c]) * fx;
+            let bottom = bl[c] + (br[c] - bl[c]) * fx;
+            result[c] = top + (bottom - top) * fy;
+        }
+        result
+    }
+
+    fn sample_cone(&self, d: Vector3<f32>, angle: f32, texel_angle: f32) -> [f32; 3] {
+        if angle <= texel_angle {
+            return self.sample(self.level_for_angle(texel_angle), d);
+        }
+
+        let up = if d.y.abs() < 0.999 {
+            Vector3::unit_y()
+        } else {
+            Vector3::unit_x()
+        };
+        let tangent = up.cross(d).normalize();
+        let bitangent = d.cross(tangent);
+
+        let level = self.level_for_angle(angle / (PREFILTER_SAMPLES as f32).sqrt());
+        let golden_angle = PI * (3.0 - 5.0f32.sqrt());
+
+        let mut result = [0.0; 3];
+        let mut total_weight = 0.0;
+        for i in 0..PREFILTER_SAMPLES {
+            let phi = angle * ((i as f32 + 0.5) / PREFILTER_SAMPLES as f32).sqrt();
+            let psi = i as f32 * golden_angle;
+            let direction = (d * phi.cos()
+                + (tangent * psi.cos() + bitangent * psi.sin()) * phi.sin())
+            .normalize();
+            let weight = phi.cos();
+
+            let value = self.sample(level, direction);
+            for c in 0..3 {
+                result[c] += value[c] * weight;
+            }
+            total_weight += weight;
+        }
+
+        result.map(|v| v / total_weight)
+    }
+}
+
+// Inverse of roughnessToMip() in shaders/cube_uv.wgsl
+fn mip_to_roughness(mip: f32) -> f32 {
+    const TABLE: [(f32, f32); 5] = [
+        (1.0, -2.0),
+        (0.8, -1.0),
+        (0.4, 2.0),
+        (0.305, 3.0),
+        (0.21, 4.0),
+    ];
+
+    for pair in TABLE.windows(2) {
+        let ((r0, m0), (r1, m1)) = (pair[0], pair[1]);
+        if mip <= m1 {
+            return r0 - (mip - m0) * (r0 - r1) / (m1 - m0);
+        }
+    }
+
+    2.0f32.powf(-mip * 0.5) / 1.16
+}
+
+// Inverse of getUV() in shaders/cube_uv.wgsl
+fn face_direction(face: u32, s: f32, t: f32) -> Vector3<f32> {
+    match face {
+        0 => Vector3::new(1.0, t, s),
+        1 => Vector3::new(-s, 1.0, -t),
+        2 => Vector3::new(-s, t, 1.0),
+        3 => Vector3::new(-1.0, t, -s),
+        4 => Vector3::new(-s, -1.0, t),
+        _ => Vector3::new(s, t, -1.0),
+    }
+    .normalize()
+}
+
+fn encode_rgbe(color: [f32; 3]) -> [u8; 4] {
+    let max = color[0].max(color[1]).max(color[2]);
+    if max < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+
+    let exponent = max.log2().ceil().clamp(-128.0, 127.0);
+    let scale = 2.0f32.powf(-exponent);
+
+    [
+        (color[0] * scale * 255.0).round().clamp(0.0, 255.0) as u8,
+        (color[1] * scale * 255.0).round().clamp(0.0, 255.0) as u8,
+        (color[2] * scale * 255.0).round().clamp(0.0, 255.0) as u8,
+        (exponent + 128.0) as u8,
+    ]
+}
+
+fn build_cube_uv_atlas(source: &EquirectangularPyramid) -> RgbaImage {
+    let mut atlas = RgbaImage::new(ATLAS_SIZE, ATLAS_SIZE);
+
+    let mut levels = Vec::new();
+    for mip in CUBE_UV_MIN_MIP_LEVEL..=CUBE_UV_MAX_MIP_LEVEL {
+        levels.push((mip, 0));
+    }
+    for filter in 1..=(CUBE_UV_MIN_MIP_LEVEL - CUBE_UV_MIN_FILTER_MIP) {
+        levels.push((CUBE_UV_MIN_MIP_LEVEL - filter, filter));
+    }
+
+    for (mip, filter) in levels {
+        let mip_int = mip.max(CUBE_UV_MIN_MIP_LEVEL);
+        let face_size = 1u32 << mip_int;
+
+        let roughness = mip_to_roughness(mip as f32);
+        let alpha = roughness * roughness;
+        let angle = (alpha.atan() * 2.0).min(FRAC_PI_2);
+        let texel_angle = FRAC_PI_2 / face_size as f32;
+
+        let mut origin_y = filter as u32 * 2 * CUBE_UV_MIN_TILE_SIZE;
+        if mip_int < CUBE_UV_MAX_MIP_LEVEL {
+            origin_y += 2 * CUBE_UV_MAX_TILE_SIZE;
+        }
+        let origin_x = 3 * CUBE_UV_MAX_TILE_SIZE.saturating_sub(2 * face_size);
+
+        for face in 0..6 {
+            let tile_x = origin_x + (face % 3) * face_size;
+            let tile_y = origin_y + (face / 3) * face_size;
+
+            for j in 0..face_size {
+                for i in 0..face_size {
+                    let s = 2.0 * i as f32 / (face_size - 1) as f32 - 1.0;
+                    let t = 2.0 * j as f32 / (face_size - 1) as f32 - 1.0;
+
+                    let direction = face_direction(face, s, t);
+                    let color = source.sample_cone(direction, angle, texel_angle);
+
+                    let (x, y) = (tile_x + i, tile_y + j);
+                    if x < ATLAS_SIZE && y < ATLAS_SIZE {
+                        atlas.put_pixel(x, y, image::Rgba(encode_rgbe(color)));
+                    }
+                }
+            }
+        }
+    }
+
+    atlas
+}
@@ -5,3 +5,13 @@ pub enum ObjectSelectionError {
     #[error("Async buffer read error: {0}")]
     AsyncBufferReadError(#[from] wgpu::BufferAsyncError),
 }
+
+#[derive(thiserror::Error, Debug)]
+pub enum EnvironmentLoadError {
+    #[error("Could not decode image: {0}")]
+    ImageError(#[from] image::ImageError),
+    #[error("Cubemap faces must be square")]
+    NonSquareCubemapFace,
+    #[error("Cubemap faces must have the same size")]
+    CubemapFaceSizeMismatch,
+}
@@ -1,9 +1,12 @@
 pub mod display_list;
 mod entity;
+pub mod environment;
 pub mod error;
 pub mod part;
 pub mod pipeline;
+pub mod profiler;
 pub mod projection;
+pub mod scene;
 pub mod util;
 
 pub use entity::{Entity, GpuUpdate, GpuUpdateResult};
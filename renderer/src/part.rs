@@ -1,10 +1,13 @@
 use std::{collections::HashMap, ops::Range};
 
 use ldraw::{
-    color::{ColorCatalog, ColorReference},
+    color::{ColorCatalog, ColorReference, Rgba},
     Vector4,
 };
-use ldraw_ir::{geometry::BoundingBox3, part as part_ir};
+use ldraw_ir::{
+    geometry::BoundingBox3,
+    part::{self as part_ir, EdgeColor},
+};
 use wgpu::util::DeviceExt;
 
 pub struct MeshBuffer {
@@ -18,6 +21,7 @@ pub struct MeshBuffer {
     pub colored_opaque_without_bfc_range: Option<Range<u32>>,
     pub colored_translucent_range: Option<Range<u32>>,
     pub colored_translucent_without_bfc_range: Option<Range<u32>>,
+    pub bfc_error_range: Option<Range<u32>>,
     pub index_length: u32,
 }
 
@@ -226,6 +230,14 @@ impl MeshBuffer {
                 })
                 .collect(),
         );
+        let bfc_error_range = Self::expand(
+            &part.metadata,
+            &mut data,
+            &mut index,
+            &mut index_lut,
+            &part.geometry.vertex_buffer,
+            vec![(ColorReference::Current, &part.geometry.bfc_error_mesh)],
+        );
 
         let vertices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some(&format!(
@@ -277,6 +289,7 @@ impl MeshBuffer {
             colored_opaque_without_bfc_range,
             colored_translucent_range,
             colored_translucent_without_bfc_range,
+            bfc_error_range,
             index_format,
             index_length,
         }
@@ -307,6 +320,24 @@ impl MeshBuffer {
     }
 }
 
+// Negative values are replaced with the current color in shaders.
+fn edge_color(code: u32, colors: &ColorCatalog) -> [f32; 3] {
+    let rgb = |v: Rgba| {
+        [
+            v.red() as f32 / 255.0,
+            v.green() as f32 / 255.0,
+            v.blue() as f32 / 255.0,
+        ]
+    };
+
+    match EdgeColor::from_code(code) {
+        EdgeColor::Current => [-1.0, -1.0, -1.0],
+        EdgeColor::Complement => [-2.0, -2.0, -2.0],
+        EdgeColor::Main(code) => colors.get(&code).map_or([0.0; 3], |c| rgb(c.color)),
+        EdgeColor::Edge(code) => colors.get(&code).map_or([0.0; 3], |c| rgb(c.edge)),
+    }
+}
+
 #[derive(Eq, PartialEq, Hash)]
 struct EdgeVertexIndex {
     vertex: usize,
@@ -360,28 +391,7 @@ impl EdgeBuffer {
                 if let Some(idx) = index_table.get(&idx_key) {
                     index.push(*idx);
                 } else {
-                    let color = if *color_id == 2u32 << 30 {
-                        [-1.0, -1.0, -1.0]
-                    } else if *color_id == 2u32 << 29 {
-                        [-2.0, -2.0, -2.0]
-                    } else {
-                        match colors.get(&(color_id & 0x7fffffffu32)) {
-                            Some(color) => {
-                                let buf = if *color_id & 0x8000_0000 != 0 {
-                                    &color.edge
-                                } else {
-                                    &color.color
-                                };
-
-                                let r = buf.red() as f32 / 255.0;
-                                let g = buf.green() as f32 / 255.0;
-                                let b = buf.blue() as f32 / 255.0;
-
-                                [r, g, b]
-                            }
-                            None => [0.0, 0.0, 0.0],
-                        }
-                    };
+                    let color = edge_color(*color_id, colors);
 
                     let idx_val = index_table.len() as u32;
                     index_table.insert(idx_key, idx_val);
@@ -532,28 +542,7 @@ impl OptionalEdgeBuffer {
                     return None;
                 }
 
-                let color = if color_id == 2u32 << 30 {
-                    [-1.0, -1.0, -1.0]
-                } else if color_id == 2u32 << 29 {
-                    [-2.0, -2.0, -2.0]
-                } else {
-                    match colors.get(&(color_id & 0x7fffffffu32)) {
-                        Some(color) => {
-                            let buf = if color_id & 0x8000_0000 != 0 {
-                                &color.edge
-                            } else {
-                                &color.color
-                            };
-
-                            let r = buf.red() as f32 / 255.0;
-                            let g = buf.green() as f32 / 255.0;
-                            let b = buf.blue() as f32 / 255.0;
-
-                            [r, g, b]
-                        }
-                        None => [0.0, 0.0, 0.0],
-                    }
-                };
+                let color = edge_color(color_id, colors);
 
                 vertices.extend(&vertex_buffer.0[vertex_range]);
                 vertices.extend(&vertex_buffer.0[control_1_range]);
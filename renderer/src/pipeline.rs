@@ -1,22 +1,68 @@
-use std::{collections::HashSet, fmt::Display, hash::Hash, ops::Range};
+use std::{
+    collections::HashSet,
+    fmt::{self, Display},
+    hash::Hash,
+    ops::Range,
+};
 
 use cgmath::SquareMatrix;
-use image::GenericImageView;
-use ldraw::{color::Color, Matrix4, Vector3, Vector4};
+use ldraw::{color::Color, units::LDU_PER_STUD, Matrix4, Vector3, Vector4};
+use ldraw_ir::geometry::BoundingBox3;
 use wgpu::{util::DeviceExt, TextureViewDescriptor};
 
-use crate::display_list::InstanceOps;
+use crate::display_list::{InstanceOps, MaterialParams};
 
 use super::{
     display_list::{DisplayList, Instances, SelectionDisplayList, SelectionInstances},
+    environment::Environment,
     error,
     part::{EdgeBuffer, MeshBuffer, OptionalEdgeBuffer, Part, PartQuerier},
+    profiler::{GpuProfiler, GpuTimings, Timestamp},
     projection::Projection,
     Entity, ObjectSelection,
 };
 
 const DEFAULT_OBJECT_SELECTION_FRAMEBUFFER_SIZE: u32 = 1024;
 
+// Counters collected while rendering a frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    pub draw_calls: u32,
+    pub instances: u32,
+    pub triangles: u64,
+    // Draw calls issued for translucent geometry after everything opaque.
+    pub translucent_passes: u32,
+    // Instances skipped because geometry of their part is not loaded.
+    pub culled_instances: u32,
+    // Only available while GPU profiling is enabled, and usually from an earlier frame.
+    pub gpu_timings: Option<GpuTimings>,
+}
+
+impl RenderStats {
+    fn add_mesh(&mut self, range: &Range<u32>, instances: usize) {
+        self.draw_calls += 1;
+        self.triangles += (range.end - range.start) as u64 / 3 * instances as u64;
+    }
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} draw calls, {} instances, {} triangles, {} translucent passes, {} culled instances",
+            self.draw_calls,
+            self.instances,
+            self.triangles,
+            self.translucent_passes,
+            self.culled_instances
+        )?;
+        if let Some(timings) = &self.gpu_timings {
+            write!(f, ", {}", timings)?;
+        }
+        Ok(())
+    }
+}
+
 pub struct MaterialUniformData {
     diffuse: Vector3,
     emissive: Vector3,
@@ -93,35 +139,68 @@ impl ShadingUniforms {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Bind group for shading"),
+        let bind_group = Self::create_bind_group(
+            device,
+            "Bind group for shading",
+            &material_buffer,
+            &env_map_texture_view,
+            &env_map_sampler,
+        );
+
+        Self {
+            bind_group,
+
+            material_data,
+            material_buffer,
+            material_raw,
+
+            _env_map_texture_view: env_map_texture_view,
+            _env_map_sampler: env_map_sampler,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        label: &str,
+        buffer: &wgpu::Buffer,
+        env_map_texture_view: &wgpu::TextureView,
+        env_map_sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(label),
             layout: &device.create_bind_group_layout(&Self::desc()),
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: material_buffer.as_entire_binding(),
+                    resource: buffer.as_entire_binding(),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&env_map_texture_view),
+                    resource: wgpu::BindingResource::TextureView(env_map_texture_view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
-                    resource: wgpu::BindingResource::Sampler(&env_map_sampler),
+                    resource: wgpu::BindingResource::Sampler(env_map_sampler),
                 },
             ],
-        });
-
-        Self {
-            bind_group,
-
-            material_data,
-            material_buffer,
-            material_raw,
+        })
+    }
 
-            _env_map_texture_view: env_map_texture_view,
-            _env_map_sampler: env_map_sampler,
-        }
+    pub fn set_env_map(
+        &mut self,
+        device: &wgpu::Device,
+        env_map_texture_view: wgpu::TextureView,
+        env_map_sampler: wgpu::Sampler,
+    ) {
+        self.bind_group = Self::create_bind_group(
+            device,
+            "Bind group for shading",
+            &self.material_buffer,
+            &env_map_texture_view,
+            &env_map_sampler,
+        );
+        self._env_map_texture_view = env_map_texture_view;
+        self._env_map_sampler = env_map_sampler;
     }
 
     pub fn update_materials(&mut self, queue: &wgpu::Queue) {
@@ -169,71 +248,89 @@ impl ShadingUniforms {
     }
 }
 
+fn create_env_map_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    image: &image::RgbaImage,
+) -> wgpu::Texture {
+    let (width, height) = image.dimensions();
+
+    let size = wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Environment map"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            aspect: wgpu::TextureAspect::All,
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        image,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(4 * width),
+            rows_per_image: Some(height),
+        },
+        size,
+    );
+
+    texture
+}
+
+fn create_env_map_sampler(device: &wgpu::Device) -> wgpu::Sampler {
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        min_filter: wgpu::FilterMode::Linear,
+        mag_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Nearest,
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        ..Default::default()
+    })
+}
+
 pub struct DefaultMeshRenderingPipeline {
     pipeline: wgpu::RenderPipeline,
     pub shading_uniforms: ShadingUniforms,
 }
 
 impl DefaultMeshRenderingPipeline {
-    fn load_envmap(device: &wgpu::Device, queue: &wgpu::Queue) -> (wgpu::Texture, wgpu::Sampler) {
-        let image = image::load_from_memory_with_format(
-            include_bytes!("../assets/env_cubemap.png"),
-            image::ImageFormat::Png,
-        )
-        .unwrap();
-        let rgba = image.to_rgba8();
-        let (width, height) = image.dimensions();
-
-        let size = wgpu::Extent3d {
-            width,
-            height,
-            depth_or_array_layers: 1,
-        };
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Environment map"),
-            size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[],
-        });
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            min_filter: wgpu::FilterMode::Linear,
-            mag_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            ..Default::default()
-        });
-
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * width),
-                rows_per_image: Some(height),
-            },
-            size,
+    fn new(
+        device: &wgpu::Device,
+        env_map_texture: &wgpu::Texture,
+        texture_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let shading_uniforms = ShadingUniforms::new(
+            device,
+            env_map_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            create_env_map_sampler(device),
         );
 
-        (texture, sampler)
+        Self {
+            pipeline: Self::create_pipeline(device, texture_format, sample_count),
+            shading_uniforms,
+        }
     }
 
-    fn new(
+    fn create_pipeline(
         device: &wgpu::Device,
-        queue: &wgpu::Queue,
         texture_format: wgpu::TextureFormat,
         sample_count: u32,
-    ) -> Self {
+    ) -> wgpu::RenderPipeline {
         let vertex_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Vertex shader for default mesh"),
             source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/model_vertex.wgsl").into()),
@@ -241,17 +338,15 @@ impl DefaultMeshRenderingPipeline {
         let fragment_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Fragment shader for default mesh"),
             source: wgpu::ShaderSource::Wgsl(
-                include_str!("../shaders/model_fragment_base.wgsl").into(),
+                concat!(
+                    include_str!("../shaders/cube_uv.wgsl"),
+                    include_str!("../shaders/model_fragment_base.wgsl"),
+                )
+                .into(),
             ),
         });
 
         let projection_bind_group_layout = device.create_bind_group_layout(&Projection::desc());
-
-        let (env_map_texture, env_map_sampler) = Self::load_envmap(device, queue);
-        let env_map_texture_view =
-            env_map_texture.create_view(&wgpu::TextureViewDescriptor::default());
-
-        let shading_uniforms = ShadingUniforms::new(device, env_map_texture_view, env_map_sampler);
         let shading_bind_group_layout = device.create_bind_group_layout(&ShadingUniforms::desc());
 
         let render_pipeline_layout =
@@ -261,7 +356,7 @@ impl DefaultMeshRenderingPipeline {
                 push_constant_ranges: &[],
             });
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Render pipeline for default meshes"),
             layout: Some(&render_pipeline_layout),
             vertex: wgpu::VertexState {
@@ -306,12 +401,7 @@ impl DefaultMeshRenderingPipeline {
             },
             multiview: None,
             cache: None,
-        });
-
-        Self {
-            pipeline: render_pipeline,
-            shading_uniforms,
-        }
+        })
     }
 
     fn render<K, G>(
@@ -438,6 +528,155 @@ impl NoShadingMeshRenderingPipeline {
     }
 }
 
+// Renders front faces in green and back faces in red regardless of culling, with faces tagged
+// as BFC errors while baking in blue.
+pub struct BfcDebugMeshRenderingPipeline {
+    pipeline: wgpu::RenderPipeline,
+    error_pipeline: wgpu::RenderPipeline,
+}
+
+impl BfcDebugMeshRenderingPipeline {
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        vertex_shader: &wgpu::ShaderModule,
+        fragment_shader: &wgpu::ShaderModule,
+        entry_point: &str,
+        texture_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Render pipeline for BFC debug mesh"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: vertex_shader,
+                entry_point: Some("vs"),
+                buffers: &[MeshBuffer::desc(), Instances::<i32, i32>::desc()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: fragment_shader,
+                entry_point: Some(entry_point),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: texture_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        texture_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let vertex_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Vertex shader for BFC debug mesh"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/model_vertex.wgsl").into()),
+        });
+        let fragment_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Fragment shader for BFC debug mesh"),
+            source: wgpu::ShaderSource::Wgsl(
+                include_str!("../shaders/model_fragment_bfc_debug.wgsl").into(),
+            ),
+        });
+
+        let projection_bind_group_layout = device.create_bind_group_layout(&Projection::desc());
+
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render pipeline layout for BFC debug mesh"),
+                bind_group_layouts: &[&projection_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        Self {
+            pipeline: Self::create_pipeline(
+                device,
+                &render_pipeline_layout,
+                &vertex_shader,
+                &fragment_shader,
+                "fs",
+                texture_format,
+                sample_count,
+            ),
+            error_pipeline: Self::create_pipeline(
+                device,
+                &render_pipeline_layout,
+                &vertex_shader,
+                &fragment_shader,
+                "fs_error",
+                texture_format,
+                sample_count,
+            ),
+        }
+    }
+
+    fn render<K, G>(
+        &self,
+        pass: &mut wgpu::RenderPass<'static>,
+        projection: &Projection,
+        part: &Part,
+        instances: &Instances<K, G>,
+        stats: &mut RenderStats,
+    ) {
+        let Some(buffer) = &instances.instance_buffer else {
+            return;
+        };
+        let mesh = &part.mesh;
+        let ranges = [
+            &mesh.uncolored_range,
+            &mesh.uncolored_without_bfc_range,
+            &mesh.colored_opaque_range,
+            &mesh.colored_opaque_without_bfc_range,
+            &mesh.colored_translucent_range,
+            &mesh.colored_translucent_without_bfc_range,
+        ];
+
+        pass.set_vertex_buffer(0, mesh.vertices.slice(..));
+        pass.set_bind_group(0, &projection.bind_group, &[]);
+        pass.set_vertex_buffer(1, buffer.slice(..));
+        pass.set_index_buffer(mesh.indices.slice(..), mesh.index_format);
+
+        pass.set_pipeline(&self.pipeline);
+        for range in ranges.into_iter().flatten() {
+            pass.draw_indexed(range.clone(), 0, instances.range());
+            stats.add_mesh(range, instances.count());
+        }
+        // Drawn last so that tagged faces win the depth test against themselves.
+        if let Some(range) = &mesh.bfc_error_range {
+            pass.set_pipeline(&self.error_pipeline);
+            pass.draw_indexed(range.clone(), 0, instances.range());
+            stats.add_mesh(range, instances.count());
+        }
+    }
+}
+
 pub struct EdgeRenderingPipeline {
     pipeline: wgpu::RenderPipeline,
 }
@@ -651,50 +890,95 @@ impl OptionalEdgeRenderingPipeline {
     }
 }
 
-pub struct ObjectSelectionRenderingPipeline {
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct RawSkyboxUniformData {
+    blurriness: f32,
+    intensity: f32,
+    _padding: [u8; 8],
+}
+
+pub struct SkyboxRenderingPipeline {
     pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    uniform_buffer: wgpu::Buffer,
 
-    framebuffer_size: u32,
+    pub visible: bool,
+    pub blurriness: f32,
+    pub intensity: f32,
+}
 
-    framebuffer_texture: wgpu::Texture,
-    framebuffer_texture_view: wgpu::TextureView,
+impl SkyboxRenderingPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        env_map_texture: &wgpu::Texture,
+        texture_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let blurriness = 0.0;
+        let intensity = 1.0;
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Uniform buffer for skybox"),
+            contents: bytemuck::cast_slice(&[RawSkyboxUniformData {
+                blurriness,
+                intensity,
+                _padding: [0; 8],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = Self::create_bind_group(device, &uniform_buffer, env_map_texture);
 
-    _depth_texture: wgpu::Texture,
-    depth_texture_view: wgpu::TextureView,
+        Self {
+            pipeline: Self::create_pipeline(device, texture_format, sample_count),
+            bind_group,
+            uniform_buffer,
 
-    output_buffer: wgpu::Buffer,
-}
+            visible: false,
+            blurriness,
+            intensity,
+        }
+    }
 
-impl ObjectSelectionRenderingPipeline {
-    pub fn new(device: &wgpu::Device, framebuffer_size: u32) -> Self {
+    fn create_pipeline(
+        device: &wgpu::Device,
+        texture_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Vertex shader for object selection"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/selection.wgsl").into()),
+            label: Some("Shader for skybox"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(
+                    include_str!("../shaders/cube_uv.wgsl"),
+                    include_str!("../shaders/skybox.wgsl"),
+                )
+                .into(),
+            ),
         });
 
         let projection_bind_group_layout = device.create_bind_group_layout(&Projection::desc());
+        let skybox_bind_group_layout = device.create_bind_group_layout(&ShadingUniforms::desc());
 
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render pipeline layout for object selection"),
-                bind_group_layouts: &[&projection_bind_group_layout],
+                label: Some("Render pipeline layout for skybox"),
+                bind_group_layouts: &[&projection_bind_group_layout, &skybox_bind_group_layout],
                 push_constant_ranges: &[],
             });
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render pipeline for object selection"),
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Render pipeline for skybox"),
             layout: Some(&render_pipeline_layout),
             vertex: wgpu::VertexState {
                 module: &shader,
                 entry_point: Some("vs"),
-                buffers: &[MeshBuffer::desc(), SelectionInstances::desc()],
+                buffers: &[],
                 compilation_options: Default::default(),
             },
             fragment: Some(wgpu::FragmentState {
                 module: &shader,
                 entry_point: Some("fs"),
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: wgpu::TextureFormat::R32Uint,
+                    format: texture_format,
                     blend: None,
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
@@ -711,49 +995,704 @@ impl ObjectSelectionRenderingPipeline {
             },
             depth_stencil: Some(wgpu::DepthStencilState {
                 format: wgpu::TextureFormat::Depth32Float,
-                depth_write_enabled: true,
+                depth_write_enabled: false,
                 depth_compare: wgpu::CompareFunction::LessEqual,
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
             }),
             multisample: wgpu::MultisampleState {
-                count: 1,
+                count: sample_count,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
             multiview: None,
             cache: None,
-        });
+        })
+    }
 
-        let framebuffer_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Framebuffer texture for object selection"),
-            format: wgpu::TextureFormat::R32Uint,
-            dimension: wgpu::TextureDimension::D2,
-            size: wgpu::Extent3d {
-                width: framebuffer_size,
-                height: framebuffer_size,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            usage: wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::RENDER_ATTACHMENT,
-            view_formats: &[],
-        });
+    fn create_bind_group(
+        device: &wgpu::Device,
+        uniform_buffer: &wgpu::Buffer,
+        env_map_texture: &wgpu::Texture,
+    ) -> wgpu::BindGroup {
+        ShadingUniforms::create_bind_group(
+            device,
+            "Bind group for skybox",
+            uniform_buffer,
+            &env_map_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            &create_env_map_sampler(device),
+        )
+    }
 
-        let framebuffer_texture_view =
-            framebuffer_texture.create_view(&TextureViewDescriptor::default());
+    pub fn set_env_map(&mut self, device: &wgpu::Device, env_map_texture: &wgpu::Texture) {
+        self.bind_group = Self::create_bind_group(device, &self.uniform_buffer, env_map_texture);
+    }
 
-        let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Depth texture for object selection"),
-            format: wgpu::TextureFormat::Depth32Float,
-            dimension: wgpu::TextureDimension::D2,
-            size: wgpu::Extent3d {
-                width: framebuffer_size,
-                height: framebuffer_size,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
+    pub fn update_uniforms(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0 as wgpu::BufferAddress,
+            bytemuck::cast_slice(&[RawSkyboxUniformData {
+                blurriness: self.blurriness,
+                intensity: self.intensity,
+                _padding: [0; 8],
+            }]),
+        );
+    }
+
+    fn render(&self, pass: &mut wgpu::RenderPass<'static>, projection: &Projection) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &projection.bind_group, &[]);
+        pass.set_bind_group(1, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct RawGroundUniformData {
+    center: [f32; 3],
+    size: f32,
+    footprint: [f32; 2],
+    grid_spacing: f32,
+    shadow_blur: f32,
+    grid_color: [f32; 4],
+    shadow_opacity: f32,
+    show_grid: i32,
+    _padding: [u8; 8],
+}
+
+// Draws a grid on the floor below the model with a soft shadow under its footprint.
+pub struct GroundRenderingPipeline {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    uniform_buffer: wgpu::Buffer,
+    bounding_box: BoundingBox3,
+
+    pub visible: bool,
+    pub show_grid: bool,
+    pub grid_color: Vector4,
+    pub grid_spacing: f32,
+    pub shadow_opacity: f32,
+}
+
+impl GroundRenderingPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        texture_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Uniform buffer for ground"),
+            size: std::mem::size_of::<RawGroundUniformData>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Bind group for ground"),
+            layout: &device.create_bind_group_layout(&Self::desc()),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        Self {
+            pipeline: Self::create_pipeline(device, texture_format, sample_count),
+            bind_group,
+            uniform_buffer,
+            bounding_box: BoundingBox3::nil(),
+
+            visible: false,
+            show_grid: true,
+            grid_color: Vector4::new(0.5, 0.5, 0.5, 0.5),
+            grid_spacing: LDU_PER_STUD,
+            shadow_opacity: 0.5,
+        }
+    }
+
+    fn desc() -> wgpu::BindGroupLayoutDescriptor<'static> {
+        wgpu::BindGroupLayoutDescriptor {
+            label: Some("Bind group layout for ground"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        texture_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shader for ground"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/ground.wgsl").into()),
+        });
+
+        let projection_bind_group_layout = device.create_bind_group_layout(&Projection::desc());
+        let ground_bind_group_layout = device.create_bind_group_layout(&Self::desc());
+
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render pipeline layout for ground"),
+                bind_group_layouts: &[&projection_bind_group_layout, &ground_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Render pipeline for ground"),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: texture_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    // Places the ground right below given bounding box.
+    pub fn set_bounds(&mut self, bounding_box: &BoundingBox3) {
+        self.bounding_box = bounding_box.clone();
+    }
+
+    pub fn update_uniforms(&self, queue: &wgpu::Queue) {
+        let bb = &self.bounding_box;
+        let (center, footprint) = if bb.is_null() {
+            (Vector3::new(0.0, 0.0, 0.0), [0.0, 0.0])
+        } else {
+            let center = bb.center();
+            (
+                // Y axis points downwards, so the bottom of the model is at its maximum.
+                // A slight gap keeps the floor from fighting with bottom faces of parts.
+                Vector3::new(center.x, bb.max.y + 0.5, center.z),
+                [bb.len_x() * 0.5, bb.len_z() * 0.5],
+            )
+        };
+        let extent = footprint[0].max(footprint[1]).max(LDU_PER_STUD);
+
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0 as wgpu::BufferAddress,
+            bytemuck::cast_slice(&[RawGroundUniformData {
+                center: center.into(),
+                size: extent * 3.0,
+                footprint,
+                grid_spacing: self.grid_spacing,
+                shadow_blur: extent * 0.5,
+                grid_color: self.grid_color.into(),
+                shadow_opacity: self.shadow_opacity,
+                show_grid: if self.show_grid { 1 } else { 0 },
+                _padding: [0; 8],
+            }]),
+        );
+    }
+
+    fn render(&self, pass: &mut wgpu::RenderPass<'static>, projection: &Projection) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &projection.bind_group, &[]);
+        pass.set_bind_group(1, &self.bind_group, &[]);
+        pass.draw(0..6, 0..1);
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct RawOutlineUniformData {
+    color: [f32; 4],
+    thickness: i32,
+    _padding: [u8; 12],
+}
+
+struct OutlineMask {
+    _texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+    size: (u32, u32),
+}
+
+// Draws a silhouette around instances of a display list regardless of what is in front of
+// them. Instances are drawn into a mask first, which is then traced over the framebuffer.
+pub struct OutlineRenderingPipeline {
+    mask_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    composite_bind_group_layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    mask: Option<OutlineMask>,
+
+    pub color: Vector4,
+    // In pixels.
+    pub thickness: u32,
+}
+
+impl OutlineRenderingPipeline {
+    const MASK_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+    pub fn new(
+        device: &wgpu::Device,
+        texture_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let vertex_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Vertex shader for outline mask"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/model_vertex.wgsl").into()),
+        });
+        let fragment_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Fragment shader for outline mask"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/outline_mask.wgsl").into()),
+        });
+
+        let projection_bind_group_layout = device.create_bind_group_layout(&Projection::desc());
+
+        let mask_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Render pipeline layout for outline mask"),
+            bind_group_layouts: &[&projection_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let mask_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Render pipeline for outline mask"),
+            layout: Some(&mask_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &vertex_shader,
+                entry_point: Some("vs"),
+                buffers: &[MeshBuffer::desc(), Instances::<i32, i32>::desc()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &fragment_shader,
+                entry_point: Some("fs"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: Self::MASK_FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let composite_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Bind group layout for outline"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let color = Vector4::new(1.0, 0.75, 0.0, 1.0);
+        let thickness = 2;
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Uniform buffer for outline"),
+            contents: bytemuck::cast_slice(&[Self::raw_uniforms(&color, thickness)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        Self {
+            mask_pipeline,
+            composite_pipeline: Self::create_composite_pipeline(
+                device,
+                &composite_bind_group_layout,
+                texture_format,
+                sample_count,
+            ),
+            composite_bind_group_layout,
+            uniform_buffer,
+            mask: None,
+
+            color,
+            thickness,
+        }
+    }
+
+    fn raw_uniforms(color: &Vector4, thickness: u32) -> RawOutlineUniformData {
+        RawOutlineUniformData {
+            color: (*color).into(),
+            thickness: thickness as i32,
+            _padding: [0; 12],
+        }
+    }
+
+    fn create_composite_pipeline(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        texture_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shader for outline"),
+            source: wgpu::ShaderSource::Wgsl(
+                include_str!("../shaders/outline_composite.wgsl").into(),
+            ),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Render pipeline layout for outline"),
+            bind_group_layouts: &[bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Render pipeline for outline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: texture_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        texture_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        self.composite_pipeline = Self::create_composite_pipeline(
+            device,
+            &self.composite_bind_group_layout,
+            texture_format,
+            sample_count,
+        );
+    }
+
+    fn prepare_mask(&mut self, device: &wgpu::Device, size: (u32, u32)) {
+        if self.mask.as_ref().map(|v| v.size) != Some(size) {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("Outline mask"),
+                size: wgpu::Extent3d {
+                    width: size.0.max(1),
+                    height: size.1.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: Self::MASK_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Bind group for outline"),
+                layout: &self.composite_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: self.uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&view),
+                    },
+                ],
+            });
+            self.mask = Some(OutlineMask {
+                _texture: texture,
+                view,
+                bind_group,
+                size,
+            });
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn render<K, G>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        projection: &Projection,
+        part_querier: &impl PartQuerier<G>,
+        display_list: &DisplayList<K, G>,
+        target: &wgpu::TextureView,
+        resolve_target: Option<&wgpu::TextureView>,
+        size: (u32, u32),
+    ) {
+        if display_list
+            .iter()
+            .all(|(_, _, instances)| instances.is_empty())
+        {
+            return;
+        }
+
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[Self::raw_uniforms(&self.color, self.thickness)]),
+        );
+        self.prepare_mask(device, size);
+        let mask = self.mask.as_ref().unwrap();
+
+        {
+            let mut pass = encoder
+                .begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Render pass for outline mask"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &mask.view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    occlusion_query_set: None,
+                    timestamp_writes: None,
+                })
+                .forget_lifetime();
+
+            pass.set_pipeline(&self.mask_pipeline);
+            pass.set_bind_group(0, &projection.bind_group, &[]);
+            for (group, _, instances) in display_list.iter() {
+                let (Some(part), Some(buffer)) =
+                    (part_querier.get(group), &instances.instance_buffer)
+                else {
+                    continue;
+                };
+                if instances.is_empty() {
+                    continue;
+                }
+
+                let mesh = &part.mesh;
+                pass.set_vertex_buffer(0, mesh.vertices.slice(..));
+                pass.set_vertex_buffer(1, buffer.slice(..));
+                pass.set_index_buffer(mesh.indices.slice(..), mesh.index_format);
+                for range in [
+                    &mesh.uncolored_range,
+                    &mesh.uncolored_without_bfc_range,
+                    &mesh.colored_opaque_range,
+                    &mesh.colored_opaque_without_bfc_range,
+                    &mesh.colored_translucent_range,
+                    &mesh.colored_translucent_without_bfc_range,
+                ]
+                .into_iter()
+                .flatten()
+                {
+                    pass.draw_indexed(range.clone(), 0, instances.range());
+                }
+            }
+        }
+
+        let mut pass = encoder
+            .begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Render pass for outline"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            })
+            .forget_lifetime();
+        pass.set_pipeline(&self.composite_pipeline);
+        pass.set_bind_group(0, &mask.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+pub struct ObjectSelectionRenderingPipeline {
+    pipeline: wgpu::RenderPipeline,
+
+    framebuffer_size: u32,
+
+    framebuffer_texture: wgpu::Texture,
+    framebuffer_texture_view: wgpu::TextureView,
+
+    _depth_texture: wgpu::Texture,
+    depth_texture_view: wgpu::TextureView,
+
+    output_buffer: wgpu::Buffer,
+}
+
+impl ObjectSelectionRenderingPipeline {
+    pub fn new(device: &wgpu::Device, framebuffer_size: u32) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Vertex shader for object selection"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/selection.wgsl").into()),
+        });
+
+        let projection_bind_group_layout = device.create_bind_group_layout(&Projection::desc());
+
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render pipeline layout for object selection"),
+                bind_group_layouts: &[&projection_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Render pipeline for object selection"),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs"),
+                buffers: &[MeshBuffer::desc(), SelectionInstances::desc()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: wgpu::TextureFormat::R32Uint,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        });
+
+        let framebuffer_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Framebuffer texture for object selection"),
+            format: wgpu::TextureFormat::R32Uint,
+            dimension: wgpu::TextureDimension::D2,
+            size: wgpu::Extent3d {
+                width: framebuffer_size,
+                height: framebuffer_size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+
+        let framebuffer_texture_view =
+            framebuffer_texture.create_view(&TextureViewDescriptor::default());
+
+        let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Depth texture for object selection"),
+            format: wgpu::TextureFormat::Depth32Float,
+            dimension: wgpu::TextureDimension::D2,
+            size: wgpu::Extent3d {
+                width: framebuffer_size,
+                height: framebuffer_size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
             usage: wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::RENDER_ATTACHMENT,
             view_formats: &[],
         });
@@ -880,12 +1819,24 @@ impl<'ctx, G, K: Clone + Eq + PartialEq + Hash> ObjectSelectionTestOp
 
 pub struct RenderingPipelineManager {
     mesh_default: DefaultMeshRenderingPipeline,
+    pub skybox: SkyboxRenderingPipeline,
+    pub ground: GroundRenderingPipeline,
     mesh_no_shading: NoShadingMeshRenderingPipeline,
+    mesh_bfc_debug: BfcDebugMeshRenderingPipeline,
     edge: EdgeRenderingPipeline,
     optional_edge: OptionalEdgeRenderingPipeline,
+    pub outline: OutlineRenderingPipeline,
     object_selection: ObjectSelectionRenderingPipeline,
 
     single_part_instance_buffer: Entity<Instances<i32, i32>>,
+
+    // Renders meshes by facing instead of color to reveal BFC errors.
+    pub bfc_debug: bool,
+
+    profiler: Option<GpuProfiler>,
+
+    texture_format: wgpu::TextureFormat,
+    sample_count: u32,
 }
 
 impl RenderingPipelineManager {
@@ -901,31 +1852,133 @@ impl RenderingPipelineManager {
             matrix: Matrix4::identity(),
             color: Vector4::new(0.0, 0.0, 0.0, 0.0),
             edge_color: Vector4::new(0.0, 0.0, 0.0, 0.0),
+            material: MaterialParams::default(),
+            occlusion: 1.0,
         });
 
+        let env_map_texture =
+            create_env_map_texture(device, queue, &Environment::Default.prefilter());
+
         Self {
             mesh_default: DefaultMeshRenderingPipeline::new(
                 device,
-                queue,
+                &env_map_texture,
                 render_texture_format,
                 sample_count,
             ),
+            skybox: SkyboxRenderingPipeline::new(
+                device,
+                &env_map_texture,
+                render_texture_format,
+                sample_count,
+            ),
+            ground: GroundRenderingPipeline::new(device, render_texture_format, sample_count),
             mesh_no_shading: NoShadingMeshRenderingPipeline::new(
                 device,
                 render_texture_format,
                 sample_count,
             ),
+            mesh_bfc_debug: BfcDebugMeshRenderingPipeline::new(
+                device,
+                render_texture_format,
+                sample_count,
+            ),
             edge: EdgeRenderingPipeline::new(device, render_texture_format, sample_count),
             optional_edge: OptionalEdgeRenderingPipeline::new(
                 device,
                 render_texture_format,
                 sample_count,
             ),
+            outline: OutlineRenderingPipeline::new(device, render_texture_format, sample_count),
             object_selection: ObjectSelectionRenderingPipeline::new(
                 device,
                 DEFAULT_OBJECT_SELECTION_FRAMEBUFFER_SIZE,
             ),
             single_part_instance_buffer,
+            bfc_debug: false,
+            profiler: None,
+
+            texture_format: render_texture_format,
+            sample_count,
+        }
+    }
+
+    pub fn set_environment(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        environment: &Environment,
+    ) {
+        let env_map_texture = create_env_map_texture(device, queue, &environment.prefilter());
+
+        self.mesh_default.shading_uniforms.set_env_map(
+            device,
+            env_map_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            create_env_map_sampler(device),
+        );
+        self.skybox.set_env_map(device, &env_map_texture);
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    // Recreates every pipeline that draws into the multisampled framebuffer. Uniforms and
+    // environment maps are kept as they are.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        if self.sample_count == sample_count {
+            return;
+        }
+
+        let format = self.texture_format;
+        self.mesh_default.pipeline =
+            DefaultMeshRenderingPipeline::create_pipeline(device, format, sample_count);
+        self.skybox.pipeline =
+            SkyboxRenderingPipeline::create_pipeline(device, format, sample_count);
+        self.ground.pipeline =
+            GroundRenderingPipeline::create_pipeline(device, format, sample_count);
+        self.mesh_no_shading = NoShadingMeshRenderingPipeline::new(device, format, sample_count);
+        self.mesh_bfc_debug = BfcDebugMeshRenderingPipeline::new(device, format, sample_count);
+        self.edge = EdgeRenderingPipeline::new(device, format, sample_count);
+        self.optional_edge = OptionalEdgeRenderingPipeline::new(device, format, sample_count);
+        self.outline.set_sample_count(device, format, sample_count);
+        self.sample_count = sample_count;
+    }
+
+    // Returns false if the device does not support timestamp queries.
+    pub fn enable_gpu_profiling(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        if self.profiler.is_none() {
+            self.profiler = GpuProfiler::new(device, queue);
+        }
+        self.profiler.is_some()
+    }
+
+    pub fn disable_gpu_profiling(&mut self) {
+        self.profiler = None;
+    }
+
+    pub fn is_gpu_profiling(&self) -> bool {
+        self.profiler.is_some()
+    }
+
+    // Copies timestamps written by render() out of the query set. Must be called after the
+    // render pass has ended.
+    pub fn resolve_gpu_timings(&self, encoder: &mut wgpu::CommandEncoder) {
+        if let Some(profiler) = &self.profiler {
+            profiler.resolve(encoder);
+        }
+    }
+
+    // Reads back the latest timings once the frame has been submitted.
+    pub fn collect_gpu_timings(&mut self, device: &wgpu::Device, wait: bool) -> Option<GpuTimings> {
+        self.profiler
+            .as_mut()
+            .and_then(|profiler| profiler.collect(device, wait))
+    }
+
+    fn write_timestamp(&self, pass: &mut wgpu::RenderPass<'static>, timestamp: Timestamp) {
+        if let Some(profiler) = &self.profiler {
+            profiler.write(pass, timestamp);
         }
     }
 
@@ -946,6 +1999,7 @@ impl RenderingPipelineManager {
                 matrix,
                 color: color.color.into(),
                 edge_color: color.edge.into(),
+                material: color.into(),
             });
         self.single_part_instance_buffer.update(device, queue);
 
@@ -1022,96 +2076,237 @@ impl RenderingPipelineManager {
         projection: &Projection,
         part_querier: &impl PartQuerier<G>,
         display_list: &DisplayList<K, G>,
-    ) -> u32 {
-        let mut draws = 0;
+    ) -> RenderStats {
+        self.render_layers(
+            pass,
+            projection,
+            part_querier,
+            &[(projection, display_list)],
+        )
+    }
 
-        // Render opaque items first
-        for (group, is_translucent, instances) in display_list.iter() {
-            if instances.is_empty() {
-                continue;
+    // Renders several display lists in one pass, each through its own projection so that they
+    // can be placed independently. Opaque items of every layer go before translucent ones.
+    // Skybox and ground are drawn through the given camera projection.
+    pub fn render_layers<K: Clone + Eq + PartialEq + Hash, G: Display>(
+        &self,
+        pass: &mut wgpu::RenderPass<'static>,
+        projection: &Projection,
+        part_querier: &impl PartQuerier<G>,
+        layers: &[(&Projection, &DisplayList<K, G>)],
+    ) -> RenderStats {
+        let mut stats = RenderStats::default();
+
+        self.write_timestamp(pass, Timestamp::Start);
+        if self.skybox.visible {
+            self.skybox.render(pass, projection);
+            stats.draw_calls += 1;
+        }
+        self.write_timestamp(pass, Timestamp::Skybox);
+
+        for (group, _, instances) in layers.iter().flat_map(|(_, v)| v.iter()) {
+            if part_querier.get(group).is_some() {
+                stats.instances += instances.count() as u32;
+            } else {
+                stats.culled_instances += instances.count() as u32;
             }
+        }
 
-            if let Some(part) = part_querier.get(group) {
-                if let Some(range) = &part.mesh.colored_opaque_range {
-                    self.mesh_default
-                        .render(pass, projection, part, instances, range.clone());
-                    draws += 1;
+        if self.bfc_debug {
+            for (layer_projection, display_list) in layers {
+                for (group, _, instances) in display_list.iter() {
+                    if instances.is_empty() {
+                        continue;
+                    }
+
+                    if let Some(part) = part_querier.get(group) {
+                        self.mesh_bfc_debug.render(
+                            pass,
+                            layer_projection,
+                            part,
+                            instances,
+                            &mut stats,
+                        );
+                        if self.edge.render(pass, layer_projection, part, instances) {
+                            stats.draw_calls += 1;
+                        }
+                    }
                 }
-                if let Some(range) = &part.mesh.colored_opaque_without_bfc_range {
-                    self.mesh_no_shading
-                        .render(pass, projection, part, instances, range.clone());
-                    draws += 1;
+            }
+            self.write_timestamp(pass, Timestamp::Opaque);
+            self.write_timestamp(pass, Timestamp::Translucent);
+
+            return stats;
+        }
+
+        // Render opaque items first
+        for (layer_projection, display_list) in layers {
+            for (group, is_translucent, instances) in display_list.iter() {
+                if instances.is_empty() {
+                    continue;
                 }
-                if !is_translucent {
-                    if let Some(range) = &part.mesh.uncolored_range {
-                        self.mesh_default
-                            .render(pass, projection, part, instances, range.clone());
-                        draws += 1;
+
+                if let Some(part) = part_querier.get(group) {
+                    let count = instances.count();
+                    if let Some(range) = &part.mesh.colored_opaque_range {
+                        self.mesh_default.render(
+                            pass,
+                            layer_projection,
+                            part,
+                            instances,
+                            range.clone(),
+                        );
+                        stats.add_mesh(range, count);
                     }
-                    if let Some(range) = &part.mesh.uncolored_without_bfc_range {
+                    if let Some(range) = &part.mesh.colored_opaque_without_bfc_range {
                         self.mesh_no_shading.render(
                             pass,
-                            projection,
+                            layer_projection,
                             part,
                             instances,
                             range.clone(),
                         );
-                        draws += 1;
-                    }
-                    if self.edge.render(pass, projection, part, instances) {
-                        draws += 1;
+                        stats.add_mesh(range, count);
                     }
+                    if !is_translucent {
+                        if let Some(range) = &part.mesh.uncolored_range {
+                            self.mesh_default.render(
+                                pass,
+                                layer_projection,
+                                part,
+                                instances,
+                                range.clone(),
+                            );
+                            stats.add_mesh(range, count);
+                        }
+                        if let Some(range) = &part.mesh.uncolored_without_bfc_range {
+                            self.mesh_no_shading.render(
+                                pass,
+                                layer_projection,
+                                part,
+                                instances,
+                                range.clone(),
+                            );
+                            stats.add_mesh(range, count);
+                        }
+                        if self.edge.render(pass, layer_projection, part, instances) {
+                            stats.draw_calls += 1;
+                        }
 
-                    if self.optional_edge.render(pass, projection, part, instances) {
-                        draws += 1;
+                        if self
+                            .optional_edge
+                            .render(pass, layer_projection, part, instances)
+                        {
+                            stats.draw_calls += 1;
+                        }
                     }
                 }
             }
         }
-        // Then translucent items
-        for (group, is_translucent, instances) in display_list.iter() {
-            if instances.is_empty() {
-                continue;
-            }
+        // Ground goes after opaque items so that they hide it, but before translucent ones
+        // which would otherwise not show it through.
+        if self.ground.visible {
+            self.ground.render(pass, projection);
+            stats.draw_calls += 1;
+        }
+        self.write_timestamp(pass, Timestamp::Opaque);
 
-            if let Some(part) = part_querier.get(group) {
-                if let Some(range) = &part.mesh.colored_translucent_range {
-                    self.mesh_default
-                        .render(pass, projection, part, instances, range.clone());
-                    draws += 1;
-                }
-                if let Some(range) = &part.mesh.colored_translucent_without_bfc_range {
-                    self.mesh_no_shading
-                        .render(pass, projection, part, instances, range.clone());
-                    draws += 1;
+        // Then translucent items
+        let opaque_draw_calls = stats.draw_calls;
+        for (layer_projection, display_list) in layers {
+            for (group, is_translucent, instances) in display_list.iter() {
+                if instances.is_empty() {
+                    continue;
                 }
-                if is_translucent {
-                    if let Some(range) = &part.mesh.uncolored_range {
-                        self.mesh_default
-                            .render(pass, projection, part, instances, range.clone());
-                        draws += 1;
+
+                if let Some(part) = part_querier.get(group) {
+                    let count = instances.count();
+                    if let Some(range) = &part.mesh.colored_translucent_range {
+                        self.mesh_default.render(
+                            pass,
+                            layer_projection,
+                            part,
+                            instances,
+                            range.clone(),
+                        );
+                        stats.add_mesh(range, count);
                     }
-                    if let Some(range) = &part.mesh.uncolored_without_bfc_range {
+                    if let Some(range) = &part.mesh.colored_translucent_without_bfc_range {
                         self.mesh_no_shading.render(
                             pass,
-                            projection,
+                            layer_projection,
                             part,
                             instances,
                             range.clone(),
                         );
-                        draws += 1;
-                    }
-                    if self.edge.render(pass, projection, part, instances) {
-                        draws += 1;
+                        stats.add_mesh(range, count);
                     }
-                    if self.optional_edge.render(pass, projection, part, instances) {
-                        draws += 1;
+                    if is_translucent {
+                        if let Some(range) = &part.mesh.uncolored_range {
+                            self.mesh_default.render(
+                                pass,
+                                layer_projection,
+                                part,
+                                instances,
+                                range.clone(),
+                            );
+                            stats.add_mesh(range, count);
+                        }
+                        if let Some(range) = &part.mesh.uncolored_without_bfc_range {
+                            self.mesh_no_shading.render(
+                                pass,
+                                layer_projection,
+                                part,
+                                instances,
+                                range.clone(),
+                            );
+                            stats.add_mesh(range, count);
+                        }
+                        if self.edge.render(pass, layer_projection, part, instances) {
+                            stats.draw_calls += 1;
+                        }
+                        if self
+                            .optional_edge
+                            .render(pass, layer_projection, part, instances)
+                        {
+                            stats.draw_calls += 1;
+                        }
                     }
                 }
             }
         }
+        stats.translucent_passes = stats.draw_calls - opaque_draw_calls;
+        self.write_timestamp(pass, Timestamp::Translucent);
 
-        draws
+        stats
+    }
+
+    // Outlines every instance in the display list on top of what has been rendered into the
+    // target, which has to be in the format and sample count of this manager.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_outline<K, G>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        projection: &Projection,
+        part_querier: &impl PartQuerier<G>,
+        display_list: &DisplayList<K, G>,
+        target: &wgpu::TextureView,
+        resolve_target: Option<&wgpu::TextureView>,
+        size: (u32, u32),
+    ) {
+        self.outline.render(
+            device,
+            queue,
+            encoder,
+            projection,
+            part_querier,
+            display_list,
+            target,
+            resolve_target,
+            size,
+        );
     }
 
     pub async fn select_objects_multiple_ops<'ctx>(
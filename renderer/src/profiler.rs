@@ -0,0 +1,181 @@
+use std::{
+    cell::Cell,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+// Features needed for timing passes. Devices request them whenever the adapter has them so
+// that profiling can be switched on later.
+pub const GPU_PROFILING_FEATURES: wgpu::Features =
+    wgpu::Features::TIMESTAMP_QUERY.union(wgpu::Features::TIMESTAMP_QUERY_INSIDE_PASSES);
+
+// GPU time spent on each pass of a frame, in nanoseconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GpuTimings {
+    pub skybox: u64,
+    pub opaque: u64,
+    pub translucent: u64,
+}
+
+impl GpuTimings {
+    pub fn total(&self) -> u64 {
+        self.skybox + self.opaque + self.translucent
+    }
+}
+
+fn to_msecs(nanos: u64) -> f64 {
+    nanos as f64 / 1_000_000.0
+}
+
+impl fmt::Display for GpuTimings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "GPU {:.2} ms (skybox {:.2} ms, opaque {:.2} ms, translucent {:.2} ms)",
+            to_msecs(self.total()),
+            to_msecs(self.skybox),
+            to_msecs(self.opaque),
+            to_msecs(self.translucent)
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum Timestamp {
+    Start = 0,
+    Skybox = 1,
+    Opaque = 2,
+    Translucent = 3,
+}
+
+const TIMESTAMP_COUNT: u32 = 4;
+const BUFFER_SIZE: u64 = TIMESTAMP_COUNT as u64 * std::mem::size_of::<u64>() as u64;
+
+pub struct GpuProfiler {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    period: f32,
+
+    // Whether timestamps have been written since the last resolve.
+    recorded: Cell<bool>,
+    // Set while the readback buffer is mapped or waiting to be. Frames rendered in the
+    // meantime are not profiled.
+    in_flight: bool,
+    map_result: Arc<Mutex<Option<bool>>>,
+    last: Option<GpuTimings>,
+}
+
+impl GpuProfiler {
+    // Returns None if the device was created without timestamp queries.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(GPU_PROFILING_FEATURES) {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Query set for GPU profiling"),
+            ty: wgpu::QueryType::Timestamp,
+            count: TIMESTAMP_COUNT,
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Resolve buffer for GPU profiling"),
+            size: BUFFER_SIZE,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback buffer for GPU profiling"),
+            size: BUFFER_SIZE,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            period: queue.get_timestamp_period(),
+            recorded: Cell::new(false),
+            in_flight: false,
+            map_result: Arc::new(Mutex::new(None)),
+            last: None,
+        })
+    }
+
+    pub(crate) fn write(&self, pass: &mut wgpu::RenderPass<'static>, timestamp: Timestamp) {
+        if self.in_flight {
+            return;
+        }
+
+        pass.write_timestamp(&self.query_set, timestamp as u32);
+        self.recorded.set(true);
+    }
+
+    // Must be called after the render pass has ended.
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        if !self.recorded.get() || self.in_flight {
+            return;
+        }
+
+        encoder.resolve_query_set(&self.query_set, 0..TIMESTAMP_COUNT, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            BUFFER_SIZE,
+        );
+    }
+
+    // Must be called after the resolved commands have been submitted. Without waiting, the
+    // result lags behind by a few frames.
+    pub fn collect(&mut self, device: &wgpu::Device, wait: bool) -> Option<GpuTimings> {
+        if self.recorded.replace(false) {
+            let map_result = Arc::clone(&self.map_result);
+            self.readback_buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    *map_result.lock().unwrap() = Some(result.is_ok());
+                });
+            self.in_flight = true;
+        }
+
+        if !self.in_flight {
+            return self.last;
+        }
+
+        device.poll(if wait {
+            wgpu::Maintain::Wait
+        } else {
+            wgpu::Maintain::Poll
+        });
+
+        let Some(mapped) = self.map_result.lock().unwrap().take() else {
+            return self.last;
+        };
+
+        if mapped {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            let timestamps = data
+                .chunks_exact(std::mem::size_of::<u64>())
+                .map(|v| u64::from_le_bytes(v.try_into().unwrap()))
+                .collect::<Vec<_>>();
+            drop(data);
+            self.readback_buffer.unmap();
+
+            let elapsed = |from: Timestamp, to: Timestamp| {
+                let ticks = timestamps[to as usize].saturating_sub(timestamps[from as usize]);
+                (ticks as f64 * self.period as f64) as u64
+            };
+            self.last = Some(GpuTimings {
+                skybox: elapsed(Timestamp::Start, Timestamp::Skybox),
+                opaque: elapsed(Timestamp::Skybox, Timestamp::Opaque),
+                translucent: elapsed(Timestamp::Opaque, Timestamp::Translucent),
+            });
+        }
+        self.in_flight = false;
+
+        self.last
+    }
+}
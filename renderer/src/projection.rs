@@ -1,27 +1,36 @@
 use std::{collections::HashSet, hash::Hash};
 
 use cgmath::{prelude::*, Deg, Matrix, Ortho, PerspectiveFov, Point3, SquareMatrix};
-use ldraw::{Matrix3, Matrix4, Vector2, Vector3};
+use ldraw::{Matrix3, Matrix4, Vector2, Vector3, Vector4};
 use ldraw_ir::geometry::{BoundingBox2, BoundingBox3};
 use wgpu::util::DeviceExt;
 
 use crate::{AspectRatio, GpuUpdate, GpuUpdateResult};
 
+// Number of clip planes the shaders take into account.
+pub const MAX_CLIP_PLANES: usize = 4;
+
 struct ProjectionData {
     model_matrix_stack: Vec<Matrix4>,
     projection_matrix: Matrix4,
     view_matrix: Matrix4,
     is_orthographic: bool,
+    clip_planes: Vec<Vector4>,
 }
 
 pub enum ProjectionMutator {
     PushModelMatrix(Matrix4),
     PopModelMatrix,
+    // Replaces the whole model matrix stack.
+    SetModelMatrix(Matrix4),
     SetProjectionMatrix {
         matrix: Matrix4,
         is_orthographic: bool,
     },
     SetViewMatrix(Matrix4),
+    // Each plane is (a, b, c, d) in model coordinates. Points where ax + by + cz + d is
+    // negative are cut away. Planes beyond MAX_CLIP_PLANES are ignored.
+    SetClipPlanes(Vec<Vector4>),
 }
 
 impl ProjectionData {
@@ -48,6 +57,7 @@ impl Default for ProjectionData {
             projection_matrix: Matrix4::identity(),
             view_matrix: Matrix4::identity(),
             is_orthographic: false,
+            clip_planes: Vec::new(),
         }
     }
 }
@@ -66,6 +76,9 @@ struct RawProjectionData {
     _padding2: [u8; 4],
     is_orthographic: i32,
     _padding3: [u8; 12],
+    clip_planes: [[f32; 4]; MAX_CLIP_PLANES],
+    clip_plane_count: i32,
+    _padding4: [u8; 12],
 }
 
 impl Default for RawProjectionData {
@@ -82,6 +95,9 @@ impl Default for RawProjectionData {
             _padding2: [0; 4],
             is_orthographic: 0,
             _padding3: [0; 12],
+            clip_planes: [[0.0; 4]; MAX_CLIP_PLANES],
+            clip_plane_count: 0,
+            _padding4: [0; 12],
         }
     }
 }
@@ -107,6 +123,15 @@ impl RawProjectionData {
         self.normal_matrix_1 = normal_matrix.y.into();
         self.normal_matrix_2 = normal_matrix.z.into();
     }
+
+    fn update_clip_planes(&mut self, planes: &[Vector4]) {
+        let count = planes.len().min(MAX_CLIP_PLANES);
+        self.clip_planes = [[0.0; 4]; MAX_CLIP_PLANES];
+        for (raw, plane) in self.clip_planes.iter_mut().zip(&planes[..count]) {
+            *raw = (*plane).into();
+        }
+        self.clip_plane_count = count as i32;
+    }
 }
 
 pub struct Projection {
@@ -137,6 +162,17 @@ impl GpuUpdate for Projection {
                     GpuUpdateResult::NotModified
                 }
             }
+            ProjectionMutator::SetModelMatrix(matrix) => {
+                if self.data.model_matrix_stack != [matrix] {
+                    self.data.model_matrix_stack = vec![matrix];
+                    self.raw.model_matrix = matrix.into();
+                    self.raw
+                        .update_normal_matrix(self.data.view_matrix * matrix);
+                    GpuUpdateResult::Modified
+                } else {
+                    GpuUpdateResult::NotModified
+                }
+            }
             ProjectionMutator::SetProjectionMatrix {
                 matrix,
                 is_orthographic,
@@ -163,6 +199,15 @@ impl GpuUpdate for Projection {
                     GpuUpdateResult::NotModified
                 }
             }
+            ProjectionMutator::SetClipPlanes(planes) => {
+                if self.data.clip_planes != planes {
+                    self.raw.update_clip_planes(&planes);
+                    self.data.clip_planes = planes;
+                    GpuUpdateResult::Modified
+                } else {
+                    GpuUpdateResult::NotModified
+                }
+            }
         }
     }
 
@@ -202,6 +247,34 @@ impl Projection {
         }
     }
 
+    pub fn clip_planes(&self) -> &[Vector4] {
+        &self.data.clip_planes
+    }
+
+    // Mutations that make another projection look through the same camera, with the model
+    // matrix of this one followed by given matrix. Clip planes are carried into coordinates
+    // of the other projection, so that both are cut at the same place.
+    pub fn follow(&self, model_matrix: Matrix4) -> Vec<ProjectionMutator> {
+        let transposed = model_matrix.transpose();
+        vec![
+            ProjectionMutator::SetProjectionMatrix {
+                matrix: self.data.projection_matrix,
+                is_orthographic: self.data.is_orthographic,
+            },
+            ProjectionMutator::SetViewMatrix(self.data.view_matrix),
+            ProjectionMutator::SetModelMatrix(
+                self.data.model_matrix_stack.last().unwrap() * model_matrix,
+            ),
+            ProjectionMutator::SetClipPlanes(
+                self.data
+                    .clip_planes
+                    .iter()
+                    .map(|plane| transposed * plane)
+                    .collect(),
+            ),
+        ]
+    }
+
     pub fn get_model_view_matrix(&self) -> Matrix4 {
         self.data.view_matrix * self.data.model_matrix_stack.last().unwrap()
     }
@@ -439,3 +512,359 @@ impl ProjectionModifier for OrthographicCamera {
         ]
     }
 }
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CameraLens {
+    Perspective { fov: Deg<f32> },
+    // Size of the visible area in LDU.
+    Orthographic { width: f32, height: f32 },
+}
+
+// Camera placement in model space (LDU, -Y up) that can be handed over to offline
+// renderers to reproduce the framing of the viewer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CameraDescription {
+    pub position: Point3<f32>,
+    pub look_at: Point3<f32>,
+    pub up: Vector3,
+    pub lens: CameraLens,
+    pub aspect_ratio: f32,
+}
+
+// Default scale of the LDraw importer add-on for Blender, in Blender units per LDU.
+pub const BLENDER_IMPORT_SCALE: f32 = 0.01;
+
+impl CameraDescription {
+    fn forward(&self) -> Vector3 {
+        (self.look_at - self.position).normalize()
+    }
+
+    // Python snippet creating a camera in Blender, where Z points up. Coordinates are
+    // multiplied by `scale` to match the importer used for the model.
+    pub fn to_blender_script(&self, scale: f32) -> String {
+        // LDraw (x, y, z) maps to Blender (x, z, -y).
+        let convert = |v: Vector3| Vector3::new(v.x, v.z, -v.y);
+        let position = convert(self.position.to_vec()) * scale;
+        let right = convert(self.forward().cross(self.up).normalize());
+        let up = convert(self.up);
+        let back = convert(-self.forward());
+        let distance = (self.look_at - self.position).magnitude() * scale;
+
+        let lens = match self.lens {
+            CameraLens::Perspective { fov } => format!(
+                "data.type = 'PERSP'\ndata.sensor_fit = 'VERTICAL'\ndata.angle_y = {:.6}\n",
+                cgmath::Rad::from(fov).0
+            ),
+            CameraLens::Orthographic { width, height } => format!(
+                "data.type = 'ORTHO'\ndata.ortho_scale = {:.6}\n",
+                width.max(height) * scale
+            ),
+        };
+
+        format!(
+            "import bpy
+from mathutils import Matrix
+
+scene = bpy.context.scene
+data = bpy.data.cameras.new('ldraw.rs camera')
+{lens}data.clip_start = {clip_start:.6}
+data.clip_end = {clip_end:.6}
+camera = bpy.data.objects.new('ldraw.rs camera', data)
+scene.collection.objects.link(camera)
+camera.matrix_world = Matrix((
+    ({:.6}, {:.6}, {:.6}, {:.6}),
+    ({:.6}, {:.6}, {:.6}, {:.6}),
+    ({:.6}, {:.6}, {:.6}, {:.6}),
+    (0.0, 0.0, 0.0, 1.0),
+))
+scene.camera = camera
+scene.render.resolution_y = round(scene.render.resolution_x / {aspect:.6})
+",
+            right.x,
+            up.x,
+            back.x,
+            position.x,
+            right.y,
+            up.y,
+            back.y,
+            position.y,
+            right.z,
+            up.z,
+            back.z,
+            position.z,
+            lens = lens,
+            clip_start = distance * 0.01,
+            clip_end = distance * 100.0,
+            aspect = self.aspect_ratio,
+        )
+    }
+
+    // POV-Ray camera in LDraw coordinates, as used by LDView and L3P exports. POV-Ray is
+    // left handed, so the right vector is mirrored.
+    pub fn to_povray(&self) -> String {
+        let vector = |v: Vector3| format!("<{:.4}, {:.4}, {:.4}>", v.x, v.y, v.z);
+        let lens = match self.lens {
+            CameraLens::Perspective { fov } => {
+                let horizontal = Deg::atan((fov / 2.0).tan() * self.aspect_ratio) * 2.0;
+                format!(
+                    "  right <{:.6}, 0, 0>\n  angle {:.4}\n",
+                    -self.aspect_ratio, horizontal.0
+                )
+            }
+            CameraLens::Orthographic { width, height } => format!(
+                "  orthographic\n  right <{:.4}, 0, 0>\n  up <0, {:.4}, 0>\n",
+                -width, height
+            ),
+        };
+
+        format!(
+            "camera {{\n  location {}\n  sky {}\n{}  look_at {}\n}}\n",
+            vector(self.position.to_vec()),
+            vector(self.up),
+            lens,
+            vector(self.look_at.to_vec()),
+        )
+    }
+}
+
+impl Projection {
+    // Recovers camera placement from the current view, projection and model matrices.
+    pub fn describe_camera(&self) -> Option<CameraDescription> {
+        self.data.describe_camera()
+    }
+}
+
+impl ProjectionData {
+    fn describe_camera(&self) -> Option<CameraDescription> {
+        let model_view = self.view_matrix * self.model_matrix_stack.last().unwrap();
+        let inverse = model_view.invert()?;
+        let projection = &self.projection_matrix;
+
+        let up = (inverse * Vector3::unit_y().extend(0.0))
+            .truncate()
+            .normalize();
+        let forward = (inverse * (-Vector3::unit_z()).extend(0.0))
+            .truncate()
+            .normalize();
+        let right = forward.cross(up).normalize();
+        let mut position = Point3::from_homogeneous(inverse * Vector4::new(0.0, 0.0, 0.0, 1.0));
+
+        let (lens, aspect_ratio) = if self.is_orthographic {
+            let width = 2.0 / projection.x.x;
+            let height = 2.0 / projection.y.y;
+            // View bounds need not be centered around the view axis.
+            let offset_x = -projection.w.x / projection.x.x;
+            let offset_y = -projection.w.y / projection.y.y;
+            position += right * offset_x + up * offset_y;
+            (CameraLens::Orthographic { width, height }, width / height)
+        } else {
+            let fov = Deg::from(cgmath::Rad(2.0 * (1.0 / projection.y.y).atan()));
+            (
+                CameraLens::Perspective { fov },
+                projection.y.y / projection.x.x,
+            )
+        };
+
+        // Distance to the look at point doesn't change the view itself, but is used as
+        // a hint for clipping planes. Model origin is the best guess available.
+        let distance = (Point3::new(0.0, 0.0, 0.0) - position)
+            .dot(forward)
+            .max(1.0);
+
+        Some(CameraDescription {
+            position,
+            look_at: position + forward * distance,
+            up,
+            lens,
+            aspect_ratio,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{assert_abs_diff_eq, prelude::*, Deg, Ortho, PerspectiveFov, Point3};
+    use ldraw::{Matrix4, Vector3};
+
+    use super::{CameraDescription, CameraLens, ProjectionData};
+
+    fn projection(projection_matrix: Matrix4, is_orthographic: bool) -> ProjectionData {
+        ProjectionData {
+            projection_matrix,
+            view_matrix: Matrix4::look_at_rh(
+                Point3::new(200.0, -150.0, 300.0),
+                Point3::new(0.0, 0.0, 0.0),
+                Vector3::new(0.0, -1.0, 0.0),
+            ),
+            is_orthographic,
+            ..Default::default()
+        }
+    }
+
+    // Numbers in the given line after the prefix, in order.
+    fn numbers(text: &str, prefix: &str) -> Vec<f32> {
+        let line = text
+            .lines()
+            .map(str::trim)
+            .find(|v| v.starts_with(prefix))
+            .unwrap();
+        line[prefix.len()..]
+            .split(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+            .filter(|v| !v.is_empty())
+            .map(|v| v.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_describe_perspective_camera() {
+        let data = projection(
+            Matrix4::from(PerspectiveFov {
+                fovy: cgmath::Rad::from(Deg(40.0)),
+                aspect: 1.5,
+                near: 10.0,
+                far: 100000.0,
+            }),
+            false,
+        );
+        let camera = data.describe_camera().unwrap();
+
+        assert_abs_diff_eq!(
+            camera.position,
+            Point3::new(200.0, -150.0, 300.0),
+            epsilon = 1e-2
+        );
+        assert_abs_diff_eq!(camera.look_at, Point3::new(0.0, 0.0, 0.0), epsilon = 1e-2);
+        match camera.lens {
+            CameraLens::Perspective { fov } => assert_abs_diff_eq!(fov.0, 40.0, epsilon = 1e-3),
+            _ => panic!("Camera should be perspective"),
+        }
+        assert_abs_diff_eq!(camera.aspect_ratio, 1.5, epsilon = 1e-4);
+        assert_abs_diff_eq!(
+            camera.up.dot(camera.look_at - camera.position),
+            0.0,
+            epsilon = 1e-2
+        );
+    }
+
+    #[test]
+    fn test_describe_orthographic_camera() {
+        let data = projection(
+            Matrix4::from(Ortho {
+                left: -50.0,
+                right: 150.0,
+                bottom: -50.0,
+                top: 50.0,
+                near: -10000.0,
+                far: 10000.0,
+            }),
+            true,
+        );
+        let camera = data.describe_camera().unwrap();
+
+        assert_eq!(
+            camera.lens,
+            CameraLens::Orthographic {
+                width: 200.0,
+                height: 100.0
+            }
+        );
+        assert_abs_diff_eq!(camera.aspect_ratio, 2.0, epsilon = 1e-4);
+        // Off-center bounds move the camera sideways, keeping its direction.
+        let centered = projection(
+            Matrix4::from(Ortho {
+                left: -100.0,
+                right: 100.0,
+                bottom: -50.0,
+                top: 50.0,
+                near: -10000.0,
+                far: 10000.0,
+            }),
+            true,
+        )
+        .describe_camera()
+        .unwrap();
+        assert_abs_diff_eq!(
+            (camera.position - centered.position).magnitude(),
+            50.0,
+            epsilon = 1e-2
+        );
+        assert_abs_diff_eq!(
+            (camera.look_at - camera.position).normalize(),
+            (centered.look_at - centered.position).normalize(),
+            epsilon = 1e-4
+        );
+    }
+
+    fn description(lens: CameraLens) -> CameraDescription {
+        CameraDescription {
+            position: Point3::new(200.0, -150.0, 300.0),
+            look_at: Point3::new(0.0, -20.0, 0.0),
+            up: Vector3::new(0.0, -1.0, 0.0),
+            lens,
+            aspect_ratio: 1.5,
+        }
+    }
+
+    #[test]
+    fn test_blender_script() {
+        let camera = description(CameraLens::Perspective { fov: Deg(40.0) });
+        let script = camera.to_blender_script(0.01);
+
+        // Last column of matrix_world is the position, in Blender coordinates where Z is up.
+        let rows = script
+            .lines()
+            .filter(|v| v.trim_start().starts_with('(') && v.trim_end().ends_with("),"))
+            .map(|v| numbers(v, ""))
+            .collect::<Vec<_>>();
+        assert_eq!(rows.len(), 4);
+        assert_abs_diff_eq!(rows[0][3], 2.0, epsilon = 1e-4);
+        assert_abs_diff_eq!(rows[1][3], 3.0, epsilon = 1e-4);
+        assert_abs_diff_eq!(rows[2][3], 1.5, epsilon = 1e-4);
+        // Camera looks down its local -Z, which is the third column negated.
+        let forward = Vector3::new(-rows[0][2], -rows[1][2], -rows[2][2]);
+        let expected = (camera.look_at - camera.position).normalize();
+        assert_abs_diff_eq!(
+            forward,
+            Vector3::new(expected.x, expected.z, -expected.y),
+            epsilon = 1e-4
+        );
+        assert_abs_diff_eq!(
+            numbers(&script, "data.angle_y = ")[0],
+            cgmath::Rad::from(Deg(40.0)).0,
+            epsilon = 1e-5
+        );
+
+        let script = description(CameraLens::Orthographic {
+            width: 300.0,
+            height: 200.0,
+        })
+        .to_blender_script(0.01);
+        assert_abs_diff_eq!(
+            numbers(&script, "data.ortho_scale = ")[0],
+            3.0,
+            epsilon = 1e-5
+        );
+    }
+
+    #[test]
+    fn test_povray() {
+        let pov = description(CameraLens::Perspective { fov: Deg(40.0) }).to_povray();
+
+        assert_eq!(numbers(&pov, "location"), vec![200.0, -150.0, 300.0]);
+        assert_eq!(numbers(&pov, "look_at"), vec![0.0, -20.0, 0.0]);
+        assert_eq!(numbers(&pov, "sky"), vec![0.0, -1.0, 0.0]);
+        assert_eq!(numbers(&pov, "right"), vec![-1.5, 0.0, 0.0]);
+        // Horizontal angle from the vertical field of view and the aspect ratio.
+        let horizontal = Deg::atan((Deg(20.0f32)).tan() * 1.5) * 2.0;
+        assert_abs_diff_eq!(numbers(&pov, "angle")[0], horizontal.0, epsilon = 1e-3);
+
+        let pov = description(CameraLens::Orthographic {
+            width: 300.0,
+            height: 200.0,
+        })
+        .to_povray();
+        assert!(pov.contains("orthographic"));
+        assert_eq!(numbers(&pov, "right"), vec![-300.0, 0.0, 0.0]);
+        assert_eq!(numbers(&pov, "up"), vec![0.0, 200.0, 0.0]);
+    }
+}
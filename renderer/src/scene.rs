@@ -0,0 +1,191 @@
+use std::{fmt::Display, hash::Hash};
+
+use ldraw::Matrix4;
+use ldraw_ir::geometry::BoundingBox3;
+
+use crate::{display_list::DisplayList, part::PartQuerier, projection::Projection, Entity};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct SceneModelId(u32);
+
+impl Display for SceneModelId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+pub struct SceneModel<K, G> {
+    pub display_list: Entity<DisplayList<K, G>>,
+    // Each model has its own projection so that its root transform applies to the whole
+    // display list without touching instances.
+    projection: Entity<Projection>,
+    transform: Matrix4,
+    visible: bool,
+}
+
+impl<K, G> SceneModel<K, G> {
+    pub fn transform(&self) -> &Matrix4 {
+        &self.transform
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn projection(&self) -> &Projection {
+        self.projection.get()
+    }
+}
+
+impl<K, G: Clone + Eq + PartialEq + Hash + Display> SceneModel<K, G> {
+    // Bounds in scene coordinates, including the root transform.
+    pub fn bounding_box(&self, parts: &impl PartQuerier<G>) -> BoundingBox3 {
+        let mut bb = BoundingBox3::nil();
+        for (group, _, instances) in self.display_list.iter() {
+            let Some(part) = parts.get(group) else {
+                continue;
+            };
+            for matrix in instances.matrices() {
+                bb.update(&part.bounding_box.transform(&(self.transform * matrix)));
+            }
+        }
+        bb
+    }
+}
+
+// Display lists placed side by side in one view, e.g. to compare variants of a model. Models
+// are drawn in the order they were added.
+pub struct Scene<K, G> {
+    models: Vec<(SceneModelId, SceneModel<K, G>)>,
+    next_id: u32,
+}
+
+impl<K, G> Default for Scene<K, G> {
+    fn default() -> Self {
+        Self {
+            models: Vec::new(),
+            next_id: 0,
+        }
+    }
+}
+
+impl<K, G> Scene<K, G> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.models.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.models.is_empty()
+    }
+
+    pub fn add(
+        &mut self,
+        device: &wgpu::Device,
+        display_list: Entity<DisplayList<K, G>>,
+        transform: Matrix4,
+    ) -> SceneModelId {
+        let id = SceneModelId(self.next_id);
+        self.next_id += 1;
+        self.models.push((
+            id,
+            SceneModel {
+                display_list,
+                projection: Projection::new(device).into(),
+                transform,
+                visible: true,
+            },
+        ));
+        id
+    }
+
+    pub fn remove(&mut self, id: SceneModelId) -> bool {
+        let len = self.models.len();
+        self.models.retain(|(v, _)| *v != id);
+        self.models.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.models.clear();
+    }
+
+    pub fn get(&self, id: SceneModelId) -> Option<&SceneModel<K, G>> {
+        self.models
+            .iter()
+            .find(|(v, _)| *v == id)
+            .map(|(_, model)| model)
+    }
+
+    pub fn get_mut(&mut self, id: SceneModelId) -> Option<&mut SceneModel<K, G>> {
+        self.models
+            .iter_mut()
+            .find(|(v, _)| *v == id)
+            .map(|(_, model)| model)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (SceneModelId, &SceneModel<K, G>)> {
+        self.models.iter().map(|(id, model)| (*id, model))
+    }
+
+    pub fn set_transform(&mut self, id: SceneModelId, transform: Matrix4) -> bool {
+        match self.get_mut(id) {
+            Some(model) => {
+                model.transform = transform;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_visible(&mut self, id: SceneModelId, visible: bool) -> bool {
+        match self.get_mut(id) {
+            Some(model) => {
+                model.visible = visible;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Display lists of visible models with projections to draw them through, in the form
+    // RenderingPipelineManager::render_layers() takes.
+    pub fn layers(&self) -> Vec<(&Projection, &DisplayList<K, G>)> {
+        self.models
+            .iter()
+            .filter(|(_, model)| model.visible)
+            .map(|(_, model)| (model.projection.get(), &*model.display_list))
+            .collect()
+    }
+}
+
+impl<
+        K: Clone + std::fmt::Debug + Eq + PartialEq + Hash + Display,
+        G: Clone + Eq + PartialEq + Hash + Display,
+    > Scene<K, G>
+{
+    // Brings projections of every model in line with the camera, and uploads pending changes.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, camera: &Projection) {
+        for (_, model) in self.models.iter_mut() {
+            model
+                .projection
+                .mutate_all(camera.follow(model.transform).into_iter());
+            model.projection.update(device, queue);
+            model.display_list.update(device, queue);
+        }
+    }
+
+    // Bounds of visible models in scene coordinates.
+    pub fn bounding_box(&self, parts: &impl PartQuerier<G>) -> BoundingBox3 {
+        let mut bb = BoundingBox3::nil();
+        for (_, model) in self.models.iter().filter(|(_, model)| model.visible) {
+            let model_bb = model.bounding_box(parts);
+            if !model_bb.is_null() {
+                bb.update(&model_bb);
+            }
+        }
+        bb
+    }
+}
@@ -7,7 +7,22 @@ use ldraw_ir::{
     model::{self, GroupId},
 };
 
-use crate::part::PartQuerier;
+use crate::{part::PartQuerier, profiler::GPU_PROFILING_FEATURES};
+
+pub const SAMPLE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+
+// Sample counts usable for rendering into the given format along with a depth buffer.
+pub fn supported_sample_counts(adapter: &wgpu::Adapter, format: wgpu::TextureFormat) -> Vec<u32> {
+    let color = adapter.get_texture_format_features(format).flags;
+    let depth = adapter
+        .get_texture_format_features(wgpu::TextureFormat::Depth32Float)
+        .flags;
+
+    SAMPLE_COUNTS
+        .into_iter()
+        .filter(|&v| color.sample_count_supported(v) && depth.sample_count_supported(v))
+        .collect()
+}
 
 pub async fn request_device(
     adapter: &wgpu::Adapter,
@@ -27,7 +42,7 @@ pub async fn request_device(
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label,
-                    required_features: wgpu::Features::default(),
+                    required_features: adapter.features() & GPU_PROFILING_FEATURES,
                     required_limits,
                     memory_hints: Default::default(),
                 },
@@ -77,6 +92,17 @@ fn calculate_bounding_box_recursive<K: Clone + Eq + PartialEq + Hash, Q: PartQue
     }
 }
 
+// Bounds of given objects of the model, such as a selection.
+pub fn calculate_objects_bounding_box<K: Clone + Eq + PartialEq + Hash, Q: PartQuerier<K>>(
+    model: &model::Model<K>,
+    objects: &[model::Object<K>],
+    parts: &Q,
+) -> BoundingBox3 {
+    let mut bb = BoundingBox3::nil();
+    calculate_bounding_box_recursive(&mut bb, parts, Matrix4::identity(), objects, model);
+    bb
+}
+
 pub fn calculate_model_bounding_box<K: Clone + Eq + PartialEq + Hash, Q: PartQuerier<K>>(
     model: &model::Model<K>,
     group_id: Option<GroupId>,
@@ -0,0 +1,225 @@
+use ldraw_ir::{
+    geometry::BoundingBox3,
+    part::{EdgeColor, MeshBuffer, Part, PartMetadata, VertexBuffer},
+};
+use serde::Serialize;
+
+type Point = [f32; 3];
+
+#[derive(Serialize)]
+pub struct MeshDump {
+    group: String,
+    bfc: bool,
+    range: [usize; 2],
+    positions: Vec<[Point; 3]>,
+    normals: Vec<[Point; 3]>,
+}
+
+#[derive(Serialize)]
+pub struct EdgeDump {
+    color: String,
+    points: [Point; 2],
+}
+
+#[derive(Serialize)]
+pub struct OptionalEdgeDump {
+    color: String,
+    points: [Point; 2],
+    controls: [Point; 2],
+}
+
+#[derive(Serialize)]
+pub struct PartDump<'a> {
+    metadata: &'a PartMetadata,
+    bounding_box: &'a BoundingBox3,
+    rotation_center: Point,
+    vertex_count: usize,
+    meshes: Vec<MeshDump>,
+    edges: Vec<EdgeDump>,
+    optional_edges: Vec<OptionalEdgeDump>,
+}
+
+fn point(buffer: &VertexBuffer, index: u32) -> Point {
+    let index = index as usize * 3;
+    match buffer.0.get(index..index + 3) {
+        Some(v) => [v[0], v[1], v[2]],
+        None => [f32::NAN; 3],
+    }
+}
+
+fn describe_color(code: u32) -> String {
+    match EdgeColor::from_code(code) {
+        EdgeColor::Current => String::from("current"),
+        EdgeColor::Complement => String::from("complement"),
+        EdgeColor::Main(code) => format!("{}", code),
+        EdgeColor::Edge(code) => format!("edge of {}", code),
+    }
+}
+
+fn dump_mesh(
+    buffer: &VertexBuffer,
+    group: String,
+    bfc: bool,
+    mesh: &MeshBuffer,
+    offset: &mut usize,
+) -> MeshDump {
+    let mut positions = vec![];
+    let mut normals = vec![];
+
+    for (vertices, normal_indices) in mesh
+        .vertex_indices
+        .chunks(3)
+        .zip(mesh.normal_indices.chunks(3))
+    {
+        if vertices.len() != 3 || normal_indices.len() != 3 {
+            break;
+        }
+        positions.push([
+            point(buffer, vertices[0]),
+            point(buffer, vertices[1]),
+            point(buffer, vertices[2]),
+        ]);
+        normals.push([
+            point(buffer, normal_indices[0]),
+            point(buffer, normal_indices[1]),
+            point(buffer, normal_indices[2]),
+        ]);
+    }
+
+    let range = [*offset, *offset + mesh.len()];
+    *offset += mesh.len();
+
+    MeshDump {
+        group,
+        bfc,
+        range,
+        positions,
+        normals,
+    }
+}
+
+impl<'a> From<&'a Part> for PartDump<'a> {
+    fn from(part: &'a Part) -> Self {
+        let geometry = &part.geometry;
+        let buffer = &geometry.vertex_buffer;
+
+        let mut offset = 0;
+        let mut meshes = vec![
+            dump_mesh(
+                buffer,
+                String::from("current"),
+                true,
+                &geometry.uncolored_mesh,
+                &mut offset,
+            ),
+            dump_mesh(
+                buffer,
+                String::from("current"),
+                false,
+                &geometry.uncolored_without_bfc_mesh,
+                &mut offset,
+            ),
+        ];
+
+        let mut colored_meshes = geometry.colored_meshes.iter().collect::<Vec<_>>();
+        colored_meshes.sort_by(|a, b| a.0.cmp(b.0));
+        for (key, mesh) in colored_meshes {
+            meshes.push(dump_mesh(
+                buffer,
+                key.color_ref.code().to_string(),
+                key.bfc,
+                mesh,
+                &mut offset,
+            ));
+        }
+        meshes.retain(|v| !v.positions.is_empty());
+
+        let edges = geometry
+            .edges
+            .vertex_indices
+            .chunks(2)
+            .zip(geometry.edges.colors.chunks(2))
+            .filter(|(v, _)| v.len() == 2)
+            .map(|(v, c)| EdgeDump {
+                color: describe_color(c[0]),
+                points: [point(buffer, v[0]), point(buffer, v[1])],
+            })
+            .collect();
+
+        let optional_edges = &geometry.optional_edges;
+        let optional_edges = (0..optional_edges.len() / 2)
+            .map(|i| i * 2)
+            .filter(|i| *i + 1 < optional_edges.len() && optional_edges.is_valid())
+            .map(|i| OptionalEdgeDump {
+                color: describe_color(optional_edges.colors[i]),
+                points: [
+                    point(buffer, optional_edges.vertex_indices[i]),
+                    point(buffer, optional_edges.vertex_indices[i + 1]),
+                ],
+                controls: [
+                    point(buffer, optional_edges.control_1_indices[i]),
+                    point(buffer, optional_edges.control_2_indices[i]),
+                ],
+            })
+            .collect();
+
+        PartDump {
+            metadata: &part.metadata,
+            bounding_box: &part.bounding_box,
+            rotation_center: part.rotation_center.into(),
+            vertex_count: buffer.0.len() / 3,
+            meshes,
+            edges,
+            optional_edges,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ldraw::{
+        color::{Color, ColorReference},
+        document::{Document, MultipartDocument},
+        elements::{Command, Line},
+        library::ResolutionResult,
+        Vector4,
+    };
+    use ldraw_ir::part::bake_part_from_multipart_document;
+
+    use super::PartDump;
+
+    #[test]
+    fn test_edge_colors() {
+        let line = |color: ColorReference, x: f32| {
+            Command::Line(Line {
+                color,
+                a: Vector4::new(x, 0.0, 0.0, 1.0),
+                b: Vector4::new(x, 1.0, 0.0, 1.0),
+            })
+        };
+        let red = ColorReference::Color(Color {
+            code: 4,
+            ..Default::default()
+        });
+        let document = MultipartDocument {
+            body: Document {
+                commands: vec![
+                    line(red, 0.0),
+                    line(ColorReference::Current, 1.0),
+                    line(ColorReference::Complement, 2.0),
+                ],
+                ..Default::default()
+            },
+            subparts: Default::default(),
+        };
+
+        let part = bake_part_from_multipart_document(&document, &ResolutionResult::new(), false);
+        let mut colors = PartDump::from(&part)
+            .edges
+            .into_iter()
+            .map(|v| v.color)
+            .collect::<Vec<_>>();
+        colors.sort();
+        assert_eq!(colors, vec!["4", "complement", "current"]);
+    }
+}
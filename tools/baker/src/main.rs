@@ -1,27 +1,51 @@
+mod dump;
+mod manifest;
+
 use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
     env,
     path::{Path, PathBuf},
-    sync::{Arc, RwLock},
+    rc::Rc,
+    sync::Arc,
+    time::Duration,
 };
 
-use bincode::serialize;
 use clap::{App, Arg};
 use futures::{future::join_all, StreamExt};
-use itertools::Itertools;
+use indicatif::{ProgressBar, ProgressStyle};
 use ldraw::{
     color::ColorCatalog,
     library::{resolve_dependencies_multipart, CacheCollectionStrategy, LibraryLoader, PartCache},
-    parser::{parse_color_definitions, parse_multipart_document},
-    resolvers::local::LocalLoader,
+    parser::{parse_multipart_document_with_options, ParseOptions},
+    resolvers::composite::CompositeLoader,
+    PartAlias,
+};
+use ldraw_ir::{
+    export::{
+        obj::{export_part, ObjExportOptions, ObjGrouping},
+        threemf,
+    },
+    part::{
+        bake_part_from_multipart_document_with_statistics,
+        io::{encode_part, PartWriteOptions, FORMAT_VERSION},
+        pack::encode_pack_header,
+        simplify, BakeOptions, Part,
+    },
 };
-use ldraw_ir::part::bake_part_from_multipart_document;
 use tokio::{
     fs::{self, File},
-    io::{AsyncWriteExt, BufReader, BufWriter},
-    task::spawn_blocking,
+    io::{self, AsyncWriteExt, BufWriter},
+    sync::Semaphore,
+    task::{spawn_blocking, LocalSet},
 };
 use tokio_stream::wrappers::ReadDirStream;
 
+use crate::{
+    dump::PartDump,
+    manifest::{hash_document, Manifest, ManifestEntry, MANIFEST_FILE_NAME},
+};
+
 #[tokio::main]
 async fn main() {
     let matches = App::new("baker")
@@ -31,7 +55,9 @@ async fn main() {
                 .long("ldraw-dir")
                 .value_name("PATH")
                 .takes_value(true)
-                .help("Path to LDraw directory"),
+                .multiple(true)
+                .number_of_values(1)
+                .help("Path to LDraw directory, zipped library or URL of a mirror, tried in the order given"),
         )
         .arg(
             Arg::with_name("files")
@@ -47,12 +73,95 @@ async fn main() {
                 .takes_value(true)
                 .help("Output path"),
         )
+        .arg(
+            Arg::with_name("format")
+                .short("f")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["bincode", "json", "obj", "3mf"])
+                .default_value("bincode")
+                .help("Output format. JSON output is intended for debugging, and OBJ output is written with an MTL file next to it"),
+        )
+        .arg(
+            Arg::with_name("compression")
+                .long("compression")
+                .value_name("LEVEL")
+                .takes_value(true)
+                .help("Compress bincode output with zstd at given level, between 1 and 22"),
+        )
+        .arg(
+            Arg::with_name("obj_grouping")
+                .long("obj-grouping")
+                .takes_value(true)
+                .possible_values(&["part", "color"])
+                .default_value("part")
+                .help("Grouping of faces in OBJ output"),
+        )
+        .arg(
+            Arg::with_name("obj_edges")
+                .long("obj-edges")
+                .help("Include edges in OBJ output as lines"),
+        )
+        .arg(
+            Arg::with_name("repair_t_junctions")
+                .long("repair-t-junctions")
+                .help("Split faces at T-junctions to make meshes watertight"),
+        )
+        .arg(
+            Arg::with_name("no_cleanup")
+                .long("no-cleanup")
+                .help("Keep degenerate and duplicate faces"),
+        )
+        .arg(
+            Arg::with_name("no_optimize")
+                .long("no-optimize")
+                .help("Skip vertex welding and triangle reordering"),
+        )
+        .arg(
+            Arg::with_name("simplify")
+                .long("simplify")
+                .value_name("RATIO")
+                .takes_value(true)
+                .help("Reduce number of faces to given ratio, between 0 and 1"),
+        )
+        .arg(
+            Arg::with_name("lenient")
+                .long("lenient")
+                .help("Skip malformed lines instead of failing"),
+        )
+        .arg(
+            Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .value_name("N")
+                .takes_value(true)
+                .help("Number of files to bake in parallel. Defaults to the number of CPUs"),
+        )
+        .arg(
+            Arg::with_name("manifest")
+                .long("manifest")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Path to the manifest recording what was baked, to only rebake changed files on later runs. Defaults to baker-manifest.json in the output path"),
+        )
+        .arg(
+            Arg::with_name("force")
+                .long("force")
+                .help("Rebake every file even if it is unchanged since the last run"),
+        )
+        .arg(
+            Arg::with_name("pack")
+                .long("pack")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Also bundle every baked part and the color definitions into a single file, e.g. out.ldrpack"),
+        )
         .get_matches();
 
-    let ldrawdir = match matches.value_of("ldraw_dir") {
-        Some(v) => v.to_string(),
+    let ldraw_dirs = match matches.values_of("ldraw_dir") {
+        Some(v) => v.map(String::from).collect::<Vec<_>>(),
         None => match env::var("LDRAWDIR") {
-            Ok(v) => v,
+            Ok(v) => vec![v],
             Err(_) => {
                 panic!("--ldraw-dir option or LDRAWDIR environment variable is required.");
             }
@@ -70,167 +179,516 @@ async fn main() {
         None => None,
     };
 
-    let ldrawpath = PathBuf::from(&ldrawdir);
+    let format = match matches.value_of("format") {
+        Some("json") => OutputFormat::Json,
+        Some("obj") => OutputFormat::Obj(ObjExportOptions {
+            grouping: match matches.value_of("obj_grouping") {
+                Some("color") => ObjGrouping::Color,
+                _ => ObjGrouping::Part,
+            },
+            include_edges: matches.is_present("obj_edges"),
+            ..Default::default()
+        }),
+        Some("3mf") => OutputFormat::ThreeMf,
+        _ => OutputFormat::Bincode(PartWriteOptions {
+            compression: matches
+                .value_of("compression")
+                .map(|v| v.parse::<i32>().expect("Invalid compression level.")),
+        }),
+    };
+    let pack_path = matches.value_of("pack").map(PathBuf::from);
+    if pack_path.is_some() && !matches!(format, OutputFormat::Bincode(_)) {
+        panic!("--pack is only supported with bincode format.");
+    }
 
-    let colors = parse_color_definitions(&mut BufReader::new(
-        File::open(ldrawpath.join("LDConfig.ldr"))
-            .await
-            .expect("Could not load color definition."),
-    ))
-    .await
-    .expect("Could not parse color definition");
-
-    let loader = LocalLoader::new(Some(ldrawpath), None);
-
-    let mut tasks = vec![];
-
-    let cache = Arc::new(RwLock::new(PartCache::new()));
-    if let Some(files) = matches.values_of("files") {
-        for file in files {
-            let path = PathBuf::from(&file);
-            if !fs::try_exists(&path).await.unwrap_or(false) {
-                panic!("Path {} does not exists.", file);
-            } else if path.is_dir() {
-                let mut dir = ReadDirStream::new(
-                    fs::read_dir(&path)
-                        .await
-                        .expect("Could not read directory."),
-                );
-                while let Some(entry) = dir.next().await {
-                    let entry = entry.unwrap();
-                    let path = entry.path();
-                    let ext = path.extension();
-                    if ext.is_none() {
-                        continue;
-                    }
-                    let ext = ext.unwrap().to_str().unwrap().to_string().to_lowercase();
-                    if ext == "dat" || ext == "ldr" {
-                        tasks.push(bake(
-                            &loader,
-                            &colors,
-                            Arc::clone(&cache),
-                            path,
-                            &output_path,
-                        ));
-                    }
+    let options = BakeOptions {
+        repair_t_junctions: matches.is_present("repair_t_junctions"),
+        skip_cleanup: matches.is_present("no_cleanup"),
+        skip_optimization: matches.is_present("no_optimize"),
+        ..Default::default()
+    };
+    let simplify_ratio = matches
+        .value_of("simplify")
+        .map(|v| v.parse::<f32>().expect("Invalid simplification ratio."));
+    let parse_options = ParseOptions {
+        strict: !matches.is_present("lenient"),
+    };
+    let jobs = match matches.value_of("jobs") {
+        Some(v) => v
+            .parse::<usize>()
+            .ok()
+            .filter(|v| *v > 0)
+            .expect("Invalid number of jobs."),
+        None => num_cpus::get(),
+    };
+
+    let manifest_path = match matches.value_of("manifest") {
+        Some(v) => Some(PathBuf::from(v)),
+        None => output_path.map(|v| v.join(MANIFEST_FILE_NAME)),
+    };
+    // Anything that changes the output goes in here, as entries are only valid for the same
+    // options.
+    let fingerprint = format!(
+        "baker {}, format version {}, {:?}, {:?}, simplify {:?}, strict {}",
+        env!("CARGO_PKG_VERSION"),
+        FORMAT_VERSION,
+        format,
+        options,
+        simplify_ratio,
+        parse_options.strict
+    );
+    let manifest = match manifest_path.as_ref() {
+        Some(path) => Manifest::load(path, fingerprint).await,
+        None => Manifest::new(fingerprint),
+    };
+
+    let loader = CompositeLoader::from_locations(&ldraw_dirs, None)
+        .await
+        .expect("Could not open LDraw library.");
+    let colors = loader
+        .load_colors()
+        .await
+        .expect("Could not load color definition.");
+
+    let files = match matches.values_of("files") {
+        Some(files) => files.map(PathBuf::from).collect::<Vec<_>>(),
+        None => panic!("Required input files are missing."),
+    };
+
+    let mut paths = join_all(files.into_iter().map(collect_files))
+        .await
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    let progress = ProgressBar::new(paths.len() as u64);
+    progress.set_style(
+        ProgressStyle::with_template(
+            "[{elapsed_precise}] {bar:40} {pos}/{len} ({per_sec}, ETA {eta}) {wide_msg}",
+        )
+        .unwrap(),
+    );
+    progress.enable_steady_tick(Duration::from_millis(200));
+
+    let context = Rc::new(BakeContext {
+        loader,
+        colors: Arc::new(colors),
+        cache: PartCache::new(),
+        output_path: output_path.map(Path::to_path_buf),
+        format,
+        options,
+        parse_options,
+        simplify_ratio,
+        force: matches.is_present("force"),
+        manifest: RefCell::new(manifest),
+        dependency_hashes: RefCell::new(HashMap::new()),
+        progress: progress.clone(),
+    });
+    let semaphore = Arc::new(Semaphore::new(jobs));
+    let unchanged = Rc::new(Cell::new(0usize));
+    let succeeded = Rc::new(RefCell::new(Vec::new()));
+
+    // Documents are parsed and resolved on this thread, while baking and serializing is done
+    // on the blocking pool, so that up to `jobs` files are baked at the same time.
+    let local = LocalSet::new();
+    for path in paths {
+        let context = Rc::clone(&context);
+        let semaphore = Arc::clone(&semaphore);
+        let unchanged = Rc::clone(&unchanged);
+        let succeeded = Rc::clone(&succeeded);
+
+        local.spawn_local(async move {
+            let _permit = semaphore.acquire_owned().await.unwrap();
+
+            context
+                .progress
+                .set_message(path.to_string_lossy().to_string());
+            match bake(&context, &path).await {
+                BakeOutcome::Baked => succeeded.borrow_mut().push(path),
+                BakeOutcome::Unchanged => {
+                    unchanged.set(unchanged.get() + 1);
+                    succeeded.borrow_mut().push(path);
                 }
-            } else {
-                tasks.push(bake(
-                    &loader,
-                    &colors,
-                    Arc::clone(&cache),
-                    path,
-                    &output_path,
-                ));
+                BakeOutcome::Failed => {}
             }
+            context.progress.inc(1);
+        });
+    }
+    local.await;
+
+    progress.finish_with_message(format!("Done. {} files were unchanged.", unchanged.get()));
+
+    if let Some(path) = manifest_path.as_ref() {
+        let serialized = context.manifest.borrow().to_vec();
+        if let Err(err) = fs::write(path, serialized).await {
+            println!(
+                "Could not write manifest {}: {}",
+                path.to_str().unwrap(),
+                err
+            );
         }
-    } else {
-        panic!("Required input files are missing.");
     }
 
-    let cpus = num_cpus::get();
-    let tasks = tasks
-        .into_iter()
-        .chunks(cpus)
-        .into_iter()
-        .map(|chunk| chunk.collect())
-        .collect::<Vec<Vec<_>>>();
-    for items in tasks {
-        join_all(items).await;
+    if let Some(path) = pack_path.as_ref() {
+        let mut paths = succeeded.take();
+        paths.sort();
+        match write_pack(&context, &paths, path).await {
+            Ok(()) => println!(
+                "Packed {} parts into {}.",
+                paths.len(),
+                path.to_str().unwrap()
+            ),
+            Err(err) => println!("Could not write pack {}: {}", path.to_str().unwrap(), err),
+        }
     }
 
-    let collected = cache
-        .write()
-        .unwrap()
+    // Parts are collected only after every bake is done, as documents in the cache are shared
+    // between bakes in flight.
+    let collected = context
+        .cache
         .collect(CacheCollectionStrategy::PartsAndPrimitives);
     println!("Collected {} entries.", collected);
 }
 
-async fn bake<L: LibraryLoader>(
-    loader: &L,
-    colors: &ColorCatalog,
-    cache: Arc<RwLock<PartCache>>,
-    path: PathBuf,
-    output_path: &Option<&Path>,
-) {
-    println!("{}", path.to_str().unwrap());
+struct BakeContext<L> {
+    loader: L,
+    colors: Arc<ColorCatalog>,
+    cache: PartCache,
+    output_path: Option<PathBuf>,
+    format: OutputFormat,
+    options: BakeOptions,
+    parse_options: ParseOptions,
+    simplify_ratio: Option<f32>,
+    force: bool,
+    manifest: RefCell<Manifest>,
+    // Hashes of library files, as most of them are shared by many parts.
+    dependency_hashes: RefCell<HashMap<PartAlias, u32>>,
+    progress: ProgressBar,
+}
+
+impl<L> BakeContext<L> {
+    fn outpath(&self, path: &Path) -> PathBuf {
+        match self.output_path.as_ref() {
+            Some(e) => e.to_path_buf().join(format!(
+                "{}.{}",
+                path.file_name().unwrap().to_str().unwrap(),
+                self.format.extension()
+            )),
+            None => {
+                let mut path_buf = path.to_path_buf();
+                path_buf.set_extension(match path.extension() {
+                    Some(e) => format!("{}.{}", e.to_str().unwrap(), self.format.extension()),
+                    None => String::from(self.format.extension()),
+                });
+                path_buf
+            }
+        }
+    }
+}
+
+enum BakeOutcome {
+    Baked,
+    Unchanged,
+    Failed,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum OutputFormat {
+    Bincode(PartWriteOptions),
+    Json,
+    Obj(ObjExportOptions),
+    ThreeMf,
+}
+
+impl OutputFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Bincode(_) => "part",
+            OutputFormat::Json => "part.json",
+            OutputFormat::Obj(_) => "obj",
+            OutputFormat::ThreeMf => "3mf",
+        }
+    }
+
+    // Returns contents of each file to write, as some formats span more than one.
+    fn serialize(
+        &self,
+        part: &Part,
+        colors: &ColorCatalog,
+        outpath: &Path,
+    ) -> Result<Vec<(PathBuf, Vec<u8>)>, String> {
+        match self {
+            OutputFormat::Bincode(options) => encode_part(part, options)
+                .map(|v| vec![(outpath.to_path_buf(), v)])
+                .map_err(|e| e.to_string()),
+            OutputFormat::Json => serde_json::to_vec_pretty(&PartDump::from(part))
+                .map(|v| vec![(outpath.to_path_buf(), v)])
+                .map_err(|e| e.to_string()),
+            OutputFormat::Obj(options) => {
+                let mtl_path = outpath.with_extension("mtl");
+                let mtl_name = mtl_path.file_name().unwrap().to_string_lossy().to_string();
+                let export = export_part(part, colors, *options, &mtl_name);
+                Ok(vec![
+                    (outpath.to_path_buf(), export.obj.into_bytes()),
+                    (mtl_path, export.mtl.into_bytes()),
+                ])
+            }
+            OutputFormat::ThreeMf => Ok(vec![(
+                outpath.to_path_buf(),
+                threemf::export_part(part, colors, Default::default()),
+            )]),
+        }
+    }
+}
+
+// Directories are walked level by level, reading every directory of a level at the same time.
+async fn collect_files(path: PathBuf) -> Vec<PathBuf> {
+    if !fs::try_exists(&path).await.unwrap_or(false) {
+        panic!("Path {} does not exists.", path.to_str().unwrap());
+    } else if !path.is_dir() {
+        return vec![path];
+    }
+
+    let mut result = vec![];
+    let mut directories = vec![path];
+    while !directories.is_empty() {
+        let entries = join_all(directories.drain(..).map(read_directory)).await;
+        for path in entries.into_iter().flatten() {
+            if path.is_dir() {
+                directories.push(path);
+                continue;
+            }
+            let ext = match path.extension() {
+                Some(e) => e.to_str().unwrap().to_lowercase(),
+                None => continue,
+            };
+            if ext == "dat" || ext == "ldr" {
+                result.push(path);
+            }
+        }
+    }
+
+    result
+}
 
-    let file = match File::open(path.clone()).await {
+async fn read_directory(path: PathBuf) -> Vec<PathBuf> {
+    let mut dir = ReadDirStream::new(
+        fs::read_dir(&path)
+            .await
+            .expect("Could not read directory."),
+    );
+    let mut result = vec![];
+    while let Some(entry) = dir.next().await {
+        result.push(entry.unwrap().path());
+    }
+    result
+}
+
+// Parts are copied from files written by bake one by one, rather than kept in memory until all
+// of them are baked.
+async fn write_pack<L>(
+    context: &BakeContext<L>,
+    paths: &[PathBuf],
+    pack_path: &Path,
+) -> Result<(), String> {
+    let mut parts = Vec::with_capacity(paths.len());
+    let mut lengths = Vec::with_capacity(paths.len());
+    for path in paths {
+        let outpath = context.outpath(path);
+        let length = fs::metadata(&outpath)
+            .await
+            .map_err(|e| format!("{}: {}", outpath.to_str().unwrap(), e))?
+            .len();
+        let alias = PartAlias::from(path.file_name().unwrap().to_string_lossy().as_ref());
+        lengths.push((alias, length));
+        parts.push(outpath);
+    }
+
+    let header = encode_pack_header(&context.colors, &lengths).map_err(|e| e.to_string())?;
+    let mut writer = BufWriter::new(File::create(pack_path).await.map_err(|e| e.to_string())?);
+    writer.write_all(&header).await.map_err(|e| e.to_string())?;
+    for outpath in parts {
+        let mut file = File::open(&outpath)
+            .await
+            .map_err(|e| format!("{}: {}", outpath.to_str().unwrap(), e))?;
+        io::copy(&mut file, &mut writer)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    writer.shutdown().await.map_err(|e| e.to_string())
+}
+
+async fn bake<L: LibraryLoader>(context: &BakeContext<L>, path: &Path) -> BakeOutcome {
+    let progress = &context.progress;
+    let source = match fs::read(path).await {
         Ok(v) => v,
         Err(err) => {
-            println!(
+            progress.println(format!(
                 "Could not open document {}: {}",
                 path.to_str().unwrap(),
                 err
-            );
-            return;
+            ));
+            return BakeOutcome::Failed;
         }
     };
 
-    let document = match parse_multipart_document(&mut BufReader::new(file), colors).await {
-        Ok(v) => v,
+    let document = match parse_multipart_document_with_options(
+        &mut &source[..],
+        &context.colors,
+        &context.parse_options,
+    )
+    .await
+    {
+        Ok((document, warnings)) => {
+            for warning in warnings {
+                progress.println(format!(
+                    "Skipped malformed line in {}: {}",
+                    path.to_str().unwrap(),
+                    warning
+                ));
+            }
+            document
+        }
         Err(err) => {
-            println!(
+            progress.println(format!(
                 "Could not parse document {}: {}",
                 path.to_str().unwrap(),
                 err
-            );
-            return;
+            ));
+            return BakeOutcome::Failed;
         }
     };
 
     let resolution_result = resolve_dependencies_multipart(
         &document,
-        Arc::clone(&cache),
-        colors,
-        loader,
+        context.cache.clone(),
+        &context.colors,
+        &context.loader,
         &|alias, result| {
             if let Err(err) = result {
-                println!("Could not open file {}: {}", alias, err);
+                progress.println(format!("Could not open file {}: {}", alias, err));
             }
         },
     )
     .await;
 
-    let part = spawn_blocking(move || {
-        bake_part_from_multipart_document(&document, &resolution_result, false)
+    let outpath = context.outpath(path);
+
+    // Resolving is still needed to tell whether any of the dependencies changed, but it is
+    // cheap next to baking as library files are cached across parts.
+    let mut dependencies = resolution_result
+        .list_dependencies()
+        .into_iter()
+        .collect::<Vec<_>>();
+    dependencies.sort_by(|a, b| a.normalized.cmp(&b.normalized));
+    let mut hasher = crc32fast::Hasher::new();
+    for alias in dependencies {
+        let Some((document, _)) = resolution_result.query(&alias, true) else {
+            continue;
+        };
+        let cached = context.dependency_hashes.borrow().get(&alias).copied();
+        let hash = match cached {
+            Some(v) => v,
+            None => {
+                let hash = hash_document(&document).await;
+                context
+                    .dependency_hashes
+                    .borrow_mut()
+                    .insert(alias.clone(), hash);
+                hash
+            }
+        };
+        hasher.update(alias.normalized.as_bytes());
+        hasher.update(&hash.to_le_bytes());
+    }
+    let entry = ManifestEntry {
+        source: crc32fast::hash(&source),
+        dependencies: hasher.finalize(),
+    };
+    let key = fs::canonicalize(path)
+        .await
+        .unwrap_or_else(|_| path.to_path_buf())
+        .to_string_lossy()
+        .to_string();
+
+    let unchanged = !context.force && context.manifest.borrow().is_unchanged(&key, &entry);
+    if unchanged && fs::try_exists(&outpath).await.unwrap_or(false) {
+        return BakeOutcome::Unchanged;
+    }
+    context.manifest.borrow_mut().remove(&key);
+
+    let options = context.options;
+    let simplify_ratio = context.simplify_ratio;
+    let (part, statistics) = spawn_blocking(move || {
+        let (part, statistics) = bake_part_from_multipart_document_with_statistics(
+            &document,
+            &resolution_result,
+            false,
+            &options,
+        );
+        match simplify_ratio {
+            Some(ratio) => (simplify(&part, ratio), statistics),
+            None => (part, statistics),
+        }
     })
     .await
     .unwrap();
 
-    let outpath = match output_path {
-        Some(e) => e.to_path_buf().join(format!(
-            "{}.part",
-            path.file_name().unwrap().to_str().unwrap()
-        )),
-        None => {
-            let mut path_buf = path.to_path_buf();
-            path_buf.set_extension(match path.extension() {
-                Some(e) => format!("{}.part", e.to_str().unwrap()),
-                None => String::from("part"),
-            });
-            path_buf
-        }
+    if !statistics.is_empty() {
+        progress.println(format!(
+            "Removed {} degenerate and {} duplicate faces and merged {} vertices from {}",
+            statistics.degenerate_faces,
+            statistics.duplicate_faces,
+            statistics.merged_vertices,
+            path.to_str().unwrap()
+        ));
+    }
+
+    // Compressing and exporting can take as long as baking for large parts.
+    let format = context.format;
+    let colors = Arc::clone(&context.colors);
+    let serialized = {
+        let outpath = outpath.clone();
+        spawn_blocking(move || format.serialize(&part, &colors, &outpath))
+            .await
+            .unwrap()
     };
 
-    match serialize(&part) {
-        Ok(serialized) => match File::create(&outpath).await {
-            Ok(file) => {
-                let mut writer = BufWriter::new(file);
-                writer.write_all(&serialized).await.unwrap();
-                writer.shutdown().await.unwrap();
-            }
-            Err(err) => {
-                println!("Could not create {}: {}", outpath.to_str().unwrap(), err);
+    let mut outcome = BakeOutcome::Baked;
+    match serialized {
+        Ok(files) => {
+            for (outpath, serialized) in files {
+                match File::create(&outpath).await {
+                    Ok(file) => {
+                        let mut writer = BufWriter::new(file);
+                        writer.write_all(&serialized).await.unwrap();
+                        writer.shutdown().await.unwrap();
+                    }
+                    Err(err) => {
+                        progress.println(format!(
+                            "Could not create {}: {}",
+                            outpath.to_str().unwrap(),
+                            err
+                        ));
+                        outcome = BakeOutcome::Failed;
+                    }
+                }
             }
-        },
+        }
         Err(err) => {
-            println!("Could not bake part {}: {}", path.to_str().unwrap(), err);
+            progress.println(format!(
+                "Could not bake part {}: {}",
+                path.to_str().unwrap(),
+                err
+            ));
+            outcome = BakeOutcome::Failed;
         }
     };
 
-    cache
-        .write()
-        .unwrap()
-        .collect(CacheCollectionStrategy::Parts);
+    if let BakeOutcome::Baked = outcome {
+        context.manifest.borrow_mut().insert(key, entry);
+    }
+
+    outcome
 }
@@ -0,0 +1,77 @@
+use std::{collections::BTreeMap, path::Path};
+
+use ldraw::{document::MultipartDocument, writer::LDrawWriter};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+pub const MANIFEST_FILE_NAME: &str = "baker-manifest.json";
+
+// What a file was baked from. Dependencies are hashed as well, so that updating the library
+// rebakes the parts using what was changed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub source: u32,
+    pub dependencies: u32,
+}
+
+// Records files baked in previous runs, so that rerunning the baker over the same files only
+// rebakes ones that changed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    // Everything is rebaked if any of the options differ.
+    options: String,
+    files: BTreeMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn new(options: String) -> Self {
+        Manifest {
+            options,
+            files: BTreeMap::new(),
+        }
+    }
+
+    // Reads the manifest at the path, starting over if it is missing, unreadable or written
+    // with other options.
+    pub async fn load(path: &Path, options: String) -> Self {
+        let manifest = match fs::read(path).await {
+            Ok(v) => serde_json::from_slice::<Manifest>(&v).ok(),
+            Err(_) => None,
+        };
+        match manifest {
+            Some(v) if v.options == options => v,
+            _ => Manifest::new(options),
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).unwrap()
+    }
+
+    pub fn is_unchanged(&self, key: &str, entry: &ManifestEntry) -> bool {
+        self.files.get(key) == Some(entry)
+    }
+
+    pub fn insert(&mut self, key: String, entry: ManifestEntry) {
+        self.files.insert(key, entry);
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.files.remove(key);
+    }
+}
+
+// Hashes a document as it would be written. Dependencies are resolved from parsed documents
+// rather than files, and this ignores changes to whitespace and line endings as well.
+pub async fn hash_document(document: &MultipartDocument) -> u32 {
+    let mut buffer = Vec::new();
+    document.body.write(&mut buffer).await.unwrap();
+    // Subparts are kept in a hash map, so they are sorted to write them in the same order.
+    let mut subparts = document.subparts.iter().collect::<Vec<_>>();
+    subparts.sort_by(|a, b| a.0.normalized.cmp(&b.0.normalized));
+    for (alias, subpart) in subparts {
+        buffer.extend_from_slice(format!("0 FILE {}\n", alias.original).as_bytes());
+        subpart.write(&mut buffer).await.unwrap();
+    }
+    crc32fast::hash(&buffer)
+}
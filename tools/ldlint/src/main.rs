@@ -0,0 +1,308 @@
+use std::{collections::HashMap, env, fs::File, io::BufReader, path::PathBuf, process};
+
+use clap::{App, Arg};
+use ldraw::{
+    library::{resolve_dependencies_multipart, LibraryLoader, PartCache},
+    parser::{parse_multipart_document_with_lines, ParseOptions},
+    resolvers::composite::CompositeLoader,
+    PartAlias,
+};
+use ldraw_ir::{
+    analysis::{
+        estimation::CsvPartData,
+        validation::{validate, ValidationIssue, ValidationParams},
+    },
+    geometry::BoundingBox3,
+    model::Model,
+    part::{bake_part_from_multipart_document, PartDimensionQuerier},
+};
+
+struct PartDimensions(HashMap<PartAlias, BoundingBox3>);
+
+impl PartDimensionQuerier<PartAlias> for PartDimensions {
+    fn query_part_dimension(&self, alias: &PartAlias) -> Option<BoundingBox3> {
+        self.0.get(alias).cloned()
+    }
+}
+
+// Issues point at commands of each document, which are shown as lines of the input file.
+// Commands whose line is unknown are shown by their index in the document instead, and the
+// main document may not have a name, in which case the file name is shown.
+fn describe(
+    issue: &ValidationIssue,
+    file_name: &str,
+    line: &dyn Fn(&str, usize) -> Option<usize>,
+) -> String {
+    let name = |document: &String| -> String {
+        if document.is_empty() {
+            file_name.to_string()
+        } else {
+            document.clone()
+        }
+    };
+    let at = |document: &String, command: usize| -> String {
+        match line(document, command) {
+            Some(line) => format!("{}:{}", file_name, line),
+            None => format!("{}, command {}", name(document), command),
+        }
+    };
+
+    match issue {
+        ValidationIssue::UnknownColor {
+            document,
+            command,
+            code,
+        } => format!("{}: Unknown color code {}", at(document, *command), code),
+        ValidationIssue::MissingPart {
+            document,
+            command,
+            name: part,
+        } => format!("{}: Could not find part {}", at(document, *command), part),
+        ValidationIssue::UncertifiedBfc { name: part } => {
+            format!("{}: Subfile is not BFC certified", part)
+        }
+        ValidationIssue::SingularMatrix {
+            document,
+            command,
+            name: part,
+        } => format!(
+            "{}: Placement matrix of {} has zero determinant",
+            at(document, *command),
+            part
+        ),
+        ValidationIssue::DuplicatePlacement {
+            document,
+            command,
+            duplicate_of,
+            name: part,
+        } => format!(
+            "{}: {} is placed at the same position as {}",
+            at(document, *command),
+            part,
+            match line(document, *duplicate_of) {
+                Some(line) => format!("line {}", line),
+                None => format!("command {}", duplicate_of),
+            }
+        ),
+        ValidationIssue::OversizedCoordinate {
+            document,
+            command,
+            value,
+        } => format!(
+            "{}: Coordinate {} is out of range",
+            at(document, *command),
+            value
+        ),
+        ValidationIssue::MissingHeader { document, header } => match header.as_str() {
+            "description" => format!("{}: Missing description", name(document)),
+            _ => format!("{}: Missing {} header", name(document), header),
+        },
+        ValidationIssue::MalformedLine { line, message } => {
+            format!(
+                "{}:{}: Skipped malformed line: {}",
+                file_name, line, message
+            )
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let matches = App::new("ldrlint")
+        .about("Check LDraw models for common mistakes")
+        .arg(
+            Arg::with_name("ldraw_dir")
+                .long("ldraw-dir")
+                .value_name("PATH")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Path to LDraw directory, zipped library or URL of a mirror, tried in the order given"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .takes_value(true)
+                .required(true)
+                .index(1)
+                .help("Input file name"),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .help("Print report in JSON"),
+        )
+        .arg(
+            Arg::with_name("max_coordinate")
+                .long("max-coordinate")
+                .value_name("LDU")
+                .takes_value(true)
+                .help("Maximum allowed distance of coordinates from the origin"),
+        )
+        .arg(
+            Arg::with_name("part_data")
+                .long("part-data")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("CSV file with part,color,weight,price rows for weight and price estimation"),
+        )
+        .arg(
+            Arg::with_name("no_header_check")
+                .long("no-header-check")
+                .help("Don't report missing description, name or author"),
+        )
+        .get_matches();
+
+    let ldraw_dirs = match matches.values_of("ldraw_dir") {
+        Some(v) => v.map(String::from).collect::<Vec<_>>(),
+        None => match env::var("LDRAWDIR") {
+            Ok(v) => vec![v],
+            Err(_) => {
+                panic!("--ldraw-dir option or LDRAWDIR environment variable is required.");
+            }
+        },
+    };
+
+    let input_path = PathBuf::from(matches.value_of("input").unwrap());
+    let loader =
+        match CompositeLoader::from_locations(&ldraw_dirs, input_path.parent().map(PathBuf::from))
+            .await
+        {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("Could not open LDraw library: {}", e);
+                process::exit(2);
+            }
+        };
+
+    let colors = loader.load_colors().await.unwrap();
+    // Malformed lines are reported as issues rather than aborting.
+    let parsed = match tokio::fs::File::open(&input_path).await {
+        Ok(file) => {
+            parse_multipart_document_with_lines(
+                &mut tokio::io::BufReader::new(file),
+                &colors,
+                &ParseOptions { strict: false },
+            )
+            .await
+        }
+        Err(e) => {
+            eprintln!("Could not open {}: {}", input_path.display(), e);
+            process::exit(2);
+        }
+    };
+    let (document, source_lines, parse_errors) = match parsed {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Could not read {}: {}", input_path.display(), e);
+            process::exit(2);
+        }
+    };
+
+    let cache = PartCache::new();
+    let resolution_result =
+        resolve_dependencies_multipart(&document, cache.clone(), &colors, &loader, &|_, _| {})
+            .await;
+
+    let mut params = ValidationParams::default();
+    if let Some(v) = matches.value_of("max_coordinate") {
+        params.max_coordinate = v.parse().unwrap();
+    }
+    params.check_headers = !matches.is_present("no_header_check");
+
+    let mut report = validate(&document, &resolution_result, &params);
+    report.add_parse_errors(&parse_errors);
+
+    let dimensions = PartDimensions(
+        document
+            .list_dependencies()
+            .into_iter()
+            .filter_map(|alias| {
+                resolution_result.query(&alias, true).map(|(part, local)| {
+                    let part = bake_part_from_multipart_document(part, &resolution_result, local);
+                    (alias, part.bounding_box)
+                })
+            })
+            .collect(),
+    );
+    let model = Model::<PartAlias>::from_ldraw_multipart_document(
+        &document,
+        &colors,
+        Some((&loader, cache)),
+    )
+    .await;
+    let summary = model.summary(None, &dimensions);
+
+    let estimate = matches.value_of("part_data").map(|path| {
+        let file = match File::open(path) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("Could not open {}: {}", path, e);
+                process::exit(2);
+            }
+        };
+        match CsvPartData::parse(BufReader::new(file)) {
+            Ok(v) => summary.estimate(&v),
+            Err(e) => {
+                eprintln!("Could not read {}: {}", path, e);
+                process::exit(2);
+            }
+        }
+    });
+
+    if matches.is_present("json") {
+        let mut value = serde_json::to_value(&report).unwrap();
+        value["summary"] = serde_json::to_value(&summary).unwrap();
+        if let Some(estimate) = &estimate {
+            value["estimate"] = serde_json::to_value(estimate).unwrap();
+        }
+        println!("{}", serde_json::to_string_pretty(&value).unwrap());
+    } else {
+        println!("{}", summary);
+        if let Some(estimate) = &estimate {
+            println!(
+                "Estimated weight {:.1} g ({} parts unknown), price {:.2} ({} parts unknown)",
+                estimate.weight, estimate.missing_weight, estimate.price, estimate.missing_price
+            );
+        }
+        let statistics = &report.statistics;
+        println!(
+            "{} documents, {} part references ({} unique), {} steps",
+            statistics.documents,
+            statistics.part_references,
+            statistics.unique_parts,
+            statistics.steps
+        );
+        println!(
+            "{} lines, {} triangles, {} quads, {} optional lines",
+            statistics.lines, statistics.triangles, statistics.quads, statistics.optional_lines
+        );
+        let file_name = input_path
+            .file_name()
+            .map(|v| v.to_string_lossy().to_string())
+            .unwrap_or_default();
+        // Issues name documents by their names, which the subparts are not keyed by.
+        let line = |name: &str, command: usize| -> Option<usize> {
+            if document.body.name == name {
+                source_lines.line(None, command)
+            } else {
+                document
+                    .subparts
+                    .iter()
+                    .find(|(_, v)| v.name == name)
+                    .and_then(|(alias, _)| source_lines.line(Some(alias), command))
+            }
+        };
+        for issue in report.issues.iter() {
+            println!("{}", describe(issue, &file_name, &line));
+        }
+        if report.is_valid() {
+            println!("No issues found.");
+        } else {
+            println!("{} issues found.", report.issues.len());
+        }
+    }
+
+    if !report.is_valid() {
+        process::exit(1);
+    }
+}
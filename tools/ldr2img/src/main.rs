@@ -1,21 +1,38 @@
 use std::{
-    collections::HashMap,
     env,
+    fmt::Display,
     path::{Path, PathBuf},
-    sync::{Arc, RwLock},
+    process,
+    str::FromStr,
 };
 
-use clap::{App, Arg};
+use cgmath::Deg;
+use clap::{App, Arg, ArgMatches};
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    Delay, Frame, RgbaImage,
+};
 use ldraw::{
-    library::{resolve_dependencies_multipart, PartCache},
-    parser::{parse_color_definitions, parse_multipart_document},
-    resolvers::local::LocalLoader,
+    color::{ColorCatalog, Rgba},
+    library::LibraryLoader,
+    parser::ParseOptions,
+    resolvers::composite::CompositeLoader,
     PartAlias,
 };
-use ldraw_ir::{model::Model, part::bake_part_from_multipart_document};
-use ldraw_olr::{context::Context, ops::Ops};
-use ldraw_renderer::part::{Part, PartQuerier};
-use tokio::{fs::File, io::BufReader};
+use ldraw_ir::{
+    model::{Object, ObjectInstance},
+    part::BakeOptions,
+    steps::StepInferenceParams,
+};
+use ldraw_olr::{
+    batch::{load_batch, BatchModel},
+    context::{Context, ContextOptions},
+    ops::{CameraOptions, CameraProjection, Ops, RenderOptions},
+};
+use ldraw_renderer::{
+    display_list::DisplayList, projection::BLENDER_IMPORT_SCALE,
+    util::calculate_model_bounding_box, Entity,
+};
 
 #[tokio::main]
 async fn main() {
@@ -26,7 +43,9 @@ async fn main() {
                 .long("ldraw-dir")
                 .value_name("PATH")
                 .takes_value(true)
-                .help("Path to LDraw directory"),
+                .multiple(true)
+                .number_of_values(1)
+                .help("Path to LDraw directory, zipped library or URL of a mirror, tried in the order given"),
         )
         .arg(
             Arg::with_name("output")
@@ -53,18 +72,163 @@ async fn main() {
                 .short("m")
                 .help("Number of samples"),
         )
+        .arg(
+            Arg::with_name("transparent")
+                .short("t")
+                .long("transparent")
+                .help("Render with transparent background"),
+        )
+        .arg(
+            Arg::with_name("background")
+                .short("b")
+                .long("background")
+                .value_name("COLOR")
+                .takes_value(true)
+                .help("Background color in #RRGGBB form or LDraw color code/name"),
+        )
+        .arg(
+            Arg::with_name("crop-to-content")
+                .short("c")
+                .long("crop-to-content")
+                .help("Crop output image to the rendered content"),
+        )
+        .arg(
+            Arg::with_name("latitude")
+                .long("latitude")
+                .value_name("DEGREES")
+                .takes_value(true)
+                .default_value("30")
+                .allow_hyphen_values(true)
+                .help("Camera elevation angle"),
+        )
+        .arg(
+            Arg::with_name("longitude")
+                .long("longitude")
+                .value_name("DEGREES")
+                .takes_value(true)
+                .default_value("45")
+                .allow_hyphen_values(true)
+                .help("Camera rotation angle around vertical axis"),
+        )
+        .arg(
+            Arg::with_name("distance")
+                .long("distance")
+                .value_name("MULTIPLIER")
+                .takes_value(true)
+                .default_value("1.0")
+                .help("Camera distance multiplier"),
+        )
+        .arg(
+            Arg::with_name("projection")
+                .long("projection")
+                .takes_value(true)
+                .possible_values(&["orthographic", "perspective"])
+                .default_value("orthographic")
+                .help("Camera projection mode"),
+        )
+        .arg(
+            Arg::with_name("fov")
+                .long("fov")
+                .value_name("DEGREES")
+                .takes_value(true)
+                .default_value("45")
+                .help("Vertical field of view for perspective projection"),
+        )
+        .arg(
+            Arg::with_name("turntable")
+                .long("turntable")
+                .value_name("FRAMES")
+                .takes_value(true)
+                .help("Render turntable animation with given number of frames into GIF or numbered PNG files"),
+        )
+        .arg(
+            Arg::with_name("frame-delay")
+                .long("frame-delay")
+                .value_name("MILLISECONDS")
+                .takes_value(true)
+                .default_value("50")
+                .help("Delay between turntable animation frames"),
+        )
+        .arg(
+            Arg::with_name("steps")
+                .long("steps")
+                .help("Render each building step into numbered files with previous steps dimmed"),
+        )
+        .arg(
+            Arg::with_name("dim")
+                .long("dim")
+                .value_name("FACTOR")
+                .takes_value(true)
+                .default_value("0.6")
+                .help("Amount of fading applied to parts placed in previous steps"),
+        )
+        .arg(
+            Arg::with_name("export-camera")
+                .long("export-camera")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Write the camera as a Blender script (.py) or POV-Ray include (.pov/.inc)"),
+        )
+        .arg(
+            Arg::with_name("profile-gpu")
+                .long("profile-gpu")
+                .help("Print GPU time spent on each pass"),
+        )
+        .arg(
+            Arg::with_name("backend")
+                .long("backend")
+                .value_name("NAME")
+                .takes_value(true)
+                .help("Graphics backend to render with: auto, vulkan, metal, dx12 or gl"),
+        )
+        .arg(
+            Arg::with_name("software")
+                .long("software")
+                .help("Render with a software adapter such as llvmpipe, for machines without a GPU"),
+        )
+        .arg(
+            Arg::with_name("lenient")
+                .long("lenient")
+                .help("Skip malformed lines in the model instead of failing"),
+        )
         .get_matches();
 
-    let ldrawdir = match matches.value_of("ldraw_dir") {
-        Some(v) => v.to_string(),
+    // Checked before anything is rendered, so that a typo doesn't waste the rendering.
+    let export_camera_to = matches.value_of("export-camera").map(|v| {
+        let path = PathBuf::from(v);
+        match CameraFormat::from_path(&path) {
+            Some(format) => (path, format),
+            None => {
+                eprintln!(
+                    "Unknown camera format for {}. Use .py, .pov or .inc.",
+                    path.display()
+                );
+                process::exit(1);
+            }
+        }
+    });
+
+    let ldraw_dirs = match matches.values_of("ldraw_dir") {
+        Some(v) => v.map(String::from).collect::<Vec<_>>(),
         None => match env::var("LDRAWDIR") {
-            Ok(v) => v,
+            Ok(v) => vec![v],
             Err(_) => {
                 panic!("--ldraw-dir option or LDRAWDIR environment variable is required.");
             }
         },
     };
-    let ldraw_path = PathBuf::from(&ldrawdir);
+    let input = matches.value_of("input").unwrap();
+    let input_path = PathBuf::from(input);
+
+    let mut loader = CompositeLoader::from_locations(
+        &ldraw_dirs,
+        Some(PathBuf::from(input_path.parent().unwrap())),
+    )
+    .await
+    .unwrap();
+    loader.set_parse_options(ParseOptions {
+        strict: !matches.is_present("lenient"),
+    });
 
     let size = matches.value_of("size").unwrap().parse::<u32>().unwrap();
     let sample_count = if matches.is_present("without-multisample") {
@@ -73,68 +237,310 @@ async fn main() {
         4
     };
 
-    let mut context = Context::new(size, size, sample_count).await.unwrap();
+    let mut context_options = ContextOptions {
+        software: matches.is_present("software"),
+        ..Default::default()
+    };
+    if let Some(backend) = matches.value_of("backend") {
+        context_options.backend = match backend.parse() {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        };
+    }
+    let mut context = match Context::with_options(size, size, sample_count, &context_options).await
+    {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Could not create rendering context: {}", e);
+            process::exit(2);
+        }
+    };
+    if matches.is_present("profile-gpu") && !context.enable_gpu_profiling() {
+        println!("GPU profiling is not supported on this device.");
+    }
 
-    let colors = parse_color_definitions(&mut BufReader::new(
-        File::open(ldraw_path.join("LDConfig.ldr")).await.unwrap(),
-    ))
-    .await
-    .unwrap();
+    let colors = loader.load_colors().await.unwrap();
+
+    let camera = CameraOptions {
+        latitude: Deg(parse_arg(&matches, "latitude")),
+        longitude: Deg(parse_arg(&matches, "longitude")),
+        distance: parse_arg(&matches, "distance"),
+        projection: match matches.value_of("projection") {
+            Some("perspective") => CameraProjection::Perspective {
+                fov: Deg(parse_arg(&matches, "fov")),
+            },
+            _ => CameraProjection::Orthographic,
+        },
+        ..Default::default()
+    };
+
+    let mut options = RenderOptions {
+        transparent: matches.is_present("transparent"),
+        crop_to_content: matches.is_present("crop-to-content"),
+        camera,
+        ..Default::default()
+    };
+    if let Some(background) = matches.value_of("background") {
+        options.background = match parse_color(background, &colors) {
+            Some(v) => v,
+            None => panic!("Invalid background color: {}", background),
+        };
+    }
 
-    let input = matches.value_of("input").unwrap();
     let output = matches.value_of("output").unwrap_or("image.png");
 
-    let document = parse_multipart_document(
-        &mut BufReader::new(File::open(&input).await.unwrap()),
+    let batch = load_batch(
+        &context,
+        &loader,
         &colors,
+        vec![input_path],
+        &BakeOptions::default(),
     )
-    .await
-    .unwrap();
+    .await;
+    let parts = batch.parts;
+    let BatchModel {
+        document,
+        mut model,
+        ..
+    } = batch.models.into_iter().next().unwrap().1.unwrap();
 
-    let input_path = PathBuf::from(input);
+    // Camera stored in the document is honored unless overridden from the command line.
+    if let Some(preferred) = document.body.camera() {
+        let camera = &mut options.camera;
+        if matches.occurrences_of("latitude") == 0 {
+            camera.latitude = preferred.latitude;
+        }
+        if matches.occurrences_of("longitude") == 0 {
+            camera.longitude = preferred.longitude;
+        }
+        if matches.occurrences_of("distance") == 0 {
+            camera.distance = preferred.distance;
+        }
+        if let Some(fov) = preferred.fov {
+            let fov = if matches.occurrences_of("fov") == 0 {
+                fov
+            } else {
+                Deg(parse_arg(&matches, "fov"))
+            };
+            match camera.projection {
+                CameraProjection::Perspective { .. } => {
+                    camera.projection = CameraProjection::Perspective { fov };
+                }
+                CameraProjection::Orthographic if matches.occurrences_of("projection") == 0 => {
+                    camera.projection = CameraProjection::Perspective { fov };
+                }
+                _ => (),
+            }
+        }
+    }
 
-    let loader = LocalLoader::new(
-        Some(ldraw_path),
-        Some(PathBuf::from(input_path.parent().unwrap())),
-    );
+    if matches.is_present("steps") {
+        if !model.has_steps(None) {
+            model.infer_steps(&parts, &StepInferenceParams::default());
+        }
 
-    let cache = Arc::new(RwLock::new(PartCache::new()));
-    let resolution_result =
-        resolve_dependencies_multipart(&document, Arc::clone(&cache), &colors, &loader, &|_, _| {})
-            .await;
+        let dim = matches.value_of("dim").unwrap().parse::<f32>().unwrap();
+        let steps = model
+            .objects
+            .split(|v| matches!(v.data, ObjectInstance::Step))
+            .filter(|v| v.iter().any(is_placeable))
+            .collect::<Vec<_>>();
+
+        // Every step is framed with the whole model so that the camera stays still.
+        let bounding_box = calculate_model_bounding_box(&model, None, &parts);
+        let mut images = Vec::new();
+        for (index, step) in steps.iter().enumerate() {
+            let mut display_list = Entity::new(DisplayList::new());
+            for previous in steps[..index].iter() {
+                DisplayList::insert_objects(&mut display_list, &model, previous, &colors, |c| {
+                    c.blend(options.background, dim)
+                });
+            }
+            DisplayList::insert_objects(&mut display_list, &model, step, &colors, Clone::clone);
 
-    struct PartsPoolImpl(HashMap<PartAlias, Part>);
-    impl PartQuerier<PartAlias> for PartsPoolImpl {
-        fn get(&self, key: &PartAlias) -> Option<&Part> {
-            self.0.get(key)
+            let ops = Ops::new(&mut context);
+            images.push(
+                ops.render_scene(&mut display_list, &parts, bounding_box.clone(), &options)
+                    .await,
+            );
+            report_gpu_timings(&mut context, Some(index));
         }
+
+        save_sequence(&images, Path::new(output));
+    } else if matches.is_present("turntable") {
+        let frames = parse_arg::<u32>(&matches, "turntable");
+        if frames == 0 {
+            eprintln!("--turntable needs at least one frame.");
+            process::exit(1);
+        }
+        let delay = parse_arg::<u32>(&matches, "frame-delay");
+
+        let mut frame_options = RenderOptions {
+            crop_to_content: false,
+            ..options.clone()
+        };
+        frame_options.camera.fit_to_sphere = true;
+
+        let mut images = Vec::new();
+        for i in 0..frames {
+            frame_options.camera.longitude =
+                options.camera.longitude + Deg(360.0 * i as f32 / frames as f32);
+            let ops = Ops::new(&mut context);
+            images.push(
+                ops.render_model(&model, None, &parts, &colors, &frame_options)
+                    .await,
+            );
+            report_gpu_timings(&mut context, Some(i as usize));
+        }
+
+        if options.crop_to_content {
+            let bounds = images
+                .iter()
+                .filter_map(|image| options.content_bounds(image))
+                .reduce(|(x1, y1, w1, h1), (x2, y2, w2, h2)| {
+                    let x = x1.min(x2);
+                    let y = y1.min(y2);
+                    (x, y, (x1 + w1).max(x2 + w2) - x, (y1 + h1).max(y2 + h2) - y)
+                });
+            if let Some((x, y, width, height)) = bounds {
+                images = images
+                    .iter()
+                    .map(|image| image::imageops::crop_imm(image, x, y, width, height).to_image())
+                    .collect();
+            }
+        }
+
+        save_animation(images, Path::new(output), delay);
+    } else {
+        let image = {
+            let ops = Ops::new(&mut context);
+            ops.render_model(&model, None, &parts, &colors, &options)
+                .await
+        };
+        report_gpu_timings(&mut context, None);
+        image.save(Path::new(output)).unwrap();
     }
 
-    let parts = document
-        .list_dependencies()
-        .into_iter()
-        .filter_map(|alias| {
-            resolution_result.query(&alias, true).map(|(part, local)| {
-                (
-                    alias.clone(),
-                    Part::new(
-                        &bake_part_from_multipart_document(part, &resolution_result, local),
-                        &context.device,
-                        &colors,
-                    ),
-                )
-            })
-        })
-        .collect::<HashMap<_, _>>();
-
-    let parts = PartsPoolImpl(parts);
-
-    let model =
-        Model::from_ldraw_multipart_document(&document, &colors, Some((&loader, cache))).await;
-
-    let image = {
-        let ops = Ops::new(&mut context);
-        ops.render_model(&model, None, &parts, &colors).await
+    if let Some((path, format)) = export_camera_to {
+        export_camera(&context, &path, format);
+    }
+}
+
+// Exits naming the option if its value could not be read.
+fn parse_arg<T: FromStr>(matches: &ArgMatches, name: &str) -> T
+where
+    T::Err: Display,
+{
+    let value = matches.value_of(name).unwrap();
+    match value.parse() {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Invalid value {} for --{}: {}", value, name, e);
+            process::exit(1);
+        }
+    }
+}
+
+fn report_gpu_timings(context: &mut Context, frame: Option<usize>) {
+    if let Some(timings) = context.collect_gpu_timings() {
+        match frame {
+            Some(frame) => println!("Frame {}: {}", frame + 1, timings),
+            None => println!("{}", timings),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum CameraFormat {
+    Blender,
+    PovRay,
+}
+
+impl CameraFormat {
+    fn from_path(path: &Path) -> Option<Self> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+        match extension.as_deref() {
+            Some("py") => Some(CameraFormat::Blender),
+            Some("pov" | "inc") => Some(CameraFormat::PovRay),
+            _ => None,
+        }
+    }
+}
+
+fn export_camera(context: &Context, path: &Path, format: CameraFormat) {
+    let Some(camera) = context.projection().describe_camera() else {
+        panic!("Could not derive camera from the current projection.");
     };
-    image.save(&Path::new(output)).unwrap();
+    let content = match format {
+        CameraFormat::Blender => camera.to_blender_script(BLENDER_IMPORT_SCALE),
+        CameraFormat::PovRay => camera.to_povray(),
+    };
+    std::fs::write(path, content).unwrap();
+}
+
+fn save_animation(images: Vec<RgbaImage>, path: &Path, delay: u32) {
+    let is_gif = path
+        .extension()
+        .map(|e| e.eq_ignore_ascii_case("gif"))
+        .unwrap_or(false);
+
+    if is_gif {
+        let mut encoder = GifEncoder::new(std::fs::File::create(path).unwrap());
+        encoder.set_repeat(Repeat::Infinite).unwrap();
+        encoder
+            .encode_frames(
+                images.into_iter().map(|image| {
+                    Frame::from_parts(image, 0, 0, Delay::from_numer_denom_ms(delay, 1))
+                }),
+            )
+            .unwrap();
+    } else {
+        save_sequence(&images, path);
+    }
+}
+
+fn save_sequence(images: &[RgbaImage], path: &Path) {
+    let stem = path.file_stem().unwrap().to_str().unwrap();
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("png");
+    for (index, image) in images.iter().enumerate() {
+        image
+            .save(path.with_file_name(format!("{}_{:04}.{}", stem, index, extension)))
+            .unwrap();
+    }
+}
+
+fn is_placeable(object: &Object<PartAlias>) -> bool {
+    matches!(
+        object.data,
+        ObjectInstance::Part(_) | ObjectInstance::PartGroup(_)
+    )
+}
+
+fn parse_color(value: &str, colors: &ColorCatalog) -> Option<Rgba> {
+    if let Some(hex) = value.strip_prefix('#') {
+        return match hex.len() {
+            6 => u32::from_str_radix(hex, 16)
+                .ok()
+                .map(|v| Rgba::from_value(0xff00_0000 | v)),
+            8 => u32::from_str_radix(hex, 16)
+                .ok()
+                .map(|v| Rgba::from_value(v.rotate_right(8))),
+            _ => None,
+        };
+    }
+
+    if let Ok(code) = value.parse::<u32>() {
+        return colors.get(&code).map(|c| c.color);
+    }
+
+    colors
+        .values()
+        .find(|c| c.name.eq_ignore_ascii_case(value))
+        .map(|c| c.color)
 }
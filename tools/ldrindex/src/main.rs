@@ -0,0 +1,172 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process,
+};
+
+use clap::{App, AppSettings, Arg, SubCommand};
+use ldraw::{
+    index::{write_library_index, LibraryIndex, INDEX_FILE_NAME},
+    library::search::{browse, categories, search, SearchHit, SearchOptions},
+    resolvers::local::LocalLoader,
+};
+use tokio::{
+    fs::File,
+    io::{AsyncWriteExt, BufWriter},
+};
+
+#[tokio::main]
+async fn main() {
+    let matches = App::new("ldrindex")
+        .about("Build and search an index of LDraw parts")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("ldraw_dir")
+                .long("ldraw-dir")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Path to LDraw directory"),
+        )
+        .subcommand(
+            SubCommand::with_name("build")
+                .about("Index every part and primitive in the library")
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .value_name("PATH")
+                        .takes_value(true)
+                        .help("Output file name, defaults to parts.idx in the LDraw directory"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("search")
+                .about("Find parts by number, description or keywords")
+                .arg(
+                    Arg::with_name("query")
+                        .takes_value(true)
+                        .required(true)
+                        .multiple(true)
+                        .help("Words to look for"),
+                )
+                .arg(
+                    Arg::with_name("category")
+                        .long("category")
+                        .value_name("NAME")
+                        .takes_value(true)
+                        .help("Only search parts in the category"),
+                )
+                .arg(
+                    Arg::with_name("all")
+                        .long("all")
+                        .help("Include primitives, subparts and obsolete parts"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("categories")
+                .about("List categories, or parts in a category")
+                .arg(
+                    Arg::with_name("category")
+                        .takes_value(true)
+                        .index(1)
+                        .help("Category to list parts of"),
+                ),
+        )
+        .get_matches();
+
+    let ldrawdir = match matches.value_of("ldraw_dir") {
+        Some(v) => v.to_string(),
+        None => match env::var("LDRAWDIR") {
+            Ok(v) => v,
+            Err(_) => {
+                panic!("--ldraw-dir option or LDRAWDIR environment variable is required.");
+            }
+        },
+    };
+    let ldrawdir = PathBuf::from(&ldrawdir);
+    let mut loader = LocalLoader::new(Some(ldrawdir.clone()), None);
+
+    match matches.subcommand() {
+        ("build", Some(matches)) => {
+            let index = build_index(&loader, &ldrawdir).await;
+
+            let output = matches
+                .value_of("output")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| ldrawdir.join(INDEX_FILE_NAME));
+            let result = async {
+                let mut writer = BufWriter::new(File::create(&output).await?);
+                write_library_index(&index, &mut writer).await?;
+                writer.shutdown().await
+            }
+            .await;
+            if let Err(e) = result {
+                eprintln!("Could not write {}: {}", output.display(), e);
+                process::exit(2);
+            }
+            println!("Indexed {} files into {}", index.len(), output.display());
+        }
+        (command, Some(matches)) => {
+            let index = load_index(&mut loader, &ldrawdir).await;
+            let options = SearchOptions {
+                category: matches.value_of("category").map(String::from),
+                include_primitives: matches.is_present("all"),
+                include_hidden: matches.is_present("all"),
+                limit: None,
+            };
+
+            match (command, matches.value_of("category")) {
+                ("search", _) => {
+                    let query = matches
+                        .values_of("query")
+                        .unwrap()
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    print_hits(search(&index, &query, &options));
+                }
+                (_, Some(category)) => print_hits(browse(&index, category, &options)),
+                (_, None) => {
+                    for (category, count) in categories(&index, &options) {
+                        println!("{:<24} {}", category, count);
+                    }
+                }
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+async fn build_index(loader: &LocalLoader, ldrawdir: &Path) -> LibraryIndex {
+    match loader.build_index().await {
+        Ok((index, skipped)) => {
+            for (path, e) in skipped {
+                eprintln!("Skipped {}: {}", path, e);
+            }
+            index
+        }
+        Err(e) => {
+            eprintln!("Could not index {}: {}", ldrawdir.display(), e);
+            process::exit(2);
+        }
+    }
+}
+
+// Searching a library without an index saved walks through it every time.
+async fn load_index(loader: &mut LocalLoader, ldrawdir: &Path) -> LibraryIndex {
+    match loader.load_index().await {
+        Ok(true) => loader.index().cloned().unwrap_or_default(),
+        Ok(false) => build_index(loader, ldrawdir).await,
+        Err(e) => {
+            eprintln!("Could not read index: {}", e);
+            process::exit(2);
+        }
+    }
+}
+
+fn print_hits(hits: Vec<SearchHit>) {
+    for hit in hits {
+        println!(
+            "{:<24} {:<16} {}",
+            hit.alias, hit.entry.category, hit.entry.description
+        );
+    }
+}
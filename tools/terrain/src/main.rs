@@ -0,0 +1,238 @@
+use std::{path::PathBuf, process};
+
+use clap::{App, Arg};
+use image::{imageops::FilterType, GrayImage};
+use ldraw::{
+    build::ModelBuilder,
+    units::{LDU_PER_BRICK, LDU_PER_STUD},
+    writer::LDrawWriter,
+    Matrix4, Vector3,
+};
+use tokio::fs;
+
+const BRICK_1X1: &str = "3005.dat";
+const BRICK_2X2: &str = "3003.dat";
+
+// Blue, tan, green, dark bluish gray and white, from the bottom up.
+const DEFAULT_COLORS: &str = "1,19,2,72,15";
+
+// Number of bricks stacked at each stud of the terrain, at least one everywhere.
+struct HeightField {
+    width: u32,
+    depth: u32,
+    heights: Vec<u32>,
+}
+
+impl HeightField {
+    // Brighter pixels stand taller. The image is scaled to width studs across, keeping its
+    // aspect ratio.
+    fn from_image(image: &GrayImage, width: u32, layers: u32) -> Self {
+        let depth =
+            ((image.height() as f32 * width as f32 / image.width() as f32).round() as u32).max(1);
+        let scaled = image::imageops::resize(image, width, depth, FilterType::Triangle);
+        let heights = scaled
+            .pixels()
+            .map(|v| 1 + (v.0[0] as f32 / 255.0 * (layers - 1) as f32).round() as u32)
+            .collect();
+
+        HeightField {
+            width,
+            depth,
+            heights,
+        }
+    }
+
+    fn is_filled(&self, x: u32, z: u32, layer: u32) -> bool {
+        x < self.width && z < self.depth && self.heights[(z * self.width + x) as usize] > layer
+    }
+}
+
+// Covers studs of a layer with 2x2 bricks where they fit, and 1x1 bricks elsewhere. Bricks
+// are given as their size along with the stud at their corner.
+fn tile_layer(field: &HeightField, layer: u32) -> Vec<(u32, u32, u32)> {
+    let mut covered = vec![false; (field.width * field.depth) as usize];
+    let mut bricks = Vec::new();
+    let is_free = |covered: &[bool], x: u32, z: u32| {
+        field.is_filled(x, z, layer) && !covered[(z * field.width + x) as usize]
+    };
+
+    for z in 0..field.depth {
+        for x in 0..field.width {
+            if !is_free(&covered, x, z) {
+                continue;
+            }
+            let size = if is_free(&covered, x + 1, z)
+                && is_free(&covered, x, z + 1)
+                && is_free(&covered, x + 1, z + 1)
+            {
+                2
+            } else {
+                1
+            };
+            for dz in 0..size {
+                for dx in 0..size {
+                    covered[((z + dz) * field.width + x + dx) as usize] = true;
+                }
+            }
+            bricks.push((size, x, z));
+        }
+    }
+
+    bricks
+}
+
+fn parse_number<T: std::str::FromStr>(value: Option<&str>, name: &str) -> Option<T> {
+    value.map(|v| match v.parse::<T>() {
+        Ok(v) => v,
+        Err(_) => {
+            eprintln!("Invalid {}: {}", name, v);
+            process::exit(1);
+        }
+    })
+}
+
+#[tokio::main]
+async fn main() {
+    let matches = App::new("terrain")
+        .about("Build terrain of stacked bricks from a grayscale heightmap")
+        .arg(
+            Arg::with_name("heightmap")
+                .takes_value(true)
+                .required(true)
+                .help("Image to read heights from, brighter being higher"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Output file name, defaults to the heightmap with .mpd extension"),
+        )
+        .arg(
+            Arg::with_name("width")
+                .short("w")
+                .long("width")
+                .value_name("STUDS")
+                .takes_value(true)
+                .help("Width of the terrain in studs, defaults to 32"),
+        )
+        .arg(
+            Arg::with_name("layers")
+                .short("l")
+                .long("layers")
+                .value_name("COUNT")
+                .takes_value(true)
+                .help("Bricks stacked at the highest point, defaults to 12"),
+        )
+        .arg(
+            Arg::with_name("colors")
+                .short("c")
+                .long("colors")
+                .value_name("CODES")
+                .takes_value(true)
+                .help("Comma separated color codes of layers from the bottom up, spread evenly"),
+        )
+        .get_matches();
+
+    let input = PathBuf::from(matches.value_of("heightmap").unwrap());
+    let output = matches
+        .value_of("output")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| input.with_extension("mpd"));
+    let width = parse_number::<u32>(matches.value_of("width"), "width")
+        .unwrap_or(32)
+        .max(1);
+    let layers = parse_number::<u32>(matches.value_of("layers"), "layer count")
+        .unwrap_or(12)
+        .max(1);
+    let colors = matches
+        .value_of("colors")
+        .unwrap_or(DEFAULT_COLORS)
+        .split(',')
+        .map(|v| parse_number::<u32>(Some(v.trim()), "color code").unwrap())
+        .collect::<Vec<_>>();
+
+    let image = match image::open(&input) {
+        Ok(v) => v.to_luma8(),
+        Err(e) => {
+            eprintln!("Could not read {}: {}", input.display(), e);
+            process::exit(2);
+        }
+    };
+    let field = HeightField::from_image(&image, width, layers);
+
+    let name = output
+        .file_name()
+        .map(|v| v.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "terrain.mpd".to_string());
+    let mut builder = ModelBuilder::new(&name);
+    builder.description(&format!(
+        "Terrain from {}",
+        input.file_name().unwrap_or_default().to_string_lossy()
+    ));
+
+    // Terrain is centered around the origin, with the bottom layer resting on it.
+    let origin_x = -(field.width as f32) * LDU_PER_STUD / 2.0;
+    let origin_z = -(field.depth as f32) * LDU_PER_STUD / 2.0;
+    let mut bricks = 0;
+    for layer in 0..layers {
+        let tiles = tile_layer(&field, layer);
+        if tiles.is_empty() {
+            break;
+        }
+        bricks += tiles.len();
+
+        let color = colors[(layer as usize * colors.len()) / layers as usize];
+        let layer_name = format!("layer{:02}.ldr", layer + 1);
+        builder.define(&layer_name, |b| {
+            for (size, x, z) in tiles {
+                let offset = size as f32 * LDU_PER_STUD / 2.0;
+                b.add_part(
+                    if size == 2 { BRICK_2X2 } else { BRICK_1X1 },
+                    color,
+                    Matrix4::from_translation(Vector3::new(
+                        origin_x + x as f32 * LDU_PER_STUD + offset,
+                        0.0,
+                        origin_z + z as f32 * LDU_PER_STUD + offset,
+                    )),
+                );
+            }
+        });
+        // Bricks have their origin at the top, and Y axis points downwards.
+        builder
+            .add_part(
+                layer_name.as_str(),
+                16,
+                Matrix4::from_translation(Vector3::new(
+                    0.0,
+                    -((layer + 1) as f32) * LDU_PER_BRICK,
+                    0.0,
+                )),
+            )
+            .begin_step();
+    }
+
+    let document = match builder.build() {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Could not build the model: {}", e);
+            process::exit(2);
+        }
+    };
+    let mut buffer = Vec::new();
+    if let Err(e) = document.write(&mut buffer).await {
+        eprintln!("Could not serialize the model: {}", e);
+        process::exit(2);
+    }
+    if let Err(e) = fs::write(&output, buffer).await {
+        eprintln!("Could not write {}: {}", output.display(), e);
+        process::exit(2);
+    }
+
+    println!(
+        "Wrote {} bricks in {} layers to {}",
+        bricks,
+        document.subparts.len(),
+        output.display()
+    );
+}
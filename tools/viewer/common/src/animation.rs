@@ -0,0 +1,343 @@
+use std::{collections::HashMap, f32};
+
+use cgmath::{InnerSpace, SquareMatrix};
+use ldraw::{color::ColorCatalog, Matrix4, PartAlias, Vector3};
+use ldraw_ir::{
+    animation::Animation,
+    articulation::{Articulation, JointValue},
+    model::{GroupId, Model, ObjectId, ObjectInstance},
+};
+use ldraw_renderer::display_list::{DisplayList, DisplayListOps};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    // Quarter of a sine wave, slowing down towards the end.
+    SineOut,
+}
+
+impl Easing {
+    // Maps progress from 0 to 1 onto the curve.
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    2.0 * t * t
+                } else {
+                    -1.0 + (4.0 - 2.0 * t) * t
+                }
+            }
+            Easing::SineOut => (t * f32::consts::FRAC_PI_2).sin(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnimationStyle {
+    // Drops from above while fading in. Distance is in LDraw units.
+    Fall { distance: f32 },
+    Fade,
+    // Grows from its origin to full size.
+    Scale,
+    // Moves in from the given direction while fading in.
+    Slide { direction: Vector3, distance: f32 },
+}
+
+// How a part appears partway through its entrance.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AnimationFrame {
+    pub matrix: Matrix4,
+    // Multiplied with alpha of the part's color.
+    pub alpha: f32,
+}
+
+// Entrance of parts while a model is built up step by step.
+pub trait Animator {
+    // Time between parts of a step with the given number of parts starting to appear, in
+    // seconds.
+    fn interval(&self, parts: usize) -> f32;
+
+    // How long each part takes to appear, in seconds.
+    fn duration(&self) -> f32;
+
+    // Part placed at matrix, progress from 0 to 1 into its entrance.
+    fn frame(&self, matrix: &Matrix4, progress: f32) -> AnimationFrame;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnimationConfig {
+    pub style: AnimationStyle,
+    pub easing: Easing,
+    // How long each part takes to appear, in seconds.
+    pub duration: f32,
+    // Time between parts starting to appear, in seconds.
+    pub stagger: f32,
+    // Steps with many parts shorten stagger so that they finish within this many seconds.
+    pub max_step_duration: f32,
+}
+
+impl Default for AnimationConfig {
+    fn default() -> Self {
+        Self {
+            style: AnimationStyle::Fall { distance: 300.0 },
+            easing: Easing::SineOut,
+            duration: 0.5,
+            stagger: 0.2,
+            max_step_duration: 10.0,
+        }
+    }
+}
+
+impl Animator for AnimationConfig {
+    fn interval(&self, parts: usize) -> f32 {
+        if parts as f32 * self.stagger >= self.max_step_duration {
+            self.max_step_duration / parts as f32
+        } else {
+            self.stagger
+        }
+    }
+
+    fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    fn frame(&self, matrix: &Matrix4, progress: f32) -> AnimationFrame {
+        let ease = self.easing.apply(progress);
+        // Y axis points downward in LDraw coordinates.
+        let offset = match self.style {
+            AnimationStyle::Fall { distance } => Some(Vector3::new(0.0, -distance, 0.0)),
+            AnimationStyle::Slide {
+                direction,
+                distance,
+            } if direction.magnitude2() > 0.0 => Some(direction.normalize() * distance),
+            _ => None,
+        };
+
+        match self.style {
+            AnimationStyle::Scale => AnimationFrame {
+                // Keeps the matrix invertible at the very start.
+                matrix: matrix * Matrix4::from_scale(ease.max(0.001)),
+                alpha: 1.0,
+            },
+            _ => AnimationFrame {
+                matrix: match offset {
+                    Some(offset) => Matrix4::from_translation(offset * (1.0 - ease)) * matrix,
+                    None => *matrix,
+                },
+                alpha: ease,
+            },
+        }
+    }
+}
+
+struct KeyframeTarget {
+    key: ObjectId,
+    // Placement of the part relative to the animated object.
+    relative: Matrix4,
+    original: Matrix4,
+}
+
+// Plays keyframe animation on a model being displayed. Tracks place objects at the top of
+// the displayed model, and parts of submodels move along with them.
+pub struct KeyframePlayer {
+    animation: Animation,
+    started_at: f32,
+    targets: HashMap<ObjectId, Vec<KeyframeTarget>>,
+}
+
+impl KeyframePlayer {
+    pub fn new(
+        animation: Animation,
+        model: &Model<PartAlias>,
+        group_id: Option<GroupId>,
+        color_catalog: &ColorCatalog,
+        started_at: f32,
+    ) -> Self {
+        let mut targets = HashMap::new();
+        for object in model.get_objects(group_id).into_iter().flatten() {
+            if !animation.tracks.contains_key(&object.id) {
+                continue;
+            }
+            let placement = match &object.data {
+                ObjectInstance::Part(p) => p.matrix,
+                ObjectInstance::PartGroup(pg) => pg.matrix,
+                _ => continue,
+            };
+            let Some(inverse) = placement.invert() else {
+                continue;
+            };
+
+            let ops = DisplayList::expand_objects(
+                model,
+                std::slice::from_ref(object),
+                color_catalog,
+                Clone::clone,
+            );
+            targets.insert(
+                object.id,
+                ops.into_iter()
+                    .filter_map(|op| match op {
+                        DisplayListOps::Insert { key, matrix, .. } => Some(KeyframeTarget {
+                            key,
+                            relative: inverse * matrix,
+                            original: matrix,
+                        }),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>(),
+            );
+        }
+
+        Self {
+            animation,
+            started_at,
+            targets,
+        }
+    }
+
+    pub fn animation(&self) -> &Animation {
+        &self.animation
+    }
+
+    pub fn is_finished(&self, time: f32) -> bool {
+        self.animation.is_finished(time - self.started_at)
+    }
+
+    pub fn animate(&self, time: f32) -> Vec<DisplayListOps<ObjectId, PartAlias>> {
+        let mut ops = Vec::new();
+        for (id, sample) in self.animation.sample(time - self.started_at) {
+            for target in self.targets.get(&id).into_iter().flatten() {
+                ops.push(DisplayListOps::UpdateMatrix {
+                    key: target.key,
+                    matrix: sample.matrix * target.relative,
+                });
+                ops.push(DisplayListOps::SetVisible {
+                    key: target.key,
+                    visible: sample.visible,
+                });
+            }
+        }
+        ops
+    }
+
+    // Puts parts back where the model places them.
+    pub fn restore(&self) -> Vec<DisplayListOps<ObjectId, PartAlias>> {
+        self.targets
+            .values()
+            .flatten()
+            .flat_map(|target| {
+                [
+                    DisplayListOps::UpdateMatrix {
+                        key: target.key,
+                        matrix: target.original,
+                    },
+                    DisplayListOps::SetVisible {
+                        key: target.key,
+                        visible: true,
+                    },
+                ]
+            })
+            .collect()
+    }
+}
+
+// Poses articulated objects of a model being displayed. Joints join objects at the top of the
+// displayed model, and parts of submodels move along with them.
+pub struct Poser {
+    articulation: Articulation,
+    // Parts under each jointed object along with their placement at rest.
+    targets: HashMap<ObjectId, Vec<(ObjectId, Matrix4)>>,
+}
+
+impl Poser {
+    pub fn new(
+        articulation: Articulation,
+        model: &Model<PartAlias>,
+        group_id: Option<GroupId>,
+        color_catalog: &ColorCatalog,
+    ) -> Self {
+        let mut targets = HashMap::new();
+        for object in model.get_objects(group_id).into_iter().flatten() {
+            if articulation.joint(&object.id).is_none() {
+                continue;
+            }
+
+            let ops = DisplayList::expand_objects(
+                model,
+                std::slice::from_ref(object),
+                color_catalog,
+                Clone::clone,
+            );
+            targets.insert(
+                object.id,
+                ops.into_iter()
+                    .filter_map(|op| match op {
+                        DisplayListOps::Insert { key, matrix, .. } => Some((key, matrix)),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>(),
+            );
+        }
+
+        Self {
+            articulation,
+            targets,
+        }
+    }
+
+    pub fn articulation(&self) -> &Articulation {
+        &self.articulation
+    }
+
+    pub fn into_articulation(self) -> Articulation {
+        self.articulation
+    }
+
+    // Returns None if the joint doesn't take the value.
+    pub fn set_value(
+        &mut self,
+        id: &ObjectId,
+        value: JointValue,
+    ) -> Option<Vec<DisplayListOps<ObjectId, PartAlias>>> {
+        if self.articulation.set_value(id, value) {
+            Some(self.pose())
+        } else {
+            None
+        }
+    }
+
+    pub fn pose(&self) -> Vec<DisplayListOps<ObjectId, PartAlias>> {
+        let motions = self.articulation.evaluate();
+        self.targets
+            .iter()
+            .flat_map(|(id, targets)| {
+                let motion = motions.get(id).copied().unwrap_or_else(Matrix4::identity);
+                targets
+                    .iter()
+                    .map(move |(key, matrix)| DisplayListOps::UpdateMatrix {
+                        key: *key,
+                        matrix: motion * matrix,
+                    })
+            })
+            .collect()
+    }
+
+    // Puts parts back where the model places them.
+    pub fn restore(&self) -> Vec<DisplayListOps<ObjectId, PartAlias>> {
+        self.targets
+            .values()
+            .flatten()
+            .map(|(key, matrix)| DisplayListOps::UpdateMatrix {
+                key: *key,
+                matrix: *matrix,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+
+use ldraw::{
+    color::{Color, ColorCatalog, Material, Rgba},
+    PartAlias,
+};
+use ldraw_ir::{
+    model::{GroupId, Model, ObjectId},
+    part::PartGeometryQuerier,
+};
+use ldraw_renderer::display_list::{DisplayList, DisplayListOps};
+
+// Golden ratio conjugate, spreads consecutive hues evenly around the color wheel.
+const HUE_STEP: f32 = 0.618_034;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BreakdownMode {
+    // Each distinct combination of part and color gets its own tint.
+    Lot,
+    // Parts are grouped by the first word of their description, e.g. "Brick" or "Plate".
+    Category,
+}
+
+#[derive(Clone, Debug)]
+pub struct LegendEntry {
+    pub label: String,
+    pub color: Rgba,
+    pub count: usize,
+}
+
+#[derive(Clone, Debug)]
+struct BreakdownItem {
+    key: ObjectId,
+    original: Color,
+    entry: usize,
+}
+
+#[derive(Default)]
+pub struct ColorBreakdown {
+    mode: Option<BreakdownMode>,
+    items: Vec<BreakdownItem>,
+    legend: Vec<LegendEntry>,
+}
+
+fn palette(index: usize) -> Rgba {
+    let hue = (index as f32 * HUE_STEP).fract() * 6.0;
+    // Alternate saturation and value so that colors are distinguishable past a full turn.
+    let (saturation, value) = match (index / 6) % 3 {
+        0 => (0.75, 0.95),
+        1 => (0.55, 0.75),
+        _ => (0.9, 0.6),
+    };
+
+    let chroma = value * saturation;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = value - chroma;
+    let channel = |v: f32| ((v + m) * 255.0).round() as u8;
+
+    Rgba::new(channel(r), channel(g), channel(b), 255)
+}
+
+fn category(description: &str) -> String {
+    description
+        .trim_start_matches(['~', '=', '_', '|'])
+        .split_whitespace()
+        .next()
+        .unwrap_or("Unknown")
+        .to_string()
+}
+
+impl ColorBreakdown {
+    pub fn mode(&self) -> Option<BreakdownMode> {
+        self.mode
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.mode.is_some()
+    }
+
+    // Entries sorted by number of parts, most used first.
+    pub fn legend(&self) -> &[LegendEntry] {
+        &self.legend
+    }
+
+    pub fn build(
+        &mut self,
+        mode: BreakdownMode,
+        model: &Model<PartAlias>,
+        group_id: Option<GroupId>,
+        parts: &impl PartGeometryQuerier<PartAlias>,
+        colors: &ColorCatalog,
+    ) {
+        self.clear();
+
+        let objects = match model.get_objects(group_id) {
+            Some(objects) => objects.cloned().collect::<Vec<_>>(),
+            None => return,
+        };
+
+        let mut entries: HashMap<String, usize> = HashMap::new();
+        let mut labels = Vec::new();
+        let mut items = Vec::new();
+        for op in DisplayList::expand_objects(model, &objects, colors, Clone::clone) {
+            let DisplayListOps::Insert {
+                group, key, color, ..
+            } = op
+            else {
+                continue;
+            };
+
+            let label = match mode {
+                BreakdownMode::Lot => format!("{} ({})", group.original, color.name),
+                BreakdownMode::Category => parts
+                    .query_part_geometry(&group)
+                    .map(|part| category(&part.metadata.description))
+                    .unwrap_or_else(|| String::from("Unknown")),
+            };
+            let entry = *entries.entry(label.clone()).or_insert_with(|| {
+                labels.push((label, 0));
+                labels.len() - 1
+            });
+            labels[entry].1 += 1;
+
+            items.push((key, color, entry));
+        }
+
+        // Most used entries get the most distinct colors.
+        let mut order = (0..labels.len()).collect::<Vec<_>>();
+        order.sort_by(|a, b| labels[*b].1.cmp(&labels[*a].1).then(a.cmp(b)));
+        let mut rank = vec![0; labels.len()];
+        for (i, entry) in order.iter().enumerate() {
+            rank[*entry] = i;
+        }
+
+        self.legend = order
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| LegendEntry {
+                label: labels[*entry].0.clone(),
+                color: palette(i),
+                count: labels[*entry].1,
+            })
+            .collect();
+        self.items = items
+            .into_iter()
+            .map(|(key, original, entry)| BreakdownItem {
+                key,
+                original,
+                entry: rank[entry],
+            })
+            .collect();
+        self.mode = Some(mode);
+    }
+
+    pub fn clear(&mut self) {
+        self.mode = None;
+        self.items.clear();
+        self.legend.clear();
+    }
+
+    // Tints every part with the color of its legend entry.
+    pub fn ops(&self, opacity: f32) -> Vec<DisplayListOps<ObjectId, PartAlias>> {
+        self.items
+            .iter()
+            .map(|item| {
+                let entry = &self.legend[item.entry];
+                let rgba = Rgba::new(
+                    entry.color.red(),
+                    entry.color.green(),
+                    entry.color.blue(),
+                    (opacity.clamp(0.0, 1.0) * 255.0) as u8,
+                );
+                DisplayListOps::UpdateColor {
+                    key: item.key,
+                    color: Color {
+                        code: 0,
+                        name: entry.label.clone(),
+                        color: rgba,
+                        edge: Rgba::new(0x33, 0x33, 0x33, rgba.alpha()),
+                        luminance: 0,
+                        material: Material::Plastic,
+                        attributes: HashMap::new(),
+                    },
+                }
+            })
+            .collect()
+    }
+
+    // Reverts parts to their actual colors.
+    pub fn restore_ops(&self) -> Vec<DisplayListOps<ObjectId, PartAlias>> {
+        self.items
+            .iter()
+            .map(|item| DisplayListOps::UpdateColor {
+                key: item.key,
+                color: item.original.clone(),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,58 @@
+use cgmath::Deg;
+use ldraw::Point3;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum StandardView {
+    Front,
+    Back,
+    Left,
+    Right,
+    Top,
+    Bottom,
+    Isometric,
+}
+
+impl StandardView {
+    pub const ALL: [StandardView; 7] = [
+        StandardView::Front,
+        StandardView::Back,
+        StandardView::Left,
+        StandardView::Right,
+        StandardView::Top,
+        StandardView::Bottom,
+        StandardView::Isometric,
+    ];
+
+    // Horizontal angle around the model and elevation above it, in degrees. Front looks at
+    // the model from -Z as LDraw editors do.
+    pub fn angles(&self) -> (f32, f32) {
+        match self {
+            StandardView::Front => (0.0, 0.0),
+            StandardView::Back => (180.0, 0.0),
+            StandardView::Left => (-90.0, 0.0),
+            StandardView::Right => (90.0, 0.0),
+            StandardView::Top => (0.0, 90.0),
+            StandardView::Bottom => (0.0, -90.0),
+            StandardView::Isometric => (45.0, 35.264),
+        }
+    }
+}
+
+// Exact camera placement, for coming back to a view later.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CameraBookmark {
+    pub position: Point3,
+    pub look_at: Point3,
+    pub fov: Deg<f32>,
+}
+
+// Bookmarks are bound to number keys 1 to 9.
+pub const BOOKMARK_SLOTS: usize = 9;
+
+pub fn bookmark_slot(key: &str) -> Option<usize> {
+    key.parse::<usize>()
+        .ok()
+        .filter(|v| (1..=BOOKMARK_SLOTS).contains(v))
+        .map(|v| v - 1)
+}
@@ -0,0 +1,108 @@
+use std::{cell::Cell, rc::Rc};
+
+use instant::Instant;
+
+// Time source for animations, in seconds.
+pub trait Clock {
+    fn now(&self) -> f32;
+
+    // Called once after every frame.
+    fn tick(&mut self) {}
+}
+
+pub struct RealTimeClock {
+    started: Instant,
+    offset: f32,
+}
+
+impl RealTimeClock {
+    pub fn new() -> Self {
+        Self::starting_at(0.0)
+    }
+
+    // Continues from given time, so that switching clocks does not make time jump.
+    pub fn starting_at(time: f32) -> Self {
+        Self {
+            started: Instant::now(),
+            offset: time,
+        }
+    }
+}
+
+impl Default for RealTimeClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for RealTimeClock {
+    fn now(&self) -> f32 {
+        self.offset + self.started.elapsed().as_secs_f32()
+    }
+}
+
+// Advances by a constant interval per frame regardless of how long frames take.
+pub struct FixedStepClock {
+    start: f32,
+    step: f32,
+    frame: u64,
+}
+
+impl FixedStepClock {
+    pub fn new(step: f32) -> Self {
+        Self::starting_at(0.0, step)
+    }
+
+    pub fn starting_at(start: f32, step: f32) -> Self {
+        Self {
+            start,
+            step,
+            frame: 0,
+        }
+    }
+
+    pub fn with_frame_rate(fps: f32) -> Self {
+        Self::new(1.0 / fps)
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+}
+
+impl Clock for FixedStepClock {
+    fn now(&self) -> f32 {
+        self.start + self.frame as f32 * self.step
+    }
+
+    fn tick(&mut self) {
+        self.frame += 1;
+    }
+}
+
+// Only moves when told to. Clones share the same time, so a handle can be kept
+// after giving the clock to the app.
+#[derive(Clone, Default)]
+pub struct ManualClock {
+    time: Rc<Cell<f32>>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, time: f32) {
+        self.time.set(time);
+    }
+
+    pub fn advance(&self, delta: f32) {
+        self.time.set(self.time.get() + delta);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> f32 {
+        self.time.get()
+    }
+}
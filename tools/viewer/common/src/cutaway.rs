@@ -0,0 +1,81 @@
+use cgmath::InnerSpace;
+use ldraw::{units::LDU_PER_STUD, Vector4};
+use ldraw_ir::geometry::BoundingBox3;
+
+use crate::gizmo::Axis;
+
+// Moving the plane by a plate at a time keeps layers of bricks lined up with it.
+const STEP: f32 = LDU_PER_STUD * 0.4;
+
+// Slices through the model with an axis-aligned plane to reveal its interior.
+#[derive(Clone, Debug, Default)]
+pub struct Cutaway {
+    axis: Option<Axis>,
+    offset: f32,
+    flipped: bool,
+    // Extent of the model along the axis.
+    range: (f32, f32),
+}
+
+impl Cutaway {
+    pub fn is_active(&self) -> bool {
+        self.axis.is_some()
+    }
+
+    pub fn axis(&self) -> Option<Axis> {
+        self.axis
+    }
+
+    pub fn offset(&self) -> f32 {
+        self.offset
+    }
+
+    // Switches between no cutaway and a plane on each axis in turn. The plane starts from
+    // the center of the model.
+    pub fn cycle_axis(&mut self, bounding_box: &BoundingBox3) {
+        self.axis = match self.axis {
+            None => Some(Axis::X),
+            Some(Axis::X) => Some(Axis::Y),
+            Some(Axis::Y) => Some(Axis::Z),
+            Some(Axis::Z) => None,
+        };
+
+        if let Some(axis) = self.axis {
+            let unit = axis.unit();
+            self.range = if bounding_box.is_null() {
+                (0.0, 0.0)
+            } else {
+                (bounding_box.min.dot(unit), bounding_box.max.dot(unit))
+            };
+            self.offset = (self.range.0 + self.range.1) * 0.5;
+        }
+    }
+
+    // Moves the plane by given number of plates, staying within the model.
+    pub fn advance(&mut self, steps: f32) {
+        if self.axis.is_some() {
+            self.offset = (self.offset + STEP * steps).clamp(self.range.0, self.range.1);
+        }
+    }
+
+    // Cuts away the other side of the plane.
+    pub fn flip(&mut self) {
+        self.flipped = !self.flipped;
+    }
+
+    pub fn clear(&mut self) {
+        self.axis = None;
+        self.flipped = false;
+    }
+
+    pub fn clip_planes(&self) -> Vec<Vector4> {
+        match self.axis {
+            Some(axis) => {
+                let unit = axis.unit();
+                let plane = unit.extend(-self.offset);
+                vec![if self.flipped { -plane } else { plane }]
+            }
+            None => Vec::new(),
+        }
+    }
+}
@@ -27,7 +27,7 @@ impl Display for AppCreationError {
 }
 
 impl Error for AppCreationError {
-    fn cause(&self) -> Option<&(dyn Error)> {
+    fn cause(&self) -> Option<&dyn Error> {
         match *self {
             Self::NoAdapterFound => None,
             Self::RequestDeviceError(ref e) => Some(e),
@@ -47,3 +47,35 @@ impl From<wgpu::CreateSurfaceError> for AppCreationError {
         Self::CreateSurfaceError(e)
     }
 }
+
+#[derive(Debug)]
+pub enum CaptureError {
+    UnsupportedFormat(wgpu::TextureFormat),
+    BufferMapping(wgpu::BufferAsyncError),
+    Cancelled,
+}
+
+impl Display for CaptureError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::UnsupportedFormat(format) => {
+                write!(f, "Can't read back frames in {:?}.", format)
+            }
+            Self::BufferMapping(e) => {
+                write!(f, "Error reading back frame: {}", e)
+            }
+            Self::Cancelled => {
+                write!(f, "Frame has been dropped before being read back.")
+            }
+        }
+    }
+}
+
+impl Error for CaptureError {
+    fn cause(&self) -> Option<&dyn Error> {
+        match *self {
+            Self::BufferMapping(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
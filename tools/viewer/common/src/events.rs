@@ -0,0 +1,89 @@
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+use ldraw::PartAlias;
+use ldraw_ir::{
+    analysis::summary::ModelSummary,
+    model::{GroupId, ObjectId},
+};
+use ldraw_renderer::pipeline::RenderStats;
+
+use crate::{
+    breakdown::{BreakdownMode, LegendEntry},
+    State,
+};
+
+#[derive(Clone, Debug)]
+pub enum AppEvent {
+    ModelLoaded(ModelSummary<PartAlias>),
+    // Submodels of the model loaded, as returned by App::get_subparts().
+    SubpartsChanged(Vec<(GroupId, String)>),
+    // Step counts from zero at the start of the animation.
+    StepChanged {
+        step: usize,
+        state: State,
+    },
+    SelectionChanged(Vec<ObjectId>),
+    PartLoaded(PartAlias),
+    PartLoadFailed {
+        alias: PartAlias,
+        error: String,
+    },
+    // Legend is empty while parts are shown in their own colors.
+    BreakdownChanged {
+        mode: Option<BreakdownMode>,
+        legend: Vec<LegendEntry>,
+    },
+    // Emitted at most once per second while frames are being rendered.
+    RenderStatsTick(RenderStats),
+    PlaybackFinished,
+    // Parts the document uses that changed in the library, see App::invalidate_parts().
+    LibraryChanged(Vec<PartAlias>),
+    // Errors the device raised outside of any error scope, such as validation failures.
+    GpuError(String),
+}
+
+pub type ListenerId = usize;
+
+type Listener = Box<dyn FnMut(&AppEvent) -> bool>;
+
+#[derive(Default)]
+pub struct EventBus {
+    listeners: Vec<(ListenerId, Listener)>,
+    next_id: ListenerId,
+}
+
+impl EventBus {
+    pub fn subscribe<F: FnMut(&AppEvent) + 'static>(&mut self, mut listener: F) -> ListenerId {
+        self.add_listener(Box::new(move |event| {
+            listener(event);
+            true
+        }))
+    }
+
+    pub fn unsubscribe(&mut self, id: ListenerId) {
+        self.listeners.retain(|(v, _)| *v != id);
+    }
+
+    // Listeners backed by a channel are dropped once the receiver goes away.
+    pub fn channel(&mut self) -> UnboundedReceiver<AppEvent> {
+        let (sender, receiver) = unbounded();
+        self.add_listener(Box::new(move |event| {
+            sender.unbounded_send(event.clone()).is_ok()
+        }));
+        receiver
+    }
+
+    pub fn has_listeners(&self) -> bool {
+        !self.listeners.is_empty()
+    }
+
+    pub fn emit(&mut self, event: AppEvent) {
+        self.listeners.retain_mut(|(_, listener)| listener(&event));
+    }
+
+    fn add_listener(&mut self, listener: Listener) -> ListenerId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.listeners.push((id, listener));
+        id
+    }
+}
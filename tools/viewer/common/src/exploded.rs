@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Zero};
+use ldraw::{color::ColorCatalog, Matrix4, PartAlias, Vector3};
+use ldraw_ir::{
+    constraints::ConnectivityGraph,
+    geometry::BoundingBox3,
+    model::{GroupId, Model, ObjectId},
+    part::PartDimensionQuerier,
+};
+use ldraw_renderer::display_list::{DisplayList, DisplayListOps};
+
+const FACTOR_STEP: f32 = 0.25;
+const MAX_FACTOR: f32 = 4.0;
+// How fast the explosion factor approaches its target, per second.
+const EASING_RATE: f32 = 6.0;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExplosionDirection {
+    // Away from the center of the model.
+    Centroid,
+    // Away from the parts each object is connected to.
+    Connectivity,
+}
+
+#[derive(Clone, Debug)]
+struct ExplodedItem {
+    key: ObjectId,
+    matrix: Matrix4,
+    offset: Vector3,
+}
+
+pub struct ExplodedView {
+    pub direction: ExplosionDirection,
+
+    items: Vec<ExplodedItem>,
+    factor: f32,
+    target_factor: f32,
+    last_time: Option<f32>,
+}
+
+impl Default for ExplodedView {
+    fn default() -> Self {
+        Self {
+            direction: ExplosionDirection::Centroid,
+
+            items: Vec::new(),
+            factor: 0.0,
+            target_factor: 0.0,
+            last_time: None,
+        }
+    }
+}
+
+fn normalize_or_zero(v: Vector3) -> Vector3 {
+    if v.magnitude2() > f32::EPSILON {
+        v.normalize()
+    } else {
+        Vector3::zero()
+    }
+}
+
+impl ExplodedView {
+    // Captures current placement of every top level object. Groups are moved as a whole.
+    // Current explosion factor is kept, call `ops` afterwards to apply new offsets.
+    pub fn build(
+        &mut self,
+        model: &Model<PartAlias>,
+        group_id: Option<GroupId>,
+        parts: &impl PartDimensionQuerier<PartAlias>,
+        connectivity: Option<&ConnectivityGraph>,
+        colors: &ColorCatalog,
+    ) {
+        self.items.clear();
+
+        let objects = match model.get_objects(group_id) {
+            Some(objects) => objects.cloned().collect::<Vec<_>>(),
+            None => return,
+        };
+
+        let mut entries = Vec::new();
+        let mut model_bounding_box = BoundingBox3::nil();
+        for object in objects.iter() {
+            let mut bounding_box = BoundingBox3::nil();
+            let items = DisplayList::expand_objects(
+                model,
+                std::slice::from_ref(object),
+                colors,
+                Clone::clone,
+            )
+            .into_iter()
+            .filter_map(|op| match op {
+                DisplayListOps::Insert {
+                    group, key, matrix, ..
+                } => {
+                    if let Some(bb) = parts.query_part_dimension(&group) {
+                        bounding_box.update(&bb.transform(&matrix));
+                    }
+                    Some((key, matrix))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+            if items.is_empty() || bounding_box.is_null() {
+                continue;
+            }
+            model_bounding_box.update(&bounding_box);
+            entries.push((object.id, bounding_box.center(), items));
+        }
+
+        let centroid = model_bounding_box.center();
+        let centers = entries
+            .iter()
+            .map(|(id, center, _)| (*id, *center))
+            .collect::<HashMap<_, _>>();
+
+        for (id, center, items) in entries {
+            let outward = center - centroid;
+            let direction = match (self.direction, connectivity) {
+                (ExplosionDirection::Connectivity, Some(graph)) => {
+                    let neighbors = graph
+                        .neighbors(&id)
+                        .iter()
+                        .filter_map(|v| centers.get(v))
+                        .copied()
+                        .collect::<Vec<_>>();
+                    if neighbors.is_empty() {
+                        normalize_or_zero(outward)
+                    } else {
+                        let mean = neighbors.iter().fold(Vector3::zero(), |a, b| a + b)
+                            / neighbors.len() as f32;
+                        match normalize_or_zero(center - mean) {
+                            v if v.is_zero() => normalize_or_zero(outward),
+                            v => v,
+                        }
+                    }
+                }
+                _ => normalize_or_zero(outward),
+            };
+            let offset = direction * outward.magnitude();
+
+            self.items
+                .extend(items.into_iter().map(|(key, matrix)| ExplodedItem {
+                    key,
+                    matrix,
+                    offset,
+                }));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.factor = 0.0;
+        self.target_factor = 0.0;
+        self.last_time = None;
+    }
+
+    pub fn factor(&self) -> f32 {
+        self.factor
+    }
+
+    pub fn target_factor(&self) -> f32 {
+        self.target_factor
+    }
+
+    pub fn set_target_factor(&mut self, factor: f32) {
+        self.target_factor = factor.clamp(0.0, MAX_FACTOR);
+    }
+
+    pub fn increase(&mut self) {
+        self.set_target_factor(self.target_factor + FACTOR_STEP);
+    }
+
+    pub fn decrease(&mut self) {
+        self.set_target_factor(self.target_factor - FACTOR_STEP);
+    }
+
+    // Whether any part is (or is going to be) displaced from its original position.
+    pub fn is_active(&self) -> bool {
+        !self.items.is_empty() && (self.factor > 0.0 || self.target_factor > 0.0)
+    }
+
+    pub fn ops(&self) -> Vec<DisplayListOps<ObjectId, PartAlias>> {
+        self.items
+            .iter()
+            .map(|item| DisplayListOps::UpdateMatrix {
+                key: item.key,
+                matrix: Matrix4::from_translation(item.offset * self.factor) * item.matrix,
+            })
+            .collect()
+    }
+
+    pub fn animate(&mut self, time: f32) -> Vec<DisplayListOps<ObjectId, PartAlias>> {
+        let elapsed = match self.last_time.replace(time) {
+            Some(last_time) => (time - last_time).max(0.0),
+            None => 0.0,
+        };
+
+        if self.items.is_empty() || self.factor == self.target_factor {
+            return Vec::new();
+        }
+
+        let delta = self.target_factor - self.factor;
+        let step = delta * (elapsed * EASING_RATE).min(1.0);
+        self.factor = if (delta - step).abs() < 1e-3 {
+            self.target_factor
+        } else {
+            self.factor + step
+        };
+
+        self.ops()
+    }
+}
@@ -0,0 +1,319 @@
+use cgmath::{Deg, Rad};
+use ldraw::{
+    color::{Color, ColorCatalog, Rgba},
+    document::MultipartDocument,
+    units::LDU_PER_STUD,
+    Matrix4, PartAlias, Vector3,
+};
+use ldraw_ir::model::{GroupId, Model, Object, ObjectId, ObjectInstance};
+use ldraw_renderer::display_list::{DisplayList, DisplayListOps};
+
+const DRAG_PIXELS_PER_STEP: f32 = 20.0;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TransformMode {
+    Translate,
+    Rotate,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    pub fn unit(&self) -> Vector3 {
+        match self {
+            Axis::X => Vector3::new(1.0, 0.0, 0.0),
+            Axis::Y => Vector3::new(0.0, 1.0, 0.0),
+            Axis::Z => Vector3::new(0.0, 0.0, 1.0),
+        }
+    }
+}
+
+pub struct TransformGizmo {
+    pub mode: TransformMode,
+    pub axis: Axis,
+    pub translation_step: f32,
+    pub rotation_step: Deg<f32>,
+
+    selection: Vec<ObjectId>,
+    dragging: bool,
+    last_drag_x: Option<f32>,
+    drag_remainder: f32,
+}
+
+impl Default for TransformGizmo {
+    fn default() -> Self {
+        Self {
+            mode: TransformMode::Translate,
+            axis: Axis::X,
+            translation_step: LDU_PER_STUD / 2.0,
+            rotation_step: Deg(15.0),
+
+            selection: Vec::new(),
+            dragging: false,
+            last_drag_x: None,
+            drag_remainder: 0.0,
+        }
+    }
+}
+
+fn highlight_color(color: &Color) -> Color {
+    Color {
+        color: color.color.blend(Rgba::new(0xff, 0xc0, 0x00, 255), 0.5),
+        ..color.clone()
+    }
+}
+
+fn get_objects_mut(
+    model: &mut Model<PartAlias>,
+    group_id: Option<GroupId>,
+) -> Option<&mut Vec<Object<PartAlias>>> {
+    match group_id {
+        Some(group_id) => model
+            .object_groups
+            .get_mut(&group_id)
+            .map(|v| &mut v.objects),
+        None => Some(&mut model.objects),
+    }
+}
+
+fn get_matrix(object: &Object<PartAlias>) -> Option<Matrix4> {
+    match &object.data {
+        ObjectInstance::Part(p) => Some(p.matrix),
+        ObjectInstance::PartGroup(pg) => Some(pg.matrix),
+        _ => None,
+    }
+}
+
+impl TransformGizmo {
+    pub fn selection(&self) -> &[ObjectId] {
+        &self.selection
+    }
+
+    pub fn is_selected(&self, id: &ObjectId) -> bool {
+        self.selection.contains(id)
+    }
+
+    pub fn selected_objects(
+        &self,
+        model: &Model<PartAlias>,
+        group_id: Option<GroupId>,
+    ) -> Vec<Object<PartAlias>> {
+        model
+            .get_objects(group_id)
+            .map(|objects| {
+                objects
+                    .filter(|v| self.selection.contains(&v.id))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn highlight_ops(
+        objects: &[Object<PartAlias>],
+        model: &Model<PartAlias>,
+        colors: &ColorCatalog,
+        highlighted: bool,
+    ) -> Vec<DisplayListOps<ObjectId, PartAlias>> {
+        DisplayList::expand_objects(model, objects, colors, |c| {
+            if highlighted {
+                highlight_color(c)
+            } else {
+                c.clone()
+            }
+        })
+        .into_iter()
+        .filter_map(|op| match op {
+            DisplayListOps::Insert { key, color, .. } => {
+                Some(DisplayListOps::UpdateColor { key, color })
+            }
+            _ => None,
+        })
+        .collect()
+    }
+
+    pub fn set_selection(
+        &mut self,
+        selection: Vec<ObjectId>,
+        model: &Model<PartAlias>,
+        group_id: Option<GroupId>,
+        colors: &ColorCatalog,
+    ) -> Vec<DisplayListOps<ObjectId, PartAlias>> {
+        let mut ops = Self::highlight_ops(
+            &self.selected_objects(model, group_id),
+            model,
+            colors,
+            false,
+        );
+        self.selection = selection;
+        ops.extend(Self::highlight_ops(
+            &self.selected_objects(model, group_id),
+            model,
+            colors,
+            true,
+        ));
+        ops
+    }
+
+    // Inserts every part under the selection, for drawing outlines around them.
+    pub fn outline_ops(
+        &self,
+        model: &Model<PartAlias>,
+        group_id: Option<GroupId>,
+        colors: &ColorCatalog,
+    ) -> Vec<DisplayListOps<ObjectId, PartAlias>> {
+        DisplayList::expand_objects(
+            model,
+            &self.selected_objects(model, group_id),
+            colors,
+            Clone::clone,
+        )
+    }
+
+    // Selects an object next to the last selected one.
+    pub fn select_next(
+        &mut self,
+        model: &Model<PartAlias>,
+        group_id: Option<GroupId>,
+        colors: &ColorCatalog,
+    ) -> Vec<DisplayListOps<ObjectId, PartAlias>> {
+        let candidates = match model.get_objects(group_id) {
+            Some(objects) => objects
+                .filter(|v| get_matrix(v).is_some())
+                .map(|v| v.id)
+                .collect::<Vec<_>>(),
+            None => return Vec::new(),
+        };
+        if candidates.is_empty() {
+            return Vec::new();
+        }
+
+        let next = match self
+            .selection
+            .last()
+            .and_then(|last| candidates.iter().position(|v| v == last))
+        {
+            Some(index) => candidates[(index + 1) % candidates.len()],
+            None => candidates[0],
+        };
+
+        self.set_selection(vec![next], model, group_id, colors)
+    }
+
+    fn delta(&self, model: &Model<PartAlias>, group_id: Option<GroupId>, amount: f32) -> Matrix4 {
+        match self.mode {
+            TransformMode::Translate => {
+                Matrix4::from_translation(self.axis.unit() * self.translation_step * amount)
+            }
+            TransformMode::Rotate => {
+                let origins = self
+                    .selected_objects(model, group_id)
+                    .iter()
+                    .filter_map(get_matrix)
+                    .map(|m| m.w.truncate())
+                    .collect::<Vec<_>>();
+                let pivot = if origins.is_empty() {
+                    Vector3::new(0.0, 0.0, 0.0)
+                } else {
+                    origins
+                        .iter()
+                        .fold(Vector3::new(0.0, 0.0, 0.0), |a, b| a + b)
+                        / origins.len() as f32
+                };
+                let angle: Rad<f32> = (self.rotation_step * amount).into();
+
+                Matrix4::from_translation(pivot)
+                    * Matrix4::from_axis_angle(self.axis.unit(), angle)
+                    * Matrix4::from_translation(-pivot)
+            }
+        }
+    }
+
+    // Transforms selected objects by given number of steps, updating the model and the
+    // document it has been built from.
+    pub fn transform(
+        &self,
+        amount: f32,
+        model: &mut Model<PartAlias>,
+        group_id: Option<GroupId>,
+        mut document: Option<&mut MultipartDocument>,
+        colors: &ColorCatalog,
+    ) -> Vec<DisplayListOps<ObjectId, PartAlias>> {
+        if self.selection.is_empty() || amount == 0.0 {
+            return Vec::new();
+        }
+
+        let delta = self.delta(model, group_id, amount);
+
+        for object in self.selected_objects(model, group_id) {
+            let matrix = match get_matrix(&object) {
+                Some(m) => delta * m,
+                None => continue,
+            };
+
+            if let Some(document) = document.as_deref_mut() {
+                if let Some(reference) = model.find_part_reference_mut(document, &object.id) {
+                    reference.matrix = matrix;
+                }
+            }
+
+            if let Some(objects) = get_objects_mut(model, group_id) {
+                if let Some(object) = objects.iter_mut().find(|v| v.id == object.id) {
+                    match &mut object.data {
+                        ObjectInstance::Part(p) => p.matrix = matrix,
+                        ObjectInstance::PartGroup(pg) => pg.matrix = matrix,
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        DisplayList::expand_objects(
+            model,
+            &self.selected_objects(model, group_id),
+            colors,
+            Clone::clone,
+        )
+        .into_iter()
+        .filter_map(|op| match op {
+            DisplayListOps::Insert { key, matrix, .. } => {
+                Some(DisplayListOps::UpdateMatrix { key, matrix })
+            }
+            _ => None,
+        })
+        .collect()
+    }
+
+    pub fn on_drag_press(&mut self, pressed: bool) {
+        self.dragging = pressed;
+        self.last_drag_x = None;
+        self.drag_remainder = 0.0;
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.dragging
+    }
+
+    // Returns number of steps to transform for the mouse movement.
+    pub fn on_drag_move(&mut self, x: f32) -> f32 {
+        if !self.dragging {
+            return 0.0;
+        }
+
+        let last_x = self.last_drag_x.replace(x).unwrap_or(x);
+        self.drag_remainder += (x - last_x) / DRAG_PIXELS_PER_STEP;
+        let steps = self.drag_remainder.trunc();
+        self.drag_remainder -= steps;
+        steps
+    }
+
+    pub fn clear(&mut self) {
+        self.selection.clear();
+        self.on_drag_press(false);
+    }
+}
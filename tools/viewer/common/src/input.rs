@@ -0,0 +1,452 @@
+#[cfg(feature = "gamepad")]
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use winit::keyboard::{Key, NamedKey, SmolStr};
+
+use crate::camera::{StandardView, BOOKMARK_SLOTS};
+#[cfg(feature = "gamepad")]
+use crate::recorder::InputEvent;
+
+// Things keys and gamepad buttons can be bound to, each happening once per press.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    NextStep,
+    BakeAmbientOcclusion,
+    SaveCamera,
+    ToggleRenderStats,
+    CycleSampleCount,
+    SelectNextObject,
+    ClearSelection,
+    TranslateMode,
+    RotateMode,
+    SelectAxisX,
+    SelectAxisY,
+    SelectAxisZ,
+    StandardView(StandardView),
+    AddBookmark,
+    // Slots are counted from zero.
+    RecallBookmark(usize),
+    FrameSelection,
+    HideSelection,
+    ShowAllObjects,
+    ToggleGround,
+    CycleCutaway,
+    MoveCutawayBackward,
+    MoveCutawayForward,
+    FlipCutaway,
+    ToggleExplodedView,
+    IncreaseExplosion,
+    DecreaseExplosion,
+    ToggleExplosionDirection,
+    DecreaseOpacity,
+    IncreaseOpacity,
+    DecreaseOverlayOpacity,
+    IncreaseOverlayOpacity,
+    TransformForward,
+    TransformBackward,
+    ToggleArticulation,
+    ArticulateForward,
+    ArticulateBackward,
+    // Only do something with the physics feature.
+    TogglePhysics,
+    KnockApart,
+}
+
+// Things gamepad axes can be bound to, which keep going for as long as the axis is held.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum AxisAction {
+    OrbitHorizontal,
+    OrbitVertical,
+    PanHorizontal,
+    PanVertical,
+    // Positive values move the camera closer.
+    Zoom,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftTrigger,
+    LeftTrigger2,
+    RightTrigger,
+    RightTrigger2,
+    Select,
+    Start,
+    LeftThumb,
+    RightThumb,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftZ,
+    RightZ,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct KeyBinding {
+    pub key: Key,
+    pub action: Action,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ButtonBinding {
+    pub button: GamepadButton,
+    pub action: Action,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AxisBinding {
+    pub axis: GamepadAxis,
+    pub action: AxisAction,
+    // Multiplies values of the axis, negative to invert it.
+    pub scale: f32,
+}
+
+// Which keys and gamepad controls do what in the app. Keys are matched by the character or
+// named key they produce, so that bindings follow the keyboard layout.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputMap {
+    pub keys: Vec<KeyBinding>,
+    pub buttons: Vec<ButtonBinding>,
+    pub axes: Vec<AxisBinding>,
+    // Axis values closer to rest than this are taken as zero, as sticks rarely center exactly.
+    pub dead_zone: f32,
+}
+
+fn character(c: &str) -> Key {
+    Key::Character(SmolStr::new(c))
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        let mut keys = vec![
+            (Key::Named(NamedKey::Space), Action::NextStep),
+            (character("o"), Action::BakeAmbientOcclusion),
+            (character("v"), Action::SaveCamera),
+            (character("i"), Action::ToggleRenderStats),
+            (character("m"), Action::CycleSampleCount),
+            (Key::Named(NamedKey::Tab), Action::SelectNextObject),
+            (Key::Named(NamedKey::Escape), Action::ClearSelection),
+            (character("g"), Action::TranslateMode),
+            (character("r"), Action::RotateMode),
+            (character("x"), Action::SelectAxisX),
+            (character("y"), Action::SelectAxisY),
+            (character("z"), Action::SelectAxisZ),
+            (
+                Key::Named(NamedKey::F1),
+                Action::StandardView(StandardView::Front),
+            ),
+            (
+                Key::Named(NamedKey::F2),
+                Action::StandardView(StandardView::Back),
+            ),
+            (
+                Key::Named(NamedKey::F3),
+                Action::StandardView(StandardView::Left),
+            ),
+            (
+                Key::Named(NamedKey::F4),
+                Action::StandardView(StandardView::Right),
+            ),
+            (
+                Key::Named(NamedKey::F5),
+                Action::StandardView(StandardView::Top),
+            ),
+            (
+                Key::Named(NamedKey::F6),
+                Action::StandardView(StandardView::Bottom),
+            ),
+            (
+                Key::Named(NamedKey::F7),
+                Action::StandardView(StandardView::Isometric),
+            ),
+            (character("n"), Action::AddBookmark),
+            (character("w"), Action::FrameSelection),
+            (character("j"), Action::HideSelection),
+            (character("J"), Action::ShowAllObjects),
+            (character("h"), Action::ToggleGround),
+            (character("q"), Action::CycleCutaway),
+            (character("a"), Action::MoveCutawayBackward),
+            (character("d"), Action::MoveCutawayForward),
+            (character("f"), Action::FlipCutaway),
+            (character("e"), Action::ToggleExplodedView),
+            (character("="), Action::IncreaseExplosion),
+            (character("+"), Action::IncreaseExplosion),
+            (character("-"), Action::DecreaseExplosion),
+            (character(","), Action::DecreaseOpacity),
+            (character("."), Action::IncreaseOpacity),
+            (character("<"), Action::DecreaseOverlayOpacity),
+            (character(">"), Action::IncreaseOverlayOpacity),
+            (character("c"), Action::ToggleExplosionDirection),
+            (Key::Named(NamedKey::ArrowRight), Action::TransformForward),
+            (Key::Named(NamedKey::ArrowUp), Action::TransformForward),
+            (Key::Named(NamedKey::ArrowLeft), Action::TransformBackward),
+            (Key::Named(NamedKey::ArrowDown), Action::TransformBackward),
+            (Key::Named(NamedKey::Home), Action::ToggleArticulation),
+            (Key::Named(NamedKey::PageUp), Action::ArticulateForward),
+            (Key::Named(NamedKey::PageDown), Action::ArticulateBackward),
+            (Key::Named(NamedKey::End), Action::TogglePhysics),
+            (Key::Named(NamedKey::Delete), Action::KnockApart),
+        ];
+        for slot in 0..BOOKMARK_SLOTS {
+            keys.push((
+                character(&(slot + 1).to_string()),
+                Action::RecallBookmark(slot),
+            ));
+        }
+
+        let buttons = vec![
+            (GamepadButton::South, Action::NextStep),
+            (GamepadButton::East, Action::ClearSelection),
+            (GamepadButton::West, Action::SelectNextObject),
+            (GamepadButton::North, Action::FrameSelection),
+            (GamepadButton::Start, Action::ToggleExplodedView),
+            (GamepadButton::Select, Action::ToggleGround),
+            (
+                GamepadButton::DPadUp,
+                Action::StandardView(StandardView::Top),
+            ),
+            (
+                GamepadButton::DPadDown,
+                Action::StandardView(StandardView::Isometric),
+            ),
+            (
+                GamepadButton::DPadLeft,
+                Action::StandardView(StandardView::Left),
+            ),
+            (
+                GamepadButton::DPadRight,
+                Action::StandardView(StandardView::Right),
+            ),
+            (GamepadButton::LeftTrigger, Action::TransformBackward),
+            (GamepadButton::RightTrigger, Action::TransformForward),
+        ];
+
+        let axes = vec![
+            (GamepadAxis::LeftStickX, AxisAction::OrbitHorizontal, 1.0),
+            (GamepadAxis::LeftStickY, AxisAction::OrbitVertical, 1.0),
+            (GamepadAxis::RightStickX, AxisAction::PanHorizontal, 1.0),
+            (GamepadAxis::RightStickY, AxisAction::PanVertical, -1.0),
+            (GamepadAxis::RightZ, AxisAction::Zoom, 1.0),
+            (GamepadAxis::LeftZ, AxisAction::Zoom, -1.0),
+        ];
+
+        Self {
+            keys: keys
+                .into_iter()
+                .map(|(key, action)| KeyBinding { key, action })
+                .collect(),
+            buttons: buttons
+                .into_iter()
+                .map(|(button, action)| ButtonBinding { button, action })
+                .collect(),
+            axes: axes
+                .into_iter()
+                .map(|(axis, action, scale)| AxisBinding {
+                    axis,
+                    action,
+                    scale,
+                })
+                .collect(),
+            dead_zone: 0.15,
+        }
+    }
+}
+
+impl InputMap {
+    // No bindings at all, to be filled with bind_*().
+    pub fn empty() -> Self {
+        Self {
+            keys: Vec::new(),
+            buttons: Vec::new(),
+            axes: Vec::new(),
+            ..Default::default()
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    // Fields left out are taken from the default map.
+    pub fn from_json(text: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(text)
+    }
+
+    pub fn key_action(&self, key: &Key) -> Option<Action> {
+        self.keys.iter().find(|v| v.key == *key).map(|v| v.action)
+    }
+
+    pub fn button_action(&self, button: GamepadButton) -> Option<Action> {
+        self.buttons
+            .iter()
+            .find(|v| v.button == button)
+            .map(|v| v.action)
+    }
+
+    pub fn axis_bindings(&self, axis: GamepadAxis) -> impl Iterator<Item = &AxisBinding> {
+        self.axes.iter().filter(move |v| v.axis == axis)
+    }
+
+    // Replaces whatever the key was bound to.
+    pub fn bind_key(&mut self, key: Key, action: Action) {
+        self.unbind_key(&key);
+        self.keys.push(KeyBinding { key, action });
+    }
+
+    pub fn unbind_key(&mut self, key: &Key) {
+        self.keys.retain(|v| v.key != *key);
+    }
+
+    pub fn bind_button(&mut self, button: GamepadButton, action: Action) {
+        self.unbind_button(button);
+        self.buttons.push(ButtonBinding { button, action });
+    }
+
+    pub fn unbind_button(&mut self, button: GamepadButton) {
+        self.buttons.retain(|v| v.button != button);
+    }
+
+    // An axis may drive several actions, each with its own scale.
+    pub fn bind_axis(&mut self, axis: GamepadAxis, action: AxisAction, scale: f32) {
+        self.axes.retain(|v| v.axis != axis || v.action != action);
+        self.axes.push(AxisBinding {
+            axis,
+            action,
+            scale,
+        });
+    }
+
+    pub fn unbind_axis(&mut self, axis: GamepadAxis) {
+        self.axes.retain(|v| v.axis != axis);
+    }
+
+    // Value of an axis as seen by the action bound to it.
+    pub fn axis_value(&self, binding: &AxisBinding, value: f32) -> f32 {
+        if value.abs() < self.dead_zone {
+            0.0
+        } else {
+            value * binding.scale
+        }
+    }
+}
+
+#[cfg(feature = "gamepad")]
+impl GamepadButton {
+    fn from_gilrs(button: gilrs::Button) -> Option<Self> {
+        Some(match button {
+            gilrs::Button::South => GamepadButton::South,
+            gilrs::Button::East => GamepadButton::East,
+            gilrs::Button::North => GamepadButton::North,
+            gilrs::Button::West => GamepadButton::West,
+            gilrs::Button::LeftTrigger => GamepadButton::LeftTrigger,
+            gilrs::Button::LeftTrigger2 => GamepadButton::LeftTrigger2,
+            gilrs::Button::RightTrigger => GamepadButton::RightTrigger,
+            gilrs::Button::RightTrigger2 => GamepadButton::RightTrigger2,
+            gilrs::Button::Select => GamepadButton::Select,
+            gilrs::Button::Start => GamepadButton::Start,
+            gilrs::Button::LeftThumb => GamepadButton::LeftThumb,
+            gilrs::Button::RightThumb => GamepadButton::RightThumb,
+            gilrs::Button::DPadUp => GamepadButton::DPadUp,
+            gilrs::Button::DPadDown => GamepadButton::DPadDown,
+            gilrs::Button::DPadLeft => GamepadButton::DPadLeft,
+            gilrs::Button::DPadRight => GamepadButton::DPadRight,
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(feature = "gamepad")]
+impl GamepadAxis {
+    fn from_gilrs(axis: gilrs::Axis) -> Option<Self> {
+        Some(match axis {
+            gilrs::Axis::LeftStickX => GamepadAxis::LeftStickX,
+            gilrs::Axis::LeftStickY => GamepadAxis::LeftStickY,
+            gilrs::Axis::RightStickX => GamepadAxis::RightStickX,
+            gilrs::Axis::RightStickY => GamepadAxis::RightStickY,
+            gilrs::Axis::LeftZ => GamepadAxis::LeftZ,
+            gilrs::Axis::RightZ => GamepadAxis::RightZ,
+            _ => return None,
+        })
+    }
+}
+
+// Reads connected gamepads, to be polled once per frame.
+#[cfg(feature = "gamepad")]
+pub struct Gamepads {
+    gilrs: gilrs::Gilrs,
+    // Last values of axes, as actions bound to several of them take their sum.
+    values: HashMap<GamepadAxis, f32>,
+}
+
+#[cfg(feature = "gamepad")]
+impl Gamepads {
+    pub fn new() -> Result<Self, gilrs::Error> {
+        Ok(Self {
+            gilrs: gilrs::Gilrs::new()?,
+            values: HashMap::new(),
+        })
+    }
+
+    // Turns what happened on gamepads since the last call into input for the app, going
+    // through the bindings of map.
+    pub fn poll(&mut self, map: &InputMap) -> Vec<InputEvent> {
+        let mut inputs = Vec::new();
+        while let Some(event) = self.gilrs.next_event() {
+            match event.event {
+                gilrs::EventType::ButtonPressed(button, _) => {
+                    if let Some(action) =
+                        GamepadButton::from_gilrs(button).and_then(|v| map.button_action(v))
+                    {
+                        inputs.push(InputEvent::Action(action));
+                    }
+                }
+                gilrs::EventType::AxisChanged(axis, value, _) => {
+                    let Some(axis) = GamepadAxis::from_gilrs(axis) else {
+                        continue;
+                    };
+                    self.values.insert(axis, value);
+                    for binding in map.axis_bindings(axis) {
+                        let value = map
+                            .axes
+                            .iter()
+                            .filter(|v| v.action == binding.action)
+                            .map(|v| {
+                                map.axis_value(v, self.values.get(&v.axis).copied().unwrap_or(0.0))
+                            })
+                            .sum();
+                        inputs.push(InputEvent::Axis {
+                            action: binding.action,
+                            value,
+                        });
+                    }
+                }
+                // Nothing keeps moving once the gamepad is gone.
+                gilrs::EventType::Disconnected => {
+                    self.values.clear();
+                    for binding in map.axes.iter() {
+                        inputs.push(InputEvent::Axis {
+                            action: binding.action,
+                            value: 0.0,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        inputs
+    }
+}
@@ -1,45 +1,142 @@
+pub mod animation;
+pub mod breakdown;
+pub mod camera;
+pub mod clock;
+pub mod cutaway;
 mod error;
+pub mod events;
+pub mod exploded;
+pub mod gizmo;
+pub mod input;
+#[cfg(feature = "physics")]
+pub mod physics;
+pub mod recorder;
 mod texture;
+pub mod touch;
+#[cfg(feature = "egui")]
+pub mod ui;
 
 use std::{
     cell::RefCell,
     cmp::min,
     collections::{HashMap, HashSet},
-    f32,
+    f32, mem,
     rc::Rc,
-    sync::{Arc, RwLock},
+    sync::{Arc, Mutex},
     vec::Vec,
 };
 
-use cgmath::{Deg, SquareMatrix};
+use cgmath::{Deg, InnerSpace, Quaternion, Rad, Rotation3, SquareMatrix, Zero};
+use futures::channel::{mpsc::UnboundedReceiver, oneshot};
+use image::RgbaImage;
 use instant::{Duration, Instant};
 use ldraw::{
-    color::{Color, ColorCatalog},
-    document::MultipartDocument,
+    color::{Color, ColorCatalog, ColorReference},
+    document::{Document, MultipartDocument, SourceLines},
+    elements::{Camera, Command, PartReference},
     error::ResolutionError,
-    library::{resolve_dependencies_multipart, LibraryLoader, PartCache},
-    Matrix4, PartAlias, Point2, Point3, Vector2,
+    library::{
+        resolve_dependencies, resolve_dependencies_multipart_with_fallbacks, FallbackChain,
+        LibraryLoader, PartCache, PartSource, ResolutionResult,
+    },
+    parser::parse_multipart_document,
+    writer::LDrawWriter,
+    Matrix4, PartAlias, Point2, Point3, Vector2, Vector3,
 };
 use ldraw_ir::{
+    analysis::{query::ModelQuery, summary::ModelSummary},
+    animation::Animation,
+    articulation::{Articulation, JointKind, JointValue},
+    constraints::{
+        infer_connection_points, ConnectionParams, ConnectionPoint, PartConnectionQuerier,
+    },
+    flex::{generate_flex_parts, FlexOptions, FlexParts},
+    geometry::BoundingBox3,
     model::{self, GroupId, ObjectId},
-    part::bake_part_from_multipart_document,
+    occlusion::OcclusionParams,
+    part::{
+        self as part_ir, bake_part_from_multipart_document_with_options, BakeOptions,
+        PartDimensionQuerier, PartGeometryQuerier,
+    },
+    steps::StepInferenceParams,
 };
 use ldraw_renderer::{
-    display_list::{DisplayList, DisplayListOps},
+    display_list::{DisplayList, DisplayListOps, SelectionDisplayList},
     part::{Part, PartQuerier},
-    pipeline::RenderingPipelineManager,
-    projection::{PerspectiveCamera, Projection, ProjectionModifier, ProjectionMutator},
-    util::calculate_model_bounding_box,
-    Entity,
+    pipeline::{DisplayListObjectSelectionOp, RenderStats, RenderingPipelineManager},
+    projection::{
+        CameraDescription, PerspectiveCamera, Projection, ProjectionModifier, ProjectionMutator,
+    },
+    scene::{Scene, SceneModelId},
+    util::{calculate_model_bounding_box, calculate_objects_bounding_box, supported_sample_counts},
+    Entity, ObjectSelection,
 };
+use tokio::io::BufReader;
 use uuid::Uuid;
-use winit::{
-    event,
-    keyboard::{Key, NamedKey},
-    window::Window,
+use winit::{event, window::Window};
+
+use self::{
+    animation::{AnimationConfig, Animator, KeyframePlayer, Poser},
+    breakdown::{BreakdownMode, ColorBreakdown},
+    camera::{CameraBookmark, StandardView, BOOKMARK_SLOTS},
+    clock::{Clock, FixedStepClock, RealTimeClock},
+    cutaway::Cutaway,
+    events::{AppEvent, EventBus, ListenerId},
+    exploded::{ExplodedView, ExplosionDirection},
+    gizmo::{Axis, TransformGizmo, TransformMode},
+    input::{Action, AxisAction, InputMap},
+    recorder::{InputEvent, Player, Recorder, Recording, ViewState},
+    texture::Texture,
+    touch::{TouchGesture, TouchTracker},
 };
 
-use self::texture::Texture;
+// Fraction of the orbit radius moved per pixel while panning.
+const PAN_SPEED: f32 = 0.002;
+// Looking straight up or down would leave the up vector undefined.
+const MAX_ELEVATION: f32 = f32::consts::FRAC_PI_2 - 0.017;
+// Seconds taken by camera moves requested through the app.
+const CAMERA_TRANSITION_DURATION: f32 = 0.4;
+// Room left around framed objects, as a fraction of their size.
+const FRAMING_MARGIN: f32 = 1.15;
+
+// Eased camera move towards a target view.
+struct CameraTransition {
+    from: ViewState,
+    to: ViewState,
+    duration: f32,
+    // Set on the first update after the transition was requested.
+    started: Option<f32>,
+}
+
+impl CameraTransition {
+    fn interpolate(&self, time: f32) -> (ViewState, bool) {
+        let start = self.started.unwrap_or(time);
+        let t = ((time - start) / self.duration).clamp(0.0, 1.0);
+        let e = t * t * (3.0 - 2.0 * t);
+
+        // Turns the short way around.
+        let latitude = (self.to.latitude - self.from.latitude + f32::consts::PI)
+            .rem_euclid(f32::consts::TAU)
+            - f32::consts::PI;
+        // Interpolating the radius geometrically keeps the zoom speed even.
+        let radius = if self.from.radius > 0.0 && self.to.radius > 0.0 {
+            self.from.radius * (self.to.radius / self.from.radius).powf(e)
+        } else {
+            self.to.radius
+        };
+
+        (
+            ViewState {
+                latitude: self.from.latitude + latitude * e,
+                longitude: self.from.longitude + (self.to.longitude - self.from.longitude) * e,
+                radius,
+                look_at: self.from.look_at + (self.to.look_at - self.from.look_at) * e,
+                velocity: Vector2::new(0.0, 0.0),
+            },
+            t >= 1.0,
+        )
+    }
+}
 
 pub struct OrbitController {
     last_pos: Option<Point2>,
@@ -49,9 +146,12 @@ pub struct OrbitController {
     longitude: f32,
 
     pub radius: f32,
+    // Radius that frames the whole model, `radius` is relative to this when stored.
+    framing_radius: f32,
 
     tick: Option<f32>,
     velocity: Vector2,
+    transition: Option<CameraTransition>,
 
     camera: PerspectiveCamera,
 }
@@ -72,9 +172,11 @@ impl OrbitController {
             longitude: 0.262,
 
             radius: 300.0,
+            framing_radius: 300.0,
 
             velocity: Vector2::new(0.1, 0.0),
             tick: None,
+            transition: None,
 
             camera,
         }
@@ -82,6 +184,9 @@ impl OrbitController {
 
     pub fn on_mouse_press(&mut self, pressed: bool) {
         self.pressing = pressed;
+        if pressed {
+            self.transition = None;
+        }
 
         if !pressed {
             self.last_pos = None;
@@ -91,31 +196,160 @@ impl OrbitController {
     pub fn on_mouse_move(&mut self, x: f32, y: f32) {
         if self.pressing {
             if let Some(last_pos) = self.last_pos {
-                self.latitude -= (x - last_pos.x) * 0.01;
-                self.longitude = (self.longitude + (y - last_pos.y) * 0.01).clamp(
-                    -f32::consts::FRAC_PI_2 + 0.017,
-                    f32::consts::FRAC_PI_2 - 0.017,
-                );
+                self.rotate(Vector2::new(x - last_pos.x, y - last_pos.y));
             }
             self.last_pos = Some(Point2::new(x, y));
         }
     }
 
+    // Orbits around the target by pixels dragged.
+    pub fn rotate(&mut self, delta: Vector2) {
+        self.transition = None;
+        self.latitude -= delta.x * 0.01;
+        self.longitude = (self.longitude + delta.y * 0.01).clamp(-MAX_ELEVATION, MAX_ELEVATION);
+    }
+
+    // Moves the target along the view plane so that it follows pixels dragged.
+    pub fn pan(&mut self, delta: Vector2) {
+        self.transition = None;
+        let right = Vector3::new(self.latitude.cos(), 0.0, self.latitude.sin());
+        let up = Vector3::new(
+            -self.latitude.sin() * self.longitude.sin(),
+            -self.longitude.cos(),
+            self.latitude.cos() * self.longitude.sin(),
+        );
+        let factor = self.radius * PAN_SPEED;
+
+        self.camera.look_at += (up * delta.y - right * delta.x) * factor;
+    }
+
+    // Scale above 1 moves the camera closer, as when spreading fingers apart.
+    pub fn scale(&mut self, scale: f32) {
+        self.transition = None;
+        if scale > 0.0 {
+            self.radius /= scale;
+        }
+    }
+
+    pub fn apply_gesture(&mut self, gesture: &TouchGesture) {
+        self.transition = None;
+        match gesture {
+            TouchGesture::Orbit(delta) => self.rotate(*delta),
+            TouchGesture::Transform {
+                pan,
+                scale,
+                rotation,
+            } => {
+                self.pan(*pan);
+                self.scale(*scale);
+                // Without roll, twisting turns the model around its vertical axis.
+                self.latitude -= rotation;
+            }
+        }
+    }
+
+    pub fn view_state(&self) -> ViewState {
+        ViewState {
+            latitude: self.latitude,
+            longitude: self.longitude,
+            radius: self.radius,
+            look_at: self.camera.look_at,
+            velocity: self.velocity,
+        }
+    }
+
+    pub fn set_view_state(&mut self, state: &ViewState) {
+        self.transition = None;
+        self.latitude = state.latitude;
+        self.longitude = state.longitude;
+        self.radius = state.radius;
+        self.camera.look_at = state.look_at;
+        self.velocity = state.velocity;
+    }
+
     pub fn zoom(&mut self, delta: f32) {
+        self.transition = None;
         if self.radius - delta > 0.0 {
             self.radius -= delta;
         }
     }
 
+    // Moves the camera to the given view over `duration` seconds, or at once if it is not
+    // positive. Spinning stops, and any user input cancels the move.
+    pub fn animate_to_view(&mut self, target: ViewState, duration: f32) {
+        let target = ViewState {
+            longitude: target.longitude.clamp(-MAX_ELEVATION, MAX_ELEVATION),
+            velocity: Vector2::new(0.0, 0.0),
+            ..target
+        };
+        if duration > 0.0 {
+            self.velocity = Vector2::new(0.0, 0.0);
+            self.transition = Some(CameraTransition {
+                from: self.view_state(),
+                to: target,
+                duration,
+                started: None,
+            });
+        } else {
+            self.set_view_state(&target);
+        }
+    }
+
+    // Keeps the viewing angles.
+    pub fn animate_to(&mut self, look_at: Point3, radius: f32, duration: f32) {
+        let target = ViewState {
+            look_at,
+            radius,
+            ..self.view_state()
+        };
+        self.animate_to_view(target, duration);
+    }
+
+    pub fn is_animating(&self) -> bool {
+        self.transition.is_some()
+    }
+
+    // Jumps to where the running transition would end.
+    pub fn stop_animation(&mut self) {
+        if let Some(transition) = self.transition.take() {
+            self.set_view_state(&transition.to);
+        }
+    }
+
+    // Distance at which a sphere around the bounding box fills the vertical field of view.
+    pub fn fit_radius(&self, bounding_box: &BoundingBox3) -> f32 {
+        let half_fov = Rad::from(self.camera.fov).0 * 0.5;
+        (bounding_box.len() * 0.5 * FRAMING_MARGIN / half_fov.sin()).max(1.0)
+    }
+
+    pub fn frame_bounds(&mut self, bounding_box: &BoundingBox3, duration: f32) {
+        if bounding_box.is_null() {
+            return;
+        }
+        let center = bounding_box.center();
+        let radius = self.fit_radius(bounding_box);
+        self.animate_to(Point3::new(center.x, center.y, center.z), radius, duration);
+    }
+
     pub fn update(&mut self, width: u32, height: u32, tick: Option<f32>) -> Vec<ProjectionMutator> {
+        if let (Some(transition), Some(time)) = (&mut self.transition, tick) {
+            transition.started.get_or_insert(time);
+            let (state, finished) = transition.interpolate(time);
+            self.latitude = state.latitude;
+            self.longitude = state.longitude;
+            self.radius = state.radius;
+            self.camera.look_at = state.look_at;
+            if finished {
+                self.transition = None;
+            }
+        }
+
         if let (Some(p), Some(n)) = (self.tick, tick) {
             let delta = n - p;
 
             self.latitude += self.velocity.x * delta;
-            self.longitude = (self.longitude + self.velocity.y * delta).clamp(
-                -f32::consts::FRAC_PI_2 + 0.017,
-                f32::consts::FRAC_PI_2 - 0.017,
-            );
+            self.longitude =
+                (self.longitude + self.velocity.y * delta).clamp(-MAX_ELEVATION, MAX_ELEVATION);
         }
         self.tick = tick;
 
@@ -123,6 +357,76 @@ impl OrbitController {
         self.camera.update_projections((width, height).into())
     }
 
+    // Points the camera at the center of given bounding box, restoring the stored camera if any.
+    pub fn frame(&mut self, bounding_box: &BoundingBox3, camera: Option<&Camera>) {
+        self.transition = None;
+        let center = bounding_box.center();
+        self.camera.look_at = Point3::new(center.x, center.y, center.z);
+        self.framing_radius = bounding_box.len() * 2.0;
+        self.radius = self.framing_radius;
+
+        if let Some(camera) = camera {
+            // Latitude is an elevation angle in stored cameras, as in ldr2img.
+            self.latitude = Rad::from(camera.longitude).0;
+            self.longitude = Rad::from(camera.latitude)
+                .0
+                .clamp(-MAX_ELEVATION, MAX_ELEVATION);
+            self.radius = self.framing_radius * camera.distance.max(0.01);
+            if let Some(fov) = camera.fov {
+                self.camera.fov = fov;
+            }
+            self.velocity = Vector2::new(0.0, 0.0);
+        }
+    }
+
+    pub fn to_camera(&self) -> Camera {
+        Camera {
+            latitude: Rad(self.longitude).into(),
+            longitude: Rad(self.latitude.rem_euclid(f32::consts::TAU)).into(),
+            distance: if self.framing_radius > 0.0 {
+                self.radius / self.framing_radius
+            } else {
+                1.0
+            },
+            fov: Some(self.camera.fov),
+        }
+    }
+
+    // Keeps the target and distance, and stops spinning.
+    pub fn set_standard_view(&mut self, view: StandardView, duration: f32) {
+        let (latitude, longitude) = view.angles();
+        let target = ViewState {
+            latitude: Rad::from(Deg(latitude)).0,
+            longitude: Rad::from(Deg(longitude)).0,
+            ..self.view_state()
+        };
+        self.animate_to_view(target, duration);
+    }
+
+    pub fn bookmark(&self) -> CameraBookmark {
+        CameraBookmark {
+            position: self.derive_coordinate(),
+            look_at: self.camera.look_at,
+            fov: self.camera.fov,
+        }
+    }
+
+    pub fn restore_bookmark(&mut self, bookmark: &CameraBookmark, duration: f32) {
+        let mut target = ViewState {
+            look_at: bookmark.look_at,
+            ..self.view_state()
+        };
+        let offset = bookmark.position - bookmark.look_at;
+        let radius = offset.magnitude();
+        if radius > 0.0 {
+            target.radius = radius;
+            target.latitude = offset.x.atan2(-offset.z);
+            target.longitude = (-offset.y / radius).asin();
+        }
+        self.camera.fov = bookmark.fov;
+        self.animate_to_view(target, duration);
+    }
+
     fn derive_coordinate(&self) -> Point3 {
         let look_at = &self.camera.look_at;
         let x = self.latitude.sin() * self.longitude.cos() * self.radius + look_at.x;
@@ -139,7 +443,7 @@ impl Default for OrbitController {
     }
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum State {
     Playing,
     Step,
@@ -147,17 +451,48 @@ pub enum State {
 }
 
 #[derive(Default)]
-struct SimplePartsPool(pub HashMap<PartAlias, Part>);
+struct SimplePartsPool(pub HashMap<PartAlias, (Part, part_ir::Part)>);
 
 impl PartQuerier<PartAlias> for SimplePartsPool {
     fn get(&self, alias: &PartAlias) -> Option<&Part> {
-        self.0.get(alias)
+        self.0.get(alias).map(|(part, _)| part)
+    }
+}
+
+impl PartDimensionQuerier<PartAlias> for SimplePartsPool {
+    fn query_part_dimension(&self, alias: &PartAlias) -> Option<BoundingBox3> {
+        self.0.get(alias).map(|(part, _)| part.bounding_box.clone())
+    }
+}
+
+impl PartGeometryQuerier<PartAlias> for SimplePartsPool {
+    fn query_part_geometry(&self, alias: &PartAlias) -> Option<&part_ir::Part> {
+        self.0.get(alias).map(|(_, geometry)| geometry)
     }
 }
 
-const FALL_INTERVAL: f32 = 0.2;
-const FALL_INTERVAL_UPPER_BOUND: f32 = 10.0;
-const FALL_DURATION: f32 = 0.5;
+#[derive(Default)]
+struct SimpleConnectionPool(pub HashMap<PartAlias, Vec<ConnectionPoint>>);
+
+impl PartConnectionQuerier<PartAlias> for SimpleConnectionPool {
+    fn query_connection_points(&self, alias: &PartAlias) -> Option<&[ConnectionPoint]> {
+        self.0.get(alias).map(|v| v.as_slice())
+    }
+}
+
+// In LDraw units per second.
+#[cfg(feature = "physics")]
+const KNOCK_SPEED: f32 = 1000.0;
+// Per second at full tilt of a gamepad axis, in logical pixels dragged for orbiting and
+// panning. Zooming scales the orbit radius by e to the power of its speed.
+const AXIS_ORBIT_SPEED: f32 = 200.0;
+const AXIS_PAN_SPEED: f32 = 400.0;
+const AXIS_ZOOM_SPEED: f32 = 1.0;
+// Opacity of parts from earlier steps while step ghosting is on.
+const GHOST_ALPHA: f32 = 0.2;
+const STATS_TICK_INTERVAL: Duration = Duration::from_secs(1);
+// Pointer movement in logical pixels under which a press and release is a click.
+const CLICK_DISTANCE: f32 = 4.0;
 
 #[derive(Clone, Debug)]
 struct RenderingItem {
@@ -167,6 +502,7 @@ struct RenderingItem {
     color: Color,
 }
 
+#[allow(clippy::large_enum_variant)]
 enum RenderingStep {
     Item(RenderingItem),
     Step,
@@ -185,9 +521,12 @@ struct AnimatedModel {
     animating: RefCell<Vec<AnimatingRenderingItem>>,
 
     state: State,
+    step: usize,
     pointer: Option<usize>,
-    fall_interval: f32,
+    animator: Rc<dyn Animator>,
+    interval: f32,
     last_time: Option<f32>,
+    opacity: f32,
 }
 
 impl Default for AnimatedModel {
@@ -198,9 +537,12 @@ impl Default for AnimatedModel {
             animating: RefCell::new(Vec::new()),
 
             state: State::Finished,
+            step: 0,
             pointer: None,
-            fall_interval: FALL_INTERVAL,
+            animator: Rc::new(AnimationConfig::default()),
+            interval: 0.0,
             last_time: None,
+            opacity: 1.0,
         }
     }
 }
@@ -236,7 +578,7 @@ impl AnimatedModel {
                             items,
                             model,
                             &group.objects,
-                            object.id,
+                            Self::uuid_xor(parent_id, object.id),
                             matrix * pg.matrix,
                         );
                     }
@@ -250,9 +592,9 @@ impl AnimatedModel {
         model: &model::Model<PartAlias>,
         group_id: Option<GroupId>,
         color_catalog: &ColorCatalog,
-        animated: bool,
+        animator: Option<Rc<dyn Animator>>,
     ) -> Self {
-        if animated {
+        if let Some(animator) = animator {
             let objects = if let Some(group_id) = group_id {
                 model
                     .object_groups
@@ -281,13 +623,12 @@ impl AnimatedModel {
                 animating: RefCell::new(Vec::new()),
 
                 state: State::Playing,
+                step: 0,
                 pointer: None,
-                fall_interval: if items_len as f32 * FALL_INTERVAL >= FALL_INTERVAL_UPPER_BOUND {
-                    FALL_INTERVAL_UPPER_BOUND / items_len as f32
-                } else {
-                    FALL_INTERVAL
-                },
+                interval: animator.interval(items_len),
+                animator,
                 last_time: None,
+                opacity: 1.0,
             }
         } else {
             let display_list = DisplayList::from_model(model, group_id, color_catalog);
@@ -298,11 +639,119 @@ impl AnimatedModel {
                 animating: RefCell::new(Vec::new()),
 
                 state: State::Finished,
+                step: 0,
                 pointer: None,
-                fall_interval: 0.0,
+                animator: Rc::new(AnimationConfig::default()),
+                interval: 0.0,
                 last_time: None,
+                opacity: 1.0,
+            }
+        }
+    }
+
+    // Applies opacity to every part that has been shown. Parts appearing later pick it up
+    // while being animated.
+    pub fn set_opacity(
+        &mut self,
+        opacity: f32,
+        model: &model::Model<PartAlias>,
+        group_id: Option<GroupId>,
+        color_catalog: &ColorCatalog,
+    ) {
+        self.opacity = opacity;
+
+        let objects = model
+            .get_objects(group_id)
+            .map(|objects| objects.cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let ops = DisplayList::expand_objects(model, &objects, color_catalog, Clone::clone)
+            .into_iter()
+            .filter_map(|op| match op {
+                DisplayListOps::Insert { key, color, .. } => Some(DisplayListOps::UpdateAlpha {
+                    key,
+                    alpha: color.color.alpha() as f32 / 255.0 * opacity,
+                }),
+                _ => None,
+            });
+        self.display_list.mutate_all(ops);
+    }
+
+    // Brings the display list from showing one model to showing another, touching only
+    // instances that differ. Instances are matched by key, so ids of objects need to stay
+    // the same between the two.
+    pub fn apply_model_changes(
+        &mut self,
+        previous: (&model::Model<PartAlias>, Option<GroupId>),
+        model: &model::Model<PartAlias>,
+        group_id: Option<GroupId>,
+        color_catalog: &ColorCatalog,
+    ) {
+        let expand = |model: &model::Model<PartAlias>, group_id| {
+            let objects = model
+                .get_objects(group_id)
+                .map(|objects| objects.cloned().collect::<Vec<_>>())
+                .unwrap_or_default();
+            DisplayList::expand_objects(model, &objects, color_catalog, Clone::clone)
+        };
+
+        let mut instances = expand(previous.0, previous.1)
+            .into_iter()
+            .filter_map(|op| match op {
+                DisplayListOps::Insert {
+                    group,
+                    key,
+                    matrix,
+                    color,
+                    ..
+                } => Some((key, (group, matrix, color))),
+                _ => None,
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut ops = Vec::new();
+        for op in expand(model, group_id) {
+            let DisplayListOps::Insert {
+                group,
+                key,
+                matrix,
+                color,
+                alpha,
+            } = op
+            else {
+                continue;
+            };
+            match instances.remove(&key) {
+                Some((previous_group, previous_matrix, previous_color))
+                    if previous_group == group =>
+                {
+                    if previous_matrix != matrix || previous_color != color {
+                        ops.push(DisplayListOps::Update { key, matrix, color });
+                    }
+                }
+                previous => {
+                    if previous.is_some() {
+                        ops.push(DisplayListOps::Remove { key });
+                    }
+                    ops.push(DisplayListOps::Insert {
+                        group,
+                        key,
+                        matrix,
+                        color,
+                        alpha,
+                    });
+                }
             }
         }
+        ops.extend(
+            instances
+                .into_keys()
+                .map(|key| DisplayListOps::Remove { key }),
+        );
+        self.display_list.mutate_all(ops.into_iter());
+
+        if self.opacity < 1.0 {
+            self.set_opacity(self.opacity, model, group_id, color_catalog);
+        }
     }
 
     pub fn advance(&mut self, time: f32) {
@@ -317,16 +766,12 @@ impl AnimatedModel {
                 count += 1;
             }
 
-            self.fall_interval = if count as f32 * FALL_INTERVAL >= FALL_INTERVAL_UPPER_BOUND {
-                FALL_INTERVAL_UPPER_BOUND / count as f32
-            } else {
-                FALL_INTERVAL
-            };
+            self.interval = self.animator.interval(count);
         }
 
         let next = if self.pointer.is_none() && self.last_time.is_none() {
             0
-        } else if time - self.last_time.unwrap() >= self.fall_interval {
+        } else if time - self.last_time.unwrap() >= self.interval {
             self.pointer.unwrap() + 1
         } else {
             return;
@@ -357,8 +802,82 @@ impl AnimatedModel {
             }
             RenderingStep::Step => {
                 self.state = State::Step;
+                self.step += 1;
+            }
+        }
+    }
+
+    // Steps the model is built in, counting parts after the last step as one.
+    fn step_count(&self) -> usize {
+        let breaks = self
+            .items
+            .iter()
+            .filter(|v| matches!(v, RenderingStep::Step))
+            .count();
+        match self.items.last() {
+            Some(RenderingStep::Item(_)) => breaks + 1,
+            _ => breaks,
+        }
+    }
+
+    // Shows parts of the first given number of steps at once, stopping at the end of the last
+    // one as if it had been played up to there. Steps are counted from 1.
+    fn seek(&mut self, step: usize) {
+        let breaks = self
+            .items
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| matches!(v, RenderingStep::Step))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        let step = step.max(1);
+        let end = breaks.get(step - 1).copied().unwrap_or(self.items.len());
+
+        let mut ops = vec![DisplayListOps::RestoreAllColors];
+        for (i, item) in self.items.iter().enumerate() {
+            let RenderingStep::Item(item) = item else {
+                continue;
+            };
+            ops.push(DisplayListOps::Remove { key: item.id });
+            if i < end {
+                ops.push(DisplayListOps::Insert {
+                    group: item.alias.clone(),
+                    key: item.id,
+                    matrix: item.matrix,
+                    color: item.color.clone(),
+                    alpha: Some(item.color.color.alpha() as f32 / 255.0 * self.opacity),
+                });
             }
         }
+        self.display_list.mutate_all(ops.into_iter());
+        self.animating.borrow_mut().clear();
+
+        self.pointer = Some(end);
+        // Advancing from here moves on to the next part right away.
+        self.last_time = Some(f32::NEG_INFINITY);
+        if end < self.items.len() {
+            self.state = State::Step;
+            self.step = step;
+        } else {
+            self.state = State::Finished;
+            self.step = breaks.len();
+        }
+    }
+
+    // Ghosts parts placed before the current step.
+    fn ghost_previous_steps(&mut self) {
+        let end = self.pointer.unwrap_or(0).min(self.items.len());
+        let ops = self.items[..end]
+            .iter()
+            .filter_map(|v| match v {
+                RenderingStep::Item(item) => Some(DisplayListOps::Ghost {
+                    key: item.id,
+                    alpha: GHOST_ALPHA,
+                }),
+                RenderingStep::Step => None,
+            })
+            .collect::<Vec<_>>();
+        self.display_list.mutate_all(ops.into_iter());
     }
 
     pub fn animate(&mut self, time: f32) {
@@ -367,15 +886,18 @@ impl AnimatedModel {
         }
 
         let mut animating = self.animating.borrow_mut();
+        let duration = self.animator.duration();
 
         for item in animating.iter_mut() {
-            let elapsed = (time - item.started_at).clamp(0.0, FALL_DURATION) / FALL_DURATION;
-
-            let ease = -(f32::consts::FRAC_PI_2 + elapsed * f32::consts::FRAC_PI_2).cos();
-            let alpha = ease * (item.item.color.color.alpha() as f32 / 255.0);
+            let elapsed = if duration > 0.0 {
+                (time - item.started_at).clamp(0.0, duration) / duration
+            } else {
+                1.0
+            };
 
-            let mut matrix = item.item.matrix;
-            matrix[3][1] = item.item.matrix[3][1] + (-(1.0 - ease) * 300.0);
+            let frame = self.animator.frame(&item.item.matrix, elapsed);
+            let matrix = frame.matrix;
+            let alpha = frame.alpha * (item.item.color.color.alpha() as f32 / 255.0) * self.opacity;
 
             self.display_list.mutate_all(
                 vec![
@@ -394,7 +916,62 @@ impl AnimatedModel {
             item.progress = elapsed;
         }
 
-        animating.retain(|v| time - v.started_at < FALL_DURATION);
+        animating.retain(|v| time - v.started_at < duration);
+    }
+}
+
+// Overlay keys are derived from this so that they never clash with the main document.
+const OVERLAY_NAMESPACE: Uuid = Uuid::from_u128(0x5f1c_93d4_7a2e_4b0f_9c61_0e8d_2b47_a3f5);
+const OPACITY_STEP: f32 = 0.1;
+
+struct OverlayItem {
+    group: PartAlias,
+    key: ObjectId,
+    matrix: Matrix4,
+    color: Color,
+}
+
+// A secondary document rendered translucently on top of the main one, sharing its origin.
+struct Overlay {
+    document: MultipartDocument,
+    resolution_result: ResolutionResult,
+    items: Vec<OverlayItem>,
+    opacity: f32,
+}
+
+impl Overlay {
+    fn alpha(&self, item: &OverlayItem) -> f32 {
+        item.color.color.alpha() as f32 / 255.0 * self.opacity
+    }
+
+    fn insert_ops(&self) -> Vec<DisplayListOps<ObjectId, PartAlias>> {
+        self.items
+            .iter()
+            .map(|item| DisplayListOps::Insert {
+                group: item.group.clone(),
+                key: item.key,
+                matrix: item.matrix,
+                color: item.color.clone(),
+                alpha: Some(self.alpha(item)),
+            })
+            .collect()
+    }
+
+    fn alpha_ops(&self) -> Vec<DisplayListOps<ObjectId, PartAlias>> {
+        self.items
+            .iter()
+            .map(|item| DisplayListOps::UpdateAlpha {
+                key: item.key,
+                alpha: self.alpha(item),
+            })
+            .collect()
+    }
+
+    fn remove_ops(&self) -> Vec<DisplayListOps<ObjectId, PartAlias>> {
+        self.items
+            .iter()
+            .map(|item| DisplayListOps::Remove { key: item.key })
+            .collect()
     }
 }
 
@@ -407,22 +984,74 @@ pub struct App<L: LibraryLoader> {
     config: wgpu::SurfaceConfiguration,
     pub size: winit::dpi::PhysicalSize<u32>,
     pub adapter_info: wgpu::AdapterInfo,
+    // Input is handled in logical pixels so that it feels the same on every display.
+    scale_factor: f64,
 
     max_texture_size: u32,
     framebuffer_texture: Option<Texture>,
     depth_texture: Texture,
     sample_count: u32,
+    supported_sample_counts: Vec<u32>,
 
     projection: Entity<Projection>,
     pipelines: RenderingPipelineManager,
+    render_stats: RenderStats,
+    show_render_stats: bool,
+    last_stats_tick: Option<Instant>,
+    events: EventBus,
+    // Filled from the device's error callback, which may run on any thread.
+    gpu_errors: Arc<Mutex<Vec<String>>>,
+    clock: Box<dyn Clock>,
 
     loader: Rc<L>,
+    fallbacks: FallbackChain,
     colors: Rc<ColorCatalog>,
 
     parts: Rc<RefCell<SimplePartsPool>>,
+    connections: SimpleConnectionPool,
+    cache: PartCache,
+    resolution_result: ResolutionResult,
+    bake_options: BakeOptions,
+    document: Option<MultipartDocument>,
+    // Lines of the document as given, which objects are mapped back to.
+    source_lines: Option<SourceLines>,
+    document_modified: bool,
     model: Option<model::Model<PartAlias>>,
+    summary: Option<ModelSummary<PartAlias>>,
+    render_target: Option<GroupId>,
     animated_model: AnimatedModel,
-
+    gizmo: TransformGizmo,
+    selection_outline: Entity<DisplayList<ObjectId, PartAlias>>,
+    exploded_view: ExplodedView,
+    keyframe_player: Option<KeyframePlayer>,
+    poser: Option<Poser>,
+    #[cfg(feature = "physics")]
+    physics: Option<physics::PhysicsSimulation>,
+    cutaway: Cutaway,
+    breakdown: ColorBreakdown,
+    document_opacity: f32,
+    step_ghosting: bool,
+    step_inference: bool,
+    animator: Rc<dyn Animator>,
+    overlay: Option<Overlay>,
+    // Further models shown next to the document, each with its own placement.
+    scene: Scene<ObjectId, PartAlias>,
+    scene_documents: HashMap<SceneModelId, (MultipartDocument, ResolutionResult)>,
+
+    touch_tracker: TouchTracker,
+    pointer: Point2,
+    click_origin: Option<Point2>,
+    // Where the pointer has been clicked, and whether the click extends the selection. Picking
+    // waits for the GPU, so it is left to the caller.
+    pending_pick: Option<(Point2, bool)>,
+    modifiers: winit::keyboard::ModifiersState,
+    input_map: InputMap,
+    // Axes being held, and when they were last applied.
+    axes: HashMap<AxisAction, f32>,
+    last_axis_time: Option<f32>,
+    recorder: Option<Recorder>,
+    player: Option<Player>,
+    bookmarks: [Option<CameraBookmark>; BOOKMARK_SLOTS],
     orbit_controller: RefCell<OrbitController>,
 }
 
@@ -434,6 +1063,7 @@ impl<L: LibraryLoader> App<L> {
         supports_antialiasing: bool,
     ) -> Result<Self, error::AppCreationError> {
         let window_size = window.inner_size();
+        let scale_factor = window.scale_factor();
 
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
             backends: wgpu::Backends::all(),
@@ -446,8 +1076,6 @@ impl<L: LibraryLoader> App<L> {
             gles_minor_version: wgpu::Gles3MinorVersion::Automatic,
         });
 
-        let sample_count = if supports_antialiasing { 4 } else { 1 };
-
         let surface = instance.create_surface(Arc::clone(&window))?;
 
         let adapter = wgpu::util::initialize_adapter_from_env_or_default(&instance, Some(&surface))
@@ -459,6 +1087,14 @@ impl<L: LibraryLoader> App<L> {
         let (device, queue, max_texture_size) =
             ldraw_renderer::util::request_device(&adapter, None).await?;
 
+        let gpu_errors = Arc::new(Mutex::new(Vec::new()));
+        {
+            let gpu_errors = Arc::clone(&gpu_errors);
+            device.on_uncaptured_error(Box::new(move |error| {
+                gpu_errors.lock().unwrap().push(error.to_string());
+            }));
+        }
+
         let size = winit::dpi::PhysicalSize {
             width: min(window_size.width, max_texture_size),
             height: min(window_size.height, max_texture_size),
@@ -472,6 +1108,13 @@ impl<L: LibraryLoader> App<L> {
             .find(|f| f.is_srgb())
             .unwrap_or(surface_caps.formats[0]);
 
+        let supported_sample_counts = supported_sample_counts(&adapter, surface_format);
+        let sample_count = if supports_antialiasing && supported_sample_counts.contains(&4) {
+            4
+        } else {
+            1
+        };
+
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
@@ -517,26 +1160,81 @@ impl<L: LibraryLoader> App<L> {
             config,
             size,
             adapter_info,
+            scale_factor,
 
             max_texture_size,
             framebuffer_texture,
             depth_texture,
             sample_count,
+            supported_sample_counts,
 
             projection,
             pipelines,
+            render_stats: RenderStats::default(),
+            show_render_stats: false,
+            last_stats_tick: None,
+            events: EventBus::default(),
+            gpu_errors,
+            clock: Box::new(RealTimeClock::new()),
 
             loader,
+            fallbacks: FallbackChain::new(),
             colors,
 
             parts: Rc::new(RefCell::new(SimplePartsPool::default())),
+            connections: SimpleConnectionPool::default(),
+            cache: PartCache::new(),
+            resolution_result: ResolutionResult::new(),
+            bake_options: BakeOptions::default(),
+            document: None,
+            source_lines: None,
+            document_modified: false,
             model: None,
+            summary: None,
+            render_target: None,
             animated_model: AnimatedModel::default(),
-
+            gizmo: TransformGizmo::default(),
+            selection_outline: DisplayList::new().into(),
+            exploded_view: ExplodedView::default(),
+            keyframe_player: None,
+            poser: None,
+            #[cfg(feature = "physics")]
+            physics: None,
+            cutaway: Cutaway::default(),
+            breakdown: ColorBreakdown::default(),
+            document_opacity: 1.0,
+            step_ghosting: false,
+            step_inference: false,
+            animator: Rc::new(AnimationConfig::default()),
+            overlay: None,
+            scene: Scene::new(),
+            scene_documents: HashMap::new(),
+
+            touch_tracker: TouchTracker::default(),
+            pointer: Point2::new(0.0, 0.0),
+            click_origin: None,
+            pending_pick: None,
+            modifiers: winit::keyboard::ModifiersState::empty(),
+            input_map: InputMap::default(),
+            axes: HashMap::new(),
+            last_axis_time: None,
+            recorder: None,
+            player: None,
+            bookmarks: Default::default(),
             orbit_controller,
         })
     }
 
+    // Loaders tried for parts the library does not have, taking effect on next load.
+    pub fn set_fallbacks(&mut self, fallbacks: FallbackChain) {
+        self.fallbacks = fallbacks;
+    }
+
+    // Where a part of the document was loaded from.
+    pub fn part_source(&self, alias: &PartAlias) -> Option<&PartSource> {
+        self.resolution_result.source(alias)
+    }
+
     pub fn loaded_parts(&self) -> HashSet<PartAlias> {
         let mut result = HashSet::new();
 
@@ -547,85 +1245,1077 @@ impl<L: LibraryLoader> App<L> {
 
     pub async fn set_document<F: Fn(PartAlias, Result<(), ResolutionError>)>(
         &mut self,
-        cache: Arc<RwLock<PartCache>>,
+        cache: PartCache,
         document: &MultipartDocument,
         on_update: &F,
     ) -> Result<(), ResolutionError> {
-        let resolution_result = resolve_dependencies_multipart(
-            document,
-            Arc::clone(&cache),
-            &self.colors,
-            &*self.loader,
-            on_update,
-        )
-        .await;
+        self.source_lines = None;
+        self.load_document(cache, document, on_update, false).await
+    }
 
-        let model = model::Model::from_ldraw_multipart_document(
+    // Also takes lines the document has been parsed from, so that objects can be traced back
+    // to them with selected_lines().
+    pub async fn set_document_with_lines<F: Fn(PartAlias, Result<(), ResolutionError>)>(
+        &mut self,
+        cache: PartCache,
+        document: &MultipartDocument,
+        lines: SourceLines,
+        on_update: &F,
+    ) -> Result<(), ResolutionError> {
+        self.source_lines = Some(lines);
+        self.load_document(cache, document, on_update, false).await
+    }
+
+    // Lines of selected objects, along with the subpart each is in. Objects added since the
+    // document has been set have none.
+    pub fn selected_lines(&self) -> Vec<(Option<PartAlias>, usize)> {
+        let Some(model) = &self.model else {
+            return Vec::new();
+        };
+        let mut lines = self
+            .gizmo
+            .selection()
+            .iter()
+            .filter_map(|id| {
+                let source = model.source_of(id)?;
+                Some((source.subpart.clone(), source.line?))
+            })
+            .collect::<Vec<_>>();
+        lines.sort_by_key(|(_, line)| *line);
+        lines
+    }
+
+    // Applies an edited version of the document. Parts already in use are neither resolved nor
+    // baked again, embedded parts are baked again only for changed subparts, and only
+    // instances that differ are updated in the display list. Falls back to rebuilding
+    // everything while the model is being animated or shown in a way that changes instances.
+    pub async fn update_document<F: Fn(PartAlias, Result<(), ResolutionError>)>(
+        &mut self,
+        document: &MultipartDocument,
+        lines: Option<SourceLines>,
+        on_update: &F,
+    ) -> Result<(), ResolutionError> {
+        self.source_lines = lines;
+
+        let incremental = self.animated_model.state == State::Finished
+            && !self.exploded_view.is_active()
+            && !self.breakdown.is_active()
+            && generate_flex_parts(document, &self.colors, &FlexOptions::default()).is_none();
+        if !incremental || self.document.is_none() || self.model.is_none() {
+            return self
+                .load_document(self.cache.clone(), document, on_update, true)
+                .await;
+        }
+        let previous = self.document.take().unwrap();
+        let previous_model = self.model.take().unwrap();
+
+        let changes = document.changes_from(&previous);
+        if changes.is_empty() {
+            // Lines may still have moved around.
+            let mut model = previous_model;
+            if let Some(lines) = &self.source_lines {
+                model.set_source_lines(lines);
+            }
+            self.model = Some(model);
+            self.document = Some(previous);
+            return Ok(());
+        }
+
+        let render_target_name = self.render_target.and_then(|group_id| {
+            previous_model
+                .object_groups
+                .get(&group_id)
+                .map(|group| group.alias())
+        });
+
+        // Parts come from the cache, so that only new ones are loaded.
+        let resolution_events = RefCell::new(Vec::new());
+        let mut resolution_result = resolve_dependencies_multipart_with_fallbacks(
             document,
+            self.cache.clone(),
             &self.colors,
-            Some((&*self.loader, cache)),
+            &*self.loader,
+            &self.fallbacks,
+            &Self::collect_resolution_events(&resolution_events, on_update),
         )
         .await;
-
-        self.parts
-            .borrow_mut()
+        self.emit_all(resolution_events.into_inner());
+        self.resolve_primitive_alternatives(&mut resolution_result)
+            .await;
+        self.resolution_result = resolution_result;
+
+        let mut model = previous_model
+            .rebuild_from_ldraw_multipart_document(
+                document,
+                &changes.subparts,
+                &self.colors,
+                Some((&*self.loader, self.cache.clone())),
+            )
+            .await;
+        if let Some(lines) = &self.source_lines {
+            model.set_source_lines(lines);
+        }
+        model.assign_stable_ids(&document.body.name);
+
+        let added = document
+            .list_dependencies()
+            .into_iter()
+            .filter(|alias| !self.parts.borrow().0.contains_key(alias))
+            .collect::<Vec<_>>();
+        let resolution_result = &self.resolution_result;
+        let parts = added
+            .iter()
+            .filter_map(|alias| {
+                resolution_result.query(alias, true).map(|(part, local)| {
+                    let geometry = bake_part_from_multipart_document_with_options(
+                        part,
+                        resolution_result,
+                        local,
+                        &self.bake_options,
+                    );
+                    (
+                        alias.clone(),
+                        (Part::new(&geometry, &self.device, &self.colors), geometry),
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        self.parts.borrow_mut().0.extend(parts);
+        self.connections
             .0
-            .extend(
-                document
-                    .list_dependencies()
-                    .into_iter()
-                    .filter_map(|alias| {
-                        resolution_result.query(&alias, true).map(|(part, local)| {
-                            (
-                                alias.clone(),
-                                Part::new(
-                                    &bake_part_from_multipart_document(
-                                        part,
-                                        &resolution_result,
-                                        local,
-                                    ),
-                                    &self.device,
-                                    &self.colors,
-                                ),
-                            )
-                        })
-                    }),
-            );
+            .extend(added.into_iter().filter_map(|alias| {
+                resolution_result.query(&alias, true).map(|(part, local)| {
+                    let points = infer_connection_points(part, resolution_result, local);
+                    (alias, points)
+                })
+            }));
 
-        let bounding_box = calculate_model_bounding_box(&model, None, &*self.parts.borrow());
-        let center = bounding_box.center();
+        if self.step_inference && !model.has_steps(None) {
+            model.infer_steps(&*self.parts.borrow(), &StepInferenceParams::default());
+        }
 
-        self.animated_model = AnimatedModel::from_model(&model, None, &self.colors, true);
+        let render_target = render_target_name.and_then(|name| {
+            model
+                .object_groups
+                .values()
+                .find(|group| group.alias() == name)
+                .map(|group| group.id)
+        });
+        self.stop_keyframes();
+        #[cfg(feature = "physics")]
+        self.stop_physics();
+        let articulation = self.clear_articulation();
+        self.animated_model.apply_model_changes(
+            (&previous_model, self.render_target),
+            &model,
+            render_target,
+            &self.colors,
+        );
+
+        self.summary = Some(model.summary(render_target, &*self.parts.borrow()));
         self.model = Some(model);
+        self.render_target = render_target;
+        self.document = Some(document.clone());
+        self.document_modified = true;
+        self.gizmo.clear();
+        self.selection_outline = DisplayList::new().into();
+        self.update_ground();
+        // Joints outlive edits, so that objects keep their pose.
+        if let Some(articulation) = articulation {
+            self.set_articulation(articulation);
+        }
 
-        let mut orbit_controller = self.orbit_controller.borrow_mut();
-        orbit_controller.camera.look_at = Point3::new(center.x, center.y, center.z);
-        orbit_controller.radius = (bounding_box.len_x() * bounding_box.len_x()
-            + bounding_box.len_y() * bounding_box.len_y()
-            + bounding_box.len_z() * bounding_box.len_z())
-        .sqrt()
-            * 2.0;
+        self.emit_model_loaded();
 
         Ok(())
     }
 
-    pub fn advance(&mut self, time: f32) {
-        self.animated_model.advance(time);
-    }
+    // Rebuilds everything from the document. With keep_view, the camera and the submodel
+    // being viewed are left as they are and the document is considered modified.
+    async fn load_document<F: Fn(PartAlias, Result<(), ResolutionError>)>(
+        &mut self,
+        cache: PartCache,
+        document: &MultipartDocument,
+        on_update: &F,
+        keep_view: bool,
+    ) -> Result<(), ResolutionError> {
+        // Group ids change along with the name of the document, so the submodel is looked up
+        // by alias.
+        let render_target_name = match (&self.model, self.render_target) {
+            (Some(model), Some(group_id)) if keep_view => model
+                .object_groups
+                .get(&group_id)
+                .map(|group| group.alias()),
+            _ => None,
+        };
 
-    pub fn animate(&mut self, time: f32) {
-        self.projection.mutate_all(
-            self.orbit_controller
-                .borrow_mut()
-                .update(self.size.width, self.size.height, Some(time))
-                .into_iter(),
+        let resolution_events = RefCell::new(Vec::new());
+        let resolution_result = resolve_dependencies_multipart_with_fallbacks(
+            document,
+            cache.clone(),
+            &self.colors,
+            &*self.loader,
+            &self.fallbacks,
+            &Self::collect_resolution_events(&resolution_events, on_update),
+        )
+        .await;
+        self.emit_all(resolution_events.into_inner());
+
+        // Flexible parts saved without generated geometry are tessellated for display only.
+        let flex = generate_flex_parts(document, &self.colors, &FlexOptions::default());
+        let mut model = model::Model::from_ldraw_multipart_document(
+            flex.as_ref().map_or(document, |v| &v.document),
+            &self.colors,
+            Some((&*self.loader, cache.clone())),
+        )
+        .await;
+        if let Some(flex) = &flex {
+            model.map_source_commands(|subpart, command| flex.original_command(subpart, command));
+        }
+        if let Some(lines) = &self.source_lines {
+            model.set_source_lines(lines);
+        }
+        // Ids follow where objects are in the document, so that update_document() can tell
+        // which instances have changed.
+        model.assign_stable_ids(&document.body.name);
+
+        self.cache = cache;
+        let mut resolution_result = resolution_result;
+        self.resolve_primitive_alternatives(&mut resolution_result)
+            .await;
+        self.resolution_result = resolution_result;
+        let resolution_result = &self.resolution_result;
+
+        let parts = self.bake_parts(document, resolution_result);
+        self.parts.borrow_mut().0.extend(parts);
+        if let Some(flex) = &flex {
+            let parts = self.bake_flex_parts(flex);
+            self.parts.borrow_mut().0.extend(parts);
+        }
+
+        self.connections.0.extend(
+            document
+                .list_dependencies()
+                .into_iter()
+                .filter_map(|alias| {
+                    resolution_result.query(&alias, true).map(|(part, local)| {
+                        (
+                            alias.clone(),
+                            infer_connection_points(part, resolution_result, local),
+                        )
+                    })
+                }),
         );
 
-        self.animated_model.animate(time);
-    }
+        if self.step_inference && !model.has_steps(None) {
+            model.infer_steps(&*self.parts.borrow(), &StepInferenceParams::default());
+        }
 
-    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
-        if new_size.width > 0 && new_size.height > 0 {
+        let render_target = render_target_name.and_then(|name| {
+            model
+                .object_groups
+                .values()
+                .find(|group| group.alias() == name)
+                .map(|group| group.id)
+        });
+
+        let previous_step = self.step_state();
+        self.keyframe_player = None;
+        self.poser = None;
+        #[cfg(feature = "physics")]
+        {
+            self.physics = None;
+        }
+        if keep_view {
+            self.animated_model =
+                AnimatedModel::from_model(&model, render_target, &self.colors, None);
+            self.animated_model.set_opacity(
+                self.document_opacity,
+                &model,
+                render_target,
+                &self.colors,
+            );
+        } else {
+            self.animated_model = AnimatedModel::from_model(
+                &model,
+                None,
+                &self.colors,
+                Some(Rc::clone(&self.animator)),
+            );
+            self.animated_model.opacity = self.document_opacity;
+        }
+        if let Some(overlay) = &self.overlay {
+            self.animated_model
+                .display_list
+                .mutate_all(overlay.insert_ops().into_iter());
+        }
+
+        if !keep_view {
+            let bounding_box = calculate_model_bounding_box(&model, None, &*self.parts.borrow());
+            self.orbit_controller
+                .borrow_mut()
+                .frame(&bounding_box, document.body.camera().as_ref());
+        }
+
+        self.summary = Some(model.summary(render_target, &*self.parts.borrow()));
+        self.model = Some(model);
+        self.render_target = render_target;
+        self.document = Some(document.clone());
+        self.document_modified = keep_view;
+        self.gizmo.clear();
+        self.selection_outline = DisplayList::new().into();
+        self.exploded_view.clear();
+        self.discard_breakdown();
+        if !keep_view {
+            self.clear_cutaway();
+        }
+        self.update_ground();
+
+        self.emit_model_loaded();
+        self.emit_step_change(previous_step);
+
+        Ok(())
+    }
+
+    pub async fn set_overlay_document<F: Fn(PartAlias, Result<(), ResolutionError>)>(
+        &mut self,
+        cache: PartCache,
+        document: &MultipartDocument,
+        on_update: &F,
+    ) -> Result<(), ResolutionError> {
+        let resolution_events = RefCell::new(Vec::new());
+        let mut resolution_result = resolve_dependencies_multipart_with_fallbacks(
+            document,
+            cache.clone(),
+            &self.colors,
+            &*self.loader,
+            &self.fallbacks,
+            &Self::collect_resolution_events(&resolution_events, on_update),
+        )
+        .await;
+        self.emit_all(resolution_events.into_inner());
+        self.resolve_primitive_alternatives(&mut resolution_result)
+            .await;
+
+        let flex = generate_flex_parts(document, &self.colors, &FlexOptions::default());
+        let model = model::Model::from_ldraw_multipart_document(
+            flex.as_ref().map_or(document, |v| &v.document),
+            &self.colors,
+            Some((&*self.loader, cache)),
+        )
+        .await;
+
+        let parts = self.bake_parts(document, &resolution_result);
+        self.parts.borrow_mut().0.extend(parts);
+        if let Some(flex) = &flex {
+            let parts = self.bake_flex_parts(flex);
+            self.parts.borrow_mut().0.extend(parts);
+        }
+
+        let namespace = ObjectId::from(OVERLAY_NAMESPACE);
+        let items = DisplayList::expand_objects(&model, &model.objects, &self.colors, Clone::clone)
+            .into_iter()
+            .filter_map(|op| match op {
+                DisplayListOps::Insert {
+                    group,
+                    key,
+                    matrix,
+                    color,
+                    ..
+                } => Some(OverlayItem {
+                    group,
+                    key: AnimatedModel::uuid_xor(namespace, key),
+                    matrix,
+                    color,
+                }),
+                _ => None,
+            })
+            .collect();
+
+        let opacity = self.overlay.as_ref().map(|v| v.opacity).unwrap_or(0.5);
+        self.clear_overlay();
+
+        let overlay = Overlay {
+            document: document.clone(),
+            resolution_result,
+            items,
+            opacity,
+        };
+        self.animated_model
+            .display_list
+            .mutate_all(overlay.insert_ops().into_iter());
+        self.overlay = Some(overlay);
+
+        Ok(())
+    }
+
+    pub fn clear_overlay(&mut self) {
+        if let Some(overlay) = self.overlay.take() {
+            self.animated_model
+                .display_list
+                .mutate_all(overlay.remove_ops().into_iter());
+        }
+    }
+
+    pub fn has_overlay(&self) -> bool {
+        self.overlay.is_some()
+    }
+
+    // Adds a model to the scene, placed by given transform relative to the document.
+    pub async fn add_scene_document<F: Fn(PartAlias, Result<(), ResolutionError>)>(
+        &mut self,
+        cache: PartCache,
+        document: &MultipartDocument,
+        transform: Matrix4,
+        on_update: &F,
+    ) -> Result<SceneModelId, ResolutionError> {
+        let resolution_events = RefCell::new(Vec::new());
+        let mut resolution_result = resolve_dependencies_multipart_with_fallbacks(
+            document,
+            cache.clone(),
+            &self.colors,
+            &*self.loader,
+            &self.fallbacks,
+            &Self::collect_resolution_events(&resolution_events, on_update),
+        )
+        .await;
+        self.emit_all(resolution_events.into_inner());
+        self.resolve_primitive_alternatives(&mut resolution_result)
+            .await;
+
+        let flex = generate_flex_parts(document, &self.colors, &FlexOptions::default());
+        let model = model::Model::from_ldraw_multipart_document(
+            flex.as_ref().map_or(document, |v| &v.document),
+            &self.colors,
+            Some((&*self.loader, cache)),
+        )
+        .await;
+
+        let parts = self.bake_parts(document, &resolution_result);
+        self.parts.borrow_mut().0.extend(parts);
+        if let Some(flex) = &flex {
+            let parts = self.bake_flex_parts(flex);
+            self.parts.borrow_mut().0.extend(parts);
+        }
+
+        let display_list = DisplayList::from_model(&model, None, &self.colors);
+        let id = self.scene.add(&self.device, display_list, transform);
+        self.scene_documents
+            .insert(id, (document.clone(), resolution_result));
+
+        Ok(id)
+    }
+
+    pub fn remove_scene_model(&mut self, id: SceneModelId) -> bool {
+        self.scene_documents.remove(&id);
+        self.scene.remove(id)
+    }
+
+    pub fn clear_scene(&mut self) {
+        self.scene_documents.clear();
+        self.scene.clear();
+    }
+
+    pub fn scene_models(&self) -> Vec<SceneModelId> {
+        self.scene.iter().map(|(id, _)| id).collect()
+    }
+
+    pub fn scene_model_transform(&self, id: SceneModelId) -> Option<Matrix4> {
+        self.scene.get(id).map(|model| *model.transform())
+    }
+
+    pub fn set_scene_model_transform(&mut self, id: SceneModelId, transform: Matrix4) -> bool {
+        self.scene.set_transform(id, transform)
+    }
+
+    pub fn is_scene_model_visible(&self, id: SceneModelId) -> Option<bool> {
+        self.scene.get(id).map(|model| model.is_visible())
+    }
+
+    pub fn set_scene_model_visible(&mut self, id: SceneModelId, visible: bool) -> bool {
+        self.scene.set_visible(id, visible)
+    }
+
+    // Bounds of the document together with every visible model of the scene.
+    pub fn scene_bounding_box(&self) -> BoundingBox3 {
+        let parts = self.parts.borrow();
+        let mut bounding_box = self.scene.bounding_box(&*parts);
+        if let Some(model) = &self.model {
+            let model_bb = calculate_model_bounding_box(model, self.render_target, &*parts);
+            if !model_bb.is_null() {
+                bounding_box.update(&model_bb);
+            }
+        }
+        bounding_box
+    }
+
+    pub fn scene_model_bounding_box(&self, id: SceneModelId) -> Option<BoundingBox3> {
+        self.scene
+            .get(id)
+            .map(|model| model.bounding_box(&*self.parts.borrow()))
+    }
+
+    pub fn frame_scene(&mut self) {
+        let bounding_box = self.scene_bounding_box();
+        self.orbit_controller
+            .borrow_mut()
+            .frame_bounds(&bounding_box, CAMERA_TRANSITION_DURATION);
+    }
+
+    pub fn document_opacity(&self) -> f32 {
+        self.document_opacity
+    }
+
+    pub fn set_document_opacity(&mut self, opacity: f32) {
+        self.document_opacity = opacity.clamp(0.0, 1.0);
+        if let Some(model) = &self.model {
+            self.animated_model.set_opacity(
+                self.document_opacity,
+                model,
+                self.render_target,
+                &self.colors,
+            );
+        }
+    }
+
+    pub fn overlay_opacity(&self) -> Option<f32> {
+        self.overlay.as_ref().map(|v| v.opacity)
+    }
+
+    pub fn set_overlay_opacity(&mut self, opacity: f32) {
+        if let Some(overlay) = &mut self.overlay {
+            overlay.opacity = opacity.clamp(0.0, 1.0);
+            self.animated_model
+                .display_list
+                .mutate_all(overlay.alpha_ops().into_iter());
+        }
+    }
+
+    // Loads low or high resolution variants of primitives required by the current bake options.
+    async fn resolve_primitive_alternatives(&self, resolution_result: &mut ResolutionResult) {
+        let resolution = self.bake_options.primitive_resolution;
+        let alternatives = resolution_result
+            .list_dependencies()
+            .iter()
+            .filter_map(|alias| resolution.alternative(alias, resolution_result.kind(alias)?))
+            .filter(|alias| resolution_result.query(alias, false).is_none())
+            .collect::<Vec<_>>();
+        if alternatives.is_empty() {
+            return;
+        }
+
+        let document = Document {
+            commands: alternatives
+                .into_iter()
+                .map(|name| {
+                    Command::PartReference(PartReference {
+                        color: ColorReference::Current,
+                        matrix: Matrix4::identity(),
+                        name,
+                    })
+                })
+                .collect(),
+            ..Default::default()
+        };
+        // Not every primitive has alternatives, so failures are not reported.
+        let result = resolve_dependencies(
+            &document,
+            self.cache.clone(),
+            &self.colors,
+            &*self.loader,
+            &|_, _| {},
+        )
+        .await;
+        resolution_result.merge(result);
+    }
+
+    fn bake_parts(
+        &self,
+        document: &MultipartDocument,
+        resolution_result: &ResolutionResult,
+    ) -> Vec<(PartAlias, (Part, part_ir::Part))> {
+        document
+            .list_dependencies()
+            .into_iter()
+            .filter_map(|alias| {
+                resolution_result.query(&alias, true).map(|(part, local)| {
+                    let geometry = bake_part_from_multipart_document_with_options(
+                        part,
+                        resolution_result,
+                        local,
+                        &self.bake_options,
+                    );
+                    (
+                        alias.clone(),
+                        (Part::new(&geometry, &self.device, &self.colors), geometry),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    fn bake_flex_parts(&self, flex: &FlexParts) -> Vec<(PartAlias, (Part, part_ir::Part))> {
+        let resolution_result = ResolutionResult::default();
+        flex.parts
+            .iter()
+            .map(|(alias, part)| {
+                let geometry = bake_part_from_multipart_document_with_options(
+                    part,
+                    &resolution_result,
+                    true,
+                    &self.bake_options,
+                );
+                (
+                    alias.clone(),
+                    (Part::new(&geometry, &self.device, &self.colors), geometry),
+                )
+            })
+            .collect()
+    }
+
+    // Forgets cached documents of parts changed in the library. Those the document depends
+    // on are announced with AppEvent::LibraryChanged, and picked up by reload_parts().
+    pub fn invalidate_parts(&mut self, aliases: &[PartAlias]) {
+        for alias in aliases {
+            self.cache.invalidate(alias);
+        }
+
+        // Direct references are included, so that newly installed parts that were missing
+        // count as well.
+        let mut dependencies = self.resolution_result.list_dependencies();
+        if let Some(document) = &self.document {
+            dependencies.extend(document.list_dependencies());
+        }
+        let affected = aliases
+            .iter()
+            .filter(|v| dependencies.contains(v))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !affected.is_empty() {
+            self.events.emit(AppEvent::LibraryChanged(affected));
+        }
+    }
+
+    // Resolves and bakes parts of the document again, keeping the view.
+    pub async fn reload_parts<F: Fn(PartAlias, Result<(), ResolutionError>)>(
+        &mut self,
+        on_update: &F,
+    ) -> Result<(), ResolutionError> {
+        let Some(document) = self.document.clone() else {
+            return Ok(());
+        };
+        let modified = self.document_modified;
+        self.load_document(self.cache.clone(), &document, on_update, true)
+            .await?;
+        self.document_modified = modified;
+        Ok(())
+    }
+
+    pub fn bake_options(&self) -> &BakeOptions {
+        &self.bake_options
+    }
+
+    // Re-bakes every part of the current document with new options.
+    pub async fn set_bake_options(&mut self, options: BakeOptions) {
+        if self.bake_options == options {
+            return;
+        }
+
+        self.bake_options = options;
+        self.pipelines.bfc_debug = options.tag_bfc_errors;
+
+        let mut resolution_result = mem::take(&mut self.resolution_result);
+        self.resolve_primitive_alternatives(&mut resolution_result)
+            .await;
+        self.resolution_result = resolution_result;
+        if let Some(document) = &self.document {
+            let parts = self.bake_parts(document, &self.resolution_result);
+            self.parts.borrow_mut().0.extend(parts);
+        }
+
+        if let Some(mut overlay) = self.overlay.take() {
+            self.resolve_primitive_alternatives(&mut overlay.resolution_result)
+                .await;
+            let parts = self.bake_parts(&overlay.document, &overlay.resolution_result);
+            self.parts.borrow_mut().0.extend(parts);
+            self.overlay = Some(overlay);
+        }
+
+        let mut scene_documents = mem::take(&mut self.scene_documents);
+        for (document, resolution_result) in scene_documents.values_mut() {
+            self.resolve_primitive_alternatives(resolution_result).await;
+            let parts = self.bake_parts(document, resolution_result);
+            self.parts.borrow_mut().0.extend(parts);
+        }
+        self.scene_documents = scene_documents;
+    }
+
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn current_time(&self) -> f32 {
+        self.clock.now()
+    }
+
+    // Animates to the current time of the clock and moves it on to the next frame.
+    pub fn update(&mut self) {
+        let time = self.clock.now();
+        let inputs = self
+            .player
+            .as_mut()
+            .map(|v| v.next_frame())
+            .unwrap_or_default();
+        for input in inputs {
+            self.handle_input(input, time);
+        }
+
+        self.animate(time);
+        self.clock.tick();
+
+        if let Some(recorder) = &mut self.recorder {
+            recorder.next_frame();
+        }
+        let gpu_errors = std::mem::take(&mut *self.gpu_errors.lock().unwrap());
+        for error in gpu_errors {
+            self.events.emit(AppEvent::GpuError(error));
+        }
+        if self.player.as_ref().is_some_and(|v| v.is_finished()) {
+            self.player = None;
+            self.clock = Box::new(RealTimeClock::starting_at(self.clock.now()));
+            self.events.emit(AppEvent::PlaybackFinished);
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    pub fn is_playing_back(&self) -> bool {
+        self.player.is_some()
+    }
+
+    // Records input from now on. Time advances by a fixed step per frame while recording
+    // so that animations come out the same on playback regardless of frame rate.
+    pub fn start_recording(&mut self, frame_rate: f32) {
+        if self.player.is_some() {
+            return;
+        }
+
+        let view = {
+            let mut orbit_controller = self.orbit_controller.borrow_mut();
+            orbit_controller.stop_animation();
+            orbit_controller.tick = None;
+            orbit_controller.view_state()
+        };
+        self.clock = Box::new(FixedStepClock::starting_at(
+            self.clock.now(),
+            1.0 / frame_rate,
+        ));
+        self.recorder = Some(Recorder::new(frame_rate, view));
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        let recording = self.recorder.take()?.finish();
+        self.clock = Box::new(RealTimeClock::starting_at(self.clock.now()));
+        Some(recording)
+    }
+
+    // Replays input from a recording made on the same model. Live input is ignored until
+    // it ends.
+    pub fn start_playback(&mut self, recording: Recording) {
+        self.recorder = None;
+        {
+            let mut orbit_controller = self.orbit_controller.borrow_mut();
+            orbit_controller.set_view_state(&recording.view);
+            orbit_controller.tick = None;
+        }
+        self.clock = Box::new(FixedStepClock::starting_at(
+            self.clock.now(),
+            1.0 / recording.frame_rate,
+        ));
+        self.player = Some(Player::new(recording));
+    }
+
+    pub fn stop_playback(&mut self) {
+        if self.player.take().is_some() {
+            self.clock = Box::new(RealTimeClock::starting_at(self.clock.now()));
+        }
+    }
+
+    pub fn advance(&mut self, time: f32) {
+        let previous = self.step_state();
+        self.animated_model.advance(time);
+        self.update_step_ghosting(previous);
+        self.emit_step_change(previous);
+    }
+
+    // Steps completed while the model is built up, zero at the start as in StepChanged.
+    pub fn current_step(&self) -> usize {
+        self.animated_model.step
+    }
+
+    // Zero unless the model is being built up step by step.
+    pub fn step_count(&self) -> usize {
+        self.animated_model.step_count()
+    }
+
+    // Jumps to the end of the given step, showing its parts and the ones before at once.
+    // Posing, keyframes, physics and explosion are dropped as parts are placed anew.
+    pub fn go_to_step(&mut self, step: usize) {
+        if self.animated_model.items.is_empty() {
+            return;
+        }
+        self.keyframe_player = None;
+        self.poser = None;
+        #[cfg(feature = "physics")]
+        {
+            self.physics = None;
+        }
+
+        self.exploded_view.clear();
+
+        let previous = self.step_state();
+        self.animated_model.seek(step);
+        self.emit_step_change(previous);
+    }
+
+    // Replaces how parts appear while the model is built up. Parts already appearing finish
+    // with the new animator.
+    pub fn set_animator(&mut self, animator: Rc<dyn Animator>) {
+        self.animated_model.animator = Rc::clone(&animator);
+        self.animator = animator;
+    }
+
+    pub fn set_animation_config(&mut self, config: AnimationConfig) {
+        self.set_animator(Rc::new(config));
+    }
+
+    pub fn step_ghosting(&self) -> bool {
+        self.step_ghosting
+    }
+
+    // While building a model step by step, shows parts of earlier steps translucently so that
+    // the current step stands out. Takes effect from the next step.
+    pub fn set_step_ghosting(&mut self, enabled: bool) {
+        self.step_ghosting = enabled;
+        if !enabled {
+            self.animated_model
+                .display_list
+                .mutate(DisplayListOps::RestoreAllColors);
+        }
+    }
+
+    pub fn step_inference(&self) -> bool {
+        self.step_inference
+    }
+
+    // Guesses steps from the layout of parts when a model loaded afterwards has none.
+    pub fn set_step_inference(&mut self, enabled: bool) {
+        self.step_inference = enabled;
+    }
+
+    fn apply_axes(&mut self, time: f32) {
+        let dt = self
+            .last_axis_time
+            .map(|v| (time - v).clamp(0.0, 0.1))
+            .unwrap_or_default();
+        self.last_axis_time = Some(time);
+        if self.axes.is_empty() || dt == 0.0 {
+            return;
+        }
+
+        let axis = |action| self.axes.get(&action).copied().unwrap_or_default();
+        let orbit = Vector2::new(
+            axis(AxisAction::OrbitHorizontal),
+            axis(AxisAction::OrbitVertical),
+        ) * AXIS_ORBIT_SPEED
+            * dt;
+        let pan = Vector2::new(
+            axis(AxisAction::PanHorizontal),
+            axis(AxisAction::PanVertical),
+        ) * AXIS_PAN_SPEED
+            * dt;
+        let zoom = axis(AxisAction::Zoom) * AXIS_ZOOM_SPEED * dt;
+
+        let mut orbit_controller = self.orbit_controller.borrow_mut();
+        if orbit != Vector2::zero() {
+            orbit_controller.rotate(orbit);
+        }
+        if pan != Vector2::zero() {
+            orbit_controller.pan(pan);
+        }
+        if zoom != 0.0 {
+            orbit_controller.scale(zoom.exp());
+        }
+    }
+
+    fn update_step_ghosting(&mut self, previous: (State, usize)) {
+        if !self.step_ghosting {
+            return;
+        }
+        match (previous.0, self.animated_model.state) {
+            (State::Step, State::Playing) => self.animated_model.ghost_previous_steps(),
+            (State::Step | State::Playing, State::Finished) => {
+                self.animated_model
+                    .display_list
+                    .mutate(DisplayListOps::RestoreAllColors);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn animate(&mut self, time: f32) {
+        self.apply_axes(time);
+        self.projection.mutate_all(
+            self.orbit_controller
+                .borrow_mut()
+                .update(self.size.width, self.size.height, Some(time))
+                .into_iter(),
+        );
+
+        let previous = self.step_state();
+        self.animated_model.animate(time);
+        self.update_step_ghosting(previous);
+        self.emit_step_change(previous);
+
+        let ops = self.exploded_view.animate(time);
+        self.animated_model.display_list.mutate_all(ops.into_iter());
+
+        // Parts stay at the last keyframe once the animation ends.
+        if let Some(player) = &self.keyframe_player {
+            self.animated_model
+                .display_list
+                .mutate_all(player.animate(time).into_iter());
+        }
+
+        #[cfg(feature = "physics")]
+        if let Some(physics) = &mut self.physics {
+            physics.advance(time);
+            if !physics.is_settled() {
+                self.animated_model
+                    .display_list
+                    .mutate_all(physics.ops().into_iter());
+            }
+        }
+    }
+
+    // Hands parts of the displayed model over to the physics simulation, which they fall
+    // under onto the ground. Replaces any simulation running. Returns false if there is
+    // nothing to simulate.
+    #[cfg(feature = "physics")]
+    pub fn start_physics(&mut self, config: &physics::PhysicsConfig) -> bool {
+        self.stop_physics();
+        self.stop_keyframes();
+        self.clear_articulation();
+        let Some(model) = &self.model else {
+            return false;
+        };
+
+        let objects = model
+            .get_objects(self.render_target)
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect::<Vec<_>>();
+        let parts = DisplayList::expand_objects(model, &objects, &self.colors, Clone::clone)
+            .into_iter()
+            .filter_map(|op| match op {
+                DisplayListOps::Insert {
+                    group, key, matrix, ..
+                } => Some((key, group, matrix)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let bounding_box =
+            calculate_model_bounding_box(model, self.render_target, &*self.parts.borrow());
+        // Y axis points downwards, so the bottom of the model is at its maximum.
+        let ground = (!bounding_box.is_null()).then_some(bounding_box.max.y);
+
+        let simulation = physics::PhysicsSimulation::new(
+            parts
+                .iter()
+                .map(|(key, alias, matrix)| (*key, alias, *matrix)),
+            &*self.parts.borrow(),
+            ground,
+            config,
+        );
+        if simulation.is_empty() {
+            return false;
+        }
+        self.animated_model
+            .display_list
+            .mutate_all(simulation.ops().into_iter());
+        self.physics = Some(simulation);
+        true
+    }
+
+    // Puts simulated parts back where the model places them.
+    #[cfg(feature = "physics")]
+    pub fn stop_physics(&mut self) {
+        if let Some(physics) = self.physics.take() {
+            self.animated_model
+                .display_list
+                .mutate_all(physics.restore().into_iter());
+        }
+    }
+
+    #[cfg(feature = "physics")]
+    pub fn is_simulating_physics(&self) -> bool {
+        self.physics.is_some()
+    }
+
+    // Flings parts away from the center of the model, starting the simulation if it isn't
+    // running.
+    #[cfg(feature = "physics")]
+    pub fn knock_apart(&mut self, speed: f32) {
+        if self.physics.is_none() && !self.start_physics(&Default::default()) {
+            return;
+        }
+        let Some(model) = &self.model else {
+            return;
+        };
+        let center =
+            calculate_model_bounding_box(model, self.render_target, &*self.parts.borrow()).center();
+        if let Some(physics) = &mut self.physics {
+            physics.knock_apart(center, speed);
+        }
+    }
+
+    // Moves objects of the displayed model along keyframes from now on, replacing any
+    // keyframe animation being played. Returns false if there is no model.
+    pub fn play_keyframes(&mut self, animation: Animation) -> bool {
+        self.stop_keyframes();
+        let Some(model) = &self.model else {
+            return false;
+        };
+        self.keyframe_player = Some(KeyframePlayer::new(
+            animation,
+            model,
+            self.render_target,
+            &self.colors,
+            self.clock.now(),
+        ));
+        true
+    }
+
+    // Puts animated objects back where the model places them.
+    pub fn stop_keyframes(&mut self) {
+        if let Some(player) = self.keyframe_player.take() {
+            self.animated_model
+                .display_list
+                .mutate_all(player.restore().into_iter());
+        }
+    }
+
+    pub fn is_playing_keyframes(&self) -> bool {
+        self.keyframe_player
+            .as_ref()
+            .is_some_and(|v| !v.is_finished(self.clock.now()))
+    }
+
+    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        if new_size.width > 0 && new_size.height > 0 {
             let new_size = winit::dpi::PhysicalSize {
                 width: min(new_size.width, self.max_texture_size),
                 height: min(new_size.height, self.max_texture_size),
@@ -634,192 +2324,1421 @@ impl<L: LibraryLoader> App<L> {
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
 
-            self.size = new_size;
+            self.size = new_size;
+
+            self.projection.mutate_all(
+                self.orbit_controller
+                    .borrow_mut()
+                    .update(self.size.width, self.size.height, None)
+                    .into_iter(),
+            );
+
+            self.recreate_framebuffers();
+        }
+    }
+
+    fn recreate_framebuffers(&mut self) {
+        self.framebuffer_texture = if self.sample_count > 1 {
+            Some(Texture::create_framebuffer(
+                &self.device,
+                &self.config,
+                self.sample_count,
+                Some("Multisample framebuffer"),
+            ))
+        } else {
+            None
+        };
+        self.depth_texture = Texture::create_depth_texture(
+            &self.device,
+            &self.config,
+            self.sample_count,
+            Some("Depth texture"),
+        );
+    }
+
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    pub fn surface_format(&self) -> wgpu::TextureFormat {
+        self.config.format
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    pub fn supported_sample_counts(&self) -> &[u32] {
+        &self.supported_sample_counts
+    }
+
+    // Returns false if the adapter cannot render with the given sample count.
+    pub fn set_sample_count(&mut self, sample_count: u32) -> bool {
+        if !self.supported_sample_counts.contains(&sample_count) {
+            return false;
+        }
+        if self.sample_count == sample_count {
+            return true;
+        }
+
+        self.sample_count = sample_count;
+        self.pipelines.set_sample_count(&self.device, sample_count);
+        self.recreate_framebuffers();
+
+        true
+    }
+
+    // Moves on to the next supported sample count, wrapping around to the lowest.
+    pub fn cycle_sample_count(&mut self) -> u32 {
+        let next = self
+            .supported_sample_counts
+            .iter()
+            .copied()
+            .find(|&v| v > self.sample_count)
+            .or_else(|| self.supported_sample_counts.first().copied())
+            .unwrap_or(1);
+        self.set_sample_count(next);
+        self.sample_count
+    }
+
+    fn prepare_frame(&mut self) {
+        self.projection.update(&self.device, &self.queue);
+        self.animated_model
+            .display_list
+            .update(&self.device, &self.queue);
+        self.selection_outline.update(&self.device, &self.queue);
+        self.scene
+            .update(&self.device, &self.queue, self.projection.get());
+    }
+
+    // Records passes drawing the scene into view, which has to be as large as the surface.
+    fn encode_frame(&mut self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let part_querier = self.parts.borrow();
+
+        let (target_view, resolve_target) = if let Some(texture) = self.framebuffer_texture.as_ref()
+        {
+            (&texture.view, Some(view))
+        } else {
+            (view, None)
+        };
+
+        {
+            let mut pass = encoder
+                .begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Main render pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: target_view,
+                        resolve_target,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color {
+                                r: 1.0,
+                                g: 1.0,
+                                b: 1.0,
+                                a: 0.0,
+                            }),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: &self.depth_texture.view,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(1.0),
+                            store: wgpu::StoreOp::Store,
+                        }),
+                        stencil_ops: None,
+                    }),
+                    occlusion_query_set: None,
+                    timestamp_writes: None,
+                })
+                .forget_lifetime();
+
+            let mut layers = vec![(self.projection.get(), &*self.animated_model.display_list)];
+            layers.extend(self.scene.layers());
+            self.render_stats = self.pipelines.render_layers(
+                &mut pass,
+                self.projection.get(),
+                &*part_querier,
+                &layers,
+            );
+        }
+        self.pipelines.render_outline(
+            &self.device,
+            &self.queue,
+            encoder,
+            self.projection.get(),
+            &*part_querier,
+            &self.selection_outline,
+            target_view,
+            resolve_target,
+            (self.config.width, self.config.height),
+        );
+        self.pipelines.resolve_gpu_timings(encoder);
+    }
+
+    pub fn render(&mut self) -> Result<Duration, wgpu::SurfaceError> {
+        self.render_with_overlay(|_, _, _, _| {})
+    }
+
+    // Lets overlay record passes drawing over the frame before it is presented, such as a user
+    // interface. These do not show up in frames captured with capture_frame().
+    pub fn render_with_overlay(
+        &mut self,
+        overlay: impl FnOnce(&wgpu::Device, &wgpu::Queue, &mut wgpu::CommandEncoder, &wgpu::TextureView),
+    ) -> Result<Duration, wgpu::SurfaceError> {
+        let now = Instant::now();
+
+        self.prepare_frame();
+
+        let output = self.surface.get_current_texture()?;
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Command Encoder"),
+            });
+        self.encode_frame(&mut encoder, &view);
+        overlay(&self.device, &self.queue, &mut encoder, &view);
+
+        self.queue.submit(std::iter::once(encoder.finish()));
+        output.present();
+        self.render_stats.gpu_timings = self.pipelines.collect_gpu_timings(&self.device, false);
+
+        if self
+            .last_stats_tick
+            .map(|v| v.elapsed() >= STATS_TICK_INTERVAL)
+            .unwrap_or(true)
+        {
+            self.last_stats_tick = Some(Instant::now());
+            self.events
+                .emit(AppEvent::RenderStatsTick(self.render_stats));
+        }
+
+        Ok(now.elapsed())
+    }
+
+    // Renders the current view offscreen and reads it back, so that nothing drawn over the
+    // window ends up in the image. Background is left transparent.
+    pub async fn capture_frame(&mut self) -> Result<RgbaImage, error::CaptureError> {
+        let (width, height) = (self.config.width, self.config.height);
+        let format = self.config.format;
+        let swap_channels = match format {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            _ => return Err(error::CaptureError::UnsupportedFormat(format)),
+        };
+
+        let extent = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            label: Some("Capture texture"),
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // Rows of the buffer have to be aligned, so they are padded and cut off afterwards.
+        let row_size = 4 * width;
+        let padded_row_size = row_size.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Capture buffer"),
+            size: (padded_row_size * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        self.prepare_frame();
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Capture Command Encoder"),
+            });
+        self.encode_frame(&mut encoder, &view);
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_size),
+                    rows_per_image: Some(height),
+                },
+            },
+            extent,
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let buffer_slice = buffer.slice(..);
+        let (tx, rx) = oneshot::channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = tx.send(result);
+        });
+        // Browsers map the buffer on their own, where this returns right away.
+        self.device.poll(wgpu::Maintain::Wait);
+        rx.await
+            .map_err(|_| error::CaptureError::Cancelled)?
+            .map_err(error::CaptureError::BufferMapping)?;
+
+        let mut pixels = Vec::with_capacity((row_size * height) as usize);
+        {
+            let data = buffer_slice.get_mapped_range();
+            for row in data.chunks(padded_row_size as usize) {
+                pixels.extend_from_slice(&row[..row_size as usize]);
+            }
+        }
+        buffer.unmap();
+
+        if swap_channels {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        Ok(RgbaImage::from_raw(width, height, pixels).unwrap())
+    }
+
+    pub fn get_subparts(&self) -> Vec<(GroupId, String)> {
+        if let Some(model) = &self.model {
+            let mut result = model
+                .object_groups
+                .iter()
+                .map(|(k, v)| (*k, v.name.clone()))
+                .collect::<Vec<_>>();
+            result.sort_by(|a, b| a.1.cmp(&b.1));
+            result
+        } else {
+            Vec::new()
+        }
+    }
+
+    // Submodels as instantiated in the model, for tree views. Unlike get_subparts(), a
+    // submodel placed several times appears once per placement.
+    pub fn submodel_tree(&self) -> Option<model::SubmodelNode> {
+        self.model.as_ref().map(|model| model.submodel_tree())
+    }
+
+    pub fn render_target(&self) -> Option<GroupId> {
+        self.render_target
+    }
+
+    pub fn set_render_target(&mut self, group_id: Option<GroupId>) {
+        let previous_step = self.step_state();
+        if let Some(model) = &mut self.model {
+            self.keyframe_player = None;
+            self.poser = None;
+            #[cfg(feature = "physics")]
+            {
+                self.physics = None;
+            }
+            self.animated_model = AnimatedModel::from_model(model, group_id, &self.colors, None);
+            self.animated_model
+                .set_opacity(self.document_opacity, model, group_id, &self.colors);
+            if let Some(overlay) = &self.overlay {
+                self.animated_model
+                    .display_list
+                    .mutate_all(overlay.insert_ops().into_iter());
+            }
+            self.render_target = group_id;
+            self.summary = Some(model.summary(group_id, &*self.parts.borrow()));
+            if !self.gizmo.selection().is_empty() {
+                self.events.emit(AppEvent::SelectionChanged(Vec::new()));
+            }
+            self.gizmo.clear();
+            self.selection_outline = DisplayList::new().into();
+            self.exploded_view.clear();
+
+            let bounding_box = calculate_model_bounding_box(model, group_id, &*self.parts.borrow());
+            self.orbit_controller
+                .borrow_mut()
+                .frame(&bounding_box, None);
+            self.discard_breakdown();
+        }
+        self.clear_cutaway();
+        self.update_ground();
+        self.emit_step_change(previous_step);
+    }
+
+    pub fn summary(&self) -> Option<&ModelSummary<PartAlias>> {
+        self.summary.as_ref()
+    }
+
+    pub fn camera_description(&self) -> Option<CameraDescription> {
+        self.projection.get().describe_camera()
+    }
+
+    pub fn state(&self) -> State {
+        self.animated_model.state
+    }
+
+    pub fn bake_ambient_occlusion(&mut self) {
+        if self.animated_model.state != State::Finished {
+            return;
+        }
+
+        let display_list = &mut self.animated_model.display_list;
+        display_list.update(&self.device, &self.queue);
+
+        let ops = display_list
+            .get()
+            .bake_ambient_occlusion(&*self.parts.borrow(), &OcclusionParams::default());
+        display_list.mutate_all(ops.into_iter());
+    }
+
+    pub fn document(&self) -> Option<&MultipartDocument> {
+        self.document.as_ref()
+    }
+
+    pub fn is_document_modified(&self) -> bool {
+        self.document_modified
+    }
+
+    // Stores current camera into the document so that it is restored when loaded again.
+    pub fn save_camera(&mut self) {
+        if let Some(document) = &mut self.document {
+            let camera = self.orbit_controller.borrow().to_camera();
+            document.body.set_camera(Some(camera));
+            self.document_modified = true;
+        }
+    }
+
+    pub fn mark_document_saved(&mut self) {
+        self.document_modified = false;
+    }
+
+    // Serializes selected objects as LDraw part references, with transforms relative to the
+    // model being viewed.
+    pub async fn copy_selection(&self) -> Option<String> {
+        let model = self.model.as_ref()?;
+        let references = self
+            .gizmo
+            .selected_objects(model, self.render_target)
+            .into_iter()
+            .filter_map(|object| match object.data {
+                model::ObjectInstance::Part(p) => Some(PartReference {
+                    color: p.color,
+                    matrix: p.matrix,
+                    name: p.part,
+                }),
+                model::ObjectInstance::PartGroup(pg) => Some(PartReference {
+                    color: pg.color,
+                    matrix: pg.matrix,
+                    name: model.object_groups.get(&pg.group_id)?.alias(),
+                }),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if references.is_empty() {
+            return None;
+        }
+
+        let mut buffer = Vec::new();
+        for reference in references {
+            reference.write(&mut buffer).await.ok()?;
+        }
+        String::from_utf8(buffer).ok()
+    }
+
+    // Appends part references in LDraw text to the model being viewed and selects them.
+    // Returns the number of pasted parts.
+    pub async fn paste<F: Fn(PartAlias, Result<(), ResolutionError>)>(
+        &mut self,
+        text: &str,
+        on_update: &F,
+    ) -> Result<usize, ResolutionError> {
+        if self.animated_model.state != State::Finished {
+            return Ok(0);
+        }
+        let (Some(model), Some(mut document)) = (&self.model, self.document.clone()) else {
+            return Ok(0);
+        };
+
+        let pasted =
+            parse_multipart_document(&mut BufReader::new(text.as_bytes()), &self.colors).await?;
+        let references = pasted.body.iter_refs().cloned().collect::<Vec<_>>();
+        let count = references.len();
+        if count == 0 {
+            return Ok(0);
+        }
+
+        match model.get_ldraw_document_mut(&mut document, self.render_target) {
+            Some(target) => target
+                .commands
+                .extend(references.into_iter().map(Command::PartReference)),
+            None => return Ok(0),
+        }
+        for (alias, subpart) in pasted.subparts {
+            document.subparts.entry(alias).or_insert(subpart);
+        }
+
+        self.load_document(self.cache.clone(), &document, on_update, true)
+            .await?;
+
+        if let Some(model) = &self.model {
+            let objects = model
+                .get_objects(self.render_target)
+                .map(|objects| objects.map(|v| v.id).collect::<Vec<_>>())
+                .unwrap_or_default();
+            let selection = objects[objects.len().saturating_sub(count)..].to_vec();
+            let ops = self
+                .gizmo
+                .set_selection(selection, model, self.render_target, &self.colors);
+            self.animated_model.display_list.mutate_all(ops.into_iter());
+            self.events
+                .emit(AppEvent::SelectionChanged(self.gizmo.selection().to_vec()));
+        }
+        self.refresh_selection_outline();
+
+        Ok(count)
+    }
+
+    pub fn gizmo(&self) -> &TransformGizmo {
+        &self.gizmo
+    }
+
+    pub fn gizmo_mut(&mut self) -> &mut TransformGizmo {
+        &mut self.gizmo
+    }
+
+    // A click waiting to be picked with select_object_at().
+    pub fn take_pending_pick(&mut self) -> Option<(Point2, bool)> {
+        self.pending_pick.take()
+    }
+
+    // Selects the object under given position in logical pixels, or clears the selection if
+    // there is none. With extend, the object is added to or removed from the selection
+    // instead. Returns the object picked. Parts stay borrowed while the GPU is polled, which
+    // blocks until the pass is done.
+    #[allow(clippy::await_holding_refcell_ref)]
+    pub async fn select_object_at(&mut self, position: Point2, extend: bool) -> Option<ObjectId> {
+        if self.animated_model.state != State::Finished {
+            return None;
+        }
+        let model = self.model.as_ref()?;
+
+        let size = self.logical_size();
+        let point = Point2::new(position.x / size.width, position.y / size.height);
+        if !(0.0..1.0).contains(&point.x) || !(0.0..1.0).contains(&point.y) {
+            return None;
+        }
+
+        let picked = {
+            let parts = self.parts.borrow();
+            // Instance id 0 stands for the background.
+            let display_list =
+                SelectionDisplayList::from_model(model, self.render_target, &self.device, 1, true);
+            let op = DisplayListObjectSelectionOp::new(&self.pipelines, &*parts, display_list);
+            match self
+                .pipelines
+                .select_objects_single_op(
+                    &self.device,
+                    &self.queue,
+                    self.projection.get(),
+                    ObjectSelection::Point(point),
+                    &op,
+                )
+                .await
+            {
+                Ok(matches) => matches.and_then(|v| v.into_iter().next()),
+                Err(e) => {
+                    self.events.emit(AppEvent::GpuError(e.to_string()));
+                    None
+                }
+            }
+        };
+        let hidden = self
+            .animated_model
+            .display_list
+            .hidden_keys()
+            .collect::<HashSet<_>>();
+        let picked = picked.filter(|id| !hidden.contains(id));
+
+        let mut selection = if extend {
+            self.gizmo.selection().to_vec()
+        } else {
+            Vec::new()
+        };
+        if let Some(id) = picked {
+            match selection.iter().position(|v| *v == id) {
+                Some(index) => {
+                    selection.remove(index);
+                }
+                None => selection.push(id),
+            }
+        }
+        if selection == self.gizmo.selection() {
+            return picked;
+        }
+
+        let ops = self
+            .gizmo
+            .set_selection(selection, model, self.render_target, &self.colors);
+        self.animated_model.display_list.mutate_all(ops.into_iter());
+        self.events
+            .emit(AppEvent::SelectionChanged(self.gizmo.selection().to_vec()));
+        self.refresh_selection_outline();
+
+        picked
+    }
+
+    pub fn select_next_object(&mut self) {
+        if self.animated_model.state != State::Finished {
+            return;
+        }
+
+        if let Some(model) = &self.model {
+            let ops = self
+                .gizmo
+                .select_next(model, self.render_target, &self.colors);
+            self.animated_model.display_list.mutate_all(ops.into_iter());
+            self.events
+                .emit(AppEvent::SelectionChanged(self.gizmo.selection().to_vec()));
+        }
+        self.refresh_selection_outline();
+    }
+
+    // Objects in the current render target matching the query.
+    pub fn query(&self, query: &ModelQuery) -> HashSet<ObjectId> {
+        match &self.model {
+            Some(model) => model.query(query, self.render_target, &*self.parts.borrow()),
+            None => HashSet::new(),
+        }
+    }
+
+    // Selects objects matching the query, highlighting them as any selection. Returns the
+    // number of objects selected.
+    pub fn select_query(&mut self, query: &ModelQuery) -> usize {
+        if self.animated_model.state != State::Finished {
+            return 0;
+        }
+
+        let matched = self.query(query);
+        if let Some(model) = &self.model {
+            let selection = model
+                .get_objects(self.render_target)
+                .map(|objects| {
+                    objects
+                        .filter(|v| matched.contains(&v.id))
+                        .map(|v| v.id)
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            let ops = self
+                .gizmo
+                .set_selection(selection, model, self.render_target, &self.colors);
+            self.animated_model.display_list.mutate_all(ops.into_iter());
+            self.events
+                .emit(AppEvent::SelectionChanged(self.gizmo.selection().to_vec()));
+        }
+        self.refresh_selection_outline();
+
+        self.gizmo.selection().len()
+    }
+
+    pub fn clear_selection(&mut self) {
+        if let Some(model) = &self.model {
+            let had_selection = !self.gizmo.selection().is_empty();
+            let ops = self
+                .gizmo
+                .set_selection(Vec::new(), model, self.render_target, &self.colors);
+            self.animated_model.display_list.mutate_all(ops.into_iter());
+            if had_selection {
+                self.events.emit(AppEvent::SelectionChanged(Vec::new()));
+            }
+        }
+        self.refresh_selection_outline();
+    }
+
+    // Hides selected objects, which stay hidden until shown again or the document is reloaded.
+    pub fn hide_selection(&mut self) {
+        let Some(model) = &self.model else {
+            return;
+        };
+        let selected = self.gizmo.selected_objects(model, self.render_target);
+        if selected.is_empty() {
+            return;
+        }
+
+        let ops = DisplayList::expand_objects(model, &selected, &self.colors, Clone::clone)
+            .into_iter()
+            .filter_map(|op| match op {
+                DisplayListOps::Insert { key, .. } => Some(DisplayListOps::SetVisible {
+                    key,
+                    visible: false,
+                }),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        self.animated_model.display_list.mutate_all(ops.into_iter());
+        self.clear_selection();
+    }
+
+    pub fn show_all_objects(&mut self) {
+        self.animated_model
+            .display_list
+            .mutate(DisplayListOps::SetAllVisible { visible: true });
+    }
+
+    pub fn has_hidden_objects(&self) -> bool {
+        self.animated_model
+            .display_list
+            .hidden_keys()
+            .next()
+            .is_some()
+    }
+
+    pub fn transform_selection(&mut self, amount: f32) {
+        if self.animated_model.state != State::Finished
+            || self.gizmo.selection().is_empty()
+            || self.exploded_view.is_active()
+        {
+            return;
+        }
+
+        if let Some(model) = &mut self.model {
+            let ops = self.gizmo.transform(
+                amount,
+                model,
+                self.render_target,
+                self.document.as_mut(),
+                &self.colors,
+            );
+            if !ops.is_empty() {
+                self.document_modified = true;
+            }
+            self.animated_model.display_list.mutate_all(ops.into_iter());
+        }
+        // Posed objects are placed relative to where the model places them.
+        if let Some(poser) = self.poser.take() {
+            self.set_articulation(poser.into_articulation());
+        }
+        self.refresh_selection_outline();
+    }
+
+    // Joins objects at the top of the displayed model, so that they can be posed. Replaces
+    // any articulation set before. Returns false if there is no model.
+    pub fn set_articulation(&mut self, articulation: Articulation) -> bool {
+        self.clear_articulation();
+        let Some(model) = &self.model else {
+            return false;
+        };
+        let poser = Poser::new(articulation, model, self.render_target, &self.colors);
+        self.animated_model
+            .display_list
+            .mutate_all(poser.pose().into_iter());
+        self.poser = Some(poser);
+        self.refresh_selection_outline();
+        true
+    }
+
+    // Puts posed objects back where the model places them, returning the articulation.
+    pub fn clear_articulation(&mut self) -> Option<Articulation> {
+        let poser = self.poser.take()?;
+        self.animated_model
+            .display_list
+            .mutate_all(poser.restore().into_iter());
+        self.refresh_selection_outline();
+        Some(poser.into_articulation())
+    }
+
+    pub fn articulation(&self) -> Option<&Articulation> {
+        self.poser.as_ref().map(|v| v.articulation())
+    }
+
+    // Joints following how objects at the top of the displayed model connect to each other,
+    // starting from root.
+    pub fn infer_articulation(&self, root: ObjectId) -> Option<Articulation> {
+        let model = self.model.as_ref()?;
+        let graph = model.connectivity_graph(
+            self.render_target,
+            &self.connections,
+            &ConnectionParams::default(),
+        );
+        Some(Articulation::from_connectivity(&graph, root))
+    }
+
+    // Returns false if the object has no joint taking the value.
+    pub fn set_joint_value(&mut self, id: &ObjectId, value: JointValue) -> bool {
+        let Some(ops) = self.poser.as_mut().and_then(|v| v.set_value(id, value)) else {
+            return false;
+        };
+        self.animated_model.display_list.mutate_all(ops.into_iter());
+        self.refresh_selection_outline();
+        true
+    }
+
+    // Articulates the model from the first selected object, or the first object if nothing is
+    // selected. Puts the model back at rest if it is articulated already.
+    pub fn toggle_articulation(&mut self) {
+        if self.clear_articulation().is_some() {
+            return;
+        }
+        let root = match self.gizmo.selection().first() {
+            Some(id) => Some(*id),
+            None => self
+                .model
+                .as_ref()
+                .and_then(|model| model.get_objects(self.render_target)?.next())
+                .map(|v| v.id),
+        };
+        if let Some(articulation) = root.and_then(|v| self.infer_articulation(v)) {
+            self.set_articulation(articulation);
+        }
+    }
+
+    // Turns or slides joints of selected objects by steps of the gizmo. Ball joints turn
+    // around the axis of the gizmo.
+    pub fn articulate_selection(&mut self, amount: f32) {
+        if self.animated_model.state != State::Finished || self.exploded_view.is_active() {
+            return;
+        }
+        let Some(poser) = &self.poser else {
+            return;
+        };
+
+        let angle: Rad<f32> = (self.gizmo.rotation_step * amount).into();
+        let values = self
+            .gizmo
+            .selection()
+            .iter()
+            .filter_map(|id| {
+                let joint = poser.articulation().joint(id)?;
+                let value = match (joint.kind, poser.articulation().value(id)?) {
+                    (JointKind::Revolute { .. }, JointValue::Scalar(v)) => {
+                        JointValue::Scalar(v + angle.0)
+                    }
+                    (JointKind::Slider { .. }, JointValue::Scalar(v)) => {
+                        JointValue::Scalar(v + self.gizmo.translation_step * amount)
+                    }
+                    (JointKind::Ball, JointValue::Rotation(q)) => JointValue::Rotation(
+                        Quaternion::from_axis_angle(self.gizmo.axis.unit(), angle) * q,
+                    ),
+                    _ => return None,
+                };
+                Some((*id, value))
+            })
+            .collect::<Vec<_>>();
+
+        for (id, value) in values {
+            self.set_joint_value(&id, value);
+        }
+    }
+
+    fn refresh_selection_outline(&mut self) {
+        let mut outline: Entity<DisplayList<ObjectId, PartAlias>> = DisplayList::new().into();
+        if let Some(model) = &self.model {
+            outline.mutate_all(
+                self.gizmo
+                    .outline_ops(model, self.render_target, &self.colors)
+                    .into_iter(),
+            );
+        }
+        if let Some(poser) = &self.poser {
+            outline.mutate_all(poser.pose().into_iter());
+        }
+        self.selection_outline = outline;
+    }
+
+    pub fn set_standard_view(&mut self, view: StandardView) {
+        self.orbit_controller
+            .borrow_mut()
+            .set_standard_view(view, CAMERA_TRANSITION_DURATION);
+    }
+
+    // Fits the camera to selected objects, or to everything in view if nothing is selected.
+    pub fn frame_selection(&mut self) {
+        let Some(model) = &self.model else {
+            return;
+        };
+        let selected = self.gizmo.selected_objects(model, self.render_target);
+        let bounding_box = if selected.is_empty() {
+            calculate_model_bounding_box(model, self.render_target, &*self.parts.borrow())
+        } else {
+            calculate_objects_bounding_box(model, &selected, &*self.parts.borrow())
+        };
+        self.orbit_controller
+            .borrow_mut()
+            .frame_bounds(&bounding_box, CAMERA_TRANSITION_DURATION);
+    }
+
+    // Fits the camera to the instances of a subpart placed in the current view. Returns
+    // false if there are none.
+    pub fn frame_subpart(&mut self, group_id: GroupId) -> bool {
+        let Some(model) = &self.model else {
+            return false;
+        };
+        let instances = model
+            .get_objects(self.render_target)
+            .map(|objects| {
+                objects
+                    .filter(|v| {
+                        matches!(&v.data, model::ObjectInstance::PartGroup(pg) if pg.group_id == group_id)
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        if instances.is_empty() {
+            return false;
+        }
+
+        let bounding_box = calculate_objects_bounding_box(model, &instances, &*self.parts.borrow());
+        self.orbit_controller
+            .borrow_mut()
+            .frame_bounds(&bounding_box, CAMERA_TRANSITION_DURATION);
+        true
+    }
 
-            self.projection.mutate_all(
-                self.orbit_controller
-                    .borrow_mut()
-                    .update(self.size.width, self.size.height, None)
-                    .into_iter(),
-            );
+    pub fn camera_bookmark(&self) -> CameraBookmark {
+        self.orbit_controller.borrow().bookmark()
+    }
 
-            self.framebuffer_texture = if self.sample_count > 1 {
-                Some(Texture::create_framebuffer(
-                    &self.device,
-                    &self.config,
-                    self.sample_count,
-                    Some("Multisample framebuffer"),
-                ))
-            } else {
-                None
+    pub fn restore_camera(&mut self, bookmark: &CameraBookmark) {
+        self.orbit_controller
+            .borrow_mut()
+            .restore_bookmark(bookmark, CAMERA_TRANSITION_DURATION);
+    }
+
+    pub fn bookmarks(&self) -> &[Option<CameraBookmark>] {
+        &self.bookmarks
+    }
+
+    pub fn save_bookmark(&mut self, slot: usize) {
+        if slot < BOOKMARK_SLOTS {
+            self.bookmarks[slot] = Some(self.camera_bookmark());
+        }
+    }
+
+    // Saves current view into the first free slot, returning it.
+    pub fn add_bookmark(&mut self) -> Option<usize> {
+        let slot = self.bookmarks.iter().position(Option::is_none)?;
+        self.save_bookmark(slot);
+        Some(slot)
+    }
+
+    pub fn recall_bookmark(&mut self, slot: usize) -> bool {
+        match self.bookmarks.get(slot).cloned().flatten() {
+            Some(bookmark) => {
+                self.restore_camera(&bookmark);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_ground_visible(&self) -> bool {
+        self.pipelines.ground.visible
+    }
+
+    pub fn toggle_ground(&mut self) {
+        self.pipelines.ground.visible = !self.pipelines.ground.visible;
+    }
+
+    // Moves the ground below the model being viewed.
+    fn update_ground(&mut self) {
+        if let Some(model) = &self.model {
+            let bounding_box =
+                calculate_model_bounding_box(model, self.render_target, &*self.parts.borrow());
+            self.pipelines.ground.set_bounds(&bounding_box);
+            self.pipelines.ground.update_uniforms(&self.queue);
+        }
+    }
+
+    pub fn cutaway(&self) -> &Cutaway {
+        &self.cutaway
+    }
+
+    fn update_clip_planes(&mut self) {
+        self.projection.mutate_all(
+            vec![ProjectionMutator::SetClipPlanes(self.cutaway.clip_planes())].into_iter(),
+        );
+    }
+
+    // Turns the cutaway off or moves it to the next axis.
+    pub fn cycle_cutaway(&mut self) {
+        if let Some(model) = &self.model {
+            let bounding_box =
+                calculate_model_bounding_box(model, self.render_target, &*self.parts.borrow());
+            self.cutaway.cycle_axis(&bounding_box);
+            self.update_clip_planes();
+        }
+    }
+
+    pub fn move_cutaway(&mut self, steps: f32) {
+        self.cutaway.advance(steps);
+        self.update_clip_planes();
+    }
+
+    pub fn flip_cutaway(&mut self) {
+        if self.cutaway.is_active() {
+            self.cutaway.flip();
+            self.update_clip_planes();
+        }
+    }
+
+    pub fn clear_cutaway(&mut self) {
+        self.cutaway.clear();
+        self.update_clip_planes();
+    }
+
+    pub fn exploded_view(&self) -> &ExplodedView {
+        &self.exploded_view
+    }
+
+    fn rebuild_exploded_view(&mut self) {
+        if let Some(model) = &self.model {
+            let connectivity = match self.exploded_view.direction {
+                ExplosionDirection::Connectivity => Some(model.connectivity_graph(
+                    self.render_target,
+                    &self.connections,
+                    &ConnectionParams::default(),
+                )),
+                ExplosionDirection::Centroid => None,
             };
-            self.depth_texture = Texture::create_depth_texture(
-                &self.device,
-                &self.config,
-                self.sample_count,
-                Some("Depth texture"),
+            self.exploded_view.build(
+                model,
+                self.render_target,
+                &*self.parts.borrow(),
+                connectivity.as_ref(),
+                &self.colors,
             );
+            let ops = self.exploded_view.ops();
+            self.animated_model.display_list.mutate_all(ops.into_iter());
         }
     }
 
-    pub fn render(&mut self) -> Result<Duration, wgpu::SurfaceError> {
-        let now = Instant::now();
-
-        self.projection.update(&self.device, &self.queue);
-        self.animated_model
-            .display_list
-            .update(&self.device, &self.queue);
+    pub fn toggle_exploded_view(&mut self) {
+        if self.animated_model.state != State::Finished {
+            return;
+        }
 
-        let part_querier = self.parts.borrow();
+        if self.exploded_view.target_factor() > 0.0 {
+            self.exploded_view.set_target_factor(0.0);
+        } else {
+            if !self.exploded_view.is_active() {
+                self.rebuild_exploded_view();
+            }
+            self.exploded_view.set_target_factor(1.0);
+        }
+    }
 
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+    pub fn adjust_exploded_view(&mut self, increase: bool) {
+        if !self.exploded_view.is_active() {
+            return;
+        }
 
-        let (target_view, resolve_target) = if let Some(texture) = self.framebuffer_texture.as_ref()
-        {
-            (&texture.view, Some(&view))
+        if increase {
+            self.exploded_view.increase();
         } else {
-            (&view, None)
-        };
+            self.exploded_view.decrease();
+        }
+    }
 
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Command Encoder"),
-            });
+    pub fn set_explosion_direction(&mut self, direction: ExplosionDirection) {
+        if self.exploded_view.direction == direction {
+            return;
+        }
 
-        {
-            let mut pass = encoder
-                .begin_render_pass(&wgpu::RenderPassDescriptor {
-                    label: Some("Main render pass"),
-                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                        view: target_view,
-                        resolve_target,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color {
-                                r: 1.0,
-                                g: 1.0,
-                                b: 1.0,
-                                a: 0.0,
-                            }),
-                            store: wgpu::StoreOp::Store,
-                        },
-                    })],
-                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                        view: &self.depth_texture.view,
-                        depth_ops: Some(wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(1.0),
-                            store: wgpu::StoreOp::Store,
-                        }),
-                        stencil_ops: None,
-                    }),
-                    occlusion_query_set: None,
-                    timestamp_writes: None,
-                })
-                .forget_lifetime();
+        self.exploded_view.direction = direction;
+        if self.exploded_view.is_active() {
+            self.rebuild_exploded_view();
+        }
+    }
 
-            self.pipelines.render::<_, _>(
-                &mut pass,
-                self.projection.get(),
-                &*part_querier,
-                &self.animated_model.display_list,
-            );
+    pub fn breakdown(&self) -> &ColorBreakdown {
+        &self.breakdown
+    }
+
+    // Statistics of the last rendered frame.
+    pub fn subscribe<F: FnMut(&AppEvent) + 'static>(&mut self, listener: F) -> ListenerId {
+        self.events.subscribe(listener)
+    }
+
+    pub fn unsubscribe(&mut self, id: ListenerId) {
+        self.events.unsubscribe(id);
+    }
+
+    // Returns a stream of events for embedders that would rather await them.
+    pub fn event_channel(&mut self) -> UnboundedReceiver<AppEvent> {
+        self.events.channel()
+    }
+
+    fn step_state(&self) -> (State, usize) {
+        (self.animated_model.state, self.animated_model.step)
+    }
+
+    fn emit_step_change(&mut self, previous: (State, usize)) {
+        let (state, step) = self.step_state();
+        if (state, step) != previous {
+            self.events.emit(AppEvent::StepChanged { step, state });
         }
+    }
 
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+    fn emit_all(&mut self, events: Vec<AppEvent>) {
+        for event in events {
+            self.events.emit(event);
+        }
+    }
 
-        Ok(now.elapsed())
+    fn emit_model_loaded(&mut self) {
+        if let Some(summary) = &self.summary {
+            self.events.emit(AppEvent::ModelLoaded(summary.clone()));
+        }
+        let subparts = self.get_subparts();
+        self.events.emit(AppEvent::SubpartsChanged(subparts));
     }
 
-    pub fn get_subparts(&self) -> Vec<(GroupId, String)> {
-        if let Some(model) = &self.model {
-            let mut result = model
-                .object_groups
-                .iter()
-                .map(|(k, v)| (*k, v.name.clone()))
-                .collect::<Vec<_>>();
-            result.sort_by(|a, b| a.1.cmp(&b.1));
-            result
+    fn emit_breakdown_change(&mut self) {
+        self.events.emit(AppEvent::BreakdownChanged {
+            mode: self.breakdown.mode(),
+            legend: self.breakdown.legend().to_vec(),
+        });
+    }
+
+    // Stops showing the breakdown without restoring colors, for when the display list is
+    // being built again anyway.
+    fn discard_breakdown(&mut self) {
+        if self.breakdown.is_active() {
+            self.breakdown.clear();
+            self.emit_breakdown_change();
+        }
+    }
+
+    // Records part loads so that they can be emitted once resolution is done.
+    fn collect_resolution_events<'a, F: Fn(PartAlias, Result<(), ResolutionError>)>(
+        events: &'a RefCell<Vec<AppEvent>>,
+        on_update: &'a F,
+    ) -> impl Fn(PartAlias, Result<(), ResolutionError>) + 'a {
+        move |alias, result| {
+            events.borrow_mut().push(match &result {
+                Ok(()) => AppEvent::PartLoaded(alias.clone()),
+                Err(e) => AppEvent::PartLoadFailed {
+                    alias: alias.clone(),
+                    error: e.to_string(),
+                },
+            });
+            on_update(alias, result);
+        }
+    }
+
+    pub fn render_stats(&self) -> &RenderStats {
+        &self.render_stats
+    }
+
+    pub fn toggle_render_stats(&mut self) {
+        self.show_render_stats = !self.show_render_stats;
+    }
+
+    // Text to be shown over the viewport, if enabled.
+    pub fn render_stats_overlay(&self) -> Option<String> {
+        if !self.show_render_stats {
+            return None;
+        }
+
+        let stats = &self.render_stats;
+        let mut overlay = format!(
+            "{} draw calls ({} translucent), {} instances ({} culled), {} triangles",
+            stats.draw_calls,
+            stats.translucent_passes,
+            stats.instances,
+            stats.culled_instances,
+            stats.triangles
+        );
+        if let Some(timings) = &stats.gpu_timings {
+            overlay.push_str(&format!(", {}", timings));
+        }
+        Some(overlay)
+    }
+
+    // Returns false if the device does not support timestamp queries.
+    pub fn set_gpu_profiling(&mut self, enabled: bool) -> bool {
+        if enabled {
+            self.pipelines
+                .enable_gpu_profiling(&self.device, &self.queue)
         } else {
-            Vec::new()
+            self.pipelines.disable_gpu_profiling();
+            true
         }
     }
 
-    pub fn set_render_target(&mut self, group_id: Option<GroupId>) {
-        if let Some(model) = &mut self.model {
-            self.animated_model = AnimatedModel::from_model(model, group_id, &self.colors, false);
+    // Tints parts by lot or category, or reverts them to their colors if mode is None.
+    pub fn set_breakdown_mode(&mut self, mode: Option<BreakdownMode>) {
+        if self.breakdown.mode() == mode {
+            return;
+        }
 
-            let bounding_box = calculate_model_bounding_box(model, group_id, &*self.parts.borrow());
-            let center = bounding_box.center();
+        if self.breakdown.is_active() {
+            let ops = self.breakdown.restore_ops();
+            self.animated_model.display_list.mutate_all(ops.into_iter());
+            self.breakdown.clear();
+            if self.document_opacity < 1.0 {
+                self.set_document_opacity(self.document_opacity);
+            }
+        }
 
-            let mut orbit_controller = self.orbit_controller.borrow_mut();
-            orbit_controller.camera.look_at = Point3::new(center.x, center.y, center.z);
-            orbit_controller.radius = (bounding_box.len_x() * bounding_box.len_x()
-                + bounding_box.len_y() * bounding_box.len_y()
-                + bounding_box.len_z() * bounding_box.len_z())
-            .sqrt()
-                * 2.0;
+        if let (Some(mode), Some(model)) = (mode, &self.model) {
+            if self.animated_model.state == State::Finished {
+                self.breakdown.build(
+                    mode,
+                    model,
+                    self.render_target,
+                    &*self.parts.borrow(),
+                    &self.colors,
+                );
+                let ops = self.breakdown.ops(self.document_opacity);
+                self.animated_model.display_list.mutate_all(ops.into_iter());
+            }
         }
+        self.emit_breakdown_change();
     }
 
-    pub fn state(&self) -> State {
-        self.animated_model.state
+    pub fn cycle_breakdown_mode(&mut self) {
+        self.set_breakdown_mode(match self.breakdown.mode() {
+            None => Some(BreakdownMode::Lot),
+            Some(BreakdownMode::Lot) => Some(BreakdownMode::Category),
+            Some(BreakdownMode::Category) => None,
+        });
     }
 
     pub fn handle_window_event(&mut self, event: event::WindowEvent, current_time: f32) -> bool {
-        match event {
+        let input = match event {
             event::WindowEvent::Resized(size) => {
                 self.resize(size);
+                return true;
+            }
+            event::WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                self.set_scale_factor(scale_factor);
+                return true;
+            }
+            event::WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+                return true;
             }
             event::WindowEvent::KeyboardInput { event, .. } => {
-                if event.logical_key == Key::Named(NamedKey::Space)
-                    && event.state == event::ElementState::Pressed
-                {
-                    self.advance(current_time);
+                if event.state != event::ElementState::Pressed {
+                    return true;
                 }
+                InputEvent::KeyPressed(event.logical_key)
             }
-            event::WindowEvent::MouseInput { state, button, .. } => {
-                if button == event::MouseButton::Left {
-                    self.orbit_controller
-                        .borrow_mut()
-                        .on_mouse_press(state == event::ElementState::Pressed);
+            event::WindowEvent::MouseInput { state, button, .. } => InputEvent::MouseButton {
+                button,
+                pressed: state == event::ElementState::Pressed,
+            },
+            event::WindowEvent::MouseWheel { delta, .. } => InputEvent::Zoom(match delta {
+                event::MouseScrollDelta::LineDelta(_x, y) => y,
+                event::MouseScrollDelta::PixelDelta(d) => {
+                    d.to_logical::<f32>(self.scale_factor).y * 0.5
+                }
+            }),
+            event::WindowEvent::Touch(touch) => InputEvent::Touch {
+                id: touch.id,
+                phase: touch.phase,
+                location: self.to_logical(touch.location),
+            },
+            event::WindowEvent::TouchpadMagnify { delta, .. } => InputEvent::Zoom(delta as f32),
+            event::WindowEvent::CursorMoved { position, .. } => {
+                InputEvent::CursorMoved(self.to_logical(position))
+            }
+            _ => return false,
+        };
+        self.dispatch_input(input, current_time);
+
+        true
+    }
+
+    // Handles live input from any source, such as gamepads polled by the caller.
+    pub fn dispatch_input(&mut self, input: InputEvent, current_time: f32) {
+        // Live input would make playback diverge from the recording.
+        if self.player.is_some() {
+            return;
+        }
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(input.clone());
+        }
+        self.handle_input(input, current_time);
+    }
+
+    pub fn input_map(&self) -> &InputMap {
+        &self.input_map
+    }
+
+    // Axes being held are released, as their bindings may be gone.
+    pub fn set_input_map(&mut self, input_map: InputMap) {
+        self.input_map = input_map;
+        self.axes.clear();
+    }
+
+    pub fn handle_input(&mut self, input: InputEvent, current_time: f32) {
+        match input {
+            InputEvent::KeyPressed(key) => {
+                if let Some(action) = self.input_map.key_action(&key) {
+                    self.perform_action(action, current_time);
                 }
             }
-            event::WindowEvent::MouseWheel { delta, .. } => match delta {
-                event::MouseScrollDelta::LineDelta(_x, y) => {
-                    self.orbit_controller.borrow_mut().zoom(y);
+            InputEvent::Action(action) => self.perform_action(action, current_time),
+            InputEvent::Axis { action, value } => {
+                if value == 0.0 {
+                    self.axes.remove(&action);
+                } else {
+                    self.axes.insert(action, value);
                 }
-                event::MouseScrollDelta::PixelDelta(d) => {
-                    self.orbit_controller.borrow_mut().zoom(d.y as f32 * 0.5);
+            }
+            InputEvent::MouseButton { button, pressed } => {
+                if button == event::MouseButton::Left {
+                    self.orbit_controller.borrow_mut().on_mouse_press(pressed);
+                    if pressed {
+                        self.click_origin = Some(self.pointer);
+                    } else if let Some(origin) = self.click_origin.take() {
+                        if (self.pointer - origin).magnitude() < CLICK_DISTANCE {
+                            self.pending_pick = Some((self.pointer, self.modifiers.shift_key()));
+                        }
+                    }
+                } else if button == event::MouseButton::Right {
+                    self.gizmo.on_drag_press(pressed);
                 }
-            },
-            event::WindowEvent::Touch(touch) => match touch.phase {
-                event::TouchPhase::Started => {
-                    self.orbit_controller.borrow_mut().on_mouse_press(true)
+            }
+            InputEvent::CursorMoved(position) => self.on_pointer_move(position),
+            InputEvent::Zoom(delta) => self.orbit_controller.borrow_mut().zoom(delta),
+            InputEvent::Touch {
+                id,
+                phase,
+                location,
+            } => self.on_touch(id, phase, location),
+        }
+    }
+
+    pub fn perform_action(&mut self, action: Action, time: f32) {
+        match action {
+            Action::NextStep => self.advance(time),
+            Action::BakeAmbientOcclusion => self.bake_ambient_occlusion(),
+            Action::SaveCamera => self.save_camera(),
+            Action::ToggleRenderStats => self.toggle_render_stats(),
+            Action::CycleSampleCount => {
+                self.cycle_sample_count();
+            }
+            Action::SelectNextObject => self.select_next_object(),
+            Action::ClearSelection => self.clear_selection(),
+            Action::TranslateMode => self.gizmo.mode = TransformMode::Translate,
+            Action::RotateMode => self.gizmo.mode = TransformMode::Rotate,
+            Action::SelectAxisX => self.gizmo.axis = Axis::X,
+            Action::SelectAxisY => self.gizmo.axis = Axis::Y,
+            Action::SelectAxisZ => self.gizmo.axis = Axis::Z,
+            Action::StandardView(view) => self.set_standard_view(view),
+            Action::AddBookmark => {
+                self.add_bookmark();
+            }
+            Action::RecallBookmark(slot) => {
+                self.recall_bookmark(slot);
+            }
+            Action::FrameSelection => self.frame_selection(),
+            Action::HideSelection => self.hide_selection(),
+            Action::ShowAllObjects => self.show_all_objects(),
+            Action::ToggleGround => self.toggle_ground(),
+            Action::CycleCutaway => self.cycle_cutaway(),
+            Action::MoveCutawayBackward => self.move_cutaway(-1.0),
+            Action::MoveCutawayForward => self.move_cutaway(1.0),
+            Action::FlipCutaway => self.flip_cutaway(),
+            Action::ToggleExplodedView => self.toggle_exploded_view(),
+            Action::IncreaseExplosion => self.adjust_exploded_view(true),
+            Action::DecreaseExplosion => self.adjust_exploded_view(false),
+            Action::DecreaseOpacity => {
+                self.set_document_opacity(self.document_opacity - OPACITY_STEP)
+            }
+            Action::IncreaseOpacity => {
+                self.set_document_opacity(self.document_opacity + OPACITY_STEP)
+            }
+            Action::DecreaseOverlayOpacity => {
+                if let Some(opacity) = self.overlay_opacity() {
+                    self.set_overlay_opacity(opacity - OPACITY_STEP);
                 }
-                event::TouchPhase::Ended | event::TouchPhase::Cancelled => {
-                    self.orbit_controller.borrow_mut().on_mouse_press(false)
+            }
+            Action::IncreaseOverlayOpacity => {
+                if let Some(opacity) = self.overlay_opacity() {
+                    self.set_overlay_opacity(opacity + OPACITY_STEP);
                 }
-                event::TouchPhase::Moved => self
-                    .orbit_controller
-                    .borrow_mut()
-                    .on_mouse_move(touch.location.x as f32, touch.location.y as f32),
-            },
-            event::WindowEvent::TouchpadMagnify { delta, .. } => {
-                self.orbit_controller.borrow_mut().zoom(delta as f32);
             }
-            event::WindowEvent::CursorMoved { position, .. } => {
-                self.orbit_controller
-                    .borrow_mut()
-                    .on_mouse_move(position.x as f32, position.y as f32);
+            Action::ToggleExplosionDirection => {
+                self.set_explosion_direction(match self.exploded_view.direction {
+                    ExplosionDirection::Centroid => ExplosionDirection::Connectivity,
+                    ExplosionDirection::Connectivity => ExplosionDirection::Centroid,
+                })
             }
-            _ => return false,
+            Action::TransformForward => self.transform_selection(1.0),
+            Action::TransformBackward => self.transform_selection(-1.0),
+            Action::ToggleArticulation => self.toggle_articulation(),
+            Action::ArticulateForward => self.articulate_selection(1.0),
+            Action::ArticulateBackward => self.articulate_selection(-1.0),
+            #[cfg(feature = "physics")]
+            Action::TogglePhysics => {
+                if self.is_simulating_physics() {
+                    self.stop_physics();
+                } else {
+                    self.start_physics(&physics::PhysicsConfig::falling());
+                }
+            }
+            #[cfg(feature = "physics")]
+            Action::KnockApart => self.knock_apart(KNOCK_SPEED),
+            #[cfg(not(feature = "physics"))]
+            Action::TogglePhysics | Action::KnockApart => {}
         }
+    }
 
-        true
+    pub fn scale_factor(&self) -> f64 {
+        self.scale_factor
+    }
+
+    pub fn set_scale_factor(&mut self, scale_factor: f64) {
+        if scale_factor > 0.0 {
+            self.scale_factor = scale_factor;
+        }
+    }
+
+    pub fn logical_size(&self) -> winit::dpi::LogicalSize<f32> {
+        self.size.to_logical(self.scale_factor)
+    }
+
+    pub fn to_logical(&self, position: winit::dpi::PhysicalPosition<f64>) -> Point2 {
+        let position = position.to_logical::<f32>(self.scale_factor);
+        Point2::new(position.x, position.y)
+    }
+
+    // Takes pointer position in logical pixels.
+    pub fn on_pointer_move(&mut self, position: Point2) {
+        self.pointer = position;
+        if self.gizmo.is_dragging() {
+            let steps = self.gizmo.on_drag_move(position.x);
+            self.transform_selection(steps);
+        } else {
+            self.orbit_controller
+                .borrow_mut()
+                .on_mouse_move(position.x, position.y);
+        }
+    }
+
+    // Takes touch location in logical pixels.
+    pub fn on_touch(&mut self, id: u64, phase: event::TouchPhase, location: Point2) {
+        if let Some(gesture) = self.touch_tracker.on_touch(id, phase, location) {
+            self.orbit_controller.borrow_mut().apply_gesture(&gesture);
+        }
     }
 
     pub fn request_redraw(&self) {
@@ -0,0 +1,267 @@
+use cgmath::{InnerSpace, Quaternion};
+use ldraw::{units::LDU_PER_STUD, Matrix4, PartAlias, Vector3};
+use ldraw_ir::{model::ObjectId, part::PartGeometryQuerier};
+use ldraw_renderer::display_list::DisplayListOps;
+use rapier3d::{
+    dynamics::{
+        CCDSolver, ImpulseJointSet, IntegrationParameters, IslandManager, MultibodyJointSet,
+        RigidBodyBuilder, RigidBodyHandle, RigidBodySet,
+    },
+    geometry::{ColliderBuilder, ColliderSet, DefaultBroadPhase, NarrowPhase},
+    math::{Point, Vector},
+    na::Unit,
+    pipeline::PhysicsPipeline,
+};
+
+const LDU_PER_METER: f32 = 2500.0;
+// Frames taking longer than this are simulated as if they didn't, so that a stall doesn't
+// have the simulation catch up for a long while.
+const MAX_FRAME_TIME: f32 = 0.25;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PhysicsConfig {
+    // In LDraw units per second squared, towards the ground.
+    pub gravity: f32,
+    // Parts start this far above where they are placed.
+    pub drop_height: f32,
+    // Added to the height of each part in order, so that they come down one after another.
+    pub stagger: f32,
+    pub friction: f32,
+    pub restitution: f32,
+}
+
+impl Default for PhysicsConfig {
+    fn default() -> Self {
+        Self {
+            gravity: 9.81 * LDU_PER_METER,
+            drop_height: 0.0,
+            stagger: 0.0,
+            friction: 0.6,
+            restitution: 0.2,
+        }
+    }
+}
+
+impl PhysicsConfig {
+    // Parts rain down onto the ground in the order they are built.
+    pub fn falling() -> Self {
+        Self {
+            drop_height: LDU_PER_STUD * 10.0,
+            stagger: LDU_PER_STUD,
+            ..Default::default()
+        }
+    }
+}
+
+struct SimulatedPart {
+    key: ObjectId,
+    body: RigidBodyHandle,
+    // Placement without translation. Hulls are built with it applied, so that bodies start
+    // unrotated and mirrored or scaled parts keep their shape.
+    linear: Matrix4,
+    original: Matrix4,
+}
+
+// Rigid body simulation of parts being displayed, each approximated by the convex hull of its
+// baked mesh. Parts without geometry stay where they are.
+pub struct PhysicsSimulation {
+    parts: Vec<SimulatedPart>,
+    bodies: RigidBodySet,
+    colliders: ColliderSet,
+    gravity: Vector<f32>,
+    parameters: IntegrationParameters,
+    pipeline: PhysicsPipeline,
+    islands: IslandManager,
+    broad_phase: DefaultBroadPhase,
+    narrow_phase: NarrowPhase,
+    impulse_joints: ImpulseJointSet,
+    multibody_joints: MultibodyJointSet,
+    ccd_solver: CCDSolver,
+    last_time: Option<f32>,
+    // Time not simulated yet, as steps are of fixed length.
+    remainder: f32,
+}
+
+impl PhysicsSimulation {
+    // Parts are given as display list keys along with what and where they are. The ground is
+    // a plane at the given height, if any.
+    pub fn new<'a>(
+        parts: impl IntoIterator<Item = (ObjectId, &'a PartAlias, Matrix4)>,
+        geometry: &impl PartGeometryQuerier<PartAlias>,
+        ground: Option<f32>,
+        config: &PhysicsConfig,
+    ) -> Self {
+        let mut bodies = RigidBodySet::new();
+        let mut colliders = ColliderSet::new();
+
+        if let Some(ground) = ground {
+            // Y axis points downwards.
+            let ground =
+                bodies.insert(RigidBodyBuilder::fixed().translation(Vector::new(0.0, ground, 0.0)));
+            colliders.insert_with_parent(
+                ColliderBuilder::halfspace(Unit::new_normalize(Vector::new(0.0, -1.0, 0.0)))
+                    .friction(config.friction)
+                    .restitution(config.restitution),
+                ground,
+                &mut bodies,
+            );
+        }
+
+        let mut simulated = Vec::new();
+        for (key, alias, matrix) in parts {
+            let Some(part) = geometry.query_part_geometry(alias) else {
+                continue;
+            };
+            let mut linear = matrix;
+            linear.w = cgmath::Vector4::new(0.0, 0.0, 0.0, 1.0);
+            let points = part
+                .geometry
+                .vertex_buffer
+                .0
+                .chunks_exact(3)
+                .map(|v| {
+                    let v = linear * cgmath::Vector4::new(v[0], v[1], v[2], 1.0);
+                    Point::new(v.x, v.y, v.z)
+                })
+                .collect::<Vec<_>>();
+            let Some(collider) = ColliderBuilder::convex_hull(&points) else {
+                continue;
+            };
+
+            let height = config.drop_height + config.stagger * simulated.len() as f32;
+            let body = bodies.insert(
+                RigidBodyBuilder::dynamic()
+                    .translation(Vector::new(matrix.w.x, matrix.w.y - height, matrix.w.z))
+                    .ccd_enabled(true),
+            );
+            colliders.insert_with_parent(
+                collider
+                    .friction(config.friction)
+                    .restitution(config.restitution),
+                body,
+                &mut bodies,
+            );
+            simulated.push(SimulatedPart {
+                key,
+                body,
+                linear,
+                original: matrix,
+            });
+        }
+
+        let parameters = IntegrationParameters {
+            // Tolerances scale with the size of a brick rather than a meter.
+            length_unit: LDU_PER_STUD * 5.0,
+            ..Default::default()
+        };
+
+        Self {
+            parts: simulated,
+            bodies,
+            colliders,
+            gravity: Vector::new(0.0, config.gravity, 0.0),
+            parameters,
+            pipeline: PhysicsPipeline::new(),
+            islands: IslandManager::new(),
+            broad_phase: DefaultBroadPhase::new(),
+            narrow_phase: NarrowPhase::new(),
+            impulse_joints: ImpulseJointSet::new(),
+            multibody_joints: MultibodyJointSet::new(),
+            ccd_solver: CCDSolver::new(),
+            last_time: None,
+            remainder: 0.0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parts.is_empty()
+    }
+
+    // Every part has come to rest.
+    pub fn is_settled(&self) -> bool {
+        self.parts
+            .iter()
+            .all(|v| self.bodies.get(v.body).is_none_or(|v| v.is_sleeping()))
+    }
+
+    // Simulates up to the given time in fixed steps. The first call only marks the start.
+    pub fn advance(&mut self, time: f32) {
+        let elapsed = match self.last_time.replace(time) {
+            Some(last_time) => (time - last_time).clamp(0.0, MAX_FRAME_TIME),
+            None => 0.0,
+        };
+        self.remainder += elapsed;
+
+        while self.remainder >= self.parameters.dt {
+            self.pipeline.step(
+                &self.gravity,
+                &self.parameters,
+                &mut self.islands,
+                &mut self.broad_phase,
+                &mut self.narrow_phase,
+                &mut self.bodies,
+                &mut self.colliders,
+                &mut self.impulse_joints,
+                &mut self.multibody_joints,
+                &mut self.ccd_solver,
+                None,
+                &(),
+                &(),
+            );
+            self.remainder -= self.parameters.dt;
+        }
+    }
+
+    // Flings every part away from origin, and upwards a little. Speed is in LDraw units per
+    // second.
+    pub fn knock_apart(&mut self, origin: Vector3, speed: f32) {
+        for part in &self.parts {
+            let Some(body) = self.bodies.get_mut(part.body) else {
+                continue;
+            };
+            let center = body.center_of_mass();
+            let away = Vector3::new(center.x, center.y, center.z) - origin;
+            let direction = if away.magnitude2() > 0.0 {
+                away.normalize()
+            } else {
+                Vector3::new(0.0, 0.0, 0.0)
+            };
+            // Y axis points downwards.
+            let direction = (direction + Vector3::new(0.0, -0.5, 0.0)) * speed * body.mass();
+            body.apply_impulse(Vector::new(direction.x, direction.y, direction.z), true);
+        }
+    }
+
+    pub fn ops(&self) -> Vec<DisplayListOps<ObjectId, PartAlias>> {
+        self.parts
+            .iter()
+            .filter_map(|part| {
+                let body = self.bodies.get(part.body)?;
+                let translation = body.translation();
+                let rotation = body.rotation();
+                let matrix = Matrix4::from_translation(Vector3::new(
+                    translation.x,
+                    translation.y,
+                    translation.z,
+                )) * Matrix4::from(Quaternion::new(
+                    rotation.w, rotation.i, rotation.j, rotation.k,
+                )) * part.linear;
+                Some(DisplayListOps::UpdateMatrix {
+                    key: part.key,
+                    matrix,
+                })
+            })
+            .collect()
+    }
+
+    // Puts parts back where the model places them.
+    pub fn restore(&self) -> Vec<DisplayListOps<ObjectId, PartAlias>> {
+        self.parts
+            .iter()
+            .map(|part| DisplayListOps::UpdateMatrix {
+                key: part.key,
+                matrix: part.original,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,135 @@
+use ldraw::{Point2, Point3, Vector2};
+use serde::{Deserialize, Serialize};
+use winit::{
+    event::{MouseButton, TouchPhase},
+    keyboard::Key,
+};
+
+use crate::input::{Action, AxisAction};
+
+// Input as understood by the app, independent of the window it came from.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum InputEvent {
+    KeyPressed(Key),
+    MouseButton {
+        button: MouseButton,
+        pressed: bool,
+    },
+    // In logical pixels.
+    CursorMoved(Point2),
+    Zoom(f32),
+    Touch {
+        id: u64,
+        phase: TouchPhase,
+        location: Point2,
+    },
+    // Bound to a gamepad button, or triggered by the embedder.
+    Action(Action),
+    // Held until another value comes for the same action.
+    Axis {
+        action: AxisAction,
+        value: f32,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    // Number of frames rendered since recording began.
+    pub frame: u64,
+    pub input: InputEvent,
+}
+
+// Camera orbit at the beginning of a recording.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ViewState {
+    pub latitude: f32,
+    pub longitude: f32,
+    pub radius: f32,
+    pub look_at: Point3,
+    pub velocity: Vector2,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Recording {
+    pub frame_rate: f32,
+    pub frames: u64,
+    pub view: ViewState,
+    pub events: Vec<RecordedEvent>,
+}
+
+impl Recording {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(text)
+    }
+}
+
+pub struct Recorder {
+    recording: Recording,
+}
+
+impl Recorder {
+    pub fn new(frame_rate: f32, view: ViewState) -> Self {
+        Self {
+            recording: Recording {
+                frame_rate,
+                frames: 0,
+                view,
+                events: Vec::new(),
+            },
+        }
+    }
+
+    pub fn record(&mut self, input: InputEvent) {
+        self.recording.events.push(RecordedEvent {
+            frame: self.recording.frames,
+            input,
+        });
+    }
+
+    pub fn next_frame(&mut self) {
+        self.recording.frames += 1;
+    }
+
+    pub fn finish(self) -> Recording {
+        self.recording
+    }
+}
+
+pub struct Player {
+    recording: Recording,
+    frame: u64,
+    cursor: usize,
+}
+
+impl Player {
+    pub fn new(mut recording: Recording) -> Self {
+        recording.events.sort_by_key(|v| v.frame);
+        Self {
+            recording,
+            frame: 0,
+            cursor: 0,
+        }
+    }
+
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    // Returns input given before the current frame and moves on to the next one.
+    pub fn next_frame(&mut self) -> Vec<InputEvent> {
+        let events = &self.recording.events[self.cursor..];
+        let count = events.iter().take_while(|v| v.frame <= self.frame).count();
+        self.cursor += count;
+        self.frame += 1;
+
+        events[..count].iter().map(|v| v.input.clone()).collect()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.recording.frames
+    }
+}
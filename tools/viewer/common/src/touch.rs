@@ -0,0 +1,88 @@
+use std::f32;
+
+use cgmath::{EuclideanSpace, InnerSpace};
+use ldraw::{Point2, Vector2};
+use winit::event::TouchPhase;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TouchGesture {
+    // Movement of a single finger, in pixels.
+    Orbit(Vector2),
+    // Derived from the first two fingers on the screen. Pan is movement of their midpoint in
+    // pixels, scale is the ratio of distances between them and rotation is in radians.
+    Transform {
+        pan: Vector2,
+        scale: f32,
+        rotation: f32,
+    },
+}
+
+// Tracks raw touch points instead of relying on platform gestures, which are not delivered
+// on every platform.
+#[derive(Default)]
+pub struct TouchTracker {
+    // In the order fingers went down.
+    touches: Vec<(u64, Point2)>,
+}
+
+fn wrap_angle(angle: f32) -> f32 {
+    (angle + f32::consts::PI).rem_euclid(f32::consts::TAU) - f32::consts::PI
+}
+
+impl TouchTracker {
+    pub fn active_touches(&self) -> usize {
+        self.touches.len()
+    }
+
+    pub fn on_touch(
+        &mut self,
+        id: u64,
+        phase: TouchPhase,
+        location: Point2,
+    ) -> Option<TouchGesture> {
+        match phase {
+            TouchPhase::Started => {
+                self.touches.retain(|(v, _)| *v != id);
+                self.touches.push((id, location));
+                None
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.touches.retain(|(v, _)| *v != id);
+                None
+            }
+            TouchPhase::Moved => {
+                let index = self.touches.iter().position(|(v, _)| *v == id)?;
+                let previous = self.touches.iter().map(|(_, v)| *v).collect::<Vec<_>>();
+                self.touches[index].1 = location;
+
+                // Fingers beyond the first two do not take part.
+                if index >= 2 {
+                    return None;
+                }
+
+                if self.touches.len() == 1 {
+                    return Some(TouchGesture::Orbit(location - previous[0]));
+                }
+
+                let (a0, b0) = (previous[0], previous[1]);
+                let (a1, b1) = (self.touches[0].1, self.touches[1].1);
+
+                let span0 = b0 - a0;
+                let span1 = b1 - a1;
+                let pan = (a1.to_vec() + b1.to_vec() - a0.to_vec() - b0.to_vec()) * 0.5;
+                let scale = if span0.magnitude() > f32::EPSILON {
+                    span1.magnitude() / span0.magnitude()
+                } else {
+                    1.0
+                };
+                let rotation = wrap_angle(span1.y.atan2(span1.x) - span0.y.atan2(span0.x));
+
+                Some(TouchGesture::Transform {
+                    pan,
+                    scale,
+                    rotation,
+                })
+            }
+        }
+    }
+}
@@ -0,0 +1,556 @@
+use std::collections::VecDeque;
+
+use cgmath::{Deg, Rad};
+use futures::channel::mpsc::UnboundedReceiver;
+use ldraw::library::LibraryLoader;
+use ldraw_ir::{
+    model::SubmodelNode,
+    part::{BakeOptions, PrimitiveResolution, StudDetail},
+};
+use winit::{
+    event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent},
+    keyboard::{Key, NamedKey},
+};
+
+use crate::{breakdown::BreakdownMode, events::AppEvent, App, State};
+
+// Lines kept in the message log at the bottom of the panel.
+const MAX_MESSAGES: usize = 8;
+
+fn translate_key(key: &Key) -> Option<egui::Key> {
+    let Key::Named(key) = key else {
+        return None;
+    };
+    Some(match key {
+        NamedKey::Enter => egui::Key::Enter,
+        NamedKey::Tab => egui::Key::Tab,
+        NamedKey::Space => egui::Key::Space,
+        NamedKey::Backspace => egui::Key::Backspace,
+        NamedKey::Delete => egui::Key::Delete,
+        NamedKey::Escape => egui::Key::Escape,
+        NamedKey::Home => egui::Key::Home,
+        NamedKey::End => egui::Key::End,
+        NamedKey::PageUp => egui::Key::PageUp,
+        NamedKey::PageDown => egui::Key::PageDown,
+        NamedKey::ArrowLeft => egui::Key::ArrowLeft,
+        NamedKey::ArrowRight => egui::Key::ArrowRight,
+        NamedKey::ArrowUp => egui::Key::ArrowUp,
+        NamedKey::ArrowDown => egui::Key::ArrowDown,
+        _ => return None,
+    })
+}
+
+fn translate_button(button: MouseButton) -> Option<egui::PointerButton> {
+    match button {
+        MouseButton::Left => Some(egui::PointerButton::Primary),
+        MouseButton::Right => Some(egui::PointerButton::Secondary),
+        MouseButton::Middle => Some(egui::PointerButton::Middle),
+        _ => None,
+    }
+}
+
+fn describe_event(event: &AppEvent) -> Option<String> {
+    Some(match event {
+        AppEvent::StepChanged {
+            step,
+            state: State::Step,
+        } => format!("Reached step {}.", step),
+        AppEvent::StepChanged {
+            state: State::Finished,
+            ..
+        } => String::from("Animation finished."),
+        AppEvent::SelectionChanged(selection) => {
+            format!("{} object(s) selected.", selection.len())
+        }
+        AppEvent::PartLoadFailed { alias, error } => {
+            format!("Could not load part {}: {}", alias, error)
+        }
+        AppEvent::PlaybackFinished => String::from("Playback finished."),
+        AppEvent::LibraryChanged(aliases) => {
+            format!("{} part(s) changed in the library.", aliases.len())
+        }
+        AppEvent::GpuError(error) => format!("GPU error: {}", error),
+        _ => return None,
+    })
+}
+
+// Control panel drawn over the viewport with egui, with steps, submodels, render settings and
+// statistics of the app. Window events go through on_window_event() first, so that ones the
+// panel handles don't reach the app, and the panel is drawn with paint() from the overlay
+// passed to App::render_with_overlay().
+pub struct ControlPanel {
+    context: egui::Context,
+    renderer: egui_wgpu::Renderer,
+    visible: bool,
+
+    // Input gathered since the last frame, in points.
+    events: Vec<egui::Event>,
+    modifiers: egui::Modifiers,
+    pointer: egui::Pos2,
+    pixels_per_point: f32,
+
+    paint_jobs: Vec<egui::ClippedPrimitive>,
+    textures: egui::TexturesDelta,
+    screen: egui_wgpu::ScreenDescriptor,
+
+    // Bake options being edited while a slider is dragged.
+    pending_bake_options: Option<BakeOptions>,
+
+    app_events: UnboundedReceiver<AppEvent>,
+    messages: VecDeque<String>,
+    frame_rate: Option<(u32, f32)>,
+}
+
+impl ControlPanel {
+    pub fn new<L: LibraryLoader>(app: &mut App<L>) -> Self {
+        Self {
+            context: egui::Context::default(),
+            renderer: egui_wgpu::Renderer::new(app.device(), app.surface_format(), None, 1, false),
+            visible: true,
+
+            events: Vec::new(),
+            modifiers: egui::Modifiers::default(),
+            pointer: egui::Pos2::ZERO,
+            pixels_per_point: app.scale_factor() as f32,
+
+            paint_jobs: Vec::new(),
+            textures: egui::TexturesDelta::default(),
+            screen: egui_wgpu::ScreenDescriptor {
+                size_in_pixels: [app.size.width, app.size.height],
+                pixels_per_point: app.scale_factor() as f32,
+            },
+
+            pending_bake_options: None,
+
+            app_events: app.event_channel(),
+            messages: VecDeque::new(),
+            frame_rate: None,
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    // Shown along with render statistics, as the app doesn't keep track of it. Frame time is
+    // in milliseconds.
+    pub fn set_frame_rate(&mut self, frame_rate: u32, frame_time: f32) {
+        self.frame_rate = Some((frame_rate, frame_time));
+    }
+
+    // Adds a line to the message log.
+    pub fn log(&mut self, message: impl Into<String>) {
+        if self.messages.len() >= MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(message.into());
+    }
+
+    // Returns true if the panel took the event, in which case the app should not see it.
+    // Releases always go through so that drags started in the viewport end there.
+    pub fn on_window_event(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                self.pixels_per_point = *scale_factor as f32;
+                return false;
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                let state = modifiers.state();
+                self.modifiers = egui::Modifiers {
+                    alt: state.alt_key(),
+                    ctrl: state.control_key(),
+                    shift: state.shift_key(),
+                    mac_cmd: cfg!(target_os = "macos") && state.super_key(),
+                    command: if cfg!(target_os = "macos") {
+                        state.super_key()
+                    } else {
+                        state.control_key()
+                    },
+                };
+                return false;
+            }
+            _ => {}
+        }
+        if !self.visible {
+            return false;
+        }
+
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.pointer = egui::pos2(
+                    position.x as f32 / self.pixels_per_point,
+                    position.y as f32 / self.pixels_per_point,
+                );
+                self.events.push(egui::Event::PointerMoved(self.pointer));
+                self.context.wants_pointer_input()
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.events.push(egui::Event::PointerGone);
+                false
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let Some(button) = translate_button(*button) else {
+                    return false;
+                };
+                let pressed = *state == ElementState::Pressed;
+                self.events.push(egui::Event::PointerButton {
+                    pos: self.pointer,
+                    button,
+                    pressed,
+                    modifiers: self.modifiers,
+                });
+                pressed && self.context.is_pointer_over_area()
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let (unit, delta) = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => {
+                        (egui::MouseWheelUnit::Line, egui::vec2(*x, *y))
+                    }
+                    MouseScrollDelta::PixelDelta(d) => (
+                        egui::MouseWheelUnit::Point,
+                        egui::vec2(d.x as f32, d.y as f32) / self.pixels_per_point,
+                    ),
+                };
+                self.events.push(egui::Event::MouseWheel {
+                    unit,
+                    delta,
+                    modifiers: self.modifiers,
+                });
+                self.context.is_pointer_over_area()
+            }
+            WindowEvent::KeyboardInput { event, .. } => {
+                let pressed = event.state == ElementState::Pressed;
+                if let Some(key) = translate_key(&event.logical_key) {
+                    self.events.push(egui::Event::Key {
+                        key,
+                        physical_key: None,
+                        pressed,
+                        repeat: event.repeat,
+                        modifiers: self.modifiers,
+                    });
+                }
+                if let Some(text) = event.text.as_ref().filter(|_| pressed) {
+                    if text.chars().all(|c| !c.is_control()) {
+                        self.events.push(egui::Event::Text(text.to_string()));
+                    }
+                }
+                pressed && self.context.wants_keyboard_input()
+            }
+            _ => false,
+        }
+    }
+
+    // Lays out the panel for the next frame and applies changes made with it to the app.
+    // Bake options picked are returned rather than applied, as rebaking is asynchronous.
+    pub fn run<L: LibraryLoader>(&mut self, app: &mut App<L>, time: f32) -> Option<BakeOptions> {
+        while let Ok(event) = self.app_events.try_recv() {
+            if let Some(message) = describe_event(&event) {
+                self.log(message);
+            }
+        }
+
+        self.screen = egui_wgpu::ScreenDescriptor {
+            size_in_pixels: [app.size.width, app.size.height],
+            pixels_per_point: self.pixels_per_point,
+        };
+        if !self.visible {
+            self.events.clear();
+            self.paint_jobs.clear();
+            return None;
+        }
+
+        let mut input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                egui::Pos2::ZERO,
+                egui::vec2(
+                    app.size.width as f32 / self.pixels_per_point,
+                    app.size.height as f32 / self.pixels_per_point,
+                ),
+            )),
+            time: Some(time as f64),
+            modifiers: self.modifiers,
+            events: std::mem::take(&mut self.events),
+            ..Default::default()
+        };
+        input
+            .viewports
+            .entry(input.viewport_id)
+            .or_default()
+            .native_pixels_per_point = Some(self.pixels_per_point);
+
+        let mut bake_options = None;
+        let context = self.context.clone();
+        let output = context.run(input, |ctx| {
+            egui::Window::new("Controls")
+                .default_pos(egui::pos2(8.0, 8.0))
+                .default_width(240.0)
+                .show(ctx, |ui| {
+                    bake_options = self.show(ui, app, time);
+                });
+        });
+
+        self.textures.append(output.textures_delta);
+        self.paint_jobs = context.tessellate(output.shapes, output.pixels_per_point);
+
+        bake_options
+    }
+
+    fn show<L: LibraryLoader>(
+        &mut self,
+        ui: &mut egui::Ui,
+        app: &mut App<L>,
+        time: f32,
+    ) -> Option<BakeOptions> {
+        if let Some(summary) = app.summary() {
+            ui.label(summary.to_string());
+        }
+
+        let steps = app.step_count();
+        if steps > 0 {
+            ui.collapsing("Steps", |ui| {
+                // Parts being played belong to the step after the ones completed.
+                let mut step = match app.state() {
+                    State::Playing => app.current_step() + 1,
+                    State::Step => app.current_step(),
+                    State::Finished => steps,
+                }
+                .clamp(1, steps);
+                if ui
+                    .add(egui::Slider::new(&mut step, 1..=steps).text("Step"))
+                    .changed()
+                {
+                    app.go_to_step(step);
+                }
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(app.state() == State::Step, egui::Button::new("Next"))
+                        .clicked()
+                    {
+                        app.advance(time);
+                    }
+                    let mut ghosting = app.step_ghosting();
+                    if ui.checkbox(&mut ghosting, "Ghost earlier steps").changed() {
+                        app.set_step_ghosting(ghosting);
+                    }
+                });
+            });
+        }
+
+        if let Some(tree) = app.submodel_tree() {
+            egui::CollapsingHeader::new("Submodels").show(ui, |ui| {
+                egui::ScrollArea::vertical()
+                    .max_height(200.0)
+                    .show(ui, |ui| Self::show_submodel(ui, app, &tree));
+            });
+        }
+
+        let bake_options = egui::CollapsingHeader::new("Rendering")
+            .show(ui, |ui| self.show_render_settings(ui, app))
+            .body_returned
+            .flatten();
+
+        ui.collapsing("Statistics", |ui| {
+            if let Some((frame_rate, frame_time)) = self.frame_rate {
+                ui.label(format!(
+                    "{} frames per second, {:.1} msecs per frame",
+                    frame_rate, frame_time
+                ));
+            }
+            let stats = app.render_stats();
+            ui.label(format!(
+                "{} draw calls, {} translucent",
+                stats.draw_calls, stats.translucent_passes
+            ));
+            ui.label(format!(
+                "{} instances, {} culled",
+                stats.instances, stats.culled_instances
+            ));
+            ui.label(format!("{} triangles", stats.triangles));
+            if let Some(timings) = &stats.gpu_timings {
+                ui.label(timings.to_string());
+            }
+        });
+
+        if !self.messages.is_empty() {
+            ui.separator();
+            for message in self.messages.iter() {
+                ui.small(message);
+            }
+        }
+
+        bake_options
+    }
+
+    // Selecting a submodel shows it alone, and the top level one brings back the whole model.
+    fn show_submodel<L: LibraryLoader>(ui: &mut egui::Ui, app: &mut App<L>, node: &SubmodelNode) {
+        let name = if node.name.is_empty() {
+            "Whole model"
+        } else {
+            node.name.as_str()
+        };
+        let label = format!("{} ({} parts)", name, node.parts);
+        let selected = app.render_target() == node.group_id;
+
+        if node.children.is_empty() {
+            if ui.selectable_label(selected, label).clicked() {
+                app.set_render_target(node.group_id);
+            }
+            return;
+        }
+        let id = ui.make_persistent_id(&node.path);
+        egui::collapsing_header::CollapsingState::load_with_default_open(
+            ui.ctx(),
+            id,
+            node.path.0.is_empty(),
+        )
+        .show_header(ui, |ui| {
+            if ui.selectable_label(selected, label).clicked() {
+                app.set_render_target(node.group_id);
+            }
+        })
+        .body(|ui| {
+            for child in node.children.iter() {
+                Self::show_submodel(ui, app, child);
+            }
+        });
+    }
+
+    fn show_render_settings<L: LibraryLoader>(
+        &mut self,
+        ui: &mut egui::Ui,
+        app: &mut App<L>,
+    ) -> Option<BakeOptions> {
+        let mut sample_count = app.sample_count();
+        egui::ComboBox::from_label("Antialiasing")
+            .selected_text(format!("{}x MSAA", sample_count))
+            .show_ui(ui, |ui| {
+                for &count in app.supported_sample_counts() {
+                    ui.selectable_value(&mut sample_count, count, format!("{}x MSAA", count));
+                }
+            });
+        if sample_count != app.sample_count() {
+            app.set_sample_count(sample_count);
+        }
+
+        let mut ground = app.is_ground_visible();
+        if ui.checkbox(&mut ground, "Ground").changed() {
+            app.toggle_ground();
+        }
+        let mut opacity = app.document_opacity();
+        if ui
+            .add(egui::Slider::new(&mut opacity, 0.0..=1.0).text("Opacity"))
+            .changed()
+        {
+            app.set_document_opacity(opacity);
+        }
+
+        let mut mode = app.breakdown().mode();
+        egui::ComboBox::from_label("Colors")
+            .selected_text(match mode {
+                None => "Actual",
+                Some(BreakdownMode::Lot) => "By lot",
+                Some(BreakdownMode::Category) => "By category",
+            })
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut mode, None, "Actual");
+                ui.selectable_value(&mut mode, Some(BreakdownMode::Lot), "By lot");
+                ui.selectable_value(&mut mode, Some(BreakdownMode::Category), "By category");
+            });
+        if mode != app.breakdown().mode() {
+            app.set_breakdown_mode(mode);
+        }
+
+        ui.separator();
+        let current = *app.bake_options();
+        let mut options = self.pending_bake_options.unwrap_or(current);
+        let mut angle = Deg::from(options.smoothing_angle).0;
+        ui.add(egui::Slider::new(&mut angle, 0.0..=180.0).text("Smoothing angle"));
+        options.smoothing_angle = Rad::from(Deg(angle));
+        let mut studs = options.stud_detail == StudDetail::Full;
+        ui.checkbox(&mut studs, "Studs");
+        options.stud_detail = if studs {
+            StudDetail::Full
+        } else {
+            StudDetail::Hidden
+        };
+        egui::ComboBox::from_label("Primitives")
+            .selected_text(format!("{:?}", options.primitive_resolution))
+            .show_ui(ui, |ui| {
+                for resolution in [
+                    PrimitiveResolution::Low,
+                    PrimitiveResolution::Standard,
+                    PrimitiveResolution::High,
+                ] {
+                    ui.selectable_value(
+                        &mut options.primitive_resolution,
+                        resolution,
+                        format!("{:?}", resolution),
+                    );
+                }
+            });
+        ui.checkbox(&mut options.tag_bfc_errors, "Show BFC errors");
+        ui.checkbox(&mut options.repair_t_junctions, "Repair T-junctions");
+
+        // Dragging the slider would rebake on every frame, so the angle waits for release.
+        if ui.ctx().dragged_id().is_some() {
+            self.pending_bake_options = Some(options);
+            return None;
+        }
+        self.pending_bake_options = None;
+        (options != current).then_some(options)
+    }
+
+    // Records drawing of the panel into view, which the app has just rendered the frame to.
+    pub fn paint(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+    ) {
+        let textures = std::mem::take(&mut self.textures);
+        for (id, delta) in textures.set.iter() {
+            self.renderer.update_texture(device, queue, *id, delta);
+        }
+
+        if !self.paint_jobs.is_empty() {
+            let commands = self.renderer.update_buffers(
+                device,
+                queue,
+                encoder,
+                &self.paint_jobs,
+                &self.screen,
+            );
+            if !commands.is_empty() {
+                queue.submit(commands);
+            }
+
+            let mut pass = encoder
+                .begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Control panel render pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    occlusion_query_set: None,
+                    timestamp_writes: None,
+                })
+                .forget_lifetime();
+            self.renderer
+                .render(&mut pass, &self.paint_jobs, &self.screen);
+        }
+
+        for id in textures.free.iter() {
+            self.renderer.free_texture(id);
+        }
+    }
+}
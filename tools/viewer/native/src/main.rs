@@ -1,27 +1,263 @@
 #![cfg(not(target_arch = "wasm32"))]
 
 use std::{
-    env,
-    path::PathBuf,
+    env, fs,
+    path::{Path, PathBuf},
     rc::Rc,
-    sync::{Arc, RwLock},
+    sync::{
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    thread,
     time::{Duration, Instant},
 };
 
+use arboard::Clipboard;
+use cgmath::{Deg, SquareMatrix};
 use clap::{App as ClapApp, Arg};
+use image::{DynamicImage, RgbaImage};
 use ldraw::{
     color::ColorCatalog,
     document::MultipartDocument,
-    library::{DocumentLoader, LibraryLoader, PartCache},
-    resolvers::local::LocalLoader,
+    library::{DocumentLoader, FallbackChain, LibraryLoader, PartCache},
+    resolvers::{
+        http::{HttpLoader, UNOFFICIAL_LIBRARY_URL},
+        local::LocalLoader,
+        watch::{LibraryChange, LibraryWatcher},
+    },
+    writer::LDrawWriter,
+    Matrix4, Vector3,
 };
-use viewer_common::App;
-use winit::{event, event_loop::EventLoop, window::WindowBuilder};
+use ldraw_ir::part::{BakeOptions, PrimitiveResolution, StudDetail};
+use ldraw_renderer::projection::{CameraDescription, BLENDER_IMPORT_SCALE};
+use reqwest::Url;
+use tokio::runtime::Handle;
+#[cfg(feature = "gamepad")]
+use viewer_common::input::Gamepads;
+#[cfg(feature = "egui")]
+use viewer_common::ui::ControlPanel;
+use viewer_common::{
+    breakdown::{BreakdownMode, ColorBreakdown},
+    camera::bookmark_slot,
+    clock::FixedStepClock,
+    events::AppEvent,
+    input::InputMap,
+    recorder::Recording,
+    App, State,
+};
+use winit::{
+    event,
+    event_loop::EventLoop,
+    keyboard::{Key, ModifiersState, NamedKey},
+    window::WindowBuilder,
+};
+
+fn save_document(document: &MultipartDocument, path: &PathBuf) {
+    let mut buffer = Vec::new();
+    match futures::executor::block_on(document.write(&mut buffer)) {
+        Ok(()) => match fs::write(path, buffer) {
+            Ok(()) => println!("Saved document to {}.", path.display()),
+            Err(e) => println!("Could not save document: {}", e),
+        },
+        Err(e) => println!("Could not serialize document: {}", e),
+    }
+}
+
+const SMOOTHING_ANGLE_STEP: f32 = 5.0;
+// Recordings advance time by a fixed step per frame so that they replay identically.
+const RECORDING_FRAME_RATE: f32 = 60.0;
+const LIBRARY_POLL_INTERVAL: Duration = Duration::from_secs(5);
+// Gap between models shown side by side, in LDraw units.
+const COMPARE_SPACING: f32 = 40.0;
+
+// [ and ] adjust smoothing angle, u toggles studs, p cycles primitive resolution,
+// b toggles BFC debug view and t toggles T-junction repair.
+fn bookmark_key(key: &Key) -> Option<usize> {
+    match key {
+        Key::Character(c) => bookmark_slot(c),
+        _ => None,
+    }
+}
+
+fn adjust_bake_options(options: &BakeOptions, key: Key<&str>) -> Option<BakeOptions> {
+    let mut options = *options;
+    let angle = Deg::from(options.smoothing_angle).0;
+    match key {
+        Key::Character("[") => {
+            options.smoothing_angle = Deg((angle - SMOOTHING_ANGLE_STEP).max(0.0)).into();
+        }
+        Key::Character("]") => {
+            options.smoothing_angle = Deg((angle + SMOOTHING_ANGLE_STEP).min(180.0)).into();
+        }
+        Key::Character("u") => {
+            options.stud_detail = match options.stud_detail {
+                StudDetail::Full => StudDetail::Hidden,
+                StudDetail::Hidden => StudDetail::Full,
+            };
+        }
+        Key::Character("p") => {
+            options.primitive_resolution = match options.primitive_resolution {
+                PrimitiveResolution::Low => PrimitiveResolution::Standard,
+                PrimitiveResolution::Standard => PrimitiveResolution::High,
+                PrimitiveResolution::High => PrimitiveResolution::Low,
+            };
+        }
+        Key::Character("b") => options.tag_bfc_errors = !options.tag_bfc_errors,
+        Key::Character("t") => options.repair_t_junctions = !options.repair_t_junctions,
+        _ => return None,
+    }
+    Some(options)
+}
+
+fn window_title<L: LibraryLoader>(app: &App<L>) -> String {
+    match app.summary() {
+        Some(summary) => format!("ldraw.rs demo - {}", summary),
+        None => String::from("ldraw.rs demo"),
+    }
+}
+
+fn export_camera(camera: Option<CameraDescription>, output_path: &Path) {
+    let Some(camera) = camera else {
+        println!("Could not describe current camera.");
+        return;
+    };
+
+    let scripts = [
+        (
+            output_path.with_extension("camera.py"),
+            camera.to_blender_script(BLENDER_IMPORT_SCALE),
+        ),
+        (output_path.with_extension("camera.pov"), camera.to_povray()),
+    ];
+    for (path, script) in scripts {
+        match fs::write(&path, script) {
+            Ok(()) => println!("Exported camera to {}.", path.display()),
+            Err(e) => println!("Could not export camera: {}", e),
+        }
+    }
+}
+
+fn session_path(output_path: &Path) -> PathBuf {
+    output_path.with_extension("session.json")
+}
+
+fn save_recording(recording: &Recording, path: &Path) {
+    match fs::write(path, recording.to_json()) {
+        Ok(()) => println!(
+            "Saved {} frames of input to {}.",
+            recording.frames,
+            path.display()
+        ),
+        Err(e) => println!("Could not save recording: {}", e),
+    }
+}
+
+fn load_recording(path: &Path) -> Option<Recording> {
+    let text = match fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(e) => {
+            println!("Could not read {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    match Recording::from_json(&text) {
+        Ok(v) => Some(v),
+        Err(e) => {
+            println!("Could not parse {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn load_bindings(path: &Path) -> Option<InputMap> {
+    let text = match fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(e) => {
+            println!("Could not read {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    match InputMap::from_json(&text) {
+        Ok(v) => Some(v),
+        Err(e) => {
+            println!("Could not parse {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn print_legend(breakdown: &ColorBreakdown) {
+    let Some(mode) = breakdown.mode() else {
+        println!("Showing actual colors.");
+        return;
+    };
+
+    println!(
+        "{} distinct {}:",
+        breakdown.legend().len(),
+        match mode {
+            BreakdownMode::Lot => "lots",
+            BreakdownMode::Category => "categories",
+        }
+    );
+    for entry in breakdown.legend() {
+        println!(
+            "  #{:02x}{:02x}{:02x} {:>5} {}",
+            entry.color.red(),
+            entry.color.green(),
+            entry.color.blue(),
+            entry.count,
+            entry.label
+        );
+    }
+}
+
+// Scanning the library takes a while, so it is polled off the event loop.
+fn watch_library(mut watcher: LibraryWatcher) -> Receiver<Vec<LibraryChange>> {
+    let (sender, receiver) = mpsc::channel();
+    let runtime = Handle::current();
+    thread::spawn(move || loop {
+        thread::sleep(LIBRARY_POLL_INTERVAL);
+        let changes = runtime.block_on(watcher.poll());
+        if !changes.is_empty() && sender.send(changes).is_err() {
+            break;
+        }
+    });
+    receiver
+}
+
+// Frames written out one by one while recording with --record.
+struct FrameSequence {
+    dir: PathBuf,
+    frame_rate: f32,
+    frames: usize,
+}
+
+impl FrameSequence {
+    fn save(&mut self, image: RgbaImage) {
+        let path = self.dir.join(format!("{:06}.png", self.frames));
+        match DynamicImage::ImageRgba8(image).save(&path) {
+            Ok(()) => self.frames += 1,
+            Err(e) => println!("Could not save frame to {}: {}", path.display(), e),
+        }
+    }
+}
 
+#[allow(clippy::too_many_arguments)]
 async fn main_loop<L: LibraryLoader + 'static>(
     document: MultipartDocument,
+    overlay: Option<MultipartDocument>,
+    compare: Vec<MultipartDocument>,
     colors: ColorCatalog,
     dependency_loader: Rc<L>,
+    fallbacks: FallbackChain,
+    output_path: PathBuf,
+    profile_gpu: bool,
+    infer_steps: bool,
+    replay: Option<Recording>,
+    mut sequence: Option<FrameSequence>,
+    input_map: Option<InputMap>,
+    library_changes: Receiver<Vec<LibraryChange>>,
 ) {
     let evloop = EventLoop::new().unwrap();
     let window = WindowBuilder::new()
@@ -30,15 +266,59 @@ async fn main_loop<L: LibraryLoader + 'static>(
         .unwrap();
 
     let main_window_id = window.id();
+    let window = Arc::new(window);
 
-    let mut app = match App::new(Arc::new(window), dependency_loader, Rc::new(colors), true).await {
+    let mut app = match App::new(
+        Arc::clone(&window),
+        dependency_loader,
+        Rc::new(colors),
+        true,
+    )
+    .await
+    {
         Ok(v) => v,
         Err(e) => {
             panic!("Could not initialize app: {e}");
         }
     };
-    let cache = Arc::new(RwLock::new(PartCache::new()));
-    app.set_document(cache, &document, &|alias, result| {
+    app.set_fallbacks(fallbacks);
+    app.set_step_inference(infer_steps);
+    if let Some(input_map) = input_map {
+        app.set_input_map(input_map);
+    }
+    #[cfg(feature = "gamepad")]
+    let mut gamepads = match Gamepads::new() {
+        Ok(v) => Some(v),
+        Err(e) => {
+            println!("Gamepads are not available: {}", e);
+            None
+        }
+    };
+    if profile_gpu && !app.set_gpu_profiling(true) {
+        println!("GPU profiling is not supported on this device.");
+    }
+    app.subscribe(|event| match event {
+        AppEvent::StepChanged {
+            step,
+            state: State::Step,
+        } => println!("Reached step {}.", step),
+        AppEvent::StepChanged {
+            state: State::Finished,
+            ..
+        } => println!("Animation finished."),
+        AppEvent::SelectionChanged(selection) => {
+            println!("{} object(s) selected.", selection.len())
+        }
+        AppEvent::PlaybackFinished => println!("Playback finished."),
+        AppEvent::LibraryChanged(aliases) => println!(
+            "{} part(s) changed in the library. Press Ctrl+L to reload.",
+            aliases.len()
+        ),
+        AppEvent::GpuError(error) => println!("GPU error: {}", error),
+        _ => {}
+    });
+    let cache = PartCache::new();
+    let on_update = |alias, result: Result<(), _>| {
         match result {
             Ok(()) => {
                 println!("Loaded part {}.", alias);
@@ -47,35 +327,143 @@ async fn main_loop<L: LibraryLoader + 'static>(
                 println!("Could not load part {}: {}", alias, e);
             }
         };
-    })
-    .await
-    .unwrap();
+    };
+    app.set_document(cache.clone(), &document, &on_update)
+        .await
+        .unwrap();
+    if let Some(summary) = app.summary() {
+        println!("{}", summary);
+    }
+    window.set_title(&window_title(&app));
+    if let Some(overlay) = overlay {
+        app.set_overlay_document(cache.clone(), &overlay, &on_update)
+            .await
+            .unwrap();
+    }
+    // Models to compare with are lined up to the right of the document.
+    for document in compare.iter() {
+        let right = app.scene_bounding_box();
+        let id = app
+            .add_scene_document(cache.clone(), document, Matrix4::identity(), &on_update)
+            .await
+            .unwrap();
+        if let Some(bounding_box) = app.scene_model_bounding_box(id) {
+            if !right.is_null() && !bounding_box.is_null() {
+                let offset = right.max.x + COMPARE_SPACING - bounding_box.min.x;
+                app.set_scene_model_transform(
+                    id,
+                    Matrix4::from_translation(Vector3::new(offset, 0.0, 0.0)),
+                );
+            }
+        }
+    }
+    if !compare.is_empty() {
+        app.frame_scene();
+    }
 
-    let started = Instant::now();
+    if let Some(recording) = replay {
+        app.start_playback(recording);
+    }
+    if let Some(sequence) = &sequence {
+        // Playback already runs on a fixed step of its own.
+        if !app.is_playing_back() {
+            app.set_clock(Box::new(FixedStepClock::with_frame_rate(
+                sequence.frame_rate,
+            )));
+        }
+        println!(
+            "Recording frames at {} fps to {}.",
+            sequence.frame_rate,
+            sequence.dir.display()
+        );
+    }
 
     let mut total_duration = 0;
     let mut frames = 0;
-    let mut now = started;
+    let mut now = Instant::now();
+    let mut modifiers = ModifiersState::empty();
+    #[cfg(not(feature = "egui"))]
+    let mut showing_stats = false;
+    let mut screenshots = 0;
+    let mut playing_back = app.is_playing_back();
+    // F10 shows and hides the panel.
+    #[cfg(feature = "egui")]
+    let mut panel = ControlPanel::new(&mut app);
 
     let _ = evloop.run(move |event, target| match event {
         event::Event::WindowEvent { window_id, event } if window_id == main_window_id => {
+            #[cfg(feature = "egui")]
+            if panel.on_window_event(&event) {
+                return;
+            }
             match event {
                 event::WindowEvent::CloseRequested => {
                     target.exit();
                 }
                 event::WindowEvent::RedrawRequested => {
-                    app.animate(started.elapsed().as_millis() as f32 / 1000.0);
-                    match app.render() {
+                    app.update();
+                    #[cfg(feature = "egui")]
+                    let result = {
+                        let time = app.current_time();
+                        if let Some(options) = panel.run(&mut app, time) {
+                            futures::executor::block_on(app.set_bake_options(options));
+                        }
+                        app.render_with_overlay(|device, queue, encoder, view| {
+                            panel.paint(device, queue, encoder, view)
+                        })
+                    };
+                    #[cfg(not(feature = "egui"))]
+                    let result = app.render();
+                    match result {
                         Ok(duration) => {
+                            if let Some(sequence) = &mut sequence {
+                                match futures::executor::block_on(app.capture_frame()) {
+                                    Ok(image) => sequence.save(image),
+                                    Err(e) => println!("Could not capture frame: {}", e),
+                                }
+
+                                // Time goes back to the wall clock once playback ends.
+                                if playing_back && !app.is_playing_back() {
+                                    app.set_clock(Box::new(FixedStepClock::starting_at(
+                                        app.current_time(),
+                                        1.0 / sequence.frame_rate,
+                                    )));
+                                }
+                                playing_back = app.is_playing_back();
+                            }
+
                             total_duration += duration.as_millis();
                             frames += 1;
 
                             if now.elapsed() > Duration::from_secs(1) {
-                                println!(
-                                    "{} frames per second. {} msecs per frame.",
-                                    frames,
-                                    total_duration as f32 / frames as f32
-                                );
+                                #[cfg(feature = "egui")]
+                                panel.set_frame_rate(frames, total_duration as f32 / frames as f32);
+                                #[cfg(not(feature = "egui"))]
+                                {
+                                    println!(
+                                        "{} frames per second. {} msecs per frame.",
+                                        frames,
+                                        total_duration as f32 / frames as f32
+                                    );
+                                    if let Some(timings) = app.render_stats().gpu_timings {
+                                        println!("{}", timings);
+                                    }
+                                    // There is no text rendering, so statistics go to the title bar.
+                                    match app.render_stats_overlay() {
+                                        Some(overlay) => {
+                                            window.set_title(&format!(
+                                                "ldraw.rs demo - {} fps, {}",
+                                                frames, overlay
+                                            ));
+                                            showing_stats = true;
+                                        }
+                                        None if showing_stats => {
+                                            window.set_title(&window_title(&app));
+                                            showing_stats = false;
+                                        }
+                                        None => {}
+                                    }
+                                }
 
                                 now = Instant::now();
                                 frames = 0;
@@ -93,12 +481,201 @@ async fn main_loop<L: LibraryLoader + 'static>(
                         }
                     }
                 }
+                event::WindowEvent::ModifiersChanged(state) => {
+                    modifiers = state.state();
+                    let time = app.current_time();
+                    app.handle_window_event(event::WindowEvent::ModifiersChanged(state), time);
+                }
+                event::WindowEvent::KeyboardInput { event, .. }
+                    if modifiers.control_key()
+                        && event.state == event::ElementState::Pressed
+                        && event.logical_key.as_ref() == Key::Character("l") =>
+                {
+                    match futures::executor::block_on(app.reload_parts(&on_update)) {
+                        Ok(()) => println!("Reloaded parts."),
+                        Err(e) => println!("Could not reload parts: {}", e),
+                    }
+                    window.set_title(&window_title(&app));
+                }
+                event::WindowEvent::KeyboardInput { event, .. }
+                    if modifiers.control_key()
+                        && event.state == event::ElementState::Pressed
+                        && event.logical_key.as_ref() == Key::Character("g") =>
+                {
+                    let enabled = !app.step_ghosting();
+                    app.set_step_ghosting(enabled);
+                    println!(
+                        "Step ghosting {}.",
+                        if enabled { "enabled" } else { "disabled" }
+                    );
+                }
+                event::WindowEvent::KeyboardInput { event, .. }
+                    if modifiers.control_key()
+                        && event.state == event::ElementState::Pressed
+                        && event.logical_key.as_ref() == Key::Character("s") =>
+                {
+                    if let Some(document) = app.document() {
+                        save_document(document, &output_path);
+                        app.mark_document_saved();
+                    }
+                }
+                event::WindowEvent::KeyboardInput { event, .. }
+                    if modifiers.control_key()
+                        && event.state == event::ElementState::Pressed
+                        && event.logical_key.as_ref() == Key::Character("r") =>
+                {
+                    match app.stop_recording() {
+                        Some(recording) => save_recording(&recording, &session_path(&output_path)),
+                        None => {
+                            app.start_recording(RECORDING_FRAME_RATE);
+                            println!("Recording input. Press Ctrl+R again to stop.");
+                        }
+                    }
+                }
+                event::WindowEvent::KeyboardInput { event, .. }
+                    if modifiers.control_key()
+                        && event.state == event::ElementState::Pressed
+                        && event.logical_key.as_ref() == Key::Character("p") =>
+                {
+                    if app.is_playing_back() {
+                        app.stop_playback();
+                        println!("Playback stopped.");
+                    } else if let Some(recording) = load_recording(&session_path(&output_path)) {
+                        app.start_playback(recording);
+                    }
+                }
+                event::WindowEvent::KeyboardInput { event, .. }
+                    if modifiers.control_key()
+                        && event.state == event::ElementState::Pressed
+                        && event.logical_key.as_ref() == Key::Character("c") =>
+                {
+                    match futures::executor::block_on(app.copy_selection()) {
+                        Some(text) => match Clipboard::new().and_then(|mut v| v.set_text(text)) {
+                            Ok(()) => println!("Copied selection to clipboard."),
+                            Err(e) => println!("Could not copy to clipboard: {}", e),
+                        },
+                        None => println!("Nothing to copy."),
+                    }
+                }
+                event::WindowEvent::KeyboardInput { event, .. }
+                    if modifiers.control_key()
+                        && event.state == event::ElementState::Pressed
+                        && event.logical_key.as_ref() == Key::Character("v") =>
+                {
+                    let text = match Clipboard::new().and_then(|mut v| v.get_text()) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            println!("Could not read clipboard: {}", e);
+                            return;
+                        }
+                    };
+                    match futures::executor::block_on(app.paste(&text, &on_update)) {
+                        Ok(count) => println!("Pasted {} parts.", count),
+                        Err(e) => println!("Could not paste: {}", e),
+                    }
+                    window.set_title(&window_title(&app));
+                }
+                event::WindowEvent::KeyboardInput { event, .. }
+                    if modifiers.control_key()
+                        && event.state == event::ElementState::Pressed
+                        && bookmark_key(&event.logical_key).is_some() =>
+                {
+                    let slot = bookmark_key(&event.logical_key).unwrap();
+                    app.save_bookmark(slot);
+                    println!("Saved camera bookmark {}.", slot + 1);
+                }
+                event::WindowEvent::KeyboardInput { event, .. }
+                    if event.state == event::ElementState::Pressed
+                        && event.logical_key.as_ref() == Key::Character("n") =>
+                {
+                    match app.add_bookmark() {
+                        Some(slot) => println!("Saved camera bookmark {}.", slot + 1),
+                        None => println!("All camera bookmark slots are in use."),
+                    }
+                }
+                event::WindowEvent::KeyboardInput { event, .. }
+                    if event.state == event::ElementState::Pressed
+                        && event.logical_key.as_ref() == Key::Character("l") =>
+                {
+                    app.cycle_breakdown_mode();
+                    print_legend(app.breakdown());
+                }
+                event::WindowEvent::KeyboardInput { event, .. }
+                    if event.state == event::ElementState::Pressed
+                        && event.logical_key.as_ref() == Key::Character("k") =>
+                {
+                    export_camera(app.camera_description(), &output_path);
+                }
+                event::WindowEvent::KeyboardInput { event, .. }
+                    if event.state == event::ElementState::Pressed
+                        && event.logical_key.as_ref() == Key::Character("m") =>
+                {
+                    println!("Using {}x MSAA.", app.cycle_sample_count());
+                }
+                #[cfg(feature = "egui")]
+                event::WindowEvent::KeyboardInput { event, .. }
+                    if event.state == event::ElementState::Pressed
+                        && event.logical_key.as_ref() == Key::Named(NamedKey::F10) =>
+                {
+                    panel.toggle();
+                }
+                event::WindowEvent::KeyboardInput { event, .. }
+                    if event.state == event::ElementState::Pressed
+                        && event.logical_key.as_ref() == Key::Named(NamedKey::F9) =>
+                {
+                    screenshots += 1;
+                    let path = output_path.with_extension(format!("{}.png", screenshots));
+                    match futures::executor::block_on(app.capture_frame()) {
+                        Ok(image) => match DynamicImage::ImageRgba8(image).save(&path) {
+                            Ok(()) => println!("Saved screenshot to {}.", path.display()),
+                            Err(e) => println!("Could not save screenshot: {}", e),
+                        },
+                        Err(e) => println!("Could not capture frame: {}", e),
+                    }
+                }
                 event => {
-                    app.handle_window_event(event, started.elapsed().as_millis() as f32 / 1000.0);
+                    let options = match &event {
+                        event::WindowEvent::KeyboardInput { event, .. }
+                            if event.state == event::ElementState::Pressed =>
+                        {
+                            adjust_bake_options(app.bake_options(), event.logical_key.as_ref())
+                        }
+                        _ => None,
+                    };
+
+                    if let Some(options) = options {
+                        println!(
+                            "Re-baking parts: smoothing angle {:.0}°, studs {:?}, primitives {:?}.",
+                            Deg::from(options.smoothing_angle).0,
+                            options.stud_detail,
+                            options.primitive_resolution
+                        );
+                        futures::executor::block_on(app.set_bake_options(options));
+                    } else {
+                        let time = app.current_time();
+                        app.handle_window_event(event, time);
+                        if let Some((position, extend)) = app.take_pending_pick() {
+                            futures::executor::block_on(app.select_object_at(position, extend));
+                        }
+                    }
                 }
             }
         }
         event::Event::AboutToWait => {
+            while let Ok(changes) = library_changes.try_recv() {
+                let aliases = changes
+                    .iter()
+                    .map(|v| v.alias().clone())
+                    .collect::<Vec<_>>();
+                app.invalidate_parts(&aliases);
+            }
+            #[cfg(feature = "gamepad")]
+            if let Some(gamepads) = &mut gamepads {
+                let time = app.current_time();
+                for input in gamepads.poll(app.input_map()) {
+                    app.dispatch_input(input, time);
+                }
+            }
             app.request_redraw();
         }
         _ => (),
@@ -123,6 +700,83 @@ async fn main() {
                 .value_name("PATH_OR_URL")
                 .help("Path or URL to model file"),
         )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Path to save edited model to. Defaults to the input file"),
+        )
+        .arg(
+            Arg::with_name("overlay")
+                .long("overlay")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Path to a model file to display translucently over the model"),
+        )
+        .arg(
+            Arg::with_name("compare")
+                .long("compare")
+                .value_name("PATH")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Path to a model file to display next to the model. May be repeated"),
+        )
+        .arg(
+            Arg::with_name("unofficial")
+                .long("unofficial")
+                .help("Fetch parts missing from the library from the LDraw unofficial library"),
+        )
+        .arg(
+            Arg::with_name("fallback_url")
+                .long("fallback-url")
+                .value_name("URL")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("URL of a library to fetch missing parts from. May be repeated"),
+        )
+        .arg(
+            Arg::with_name("profile-gpu")
+                .long("profile-gpu")
+                .help("Print GPU time spent on each pass"),
+        )
+        .arg(
+            Arg::with_name("infer_steps")
+                .long("infer-steps")
+                .help("Guess building steps from the layout of parts for models without any"),
+        )
+        .arg(
+            Arg::with_name("replay")
+                .long("replay")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Play back input recorded with Ctrl+R"),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .value_name("DIR")
+                .takes_value(true)
+                .help("Save every frame to numbered PNG files in a directory. Time advances by a fixed step per frame"),
+        )
+        .arg(
+            Arg::with_name("record_fps")
+                .long("record-fps")
+                .value_name("FPS")
+                .takes_value(true)
+                .default_value("30")
+                .help("Frame rate of frames saved with --record"),
+        )
+        .arg(
+            Arg::with_name("bindings")
+                .long("bindings")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Load key and gamepad bindings from a JSON file"),
+        )
         .get_matches();
 
     let ldrawdir = match matches.value_of("ldraw_dir") {
@@ -140,6 +794,7 @@ async fn main() {
     // FIXME: There should be better ways than this
 
     let ldraw_path = PathBuf::from(&ldrawdir);
+    let library_changes = watch_library(LibraryWatcher::new(&ldraw_path).await);
     let document_base_path = PathBuf::from(&path).parent().map(PathBuf::from);
     let loader = LocalLoader::new(Some(ldraw_path), document_base_path);
 
@@ -148,5 +803,78 @@ async fn main() {
     let path_local = PathBuf::from(&path);
     let document = loader.load_document(&path_local, &colors).await.unwrap();
 
-    main_loop(document, colors, Rc::new(loader)).await;
+    let overlay = match matches.value_of("overlay") {
+        Some(v) => Some(
+            loader
+                .load_document(&PathBuf::from(v), &colors)
+                .await
+                .unwrap(),
+        ),
+        None => None,
+    };
+
+    let mut compare = Vec::new();
+    for path in matches.values_of("compare").into_iter().flatten() {
+        compare.push(
+            loader
+                .load_document(&PathBuf::from(path), &colors)
+                .await
+                .unwrap(),
+        );
+    }
+
+    // Fallbacks are tried in the order given, with the unofficial library last.
+    let mut fallbacks = FallbackChain::new();
+    for url in matches.values_of("fallback_url").into_iter().flatten() {
+        match Url::parse(url) {
+            Ok(v) => fallbacks.push(url, HttpLoader::new(Some(v), None)),
+            Err(e) => panic!("Invalid fallback URL {}: {}", url, e),
+        }
+    }
+    if matches.is_present("unofficial") {
+        fallbacks.push(
+            "unofficial",
+            HttpLoader::new(Some(Url::parse(UNOFFICIAL_LIBRARY_URL).unwrap()), None),
+        );
+    }
+
+    let output_path = PathBuf::from(matches.value_of("output").unwrap_or(&path));
+    let replay = matches
+        .value_of("replay")
+        .and_then(|v| load_recording(Path::new(v)));
+    let input_map = matches
+        .value_of("bindings")
+        .and_then(|v| load_bindings(Path::new(v)));
+    let sequence = matches.value_of("record").map(|v| {
+        let dir = PathBuf::from(v);
+        if let Err(e) = fs::create_dir_all(&dir) {
+            panic!("Could not create {}: {}", dir.display(), e);
+        }
+        let frame_rate = match matches.value_of("record_fps").unwrap().parse::<f32>() {
+            Ok(v) if v > 0.0 => v,
+            _ => panic!("--record-fps has to be a positive number."),
+        };
+        FrameSequence {
+            dir,
+            frame_rate,
+            frames: 0,
+        }
+    });
+
+    main_loop(
+        document,
+        overlay,
+        compare,
+        colors,
+        Rc::new(loader),
+        fallbacks,
+        output_path,
+        matches.is_present("profile-gpu"),
+        matches.is_present("infer_steps"),
+        replay,
+        sequence,
+        input_map,
+        library_changes,
+    )
+    .await;
 }
@@ -0,0 +1,203 @@
+use std::cell::RefCell;
+
+use gloo::events::EventListener;
+use wasm_bindgen::JsCast;
+use web_sys::{Document, Element, HtmlSelectElement};
+
+use crate::escape_html;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn class_name(&self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Category {
+    Part,
+    Parse,
+    Gpu,
+    Network,
+    General,
+}
+
+impl Category {
+    fn class_name(&self) -> &'static str {
+        match self {
+            Category::Part => "part",
+            Category::Parse => "parse",
+            Category::Gpu => "gpu",
+            Category::Network => "network",
+            Category::General => "general",
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Category::Part => "Part",
+            Category::Parse => "Parse",
+            Category::Gpu => "GPU",
+            Category::Network => "Network",
+            Category::General => "Viewer",
+        }
+    }
+}
+
+struct Entry {
+    id: usize,
+    severity: Severity,
+    category: Category,
+    message: String,
+    count: usize,
+}
+
+#[derive(Default)]
+struct ErrorPanel {
+    entries: Vec<Entry>,
+    next_id: usize,
+}
+
+impl ErrorPanel {
+    // Returns whether a new entry was added.
+    fn push(&mut self, severity: Severity, category: Category, message: String) -> bool {
+        // Repeated messages, like a validation error raised every frame, share one entry.
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|v| v.severity == severity && v.category == category && v.message == message)
+        {
+            entry.count += 1;
+            return false;
+        }
+
+        self.entries.push(Entry {
+            id: self.next_id,
+            severity,
+            category,
+            message,
+            count: 1,
+        });
+        self.next_id += 1;
+        true
+    }
+
+    fn dismiss(&mut self, id: usize) {
+        self.entries.retain(|v| v.id != id);
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn render(&self, document: &Document) {
+        // Pages embedding the viewer don't have the panel.
+        let Some(panel) = document.get_element_by_id("error-panel") else {
+            return;
+        };
+
+        let severity = select_value(document, "error-severity-filter");
+        let category = select_value(document, "error-category-filter");
+
+        let visible = self
+            .entries
+            .iter()
+            .filter(|v| severity.is_empty() || v.severity.class_name() == severity)
+            .filter(|v| category.is_empty() || v.category.class_name() == category)
+            .collect::<Vec<_>>();
+
+        panel.set_class_name(if self.entries.is_empty() {
+            ""
+        } else {
+            "active"
+        });
+
+        let count = document.get_element_by_id("error-count").unwrap();
+        count.set_inner_html(&if visible.len() == self.entries.len() {
+            format!("{} problem(s)", self.entries.len())
+        } else {
+            format!("{} of {} problem(s)", visible.len(), self.entries.len())
+        });
+
+        let list = document.get_element_by_id("error-entries").unwrap();
+        list.set_inner_html(
+            &visible
+                .iter()
+                .rev()
+                .map(|entry| {
+                    format!(
+                        "<div class=\"{}\"><span class=\"category\">{}</span>{}{}<button data-entry=\"{}\">&times;</button></div>",
+                        entry.severity.class_name(),
+                        entry.category.label(),
+                        escape_html(&entry.message),
+                        if entry.count > 1 {
+                            format!(" <span class=\"count\">&times; {}</span>", entry.count)
+                        } else {
+                            String::new()
+                        },
+                        entry.id,
+                    )
+                })
+                .collect::<String>(),
+        );
+    }
+}
+
+fn select_value(document: &Document, id: &str) -> String {
+    let select = document.get_element_by_id(id).unwrap();
+    JsCast::dyn_ref::<HtmlSelectElement>(&select)
+        .unwrap()
+        .value()
+}
+
+thread_local! {
+    static PANEL: RefCell<ErrorPanel> = RefCell::default();
+}
+
+fn update<T, F: FnOnce(&mut ErrorPanel) -> T>(f: F) -> T {
+    let document = web_sys::window().unwrap().document().unwrap();
+    PANEL.with(|panel| {
+        let mut panel = panel.borrow_mut();
+        let result = f(&mut panel);
+        panel.render(&document);
+        result
+    })
+}
+
+// Returns false if the message was folded into an existing entry.
+pub fn report(severity: Severity, category: Category, message: String) -> bool {
+    update(|panel| panel.push(severity, category, message))
+}
+
+// Wires dismiss buttons and filters. Entries are redrawn wholesale, so clicks are
+// handled once on the list rather than per button.
+pub fn install(document: &Document) {
+    let list = document.get_element_by_id("error-entries").unwrap();
+    EventListener::new(&list, "click", |event| {
+        let id = event
+            .target()
+            .and_then(|v| v.dyn_into::<Element>().ok())
+            .and_then(|v| v.get_attribute("data-entry"))
+            .and_then(|v| v.parse::<usize>().ok());
+        if let Some(id) = id {
+            update(|panel| panel.dismiss(id));
+        }
+    })
+    .forget();
+
+    let dismiss_all = document.get_element_by_id("dismiss-errors").unwrap();
+    EventListener::new(&dismiss_all, "click", |_event| update(ErrorPanel::clear)).forget();
+
+    for id in ["error-severity-filter", "error-category-filter"] {
+        let filter = document.get_element_by_id(id).unwrap();
+        EventListener::new(&filter, "change", |_event| update(|_| {})).forget();
+    }
+}
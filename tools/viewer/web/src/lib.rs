@@ -2,33 +2,42 @@
 
 extern crate console_error_panic_hook;
 
-use std::{
-    cell::RefCell,
-    panic,
-    rc::Rc,
-    sync::{Arc, RwLock},
-};
+mod error_panel;
+mod viewer;
+
+use std::rc::Rc;
 
+use cgmath::Deg;
 use gloo::events::EventListener;
 use ldraw::{
-    document::MultipartDocument,
     error::ResolutionError,
-    library::{CacheCollectionStrategy, LibraryLoader, PartCache},
-    parser::parse_multipart_document,
-    resolvers::http::HttpLoader,
+    library::CacheCollectionStrategy,
+    parser::{parse_multipart_document, parse_multipart_document_with_lines, ParseOptions},
     PartAlias,
 };
+use ldraw_ir::{
+    model::GroupId,
+    part::{BakeOptions, PrimitiveResolution, StudDetail},
+};
+use ldraw_renderer::projection::BLENDER_IMPORT_SCALE;
 use reqwest::{Client, Url};
 use tokio::io::BufReader;
 use uuid::Uuid;
-use viewer_common::{App, State};
+use viewer_common::{
+    breakdown::{BreakdownMode, LegendEntry},
+    events::AppEvent,
+    State,
+};
 use wasm_bindgen::{prelude::*, JsCast};
-use wasm_bindgen_futures::spawn_local;
+use wasm_bindgen_futures::{spawn_local, JsFuture};
 use web_sys::{
-    HtmlButtonElement, HtmlCanvasElement, HtmlDivElement, HtmlSelectElement, HtmlTextAreaElement,
+    Blob, HtmlAnchorElement, HtmlButtonElement, HtmlCanvasElement, HtmlDivElement,
+    HtmlInputElement, HtmlSelectElement, HtmlTextAreaElement, KeyboardEvent,
 };
-use winit::{
-    event, event_loop::EventLoop, platform::web::WindowBuilderExtWebSys, window::WindowBuilder,
+
+use self::{
+    error_panel::{Category, Severity},
+    viewer::LdrawViewer,
 };
 
 // A huge mess. Needs refactoring.
@@ -38,7 +47,10 @@ const ANTIALIAS: bool = true;
 fn log(s: &str, error: bool) {
     let window = web_sys::window().unwrap();
     let document = window.document().unwrap();
-    let console = document.get_element_by_id("console-pane").unwrap();
+    // Pages embedding the viewer may not have the message log.
+    let Some(console) = document.get_element_by_id("console-pane") else {
+        return;
+    };
     let node = document.create_element("p").unwrap();
     node.set_attribute(
         "class",
@@ -52,17 +64,41 @@ fn log(s: &str, error: bool) {
     console.prepend_with_node_1(&node).unwrap();
 }
 
-fn alert(s: &str) {
-    let window = web_sys::window().unwrap();
-    window.alert_with_message(s).unwrap();
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
 }
 
 macro_rules! console_log {
     ($($t:tt)*) => (log(&format_args!($($t)*).to_string(), false))
 }
 
-macro_rules! console_error {
-    ($($t:tt)*) => (alert(&format_args!($($t)*).to_string()))
+// Shows a problem in the error panel and keeps a copy in the message log.
+fn report(severity: Severity, category: Category, message: String) {
+    let logged = escape_html(&message);
+    if error_panel::report(severity, category, message) {
+        log(&logged, true);
+    }
+}
+
+macro_rules! report_error {
+    ($category:expr, $($t:tt)*) => (report(Severity::Error, $category, format!($($t)*)))
+}
+
+// Selects the given line of the text area, counting from 1.
+fn select_line(view: &HtmlTextAreaElement, line: usize) {
+    // Offsets in the text area are in UTF-16 code units.
+    let mut start = 0;
+    for (index, text) in view.value().split('\n').enumerate() {
+        let length = text.encode_utf16().count();
+        if index + 1 == line {
+            let _ = view.focus();
+            let _ = view.set_selection_range(start as u32, (start + length) as u32);
+            return;
+        }
+        start += length + 1;
+    }
 }
 
 async fn fetch_raw_data(base_url: &Url, path: &String) -> Option<String> {
@@ -77,7 +113,7 @@ async fn fetch_raw_data(base_url: &Url, path: &String) -> Option<String> {
     let url = match url {
         Ok(e) => e,
         Err(err) => {
-            alert(&format!("Could not build url {}: {}", path, err));
+            report_error!(Category::Network, "Could not build url {}: {}", path, err);
             return None;
         }
     };
@@ -86,55 +122,139 @@ async fn fetch_raw_data(base_url: &Url, path: &String) -> Option<String> {
         Ok(e) => match e.text().await {
             Ok(e) => Some(e),
             Err(err) => {
-                alert(&format!("Could not fetch from url {}: {}", url, err));
+                report_error!(
+                    Category::Network,
+                    "Could not fetch from url {}: {}",
+                    url,
+                    err
+                );
                 None
             }
         },
         Err(err) => {
-            alert(&format!("Could not make request to {}: {}", url, err));
+            report_error!(
+                Category::Network,
+                "Could not make request to {}: {}",
+                url,
+                err
+            );
             None
         }
     }
 }
 
+// Failures are reported once resolution is done, through AppEvent::PartLoadFailed.
 fn log_part_resolution(alias: PartAlias, result: Result<(), ResolutionError>) {
-    match result {
-        Ok(_) => {
-            console_log!("Part {} loaded", alias);
-        }
-        Err(err) => {
-            console_error!("Could not load part {}: {}", alias, err);
-        }
+    if result.is_ok() {
+        console_log!("Part {} loaded", alias);
+    }
+}
+
+fn update_legend(
+    web_document: &web_sys::Document,
+    mode: Option<BreakdownMode>,
+    entries: &[LegendEntry],
+) {
+    let select = web_document.get_element_by_id("breakdown-mode").unwrap();
+    let select = JsCast::dyn_ref::<HtmlSelectElement>(&select).unwrap();
+    select.set_value(match mode {
+        Some(BreakdownMode::Lot) => "lot",
+        Some(BreakdownMode::Category) => "category",
+        None => "none",
+    });
+
+    let legend = web_document.get_element_by_id("legend").unwrap();
+    legend.set_inner_html(
+        &entries
+            .iter()
+            .map(|entry| {
+                format!(
+                    "<div><span style=\"background: #{:02x}{:02x}{:02x}\"></span>{} &times; {}</div>",
+                    entry.color.red(),
+                    entry.color.green(),
+                    entry.color.blue(),
+                    entry.count,
+                    entry.label
+                )
+            })
+            .collect::<String>(),
+    );
+}
+
+fn update_subparts(web_document: &web_sys::Document, subparts: &[(GroupId, String)]) {
+    let select = web_document.get_element_by_id("subparts").unwrap();
+    select.set_inner_html("");
+
+    let body = web_document.create_element("option").unwrap();
+    body.set_attribute("value", "").unwrap();
+    body.set_inner_html("Base Model");
+    select.append_child(&body).unwrap();
+
+    for (id, name) in subparts {
+        let subpart = web_document.create_element("option").unwrap();
+        subpart.set_attribute("value", &format!("{}", id)).unwrap();
+        subpart.set_inner_html(&format!("Subpart {} ({})", name, id));
+        select.append_child(&subpart).unwrap();
+    }
+}
+
+fn read_bake_options(web_document: &web_sys::Document) -> BakeOptions {
+    let mut options = BakeOptions::default();
+
+    let smoothing_angle = web_document.get_element_by_id("smoothing-angle").unwrap();
+    let smoothing_angle = JsCast::dyn_ref::<HtmlInputElement>(&smoothing_angle).unwrap();
+    if let Ok(angle) = smoothing_angle.value().parse::<f32>() {
+        options.smoothing_angle = Deg(angle.clamp(0.0, 180.0)).into();
+    }
+
+    let stud_detail = web_document.get_element_by_id("stud-detail").unwrap();
+    let stud_detail = JsCast::dyn_ref::<HtmlSelectElement>(&stud_detail).unwrap();
+    if stud_detail.value() == "hidden" {
+        options.stud_detail = StudDetail::Hidden;
     }
+
+    let primitive_resolution = web_document
+        .get_element_by_id("primitive-resolution")
+        .unwrap();
+    let primitive_resolution = JsCast::dyn_ref::<HtmlSelectElement>(&primitive_resolution).unwrap();
+    options.primitive_resolution = match primitive_resolution.value().as_str() {
+        "low" => PrimitiveResolution::Low,
+        "high" => PrimitiveResolution::High,
+        _ => PrimitiveResolution::Standard,
+    };
+
+    let bfc_debug = web_document.get_element_by_id("bfc-debug").unwrap();
+    let bfc_debug = JsCast::dyn_ref::<HtmlInputElement>(&bfc_debug).unwrap();
+    options.tag_bfc_errors = bfc_debug.checked();
+
+    let repair_t_junctions = web_document
+        .get_element_by_id("repair-t-junctions")
+        .unwrap();
+    let repair_t_junctions = JsCast::dyn_ref::<HtmlInputElement>(&repair_t_junctions).unwrap();
+    options.repair_t_junctions = repair_t_junctions.checked();
+
+    options
 }
 
+fn download(blob: &Blob, filename: &str) -> Result<(), JsValue> {
+    let url = web_sys::Url::create_object_url_with_blob(blob)?;
+    let document = web_sys::window().unwrap().document().unwrap();
+    let anchor = document
+        .create_element("a")?
+        .dyn_into::<HtmlAnchorElement>()?;
+    anchor.set_href(&url);
+    anchor.set_download(filename);
+    anchor.click();
+    web_sys::Url::revoke_object_url(&url)
+}
+
+// Demo page, wiring controls of index.html to a viewer on its canvas.
 #[wasm_bindgen]
 #[allow(clippy::await_holding_refcell_ref)]
 pub async fn run(path: JsValue) -> JsValue {
-    panic::set_hook(Box::new(console_error_panic_hook::hook));
-
     let web_window = web_sys::window().expect("No window exists.");
     let web_document = web_window.document().expect("No document exists.");
-    let body = web_document.get_element_by_id("body").unwrap();
-    let canvas = web_document
-        .get_element_by_id("main_canvas")
-        .unwrap()
-        .dyn_into::<HtmlCanvasElement>()
-        .unwrap();
-    canvas.set_width(body.client_width() as u32);
-    canvas.set_height(body.client_height() as u32);
-
-    let event_loop = EventLoop::new().unwrap();
-    let builder = WindowBuilder::new()
-        .with_title("ldraw.rs demo")
-        .with_inner_size(winit::dpi::LogicalSize {
-            width: body.client_width() as u32,
-            height: body.client_height() as u32,
-        })
-        .with_canvas(Some(canvas));
-
-    let window = builder.build(&event_loop).unwrap();
-
+    error_panel::install(&web_document);
     let canvas = web_document
         .get_element_by_id("main_canvas")
         .unwrap()
@@ -147,54 +267,63 @@ pub async fn run(path: JsValue) -> JsValue {
         .dyn_into::<HtmlTextAreaElement>()
         .unwrap();
 
-    let mut location = Url::parse(&web_window.location().href().unwrap()).unwrap();
-    location.set_fragment(None);
-
-    let loader = Rc::new(HttpLoader::new(
-        Some(location.join("ldraw/").unwrap()),
-        Some(location.clone()),
-    ));
-
-    let colors = match loader.load_colors().await {
-        Ok(e) => Rc::new(e),
+    let viewer = match LdrawViewer::new(canvas, None).await {
+        Ok(v) => v,
         Err(err) => {
-            console_error!("Could not open color definitions: {}", err);
+            report(Severity::Error, err.category(), err.to_string());
             return JsValue::undefined();
         }
     };
+    let app = Rc::clone(&viewer.app);
+    let colors = Rc::clone(&viewer.colors);
+    let cache = viewer.cache.clone();
+    let location = viewer.base_url.clone();
 
-    let main_window_id = window.id();
-    let window = Arc::new(window);
-
-    let app = match App::new(
-        Arc::clone(&window),
-        Rc::clone(&loader),
-        Rc::clone(&colors),
-        ANTIALIAS,
-    )
-    .await
     {
-        Ok(v) => v,
-        Err(e) => {
-            console_error!("Could not initialize the app: {e}");
-            return JsValue::undefined();
-        }
-    };
-
-    let app = Rc::new(RefCell::new(app));
+        let web_document = web_document.clone();
+        let frame_time = Rc::clone(&viewer.frame_time);
+        let mut app = app.borrow_mut();
+        let backend = app.adapter_info.backend.to_str();
+        let mut summary = String::new();
+        app.subscribe(move |event| match event {
+            AppEvent::ModelLoaded(model_summary) => summary = model_summary.to_string(),
+            AppEvent::SubpartsChanged(subparts) => update_subparts(&web_document, subparts),
+            AppEvent::StepChanged { step, state } => {
+                let next_button = web_document.get_element_by_id("next-button").unwrap();
+                if *state == State::Step {
+                    console_log!("Reached step {}", step);
+                    next_button.set_class_name("active");
+                } else {
+                    next_button.set_class_name("");
+                }
+            }
+            AppEvent::SelectionChanged(selection) => {
+                console_log!("{} object(s) selected", selection.len())
+            }
+            AppEvent::PartLoadFailed { alias, error } => report(
+                Severity::Warning,
+                Category::Part,
+                format!("Could not load part {}: {}", alias, error),
+            ),
+            AppEvent::BreakdownChanged { mode, legend } => {
+                update_legend(&web_document, *mode, legend)
+            }
+            AppEvent::RenderStatsTick(stats) => {
+                let element = web_document.get_element_by_id("stats").unwrap();
+                element.set_inner_html(&format!(
+                    "Rendering backend: {}<br />{} msecs<br />{}<br />{}",
+                    backend,
+                    frame_time.get(),
+                    summary,
+                    stats
+                ));
+            }
+            AppEvent::GpuError(error) => report(Severity::Error, Category::Gpu, error.clone()),
+            _ => {}
+        });
+    }
     console_log!("Rendering context initialization done.");
 
-    let cache = Arc::new(RwLock::new(PartCache::default()));
-
-    app.borrow_mut().resize(winit::dpi::PhysicalSize {
-        width: canvas.width(),
-        height: canvas.height(),
-    });
-
-    let window = web_sys::window().unwrap();
-    let perf = window.performance().unwrap();
-    let start_time = perf.now();
-
     {
         let document_view = document_view.clone();
         if path.is_string() {
@@ -206,46 +335,30 @@ pub async fn run(path: JsValue) -> JsValue {
                 }
             };
 
-            let document = match parse_multipart_document(
+            let (document, lines, _) = match parse_multipart_document_with_lines(
                 &mut BufReader::new(document_text.as_bytes()),
                 &colors,
+                &ParseOptions::default(),
             )
             .await
             {
                 Ok(v) => v,
                 Err(err) => {
-                    console_error!("Could not parse document: {}", err);
+                    report_error!(Category::Parse, "Could not parse document: {}", err);
                     return JsValue::undefined();
                 }
             };
 
             if let Err(err) = app
                 .borrow_mut()
-                .set_document(Arc::clone(&cache), &document, &log_part_resolution)
+                .set_document_with_lines(cache.clone(), &document, lines, &log_part_resolution)
                 .await
             {
-                console_error!("Could not load model: {}", err);
+                report_error!(Category::Part, "Could not load model: {}", err);
             }
-            cache
-                .write()
-                .unwrap()
-                .collect(CacheCollectionStrategy::Parts);
+            cache.collect(CacheCollectionStrategy::Parts);
 
             let subparts = web_document.get_element_by_id("subparts").unwrap();
-            subparts.set_inner_html("");
-
-            let body = web_document.create_element("option").unwrap();
-            body.set_attribute("value", "").unwrap();
-            body.set_inner_html("Base Model");
-            subparts.append_child(&body).unwrap();
-
-            for (id, name) in app.borrow().get_subparts() {
-                let subpart = web_document.create_element("option").unwrap();
-                subpart.set_attribute("value", &format!("{}", id)).unwrap();
-                subpart.set_inner_html(&format!("Subpart {} ({})", name, id));
-                subparts.append_child(&subpart).unwrap();
-            }
-
             let web_document = web_document.clone();
             let app = Rc::clone(&app);
             let closure = Closure::wrap(Box::new(move |_event: web_sys::Event| {
@@ -268,32 +381,41 @@ pub async fn run(path: JsValue) -> JsValue {
         }
     }
 
-    let new_doc = Rc::new(RefCell::new(None));
     {
         let document_view = document_view.clone();
-        let new_doc = Rc::clone(&new_doc);
+        let app = Rc::clone(&app);
+        let cache = cache.clone();
         let colors = Rc::clone(&colors);
         let closure = Closure::wrap(Box::new(move |_event: web_sys::Event| {
             let document_view = document_view.clone();
-            let new_doc = Rc::clone(&new_doc);
+            let app = Rc::clone(&app);
+            let cache = cache.clone();
             let colors = Rc::clone(&colors);
             spawn_local(async move {
                 let document_text = document_view.value();
 
-                let document = match parse_multipart_document(
+                let (document, lines, _) = match parse_multipart_document_with_lines(
                     &mut BufReader::new(document_text.as_bytes()),
                     &colors,
+                    &ParseOptions::default(),
                 )
                 .await
                 {
                     Ok(v) => v,
                     Err(err) => {
-                        console_error!("Could not parse document: {}", err);
+                        report_error!(Category::Parse, "Could not parse document: {}", err);
                         return;
                     }
                 };
 
-                *new_doc.borrow_mut() = Some(document);
+                if let Err(err) = app
+                    .borrow_mut()
+                    .update_document(&document, Some(lines), &log_part_resolution)
+                    .await
+                {
+                    report_error!(Category::Part, "Could not reload model: {}", err);
+                };
+                cache.collect(CacheCollectionStrategy::Parts);
             });
         }) as Box<dyn FnMut(_)>);
         let submit_button = web_document.get_element_by_id("submit").unwrap();
@@ -305,148 +427,274 @@ pub async fn run(path: JsValue) -> JsValue {
     }
 
     {
-        let window = web_sys::window().unwrap();
         let app = Rc::clone(&app);
-        let closure = Closure::wrap(Box::new(move |_event: web_sys::UiEvent| {
-            let app = &mut app.borrow_mut();
-            canvas.set_width(canvas.client_width() as _);
-            canvas.set_height(canvas.client_height() as _);
-            app.resize(winit::dpi::PhysicalSize {
-                width: canvas.client_width() as _,
-                height: canvas.client_height() as _,
+        let web_document = web_document.clone();
+        let closure = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+            let app = Rc::clone(&app);
+            let options = read_bake_options(&web_document);
+            spawn_local(async move {
+                app.borrow_mut().set_bake_options(options).await;
+                console_log!("Parts re-baked with {:?}", options);
             });
         }) as Box<dyn FnMut(_)>);
-        window
-            .add_event_listener_with_callback("resize", closure.as_ref().unchecked_ref())
+        let apply_button = web_document
+            .get_element_by_id("apply-bake-options")
+            .unwrap();
+        let apply_button = JsCast::dyn_ref::<HtmlButtonElement>(&apply_button).unwrap();
+        apply_button
+            .add_event_listener_with_callback("click", closure.as_ref().unchecked_ref())
             .unwrap();
         closure.forget();
     }
 
     {
-        let next_button = web_document.get_element_by_id("next-button").unwrap();
-        let next_button = JsCast::dyn_ref::<HtmlDivElement>(&next_button).unwrap();
-        let a = Rc::clone(&app);
-        let closure = EventListener::new(next_button, "click", move |_event| {
-            let window = web_sys::window().unwrap();
-            let perf = window.performance().unwrap();
+        let overlay_view = web_document
+            .get_element_by_id("overlay-document")
+            .unwrap()
+            .dyn_into::<HtmlTextAreaElement>()
+            .unwrap();
+        let app = Rc::clone(&app);
+        let cache = cache.clone();
+        let colors = Rc::clone(&colors);
+        let closure = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+            let overlay_view = overlay_view.clone();
+            let app = Rc::clone(&app);
+            let cache = cache.clone();
+            let colors = Rc::clone(&colors);
+            spawn_local(async move {
+                let document_text = overlay_view.value();
 
-            a.borrow_mut()
-                .advance(((perf.now() - start_time) / 1000.0) as f32);
-        });
+                let document = match parse_multipart_document(
+                    &mut BufReader::new(document_text.as_bytes()),
+                    &colors,
+                )
+                .await
+                {
+                    Ok(v) => v,
+                    Err(err) => {
+                        report_error!(Category::Parse, "Could not parse overlay document: {}", err);
+                        return;
+                    }
+                };
+
+                if let Err(err) = app
+                    .borrow_mut()
+                    .set_overlay_document(cache.clone(), &document, &log_part_resolution)
+                    .await
+                {
+                    report_error!(Category::Part, "Could not load overlay: {}", err);
+                }
+            });
+        }) as Box<dyn FnMut(_)>);
+        let load_button = web_document.get_element_by_id("load-overlay").unwrap();
+        let load_button = JsCast::dyn_ref::<HtmlButtonElement>(&load_button).unwrap();
+        load_button
+            .add_event_listener_with_callback("click", closure.as_ref().unchecked_ref())
+            .unwrap();
         closure.forget();
     }
 
     {
         let app = Rc::clone(&app);
-        let new_doc = Rc::clone(&new_doc);
-        let web_document = web_document.clone();
-        let cache = Arc::clone(&cache);
+        let closure = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+            app.borrow_mut().clear_overlay();
+        }) as Box<dyn FnMut(_)>);
+        let clear_button = web_document.get_element_by_id("clear-overlay").unwrap();
+        let clear_button = JsCast::dyn_ref::<HtmlButtonElement>(&clear_button).unwrap();
+        clear_button
+            .add_event_listener_with_callback("click", closure.as_ref().unchecked_ref())
+            .unwrap();
+        closure.forget();
+    }
 
-        event_loop
-            .run(move |event, target| match event {
-                event::Event::AboutToWait => {
-                    app.borrow().request_redraw();
+    {
+        let app = Rc::clone(&app);
+        let closure = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+            match app.borrow().camera_description() {
+                Some(camera) => {
+                    console_log!("<pre>{}</pre>", escape_html(&camera.to_povray()));
+                    console_log!(
+                        "<pre>{}</pre>",
+                        escape_html(&camera.to_blender_script(BLENDER_IMPORT_SCALE))
+                    );
                 }
-                event::Event::WindowEvent { event, window_id } if window_id == main_window_id => {
-                    match event {
-                        event::WindowEvent::RedrawRequested => {
-                            let mut app_ = app.borrow_mut();
-
-                            app_.animate(((perf.now() - start_time) / 1000.0) as f32);
-                            match app_.render() {
-                                Ok(duration) => {
-                                    let next_button =
-                                        web_document.get_element_by_id("next-button").unwrap();
-                                    let next_button =
-                                        JsCast::dyn_ref::<HtmlDivElement>(&next_button).unwrap();
-                                    if app_.state() == State::Step {
-                                        next_button.set_class_name("active");
-                                    } else {
-                                        next_button.set_class_name("");
-                                    }
-
-                                    let stats = web_document.get_element_by_id("stats").unwrap();
-                                    let stats = JsCast::dyn_ref::<HtmlDivElement>(&stats).unwrap();
-                                    stats.set_inner_html(&format!(
-                                        "Rendering backend: {}<br />{} msecs",
-                                        app_.adapter_info.backend.to_str(),
-                                        duration.as_millis(),
-                                    ));
-                                }
-                                Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                                    //app.resize(app.size);
-                                }
-                                Err(wgpu::SurfaceError::OutOfMemory) => {
-                                    target.exit();
-                                }
-                                Err(wgpu::SurfaceError::Timeout) => {
-                                    println!("Surface timeout");
-                                }
-                            }
-
-                            if new_doc.borrow().is_some() {
-                                let document =
-                                    MultipartDocument::clone(new_doc.borrow().as_ref().unwrap());
-                                *new_doc.borrow_mut() = None;
-
-                                let app = Rc::clone(&app);
-                                let cache = Arc::clone(&cache);
-                                let web_document = web_document.clone();
-
-                                spawn_local(async move {
-                                    if let Err(err) = app
-                                        .borrow_mut()
-                                        .set_document(
-                                            Arc::clone(&cache),
-                                            &document,
-                                            &log_part_resolution,
-                                        )
-                                        .await
-                                    {
-                                        console_error!("Could not reload model: {}", err);
-                                    };
-
-                                    cache
-                                        .write()
-                                        .unwrap()
-                                        .collect(CacheCollectionStrategy::Parts);
-
-                                    let subparts =
-                                        web_document.get_element_by_id("subparts").unwrap();
-                                    subparts.set_inner_html("");
-
-                                    let body = web_document.create_element("option").unwrap();
-                                    body.set_attribute("value", "").unwrap();
-                                    body.set_inner_html("Base Model");
-                                    subparts.append_child(&body).unwrap();
-
-                                    console_log!("{:?}", body);
-
-                                    for (id, name) in app.borrow().get_subparts() {
-                                        let subpart =
-                                            web_document.create_element("option").unwrap();
-                                        subpart.set_attribute("value", &format!("{}", id)).unwrap();
-                                        subpart
-                                            .set_inner_html(&format!("Subpart {} ({})", name, id));
-                                        subparts.append_child(&subpart).unwrap();
-                                    }
-                                });
-                            }
-                        }
-                        event::WindowEvent::CloseRequested => {}
-                        event => {
-                            if let Ok(mut app) = app.try_borrow_mut() {
-                                app.handle_window_event(
-                                    event,
-                                    ((perf.now() - start_time) / 1000.0) as f32,
-                                );
-                            }
-                        }
+                None => console_log!("Could not describe current camera."),
+            }
+        }) as Box<dyn FnMut(_)>);
+        let export_button = web_document.get_element_by_id("export-camera").unwrap();
+        let export_button = JsCast::dyn_ref::<HtmlButtonElement>(&export_button).unwrap();
+        export_button
+            .add_event_listener_with_callback("click", closure.as_ref().unchecked_ref())
+            .unwrap();
+        closure.forget();
+    }
+
+    {
+        let app = Rc::clone(&app);
+        let closure = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+            let app = Rc::clone(&app);
+            spawn_local(async move {
+                let Some(text) = app.borrow().copy_selection().await else {
+                    console_log!("Nothing to copy.");
+                    return;
+                };
+                let clipboard = web_sys::window().unwrap().navigator().clipboard();
+                match JsFuture::from(clipboard.write_text(&text)).await {
+                    Ok(_) => console_log!("Copied selection to clipboard."),
+                    Err(err) => {
+                        report_error!(Category::General, "Could not copy to clipboard: {:?}", err)
                     }
                 }
-                _ => (),
-            })
+            });
+        }) as Box<dyn FnMut(_)>);
+        let copy_button = web_document.get_element_by_id("copy-selection").unwrap();
+        let copy_button = JsCast::dyn_ref::<HtmlButtonElement>(&copy_button).unwrap();
+        copy_button
+            .add_event_listener_with_callback("click", closure.as_ref().unchecked_ref())
+            .unwrap();
+        closure.forget();
+    }
+
+    {
+        let app = Rc::clone(&app);
+        let document_view = document_view.clone();
+        let closure = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+            match app.borrow().selected_lines().first() {
+                Some((_, line)) => select_line(&document_view, *line),
+                None => console_log!("Selection does not come from the document."),
+            }
+        }) as Box<dyn FnMut(_)>);
+        let locate_button = web_document.get_element_by_id("locate-selection").unwrap();
+        let locate_button = JsCast::dyn_ref::<HtmlButtonElement>(&locate_button).unwrap();
+        locate_button
+            .add_event_listener_with_callback("click", closure.as_ref().unchecked_ref())
+            .unwrap();
+        closure.forget();
+    }
+
+    {
+        let app = Rc::clone(&app);
+        let closure = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+            let app = Rc::clone(&app);
+            spawn_local(async move {
+                let clipboard = web_sys::window().unwrap().navigator().clipboard();
+                let text = match JsFuture::from(clipboard.read_text()).await {
+                    Ok(v) => v.as_string().unwrap_or_default(),
+                    Err(err) => {
+                        report_error!(Category::General, "Could not read clipboard: {:?}", err);
+                        return;
+                    }
+                };
+                match app.borrow_mut().paste(&text, &log_part_resolution).await {
+                    Ok(count) => console_log!("Pasted {} parts.", count),
+                    Err(err) => report_error!(Category::General, "Could not paste: {}", err),
+                }
+            });
+        }) as Box<dyn FnMut(_)>);
+        let paste_button = web_document.get_element_by_id("paste-parts").unwrap();
+        let paste_button = JsCast::dyn_ref::<HtmlButtonElement>(&paste_button).unwrap();
+        paste_button
+            .add_event_listener_with_callback("click", closure.as_ref().unchecked_ref())
+            .unwrap();
+        closure.forget();
+    }
+
+    {
+        let app = Rc::clone(&app);
+        let web_document_ = web_document.clone();
+        let closure = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+            let mode = web_document_.get_element_by_id("breakdown-mode").unwrap();
+            let mode = JsCast::dyn_ref::<HtmlSelectElement>(&mode).unwrap();
+            app.borrow_mut()
+                .set_breakdown_mode(match mode.value().as_str() {
+                    "lot" => Some(BreakdownMode::Lot),
+                    "category" => Some(BreakdownMode::Category),
+                    _ => None,
+                });
+        }) as Box<dyn FnMut(_)>);
+        let mode = web_document.get_element_by_id("breakdown-mode").unwrap();
+        mode.add_event_listener_with_callback("change", closure.as_ref().unchecked_ref())
+            .unwrap();
+        closure.forget();
+    }
+
+    for (id, is_overlay) in [("document-opacity", false), ("overlay-opacity", true)] {
+        let slider = web_document
+            .get_element_by_id(id)
+            .unwrap()
+            .dyn_into::<HtmlInputElement>()
+            .unwrap();
+        let app = Rc::clone(&app);
+        let slider_ = slider.clone();
+        let closure = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+            let opacity = match slider_.value().parse::<f32>() {
+                Ok(v) => v / 100.0,
+                Err(_) => return,
+            };
+            if is_overlay {
+                app.borrow_mut().set_overlay_opacity(opacity);
+            } else {
+                app.borrow_mut().set_document_opacity(opacity);
+            }
+        }) as Box<dyn FnMut(_)>);
+        slider
+            .add_event_listener_with_callback("input", closure.as_ref().unchecked_ref())
+            .unwrap();
+        closure.forget();
+    }
+
+    {
+        let window = web_sys::window().unwrap();
+        let closure = Closure::wrap(Box::new(move |_event: web_sys::UiEvent| {
+            viewer.resize();
+        }) as Box<dyn FnMut(_)>);
+        window
+            .add_event_listener_with_callback("resize", closure.as_ref().unchecked_ref())
             .unwrap();
+        closure.forget();
+    }
+
+    {
+        let app = Rc::clone(&app);
+        EventListener::new(&web_window, "keydown", move |event| {
+            let Some(event) = event.dyn_ref::<KeyboardEvent>() else {
+                return;
+            };
+            if event.key() != "F9" {
+                return;
+            }
+            event.prevent_default();
+
+            let app = Rc::clone(&app);
+            spawn_local(async move {
+                let blob = match viewer::capture_blob(&app).await {
+                    Ok(v) => v,
+                    Err(err) => {
+                        report_error!(Category::Gpu, "{}", err);
+                        return;
+                    }
+                };
+                match download(&blob, "screenshot.png") {
+                    Ok(()) => console_log!("Saved screenshot."),
+                    Err(err) => {
+                        report_error!(Category::General, "Could not save screenshot: {:?}", err)
+                    }
+                }
+            });
+        })
+        .forget();
+    }
+
+    {
+        let next_button = web_document.get_element_by_id("next-button").unwrap();
+        let next_button = JsCast::dyn_ref::<HtmlDivElement>(&next_button).unwrap();
+        let a = Rc::clone(&app);
+        let closure = EventListener::new(next_button, "click", move |_event| {
+            let mut app = a.borrow_mut();
+            let time = app.current_time();
+            app.advance(time);
+        });
+        closure.forget();
     }
 
     JsValue::undefined()
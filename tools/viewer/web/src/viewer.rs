@@ -0,0 +1,372 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt::{self, Display, Formatter},
+    io::Cursor,
+    panic,
+    rc::Rc,
+    sync::Arc,
+};
+
+use cgmath::Deg;
+use image::{DynamicImage, ImageOutputFormat};
+use js_sys::{Array, Promise, Uint8Array};
+use ldraw::{
+    color::ColorCatalog,
+    error::ResolutionError,
+    library::{CacheCollectionStrategy, LibraryLoader, PartCache},
+    parser::{parse_multipart_document_with_lines, ParseOptions},
+    resolvers::http::HttpLoader,
+    Point3,
+};
+use ldraw_ir::analysis::query::ModelQuery;
+use reqwest::Url;
+use tokio::io::BufReader;
+use viewer_common::{camera::CameraBookmark, App};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::{future_to_promise, spawn_local};
+use web_sys::{Blob, BlobPropertyBag, HtmlCanvasElement};
+use winit::{
+    event,
+    event_loop::EventLoop,
+    platform::web::{EventLoopExtWebSys, WindowBuilderExtWebSys},
+    window::WindowBuilder,
+};
+
+use crate::{error_panel::Category, fetch_raw_data, log_part_resolution, ANTIALIAS};
+
+pub(crate) enum CreationError {
+    Location,
+    Colors(ResolutionError),
+    Window(String),
+    App(String),
+}
+
+impl CreationError {
+    pub(crate) fn category(&self) -> Category {
+        match self {
+            CreationError::Colors(_) => Category::Network,
+            CreationError::App(_) => Category::Gpu,
+            _ => Category::General,
+        }
+    }
+}
+
+impl Display for CreationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CreationError::Location => write!(f, "Could not read location of the page."),
+            CreationError::Colors(e) => write!(f, "Could not open color definitions: {}", e),
+            CreationError::Window(e) => write!(f, "Could not create a window: {}", e),
+            CreationError::App(e) => write!(f, "Could not initialize the app: {}", e),
+        }
+    }
+}
+
+// Viewer bound to a canvas, for embedding into other pages.
+//
+// Only one viewer can live on a page, as winit allows a single event loop.
+#[wasm_bindgen]
+pub struct LdrawViewer {
+    pub(crate) app: Rc<RefCell<App<HttpLoader>>>,
+    pub(crate) canvas: HtmlCanvasElement,
+    pub(crate) cache: PartCache,
+    pub(crate) colors: Rc<ColorCatalog>,
+    pub(crate) base_url: Url,
+    // Duration of the last frame in milliseconds.
+    pub(crate) frame_time: Rc<Cell<u128>>,
+    disposed: Rc<Cell<bool>>,
+}
+
+impl LdrawViewer {
+    pub(crate) async fn new(
+        canvas: HtmlCanvasElement,
+        library_url: Option<&str>,
+    ) -> Result<Self, CreationError> {
+        panic::set_hook(Box::new(console_error_panic_hook::hook));
+
+        let mut base_url = web_sys::window()
+            .and_then(|v| v.location().href().ok())
+            .and_then(|v| Url::parse(&v).ok())
+            .ok_or(CreationError::Location)?;
+        base_url.set_fragment(None);
+        let library_url = base_url
+            .join(library_url.unwrap_or("ldraw/"))
+            .map_err(|_| CreationError::Location)?;
+
+        let loader = Rc::new(HttpLoader::new(Some(library_url), Some(base_url.clone())));
+        let colors = Rc::new(loader.load_colors().await.map_err(CreationError::Colors)?);
+
+        let event_loop = EventLoop::new().map_err(|e| CreationError::Window(e.to_string()))?;
+        let window = WindowBuilder::new()
+            .with_inner_size(winit::dpi::LogicalSize {
+                width: canvas.client_width() as u32,
+                height: canvas.client_height() as u32,
+            })
+            .with_canvas(Some(canvas.clone()))
+            .build(&event_loop)
+            .map_err(|e| CreationError::Window(e.to_string()))?;
+        let main_window_id = window.id();
+
+        let app = App::new(Arc::new(window), loader, Rc::clone(&colors), ANTIALIAS)
+            .await
+            .map_err(|e| CreationError::App(e.to_string()))?;
+
+        let viewer = LdrawViewer {
+            app: Rc::new(RefCell::new(app)),
+            canvas,
+            cache: PartCache::default(),
+            colors,
+            base_url,
+            frame_time: Rc::new(Cell::new(0)),
+            disposed: Rc::new(Cell::new(false)),
+        };
+        viewer.resize();
+
+        let app = Rc::clone(&viewer.app);
+        let frame_time = Rc::clone(&viewer.frame_time);
+        let disposed = Rc::clone(&viewer.disposed);
+        // Loading holds the app across awaits, so frames and input arriving meanwhile are
+        // dropped rather than waited for.
+        event_loop.spawn(move |event, target| {
+            if disposed.get() {
+                target.exit();
+                return;
+            }
+
+            match event {
+                event::Event::AboutToWait => {
+                    if let Ok(app) = app.try_borrow() {
+                        app.request_redraw();
+                    }
+                }
+                event::Event::WindowEvent { event, window_id } if window_id == main_window_id => {
+                    let app_handle = Rc::clone(&app);
+                    let Ok(mut app) = app.try_borrow_mut() else {
+                        return;
+                    };
+                    match event {
+                        event::WindowEvent::RedrawRequested => {
+                            app.update();
+                            match app.render() {
+                                Ok(duration) => frame_time.set(duration.as_millis()),
+                                Err(wgpu::SurfaceError::OutOfMemory) => target.exit(),
+                                Err(_) => {}
+                            }
+                        }
+                        event::WindowEvent::CloseRequested => {}
+                        event => {
+                            let time = app.current_time();
+                            app.handle_window_event(event, time);
+                            if let Some((position, extend)) = app.take_pending_pick() {
+                                let app = Rc::clone(&app_handle);
+                                spawn_local(async move {
+                                    if let Ok(mut app) = app.try_borrow_mut() {
+                                        app.select_object_at(position, extend).await;
+                                    }
+                                });
+                            }
+                        }
+                    }
+                }
+                _ => (),
+            }
+        });
+
+        Ok(viewer)
+    }
+}
+
+#[wasm_bindgen]
+impl LdrawViewer {
+    // Parts are looked up under library_url, which is relative to the page and defaults to
+    // 'ldraw/'.
+    pub async fn create(
+        canvas: HtmlCanvasElement,
+        library_url: Option<String>,
+    ) -> Result<LdrawViewer, JsError> {
+        Self::new(canvas, library_url.as_deref())
+            .await
+            .map_err(|err| JsError::new(&err.to_string()))
+    }
+
+    // Replaces the model with one parsed from LDraw text. Resolves once all parts are loaded.
+    pub fn load_from_string(&self, text: String) -> Promise {
+        let app = Rc::clone(&self.app);
+        let cache = self.cache.clone();
+        let colors = Rc::clone(&self.colors);
+        future_to_promise(async move {
+            load_text(&app, cache, &colors, &text).await?;
+            Ok(JsValue::undefined())
+        })
+    }
+
+    // As load_from_string(), with text fetched from a url relative to the page.
+    pub fn load_from_url(&self, url: String) -> Promise {
+        let app = Rc::clone(&self.app);
+        let cache = self.cache.clone();
+        let colors = Rc::clone(&self.colors);
+        let base_url = self.base_url.clone();
+        future_to_promise(async move {
+            let Some(text) = fetch_raw_data(&base_url, &url).await else {
+                return Err(JsError::new(&format!("Could not fetch {}", url)).into());
+            };
+            load_text(&app, cache, &colors, &text).await?;
+            Ok(JsValue::undefined())
+        })
+    }
+
+    // Moves the camera to position, looking at look_at. Both are [x, y, z] in LDraw units.
+    pub fn set_camera(&self, position: &[f32], look_at: &[f32], fov: f32) -> Result<(), JsError> {
+        let (Some(position), Some(look_at)) = (to_point(position), to_point(look_at)) else {
+            return Err(JsError::new(
+                "Expected [x, y, z] for camera position and target.",
+            ));
+        };
+        self.app.try_borrow_mut()?.restore_camera(&CameraBookmark {
+            position,
+            look_at,
+            fov: Deg(fov),
+        });
+        Ok(())
+    }
+
+    // Current camera as [x, y, z, target x, target y, target z, fov].
+    pub fn camera(&self) -> Result<Vec<f32>, JsError> {
+        let camera = self.app.try_borrow()?.camera_bookmark();
+        Ok(vec![
+            camera.position.x,
+            camera.position.y,
+            camera.position.z,
+            camera.look_at.x,
+            camera.look_at.y,
+            camera.look_at.z,
+            camera.fov.0,
+        ])
+    }
+
+    pub fn next_step(&self) -> Result<(), JsError> {
+        let mut app = self.app.try_borrow_mut()?;
+        let time = app.current_time();
+        app.advance(time);
+        Ok(())
+    }
+
+    // Selects parts whose names match the pattern, where '*' and '?' are wildcards. Returns
+    // the number of parts selected.
+    pub fn select_part(&self, pattern: String) -> Result<usize, JsError> {
+        Ok(self.app.try_borrow_mut()?.select_query(&ModelQuery {
+            aliases: vec![pattern],
+            ..Default::default()
+        }))
+    }
+
+    pub fn clear_selection(&self) -> Result<(), JsError> {
+        self.app.try_borrow_mut()?.clear_selection();
+        Ok(())
+    }
+
+    // Renders a frame and returns it as a PNG data url. The canvas is read right after
+    // rendering, before the browser gets to discard its contents.
+    pub fn screenshot(&self) -> Result<String, JsError> {
+        {
+            let mut app = self.app.try_borrow_mut()?;
+            app.update();
+            if let Err(err) = app.render() {
+                return Err(JsError::new(&format!("Could not render: {}", err)));
+            }
+        }
+        self.canvas
+            .to_data_url()
+            .map_err(|_| JsError::new("Could not read the canvas."))
+    }
+
+    // Renders the current view offscreen and resolves to a PNG blob of it.
+    pub fn capture_frame(&self) -> Promise {
+        let app = Rc::clone(&self.app);
+        future_to_promise(async move {
+            match capture_blob(&app).await {
+                Ok(blob) => Ok(blob.into()),
+                Err(err) => Err(JsError::new(&err).into()),
+            }
+        })
+    }
+
+    // Matches the canvas to its size on the page. Call it whenever the canvas gets resized.
+    pub fn resize(&self) {
+        let Ok(mut app) = self.app.try_borrow_mut() else {
+            return;
+        };
+        // Client size is in CSS pixels, while the canvas is rendered in device pixels.
+        let scale_factor = web_sys::window().unwrap().device_pixel_ratio();
+        let size =
+            winit::dpi::LogicalSize::new(self.canvas.client_width(), self.canvas.client_height())
+                .to_physical::<u32>(scale_factor);
+        self.canvas.set_width(size.width);
+        self.canvas.set_height(size.height);
+        app.set_scale_factor(scale_factor);
+        app.resize(size);
+    }
+
+    // Stops rendering and input handling. The viewer can't be used afterwards.
+    pub fn dispose(&self) {
+        self.disposed.set(true);
+        self.cache
+            .collect(CacheCollectionStrategy::PartsAndPrimitives);
+    }
+}
+
+#[allow(clippy::await_holding_refcell_ref)]
+pub(crate) async fn capture_blob(app: &RefCell<App<HttpLoader>>) -> Result<Blob, String> {
+    let image = match app.try_borrow_mut() {
+        Ok(mut app) => app.capture_frame().await,
+        Err(_) => return Err("Viewer is busy.".to_string()),
+    }
+    .map_err(|e| format!("Could not capture frame: {}", e))?;
+
+    let mut png = Vec::new();
+    DynamicImage::ImageRgba8(image)
+        .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+        .map_err(|e| format!("Could not encode frame: {}", e))?;
+
+    let parts = Array::of1(&Uint8Array::from(png.as_slice()));
+    let options = BlobPropertyBag::new();
+    options.set_type("image/png");
+    Blob::new_with_u8_array_sequence_and_options(&parts, &options)
+        .map_err(|_| "Could not create a blob.".to_string())
+}
+
+fn to_point(v: &[f32]) -> Option<Point3> {
+    match v {
+        [x, y, z] => Some(Point3::new(*x, *y, *z)),
+        _ => None,
+    }
+}
+
+#[allow(clippy::await_holding_refcell_ref)]
+async fn load_text(
+    app: &RefCell<App<HttpLoader>>,
+    cache: PartCache,
+    colors: &ColorCatalog,
+    text: &str,
+) -> Result<(), JsError> {
+    let (document, lines, _) = match parse_multipart_document_with_lines(
+        &mut BufReader::new(text.as_bytes()),
+        colors,
+        &ParseOptions::default(),
+    )
+    .await
+    {
+        Ok(v) => v,
+        Err(err) => return Err(JsError::new(&format!("Could not parse document: {}", err))),
+    };
+
+    let result = app
+        .try_borrow_mut()?
+        .set_document_with_lines(cache.clone(), &document, lines, &log_part_resolution)
+        .await;
+    cache.collect(CacheCollectionStrategy::Parts);
+    match result {
+        Ok(()) => Ok(()),
+        Err(err) => Err(JsError::new(&format!("Could not load model: {}", err))),
+    }
+}